@@ -0,0 +1,60 @@
+extern crate clam;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::prelude::*;
+
+fn random_pair<T: Number>(rng: &mut StdRng, dimensionality: usize, max: f64) -> (Vec<T>, Vec<T>) {
+    let mut instance = || {
+        (0..dimensionality)
+            .map(|_| T::from(rng.gen_range(0. ..max)).unwrap())
+            .collect::<Vec<T>>()
+    };
+    (instance(), instance())
+}
+
+fn metric_kernels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("metrics");
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for &dimensionality in [16, 128, 1024].iter() {
+        group.throughput(Throughput::Elements(dimensionality as u64));
+
+        let (x, y) = random_pair::<f32>(&mut rng, dimensionality, 1.);
+        for name in ["euclidean", "euclideansq", "manhattan", "cosine"] {
+            let metric = metric_from_name::<f32, f32>(name).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, dimensionality), &(&x, &y), |b, (x, y)| {
+                b.iter(|| metric.distance(black_box(x), black_box(y)))
+            });
+        }
+
+        let (x, y) = random_pair::<u8>(&mut rng, dimensionality, 4.);
+        for name in ["hamming", "jaccard"] {
+            let metric = metric_from_name::<u8, f64>(name).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, dimensionality), &(&x, &y), |b, (x, y)| {
+                b.iter(|| metric.distance(black_box(x), black_box(y)))
+            });
+        }
+
+        let metric = metric_from_name::<u8, u64>("hamming").unwrap();
+        let encoding = metric.encode(&x, &y).unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("hamming_encode", dimensionality),
+            &(&x, &y),
+            |b, (x, y)| b.iter(|| metric.encode(black_box(x), black_box(y)).unwrap()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("hamming_decode", dimensionality),
+            &(&x, &encoding),
+            |b, (x, encoding)| b.iter(|| metric.decode(black_box(x), black_box(encoding)).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, metric_kernels);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+extern crate clam;
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+use clam::Cakes;
+
+fn random_data(seed: u64, cardinality: usize, dimensionality: usize) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..cardinality)
+        .map(|_| (0..dimensionality).map(|_| rng.gen_range(-1. ..1.)).collect())
+        .collect()
+}
+
+fn partition(c: &mut Criterion) {
+    let mut group = c.benchmark_group("partition");
+    group.sample_size(10);
+
+    for &cardinality in [1_000, 4_000, 16_000].iter() {
+        let data = Arc::new(random_data(42, cardinality, 8));
+        let metric = metric_from_name::<f32, f32>("euclidean").unwrap();
+
+        group.bench_with_input(BenchmarkId::new("cakes", cardinality), &data, |b, data| {
+            b.iter(|| {
+                let dataset: Arc<dyn Dataset<f32, f32>> =
+                    Arc::new(RowMajor::new(Arc::clone(data), Arc::clone(&metric), false));
+                Cakes::build(dataset, None, None)
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("manifold_depth_10", cardinality), &data, |b, data| {
+            b.iter(|| {
+                let dataset: Arc<dyn Dataset<f32, f32>> =
+                    Arc::new(RowMajor::new(Arc::clone(data), Arc::clone(&metric), false));
+                Manifold::new(dataset, &[criteria::max_depth(10), criteria::min_cardinality(1)])
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, partition);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+extern crate clam;
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+use clam::Cakes;
+
+fn random_data(seed: u64, cardinality: usize, dimensionality: usize) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..cardinality)
+        .map(|_| (0..dimensionality).map(|_| rng.gen_range(-1. ..1.)).collect())
+        .collect()
+}
+
+fn traversal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("traversal");
+    group.sample_size(20);
+
+    let metric = metric_from_name::<f32, f32>("euclidean").unwrap();
+    let dataset: Arc<dyn Dataset<f32, f32>> =
+        Arc::new(RowMajor::new(Arc::new(random_data(42, 10_000, 8)), metric, false));
+    let cakes = Cakes::build(dataset, None, None);
+    let queries = random_data(7, 100, 8);
+    group.throughput(Throughput::Elements(queries.len() as u64));
+
+    for &fraction in [0.01, 0.05, 0.1].iter() {
+        let radius = cakes.diameter() * fraction;
+        group.bench_with_input(BenchmarkId::new("rnn", fraction), &radius, |b, &radius| {
+            b.iter(|| {
+                for query in queries.iter() {
+                    cakes.rnn(query, Some(radius));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("tree_search", fraction), &radius, |b, &radius| {
+            b.iter(|| {
+                for query in queries.iter() {
+                    cakes.tree_search(query, Some(radius));
+                }
+            })
+        });
+    }
+
+    for &k in [1, 10, 100].iter() {
+        group.bench_with_input(BenchmarkId::new("knn", k), &k, |b, &k| {
+            b.iter(|| {
+                for query in queries.iter() {
+                    cakes.knn(query, k);
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("knn_adaptive", k), &k, |b, &k| {
+            b.iter(|| {
+                for query in queries.iter() {
+                    cakes.knn_adaptive(query, k);
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, traversal);
+criterion_main!(benches);
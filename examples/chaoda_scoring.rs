@@ -0,0 +1,62 @@
+//! Anomaly detection with CHAODA on a small, labeled dataset.
+//!
+//! Run with `cargo run --release --example chaoda_scoring`.
+
+use std::sync::Arc;
+
+use eval_metrics::classification::RocCurve;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(3);
+
+    // Inliers are clustered around a few centers, and outliers are scattered far from all of them.
+    let centers = [[0., 0.], [6., 0.], [0., 6.]];
+    let mut data: Vec<Vec<f64>> = (0..600)
+        .map(|i| {
+            let [x, y] = centers[i % centers.len()];
+            vec![x + rng.gen_range(-1.0..1.0), y + rng.gen_range(-1.0..1.0)]
+        })
+        .collect();
+    let mut labels = vec![false; data.len()];
+    while data.len() < 620 {
+        let point = vec![rng.gen_range(-12.0..18.0), rng.gen_range(-12.0..18.0)];
+        let is_far = centers
+            .iter()
+            .all(|[x, y]| ((point[0] - x) * (point[0] - x) + (point[1] - y) * (point[1] - y)).sqrt() > 4.);
+        if is_far {
+            data.push(point);
+            labels.push(true);
+        }
+    }
+
+    let data = Arc::new(data);
+    let datasets: Vec<Arc<dyn Dataset<f64, f64>>> = ["euclidean", "manhattan"]
+        .iter()
+        .map(|name| {
+            let dataset: Arc<dyn Dataset<f64, f64>> =
+                Arc::new(RowMajor::new(Arc::clone(&data), metric_from_name(name).unwrap(), true));
+            dataset
+        })
+        .collect();
+
+    let chaoda = clam::Chaoda::new(datasets, Some(20), None, clam::get_meta_ml_methods(), None, false);
+    assert_eq!(chaoda.scores.len(), data.len());
+    assert!(chaoda.scores.iter().all(|score| (0. ..=1.).contains(score)));
+
+    let auc = RocCurve::compute(&chaoda.scores, &labels).unwrap().auc();
+    println!(
+        "ROC AUC of CHAODA on {} instances with 20 outliers: {:.3}",
+        data.len(),
+        auc
+    );
+    assert!(
+        auc > 0.8,
+        "CHAODA should rank the scattered outliers above the inliers."
+    );
+}
@@ -0,0 +1,74 @@
+//! Compressive search: compress a dataset into a tree of encoded clusters, save it, load it and search it.
+//!
+//! Run with `cargo run --release --example compressed_search`.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::codec::Codec;
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+use clam::Cakes;
+
+fn main() -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(11);
+
+    // Binary strings that are small edits of a few prototypes, so they encode compactly in terms of each other.
+    let prototypes: Vec<Vec<u8>> = (0..8).map(|_| (0..64).map(|_| rng.gen_range(0..2)).collect()).collect();
+    let data: Vec<Vec<u8>> = (0..800)
+        .map(|i| {
+            let mut instance = prototypes[i % prototypes.len()].clone();
+            for _ in 0..3 {
+                let position = rng.gen_range(0..instance.len());
+                instance[position] = 1 - instance[position];
+            }
+            instance
+        })
+        .collect();
+
+    let metric = metric_from_name("hamming")?;
+    let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data.clone()), metric, false));
+    let original = Arc::clone(&row_major).as_arc_dataset();
+    let compressible = Arc::clone(&row_major).as_arc_compressible_dataset();
+    let cakes = Cakes::build(Arc::clone(&original), None, None);
+
+    let codec = Codec::from_cakes(&compressible, &cakes)?;
+    let path = std::env::temp_dir().join(format!("clam-example-{}.codec", std::process::id()));
+    clam::io::save_codec(&codec, &path)?;
+    let size = std::fs::metadata(&path).map_err(|error| error.to_string())?.len();
+    println!(
+        "Saved {} compressed clusters to a {} byte artifact.",
+        codec.tree_map.len(),
+        size
+    );
+
+    let loaded = clam::io::load_codec(&path, Arc::clone(&compressible))?;
+    std::fs::remove_file(&path).map_err(|error| error.to_string())?;
+
+    let report = clam::utils::verify_compression(&original, &loaded, None);
+    println!("Decoded {} instances: {:?}", report.checked, report);
+    assert!(report.is_ok(), "Every instance must decode losslessly.");
+
+    for query in data.iter().step_by(97) {
+        for radius in [0, 2, 6] {
+            let mut expected: Vec<Vec<u8>> = cakes
+                .rnn_indices(query, Some(radius))
+                .into_iter()
+                .map(|i| data[i].clone())
+                .collect();
+            let mut actual = loaded.rnn_instances(query, Some(radius))?;
+            expected.sort();
+            actual.sort();
+            assert_eq!(
+                actual, expected,
+                "Compressive search must find the same instances as CAKES."
+            );
+        }
+    }
+    println!("Compressive search agrees with CAKES.");
+
+    Ok(())
+}
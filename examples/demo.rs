@@ -0,0 +1,82 @@
+//! Build, search, compress and score the bundled demo datasets, checking the results against their known neighbors.
+//!
+//! Run with `cargo run --release --example demo --features demo`.
+
+use std::sync::Arc;
+
+use eval_metrics::classification::RocCurve;
+
+use clam::codec::Codec;
+use clam::dataset::RowMajor;
+use clam::datasets::DEMO_K;
+use clam::prelude::*;
+use clam::Cakes;
+
+fn main() -> Result<(), String> {
+    let demo = clam::datasets::demo()?;
+
+    let vectors = &demo.vectors;
+    let cakes = Cakes::build(vectors.dataset(), None, None);
+    let recalls: Vec<_> = vectors
+        .queries
+        .iter()
+        .enumerate()
+        .map(|(i, &query)| {
+            let exact = vectors.recall(i, &cakes.knn(&vectors.instances[query], DEMO_K));
+            let greedy = vectors.recall(i, &cakes.knn_beam(&vectors.instances[query], DEMO_K, 4));
+            (exact, greedy)
+        })
+        .collect();
+    let mean = |recalls: Vec<f64>| recalls.iter().sum::<f64>() / recalls.len() as f64;
+    let (exact, greedy): (Vec<_>, Vec<_>) = recalls.into_iter().unzip();
+    println!(
+        "Recall of {}-nn search on {}: {:.3} exact, {:.3} with a beam of 4.",
+        DEMO_K,
+        vectors.name,
+        mean(exact.clone()),
+        mean(greedy)
+    );
+    assert!(
+        exact.iter().all(|&recall| recall == 1.),
+        "CAKES must find the exact neighbors."
+    );
+
+    let sequences = &demo.sequences;
+    let metric = metric_from_name(sequences.metric)?;
+    let row_major = Arc::new(RowMajor::<u8, u64>::new(
+        Arc::new(sequences.instances.clone()),
+        metric,
+        false,
+    ));
+    let cakes = Cakes::build(Arc::clone(&row_major).as_arc_dataset(), None, None);
+    let codec = Codec::from_cakes(&row_major.as_arc_compressible_dataset(), &cakes)?;
+    for (i, &query) in sequences.queries.iter().enumerate() {
+        let radius = sequences.neighbors[i].last().unwrap().1;
+        let found = codec.rnn_instances(&sequences.instances[query], Some(radius))?;
+        assert!(
+            found.len() >= DEMO_K,
+            "Compressive search must find every known neighbor."
+        );
+    }
+    println!(
+        "Compressive search found the neighbors of {} {} queries.",
+        sequences.queries.len(),
+        sequences.name
+    );
+
+    let data = Arc::new(vectors.instances.clone());
+    let datasets: Vec<Arc<dyn Dataset<f64, f64>>> = ["euclidean", "manhattan"]
+        .iter()
+        .map(|name| {
+            let dataset: Arc<dyn Dataset<f64, f64>> =
+                Arc::new(RowMajor::new(Arc::clone(&data), metric_from_name(name).unwrap(), true));
+            dataset
+        })
+        .collect();
+    let chaoda = clam::Chaoda::new(datasets, Some(20), None, clam::get_meta_ml_methods(), None, false);
+    let labels: Vec<_> = vectors.labels.iter().map(|&label| label == 1).collect();
+    let auc = RocCurve::compute(&chaoda.scores, &labels).unwrap().auc();
+    println!("ROC AUC of CHAODA on {}: {:.3}", vectors.name, auc);
+
+    Ok(())
+}
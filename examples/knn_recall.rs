@@ -0,0 +1,60 @@
+//! k-nearest-neighbors search over dense vectors, checked against a linear scan.
+//!
+//! Run with `cargo run --release --example knn_recall`.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+use clam::Cakes;
+
+fn main() {
+    let (cardinality, dimensionality, num_queries, k) = (2_000, 16, 25, 10);
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let data: Vec<Vec<f32>> = (0..cardinality)
+        .map(|_| (0..dimensionality).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect();
+    let queries: Vec<Vec<f32>> = (0..num_queries)
+        .map(|_| (0..dimensionality).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect();
+
+    let metric = metric_from_name("euclidean").unwrap();
+    let dataset: Arc<dyn Dataset<f32, f32>> =
+        Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false));
+
+    let start = std::time::Instant::now();
+    let cakes = Cakes::build(dataset, None, None);
+    println!(
+        "Built a tree of {} clusters over {} instances in {:.2?}.",
+        cakes.root.num_descendants() + 1,
+        cardinality,
+        start.elapsed()
+    );
+
+    let mut found = 0;
+    for query in queries.iter() {
+        let mut exact: Vec<(usize, f32)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, instance)| (i, metric.distance(query, instance)))
+            .collect();
+        exact.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let exact: Vec<usize> = exact.into_iter().take(k).map(|(i, _)| i).collect();
+
+        let hits = cakes.knn_indices(query, k);
+        assert_eq!(hits.len(), k, "knn must return exactly k hits.");
+        found += hits.iter().filter(|i| exact.contains(i)).count();
+    }
+
+    let recall = found as f64 / (num_queries * k) as f64;
+    println!(
+        "Recall of {}-nearest neighbors over {} queries: {:.3}",
+        k, num_queries, recall
+    );
+    assert!(recall == 1., "CAKES is exact, so its recall must be 1.");
+}
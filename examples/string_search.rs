@@ -0,0 +1,89 @@
+//! Ranged nearest-neighbors search among strings, under a user-defined edit distance.
+//!
+//! Run with `cargo run --release --example string_search`.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+use clam::Cakes;
+
+/// The Levenshtein distance, i.e. the fewest insertions, deletions and substitutions turning one string into another.
+struct Levenshtein;
+
+impl Metric<u8, u32> for Levenshtein {
+    fn name(&self) -> String {
+        "levenshtein".to_string()
+    }
+
+    fn distance(&self, x: &[u8], y: &[u8]) -> u32 {
+        let mut previous: Vec<u32> = (0..=y.len() as u32).collect();
+        for (i, &a) in x.iter().enumerate() {
+            let mut current = vec![i as u32 + 1; y.len() + 1];
+            for (j, &b) in y.iter().enumerate() {
+                let substitution = previous[j] + u32::from(a != b);
+                current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            }
+            previous = current;
+        }
+        previous[y.len()]
+    }
+}
+
+/// Returns a copy of the sequence with a few random substitutions, insertions and deletions.
+fn mutate(rng: &mut StdRng, sequence: &[u8], num_edits: usize) -> Vec<u8> {
+    let mut sequence = sequence.to_vec();
+    for _ in 0..num_edits {
+        let position = rng.gen_range(0..sequence.len());
+        match rng.gen_range(0..3) {
+            0 => sequence[position] = b"ACGT"[rng.gen_range(0..4)],
+            1 => sequence.insert(position, b"ACGT"[rng.gen_range(0..4)]),
+            _ => {
+                sequence.remove(position);
+            }
+        }
+    }
+    sequence
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(7);
+
+    // Families of similar sequences, each descended from a random ancestor.
+    let ancestors: Vec<Vec<u8>> = (0..20)
+        .map(|_| (0..60).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+        .collect();
+    let sequences: Vec<Vec<u8>> = ancestors
+        .iter()
+        .flat_map(|ancestor| (0..25).map(|_| mutate(&mut rng, ancestor, 4)).collect::<Vec<_>>())
+        .collect();
+
+    let metric: Arc<dyn Metric<u8, u32>> = Arc::new(Levenshtein);
+    let dataset: Arc<dyn Dataset<u8, u32>> = Arc::new(RowMajor::new(Arc::new(sequences.clone()), metric, true));
+    let cakes = Cakes::build(dataset, None, None);
+    println!(
+        "Built a tree of {} clusters over {} sequences.",
+        cakes.root.num_descendants() + 1,
+        sequences.len()
+    );
+
+    let radius = 8;
+    for ancestor in ancestors.iter().take(5) {
+        let query = mutate(&mut rng, ancestor, 2);
+        let mut hits = cakes.rnn_indices(&query, Some(radius));
+        let mut expected = cakes.linear_search_indices(&query, Some(radius), None);
+        hits.sort_unstable();
+        expected.sort_unstable();
+        println!(
+            "Found {} sequences within {} edits of {}.",
+            hits.len(),
+            radius,
+            String::from_utf8_lossy(&query)
+        );
+        assert_eq!(hits, expected, "Tree search must agree with a linear scan.");
+    }
+}
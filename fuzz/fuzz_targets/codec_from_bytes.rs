@@ -0,0 +1,28 @@
+//! Deserializes compressed trees from arbitrary bytes and decodes every cluster.
+//!
+//! Run with `cargo fuzz run codec_from_bytes` from the root of the repository.
+
+#![no_main]
+
+use std::sync::Arc;
+
+use libfuzzer_sys::fuzz_target;
+
+use clam::dataset::RowMajor;
+use clam::prelude::*;
+
+fuzz_target!(|data: &[u8]| {
+    let metric = metric_from_name("hamming").unwrap();
+    let instances: Vec<Vec<u8>> = (0..16).map(|i| vec![i, i % 4, i % 2]).collect();
+    let dataset = Arc::new(RowMajor::<u8, u64>::new(Arc::new(instances), metric, false)).as_arc_compressible_dataset();
+
+    if let Ok(codec) = clam::io::codec_from_bytes(data, dataset) {
+        let metric = codec.dataset.metric();
+        for cluster in codec.tree_map.values() {
+            let _ = cluster.decode_center(&metric, &codec.center);
+            let _ = cluster.decode_instances(&metric, &codec.center);
+            let _ = codec.children(cluster);
+        }
+        let _ = clam::io::codec_to_bytes(&codec);
+    }
+});
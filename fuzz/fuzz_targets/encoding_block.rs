@@ -0,0 +1,27 @@
+//! Slices a block of encodings out of arbitrary bytes and iterates over it.
+//!
+//! Run with `cargo fuzz run encoding_block` from the root of the repository.
+
+#![no_main]
+
+use std::sync::Arc;
+
+use libfuzzer_sys::fuzz_target;
+
+use clam::codec::EncodingBlock;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let (start, end, len) = (data[0] as usize, data.len() - data[1] as usize, data[2] as usize);
+    if let Ok(block) = EncodingBlock::from_shared(Arc::new(data.to_vec()), start, end, len) {
+        let encodings: Result<Vec<Vec<u8>>, String> = block.iter().map(|e| e.map(|e| e.to_vec())).collect();
+        if let Ok(encodings) = encodings {
+            // The encodings may be followed by unread bytes at the end of the block.
+            let rebuilt = EncodingBlock::from_encodings(&encodings);
+            assert!(block.as_bytes().starts_with(rebuilt.as_bytes()));
+            assert_eq!(rebuilt.len(), block.len());
+        }
+    }
+});
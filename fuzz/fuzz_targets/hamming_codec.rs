@@ -0,0 +1,31 @@
+//! Encodes one instance in terms of another and decodes it back, and decodes arbitrary bytes.
+//!
+//! Run with `cargo fuzz run hamming_codec` from the root of the repository.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use clam::prelude::*;
+
+fuzz_target!(|data: &[u8]| {
+    let (reference, target) = data.split_at(data.len() / 2);
+    let target = &target[..reference.len()];
+
+    let metric = metric_from_name::<u8, u64>("hamming").unwrap();
+    let encoding = metric.encode(reference, target).unwrap();
+    assert_eq!(metric.decode(reference, &encoding).unwrap(), target);
+    let _ = metric.decode(reference, data);
+
+    let wide = |bytes: &[u8]| -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    };
+    let (reference, target) = (wide(reference), wide(target));
+    let metric = metric_from_name::<u16, u64>("hamming").unwrap();
+    let encoding = metric.encode(&reference, &target).unwrap();
+    assert_eq!(metric.decode(&reference, &encoding).unwrap(), target);
+    let _ = metric.decode(&reference, data);
+});
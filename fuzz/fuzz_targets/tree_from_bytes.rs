@@ -0,0 +1,28 @@
+//! Deserializes trees, and migrates artifacts, from arbitrary bytes.
+//!
+//! Run with `cargo fuzz run tree_from_bytes` from the root of the repository.
+
+#![no_main]
+
+use std::sync::Arc;
+
+use libfuzzer_sys::fuzz_target;
+
+use clam::dataset::RowMajor;
+use clam::io::TreeHandle;
+use clam::prelude::*;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = clam::io::migrate_bytes(data);
+
+    if let Ok(handle) = TreeHandle::<f32, f32>::from_bytes(data) {
+        // Attach small trees, whose indices are all in a dataset of 64 instances.
+        let metric = metric_from_name("euclidean").unwrap();
+        let instances: Vec<Vec<f32>> = (0..64).map(|i| vec![i as f32, (i % 8) as f32]).collect();
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::new(Arc::new(instances), metric, false));
+        match handle.attach(dataset) {
+            Ok(root) => assert_eq!(clam::io::tree_to_bytes(&root, handle.report(), handle.card()), data),
+            Err(_) => assert!(handle.min_cardinality() > 64),
+        }
+    }
+});
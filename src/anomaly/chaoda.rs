@@ -54,7 +54,7 @@ impl<T: Number + 'static, U: Number + 'static> Chaoda<T, U> {
 
         datasets
             .iter()
-            .inspect(|&dataset| println!("Building manifold with metric {}", dataset.metric_name()))
+            .inspect(|&dataset| log::info!("Building manifold with metric {}", dataset.metric_name()))
             .map(|dataset| Manifold::new(Arc::clone(dataset), partition_criteria))
             .collect()
     }
@@ -80,7 +80,7 @@ impl<T: Number + 'static, U: Number + 'static> Chaoda<T, U> {
             })
             .map(Arc::new)
             .collect::<Vec<_>>();
-        println!("Got {} Graphs.", graphs.len());
+        log::info!("Got {} Graphs.", graphs.len());
         mml_methods.into_iter().zip(graphs.into_iter()).collect()
     }
 
@@ -102,11 +102,11 @@ impl<T: Number + 'static, U: Number + 'static> Chaoda<T, U> {
                     || mml.algorithm_name == "vd"
                     || graph.cardinality <= speed_threshold
             })
-            .inspect(|(mml, _)| println!("Applying {} method", mml))
+            .inspect(|(mml, _)| log::debug!("Applying {} method", mml))
             .map(|(mml, graph)| Arc::clone(&mml.algorithm)(Arc::clone(graph)))
             // .inspect(|scores| self.print_vec(scores))
             .collect::<Vec<_>>();
-        println!("Scored {} algorithms.", individual_scores.len());
+        log::info!("Scored {} algorithms.", individual_scores.len());
 
         if individual_scores.is_empty() {
             vec![0.5; cardinality]
@@ -127,7 +127,7 @@ impl<T: Number + 'static, U: Number + 'static> Chaoda<T, U> {
     /// This is for debugging and will be removed in a later iteration of the code.
     #[allow(dead_code)]
     fn print_vec(&self, values: &[f64]) {
-        println!(
+        log::debug!(
             "{:?}\n",
             values
                 .iter()
@@ -4,7 +4,6 @@
 //! See the paper for details on each algorithm.
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::sync::Arc;
 
 use ndarray::prelude::*;
@@ -12,6 +11,7 @@ use rayon::prelude::*;
 
 use crate::core::Subsumed;
 use crate::prelude::*;
+use crate::utils::summation;
 
 // TODO: Create Struct for IndividualAlgorithm. Add decision methods for each to intelligently decide when to apply the algorithm.
 // This should be used to handle speed thresholds and also to avoid the case where all clusters would be assigned the same score.
@@ -81,15 +81,10 @@ fn normalize<T: Number, U: Number>(scores: ClusterScores<T, U>) -> ClusterScores
 
     let num_scores = scores.len() as f64;
 
-    let mean = scores.iter().sum::<f64>() / num_scores;
-    let std_dev = 1e-8
-        + scores
-            .par_iter()
-            .map(|&score| score - mean)
-            .map(|difference| difference.powi(2))
-            .sum::<f64>()
-            .sqrt()
-            / num_scores;
+    // The clusters come from sets, so their scores are summed in sorted order to be the same on every run.
+    let mean = summation::sum_unordered(&scores) / num_scores;
+    let squares: Vec<_> = scores.iter().map(|&score| (score - mean).powi(2)).collect();
+    let std_dev = 1e-8 + summation::sum_unordered(&squares).sqrt() / num_scores;
 
     let scores: Vec<_> = scores
         .into_par_iter()
@@ -103,7 +98,7 @@ fn normalize<T: Number, U: Number>(scores: ClusterScores<T, U>) -> ClusterScores
 
 fn _cluster_cardinality<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> ClusterScores<T, U> {
     graph
-        .clusters
+        .vertices()
         .par_iter()
         .map(|cluster| (Arc::clone(cluster), -(cluster.cardinality as f64)))
         .collect()
@@ -115,7 +110,7 @@ fn _subgraph_cardinality<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> Clust
         .par_iter()
         .flat_map(|component| {
             component
-                .clusters
+                .vertices()
                 .par_iter()
                 .map(|cluster| (Arc::clone(cluster), -(component.cardinality as f64)))
         })
@@ -130,33 +125,30 @@ fn find_neighborhood_size<T: Number, U: Number>(
 ) -> usize {
     let steps_to_go = (eccentricity_fraction * (graph.eccentricity(&start).unwrap() as f64) + 1.) as usize;
 
-    let mut visited = HashSet::new();
+    let mut visited = vec![false; graph.cardinality];
+    let mut num_visited = 0;
 
-    let mut frontier = HashSet::new();
-    frontier.insert(Arc::clone(&start));
+    let mut frontier = vec![graph.id_of(&start).unwrap()];
 
     for _ in 0..steps_to_go {
         if frontier.is_empty() {
             break;
         } else {
-            visited.extend(frontier.iter().cloned());
+            frontier.iter().for_each(|&i| visited[i] = true);
+            num_visited += frontier.len();
 
-            frontier = frontier
-                .par_iter()
-                .flat_map(|cluster| {
-                    graph
-                        .neighbors(cluster)
-                        .unwrap()
-                        .par_iter()
-                        .filter(|&neighbor| !visited.contains(neighbor))
-                        .map(Arc::clone)
-                        .collect::<Vec<_>>()
-                })
+            let mut new_frontier: Vec<_> = frontier
+                .iter()
+                .flat_map(|&i| graph.neighbor_ids(i).iter().copied())
+                .filter(|&j| !visited[j])
                 .collect();
+            new_frontier.sort_unstable();
+            new_frontier.dedup();
+            frontier = new_frontier;
         }
     }
 
-    visited.len()
+    num_visited
 }
 
 /// Compute scores for subsumed clusters given scores for the subsumer clusters.
@@ -191,7 +183,7 @@ fn _graph_neighborhood<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> Cluster
     let (pruned_graph, subsumed_neighbors) = graph.pruned_graph();
 
     let scores: HashMap<_, _> = pruned_graph
-        .clusters
+        .vertices()
         .par_iter()
         .map(|cluster| {
             let score = find_neighborhood_size(Arc::clone(&pruned_graph), Arc::clone(cluster), eccentricity_fraction);
@@ -245,8 +237,7 @@ fn _stationary_probabilities<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> C
                     .collect()
             } else {
                 component
-                    .clusters
-                    .iter()
+                    .clusters()
                     .map(|cluster| (Arc::clone(cluster), 0_f64))
                     .collect()
             };
@@ -260,7 +251,7 @@ fn _stationary_probabilities<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> C
 fn _cardinality_ratio<T: Number, U: Number>(graph: Arc<Graph<T, U>>) -> ClusterScores<T, U> {
     let weight = |i: usize| 1. / (i as f64 * 0.5);
     graph
-        .clusters
+        .vertices()
         .par_iter()
         .map(|cluster| {
             let ancestors = cluster.ancestry();
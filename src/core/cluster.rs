@@ -3,26 +3,98 @@
 //! Define and implement the `Cluster` struct.
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::PoisonError;
 use std::sync::RwLock;
 use std::sync::Weak;
 
 use bitvec::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rayon::prelude::*;
 
 use crate::core::Ratios;
+use crate::io::content_hash;
+use crate::memory::Tracked;
 use crate::prelude::*;
 use crate::utils::argmax;
 use crate::utils::argmin;
+use crate::utils::parallelism;
+use crate::utils::read_cache;
+use crate::utils::summation;
+use crate::utils::write_cache;
 use criteria::PartitionCriterion;
 
 const SUB_SAMPLE_LIMIT: usize = 100;
 
 /// A 2-tuple of `Arc<Cluster>` representing the two child `Clusters`
 /// formed when a `Cluster` is partitioned.
-type Children<T, U> = (Arc<Cluster<T, U>>, Arc<Cluster<T, U>>);
+pub(crate) type Children<T, U> = (Arc<Cluster<T, U>>, Arc<Cluster<T, U>>);
+
+/// The bytes held while a tree is partitioned, in the clusters built so far and in the scratch of the partitions in
+/// progress, along with their high-water mark.
+#[derive(Debug, Default)]
+pub(crate) struct BuildGauge {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl BuildGauge {
+    pub fn grow(&self, bytes: usize) {
+        let current = self.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    pub fn shrink(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the largest number of bytes held at once.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// How the center of a `Cluster` is chosen. The center is always one of the instances in the `Cluster`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CenterPolicy {
+    /// The medoid of all instances in clusters of at most 100 instances,
+    /// and of a random sample of `sqrt(n)` instances in larger clusters.
+    #[default]
+    Sampled,
+
+    /// The medoid of all instances. This takes `n^2` distance computations.
+    Exact,
+
+    /// The medoid of a random sample of at most the given number of instances.
+    SampleSize(usize),
+
+    /// The instance closest to the element-wise mean of the instances. This only makes sense for vector data.
+    /// It takes `n` computations of the metric, which bypass the `Dataset`, e.g. its cache.
+    Centroid,
+}
+
+/// How the radius of a `Cluster` is found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RadiusPolicy {
+    /// The distance from the center to the farthest instance. This takes `n` distance computations.
+    #[default]
+    Exact,
+
+    /// The distance from the center to the farthest instance in a random sample of at most the given number of
+    /// instances. The estimate may be smaller than the exact radius, and a cluster whose estimate is zero is treated
+    /// as a singleton.
+    ///
+    /// Searches prune clusters by their radii, so `rnn` and `knn` on such a tree may miss true hits and are no
+    /// longer exact. They give no warning when this happens.
+    /// See `Cluster::sampling_errors` for measuring the error and `Cluster::tighten_radii` for restoring exactness.
+    SampleSize(usize),
+}
 
 /// A collection of similar `Instances` from a `Dataset`.
 ///
@@ -68,6 +140,20 @@ pub struct Cluster<T: Number, U: Number> {
 
     /// `Cluster` ratios for meta-ml functions.
     pub ratios: Ratios,
+
+    /// How the centers of this `Cluster` and of the `Clusters` partitioned from it are chosen.
+    pub center_policy: CenterPolicy,
+
+    /// How the radii of this `Cluster` and of the `Clusters` partitioned from it are found.
+    pub radius_policy: RadiusPolicy,
+
+    /// The seed of the samples of this `Cluster` and of the `Clusters` partitioned from it, if the build was seeded.
+    /// Each cluster samples with its own generator, seeded with this seed and its name, so that a seeded build samples
+    /// the same instances however its clusters are scheduled on threads.
+    pub seed: Option<u64>,
+
+    /// The memory held by this `Cluster`, as counted by `memory_report`. It is released when the `Cluster` is dropped.
+    pub(crate) _memory: Tracked,
 }
 
 impl<T: Number, U: Number> PartialEq for Cluster<T, U> {
@@ -104,6 +190,30 @@ impl<T: Number, U: Number> Hash for Cluster<T, U> {
     }
 }
 
+/// Descendants are dropped with an explicit stack, rather than by recursion, so that dropping a deep tree cannot
+/// overflow the stack.
+impl<T: Number, U: Number> Drop for Cluster<T, U> {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        let mut children = self.children.get_mut().unwrap_or_else(PoisonError::into_inner).take();
+        loop {
+            if let Some((left, right)) = children {
+                stack.extend([left, right]);
+            }
+            // A cluster that is still shared elsewhere keeps its children.
+            children = match stack.pop().map(Arc::try_unwrap) {
+                Some(Ok(mut cluster)) => cluster
+                    .children
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take(),
+                Some(Err(_)) => None,
+                None => break,
+            };
+        }
+    }
+}
+
 impl<T: Number, U: Number> std::fmt::Display for Cluster<T, U> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let name_str: Vec<&str> = self.name.iter().map(|b| if *b { "1" } else { "0" }).collect();
@@ -118,9 +228,36 @@ impl<T: Number, U: Number> Cluster<T, U> {
     ///
     /// * `dataset`: A reference to a struct that implements the `Dataset` trait.
     pub fn new_root(dataset: Arc<dyn Dataset<T, U>>) -> Arc<Self> {
+        Cluster::new_root_with_center_policy(dataset, CenterPolicy::default())
+    }
+
+    /// Creates a new root `Cluster` on the entire dataset whose center, and
+    /// the centers of its descendants, are chosen by the given policy.
+    pub fn new_root_with_center_policy(dataset: Arc<dyn Dataset<T, U>>, center_policy: CenterPolicy) -> Arc<Self> {
+        Cluster::new_root_with_policies(dataset, center_policy, RadiusPolicy::default())
+    }
+
+    /// Creates a new root `Cluster` on the entire dataset whose center and
+    /// radius, and those of its descendants, are found by the given policies.
+    pub fn new_root_with_policies(
+        dataset: Arc<dyn Dataset<T, U>>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+    ) -> Arc<Self> {
+        Cluster::new_root_seeded(dataset, center_policy, radius_policy, None)
+    }
+
+    /// Creates a new root `Cluster`, as with `new_root_with_policies`, whose samples, and those of its descendants,
+    /// are drawn from generators seeded with the given seed, if any.
+    pub fn new_root_seeded(
+        dataset: Arc<dyn Dataset<T, U>>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+        seed: Option<u64>,
+    ) -> Arc<Self> {
         let name = bitvec![1];
         let indices = dataset.indices();
-        Cluster::new(dataset, name, indices, None, None)
+        Cluster::build(dataset, name, indices, None, None, (center_policy, radius_policy, seed))
     }
 
     /// Creates a new `Cluster`.
@@ -139,10 +276,80 @@ impl<T: Number, U: Number> Cluster<T, U> {
         parent: Option<Weak<Cluster<T, U>>>,
         parent_ratios: Option<Ratios>,
     ) -> Arc<Self> {
+        Cluster::new_with_center_policy(dataset, name, indices, parent, parent_ratios, CenterPolicy::default())
+    }
+
+    /// Creates a new `Cluster` whose center is chosen by the given policy.
+    /// See `new` for the other arguments.
+    pub fn new_with_center_policy(
+        dataset: Arc<dyn Dataset<T, U>>,
+        name: BitVec,
+        indices: Vec<Index>,
+        parent: Option<Weak<Cluster<T, U>>>,
+        parent_ratios: Option<Ratios>,
+        center_policy: CenterPolicy,
+    ) -> Arc<Self> {
+        Cluster::new_with_policies(
+            dataset,
+            name,
+            indices,
+            parent,
+            parent_ratios,
+            center_policy,
+            RadiusPolicy::default(),
+        )
+    }
+
+    /// Creates a new `Cluster` whose center and radius are found by the given policies.
+    /// See `new` for the other arguments.
+    pub fn new_with_policies(
+        dataset: Arc<dyn Dataset<T, U>>,
+        name: BitVec,
+        indices: Vec<Index>,
+        parent: Option<Weak<Cluster<T, U>>>,
+        parent_ratios: Option<Ratios>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+    ) -> Arc<Self> {
+        Cluster::build(
+            dataset,
+            name,
+            indices,
+            parent,
+            parent_ratios,
+            (center_policy, radius_policy, None),
+        )
+    }
+
+    /// Creates the child of this `Cluster` with the given name and indices, with the policies and seed of this
+    /// `Cluster`.
+    fn new_child(self: &Arc<Self>, name: BitVec, indices: Vec<Index>) -> Arc<Self> {
+        Cluster::build(
+            Arc::clone(&self.dataset),
+            name,
+            indices,
+            Some(Arc::downgrade(self)),
+            Some(self.ratios),
+            (self.center_policy, self.radius_policy, self.seed),
+        )
+    }
+
+    fn build(
+        dataset: Arc<dyn Dataset<T, U>>,
+        name: BitVec,
+        mut indices: Vec<Index>,
+        parent: Option<Weak<Cluster<T, U>>>,
+        parent_ratios: Option<Ratios>,
+        (center_policy, radius_policy, seed): (CenterPolicy, RadiusPolicy, Option<u64>),
+    ) -> Arc<Self> {
+        // The indices of a child are split from those of its parent by a parallel partition, whose spare capacity
+        // would otherwise last as long as the tree.
+        indices.shrink_to_fit();
         let mut cluster = Cluster {
             dataset,
             name,
             cardinality: indices.len(),
+            _memory: Tracked::cluster::<T, U>(indices.len()),
             indices,
             children: RwLock::new(None),
             argcenter: 0,
@@ -151,6 +358,9 @@ impl<T: Number, U: Number> Cluster<T, U> {
             lfd: 0.,
             parent,
             ratios: [1.; 6],
+            center_policy,
+            radius_policy,
+            seed,
         };
         cluster.argcenter = cluster.argcenter();
 
@@ -219,6 +429,24 @@ impl<T: Number, U: Number> Cluster<T, U> {
         self.dataset.instance(self.argcenter)
     }
 
+    /// Returns whether the radius was estimated from a sample of the instances rather than found exactly.
+    pub fn radius_is_estimated(&self) -> bool {
+        self.radius_sample_size().is_some()
+    }
+
+    /// Returns the number of instances sampled to estimate the radius, or None if it is exact.
+    fn radius_sample_size(&self) -> Option<usize> {
+        match self.radius_policy {
+            RadiusPolicy::SampleSize(size) if self.cardinality > size.max(1) => Some(size.max(1)),
+            _ => None,
+        }
+    }
+
+    /// An estimate of the bytes held by the `Cluster`, excluding its children.
+    pub(crate) fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Cluster<T, U>>() + self.indices.len() * std::mem::size_of::<Index>() + self.name.len() / 8
+    }
+
     /// Returns whether this `Cluster` contains only one unique `Instance` or its radius is 0.
     pub fn is_singleton(&self) -> bool {
         self.radius == U::from(0).unwrap()
@@ -242,7 +470,7 @@ impl<T: Number, U: Number> Cluster<T, U> {
     pub fn find_candidates(&self, parent_candidates: &HashMap<Arc<Cluster<T, U>>, U>) -> HashMap<Arc<Cluster<T, U>>, U> {
         let mut candidates: Vec<_> = parent_candidates.iter().map(|(c, _)| Arc::clone(c)).collect();
         candidates.extend(parent_candidates.iter().flat_map(|(candidate, _)| {
-            match candidate.children.read().unwrap().clone() {
+            match read_cache(&candidate.children).clone() {
                 Some((left, right)) => vec![left, right],
                 None => Vec::new(),
             }
@@ -258,7 +486,7 @@ impl<T: Number, U: Number> Cluster<T, U> {
     /// 
     /// Returns the name of all clusters that had the instance added to them (along the branch of the subtree)
     pub fn add_instance(&self, instance: &[T], distance_to_self: U) -> HashMap<BitVec, U> {
-        let mut result = match self.children.read().unwrap().clone() {
+        let mut result = match read_cache(&self.children).clone() {
             Some((left, right)) => {
                 let distance_to_left = left.distance_to_instance(instance);
                 let distance_to_right = right.distance_to_instance(instance);
@@ -280,6 +508,7 @@ impl<T: Number, U: Number> Cluster<T, U> {
         let indices: Vec<Index> = self
             .indices
             .par_iter()
+            .with_min_len(parallelism::min_len())
             .filter(|&&i| i != self.argradius)
             .cloned()
             .collect();
@@ -298,23 +527,40 @@ impl<T: Number, U: Number> Cluster<T, U> {
     ///   Each `PartitionCriterion` must evaluate to `true` otherwise the `Cluster`
     ///   cannot be partitioned.
     pub fn partition(self: Arc<Self>, criteria: &[PartitionCriterion<T, U>]) -> Arc<Self> {
+        let gauge = BuildGauge::default();
+        gauge.grow(self.memory_bytes());
+        self.partition_gauged(criteria, &gauge)
+    }
+
+    /// Partitions the cluster as with `partition`, counting in the gauge the bytes of the clusters it builds and of
+    /// the scratch it holds meanwhile. The bytes of this cluster must already be counted.
+    pub(crate) fn partition_gauged(
+        self: Arc<Self>,
+        criteria: &[PartitionCriterion<T, U>],
+        gauge: &BuildGauge,
+    ) -> Arc<Self> {
         // Cannot partition a singleton cluster.
         if self.is_singleton() {
             return self;
         }
 
         // The cluster may only be partitioned if it passes all criteria
-        if criteria.par_iter().any(|criterion| !criterion(&self)) {
+        if criteria.iter().any(|criterion| !criterion(&self)) {
             return self;
         }
 
-        // Get indices of left and right poles
+        // Get indices of left and right poles, from the distances of the other instances to the radial instance.
+        let pole_scratch = self.cardinality * (std::mem::size_of::<Index>() + std::mem::size_of::<U>());
+        gauge.grow(pole_scratch);
         let (left, right) = self.poles();
+        gauge.shrink(pole_scratch);
 
         // Split cluster indices by proximity to left or right pole
+        gauge.grow(self.cardinality * std::mem::size_of::<Index>());
         let (left, right): (Vec<Index>, Vec<Index>) = self
             .indices
             .par_iter()
+            .with_min_len(parallelism::min_len())
             .partition(|&&i| self.dataset.distance(left, i) <= self.dataset.distance(i, right));
 
         // Ensure that left cluster is more populated than right cluster.
@@ -334,45 +580,244 @@ impl<T: Number, U: Number> Cluster<T, U> {
             name
         };
 
-        // Recursively apply partition to child clusters.
-        let (left, right) = rayon::join(
-            || {
-                Cluster::new(
-                    Arc::clone(&self.dataset),
-                    left_name,
-                    left,
-                    Some(Arc::downgrade(&self)),
-                    Some(self.ratios),
-                )
-                .partition(criteria)
-            },
-            || {
-                Cluster::new(
-                    Arc::clone(&self.dataset),
-                    right_name,
-                    right,
-                    Some(Arc::downgrade(&self)),
-                    Some(self.ratios),
-                )
-                .partition(criteria)
-            },
+        // Recursively apply partition to child clusters, whose indices are moved out of the split.
+        let child = |name: BitVec, indices: Vec<Index>| {
+            let child = self.new_child(name, indices);
+            gauge.grow(child.memory_bytes());
+            gauge.shrink(child.cardinality * std::mem::size_of::<Index>());
+            child.partition_gauged(criteria, gauge)
+        };
+        let (left, right) = parallelism::join(self.cardinality, || child(left_name, left), || child(right_name, right));
+
+        *write_cache(&self.children) = Some((left, right));
+        self
+    }
+
+    /// Recursively partition the cluster so that each of the given groups of
+    /// indices forms the subtree of one descendant, then partition each group
+    /// until some `criterion` determines that a leaf cluster has been reached.
+    /// Returns a new cluster containing the built subtree.
+    ///
+    /// The groups are split into two sets of roughly equal cardinality, with the
+    /// more populated set on the left, until a single group remains.
+    /// These splits are made regardless of the `criteria`.
+    ///
+    /// # Arguments
+    ///
+    /// * `groups`: Disjoint, non-empty groups whose union is the indices of the `Cluster`.
+    /// * `partition_criteria`: A collection of `PartitionCriterion`, used within each group.
+    pub fn partition_groups(
+        self: Arc<Self>,
+        mut groups: Vec<Vec<Index>>,
+        criteria: &[PartitionCriterion<T, U>],
+    ) -> Arc<Self> {
+        if groups.len() < 2 {
+            return self.partition(criteria);
+        }
+
+        // Assign the largest remaining group to the less populated side.
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+        let (mut left, mut right) = (vec![], vec![]);
+        let (mut left_cardinality, mut right_cardinality) = (0, 0);
+        for group in groups.into_iter() {
+            if left_cardinality <= right_cardinality {
+                left_cardinality += group.len();
+                left.push(group);
+            } else {
+                right_cardinality += group.len();
+                right.push(group);
+            }
+        }
+        let (left, right) = if right_cardinality > left_cardinality {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
+        let child = |groups: Vec<Vec<Index>>, bit: bool| {
+            let mut name = self.name.clone();
+            name.push(bit);
+            let mut indices: Vec<Index> = groups.iter().flatten().cloned().collect();
+            indices.sort_unstable();
+            self.new_child(name, indices).partition_groups(groups, criteria)
+        };
+        let (left, right) = parallelism::join(self.cardinality, || child(left, false), || child(right, true));
+
+        *write_cache(&self.children) = Some((left, right));
+        self
+    }
+
+    /// Recursively partition the cluster, as with `partition`, except that until
+    /// the given depth each of the given units of indices is kept whole.
+    /// Each unit is placed with the pole to which most of its instances are closer.
+    /// Returns a new cluster containing the built subtree.
+    ///
+    /// # Arguments
+    ///
+    /// * `units`: Disjoint, non-empty groups whose union is the indices of the `Cluster`.
+    /// * `depth`: Clusters deeper than this are partitioned as usual.
+    /// * `partition_criteria`: A collection of `PartitionCriterion`.
+    pub fn partition_cohesive(
+        self: Arc<Self>,
+        units: Vec<Vec<Index>>,
+        depth: usize,
+        criteria: &[PartitionCriterion<T, U>],
+    ) -> Arc<Self> {
+        if self.depth() >= depth || units.len() < 2 {
+            return self.partition(criteria);
+        }
+        if self.is_singleton() || criteria.iter().any(|criterion| !criterion(&self)) {
+            return self;
+        }
+
+        // For each unit, the number of its instances closer to the left pole less the number closer to the right.
+        // The votes are scoped so that they are released before the recursion into the children.
+        let (left, right) = self.poles();
+        let to_left = {
+            let votes: Vec<i64> = units
+                .par_iter()
+                .map(|unit| {
+                    unit.iter()
+                        .map(|&i| {
+                            if self.dataset.distance(left, i) <= self.dataset.distance(i, right) {
+                                1
+                            } else {
+                                -1
+                            }
+                        })
+                        .sum()
+                })
+                .collect();
+            let mut to_left: Vec<bool> = votes.iter().map(|&vote| vote >= 0).collect();
+
+            // If every unit prefers the same pole, the one with the weakest preference goes to the other.
+            if to_left.iter().all(|&l| l) || to_left.iter().all(|&l| !l) {
+                let (weakest, _) = votes.iter().enumerate().min_by_key(|(_, vote)| vote.abs()).unwrap();
+                to_left[weakest] = !to_left[weakest];
+            }
+            to_left
+        };
+
+        let (left, right): (Vec<_>, Vec<_>) = units.into_iter().zip(to_left).partition(|(_, l)| *l);
+        let (left, right): (Vec<_>, Vec<_>) = (
+            left.into_iter().map(|(unit, _)| unit).collect(),
+            right.into_iter().map(|(unit, _)| unit).collect(),
         );
 
-        *self.children.write().unwrap() = Some((left, right));
+        // Ensure that left cluster is more populated than right cluster.
+        let cardinality = |units: &[Vec<Index>]| units.iter().map(|unit| unit.len()).sum::<usize>();
+        let (left, right) = if cardinality(&right) > cardinality(&left) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
+        let child = |units: Vec<Vec<Index>>, bit: bool| {
+            let mut name = self.name.clone();
+            name.push(bit);
+            let mut indices: Vec<Index> = units.iter().flatten().cloned().collect();
+            indices.sort_unstable();
+            self.new_child(name, indices).partition_cohesive(units, depth, criteria)
+        };
+        let (left, right) = parallelism::join(self.cardinality, || child(left, false), || child(right, true));
+
+        *write_cache(&self.children) = Some((left, right));
         self
     }
 
+    /// Returns a copy of the subtree in which every radius that was estimated
+    /// from a sample is replaced by the exact radius.
+    /// Ratios are recomputed to match. Subtrees are copied in parallel.
+    ///
+    /// # Arguments
+    ///
+    /// * `depths`: Only radii at these depths are recomputed. All are recomputed if None.
+    pub fn tighten_radii(&self, depths: Option<&[usize]>) -> Arc<Self> {
+        self.updated(None, None, &|copy| {
+            if copy.radius_is_estimated() && depths.is_none_or(|depths| depths.contains(&copy.depth())) {
+                copy.radius_policy = RadiusPolicy::Exact;
+                let (argradius, radius, lfd) = copy.argradius_radius_lfd();
+                copy.argradius = argradius;
+                copy.radius = radius;
+                copy.lfd = lfd;
+            }
+        })
+    }
+
+    /// Returns a copy of the subtree in which the cardinality, radius and LFD of each of the named clusters is
+    /// recomputed from its indices, e.g. for the clusters that gained instances after the tree was built.
+    /// Only the named clusters make distance calls. Ratios are recomputed throughout, since they depend on the
+    /// ancestors of each cluster. Subtrees are copied in parallel.
+    ///
+    /// An estimated radius never shrinks, so that a radius grown to cover an inserted instance stays a bound for it.
+    pub fn refresh_statistics(&self, stale: &HashSet<BitVec>) -> Arc<Self> {
+        self.updated(None, None, &|copy| {
+            if stale.contains(&copy.name) {
+                copy.cardinality = copy.indices.len();
+                let (argradius, radius, lfd) = copy.argradius_radius_lfd();
+                if !copy.radius_is_estimated() || radius >= copy.radius {
+                    copy.argradius = argradius;
+                    copy.radius = radius;
+                }
+                copy.lfd = lfd;
+            }
+        })
+    }
+
+    /// Copies the subtree, applying the update to each copy before its ratios are recomputed.
+    fn updated(
+        &self,
+        parent: Option<Weak<Self>>,
+        parent_ratios: Option<Ratios>,
+        update: &(dyn Fn(&mut Self) + Sync),
+    ) -> Arc<Self> {
+        let mut copy = Cluster {
+            dataset: Arc::clone(&self.dataset),
+            name: self.name.clone(),
+            cardinality: self.cardinality,
+            _memory: Tracked::cluster::<T, U>(self.cardinality),
+            indices: self.indices.clone(),
+            argcenter: self.argcenter,
+            argradius: self.argradius,
+            radius: self.radius,
+            lfd: self.lfd,
+            children: RwLock::new(None),
+            parent,
+            ratios: self.ratios,
+            center_policy: self.center_policy,
+            radius_policy: self.radius_policy,
+            seed: self.seed,
+        };
+        update(&mut copy);
+        if let Some(parent_ratios) = parent_ratios {
+            copy.ratios = copy.ratios(parent_ratios);
+        }
+
+        let copy = Arc::new(copy);
+        if let Some((left, right)) = read_cache(&self.children).clone() {
+            let parent = || Some(Arc::downgrade(&copy));
+            let (left, right) = parallelism::join(
+                self.cardinality,
+                || left.updated(parent(), Some(copy.ratios), update),
+                || right.updated(parent(), Some(copy.ratios), update),
+            );
+            *write_cache(&copy.children) = Some((left, right));
+        }
+        copy
+    }
+
     /// Returns a Vec of clusters containing all descendants of the cluster excluding itself.
     pub fn flatten_tree(&self) -> Vec<Arc<Cluster<T, U>>> {
-        match self.children.read().unwrap().clone() {
-            Some((left, right)) => {
-                let mut descendants = vec![Arc::clone(&left), Arc::clone(&right)];
-                descendants.append(&mut left.flatten_tree());
-                descendants.append(&mut right.flatten_tree());
-                descendants
-            }
-            None => vec![],
+        // Each cluster is followed by the children of its left child, and so on, as if by recursion, but with an
+        // explicit stack so that a deep tree cannot overflow the stack.
+        let mut descendants = vec![];
+        let mut stack = read_cache(&self.children).clone().into_iter().collect::<Vec<_>>();
+        while let Some((left, right)) = stack.pop() {
+            descendants.extend([Arc::clone(&left), Arc::clone(&right)]);
+            stack.extend(read_cache(&right.children).clone());
+            stack.extend(read_cache(&left.children).clone());
         }
+        descendants
     }
 
     /// Returns the number of clusters in the subtree rooted at
@@ -388,14 +833,42 @@ impl<T: Number, U: Number> Cluster<T, U> {
         if self.cardinality <= SUB_SAMPLE_LIMIT {
             self.indices.clone()
         } else {
-            self.dataset
-                .choose_unique(self.indices.clone(), (self.cardinality as f64).sqrt() as usize)
+            self.choose_unique((self.cardinality as f64).sqrt() as usize, "center")
+        }
+    }
+
+    /// Returns `n` unique instances sampled from the cluster, as by `Dataset::choose_unique`. A seeded cluster draws
+    /// them from a generator seeded with its seed, its name and the purpose of the sample.
+    fn choose_unique(&self, n: usize, purpose: &str) -> Vec<Index> {
+        match self.seed {
+            Some(seed) => {
+                let mut bytes = purpose.as_bytes().to_vec();
+                bytes.extend(self.name.iter().map(|bit| u8::from(*bit)));
+                let mut rng = StdRng::seed_from_u64(seed ^ content_hash(&bytes));
+                self.dataset.choose_unique_with_rng(self.indices.clone(), n, &mut rng)
+            }
+            None => self.dataset.choose_unique(self.indices.clone(), n),
         }
     }
 
     /// Returns the `Index` of the `center` of the `Cluster`.
     fn argcenter(&self) -> Index {
-        let argsamples = self.argsamples();
+        match self.center_policy {
+            CenterPolicy::Sampled => self.argmedoid(self.argsamples()),
+            CenterPolicy::Exact => self.argmedoid(self.indices.clone()),
+            CenterPolicy::SampleSize(size) => {
+                if self.cardinality <= size {
+                    self.argmedoid(self.indices.clone())
+                } else {
+                    self.argmedoid(self.choose_unique(size.max(1), "center"))
+                }
+            }
+            CenterPolicy::Centroid => self.argcentroid(),
+        }
+    }
+
+    /// Returns the instance among the given samples with the smallest sum of distances to the other samples.
+    fn argmedoid(&self, argsamples: Vec<Index>) -> Index {
         let distances: Vec<U> = self
             .dataset
             .pairwise_distances(&argsamples)
@@ -406,20 +879,56 @@ impl<T: Number, U: Number> Cluster<T, U> {
         argsamples[argcenter]
     }
 
+    /// Returns the instance closest to the element-wise mean of all instances.
+    ///
+    /// Each dimension is summed over the instances in the order of `indices`, so the centroid is the same for any
+    /// number of threads. See `utils::summation`.
+    fn argcentroid(&self) -> Index {
+        let instances: Vec<_> = self
+            .indices
+            .par_iter()
+            .with_min_len(parallelism::min_len())
+            .map(|&i| self.dataset.instance(i))
+            .collect();
+        let dimensionality = instances.iter().map(|instance| instance.len()).min().unwrap_or(0);
+        let centroid: Vec<T> = (0..dimensionality)
+            .into_par_iter()
+            .map(|d| {
+                let column: Vec<_> = instances.iter().map(|instance| instance[d].as_f64()).collect();
+                T::from(summation::sum(&column) / self.cardinality as f64).unwrap()
+            })
+            .collect();
+
+        let metric = self.dataset.metric();
+        let distances: Vec<U> = self
+            .indices
+            .par_iter()
+            .with_min_len(parallelism::min_len())
+            .map(|&i| metric.distance(&centroid, &self.dataset.instance(i)))
+            .collect();
+        let (argcenter, _) = argmin(&distances);
+        self.indices[argcenter]
+    }
+
     /// Returns the index of the farthest point from the center, the distance to that point, and the local fractal dimension of the cluster.
     fn argradius_radius_lfd(&self) -> (Index, U, f64) {
-        let distances = self.dataset.distances_from(self.argcenter, &self.indices).to_vec();
+        let argsamples = match self.radius_sample_size() {
+            Some(size) => self.choose_unique(size, "radius"),
+            None => self.indices.clone(),
+        };
+        let distances = self.dataset.distances_from(self.argcenter, &argsamples).to_vec();
         let (argradius, _) = argmax(&distances);
 
-        let argradius = self.indices[argradius];
+        let argradius = argsamples[argradius];
         let radius = self.dataset.distance(self.argcenter, argradius);
 
         let half_count = distances
             .into_par_iter()
+            .with_min_len(parallelism::min_len())
             .filter(|&distance| distance <= (radius / U::from(2).unwrap()))
             .count();
         let lfd = if half_count > 0 {
-            ((self.cardinality as f64) / (half_count as f64)).log2()
+            ((argsamples.len() as f64) / (half_count as f64)).log2()
         } else {
             1.
         };
@@ -434,6 +943,10 @@ mod tests {
 
     use crate::dataset::RowMajor;
     use crate::prelude::*;
+    use crate::utils::parallelism;
+    use crate::CenterPolicy;
+    use crate::RadiusPolicy;
+    use crate::Stratification;
 
     #[test]
     fn test_cluster() {
@@ -461,7 +974,11 @@ mod tests {
             format!("lfd: {:?},", cluster.lfd),
             format!("children: {:?},", cluster.children),
             format!("parent: {:?},", cluster.parent),
-            format!("ratios: {:?}", cluster.ratios),
+            format!("ratios: {:?},", cluster.ratios),
+            format!("center_policy: {:?},", cluster.center_policy),
+            format!("radius_policy: {:?},", cluster.radius_policy),
+            format!("seed: {:?},", cluster.seed),
+            format!("_memory: {:?}", cluster._memory),
             "}".to_string(),
         ]
         .join(" ");
@@ -478,6 +995,212 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_partition_groups() {
+        let data: Vec<_> = (0..30).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::<f64, f64>::new(Arc::new(data), metric, false));
+        let criteria = vec![criteria::min_cardinality(1)];
+
+        // Interleaved groups, which a metric partition would never produce, of 15, 10 and 5 instances.
+        let groups: Vec<Vec<Index>> = vec![
+            (0..30).filter(|i| i % 2 == 0).collect(),
+            (0..30).filter(|i| i % 6 == 1 || i % 6 == 3).collect(),
+            (0..30).filter(|i| i % 6 == 5).collect(),
+        ];
+        let root = Cluster::new_root(Arc::clone(&dataset)).partition_groups(groups.clone(), &criteria);
+        assert_eq!(root.cardinality, 30);
+
+        let clusters = root.flatten_tree();
+        for group in groups.iter() {
+            let cluster = clusters.iter().find(|c| &c.indices == group).unwrap();
+            assert!(cluster.cardinality == 1 || cluster.children.read().unwrap().is_some());
+            assert!(cluster
+                .flatten_tree()
+                .iter()
+                .all(|c| c.indices.iter().all(|i| group.contains(i))));
+        }
+
+        // The largest group is split from the other two.
+        let (left, right) = root.children.read().unwrap().clone().unwrap();
+        assert_eq!((left.cardinality, right.cardinality), (15, 15));
+        assert_eq!(left.indices, groups[0]);
+        assert_eq!(
+            clusters.iter().filter(|c| c.children.read().unwrap().is_none()).count(),
+            30
+        );
+
+        let assignments: Vec<_> = (0..30).map(|i| if i % 2 == 0 { 3 } else { 7 }).collect();
+        let manifold = Manifold::from_assignments(Arc::clone(&dataset), &assignments, &criteria).unwrap();
+        let (left, right) = manifold.root.children.read().unwrap().clone().unwrap();
+        assert_eq!(left.indices, groups[0]);
+        assert!(right.indices.iter().all(|i| i % 2 == 1));
+        assert!(Manifold::from_assignments(dataset, &assignments[1..], &criteria).is_err());
+    }
+
+    #[test]
+    fn test_stratified() {
+        let data: Vec<_> = (0..40).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::<f64, f64>::new(Arc::new(data), metric, false));
+        let criteria = vec![criteria::min_cardinality(1)];
+
+        // Class 1 is at both ends of the line, so a metric partition splits it at the root.
+        let classes: Vec<_> = (0..40)
+            .map(|i| if !(5..35).contains(&i) { 1 } else { i % 3 + 2 })
+            .collect();
+        let class: Vec<Index> = (0..40).filter(|&i| classes[i] == 1).collect();
+        let is_split = |cluster: &Arc<Cluster<f64, f64>>| {
+            let held = class.iter().filter(|i| cluster.indices.contains(i)).count();
+            held > 0 && held < class.len()
+        };
+
+        let manifold = Manifold::new(Arc::clone(&dataset), &criteria);
+        assert!(manifold
+            .root
+            .flatten_tree()
+            .iter()
+            .any(|c| c.depth() == 1 && is_split(c)));
+
+        let stratification = Stratification::Cohesive {
+            depth: 3,
+            classes: vec![1],
+        };
+        let manifold = Manifold::stratified(Arc::clone(&dataset), &classes, &stratification, &criteria).unwrap();
+        let clusters = manifold.root.flatten_tree();
+        // Only the cluster that holds nothing but class 1 may split it.
+        assert!(clusters
+            .iter()
+            .filter(|c| c.depth() <= 3 && c.indices.iter().any(|&i| classes[i] != 1))
+            .all(|c| !is_split(c)));
+        assert!(clusters.iter().any(|c| c.depth() <= 3 && c.indices == class));
+        assert_eq!(
+            clusters.iter().filter(|c| c.children.read().unwrap().is_none()).count(),
+            40
+        );
+
+        let manifold =
+            Manifold::stratified(Arc::clone(&dataset), &classes, &Stratification::Separate, &criteria).unwrap();
+        for label in 1..5 {
+            let members: Vec<Index> = (0..40).filter(|&i| classes[i] == label).collect();
+            assert!(manifold.root.flatten_tree().iter().any(|c| c.indices == members));
+        }
+        assert!(Manifold::stratified(dataset, &classes[1..], &stratification, &criteria).is_err());
+    }
+
+    #[test]
+    fn test_center_policy() {
+        let data: Vec<_> = (0..200).map(|i| vec![i as f64, (i % 7) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::<f64, f64>::new(Arc::new(data), metric, false));
+        let criteria = vec![criteria::max_depth(3), criteria::min_cardinality(1)];
+
+        let exact = Cluster::new_root_with_center_policy(Arc::clone(&dataset), CenterPolicy::Exact);
+        let centroid = Cluster::new_root_with_center_policy(Arc::clone(&dataset), CenterPolicy::Centroid);
+        assert!((98..102).contains(&exact.argcenter));
+        assert!((98..102).contains(&centroid.argcenter));
+        assert_eq!(
+            Cluster::new_root(Arc::clone(&dataset)).center_policy,
+            CenterPolicy::Sampled
+        );
+
+        // Descendants inherit the policy.
+        let root =
+            Cluster::new_root_with_center_policy(Arc::clone(&dataset), CenterPolicy::SampleSize(5)).partition(&criteria);
+        let clusters = root.flatten_tree();
+        assert_eq!(clusters.len(), 14);
+        for cluster in clusters.iter() {
+            assert_eq!(cluster.center_policy, CenterPolicy::SampleSize(5));
+            assert!(cluster.indices.contains(&cluster.argcenter));
+        }
+
+        let cakes = crate::Cakes::build_with_center_policy(dataset, Some(3), None, CenterPolicy::Exact);
+        assert_eq!(cakes.root.argcenter, exact.argcenter);
+        assert_eq!(cakes.report.unwrap().criteria.last().unwrap(), "center_policy=Exact");
+    }
+
+    #[test]
+    fn test_reproducible_statistics() {
+        let data: Vec<_> = (0..2_000)
+            .map(|i| {
+                (0..5)
+                    .map(|j| ((i * 31 + j * 17) % 97) as f32 * 0.1 + 1e-4 * i as f32)
+                    .collect()
+            })
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::<f32, f32>::new(Arc::new(data), metric, false));
+        let criteria = vec![criteria::max_depth(6), criteria::min_cardinality(1)];
+
+        // The statistics of every cluster are bit-identical for any number of threads and any parallel threshold, and
+        // the indices split for each cluster hold no spare capacity.
+        let statistics = |threads: usize, threshold: usize| {
+            let pool = parallelism::thread_pool(threads, threshold).unwrap();
+            pool.install(|| {
+                let root = Cluster::new_root_with_center_policy(Arc::clone(&dataset), CenterPolicy::Centroid)
+                    .partition(&criteria);
+                let mut clusters = root.flatten_tree();
+                clusters.push(root);
+                assert!(clusters.iter().all(|c| c.indices.capacity() == c.indices.len()));
+                let mut statistics: Vec<_> = clusters
+                    .iter()
+                    .map(|c| (c.name.clone(), c.argcenter, c.radius.to_bits(), c.lfd.to_bits()))
+                    .collect();
+                statistics.sort();
+                statistics
+            })
+        };
+        let expected = statistics(1, parallelism::DEFAULT_PARALLEL_THRESHOLD);
+        assert_eq!(statistics(4, parallelism::DEFAULT_PARALLEL_THRESHOLD), expected);
+        assert_eq!(statistics(4, 0), expected);
+        assert_eq!(statistics(4, usize::MAX), expected);
+    }
+
+    #[test]
+    fn test_build_peak_memory() {
+        let data: Vec<Vec<f32>> = (0..2_000)
+            .map(|i| (0..4).map(|j| ((i * 37 + j * 11) % 101) as f32 * 0.3).collect())
+            .collect();
+        let cardinality = data.len();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::<f32, f32>::new(Arc::new(data), metric, false));
+        let report = crate::Cakes::build(dataset, Some(10), Some(1)).report.unwrap();
+
+        // The scratch of a partition is released before its children are partitioned, so at most one partition of each
+        // instance holds scratch at any time: the distances to its poles, and the indices it splits.
+        let scratch = cardinality * (2 * std::mem::size_of::<Index>() + std::mem::size_of::<f32>());
+        assert!(report.peak_memory_bytes >= report.tree_size_bytes);
+        assert!(report.peak_memory_bytes <= report.tree_size_bytes + scratch as u64);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        // Most instances are copies of the origin, so samples that allowed duplicates would mostly be copies too.
+        let mut data = vec![vec![0., 0.]; 500];
+        data.extend((1..=20).map(|i| vec![i as f64, 0.]));
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::<f64, f64>::new(Arc::new(data), metric, false));
+        let representatives = dataset.deduplicate();
+        assert_eq!(representatives.iter().filter(|&&r| r == 0).count(), 500);
+
+        let criteria = vec![criteria::max_depth(10), criteria::min_cardinality(1)];
+        let policies = (CenterPolicy::SampleSize(10), RadiusPolicy::SampleSize(10));
+        let root = Cluster::new_root_with_policies(Arc::clone(&dataset), policies.0, policies.1).partition(&criteria);
+        assert!(!root.is_singleton());
+
+        // Only clusters of identical instances have zero radius, and no cluster is empty.
+        let mut clusters = root.flatten_tree();
+        clusters.push(root);
+        for cluster in clusters {
+            assert!(cluster.cardinality > 0);
+            let identical = cluster
+                .indices
+                .iter()
+                .all(|&i| representatives[i] == representatives[cluster.indices[0]]);
+            assert_eq!(cluster.is_singleton(), identical);
+        }
+    }
+
     #[test]
     fn test_ancestry() {
         let data = vec![vec![0., 0., 0.], vec![1., 1., 1.], vec![2., 2., 2.], vec![3., 3., 3.]];
@@ -5,19 +5,20 @@ use std::hash::Hasher;
 use std::sync::Arc;
 use std::sync::RwLock;
 
-use dashmap::DashMap;
 use ndarray::prelude::*;
 use rayon::prelude::*;
 
+use crate::memory::Subsystem;
+use crate::memory::Tracked;
 use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::utils::write_cache;
 
 type ClusterSet<T, U> = HashSet<Arc<Cluster<T, U>>>;
 type ClusterVec<T, U> = Vec<Arc<Cluster<T, U>>>;
 type EdgeSet<T, U> = HashSet<Arc<Edge<T, U>>>;
 type Components<T, U> = Arc<RwLock<Option<Vec<Arc<Graph<T, U>>>>>>;
 
-type EdgesMap<T, U> = HashMap<Arc<Cluster<T, U>>, EdgeSet<T, U>>;
-
 /// A HashMap where the value is a HashSet of clusters that are subsumed by the key cluster.
 pub type Subsumed<T, U> = HashMap<Arc<Cluster<T, U>>, ClusterSet<T, U>>;
 
@@ -91,15 +92,9 @@ impl<T: Number, U: Number> Edge<T, U> {
 /// TODO: Implement Display in dot-file format.
 #[derive(Debug)]
 pub struct Graph<T: Number, U: Number> {
-    // TODO: Measure performance difference for using a Vec instead of a HashSet.
-    // TODO: Improve performance of hashing and lookup of the expensive properties of the graph, e.g. edges-dict, components, eccentricities, etc.
+    // TODO: Improve performance of hashing and lookup of the expensive properties of the graph,
+    // e.g. components, eccentricities, etc.
     
-    /// A HashSet of clusters/vertices in the graph.
-    pub clusters: ClusterSet<T, U>,
-
-    /// A HashSet of edges in the graph.
-    pub edges: EdgeSet<T, U>,
-
     /// The number of clusters/vertices in the graph.
     pub cardinality: usize,
 
@@ -112,24 +107,37 @@ pub struct Graph<T: Number, U: Number> {
     /// The minimum tree-depth of any cluster/vertex in the graph.
     pub min_depth: usize,
 
-    /// A HashMap from a cluster to all edges of that cluster.
-    pub edges_dict: EdgesMap<T, U>,
+    /// The clusters/vertices in sorted order. The position of a cluster in this Vec is its id in the graph.
+    vertices: ClusterVec<T, U>,
+
+    /// The adjacency lists, in compressed sparse row format.
+    /// The neighbors of the cluster with id `i` are `targets[offsets[i]..offsets[i + 1]]`, sorted by id.
+    offsets: Vec<usize>,
+
+    /// The ids of the neighbors of each cluster. See `offsets`.
+    targets: Vec<usize>,
+
+    /// The distance from each cluster to each of its neighbors, aligned with `targets`.
+    weights: Vec<U>,
 
     /// A collection of all connected components of the graph. Each component is itself a graph.
     components: Components<T, U>,
 
-    /// A HashMap of clusters and their eccentricities.
-    eccentricities: DashMap<Arc<Cluster<T, U>>, usize>,
+    /// The eccentricities of clusters, by id, as they are computed.
+    eccentricities: RwLock<Vec<Option<usize>>>,
 
     /// Name of the distance metric used in the dataset.
     pub metric_name: String,
+
+    /// The memory held by the graph, as counted by `memory_report`.
+    memory: Tracked,
 }
 // TODO: Implement Display, perhaps using Dot-String format
 
 /// Two graphs are the same if they have the same set of clusters.
 impl<T: Number, U: Number> PartialEq for Graph<T, U> {
     fn eq(&self, other: &Self) -> bool {
-        self.clusters == other.clusters
+        self.vertices == other.vertices
     }
 }
 
@@ -142,40 +150,46 @@ impl<T: Number, U: Number> Graph<T, U> {
     pub fn new(clusters: HashSet<Arc<Cluster<T, U>>>, edges: EdgeSet<T, U>) -> Self {
         assert!(!clusters.is_empty(), "Must have at least one cluster to make a graph.");
         let metric_name = clusters.iter().next().unwrap().clone().dataset.metric().name();
+        let mut vertices: Vec<_> = clusters.iter().cloned().collect();
+        vertices.par_sort();
+        let num_vertices = vertices.len();
         let mut graph = Graph {
-            clusters,
-            edges,
             cardinality: 0,
             population: 0,
             depth: 0,
             min_depth: 0,
-            edges_dict: HashMap::new(),
+            vertices,
+            offsets: Vec::new(),
+            targets: Vec::new(),
+            weights: Vec::new(),
             components: Arc::new(RwLock::new(None)),
-            eccentricities: DashMap::new(),
+            eccentricities: RwLock::new(vec![None; num_vertices]),
             metric_name,
+            memory: Tracked::new(Subsystem::Graphs, 0),
         };
         graph.cardinality = graph.cardinality();
         graph.population = graph.population();
         graph.depth = graph.depth();
         graph.min_depth = graph.min_depth();
-        graph.edges_dict = graph.edges_dict();
+        graph.build_adjacency(&edges.into_iter().collect::<Vec<_>>());
+        graph.memory.set(graph.approximate_size());
         graph
     }
 
     fn cardinality(&self) -> usize {
-        self.clusters.len()
+        self.vertices.len()
     }
 
     fn population(&self) -> usize {
-        self.clusters.par_iter().map(|cluster| cluster.cardinality).sum()
+        self.vertices.par_iter().map(|cluster| cluster.cardinality).sum()
     }
 
     fn depth(&self) -> usize {
-        self.clusters.par_iter().map(|cluster| cluster.depth()).max().unwrap()
+        self.vertices.par_iter().map(|cluster| cluster.depth()).max().unwrap()
     }
 
     fn min_depth(&self) -> usize {
-        self.clusters.par_iter().map(|cluster| cluster.depth()).min().unwrap()
+        self.vertices.par_iter().map(|cluster| cluster.depth()).min().unwrap()
     }
 
     /// Returns the minimum and maximum tree-depths of the clusters in the graph.
@@ -183,51 +197,111 @@ impl<T: Number, U: Number> Graph<T, U> {
         (self.min_depth, self.depth)
     }
 
-    fn edges_dict(&self) -> EdgesMap<T, U> {
-        self.clusters
+    /// Fills the compressed sparse row adjacency from the set of edges.
+    /// An edge from a cluster to itself appears once in the adjacency list of that cluster.
+    /// Edges to clusters that are not in the graph are ignored.
+    fn build_adjacency(&mut self, edges: &[Arc<Edge<T, U>>]) {
+        let mut pairs: Vec<_> = edges
             .par_iter()
-            .map(|cluster| {
-                (
-                    Arc::clone(cluster),
-                    self.edges
-                        .par_iter()
-                        .filter(|&edge| edge.contains(cluster))
-                        .map(Arc::clone)
-                        .collect(),
-                )
+            .filter_map(|edge| match (self.id_of(&edge.left), self.id_of(&edge.right)) {
+                (Ok(i), Ok(j)) if i == j => Some(vec![(i, j, edge.distance)]),
+                (Ok(i), Ok(j)) => Some(vec![(i, j, edge.distance), (j, i, edge.distance)]),
+                _ => None,
             })
-            .collect()
+            .flatten()
+            .collect();
+        pairs.par_sort_by_key(|&(i, j, _)| (i, j));
+
+        let mut offsets = vec![0; self.vertices.len() + 1];
+        pairs.iter().for_each(|&(i, _, _)| offsets[i + 1] += 1);
+        for i in 0..self.vertices.len() {
+            offsets[i + 1] += offsets[i];
+        }
+
+        self.offsets = offsets;
+        self.targets = pairs.iter().map(|&(_, j, _)| j).collect();
+        self.weights = pairs.into_iter().map(|(_, _, d)| d).collect();
+    }
+
+    /// Returns the approximate number of bytes held by the graph, not counting its clusters or its components.
+    fn approximate_size(&self) -> usize {
+        let pointer = std::mem::size_of::<Arc<Cluster<T, U>>>();
+        std::mem::size_of::<Self>()
+            + self.vertices.len() * (pointer + std::mem::size_of::<Option<usize>>())
+            + self.offsets.len() * std::mem::size_of::<usize>()
+            + self.targets.len() * std::mem::size_of::<usize>()
+            + self.weights.len() * std::mem::size_of::<U>()
+    }
+
+    /// Returns the id of the given cluster, i.e. its position in `vertices()`.
+    pub fn id_of(&self, cluster: &Arc<Cluster<T, U>>) -> Result<usize, String> {
+        self.vertices
+            .binary_search(cluster)
+            .map_err(|_| format!("This Graph does not contain the Cluster {}.", cluster.name))
+    }
+
+    /// Returns the clusters in the graph, sorted, so that the cluster with id `i` is at position `i`.
+    pub fn vertices(&self) -> &[Arc<Cluster<T, U>>] {
+        &self.vertices
+    }
+
+    /// Returns an iterator over the clusters in the graph, in id order.
+    pub fn clusters(&self) -> std::slice::Iter<'_, Arc<Cluster<T, U>>> {
+        self.vertices.iter()
+    }
+
+    /// Returns an iterator over the edges in the graph.
+    /// Each edge is yielded once, built from the adjacency list of its endpoint with the smaller id.
+    pub fn edges(&self) -> impl Iterator<Item = Arc<Edge<T, U>>> + '_ {
+        (0..self.vertices.len()).flat_map(move |i| {
+            self.neighbor_ids(i)
+                .iter()
+                .zip(self.neighbor_distances(i).iter())
+                .filter(move |(&j, _)| j >= i)
+                .map(move |(&j, &distance)| {
+                    Edge::new(Arc::clone(&self.vertices[i]), Arc::clone(&self.vertices[j]), distance)
+                })
+        })
+    }
+
+    /// Returns the ids of the neighbors of the cluster with the given id, in increasing order.
+    pub fn neighbor_ids(&self, id: usize) -> &[usize] {
+        &self.targets[self.offsets[id]..self.offsets[id + 1]]
+    }
+
+    /// Returns the distances to the neighbors of the cluster with the given id, in the order of `neighbor_ids`.
+    pub fn neighbor_distances(&self, id: usize) -> &[U] {
+        &self.weights[self.offsets[id]..self.offsets[id + 1]]
     }
 
     fn assert_contains(&self, cluster: &Arc<Cluster<T, U>>) -> Result<(), String> {
-        if self.clusters.contains(cluster) {
-            Ok(())
-        } else {
-            Err(format!("This Graph does not contain the Cluster {}.", cluster.name))
-        }
+        self.id_of(cluster).map(|_| ())
     }
 
     /// Returns a HashSet of edges from the given cluster.
-    pub fn edges_from(&self, cluster: &Arc<Cluster<T, U>>) -> Result<&EdgeSet<T, U>, String> {
-        self.assert_contains(cluster)?;
-        Ok(self.edges_dict.get(cluster).unwrap())
+    pub fn edges_from(&self, cluster: &Arc<Cluster<T, U>>) -> Result<EdgeSet<T, U>, String> {
+        let id = self.id_of(cluster)?;
+        Ok(self
+            .neighbor_ids(id)
+            .iter()
+            .zip(self.neighbor_distances(id).iter())
+            .map(|(&j, &distance)| Edge::new(Arc::clone(cluster), Arc::clone(&self.vertices[j]), distance))
+            .collect())
     }
 
     /// Returns the neighbors of the given cluster.
     pub fn neighbors(&self, cluster: &Arc<Cluster<T, U>>) -> Result<Vec<Arc<Cluster<T, U>>>, String> {
-        Ok((self.edges_from(cluster)?)
-            .par_iter()
-            .map(|edge| edge.neighbor(cluster).unwrap())
-            .map(Arc::clone)
+        let id = self.id_of(cluster)?;
+        Ok(self
+            .neighbor_ids(id)
+            .iter()
+            .map(|&j| Arc::clone(&self.vertices[j]))
             .collect())
     }
 
     /// Returns the distances to each neighbor of the given cluster.
     pub fn distances(&self, cluster: &Arc<Cluster<T, U>>) -> Result<Vec<U>, String> {
-        Ok((self.edges_from(cluster)?)
-            .par_iter()
-            .map(|edge| edge.distance)
-            .collect())
+        Ok(self.neighbor_distances(self.id_of(cluster)?).to_vec())
     }
 
     /// Returns the subgraph of the given subset of clusters.
@@ -237,41 +311,35 @@ impl<T: Number, U: Number> Graph<T, U> {
         }
 
         let edges = self
-            .edges
-            .par_iter()
-            .filter(|&edge| cluster_subset.contains(&edge.left) && cluster_subset.contains(&edge.right))
-            .map(Arc::clone)
+            .edges()
+            .filter(|edge| cluster_subset.contains(&edge.left) && cluster_subset.contains(&edge.right))
             .collect();
 
         Ok(Graph::new(cluster_subset, edges))
     }
 
-    /// Perform a graph traversal (in an arbitrary order) starting at the given cluster.
+    /// Perform a breadth-first traversal starting at the cluster with the given id.
     /// 
-    /// Returns the set of visited clusters and the eccentricity of the strating cluster.
-    fn traverse(&self, start: &Arc<Cluster<T, U>>) -> (ClusterSet<T, U>, usize) {
-        let mut visited = HashSet::new();
-
-        let mut frontier = HashSet::new();
-        frontier.insert(Arc::clone(start));
-
+    /// Returns the ids of visited clusters and the eccentricity of the strating cluster.
+    fn traverse(&self, start: usize) -> (Vec<usize>, usize) {
+        let mut seen = vec![false; self.vertices.len()];
+        seen[start] = true;
+        let mut visited = Vec::new();
+        let mut frontier = vec![start];
         let mut eccentricity = 0;
 
         while !frontier.is_empty() {
-            let new_frontier = frontier
-                .par_iter()
-                .map(|c| {
-                    self.neighbors(c)
-                        .unwrap()
-                        .par_iter()
-                        .filter(|&n| !visited.contains(n) && !frontier.contains(n))
-                        .map(Arc::clone)
-                        .collect::<Vec<Arc<Cluster<T, U>>>>()
-                })
-                .flatten()
-                .collect();
+            let mut new_frontier = Vec::new();
+            for &i in frontier.iter() {
+                for &j in self.neighbor_ids(i) {
+                    if !seen[j] {
+                        seen[j] = true;
+                        new_frontier.push(j);
+                    }
+                }
+            }
 
-            visited.extend(frontier);
+            visited.append(&mut frontier);
             frontier = new_frontier;
             eccentricity += 1;
         }
@@ -281,43 +349,43 @@ impl<T: Number, U: Number> Graph<T, U> {
 
     /// Returns the connected components of the graph.
     pub fn find_components(&self) -> Vec<Arc<Self>> {
-        let components: Option<Vec<Arc<Self>>> = self.components.read().unwrap().clone();
+        let components: Option<Vec<Arc<Self>>> = read_cache(&self.components).clone();
         if components.is_none() {
             let mut components = Vec::new();
-            let mut unvisited = self.clusters.clone();
+            let mut assigned = vec![false; self.vertices.len()];
 
-            while !unvisited.is_empty() {
-                let start = Arc::clone(unvisited.iter().next().unwrap());
-                let (component, _) = self.traverse(&start);
-                component
-                    .iter()
-                    .map(|cluster_item| unvisited.remove(cluster_item))
-                    .count();
+            for start in 0..self.vertices.len() {
+                if assigned[start] {
+                    continue;
+                }
+                let (component, _) = self.traverse(start);
+                component.iter().for_each(|&i| assigned[i] = true);
+                let component = component.into_iter().map(|i| Arc::clone(&self.vertices[i])).collect();
                 components.push(self.subgraph(component).unwrap());
             }
 
             let components = components.into_iter().map(Arc::new).collect();
-            *self.components.write().unwrap() = Some(components);
+            *write_cache(&self.components) = Some(components);
         }
 
-        self.components.read().unwrap().clone().unwrap()
+        read_cache(&self.components).clone().unwrap()
     }
 
     /// Returns the eccentricity of a cluster, i.e. the maximum length of all paths starting at the cluster.
     pub fn eccentricity(&self, cluster: &Arc<Cluster<T, U>>) -> Result<usize, String> {
-        self.assert_contains(cluster)?;
-
-        if !self.eccentricities.contains_key(cluster) {
-            let (_, eccentricity) = self.traverse(cluster);
-            self.eccentricities.insert(Arc::clone(cluster), eccentricity);
-        }
-
-        Ok(*self.eccentricities.get(cluster).unwrap().value())
+        let id = self.id_of(cluster)?;
+
+        let known = read_cache(&self.eccentricities)[id];
+        Ok(known.unwrap_or_else(|| {
+            let (_, eccentricity) = self.traverse(id);
+            write_cache(&self.eccentricities)[id] = Some(eccentricity);
+            eccentricity
+        }))
     }
 
     /// Returns the graph diameter, i.e. the maximum eccentricity of any cluster in the graph.
     pub fn diameter(&self) -> usize {
-        self.clusters
+        self.vertices
             .par_iter()
             .map(|cluster_item| self.eccentricity(cluster_item).unwrap())
             .max()
@@ -327,20 +395,13 @@ impl<T: Number, U: Number> Graph<T, U> {
     /// Returns a vec of the clusters in the graph and a matrix of pairwise distances between the clsuter centers.
     /// The rows and columns of the matrix are ordered by the vec of clusters.
     pub fn distance_matrix(&self) -> (ClusterVec<T, U>, Array2<U>) {
-        let clusters: Vec<_> = self.clusters.par_iter().map(Arc::clone).collect();
-        let indices: HashMap<_, _> = clusters
-            .par_iter()
-            .map(Arc::clone)
-            .enumerate()
-            .map(|(i, cluster)| (cluster, i))
-            .collect();
         let mut matrix = Array2::zeros((self.cardinality, self.cardinality));
-        for edge in self.edges.iter() {
-            let (&i, &j) = (indices.get(&edge.left).unwrap(), indices.get(&edge.right).unwrap());
-            matrix[[i, j]] = edge.distance;
-            matrix[[j, i]] = edge.distance;
+        for i in 0..self.cardinality {
+            for (&j, &distance) in self.neighbor_ids(i).iter().zip(self.neighbor_distances(i).iter()) {
+                matrix[[i, j]] = distance;
+            }
         }
-        (clusters, matrix)
+        (self.vertices.clone(), matrix)
     }
 
     /// Returns the adhjacency matrix for the graph, along with a vec of clsuters in the same order.
@@ -353,22 +414,21 @@ impl<T: Number, U: Number> Graph<T, U> {
     /// 
     /// A cluster is said to be subsumed by another cluster if its volume liex completely inside the others volume.
     pub fn pruned_graph(&self) -> (Arc<Self>, Subsumed<T, U>) {
-        let subsumed_clusters: HashSet<_> = self
-            .edges
-            .par_iter()
-            .flat_map(|edge| {
-                if edge.distance + edge.left.radius < edge.right.radius {
-                    vec![Arc::clone(&edge.left)]
-                } else if edge.distance + edge.right.radius < edge.left.radius {
-                    vec![Arc::clone(&edge.right)]
-                } else {
-                    vec![]
-                }
+        let subsumed_clusters: HashSet<_> = (0..self.cardinality)
+            .into_par_iter()
+            .flat_map(|i| {
+                let cluster = &self.vertices[i];
+                self.neighbor_ids(i)
+                    .iter()
+                    .zip(self.neighbor_distances(i).iter())
+                    .filter(|&(&j, &distance)| distance + cluster.radius < self.vertices[j].radius)
+                    .map(|_| Arc::clone(cluster))
+                    .collect::<Vec<_>>()
             })
             .collect();
 
         let pruned_clusters: HashSet<_> = self
-            .clusters
+            .vertices
             .par_iter()
             .filter(|&cluster| !subsumed_clusters.contains(cluster))
             .map(Arc::clone)
@@ -398,7 +458,63 @@ impl<T: Number, U: Number> Graph<T, U> {
         Ok(self
             .find_components()
             .into_par_iter()
-            .find_any(|component| component.clusters.contains(cluster))
+            .find_any(|component| component.id_of(cluster).is_ok())
             .unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_adjacency() {
+        // Two well-separated groups of points on a line.
+        let data: Vec<_> = (0..32).chain(100..132).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let manifold = Manifold::new(dataset, &[criteria::max_depth(4), criteria::min_cardinality(1)]);
+        let layer: Vec<_> = manifold
+            .root
+            .flatten_tree()
+            .into_iter()
+            .filter(|cluster| cluster.depth() == 4)
+            .collect();
+        let graph = manifold.create_graph(&layer);
+
+        assert_eq!(graph.vertices().len(), graph.cardinality);
+        for (i, cluster) in graph.vertices().iter().enumerate() {
+            assert_eq!(graph.id_of(cluster).unwrap(), i);
+
+            let expected: HashSet<_> = graph
+                .edges()
+                .filter(|edge| edge.contains(cluster))
+                .map(|edge| (edge.neighbor(cluster).unwrap().name.clone(), edge.distance.to_bits()))
+                .collect();
+            let actual: HashSet<_> = graph
+                .neighbors(cluster)
+                .unwrap()
+                .iter()
+                .zip(graph.distances(cluster).unwrap().iter())
+                .map(|(neighbor, distance)| (neighbor.name.clone(), distance.to_bits()))
+                .collect();
+            assert_eq!(actual, expected);
+            assert_eq!(graph.edges_from(cluster).unwrap().len(), expected.len());
+        }
+
+        let components = graph.find_components();
+        assert_eq!(components.len(), 2);
+        assert_eq!(
+            components.iter().map(|c| c.cardinality).sum::<usize>(),
+            graph.cardinality
+        );
+        assert!(graph.diameter() <= graph.cardinality);
+
+        assert!(graph.id_of(&manifold.root).is_err());
+        assert!(graph.neighbors(&manifold.root).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Coordinates for drawing `Graphs` and exporting them to GraphML.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+
+use crate::prelude::*;
+
+/// The maximum number of iterations used to refine the eigenvectors for the spectral layout.
+const MAX_LAYOUT_ITERATIONS: usize = 1000;
+
+/// Iteration stops once no coordinate changes by more than this amount.
+const LAYOUT_TOLERANCE: f64 = 1e-10;
+
+type ClusterVec<T, U> = Vec<Arc<Cluster<T, U>>>;
+
+impl<T: Number, U: Number> Graph<T, U> {
+    /// Returns the clusters of the graph, in id order, and the adjacency lists for those clusters.
+    /// Edges from a cluster to itself are ignored.
+    fn sorted_adjacency(&self) -> (ClusterVec<T, U>, Vec<Vec<usize>>) {
+        let adjacency = (0..self.cardinality)
+            .map(|i| self.neighbor_ids(i).iter().copied().filter(|&j| j != i).collect())
+            .collect();
+        (self.vertices().to_vec(), adjacency)
+    }
+
+    /// Returns the clusters in the graph, in id order, and a matrix whose rows are `dims`-dimensional
+    /// coordinates for those clusters.
+    ///
+    /// The coordinates are the eigenvectors for the smallest non-zero eigenvalues of the graph Laplacian.
+    /// These are found by subspace iteration on the sparse Laplacian, so the dense matrix is never formed.
+    /// Adjacent clusters are placed close together and, for disconnected graphs, the first few coordinates
+    /// separate the components.
+    /// The starting vectors are fixed, so the layout is deterministic.
+    /// A warning is logged if the iteration stops at `MAX_LAYOUT_ITERATIONS` without converging.
+    pub fn spectral_layout(&self, dims: usize) -> (ClusterVec<T, U>, Array2<f64>) {
+        let (clusters, layout, converged) = self.spectral_layout_converged(dims);
+        if !converged {
+            log::warn!(
+                "The spectral layout did not converge within {} iterations.",
+                MAX_LAYOUT_ITERATIONS
+            );
+        }
+        (clusters, layout)
+    }
+
+    /// Same as `spectral_layout`, but also returns whether the subspace iteration converged
+    /// before reaching `MAX_LAYOUT_ITERATIONS`.
+    pub fn spectral_layout_converged(&self, dims: usize) -> (ClusterVec<T, U>, Array2<f64>, bool) {
+        let (clusters, adjacency) = self.sorted_adjacency();
+        let n = clusters.len();
+        let mut layout = Array2::zeros((n, dims));
+
+        // The all-ones vector spans the null-space of the Laplacian for a connected graph and so it is excluded.
+        // There are at most n - 1 vectors orthogonal to it.
+        let k = std::cmp::min(dims, n.saturating_sub(1));
+        if k == 0 {
+            return (clusters, layout, true);
+        }
+
+        // The Laplacian has eigenvalues in [0, 2 * max_degree].
+        // The largest eigenvectors of (shift * I - L) are the smallest eigenvectors of L.
+        let shift = 2. * (adjacency.iter().map(|a| a.len()).max().unwrap() as f64) + 1.;
+        let apply = |x: ArrayView1<f64>| -> Array1<f64> {
+            adjacency
+                .iter()
+                .enumerate()
+                .map(|(i, neighbors)| {
+                    let laplacian = (neighbors.len() as f64) * x[i] - neighbors.iter().map(|&j| x[j]).sum::<f64>();
+                    shift * x[i] - laplacian
+                })
+                .collect()
+        };
+
+        let mut basis = Array2::from_shape_fn((n, k), |(i, j)| ((i + 1) as f64 * (j + 1) as f64).sin());
+        orthonormalize(&mut basis);
+        let mut converged = false;
+        for _ in 0..MAX_LAYOUT_ITERATIONS {
+            let mut next = Array2::zeros((n, k));
+            for (j, column) in basis.axis_iter(Axis(1)).enumerate() {
+                next.column_mut(j).assign(&apply(column));
+            }
+            orthonormalize(&mut next);
+
+            let change = (&next - &basis).iter().fold(0_f64, |max, &v| max.max(v.abs()));
+            basis = next;
+            if change < LAYOUT_TOLERANCE {
+                converged = true;
+                break;
+            }
+        }
+
+        // Fix the sign of each coordinate so that its largest-magnitude entry is positive.
+        for mut column in basis.axis_iter_mut(Axis(1)) {
+            let largest = column.iter().fold(0_f64, |l, &v| if v.abs() > l.abs() { v } else { l });
+            if largest < 0. {
+                column.mapv_inplace(|v| -v);
+            }
+        }
+
+        layout.slice_mut(s![.., ..k]).assign(&basis);
+        (clusters, layout, converged)
+    }
+
+    /// Returns the graph as a GraphML document.
+    ///
+    /// Every cluster is a node, identified by its name, with its depth, cardinality, radius and lfd as attributes.
+    /// Every edge carries the distance between the cluster centers.
+    /// If `layout_dims` is given, a spectral layout with that many dimensions is computed and
+    /// stored in node attributes `x0`, `x1`, etc.
+    pub fn to_graphml(&self, layout_dims: Option<usize>) -> String {
+        let (clusters, layout) = self.spectral_layout(layout_dims.unwrap_or(0));
+
+        let mut graphml = String::new();
+        graphml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        graphml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        let node_keys = [
+            ("depth", "int"),
+            ("cardinality", "int"),
+            ("radius", "double"),
+            ("lfd", "double"),
+        ];
+        for (name, kind) in node_keys.iter() {
+            writeln!(
+                graphml,
+                "  <key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"{1}\"/>",
+                name, kind
+            )
+            .unwrap();
+        }
+        for d in 0..layout.ncols() {
+            writeln!(
+                graphml,
+                "  <key id=\"x{0}\" for=\"node\" attr.name=\"x{0}\" attr.type=\"double\"/>",
+                d
+            )
+            .unwrap();
+        }
+        graphml.push_str("  <key id=\"distance\" for=\"edge\" attr.name=\"distance\" attr.type=\"double\"/>\n");
+        writeln!(
+            graphml,
+            "  <graph id=\"{}\" edgedefault=\"undirected\">",
+            escape_xml(&self.metric_name)
+        )
+        .unwrap();
+
+        for (cluster, coordinates) in clusters.iter().zip(layout.outer_iter()) {
+            writeln!(graphml, "    <node id=\"{}\">", cluster).unwrap();
+            writeln!(graphml, "      <data key=\"depth\">{}</data>", cluster.depth()).unwrap();
+            writeln!(
+                graphml,
+                "      <data key=\"cardinality\">{}</data>",
+                cluster.cardinality
+            )
+            .unwrap();
+            writeln!(graphml, "      <data key=\"radius\">{}</data>", cluster.radius.as_f64()).unwrap();
+            writeln!(graphml, "      <data key=\"lfd\">{}</data>", cluster.lfd).unwrap();
+            for (d, x) in coordinates.iter().enumerate() {
+                writeln!(graphml, "      <data key=\"x{}\">{}</data>", d, x).unwrap();
+            }
+            graphml.push_str("    </node>\n");
+        }
+
+        let mut edges: Vec<_> = self
+            .edges()
+            .filter(|edge| !edge.to_self())
+            .map(|edge| {
+                (
+                    format!("{}", edge.left),
+                    format!("{}", edge.right),
+                    edge.distance.as_f64(),
+                )
+            })
+            .collect();
+        edges.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        for (left, right, distance) in edges {
+            writeln!(graphml, "    <edge source=\"{}\" target=\"{}\">", left, right).unwrap();
+            writeln!(graphml, "      <data key=\"distance\">{}</data>", distance).unwrap();
+            graphml.push_str("    </edge>\n");
+        }
+
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+
+    /// Writes the graph, as GraphML, to the given path. See `to_graphml`.
+    pub fn write_graphml(&self, path: &std::path::Path, layout_dims: Option<usize>) -> Result<(), String> {
+        std::fs::write(path, self.to_graphml(layout_dims))
+            .map_err(|error| format!("Error: Failed to write {}. {}", path.display(), error))
+    }
+}
+
+/// Escapes the characters that may not appear as-is in XML text or attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Orthonormalizes the columns of the matrix, in order, and against the all-ones vector, with modified Gram-Schmidt.
+/// Columns that become degenerate are left as zeros.
+fn orthonormalize(basis: &mut Array2<f64>) {
+    let n = basis.nrows();
+    let ones = Array1::from_elem(n, 1. / (n as f64).sqrt());
+    for j in 0..basis.ncols() {
+        let mut column = basis.column(j).to_owned();
+        column.scaled_add(-column.dot(&ones), &ones);
+        for i in 0..j {
+            let previous = basis.column(i);
+            let projection = column.dot(&previous);
+            column.scaled_add(-projection, &previous);
+        }
+        let norm = column.dot(&column).sqrt();
+        if norm > 1e-12 {
+            column.mapv_inplace(|v| v / norm);
+        } else {
+            column.fill(0.);
+        }
+        basis.column_mut(j).assign(&column);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+
+    fn line_graph() -> Arc<Graph<f64, f64>> {
+        let data: Vec<_> = (0..64).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let manifold = Manifold::new(dataset, &[criteria::max_depth(4), criteria::min_cardinality(1)]);
+        let layer: Vec<_> = manifold
+            .root
+            .flatten_tree()
+            .into_iter()
+            .filter(|cluster| cluster.depth() == 4)
+            .collect();
+        Arc::new(manifold.create_graph(&layer))
+    }
+
+    #[test]
+    fn test_spectral_layout() {
+        let graph = line_graph();
+        let (clusters, layout, converged) = graph.spectral_layout_converged(2);
+        assert!(converged);
+        assert_eq!(clusters.len(), graph.cardinality);
+        assert_eq!(layout.dim(), (graph.cardinality, 2));
+
+        let (_, adjacency) = graph.sorted_adjacency();
+        for column in layout.columns() {
+            float_cmp::assert_approx_eq!(f64, column.sum(), 0., epsilon = 1e-6);
+            float_cmp::assert_approx_eq!(f64, column.dot(&column), 1., epsilon = 1e-6);
+
+            // Each coordinate must be an eigenvector of the Laplacian.
+            let laplacian: Vec<f64> = adjacency
+                .iter()
+                .enumerate()
+                .map(|(i, neighbors)| {
+                    neighbors.len() as f64 * column[i] - neighbors.iter().map(|&j| column[j]).sum::<f64>()
+                })
+                .collect();
+            let eigenvalue: f64 = laplacian.iter().zip(column.iter()).map(|(l, c)| l * c).sum();
+            let residual = laplacian
+                .iter()
+                .zip(column.iter())
+                .map(|(l, c)| (l - eigenvalue * c).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            assert!(residual < 1e-4, "residual {} was too large", residual);
+        }
+
+        assert_eq!(graph.spectral_layout(2).1, layout);
+    }
+
+    #[test]
+    fn test_graphml() {
+        let graph = line_graph();
+        let graphml = graph.to_graphml(Some(2));
+        assert!(graphml.starts_with("<?xml"));
+        assert_eq!(graphml.matches("<node ").count(), graph.cardinality);
+        assert_eq!(
+            graphml.matches("<edge ").count(),
+            graph.edges().filter(|edge| !edge.to_self()).count()
+        );
+        assert_eq!(graphml.matches("<data key=\"x1\">").count(), graph.cardinality);
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(super::escape_xml("a&b<c>\"d'"), "a&amp;b&lt;c&gt;&quot;d&apos;");
+        assert_eq!(super::escape_xml("euclidean"), "euclidean");
+    }
+}
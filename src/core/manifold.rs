@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -8,7 +9,12 @@ use num_traits::FromPrimitive;
 use ordered_float::OrderedFloat;
 use rayon::prelude::*;
 
+use crate::dataset::RowMajor;
 use crate::prelude::*;
+use crate::utils::compare_distances;
+use crate::utils::read_cache;
+use crate::CenterPolicy;
+use crate::RadiusPolicy;
 use criteria::MetaMLScorer;
 use criteria::PartitionCriterion;
 
@@ -20,6 +26,62 @@ pub type Ratios = [f64; 6];
 
 type ScoresHeap<T, U> = BinaryHeap<(OrderedFloat<f64>, Arc<Cluster<T, U>>)>;
 
+/// How the classes of instances shape the top levels of a tree built by `Manifold::stratified`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stratification {
+    /// The top levels separate the classes, as with `Manifold::from_assignments`, so that every class is the subtree
+    /// of one cluster.
+    Separate,
+
+    /// Up to the given depth, clusters are split around, rather than through, each of the given classes, so the
+    /// instances of each class stay together unless a cluster holds nothing else. Instances of other classes are
+    /// placed individually, as usual.
+    Cohesive { depth: usize, classes: Vec<usize> },
+}
+
+/// The centers of the clusters at one depth of a tree, copied into a dataset of their own by
+/// `Manifold::centers_dataset`, e.g. for cheap coarse searches or as a small proxy of the corpus for other tools.
+#[derive(Debug)]
+pub struct CentersDataset<T: Number, U: Number> {
+    /// The center of each cluster, in the order of `clusters`, with the metric of the original dataset.
+    pub dataset: Arc<RowMajor<T, U>>,
+
+    /// The cluster of each center, in the order of their names.
+    pub clusters: Vec<Arc<Cluster<T, U>>>,
+}
+
+impl<T: Number, U: Number> CentersDataset<T, U> {
+    /// Returns the cluster whose center is at the given index of the dataset of centers.
+    pub fn cluster(&self, index: Index) -> &Arc<Cluster<T, U>> {
+        &self.clusters[index]
+    }
+
+    /// Returns the index, in the original dataset, of each center.
+    pub fn argcenters(&self) -> Vec<Index> {
+        self.clusters.iter().map(|cluster| cluster.argcenter).collect()
+    }
+
+    /// Returns the number of instances each center stands for, i.e. the cardinality of its cluster.
+    pub fn weights(&self) -> Vec<usize> {
+        self.clusters.iter().map(|cluster| cluster.cardinality).collect()
+    }
+
+    /// Returns the `k` clusters whose centers are nearest the query, with the distances to those centers, nearest
+    /// first, by a linear scan of the centers.
+    pub fn nearest(&self, query: &[T], k: usize) -> Vec<(Arc<Cluster<T, U>>, U)> {
+        let metric = self.dataset.metric();
+        let mut hits: Vec<_> = self
+            .clusters
+            .par_iter()
+            .enumerate()
+            .map(|(i, cluster)| (Arc::clone(cluster), metric.distance(query, &self.dataset.instance(i))))
+            .collect();
+        hits.sort_by(|(_, a), (_, b)| compare_distances(a, b));
+        hits.truncate(k);
+        hits
+    }
+}
+
 /// A `Manifold` connects the tree and graph structures and enables higher-order applications such as search, compression, anomaly detection, etc.
 #[derive(Debug)]
 pub struct Manifold<T: Number, U: Number> {
@@ -40,6 +102,116 @@ impl<T: Number, U: Number> Manifold<T, U> {
         Arc::new(manifold)
     }
 
+    /// Create a new `Manifold`, as with `new`, whose cluster centers are chosen by the given policy.
+    pub fn new_with_center_policy(
+        dataset: Arc<dyn Dataset<T, U>>,
+        partition_criteria: &[PartitionCriterion<T, U>],
+        center_policy: CenterPolicy,
+    ) -> Arc<Self> {
+        Manifold::new_with_policies(dataset, partition_criteria, center_policy, RadiusPolicy::default())
+    }
+
+    /// Create a new `Manifold`, as with `new`, whose cluster centers and radii are found by the given policies.
+    pub fn new_with_policies(
+        dataset: Arc<dyn Dataset<T, U>>,
+        partition_criteria: &[PartitionCriterion<T, U>],
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+    ) -> Arc<Self> {
+        let mut manifold = Manifold {
+            dataset: Arc::clone(&dataset),
+            root: Cluster::new_root_with_policies(dataset, center_policy, radius_policy).partition(partition_criteria),
+            candidates: HashMap::new(),
+        };
+        manifold.candidates = manifold.compute_candidates();
+        Arc::new(manifold)
+    }
+
+    /// Create a new `Manifold` whose top levels separate the instances by the given assignments, e.g. labels from
+    /// k-means or from a previous flat clustering. Each group of instances that share an assignment is then
+    /// partitioned with the given criteria.
+    ///
+    /// Returns an Err unless there is exactly one assignment for each instance in the dataset.
+    pub fn from_assignments(
+        dataset: Arc<dyn Dataset<T, U>>,
+        assignments: &[usize],
+        partition_criteria: &[PartitionCriterion<T, U>],
+    ) -> Result<Arc<Self>, String> {
+        if assignments.len() != dataset.cardinality() {
+            return Err(format!(
+                "Expected {} assignments but got {}.",
+                dataset.cardinality(),
+                assignments.len()
+            ));
+        }
+        let mut groups: BTreeMap<usize, Vec<Index>> = BTreeMap::new();
+        for (index, &group) in assignments.iter().enumerate() {
+            groups.entry(group).or_default().push(index);
+        }
+
+        let mut manifold = Manifold {
+            dataset: Arc::clone(&dataset),
+            root: Cluster::new_root(dataset).partition_groups(groups.into_values().collect(), partition_criteria),
+            candidates: HashMap::new(),
+        };
+        manifold.candidates = manifold.compute_candidates();
+        Ok(Arc::new(manifold))
+    }
+
+    /// Create a new `Manifold` whose top levels are shaped by the classes of the instances. See `Stratification`.
+    ///
+    /// Returns an Err unless there is exactly one class for each instance in the dataset.
+    pub fn stratified(
+        dataset: Arc<dyn Dataset<T, U>>,
+        classes: &[usize],
+        stratification: &Stratification,
+        partition_criteria: &[PartitionCriterion<T, U>],
+    ) -> Result<Arc<Self>, String> {
+        let (depth, selected) = match stratification {
+            Stratification::Separate => return Manifold::from_assignments(dataset, classes, partition_criteria),
+            Stratification::Cohesive { depth, classes } => (*depth, classes),
+        };
+        if classes.len() != dataset.cardinality() {
+            return Err(format!(
+                "Expected {} classes but got {}.",
+                dataset.cardinality(),
+                classes.len()
+            ));
+        }
+
+        let mut units: BTreeMap<usize, Vec<Index>> = BTreeMap::new();
+        let mut free = Vec::new();
+        for (index, class) in classes.iter().enumerate() {
+            if selected.contains(class) {
+                units.entry(*class).or_default().push(index);
+            } else {
+                free.push(vec![index]);
+            }
+        }
+        let units = units.into_values().chain(free).collect();
+
+        let mut manifold = Manifold {
+            dataset: Arc::clone(&dataset),
+            root: Cluster::new_root(dataset).partition_cohesive(units, depth, partition_criteria),
+            candidates: HashMap::new(),
+        };
+        manifold.candidates = manifold.compute_candidates();
+        Ok(Arc::new(manifold))
+    }
+
+    /// Returns a new `Manifold` in which every radius that was estimated from a sample, e.g. with
+    /// `RadiusPolicy::SampleSize`, is replaced by the exact radius, so that searches prune correctly.
+    /// If `depths` is given, only radii at those depths are recomputed.
+    pub fn tighten_radii(&self, depths: Option<&[usize]>) -> Arc<Self> {
+        let mut manifold = Manifold {
+            dataset: Arc::clone(&self.dataset),
+            root: self.root.tighten_radii(depths),
+            candidates: HashMap::new(),
+        };
+        manifold.candidates = manifold.compute_candidates();
+        Arc::new(manifold)
+    }
+
     /// Returns the name of the metric used in the dataset.
     pub fn metric_name(&self) -> String {
         self.dataset.metric_name()
@@ -64,7 +236,7 @@ impl<T: Number, U: Number> Manifold<T, U> {
         while !parents.is_empty() {
             let child_candidates: Candidates<T, U> = parents
                 .par_iter()
-                .flat_map(|parent| match parent.children.read().unwrap().clone() {
+                .flat_map(|parent| match read_cache(&parent.children).clone() {
                     Some((left, right)) => {
                         let parent_candidates = candidates.get(parent).unwrap();
                         vec![
@@ -80,7 +252,7 @@ impl<T: Number, U: Number> Manifold<T, U> {
 
             parents = parents
                 .par_iter()
-                .flat_map(|parent| match parent.children.read().unwrap().clone() {
+                .flat_map(|parent| match read_cache(&parent.children).clone() {
                     Some((left, right)) => {
                         vec![left, right]
                     }
@@ -131,7 +303,7 @@ impl<T: Number, U: Number> Manifold<T, U> {
 
             let mut new_leaves = layer
                 .par_iter()
-                .filter(|&cluster| cluster.children.read().unwrap().clone().is_none())
+                .filter(|&cluster| read_cache(&cluster.children).clone().is_none())
                 .cloned()
                 .collect();
             layer.extend(leaves.iter().cloned());
@@ -156,7 +328,7 @@ impl<T: Number, U: Number> Manifold<T, U> {
             .chain(
                 shallow_clusters
                     .into_iter()
-                    .filter(|cluster| cluster.children.read().unwrap().is_none()),
+                    .filter(|cluster| read_cache(&cluster.children).is_none()),
             )
             .map(|cluster| (OrderedFloat::from_f64(criterion(cluster.ratios)).unwrap(), cluster))
             .collect();
@@ -199,7 +371,7 @@ impl<T: Number, U: Number> Manifold<T, U> {
         let mut ancestors = vec![Arc::clone(&self.root)];
 
         for go_right in name.iter().by_ref() {
-            let (left, right) = match ancestors.last().unwrap().children.read().unwrap().clone() {
+            let (left, right) = match read_cache(&ancestors.last().unwrap().children).clone() {
                 Some((left, right)) => (left, right),
                 None => return Err(format!("cluster {:?} not found in the tree", name)),
             };
@@ -213,8 +385,116 @@ impl<T: Number, U: Number> Manifold<T, U> {
         Ok(ancestors)
     }
 
+    /// Returns the name of the cluster, at the given depth, that a query would be routed to,
+    /// e.g. so that a load balancer can consistently send queries from one region of the space to the same replica.
+    ///
+    /// Starting at the root, the query moves to the child whose center is closer, preferring the left child on ties,
+    /// until it reaches the given depth or a leaf. The returned name is therefore a prefix of the hint at any deeper
+    /// depth. Clusters overlap, so a hint does not guarantee that every hit for the query lies in that cluster.
+    pub fn shard_hint(&self, query: &[T], depth: usize) -> BitVec {
+        let mut cluster = Arc::clone(&self.root);
+        while cluster.depth() < depth {
+            let (left, right) = match read_cache(&cluster.children).clone() {
+                Some(children) => children,
+                None => break,
+            };
+            cluster = if left.distance_to_instance(query) <= right.distance_to_instance(query) {
+                left
+            } else {
+                right
+            };
+        }
+        cluster.name.clone()
+    }
+
     /// Returns a cluster given the name of that cluster.
     pub fn select(&self, name: BitVec) -> Result<Arc<Cluster<T, U>>, String> {
         Ok(self.ancestry(&name)?.swap_remove(name.len() - 1))
     }
 }
+
+impl<T: 'static + Number, U: 'static + Number> Manifold<T, U> {
+    /// Copies the centers of the clusters at the given depth, and of any leaves at shallower depths, into an
+    /// in-memory dataset, along with the cluster of each center.
+    ///
+    /// Every instance is in one of those clusters, so the centers cover the whole dataset, and coarse searches among
+    /// them need only as many distance calls as there are clusters.
+    pub fn centers_dataset(&self, depth: usize) -> CentersDataset<T, U> {
+        let mut clusters = vec![];
+        let mut stack = vec![Arc::clone(&self.root)];
+        while let Some(cluster) = stack.pop() {
+            let children = read_cache(&cluster.children).clone();
+            match children {
+                Some((left, right)) if cluster.depth() < depth => stack.extend([right, left]),
+                _ => clusters.push(cluster),
+            }
+        }
+
+        let centers = clusters.par_iter().map(|cluster| cluster.center()).collect();
+        CentersDataset {
+            dataset: Arc::new(RowMajor::new(Arc::new(centers), self.dataset.metric(), false)),
+            clusters,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_shard_hint() {
+        // Two well-separated blobs of points.
+        let data: Vec<_> = (0..40)
+            .map(|i| vec![(i % 20) as f64 / 10. + if i < 20 { 0. } else { 100. }, (i % 3) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        let manifold = Manifold::new(dataset, &[criteria::min_cardinality(1)]);
+
+        assert_eq!(manifold.shard_hint(&data[0], 0), manifold.root.name);
+        let (near, far) = (manifold.shard_hint(&[0.5, 1.], 1), manifold.shard_hint(&[100.5, 1.], 1));
+        assert_ne!(near, far);
+        for (i, instance) in data.iter().enumerate() {
+            let hint = manifold.shard_hint(instance, 1);
+            assert_eq!(hint, if i < 20 { near.clone() } else { far.clone() });
+
+            let deeper = manifold.shard_hint(instance, 3);
+            assert_eq!(deeper.len(), 4);
+            assert_eq!(deeper[..2], hint[..]);
+        }
+
+        // Hints stop at leaves.
+        assert!(manifold.shard_hint(&data[0], 1000).len() < 1000);
+    }
+
+    #[test]
+    fn test_centers_dataset() {
+        let data: Vec<_> = (0..200).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        let manifold = Manifold::new(dataset, &[criteria::max_depth(5), criteria::min_cardinality(1)]);
+
+        let root = manifold.centers_dataset(0);
+        assert_eq!(root.dataset.cardinality(), 1);
+        assert_eq!(root.dataset.instance(0), manifold.root.center());
+
+        let centers = manifold.centers_dataset(3);
+        assert_eq!(centers.dataset.cardinality(), centers.clusters.len());
+        assert!(centers.clusters.iter().all(|cluster| cluster.depth() <= 3));
+        assert_eq!(centers.weights().iter().sum::<usize>(), 200);
+        for (i, &argcenter) in centers.argcenters().iter().enumerate() {
+            assert_eq!(centers.dataset.instance(i), data[argcenter]);
+        }
+
+        // The nearest center to an instance is its own center, if that is a center.
+        let argcenter = centers.cluster(2).argcenter;
+        let nearest = centers.nearest(&data[argcenter], 3);
+        assert_eq!(nearest.len(), 3);
+        assert_eq!((&nearest[0].0, nearest[0].1), (centers.cluster(2), 0.));
+        assert!(nearest[1].1 <= nearest[2].1);
+    }
+}
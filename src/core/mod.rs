@@ -1,13 +1,23 @@
 mod cluster;
 mod graph;
+mod layout;
 mod manifold;
+mod sampling;
 
 pub mod criteria;
 
+pub use cluster::CenterPolicy;
 pub use cluster::Cluster;
+pub use cluster::RadiusPolicy;
 pub use graph::Edge;
 pub use graph::Graph;
+pub use manifold::CentersDataset;
 pub use manifold::Manifold;
+pub use manifold::Stratification;
+pub use sampling::SamplingError;
 
 pub use graph::Subsumed;
 pub use manifold::Ratios;
+
+pub(crate) use cluster::BuildGauge;
+pub(crate) use cluster::Children;
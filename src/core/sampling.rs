@@ -0,0 +1,210 @@
+//! Measuring how far the sampled estimates of cluster centers and radii are from their exact values.
+
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+use rand::seq::IteratorRandom;
+use rand::RngCore;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+
+/// The estimated and exact center and radius of one `Cluster`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingError<U: Number> {
+    /// The name of the `Cluster`.
+    pub name: BitVec,
+
+    /// The number of instances in the `Cluster`.
+    pub cardinality: usize,
+
+    /// The radius of the `Cluster`, which may have been estimated from a sample.
+    pub estimated_radius: U,
+
+    /// The distance from the center to the farthest instance in the `Cluster`.
+    pub exact_radius: U,
+
+    /// The sum of distances from the center to every instance in the `Cluster`.
+    pub center_cost: f64,
+
+    /// The sum of distances from the exact medoid to every instance in the `Cluster`.
+    pub medoid_cost: f64,
+}
+
+impl<U: Number> SamplingError<U> {
+    /// Returns the estimated radius as a fraction of the exact radius. This is 1 for exact radii.
+    pub fn radius_ratio(&self) -> f64 {
+        if self.exact_radius == U::zero() {
+            1.
+        } else {
+            self.estimated_radius.as_f64() / self.exact_radius.as_f64()
+        }
+    }
+
+    /// Returns how much larger the cost of the center is than that of the exact medoid, as a fraction of the latter.
+    /// This is 0 when the center is the exact medoid.
+    pub fn center_excess(&self) -> f64 {
+        if self.medoid_cost == 0. {
+            0.
+        } else {
+            self.center_cost / self.medoid_cost - 1.
+        }
+    }
+}
+
+impl<T: Number, U: Number> Cluster<T, U> {
+    /// Compares the center and radius of up to `num_clusters` randomly chosen `Clusters` in the subtree, including
+    /// this one, with their exact values. The errors are returned in breadth-first order of the `Clusters`.
+    ///
+    /// This takes `n^2` distance computations for each chosen `Cluster` of `n` instances,
+    /// so it is meant to validate the `CenterPolicy` and `RadiusPolicy` on a small sample of the tree.
+    pub fn sampling_errors(self: &Arc<Self>, num_clusters: usize) -> Vec<SamplingError<U>> {
+        self.sampling_errors_with_rng(num_clusters, &mut rand::thread_rng())
+    }
+
+    /// Compares the centers and radii of `Clusters` as with `sampling_errors`, choosing the `Clusters` with the given
+    /// generator, e.g. a seeded one so that the errors are reproducible.
+    pub fn sampling_errors_with_rng(
+        self: &Arc<Self>,
+        num_clusters: usize,
+        rng: &mut dyn RngCore,
+    ) -> Vec<SamplingError<U>> {
+        let mut clusters = self.flatten_tree();
+        clusters.push(Arc::clone(self));
+        clusters.sort();
+
+        let num_clusters = num_clusters.min(clusters.len());
+        let mut chosen = (0..clusters.len()).choose_multiple(rng, num_clusters);
+        chosen.sort_unstable();
+
+        chosen.into_par_iter().map(|i| clusters[i].sampling_error()).collect()
+    }
+
+    fn sampling_error(&self) -> SamplingError<U> {
+        let distances = self.dataset.pairwise_distances(&self.indices);
+        let costs: Vec<f64> = distances
+            .outer_iter()
+            .map(|row| row.iter().map(|distance| distance.as_f64()).sum())
+            .collect();
+        let center = self.indices.iter().position(|&i| i == self.argcenter).unwrap();
+        let exact_radius =
+            distances.row(center).iter().fold(
+                U::zero(),
+                |radius, &distance| if distance > radius { distance } else { radius },
+            );
+
+        SamplingError {
+            name: self.name.clone(),
+            cardinality: self.cardinality,
+            estimated_radius: self.radius,
+            exact_radius,
+            center_cost: costs[center],
+            medoid_cost: costs.iter().cloned().fold(f64::INFINITY, f64::min),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+    use crate::CenterPolicy;
+    use crate::RadiusPolicy;
+
+    #[test]
+    fn test_sampling_errors() {
+        let data: Vec<_> = (0..300).map(|i| vec![(i * i % 301) as f64, (i % 17) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+
+        // Exact policies have no error.
+        let exact = Cakes::build_with_policies(
+            Arc::clone(&dataset),
+            Some(4),
+            None,
+            CenterPolicy::Exact,
+            RadiusPolicy::Exact,
+        );
+        let errors = exact.root.sampling_errors(usize::MAX);
+        assert_eq!(errors.len(), exact.root.num_descendants() + 1);
+        assert_eq!(errors[0].name, exact.root.name);
+        for error in errors.iter() {
+            float_cmp::assert_approx_eq!(f64, error.radius_ratio(), 1.);
+            float_cmp::assert_approx_eq!(f64, error.center_excess(), 0.);
+        }
+
+        // Sampling leaves the radius no larger, and the center no better, than exact.
+        let sampled = Cakes::build_with_policies(
+            Arc::clone(&dataset),
+            Some(4),
+            None,
+            CenterPolicy::SampleSize(3),
+            RadiusPolicy::SampleSize(10),
+        );
+        assert!(sampled.root.radius_is_estimated());
+        let report = sampled.report.unwrap();
+        assert!(report.criteria.contains(&"radius_policy=SampleSize(10)".to_string()));
+
+        let errors = sampled.root.sampling_errors(8);
+        assert_eq!(errors.len(), 8);
+        let seeded = |seed: u64| {
+            sampled
+                .root
+                .sampling_errors_with_rng(8, &mut StdRng::seed_from_u64(seed))
+        };
+        assert_eq!(seeded(42), seeded(42));
+        assert_ne!(
+            seeded(42).iter().map(|e| e.name.clone()).collect::<Vec<_>>(),
+            seeded(7).iter().map(|e| e.name.clone()).collect::<Vec<_>>()
+        );
+        for error in errors.iter() {
+            assert!(error.radius_ratio() <= 1.);
+            assert!(error.center_excess() >= 0.);
+        }
+        let root_error = &sampled.root.sampling_errors(usize::MAX)[0];
+        assert!(root_error.estimated_radius <= root_error.exact_radius);
+    }
+
+    #[test]
+    fn test_tighten_radii() {
+        let data: Vec<_> = (0..300).map(|i| vec![(i * i % 301) as f64, (i % 17) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let criteria = vec![criteria::max_depth(4), criteria::min_cardinality(1)];
+        let manifold =
+            Manifold::new_with_policies(dataset, &criteria, CenterPolicy::Sampled, RadiusPolicy::SampleSize(5));
+        let estimated = |cluster: &Arc<Cluster<f64, f64>>| {
+            let mut clusters = cluster.flatten_tree();
+            clusters.push(Arc::clone(cluster));
+            clusters
+                .into_iter()
+                .filter(|c| c.radius_is_estimated())
+                .map(|c| c.depth())
+                .collect::<Vec<_>>()
+        };
+        assert!(estimated(&manifold.root).len() > 2);
+
+        let shallow = manifold.tighten_radii(Some(&[0, 1]));
+        assert!(estimated(&shallow.root).iter().all(|&depth| depth > 1));
+        assert!(!estimated(&shallow.root).is_empty());
+
+        let tight = manifold.tighten_radii(None);
+        assert!(estimated(&tight.root).is_empty());
+        assert_eq!(tight.root.num_descendants(), manifold.root.num_descendants());
+        for error in tight.root.sampling_errors(usize::MAX) {
+            float_cmp::assert_approx_eq!(f64, error.radius_ratio(), 1.);
+        }
+
+        // Every child points at its copied parent.
+        for cluster in tight.root.flatten_tree() {
+            let parent = cluster.parent.as_ref().unwrap().upgrade().unwrap();
+            assert!(parent.is_ancestor_of(&cluster));
+        }
+    }
+}
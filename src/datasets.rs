@@ -0,0 +1,245 @@
+//! Small datasets, bundled into the library behind the `demo` feature, with their exact nearest neighbors, so that
+//! tutorials, examples and tests can build, search, compress and score the same data end to end.
+//!
+//! `demo` returns two datasets:
+//!
+//! - `annthyroid`, 7200 thyroid-function records with 6 features each, from the ODDS benchmarks of anomaly detection,
+//!   labeled 1 for the 534 patients with hypothyroidism and 0 otherwise. It is searched with `euclidean`.
+//! - `mutations`, 512 DNA sequences of 96 bases, descended from one ancestor through 8 lineages of random
+//!   substitutions, labeled with their lineage. It is searched with `hamming`. The sequences are generated, by a
+//!   generator that is fixed here, so they are the same on every platform and in every release.
+//!
+//! The bundled files are checked against their checksums when they are read.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use ndarray_npy::ReadNpyExt;
+use rayon::prelude::*;
+
+use crate::dataset::RowMajor;
+use crate::io::content_hash;
+use crate::prelude::*;
+use crate::utils::compare_distances;
+
+/// The number of nearest neighbors recorded for each query of a demo dataset.
+pub const DEMO_K: usize = 10;
+
+const ANNTHYROID: &[u8] = include_bytes!("../data/annthyroid.npy");
+const ANNTHYROID_LABELS: &[u8] = include_bytes!("../data/annthyroid_labels.npy");
+const ANNTHYROID_HASH: u64 = 0x353e_8872_4e47_200d;
+const ANNTHYROID_LABELS_HASH: u64 = 0x008d_3349_9c48_7a3e;
+
+/// One demo dataset, with queries and their exact nearest neighbors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoData<T: Number, U: Number> {
+    pub name: &'static str,
+
+    /// The name of the metric the neighbors were found with, for `metric_from_name`.
+    pub metric: &'static str,
+
+    pub instances: Vec<Vec<T>>,
+
+    /// The label of each instance. See the module documentation for their meaning in each dataset.
+    pub labels: Vec<u8>,
+
+    /// The indices of the instances that serve as queries.
+    pub queries: Vec<Index>,
+
+    /// The `DEMO_K` nearest neighbors of each query, including the query itself, with their distances, nearest first.
+    /// Instances at the same distance are in the order of their indices.
+    pub neighbors: Vec<Vec<(Index, U)>>,
+}
+
+/// The datasets returned by `demo`.
+#[derive(Debug, Clone)]
+pub struct Demo {
+    pub vectors: DemoData<f64, f64>,
+    pub sequences: DemoData<u8, u64>,
+}
+
+/// Reads the bundled datasets and finds the exact nearest neighbors of their queries by linear scans.
+///
+/// Returns an Err if a bundled file does not match its checksum.
+pub fn demo() -> Result<Demo, String> {
+    Ok(Demo {
+        vectors: annthyroid()?,
+        sequences: mutations(),
+    })
+}
+
+impl<T: 'static + Number, U: 'static + Number> DemoData<T, U> {
+    /// Returns the instances as a dataset, with the metric of the demo.
+    pub fn dataset(&self) -> Arc<dyn Dataset<T, U>> {
+        let metric = metric_from_name(self.metric).unwrap();
+        Arc::new(RowMajor::new(Arc::new(self.instances.clone()), metric, false))
+    }
+
+    /// Returns the fraction of the exact nearest neighbors of the query at the given position in `queries` that the
+    /// hits hold. A hit counts if it is no farther than the farthest exact neighbor, since ties may be broken either
+    /// way.
+    pub fn recall(&self, query: usize, hits: &[(Index, U)]) -> f64 {
+        let exact = &self.neighbors[query];
+        let farthest = exact.last().unwrap().1;
+        let found = hits.iter().filter(|(_, d)| *d <= farthest).count();
+        std::cmp::min(found, exact.len()) as f64 / exact.len() as f64
+    }
+
+    fn with_neighbors(
+        name: &'static str,
+        metric: &'static str,
+        instances: Vec<Vec<T>>,
+        labels: Vec<u8>,
+        queries: Vec<Index>,
+    ) -> Self {
+        let distance = metric_from_name::<T, U>(metric).unwrap();
+        let neighbors = queries
+            .par_iter()
+            .map(|&q| {
+                let mut hits: Vec<_> = instances
+                    .iter()
+                    .enumerate()
+                    .map(|(i, instance)| (i, distance.distance(&instances[q], instance)))
+                    .collect();
+                hits.sort_by(|(i, a), (j, b)| compare_distances(a, b).then(i.cmp(j)));
+                hits.truncate(DEMO_K);
+                hits
+            })
+            .collect();
+        DemoData {
+            name,
+            metric,
+            instances,
+            labels,
+            queries,
+            neighbors,
+        }
+    }
+}
+
+fn annthyroid() -> Result<DemoData<f64, f64>, String> {
+    for (file, bytes, hash) in [
+        ("annthyroid.npy", ANNTHYROID, ANNTHYROID_HASH),
+        ("annthyroid_labels.npy", ANNTHYROID_LABELS, ANNTHYROID_LABELS_HASH),
+    ] {
+        if content_hash(bytes) != hash {
+            return Err(format!("The bundled {} does not match its checksum.", file));
+        }
+    }
+
+    let fail = |error: ndarray_npy::ReadNpyError| format!("Error: Failed to read the bundled annthyroid. {}", error);
+    let data = Array2::<f64>::read_npy(ANNTHYROID).map_err(fail)?;
+    let labels = Array1::<u8>::read_npy(ANNTHYROID_LABELS).map_err(fail)?;
+    let instances = data.outer_iter().map(|row| row.to_vec()).collect();
+    let queries = (0..data.nrows()).step_by(72).collect();
+    Ok(DemoData::with_neighbors(
+        "annthyroid",
+        "euclidean",
+        instances,
+        labels.to_vec(),
+        queries,
+    ))
+}
+
+fn mutations() -> DemoData<u8, u64> {
+    let (length, lineages, members) = (96, 8, 64);
+
+    // A xorshift generator, rather than `rand`, so that the sequences never change with the version of a dependency.
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+    let mut mutate = |sequence: &[u8], substitutions: usize| {
+        let mut sequence = sequence.to_vec();
+        for _ in 0..substitutions {
+            let position = next(length);
+            sequence[position] = b"ACGT"[next(4)];
+        }
+        sequence
+    };
+
+    let ancestor = mutate(&vec![b'A'; length], 4 * length);
+    let (mut instances, mut labels) = (vec![], vec![]);
+    for lineage in 0..lineages {
+        let founder = mutate(&ancestor, 16);
+        for _ in 0..members {
+            instances.push(mutate(&founder, 4));
+            labels.push(lineage as u8);
+        }
+    }
+    let queries = (0..instances.len()).step_by(8).collect();
+    DemoData::with_neighbors("mutations", "hamming", instances, labels, queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use eval_metrics::classification::RocCurve;
+
+    use crate::codec::Codec;
+    use crate::dataset::RowMajor;
+    use crate::Cakes;
+
+    use super::demo;
+    use super::DEMO_K;
+
+    #[test]
+    fn test_demo() {
+        let demo = demo().unwrap();
+        let vectors = &demo.vectors;
+        assert_eq!((vectors.instances.len(), vectors.instances[0].len()), (7200, 6));
+        assert_eq!(vectors.labels.iter().filter(|&&label| label == 1).count(), 534);
+        assert_eq!(vectors.queries.len(), vectors.neighbors.len());
+        assert!(vectors
+            .neighbors
+            .iter()
+            .all(|hits| hits.len() == DEMO_K && hits[0].1 == 0.));
+
+        // Build and search.
+        let cakes = Cakes::build(vectors.dataset(), None, None);
+        for (i, &query) in vectors.queries.iter().enumerate().step_by(10) {
+            let hits = cakes.knn(&vectors.instances[query], DEMO_K);
+            assert_eq!(vectors.recall(i, &hits), 1.);
+        }
+
+        // Compress and search the sequences.
+        let sequences = &demo.sequences;
+        assert_eq!(
+            sequences,
+            &super::mutations(),
+            "The sequences must be the same on every run."
+        );
+        let row_major = Arc::new(RowMajor::new(
+            Arc::new(sequences.instances.clone()),
+            crate::metric_from_name(sequences.metric).unwrap(),
+            false,
+        ));
+        let cakes = Cakes::build(Arc::clone(&row_major).as_arc_dataset(), None, None);
+        let codec = Codec::from_cakes(&row_major.as_arc_compressible_dataset(), &cakes).unwrap();
+        for (i, &query) in sequences.queries.iter().enumerate().step_by(8) {
+            let radius = sequences.neighbors[i].last().unwrap().1;
+            let found = codec.rnn_instances(&sequences.instances[query], Some(radius)).unwrap();
+            assert!(found.len() >= DEMO_K);
+            let hits = cakes.knn(&sequences.instances[query], DEMO_K);
+            assert_eq!(sequences.recall(i, &hits), 1.);
+        }
+
+        // Score the anomalies. The meta-ml models of CHAODA expect both of these metrics.
+        let manhattan = crate::metric_from_name("manhattan").unwrap();
+        let data = Arc::new(vectors.instances.clone());
+        let chaoda = crate::Chaoda::new(
+            vec![vectors.dataset(), Arc::new(RowMajor::new(data, manhattan, false))],
+            Some(8),
+            None,
+            crate::get_meta_ml_methods(),
+            None,
+            false,
+        );
+        let labels: Vec<_> = vectors.labels.iter().map(|&label| label == 1).collect();
+        assert!(RocCurve::compute(&chaoda.scores, &labels).unwrap().auc() > 0.5);
+    }
+}
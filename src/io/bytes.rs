@@ -0,0 +1,281 @@
+//! Portable primitives for reading and writing the binary formats in `io`.
+//!
+//! Every multi-byte value is stored in big-endian byte-order and every `usize` is widened to a `u64`.
+//! This keeps the bytes identical regardless of the endianness or pointer-width of the machine that wrote them.
+
+use std::convert::TryInto;
+
+use bitvec::prelude::*;
+
+use crate::Number;
+
+/// Magic bytes at the start of every artifact written by `CLAM`.
+pub const MAGIC: &[u8; 4] = b"CLAM";
+
+/// Appends values to a growing buffer of bytes.
+#[derive(Debug, Default)]
+pub struct ByteWriter {
+    bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn new() -> Self {
+        ByteWriter { bytes: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes a `Number` using its own (big-endian) byte representation.
+    pub fn write_number<N: Number>(&mut self, value: N) {
+        self.bytes.extend_from_slice(&value.to_bytes());
+    }
+
+    /// Writes a length-prefixed sequence of `Numbers`.
+    pub fn write_numbers<N: Number>(&mut self, values: &[N]) {
+        self.write_usize(values.len());
+        values.iter().for_each(|&value| self.write_number(value));
+    }
+
+    /// Writes a length-prefixed sequence of `usize`s.
+    pub fn write_usizes(&mut self, values: &[usize]) {
+        self.write_usize(values.len());
+        values.iter().for_each(|&value| self.write_usize(value));
+    }
+
+    /// Writes a length-prefixed slice of raw bytes.
+    pub fn write_bytes(&mut self, values: &[u8]) {
+        self.write_usize(values.len());
+        self.bytes.extend_from_slice(values);
+    }
+
+    /// Writes raw bytes without a length prefix.
+    pub fn write_raw(&mut self, values: &[u8]) {
+        self.bytes.extend_from_slice(values);
+    }
+
+    /// Writes a length-prefixed utf-8 string.
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    /// Writes the number of bits followed by the bits packed into bytes, most-significant bit first.
+    ///
+    /// We do not rely on the memory layout of `BitVec` because its storage word is a `usize`.
+    pub fn write_bitvec(&mut self, bits: &BitVec) {
+        self.write_usize(bits.len());
+        for chunk in bits.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0_u8, |byte, (i, bit)| if *bit { byte | (0x80 >> i) } else { byte });
+            self.write_u8(byte);
+        }
+    }
+}
+
+/// Reads values back from a slice of bytes written by a `ByteWriter`.
+///
+/// Every read is bounds-checked so that truncated or corrupted inputs produce an Err instead of a panic.
+#[derive(Debug)]
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, position: 0 }
+    }
+
+    /// Returns whether all bytes have been consumed.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    /// Returns the number of bytes that have not yet been consumed.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    /// Consumes and returns all remaining bytes.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.position..];
+        self.position = self.bytes.len();
+        rest
+    }
+
+    /// Consumes and returns the next `n` bytes, without a length prefix.
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.remaining() {
+            return Err(format!(
+                "Unexpected end of data: needed {} bytes at offset {} but only {} remain.",
+                n,
+                self.position,
+                self.remaining()
+            ));
+        }
+        let slice = &self.bytes[self.position..(self.position + n)];
+        self.position += n;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a `u64` and narrows it to a `usize`, returning an Err if it does not fit on this platform.
+    pub fn read_usize(&mut self) -> Result<usize, String> {
+        let value = self.read_u64()?;
+        value
+            .try_into()
+            .map_err(|_| format!("Value {} does not fit in a usize on this platform.", value))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_number<N: Number>(&mut self) -> Result<N, String> {
+        Ok(N::from_bytes(self.take(N::num_bytes() as usize)?))
+    }
+
+    /// Reads a length prefix, checking that at least `length * min_item_size` bytes remain.
+    ///
+    /// This prevents a corrupted length from causing a huge allocation.
+    fn read_length(&mut self, min_item_size: usize) -> Result<usize, String> {
+        let length = self.read_usize()?;
+        if length.saturating_mul(min_item_size) > self.remaining() {
+            Err(format!(
+                "Length {} at offset {} exceeds the remaining {} bytes.",
+                length,
+                self.position,
+                self.remaining()
+            ))
+        } else {
+            Ok(length)
+        }
+    }
+
+    pub fn read_numbers<N: Number>(&mut self) -> Result<Vec<N>, String> {
+        let length = self.read_length(N::num_bytes() as usize)?;
+        (0..length).map(|_| self.read_number()).collect()
+    }
+
+    pub fn read_usizes(&mut self) -> Result<Vec<usize>, String> {
+        let length = self.read_length(8)?;
+        (0..length).map(|_| self.read_usize()).collect()
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let length = self.read_length(1)?;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    pub fn read_str(&mut self) -> Result<String, String> {
+        String::from_utf8(self.read_bytes()?).map_err(|error| format!("Invalid utf-8 string. {}", error))
+    }
+
+    pub fn read_bitvec(&mut self) -> Result<BitVec, String> {
+        let num_bits = self.read_usize()?;
+        let num_bytes = (num_bits / 8) + usize::from(num_bits % 8 != 0);
+        let bytes = self.take(num_bytes)?;
+        // The padding bits of the last byte are written as 0, so that every bitvec has exactly one encoding.
+        if num_bits % 8 != 0 && bytes[num_bytes - 1] & (0xff >> (num_bits % 8)) != 0 {
+            return Err(format!("The padding of a bitvec of {} bits is not zero.", num_bits));
+        }
+        Ok((0..num_bits).map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitvec::prelude::*;
+
+    use super::ByteReader;
+    use super::ByteWriter;
+
+    #[test]
+    fn test_big_endian_layout() {
+        let mut writer = ByteWriter::new();
+        writer.write_u16(0x0102);
+        writer.write_usize(3);
+        writer.write_number(1.5_f32);
+        writer.write_bitvec(&bitvec![1, 0, 1, 1, 0, 0, 0, 0, 1]);
+
+        // These bytes must not change across architectures.
+        #[rustfmt::skip]
+        let expected = vec![
+            0x01, 0x02, // u16
+            0, 0, 0, 0, 0, 0, 0, 3, // usize as u64
+            0x3f, 0xc0, 0x00, 0x00, // f32
+            0, 0, 0, 0, 0, 0, 0, 9, // number of bits
+            0b1011_0000, 0b1000_0000, // packed bits
+        ];
+        assert_eq!(writer.into_bytes(), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let name = bitvec![1, 0, 0, 1, 1];
+        let mut writer = ByteWriter::new();
+        writer.write_bitvec(&name);
+        writer.write_numbers(&[1_i16, -2, 3]);
+        writer.write_usizes(&[7, 8]);
+        writer.write_f64(-0.25);
+        writer.write_str("euclidean");
+        let bytes = writer.into_bytes();
+
+        let mut reader = ByteReader::new(&bytes);
+        assert_eq!(reader.read_bitvec().unwrap(), name);
+        assert_eq!(reader.read_numbers::<i16>().unwrap(), vec![1, -2, 3]);
+        assert_eq!(reader.read_usizes().unwrap(), vec![7, 8]);
+        assert_eq!(reader.read_f64().unwrap(), -0.25);
+        assert_eq!(reader.read_str().unwrap(), "euclidean");
+        assert!(reader.is_empty());
+
+        let mut reader = ByteReader::new(&bytes[..bytes.len() - 1]);
+        reader.read_bitvec().unwrap();
+        reader.read_numbers::<i16>().unwrap();
+        reader.read_usizes().unwrap();
+        reader.read_f64().unwrap();
+        assert!(reader.read_str().is_err());
+
+        // A set padding bit, e.g. in a root name of 0x81 instead of 0x80, would give two encodings of the same name.
+        let mut writer = ByteWriter::new();
+        writer.write_bitvec(&bitvec![1]);
+        let mut bytes = writer.into_bytes();
+        assert!(ByteReader::new(&bytes).read_bitvec().is_ok());
+        *bytes.last_mut().unwrap() = 0x81;
+        assert!(ByteReader::new(&bytes).read_bitvec().is_err());
+    }
+}
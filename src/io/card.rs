@@ -0,0 +1,211 @@
+//! A description of the dataset behind an artifact, stored with trees and compressed artifacts so that they document
+//! themselves when shared.
+
+use std::path::Path;
+
+use super::compressed;
+use super::migrate::type_width;
+use super::report;
+use super::ArtifactKind;
+use super::ByteReader;
+use super::ByteWriter;
+use super::Header;
+
+/// Where the dataset of an artifact came from and how it was prepared. Every field but the name is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatasetCard {
+    /// The name of the dataset, e.g. "sift-1m".
+    pub name: String,
+
+    /// Where the dataset may be found, e.g. a URL or a citation.
+    pub source: Option<String>,
+
+    /// The license under which the dataset may be used, e.g. "CC-BY-4.0".
+    pub license: Option<String>,
+
+    /// The steps, in order, that turned the source into the instances, e.g. "normalized to unit length".
+    pub preprocessing: Vec<String>,
+
+    /// The name of the metric the dataset is meant to be searched with, as in `metric_from_name`.
+    pub metric: Option<String>,
+}
+
+impl DatasetCard {
+    /// Returns an Err if the card names a metric other than the given one.
+    pub fn check_metric(&self, metric_name: &str) -> Result<(), String> {
+        match &self.metric {
+            Some(metric) if metric != metric_name => Err(format!(
+                "The card of '{}' names the metric {} but the dataset uses {}.",
+                self.name, metric, metric_name
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, writer: &mut ByteWriter) {
+        writer.write_str(&self.name);
+        write_optional_str(writer, self.source.as_deref());
+        write_optional_str(writer, self.license.as_deref());
+        writer.write_usize(self.preprocessing.len());
+        self.preprocessing.iter().for_each(|step| writer.write_str(step));
+        write_optional_str(writer, self.metric.as_deref());
+    }
+
+    fn read(reader: &mut ByteReader) -> Result<Self, String> {
+        let name = reader.read_str()?;
+        let source = read_optional_str(reader)?;
+        let license = read_optional_str(reader)?;
+        let num_steps = reader.read_usize()?;
+        if num_steps.saturating_mul(8) > reader.remaining() {
+            return Err(format!("Cannot read {} preprocessing steps.", num_steps));
+        }
+        let preprocessing = (0..num_steps).map(|_| reader.read_str()).collect::<Result<_, _>>()?;
+        Ok(DatasetCard {
+            name,
+            source,
+            license,
+            preprocessing,
+            metric: read_optional_str(reader)?,
+        })
+    }
+}
+
+impl std::fmt::Display for DatasetCard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "name: {}", self.name)?;
+        writeln!(f, "source: {}", self.source.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "license: {}", self.license.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "metric: {}", self.metric.as_deref().unwrap_or("unknown"))?;
+        write!(f, "preprocessing:")?;
+        if self.preprocessing.is_empty() {
+            write!(f, " none")?;
+        }
+        self.preprocessing
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, step)| write!(f, "\n  {}. {}", i + 1, step))
+    }
+}
+
+fn write_optional_str(writer: &mut ByteWriter, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            writer.write_u8(1);
+            writer.write_str(value);
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+fn read_optional_str(reader: &mut ByteReader) -> Result<Option<String>, String> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(reader.read_str()?)),
+        flag => Err(format!("Invalid string flag {}.", flag)),
+    }
+}
+
+/// Writes the optional card as a flag byte followed by the card.
+pub(crate) fn write_optional(writer: &mut ByteWriter, card: Option<&DatasetCard>) {
+    match card {
+        Some(card) => {
+            writer.write_u8(1);
+            card.write(writer);
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+pub(crate) fn read_optional(reader: &mut ByteReader) -> Result<Option<DatasetCard>, String> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(DatasetCard::read(reader)?)),
+        flag => Err(format!("Invalid dataset-card flag {}.", flag)),
+    }
+}
+
+/// Reads the dataset card stored with the tree or compressed artifact at the given path, without loading the rest of
+/// the artifact or its dataset.
+pub fn read_card(path: &Path) -> Result<Option<DatasetCard>, String> {
+    let bytes = super::read_file(path)?;
+    let mut reader = ByteReader::new(&bytes);
+    let header = Header::read(&mut reader)?;
+    header.check_version()?;
+    match header.kind {
+        ArtifactKind::Tree => {
+            report::read_optional(&mut reader)?;
+        }
+        ArtifactKind::Compressed => {
+            let center_length = reader.read_usize()?;
+            reader.take(center_length.saturating_mul(type_width(&header.instance_type)?))?;
+            compressed::read_metadata(&mut reader)?;
+        }
+        ArtifactKind::Trace => return Err("Traces do not store dataset cards.".to_string()),
+    }
+    read_optional(&mut reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::codec::Codec;
+    use crate::dataset::RowMajor;
+    use crate::io;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::DatasetCard;
+
+    #[test]
+    fn test_dataset_card() {
+        let data: Vec<Vec<u8>> = (0..30).map(|i: usize| vec![(i % 4) as u8, (i % 7) as u8, 1]).collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let dataset = Arc::clone(&row_major).as_arc_dataset();
+        let card = DatasetCard {
+            name: "toy".to_string(),
+            license: Some("CC0-1.0".to_string()),
+            preprocessing: vec!["rows taken modulo 4 and 7".to_string()],
+            metric: Some("hamming".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            card.to_string(),
+            "name: toy\nsource: unknown\nlicense: CC0-1.0\nmetric: hamming\npreprocessing:\n  1. rows taken modulo 4 and 7"
+        );
+
+        // The card must agree with the metric of the dataset.
+        let wrong = DatasetCard {
+            metric: Some("euclidean".to_string()),
+            ..card.clone()
+        };
+        assert!(Cakes::build(Arc::clone(&dataset), None, None).with_card(wrong).is_err());
+
+        // Trees keep their cards through saving and loading, and the card may be read on its own.
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None)
+            .with_card(card.clone())
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("clam-test-card-{}.tree", std::process::id()));
+        cakes.save(&path).unwrap();
+        assert_eq!(io::read_card(&path).unwrap().as_ref(), Some(&card));
+        assert_eq!(io::read_report(&path).unwrap(), cakes.report);
+        assert_eq!(Cakes::load(&path, Arc::clone(&dataset)).unwrap().card(), Some(&card));
+        std::fs::remove_file(&path).unwrap();
+
+        // So do compressed artifacts.
+        let compressible = Arc::clone(&row_major).as_arc_compressible_dataset();
+        let codec = Codec::from_cakes(&compressible, &cakes)
+            .unwrap()
+            .with_card(card.clone())
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("clam-test-card-{}.codec", std::process::id()));
+        io::save_codec(&codec, &path).unwrap();
+        assert_eq!(io::read_card(&path).unwrap().as_ref(), Some(&card));
+        assert_eq!(
+            io::load_codec(&path, Arc::clone(&compressible)).unwrap().card(),
+            Some(&card)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+//! Binary format for compressed trees, i.e. a `Codec` of `PackableClusters`.
+//!
+//! After the header come the instances of the reference center, the optional metadata of the instances, the optional
+//! `DatasetCard`, a table of the clusters in breadth-first order and then the blocks of encoded instances of the
+//! clusters.
+//! Each record in the table holds the name, cardinality, indices, encoded center and radius of a cluster, followed by
+//! the offset, length and number of encodings of its block.
+//!
+//! Blocks are written in depth-first order, so the leaves of any subtree are adjacent, and their offsets are relative
+//! to the start of the first block. Loading only parses the table: the blocks are sliced out of the loaded,
+//! or memory-mapped, bytes without copying them.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+
+use super::card;
+use super::ArtifactKind;
+use super::ByteReader;
+use super::ByteWriter;
+use super::Header;
+use super::MappedFile;
+use crate::codec::Codec;
+use crate::codec::EncodingBlock;
+use crate::codec::PackableCluster;
+use crate::codec::SharedBytes;
+use crate::prelude::*;
+use crate::CompressibleDataset;
+
+/// Each record in the table takes at least this many bytes, which bounds the number of clusters in a valid input.
+const MIN_RECORD_BYTES: usize = 58;
+
+/// A cluster whose fields, other than its encodings, are already serialized.
+pub(super) struct ClusterRecord<'a> {
+    pub name: BitVec,
+    /// The name, cardinality, indices, center and radius of the cluster.
+    pub fields: Vec<u8>,
+    pub block: &'a [u8],
+    pub num_encodings: usize,
+}
+
+/// Writes the table of clusters followed by their blocks.
+pub(super) fn write_records(writer: &mut ByteWriter, records: &[ClusterRecord]) {
+    // The depth-first order of clusters is the lexicographic order of their names.
+    let mut depth_first: Vec<_> = (0..records.len()).collect();
+    depth_first.sort_by(|&a, &b| records[a].name.cmp(&records[b].name));
+    let mut offsets = vec![0; records.len()];
+    let mut end = 0;
+    for &i in depth_first.iter() {
+        offsets[i] = end;
+        end += records[i].block.len();
+    }
+
+    let mut breadth_first: Vec<_> = (0..records.len()).collect();
+    breadth_first
+        .sort_by(|&a, &b| (records[a].name.len(), &records[a].name).cmp(&(records[b].name.len(), &records[b].name)));
+    writer.write_usize(records.len());
+    for &i in breadth_first.iter() {
+        writer.write_raw(&records[i].fields);
+        writer.write_usize(offsets[i]);
+        writer.write_usize(records[i].block.len());
+        writer.write_usize(records[i].num_encodings);
+    }
+
+    writer.write_usize(end);
+    depth_first.into_iter().for_each(|i| writer.write_raw(records[i].block));
+}
+
+/// Serializes the clusters and the reference center of the given `Codec`.
+pub fn codec_to_bytes<T: Number, U: Number>(codec: &Codec<T, U>) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    Header::new::<T, U>(ArtifactKind::Compressed).write(&mut writer);
+    writer.write_numbers(&codec.center);
+    write_metadata(&mut writer, codec.metadata());
+    card::write_optional(&mut writer, codec.card());
+
+    let records: Vec<_> = codec
+        .tree_map
+        .values()
+        .map(|cluster| {
+            let mut fields = ByteWriter::new();
+            fields.write_bitvec(&cluster.name);
+            fields.write_usize(cluster.cardinality);
+            fields.write_usizes(&cluster.indices);
+            fields.write_bytes(&cluster.center);
+            fields.write_number(cluster.radius);
+            ClusterRecord {
+                name: cluster.name.clone(),
+                fields: fields.into_bytes(),
+                block: cluster.encodings.as_bytes(),
+                num_encodings: cluster.encodings.len(),
+            }
+        })
+        .collect();
+    write_records(&mut writer, &records);
+
+    writer.into_bytes()
+}
+
+/// Deserializes a `Codec` and attaches it to the given dataset, whose metric is used for decoding.
+pub fn codec_from_bytes<T: Number, U: Number>(
+    bytes: &[u8],
+    dataset: Arc<dyn CompressibleDataset<T, U>>,
+) -> Result<Codec<T, U>, String> {
+    codec_from_shared(Arc::new(bytes.to_vec()), dataset)
+}
+
+/// Deserializes a `Codec` whose encoded instances remain in, and are read directly from, the given bytes.
+pub fn codec_from_shared<T: Number, U: Number>(
+    bytes: SharedBytes,
+    dataset: Arc<dyn CompressibleDataset<T, U>>,
+) -> Result<Codec<T, U>, String> {
+    let all = (*bytes).as_ref();
+    let mut reader = ByteReader::new(all);
+    Header::read(&mut reader)?.check::<T, U>(ArtifactKind::Compressed)?;
+    let center = reader.read_numbers()?;
+    let metadata = read_metadata(&mut reader)?;
+    let card = card::read_optional(&mut reader)?;
+
+    let num_clusters = reader.read_usize()?;
+    if num_clusters.saturating_mul(MIN_RECORD_BYTES) > reader.remaining() {
+        return Err(format!(
+            "Cannot read {} clusters from {} bytes.",
+            num_clusters,
+            reader.remaining()
+        ));
+    }
+    let records = (0..num_clusters)
+        .map(|_| read_record(&mut reader))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let blocks_length = reader.read_usize()?;
+    if blocks_length != reader.remaining() {
+        return Err(format!(
+            "The blocks should take {} bytes but {} remain.",
+            blocks_length,
+            reader.remaining()
+        ));
+    }
+    let blocks_start = all.len() - blocks_length;
+
+    let mut tree_map = HashMap::new();
+    for (mut cluster, offset, length, num_encodings) in records {
+        let start = blocks_start
+            .checked_add(offset)
+            .ok_or_else(|| format!("Block offset {} is out of bounds.", offset))?;
+        let end = start
+            .checked_add(length)
+            .ok_or_else(|| format!("Block length {} is out of bounds.", length))?;
+        cluster.encodings = EncodingBlock::from_shared(Arc::clone(&bytes), start, end, num_encodings)
+            .map_err(|error| format!("Cluster {:?} has a bad block. {}", cluster.name, error))?;
+
+        if tree_map.contains_key(&cluster.name) {
+            return Err(format!("Found duplicate cluster named {:?}.", cluster.name));
+        }
+        tree_map.insert(cluster.name.clone(), Arc::new(cluster));
+    }
+
+    let mut codec = Codec::new(dataset, center, tree_map)?;
+    if let Some(metadata) = metadata {
+        codec = codec.with_metadata(metadata)?;
+    }
+    match card {
+        Some(card) => codec.with_card(card),
+        None => Ok(codec),
+    }
+}
+
+/// Writes a flag for whether there is metadata, followed by the metadata if there is any.
+pub(super) fn write_metadata(writer: &mut ByteWriter, metadata: Option<&[String]>) {
+    match metadata {
+        Some(metadata) => {
+            writer.write_u8(1);
+            writer.write_usize(metadata.len());
+            metadata.iter().for_each(|entry| writer.write_str(entry));
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+/// Reads the metadata written by `write_metadata`.
+pub(super) fn read_metadata(reader: &mut ByteReader) -> Result<Option<Vec<String>>, String> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => {
+            // Each entry takes at least the 8 bytes of its length.
+            let len = reader.read_usize()?;
+            if len.saturating_mul(8) > reader.remaining() {
+                return Err(format!(
+                    "Cannot read metadata for {} instances from {} bytes.",
+                    len,
+                    reader.remaining()
+                ));
+            }
+            (0..len).map(|_| reader.read_str()).collect::<Result<_, _>>().map(Some)
+        }
+        flag => Err(format!("Invalid metadata flag {}.", flag)),
+    }
+}
+
+/// Writes the given `Codec` to a file.
+pub fn save_codec<T: Number, U: Number>(codec: &Codec<T, U>, path: &Path) -> Result<(), String> {
+    super::write_file(path, &codec_to_bytes(codec))
+}
+
+/// Reads a `Codec` from a file and attaches it to the given dataset.
+pub fn load_codec<T: Number, U: Number>(
+    path: &Path,
+    dataset: Arc<dyn CompressibleDataset<T, U>>,
+) -> Result<Codec<T, U>, String> {
+    codec_from_shared(Arc::new(super::read_file(path)?), dataset)
+}
+
+/// Memory-maps a `Codec` from a file and attaches it to the given dataset.
+///
+/// Only the table of clusters is read up front. The encoded instances are read from the mapping when searches
+/// reach their leaves, so the file must not be modified while the `Codec` is alive.
+pub fn map_codec<T: Number, U: Number>(
+    path: &Path,
+    dataset: Arc<dyn CompressibleDataset<T, U>>,
+) -> Result<Codec<T, U>, String> {
+    codec_from_shared(Arc::new(MappedFile::open(path)?), dataset)
+}
+
+/// Reads a record from the table, returning the cluster, with no encodings, and the offset, length and number of
+/// encodings of its block.
+fn read_record<U: Number>(reader: &mut ByteReader) -> Result<(PackableCluster<U>, usize, usize, usize), String> {
+    let name = reader.read_bitvec()?;
+    if name.is_empty() {
+        return Err("Found a cluster with an empty name.".to_string());
+    }
+    let cluster = PackableCluster {
+        name,
+        cardinality: reader.read_usize()?,
+        indices: reader.read_usizes()?,
+        center: reader.read_bytes()?,
+        radius: reader.read_number()?,
+        encodings: EncodingBlock::from_encodings(&[]),
+    };
+    Ok((
+        cluster,
+        reader.read_usize()?,
+        reader.read_usize()?,
+        reader.read_usize()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::codec::Codec;
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+    use crate::CompressibleDataset;
+
+    use super::codec_from_bytes;
+    use super::codec_to_bytes;
+    use super::map_codec;
+    use super::save_codec;
+
+    #[test]
+    fn test_round_trip() {
+        let data = vec![
+            vec![0, 0, 0],
+            vec![1, 1, 1],
+            vec![2, 2, 2],
+            vec![3, 3, 3],
+            vec![0, 1, 2],
+        ];
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let dataset: Arc<dyn CompressibleDataset<u8, u64>> = Arc::clone(&row_major).as_arc_compressible_dataset();
+        let cakes = Cakes::build(row_major.as_arc_dataset(), None, None);
+
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+
+        let bytes = codec_to_bytes(&codec);
+        let loaded = codec_from_bytes(&bytes, Arc::clone(&dataset)).unwrap();
+        assert_eq!(loaded.center, codec.center);
+        assert_eq!(loaded.tree_map.len(), codec.tree_map.len());
+        for (name, cluster) in codec.tree_map.iter() {
+            let other = loaded.tree_map.get(name).unwrap();
+            assert_eq!(cluster.cardinality, other.cardinality);
+            assert_eq!(cluster.indices, other.indices);
+            assert_eq!(cluster.center, other.center);
+            assert_eq!(cluster.radius, other.radius);
+            assert_eq!(cluster.encodings, other.encodings);
+        }
+        assert_eq!(bytes, codec_to_bytes(&loaded));
+
+        for end in [0, 4, bytes.len() / 2, bytes.len() - 1] {
+            assert!(codec_from_bytes(&bytes[..end], Arc::clone(&dataset)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_metadata() {
+        let data: Vec<Vec<u8>> = (0..40).map(|i: usize| vec![(i % 4) as u8, (i % 7) as u8, 1]).collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let dataset = Arc::clone(&row_major).as_arc_compressible_dataset();
+        let cakes = Cakes::build(row_major.as_arc_dataset(), None, None);
+        let names: Vec<_> = (0..40).map(|i| format!("instance {}", i)).collect();
+        let codec = Codec::from_cakes(&dataset, &cakes)
+            .unwrap()
+            .with_metadata(names.clone())
+            .unwrap();
+
+        let bytes = codec_to_bytes(&codec);
+        let loaded = codec_from_bytes(&bytes, Arc::clone(&dataset)).unwrap();
+        assert_eq!(loaded.metadata(), Some(names.as_slice()));
+        assert_eq!(bytes, codec_to_bytes(&loaded));
+
+        // The metadata must cover the dataset it is attached to.
+        let smaller = Arc::new(RowMajor::<u8, u64>::new(
+            Arc::new(vec![vec![0, 0, 1]; 10]),
+            metric_from_name("hamming").unwrap(),
+            false,
+        ));
+        assert!(codec_from_bytes(&bytes, smaller.as_arc_compressible_dataset()).is_err());
+    }
+
+    #[test]
+    fn test_map_codec() {
+        // Every instance appears five times, so the leaves hold encodings.
+        let data: Vec<Vec<u8>> = (0..100)
+            .map(|i: usize| (0..16).map(|j| ((i % 20 / (j + 1) + j) % 5) as u8).collect())
+            .collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data.clone()), metric, false));
+        let dataset = Arc::clone(&row_major).as_arc_compressible_dataset();
+        let cakes = Cakes::build(row_major.as_arc_dataset(), None, None);
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+
+        let path = std::env::temp_dir().join(format!("clam-test-map-codec-{}.codec", std::process::id()));
+        save_codec(&codec, &path).unwrap();
+        let mapped = map_codec(&path, Arc::clone(&dataset)).unwrap();
+
+        // The blocks of the leaves are sliced from the mapping in depth-first order.
+        let mut leaves: Vec<_> = mapped
+            .tree_map
+            .values()
+            .filter(|cluster| !cluster.encodings.is_empty())
+            .collect();
+        leaves.sort_by(|a, b| a.name.cmp(&b.name));
+        assert!(leaves.len() > 1);
+        for pair in leaves.windows(2) {
+            let (first, second) = (pair[0].encodings.as_bytes(), pair[1].encodings.as_bytes());
+            assert_eq!(first.as_ptr_range().end, second.as_ptr());
+        }
+
+        for query in data.iter().step_by(9) {
+            for radius in [0, 2, 5] {
+                let mut expected = codec.rnn_instances(query, Some(radius)).unwrap();
+                let mut actual = mapped.rnn_instances(query, Some(radius)).unwrap();
+                expected.sort();
+                actual.sort();
+                assert_eq!(actual, expected);
+            }
+        }
+        assert_eq!(codec_to_bytes(&mapped), codec_to_bytes(&codec));
+
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,417 @@
+//! Trees that are parsed without a dataset.
+//!
+//! `load_tree` parses a tree and attaches it to a dataset in one step. A `TreeHandle` only parses the structure of the
+//! tree, so it may be loaded once and then attached, any number of times, to datasets holding the same instances,
+//! e.g. to an `MmapDataset` shared by the replicas of a search service.
+
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::Weak;
+
+use bitvec::prelude::*;
+use rayon::prelude::*;
+
+use super::card;
+use super::report;
+use super::tree;
+use super::ArtifactKind;
+use super::BuildReport;
+use super::ByteReader;
+use super::DatasetCard;
+use super::Header;
+use super::MappedFile;
+use crate::memory::Tracked;
+use crate::prelude::*;
+use crate::utils::write_cache;
+use crate::CenterPolicy;
+use crate::RadiusPolicy;
+
+/// A cluster of a parsed tree, with its children given as positions in the list of nodes.
+#[derive(Debug, Clone)]
+struct Node<U: Number> {
+    name: BitVec,
+    indices: Vec<Index>,
+    argcenter: Index,
+    argradius: Index,
+    radius: U,
+    lfd: f64,
+    ratios: [f64; 6],
+    children: Option<(usize, usize)>,
+}
+
+/// A serialized tree, parsed but not yet attached to a dataset.
+#[derive(Debug, Clone)]
+pub struct TreeHandle<T: Number, U: Number> {
+    report: Option<BuildReport>,
+    card: Option<DatasetCard>,
+    center_policy: CenterPolicy,
+    radius_policy: RadiusPolicy,
+    /// The clusters in pre-order, so the root comes first.
+    nodes: Vec<Node<U>>,
+    /// One more than the largest index referenced by any cluster.
+    min_cardinality: usize,
+    instance_type: PhantomData<T>,
+}
+
+impl<T: Number, U: Number> TreeHandle<T, U> {
+    /// Parses a tree written by `tree_to_bytes`.
+    ///
+    /// Returns an Err if the bytes are malformed or were written for different types.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(bytes);
+        Header::read(&mut reader)?.check::<T, U>(ArtifactKind::Tree)?;
+        let report = report::read_optional(&mut reader)?;
+        let card = card::read_optional(&mut reader)?;
+        let (center_policy, radius_policy) = tree::read_policies(&mut reader)?;
+
+        let nodes = read_nodes(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(format!("Found {} trailing bytes after the tree.", reader.remaining()));
+        }
+
+        let min_cardinality = nodes
+            .iter()
+            .flat_map(|node| {
+                [node.argcenter, node.argradius]
+                    .into_iter()
+                    .chain(node.indices.iter().cloned())
+            })
+            .max()
+            .map_or(0, |i| i + 1);
+
+        Ok(TreeHandle {
+            report,
+            card,
+            center_policy,
+            radius_policy,
+            nodes,
+            min_cardinality,
+            instance_type: PhantomData,
+        })
+    }
+
+    /// Parses a tree from a file written by `save_tree`. The file is memory-mapped while it is parsed.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::from_bytes(MappedFile::open(path)?.as_ref())
+    }
+
+    /// Returns the report of the build of the tree, if it was saved with one.
+    pub fn report(&self) -> Option<&BuildReport> {
+        self.report.as_ref()
+    }
+
+    /// Returns the description of the dataset of the tree, if it was saved with one.
+    pub fn card(&self) -> Option<&DatasetCard> {
+        self.card.as_ref()
+    }
+
+    /// Returns the number of clusters in the tree, including the root.
+    pub fn num_clusters(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the smallest cardinality of a dataset to which the tree may be attached.
+    pub fn min_cardinality(&self) -> usize {
+        self.min_cardinality
+    }
+
+    /// Builds the tree of `Clusters` over the given dataset and returns its root.
+    ///
+    /// The dataset must hold the same instances, under the same indices, as the dataset the tree was built on.
+    /// Returns an Err if the tree refers to instances that are not in the dataset.
+    pub fn attach(&self, dataset: Arc<dyn Dataset<T, U>>) -> Result<Arc<Cluster<T, U>>, String> {
+        if dataset.cardinality() < self.min_cardinality {
+            return Err(format!(
+                "The tree references index {} but the dataset only has {} instances.",
+                self.min_cardinality - 1,
+                dataset.cardinality()
+            ));
+        }
+
+        // The clusters are built one depth at a time, without recursion, so that every parent exists before its
+        // children, and then each parent is given its children.
+        let mut parents = vec![None; self.nodes.len()];
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        for (position, node) in self.nodes.iter().enumerate() {
+            if let Some((left, right)) = node.children {
+                parents[left] = Some(position);
+                parents[right] = Some(position);
+            }
+            let depth = node.name.len() - 1;
+            if levels.len() <= depth {
+                levels.resize(depth + 1, Vec::new());
+            }
+            levels[depth].push(position);
+        }
+        let mut clusters: Vec<Option<Arc<Cluster<T, U>>>> = vec![None; self.nodes.len()];
+        for level in levels {
+            let built: Vec<_> = level
+                .par_iter()
+                .map(|&position| {
+                    let parent = parents[position].map(|parent| Arc::downgrade(clusters[parent].as_ref().unwrap()));
+                    self.attach_node(position, &dataset, parent)
+                })
+                .collect();
+            for (position, cluster) in level.into_iter().zip(built) {
+                clusters[position] = Some(cluster);
+            }
+        }
+        for (position, node) in self.nodes.iter().enumerate() {
+            if let Some((left, right)) = node.children {
+                let children = (clusters[left].clone().unwrap(), clusters[right].clone().unwrap());
+                *write_cache(&clusters[position].as_ref().unwrap().children) = Some(children);
+            }
+        }
+        Ok(clusters[0].take().unwrap())
+    }
+
+    /// Builds the cluster at the given position, without its children.
+    fn attach_node(
+        &self,
+        position: usize,
+        dataset: &Arc<dyn Dataset<T, U>>,
+        parent: Option<Weak<Cluster<T, U>>>,
+    ) -> Arc<Cluster<T, U>> {
+        let node = &self.nodes[position];
+        Arc::new(Cluster {
+            dataset: Arc::clone(dataset),
+            name: node.name.clone(),
+            cardinality: node.indices.len(),
+            _memory: Tracked::cluster::<T, U>(node.indices.len()),
+            indices: node.indices.clone(),
+            argcenter: node.argcenter,
+            argradius: node.argradius,
+            radius: node.radius,
+            lfd: node.lfd,
+            children: RwLock::new(None),
+            parent,
+            ratios: node.ratios,
+            center_policy: self.center_policy,
+            radius_policy: self.radius_policy,
+            seed: self.report.as_ref().and_then(|report| report.seed),
+        })
+    }
+}
+
+/// Reads the clusters of a tree, in pre-order, into a list of nodes whose root comes first.
+///
+/// The clusters are read with an explicit stack of the parents whose subtrees are not yet complete, rather than by
+/// recursion, so that a corrupted or crafted artifact cannot overflow the stack. A cluster may be no deeper than the
+/// root has instances, which bounds the depth of every valid tree, since each child holds fewer instances than its
+/// parent.
+fn read_nodes<U: Number>(reader: &mut ByteReader) -> Result<Vec<Node<U>>, String> {
+    let mut nodes: Vec<Node<U>> = Vec::new();
+    // The parents whose subtrees are being read, with the position of their left child once it has been read.
+    let mut pending: Vec<(usize, Option<usize>)> = Vec::new();
+    loop {
+        let position = nodes.len();
+        let has_children = read_node(reader, &mut nodes)?;
+        let name = &nodes[position].name;
+        match pending.last() {
+            None if *name != bitvec![1] => {
+                return Err(format!(
+                    "The first cluster in the tree must be the root but was {:?}.",
+                    name
+                ));
+            }
+            Some(_) if name.len() > std::cmp::max(nodes[0].indices.len(), 1) => {
+                return Err(format!(
+                    "Cluster {:?} is deeper than the {} instances of the tree allow.",
+                    name,
+                    nodes[0].indices.len()
+                ));
+            }
+            _ => (),
+        }
+        if has_children {
+            pending.push((position, None));
+            continue;
+        }
+
+        // A leaf completes the subtrees of the parents whose right child it ends.
+        let mut child = position;
+        loop {
+            match pending.last_mut() {
+                None => return Ok(nodes),
+                Some((_, left @ None)) => {
+                    *left = Some(child);
+                    break;
+                }
+                Some(&mut (parent, Some(left))) => {
+                    let name = &nodes[parent].name;
+                    for (child, bit) in [(left, false), (child, true)] {
+                        let child = &nodes[child].name;
+                        let is_child =
+                            child.len() == name.len() + 1 && child[..name.len()] == name[..] && child[name.len()] == bit;
+                        if !is_child {
+                            return Err(format!("Cluster {:?} is not a valid child of {:?}.", child, name));
+                        }
+                    }
+                    nodes[parent].children = Some((left, child));
+                    pending.pop();
+                    child = parent;
+                }
+            }
+        }
+    }
+}
+
+/// Reads one cluster into the list of nodes, without its children, and returns whether it has children.
+fn read_node<U: Number>(reader: &mut ByteReader, nodes: &mut Vec<Node<U>>) -> Result<bool, String> {
+    let name = reader.read_bitvec()?;
+    if name.is_empty() {
+        return Err("Found a cluster with an empty name.".to_string());
+    }
+
+    let indices = reader.read_usizes()?;
+    let argcenter = reader.read_usize()?;
+    let argradius = reader.read_usize()?;
+    let radius = reader.read_number()?;
+    let lfd = reader.read_f64()?;
+    let mut ratios = [0.; 6];
+    for ratio in ratios.iter_mut() {
+        *ratio = reader.read_f64()?;
+    }
+
+    nodes.push(Node {
+        name,
+        indices,
+        argcenter,
+        argradius,
+        radius,
+        lfd,
+        ratios,
+        children: None,
+    });
+
+    match reader.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        flag => Err(format!(
+            "Invalid children flag {} for cluster {:?}.",
+            flag,
+            nodes.last().unwrap().name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bitvec::prelude::*;
+
+    use crate::dataset::RowMajor;
+    use crate::io::tree_to_bytes;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::super::ArtifactKind;
+    use super::super::ByteWriter;
+    use super::super::Header;
+    use super::TreeHandle;
+
+    #[test]
+    fn test_attach_many() {
+        let data: Vec<_> = (0..200).map(|i| vec![(i * 7 % 31) as f64, (i % 13) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false));
+        let cakes = Cakes::build(Arc::clone(&dataset), Some(6), None);
+        let bytes = tree_to_bytes(&cakes.root, cakes.report.as_ref(), None);
+
+        let handle = TreeHandle::<f64, f64>::from_bytes(&bytes).unwrap();
+        assert_eq!(handle.num_clusters(), cakes.root.num_descendants() + 1);
+        assert_eq!(handle.min_cardinality(), 200);
+        assert_eq!(handle.report(), cakes.report.as_ref());
+
+        // Every replica gets its own tree over its own dataset.
+        for _ in 0..3 {
+            let replica: Arc<dyn Dataset<f64, f64>> =
+                Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false));
+            let root = handle.attach(Arc::clone(&replica)).unwrap();
+            assert!(Arc::ptr_eq(&root.dataset, &replica));
+            assert_eq!(tree_to_bytes(&root, handle.report(), handle.card()), bytes);
+        }
+
+        let smaller: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data[..150].to_vec()), metric, false));
+        assert!(handle.attach(smaller).is_err());
+
+        assert!(TreeHandle::<f64, f32>::from_bytes(&bytes).is_err());
+        assert!(TreeHandle::<f64, f64>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    /// Returns the bytes of a tree whose root has the given number of instances and whose left children form a chain
+    /// of the given depth, each with a leaf as its right child. Only the root has more than one instance.
+    fn chain_tree(cardinality: usize, depth: usize) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        Header::new::<f64, f64>(ArtifactKind::Tree).write(&mut writer);
+        (0..4).for_each(|_| writer.write_u8(0));
+        let mut write_node = |name: &BitVec, indices: &[usize], has_children: bool| {
+            writer.write_bitvec(name);
+            writer.write_usizes(indices);
+            writer.write_usize(indices[0]);
+            writer.write_usize(indices[0]);
+            writer.write_number(0_f64);
+            (0..7).for_each(|_| writer.write_f64(0.));
+            writer.write_u8(u8::from(has_children));
+        };
+
+        let indices: Vec<_> = (0..cardinality).collect();
+        let mut name = bitvec![1];
+        for level in 0..=depth {
+            write_node(&name, if level == 0 { &indices } else { &indices[..1] }, level < depth);
+            name.push(false);
+        }
+        for _ in 0..depth {
+            name.pop();
+            name.pop();
+            name.push(true);
+            write_node(&name, &indices[..1], false);
+            name.pop();
+            name.push(false);
+        }
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        // Deep trees are read, attached, written, copied and dropped without recursion.
+        let bytes = chain_tree(2_000, 1_999);
+        let handle = TreeHandle::<f64, f64>::from_bytes(&bytes).unwrap();
+        assert_eq!(handle.num_clusters(), 2 * 1_999 + 1);
+        let data: Vec<_> = (0..2_000).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let root = handle.attach(Arc::clone(&dataset)).unwrap();
+        assert_eq!(tree_to_bytes(&root, None, None), bytes);
+        let copy = crate::io::attach(&root, Arc::clone(&dataset));
+        assert_eq!(copy.flatten_tree().len(), 2 * 1_999);
+        drop(copy);
+        let moved = crate::io::attach_owned(root, dataset);
+        assert_eq!(tree_to_bytes(&moved, None, None), bytes);
+        drop(moved);
+
+        // The root must be named 1.
+        let mut writer = ByteWriter::new();
+        writer.write_bitvec(&bitvec![1]);
+        let root_name = writer.into_bytes();
+        let mut writer = ByteWriter::new();
+        writer.write_bitvec(&bitvec![0]);
+        let bad_name = writer.into_bytes();
+        let mut bytes = chain_tree(1, 0);
+        let at = bytes
+            .windows(root_name.len())
+            .rposition(|window| window == root_name)
+            .unwrap();
+        bytes[at..at + root_name.len()].copy_from_slice(&bad_name);
+        let error = TreeHandle::<f64, f64>::from_bytes(&bytes).unwrap_err();
+        assert!(error.contains("must be the root"));
+
+        // No valid tree is deeper than it has instances.
+        let error = TreeHandle::<f64, f64>::from_bytes(&chain_tree(10, 100)).unwrap_err();
+        assert!(error.contains("deeper than the 10 instances"));
+    }
+}
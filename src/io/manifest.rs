@@ -0,0 +1,288 @@
+//! A manifest of the artifacts of a multi-step run, so that an interrupted run can resume where it stopped.
+//!
+//! Each step of a run, e.g. building a tree, compressing a dataset or timing a search, writes one artifact file into
+//! the directory of the manifest. The manifest records, for each artifact, a hash of the inputs of the step and a hash
+//! of the contents of the file. A step is skipped on a later run only if the recorded inputs match its current inputs
+//! and the file is still there with the recorded contents, so a partially written file, a file that was changed by
+//! hand, or a change of inputs, all cause the step to run again.
+//!
+//! The inputs of a step may include the content hashes of the artifacts it reads, from `content_hash`, so that
+//! rebuilding an artifact invalidates the artifacts that were derived from it.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json::json;
+use serde_json::Value;
+
+/// The name of the manifest file in its directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The state of one completed artifact in a `Manifest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The name of the file of the artifact, relative to the directory of the manifest.
+    pub file: String,
+
+    /// The hash of the inputs of the step that wrote the artifact.
+    pub inputs: u64,
+
+    /// The hash of the contents of the file when the step completed.
+    pub content: u64,
+}
+
+/// Records which artifacts in a directory are complete, and for which inputs.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    directory: PathBuf,
+    entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Opens the manifest in the directory, creating the directory if needed.
+    /// The manifest is empty if the directory has no manifest file.
+    ///
+    /// Returns an Err if the directory cannot be created or the manifest file is malformed.
+    pub fn open(directory: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(directory).map_err(|error| format!("Could not create {:?}: {}", directory, error))?;
+
+        let path = directory.join(MANIFEST_FILE);
+        let mut entries = BTreeMap::new();
+        if path.exists() {
+            let text =
+                std::fs::read_to_string(&path).map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+            let value: Value =
+                serde_json::from_str(&text).map_err(|error| format!("Malformed manifest {:?}: {}", path, error))?;
+            let artifacts = value
+                .get("artifacts")
+                .and_then(Value::as_object)
+                .ok_or_else(|| format!("The manifest {:?} has no artifacts.", path))?;
+            for (name, entry) in artifacts {
+                entries.insert(
+                    name.clone(),
+                    read_entry(entry).map_err(|error| format!("{} in {:?}", error, path))?,
+                );
+            }
+        }
+
+        Ok(Manifest {
+            directory: directory.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Returns the directory of the manifest and its artifacts.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the entries of the completed artifacts, by name.
+    pub fn entries(&self) -> &BTreeMap<String, ManifestEntry> {
+        &self.entries
+    }
+
+    /// Returns the path of the given file in the directory of the manifest.
+    pub fn path_of(&self, file: &str) -> PathBuf {
+        self.directory.join(file)
+    }
+
+    /// Returns whether the artifact was completed for the given inputs and its file still has the recorded contents.
+    pub fn is_complete(&self, name: &str, inputs: u64) -> bool {
+        match self.entries.get(name) {
+            Some(entry) if entry.inputs == inputs => hash_file(&self.path_of(&entry.file)) == Ok(entry.content),
+            _ => false,
+        }
+    }
+
+    /// Returns the recorded hash of the contents of the artifact, if it is in the manifest.
+    pub fn content_hash(&self, name: &str) -> Option<u64> {
+        self.entries.get(name).map(|entry| entry.content)
+    }
+
+    /// Records that the step for the artifact wrote the file for the given inputs, and saves the manifest.
+    ///
+    /// Returns an Err if the file cannot be read or the manifest cannot be saved.
+    pub fn record(&mut self, name: &str, file: &str, inputs: u64) -> Result<(), String> {
+        let content = hash_file(&self.path_of(file))?;
+        let entry = ManifestEntry {
+            file: file.to_string(),
+            inputs,
+            content,
+        };
+        self.entries.insert(name.to_string(), entry);
+        self.save()
+    }
+
+    /// Removes the artifact from the manifest, so that its step runs again, and saves the manifest.
+    /// The file of the artifact is left in place.
+    pub fn invalidate(&mut self, name: &str) -> Result<(), String> {
+        if self.entries.remove(name).is_some() {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs the step unless the artifact is already complete for the given inputs.
+    /// The step is given the path of the file it must write, and the artifact is recorded once it returns Ok.
+    ///
+    /// Returns whether the step was run, or the Err returned by the step, in which case nothing is recorded.
+    pub fn run_step<F>(&mut self, name: &str, file: &str, inputs: u64, step: F) -> Result<bool, String>
+    where
+        F: FnOnce(&Path) -> Result<(), String>,
+    {
+        if self.is_complete(name, inputs) {
+            log::info!("Skipping {}: already complete.", name);
+            return Ok(false);
+        }
+
+        // Forget any earlier run first, so that a failed step is never taken as complete.
+        self.invalidate(name)?;
+        log::info!("Running {}.", name);
+        step(&self.path_of(file))?;
+        self.record(name, file, inputs)?;
+        Ok(true)
+    }
+
+    /// Writes the manifest to a temporary file and then renames it over the manifest file, so that an interruption
+    /// never leaves a partially written manifest.
+    fn save(&self) -> Result<(), String> {
+        let artifacts: serde_json::Map<_, _> = self
+            .entries
+            .iter()
+            .map(|(name, entry)| {
+                let entry = json!({
+                    "file": entry.file,
+                    "inputs": format!("{:016x}", entry.inputs),
+                    "content": format!("{:016x}", entry.content),
+                });
+                (name.clone(), entry)
+            })
+            .collect();
+        let text = serde_json::to_string_pretty(&json!({ "artifacts": artifacts })).unwrap();
+
+        let path = self.path_of(MANIFEST_FILE);
+        let temporary = self.path_of(&format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&temporary, text).map_err(|error| format!("Could not write {:?}: {}", temporary, error))?;
+        std::fs::rename(&temporary, &path).map_err(|error| format!("Could not write {:?}: {}", path, error))
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of the bytes, e.g. to hash the inputs of a step.
+///
+/// Unlike the hashers in the standard library, this hash is the same across platforms and releases, so it may be
+/// saved in a manifest and compared in later runs.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv::default();
+    hasher.write(bytes);
+    hasher.0
+}
+
+/// Returns the `content_hash` of the contents of the file, read in chunks.
+pub fn hash_file(path: &Path) -> Result<u64, String> {
+    let mut file = std::fs::File::open(path).map_err(|error| format!("Could not open {:?}: {}", path, error))?;
+    let mut hasher = Fnv::default();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    Ok(hasher.0)
+}
+
+/// The state of a 64-bit FNV-1a hash.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn read_entry(entry: &Value) -> Result<ManifestEntry, String> {
+    let field = |key: &str| {
+        entry
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("Missing '{}' of an artifact", key))
+    };
+    let hash = |key: &str| {
+        field(key).and_then(|hex| u64::from_str_radix(hex, 16).map_err(|_| format!("Bad '{}' of an artifact", key)))
+    };
+    Ok(ManifestEntry {
+        file: field("file")?.to_string(),
+        inputs: hash("inputs")?,
+        content: hash("content")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::content_hash;
+    use super::Manifest;
+
+    #[test]
+    fn test_resume() {
+        let directory = std::env::temp_dir().join(format!("clam-manifest-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let inputs = content_hash(b"dataset=arrhythmia depth=10");
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+
+        let mut manifest = Manifest::open(&directory).unwrap();
+        let write =
+            |text: &'static str| move |path: &std::path::Path| std::fs::write(path, text).map_err(|e| e.to_string());
+        assert!(manifest.run_step("tree", "tree.bin", inputs, write("tree")).unwrap());
+        assert!(!manifest.run_step("tree", "tree.bin", inputs, write("tree")).unwrap());
+
+        // A failed step is not recorded, and runs again.
+        assert!(manifest
+            .run_step("codec", "codec.bin", inputs, |_| Err("interrupted".to_string()))
+            .is_err());
+        assert!(!manifest.is_complete("codec", inputs));
+
+        // The manifest persists across runs.
+        let mut manifest = Manifest::open(&directory).unwrap();
+        assert!(manifest.is_complete("tree", inputs));
+        assert_eq!(manifest.entries().len(), 1);
+
+        // Changed inputs or changed contents invalidate the artifact.
+        assert!(!manifest.is_complete("tree", inputs + 1));
+        std::fs::write(manifest.path_of("tree.bin"), "truncated").unwrap();
+        assert!(!manifest.is_complete("tree", inputs));
+        assert!(manifest.run_step("tree", "tree.bin", inputs, write("tree")).unwrap());
+
+        // Downstream steps depend on the contents of the upstream artifacts.
+        let codec_inputs = content_hash(&manifest.content_hash("tree").unwrap().to_be_bytes());
+        assert!(manifest
+            .run_step("codec", "codec.bin", codec_inputs, write("codec"))
+            .unwrap());
+        assert!(manifest
+            .run_step("tree", "tree.bin", inputs + 1, write("new tree"))
+            .unwrap());
+        let codec_inputs = content_hash(&manifest.content_hash("tree").unwrap().to_be_bytes());
+        assert!(!manifest.is_complete("codec", codec_inputs));
+
+        manifest.invalidate("tree").unwrap();
+        assert!(!Manifest::open(&directory).unwrap().is_complete("tree", inputs + 1));
+
+        std::fs::write(manifest.path_of("manifest.json"), "{").unwrap();
+        assert!(Manifest::open(&directory).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
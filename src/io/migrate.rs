@@ -0,0 +1,196 @@
+//! Upgrading artifacts written by older versions of the crate to the current format.
+
+use std::path::Path;
+
+use super::ArtifactKind;
+use super::ByteReader;
+use super::ByteWriter;
+use super::Header;
+use super::FORMAT_VERSION;
+
+/// A step that rewrites the payload (everything after the header) of an artifact from one format version to the next.
+type Migration = fn(&Header, &[u8]) -> Result<Vec<u8>, String>;
+
+/// `MIGRATIONS[i]` upgrades a payload from version `i + 1` to version `i + 2`.
+///
+/// When the format changes, bump `FORMAT_VERSION` and append a step here.
+/// If an old artifact lacks information that cannot be recomputed without the dataset, the step should return an Err.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+/// Version 2 added the `peak_memory_bytes` of the `BuildReport` after its `tree_size_bytes`.
+///
+/// Version 1 trees did not measure the peak, so it is taken to be the size of the tree, which is a lower bound.
+fn v1_to_v2(header: &Header, payload: &[u8]) -> Result<Vec<u8>, String> {
+    if header.kind != ArtifactKind::Tree {
+        return Ok(payload.to_vec());
+    }
+
+    let mut reader = ByteReader::new(payload);
+    let mut writer = ByteWriter::new();
+    match reader.read_u8()? {
+        0 => writer.write_u8(0),
+        1 => {
+            writer.write_u8(1);
+            writer.write_str(&reader.read_str()?);
+            writer.write_str(&reader.read_str()?);
+            let num_criteria = reader.read_usize()?;
+            if num_criteria.saturating_mul(8) > reader.remaining() {
+                return Err(format!("Cannot read {} criteria.", num_criteria));
+            }
+            writer.write_usize(num_criteria);
+            for _ in 0..num_criteria {
+                writer.write_str(&reader.read_str()?);
+            }
+            match reader.read_u8()? {
+                0 => writer.write_u8(0),
+                1 => {
+                    writer.write_u8(1);
+                    writer.write_u64(reader.read_u64()?);
+                }
+                flag => return Err(format!("Invalid seed flag {}.", flag)),
+            }
+            writer.write_f64(reader.read_f64()?);
+            writer.write_u64(reader.read_u64()?);
+            let tree_size_bytes = reader.read_u64()?;
+            writer.write_u64(tree_size_bytes);
+            writer.write_u64(tree_size_bytes);
+        }
+        flag => return Err(format!("Invalid build-report flag {}.", flag)),
+    }
+    writer.write_raw(reader.rest());
+    Ok(writer.into_bytes())
+}
+
+/// Returns the number of bytes taken by a `Number` of the named type.
+pub(super) fn type_width(type_name: &str) -> Result<usize, String> {
+    match type_name {
+        "u8" | "i8" => Ok(1),
+        "u16" | "i16" => Ok(2),
+        "f32" | "u32" | "i32" => Ok(4),
+        "f64" | "u64" | "i64" => Ok(8),
+        _ => Err(format!("Unknown number type {}.", type_name)),
+    }
+}
+
+/// Upgrades the artifact in the given bytes to the current format version.
+///
+/// Artifacts that are already in the current format are returned unchanged.
+/// Returns an Err for malformed headers, for artifacts written by a newer version of the crate,
+/// and for artifacts that cannot be upgraded without the original dataset.
+pub fn migrate_bytes(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = ByteReader::new(bytes);
+    let mut header = Header::read(&mut reader)?;
+
+    if header.version == 0 || header.version > FORMAT_VERSION {
+        return Err(format!(
+            "Cannot migrate from format version {}. This crate reads versions 1 through {}.",
+            header.version, FORMAT_VERSION
+        ));
+    }
+
+    let mut payload = reader.rest().to_vec();
+    while header.version < FORMAT_VERSION {
+        let step = MIGRATIONS[(header.version - 1) as usize];
+        payload = step(&header, &payload)
+            .map_err(|error| format!("Failed to migrate from format version {}. {}", header.version, error))?;
+        header.version += 1;
+    }
+
+    let mut writer = ByteWriter::new();
+    header.write(&mut writer);
+    let mut bytes = writer.into_bytes();
+    bytes.extend(payload);
+    Ok(bytes)
+}
+
+/// Reads the artifact at `old_path`, upgrades it to the current format version and writes it to `new_path`.
+///
+/// `old_path` and `new_path` may be the same. Returns the header of the upgraded artifact.
+pub fn migrate(old_path: &Path, new_path: &Path) -> Result<Header, String> {
+    let bytes = migrate_bytes(&super::read_file(old_path)?)?;
+    let header = Header::read(&mut ByteReader::new(&bytes))?;
+    super::write_file(new_path, &bytes)?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::codec::Codec;
+    use crate::dataset::RowMajor;
+    use crate::io;
+    use crate::io::ArtifactKind;
+    use crate::io::ByteWriter;
+    use crate::io::Header;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::migrate_bytes;
+
+    #[test]
+    fn test_migrate() {
+        let data: Vec<Vec<u8>> = (0..30).map(|i: usize| vec![(i % 4) as u8, (i % 7) as u8, 1]).collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&row_major).as_arc_dataset(), None, None);
+        let codec = Codec::from_cakes(&row_major.as_arc_compressible_dataset(), &cakes).unwrap();
+
+        // Artifacts in the current format are unchanged.
+        let bytes = io::tree_to_bytes(&cakes.root, cakes.report.as_ref(), None);
+        assert_eq!(migrate_bytes(&bytes).unwrap(), bytes);
+        let codec_bytes = io::codec_to_bytes(&codec);
+        assert_eq!(migrate_bytes(&codec_bytes).unwrap(), codec_bytes);
+
+        // Artifacts from the future and from before the first version cannot be migrated.
+        for version in [0, io::FORMAT_VERSION + 1] {
+            let mut bytes = bytes.clone();
+            bytes[4..6].copy_from_slice(&version.to_be_bytes());
+            assert!(migrate_bytes(&bytes).is_err());
+        }
+
+        assert!(migrate_bytes(b"not a tree").is_err());
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let data: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32, (i % 7) as f32]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build_seeded(
+            Arc::clone(&dataset),
+            Some(4),
+            None,
+            Default::default(),
+            Default::default(),
+            7,
+        );
+        let mut report = cakes.report.clone().unwrap();
+        report.peak_memory_bytes = report.tree_size_bytes;
+        let current = io::tree_to_bytes(&cakes.root, Some(&report), None);
+
+        // A version 1 tree is the same but for the version and the peak memory missing from its report.
+        let length = |write: &dyn Fn(&mut ByteWriter)| {
+            let mut writer = ByteWriter::new();
+            write(&mut writer);
+            writer.into_bytes().len()
+        };
+        let header_length = length(&|writer| Header::new::<f32, f32>(ArtifactKind::Tree).write(writer));
+        let peak_at = header_length + 1 + length(&|writer| report.write(writer)) - 8;
+        let mut old = current.clone();
+        old.drain(peak_at..peak_at + 8);
+        old[4..6].copy_from_slice(&1_u16.to_be_bytes());
+        assert!(io::tree_from_bytes(&old, Arc::clone(&dataset)).is_err());
+
+        let migrated = migrate_bytes(&old).unwrap();
+        assert_eq!(migrated, current);
+        let (root, migrated_report) = io::tree_from_bytes(&migrated, dataset).unwrap();
+        assert_eq!(root.num_descendants(), cakes.root.num_descendants());
+        assert_eq!(migrated_report, Some(report));
+
+        // Trees without a report need no change but their version.
+        let mut old = io::tree_to_bytes(&cakes.root, None, None);
+        old[4..6].copy_from_slice(&1_u16.to_be_bytes());
+        assert_eq!(migrate_bytes(&old).unwrap(), io::tree_to_bytes(&cakes.root, None, None));
+    }
+}
@@ -0,0 +1,131 @@
+//! Read-only memory maps of files, so that large artifacts can be searched without first reading them into memory.
+//!
+//! On platforms without `mmap` the file is read into a buffer instead.
+
+use std::path::Path;
+
+/// The bytes of a file, mapped read-only into memory.
+///
+/// The file must not be modified or truncated while it is mapped.
+pub struct MappedFile {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// The mapping is private and read-only, so it may be shared across threads.
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+#[cfg(unix)]
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps the whole file at the given path.
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> Result<Self, String> {
+        use std::os::unix::io::AsRawFd;
+
+        let file =
+            std::fs::File::open(path).map_err(|error| format!("Error: Failed to open {}. {}", path.display(), error))?;
+        let len = file
+            .metadata()
+            .map_err(|error| format!("Error: Failed to read the metadata of {}. {}", path.display(), error))?
+            .len() as usize;
+
+        // Empty mappings are not allowed.
+        if len == 0 {
+            return Ok(MappedFile {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            Err(format!(
+                "Error: Failed to map {}. {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            ))
+        } else {
+            Ok(MappedFile { ptr, len })
+        }
+    }
+
+    /// Reads the whole file at the given path.
+    #[cfg(not(unix))]
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Ok(MappedFile {
+            bytes: super::read_file(path)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    #[cfg(unix)]
+    fn as_ref(&self) -> &[u8] {
+        if self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MappedFile;
+
+    #[test]
+    fn test_mapped_file() {
+        let path = std::env::temp_dir().join(format!("clam-test-mapped-file-{}", std::process::id()));
+
+        let bytes: Vec<u8> = (0..10_000).map(|i: usize| (i % 251) as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        let mapped = MappedFile::open(&path).unwrap();
+        assert_eq!(mapped.as_ref(), bytes.as_slice());
+        drop(mapped);
+
+        std::fs::write(&path, []).unwrap();
+        assert!(MappedFile::open(&path).unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(MappedFile::open(&path).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Reading and writing `CLAM` artifacts, i.e. search trees, compressed clusters and traces of searches, to and from
+//! bytes.
+//!
+//! All artifacts share a small header:
+//!
+//! * the magic bytes `CLAM`,
+//! * the format version as a `u16`,
+//! * a tag for the kind of artifact,
+//! * the names of the instance type `T` and the distance type `U`.
+//!
+//! Trees also store an optional `BuildReport` right after the header, and the policies with which their centers and
+//! radii were chosen.
+//! Trees and compressed artifacts may also store a `DatasetCard` describing their dataset, which `read_card` reads
+//! without loading the rest of the artifact.
+//!
+//! The payload is written in big-endian byte-order with every `usize` widened to a `u64`,
+//! so a tree built on an x86 server can be loaded, unchanged, on an ARM device.
+//! Datasets are never written into these artifacts; a dataset must be supplied when loading.
+//! Compressed artifacts may also be memory-mapped, with `map_codec`, and searched without being read into memory.
+//! A tree may be parsed once, as a `TreeHandle`, and attached to many datasets, e.g. to `MappedDatasets` over a shared
+//! `.npy` file.
+//! A `Manifest` records which artifacts of a multi-step run are complete, so that an interrupted run can resume.
+
+mod bytes;
+mod card;
+mod compressed;
+mod handle;
+mod manifest;
+mod migrate;
+mod mmap;
+mod npy;
+mod report;
+mod trace;
+mod tree;
+
+use std::path::Path;
+
+pub use card::read_card;
+pub use card::DatasetCard;
+pub use compressed::codec_from_bytes;
+pub use compressed::codec_from_shared;
+pub use compressed::codec_to_bytes;
+pub use compressed::load_codec;
+pub use compressed::map_codec;
+pub use compressed::save_codec;
+pub use handle::TreeHandle;
+pub use manifest::content_hash;
+pub use manifest::hash_file;
+pub use manifest::Manifest;
+pub use manifest::ManifestEntry;
+pub use migrate::migrate;
+pub use migrate::migrate_bytes;
+pub use mmap::MappedFile;
+pub use npy::MappedDataset;
+pub use report::read_report;
+pub use report::BuildReport;
+pub use trace::load_trace;
+pub use trace::save_trace;
+pub use trace::trace_from_bytes;
+pub use trace::trace_to_bytes;
+pub use tree::attach;
+pub use tree::load_tree;
+pub use tree::save_tree;
+pub use tree::tree_from_bytes;
+pub use tree::tree_to_bytes;
+pub use tree::TreeWithReport;
+
+use crate::Number;
+use bytes::ByteReader;
+use bytes::ByteWriter;
+use bytes::MAGIC;
+pub(crate) use npy::decode_row;
+pub(crate) use npy::npy_layout;
+pub(crate) use npy::read_npy_header;
+pub(crate) use npy::NpyHeader;
+pub(crate) use tree::attach_owned;
+
+/// The version of the binary format written by this version of the crate.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// The kinds of artifacts that can be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    /// A tree of `Clusters`.
+    Tree,
+    /// A tree of `PackableClusters` along with the reference center.
+    Compressed,
+    /// The trace of a search. See `trace::SearchTrace`.
+    Trace,
+}
+
+impl ArtifactKind {
+    fn tag(&self) -> u8 {
+        match self {
+            ArtifactKind::Tree => 0,
+            ArtifactKind::Compressed => 1,
+            ArtifactKind::Trace => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(ArtifactKind::Tree),
+            1 => Ok(ArtifactKind::Compressed),
+            2 => Ok(ArtifactKind::Trace),
+            _ => Err(format!("Unknown artifact tag {}.", tag)),
+        }
+    }
+}
+
+/// The header found at the start of every artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub kind: ArtifactKind,
+    pub instance_type: String,
+    pub distance_type: String,
+}
+
+impl Header {
+    fn new<T: Number, U: Number>(kind: ArtifactKind) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            kind,
+            instance_type: T::type_name().to_string(),
+            distance_type: U::type_name().to_string(),
+        }
+    }
+
+    fn write(&self, writer: &mut ByteWriter) {
+        MAGIC.iter().for_each(|&b| writer.write_u8(b));
+        writer.write_u16(self.version);
+        writer.write_u8(self.kind.tag());
+        writer.write_str(&self.instance_type);
+        writer.write_str(&self.distance_type);
+    }
+
+    fn read(reader: &mut ByteReader) -> Result<Self, String> {
+        let magic = [
+            reader.read_u8()?,
+            reader.read_u8()?,
+            reader.read_u8()?,
+            reader.read_u8()?,
+        ];
+        if &magic != MAGIC {
+            return Err("Not a CLAM artifact: bad magic bytes.".to_string());
+        }
+        Ok(Header {
+            version: reader.read_u16()?,
+            kind: ArtifactKind::from_tag(reader.read_u8()?)?,
+            instance_type: reader.read_str()?,
+            distance_type: reader.read_str()?,
+        })
+    }
+
+    /// Checks that the artifact was written in the current format.
+    fn check_version(&self) -> Result<(), String> {
+        if self.version == FORMAT_VERSION {
+            Ok(())
+        } else {
+            Err(format!(
+                "Artifact has format version {} but this crate reads version {}. Try `clam::io::migrate`.",
+                self.version, FORMAT_VERSION
+            ))
+        }
+    }
+
+    /// Checks that the header describes an artifact of the given kind and types, written in the current format.
+    fn check<T: Number, U: Number>(&self, kind: ArtifactKind) -> Result<(), String> {
+        self.check_version()?;
+        if self.kind != kind {
+            return Err(format!(
+                "Expected a {:?} artifact but found a {:?} artifact.",
+                kind, self.kind
+            ));
+        }
+        if self.instance_type != T::type_name() || self.distance_type != U::type_name() {
+            return Err(format!(
+                "Artifact holds types ({}, {}) but ({}, {}) were requested.",
+                self.instance_type,
+                self.distance_type,
+                T::type_name(),
+                U::type_name()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads only the header of the artifact at the given path.
+pub fn read_header(path: &Path) -> Result<Header, String> {
+    let bytes = read_file(path)?;
+    Header::read(&mut ByteReader::new(&bytes))
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    std::fs::write(path, bytes).map_err(|error| format!("Error: Failed to write {}. {}", path.display(), error))
+}
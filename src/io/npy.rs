@@ -0,0 +1,374 @@
+//! Datasets whose instances are read directly from memory-mapped `.npy` files.
+//!
+//! Only 2-dimensional arrays in row-major order are supported. Every replica that maps the same file shares its pages,
+//! so one copy of the dataset in memory may serve many searches. The headers of `.npy` files are parsed here so that
+//! other datasets may also read their arrays in place.
+
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use super::MappedFile;
+use crate::prelude::*;
+
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// A `Dataset` over the rows of a 2-dimensional array in a memory-mapped `.npy` file, or in a headerless file of
+/// fixed-width rows.
+///
+/// Only the pages holding the instances needed by a search are read into memory, and the operating system may evict
+/// them under memory pressure, so trees can be built and searched over datasets that do not fit in RAM.
+/// Instances are decoded from the mapping whenever they are needed, and distances are never cached.
+/// The file must not be modified while it is mapped.
+pub struct MappedDataset<T: Number, U: Number> {
+    file: Arc<MappedFile>,
+    metric: Arc<dyn Metric<T, U>>,
+    /// The position of the first byte of the array in the file.
+    offset: usize,
+    cardinality: usize,
+    dimensionality: usize,
+    big_endian: bool,
+    instance_type: PhantomData<T>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for MappedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("MappedDataset")
+            .field("data-cardinality", &self.cardinality)
+            .field("data-dimensionality", &self.dimensionality)
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> MappedDataset<T, U> {
+    /// Maps the `.npy` file at the given path.
+    ///
+    /// Returns an Err if the file does not hold a 2-dimensional, row-major array of `T`.
+    pub fn open(path: &Path, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        Self::from_file(Arc::new(MappedFile::open(path)?), metric)
+            .map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))
+    }
+
+    /// Uses an already mapped `.npy` file, e.g. to share one mapping among datasets with different metrics.
+    pub fn from_file(file: Arc<MappedFile>, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let header = read_npy_header((*file).as_ref())?;
+        let (cardinality, dimensionality, big_endian) = npy_layout::<T>(&header, file.len())?;
+
+        Ok(MappedDataset {
+            file,
+            metric,
+            offset: header.offset,
+            cardinality,
+            dimensionality,
+            big_endian,
+            instance_type: PhantomData,
+        })
+    }
+
+    /// Maps a headerless file of rows of `dimensionality` little-endian numbers, e.g. as written by numpy's `tofile`.
+    pub fn open_raw(path: &Path, dimensionality: usize, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        Self::from_raw_file(Arc::new(MappedFile::open(path)?), dimensionality, metric)
+            .map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))
+    }
+
+    /// Uses an already mapped, headerless file of rows of `dimensionality` little-endian numbers.
+    pub fn from_raw_file(
+        file: Arc<MappedFile>,
+        dimensionality: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let row_length = dimensionality * T::num_bytes() as usize;
+        if row_length == 0 || !file.len().is_multiple_of(row_length) {
+            return Err(format!(
+                "{} bytes do not hold a whole number of rows of {} bytes.",
+                file.len(),
+                row_length
+            ));
+        }
+
+        Ok(MappedDataset {
+            cardinality: file.len() / row_length,
+            file,
+            metric,
+            offset: 0,
+            dimensionality,
+            big_endian: false,
+            instance_type: PhantomData,
+        })
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for MappedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality).collect()
+    }
+
+    /// Decodes the row at the provided index from the mapping.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let length = self.dimensionality * T::num_bytes() as usize;
+        let start = self.offset + i * length;
+        decode_row(&(*self.file).as_ref()[start..start + length], self.big_endian)
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        Array1::from_vec(
+            right
+                .par_iter()
+                .map(|&r| {
+                    if r == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &self.instance(r))
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+/// The layout of the array in a `.npy` file.
+pub(crate) struct NpyHeader {
+    /// The position of the first byte of the array in the file.
+    pub offset: usize,
+    /// The dtype of the array, e.g. `<f4`.
+    pub descr: String,
+    pub fortran_order: bool,
+    pub shape: Vec<usize>,
+}
+
+/// Parses the header at the start of the bytes of a `.npy` file.
+pub(crate) fn read_npy_header(bytes: &[u8]) -> Result<NpyHeader, String> {
+    if bytes.len() < 10 || &bytes[..6] != NPY_MAGIC {
+        return Err("Not a .npy file: bad magic bytes.".to_string());
+    }
+    let (header_start, header_length) = match bytes[6] {
+        1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        2 | 3 if bytes.len() >= 12 => (
+            12,
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+        ),
+        version => return Err(format!("Unsupported .npy version {}.", version)),
+    };
+    let offset = header_start + header_length;
+    let header = bytes
+        .get(header_start..offset)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| "The .npy header is truncated or not valid text.".to_string())?;
+
+    let value = |key: &str| {
+        header
+            .find(&format!("'{}':", key))
+            .map(|i| header[i + key.len() + 3..].trim_start())
+            .ok_or_else(|| format!("The .npy header has no '{}'.", key))
+    };
+
+    let descr = value("descr")?;
+    let descr = descr
+        .strip_prefix('\'')
+        .and_then(|descr| descr.split('\'').next())
+        .ok_or_else(|| format!("Bad 'descr' in the .npy header: {}", header))?
+        .to_string();
+
+    let fortran_order = value("fortran_order")?.starts_with("True");
+
+    let shape = value("shape")?;
+    let shape = shape
+        .strip_prefix('(')
+        .and_then(|shape| shape.split(')').next())
+        .ok_or_else(|| format!("Bad 'shape' in the .npy header: {}", header))?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse()
+                .map_err(|_| format!("Bad 'shape' in the .npy header: {}", header))
+        })
+        .collect::<Result<Vec<usize>, _>>()?;
+
+    Ok(NpyHeader {
+        offset,
+        descr,
+        fortran_order,
+        shape,
+    })
+}
+
+/// Checks that the `.npy` header describes a 2-dimensional, row-major array of `T` that fills the rest of a file of
+/// `file_length` bytes, and returns its cardinality, its dimensionality and whether it is big-endian.
+pub(crate) fn npy_layout<T: Number>(header: &NpyHeader, file_length: usize) -> Result<(usize, usize, bool), String> {
+    let byte_order = header.descr.chars().next().unwrap_or(' ');
+    let expected = format!("{}{}", &T::type_name()[..1], T::num_bytes());
+    if !['<', '>', '|', '='].contains(&byte_order) || header.descr.get(1..) != Some(expected.as_str()) {
+        return Err(format!(
+            "The array holds '{}' but {} was requested.",
+            header.descr,
+            T::type_name()
+        ));
+    }
+    let big_endian = byte_order == '>' || (byte_order == '=' && cfg!(target_endian = "big"));
+    if header.fortran_order {
+        return Err("The array must be in row-major order.".to_string());
+    }
+    let (cardinality, dimensionality) = match header.shape[..] {
+        [cardinality, dimensionality] => (cardinality, dimensionality),
+        _ => {
+            return Err(format!(
+                "The array must be 2-dimensional but has shape {:?}.",
+                header.shape
+            ))
+        }
+    };
+
+    let length = cardinality
+        .checked_mul(dimensionality)
+        .and_then(|n| n.checked_mul(T::num_bytes() as usize))
+        .ok_or_else(|| format!("The shape {:?} is too large.", header.shape))?;
+    if file_length.saturating_sub(header.offset) != length {
+        return Err(format!(
+            "The array should take {} bytes but {} remain after the header.",
+            length,
+            file_length.saturating_sub(header.offset)
+        ));
+    }
+
+    Ok((cardinality, dimensionality, big_endian))
+}
+
+/// Decodes a row of numbers from their bytes.
+pub(crate) fn decode_row<T: Number>(bytes: &[u8], big_endian: bool) -> Vec<T> {
+    let width = T::num_bytes() as usize;
+    if big_endian {
+        bytes.chunks(width).map(T::from_bytes).collect()
+    } else {
+        bytes
+            .chunks(width)
+            .map(|value| {
+                let value: Vec<u8> = value.iter().rev().copied().collect();
+                T::from_bytes(&value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+    use ndarray::prelude::*;
+    use ndarray_npy::write_npy;
+
+    use crate::dataset::RowMajor;
+    use crate::io::TreeHandle;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::npy_layout;
+    use super::MappedDataset;
+    use super::NpyHeader;
+
+    #[test]
+    fn test_mapped_dataset() {
+        let data: Vec<Vec<f32>> = (0..120)
+            .map(|i| (0..5).map(|j| ((i * (j + 3)) % 17) as f32 - 0.5 * j as f32).collect())
+            .collect();
+        let array = Array2::from_shape_vec((120, 5), data.iter().flatten().cloned().collect()).unwrap();
+        let path = std::env::temp_dir().join(format!("clam-test-mapped-dataset-{}.npy", std::process::id()));
+        write_npy(&path, &array).unwrap();
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let mapped = Arc::new(MappedDataset::<f32, f32>::open(&path, Arc::clone(&metric)).unwrap());
+        assert_eq!(mapped.cardinality(), 120);
+        assert_eq!(mapped.dimensionality(), 5);
+        for (i, row) in data.iter().enumerate() {
+            assert_eq!(&mapped.instance(i), row);
+        }
+
+        // A tree built in memory may be attached to the mapped dataset.
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        let cakes = Cakes::build(dataset, Some(5), None);
+        let handle = TreeHandle::from_bytes(&crate::io::tree_to_bytes(&cakes.root, None, None)).unwrap();
+        let replica = Cakes::attach(&handle, mapped.as_arc_dataset()).unwrap();
+        for query in data.iter().step_by(11) {
+            let mut expected = cakes.rnn_indices(query, Some(3.));
+            let mut actual = replica.rnn_indices(query, Some(3.));
+            expected.sort_unstable();
+            actual.sort_unstable();
+            assert_eq!(actual, expected);
+        }
+
+        let metric = metric_from_name("euclidean").unwrap();
+        assert!(MappedDataset::<f64, f64>::open(&path, metric).is_err());
+
+        // The same rows in a headerless file.
+        let raw_path = path.with_extension("f32");
+        let bytes: Vec<u8> = data.iter().flatten().flat_map(|value| value.to_le_bytes()).collect();
+        std::fs::write(&raw_path, &bytes).unwrap();
+        let metric = metric_from_name("euclidean").unwrap();
+        let raw = MappedDataset::<f32, f32>::open_raw(&raw_path, 5, Arc::clone(&metric)).unwrap();
+        assert_eq!(raw.cardinality(), 120);
+        assert_eq!(raw.instance(7), data[7]);
+        assert!(approx_eq!(f32, raw.distance(3, 9), replica.dataset.distance(3, 9)));
+        assert!(MappedDataset::<f32, f32>::open_raw(&raw_path, 7, metric).is_err());
+
+        std::fs::remove_file(&raw_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_npy_layout() {
+        let header = |descr: &str| NpyHeader {
+            offset: 128,
+            descr: descr.to_string(),
+            fortran_order: false,
+            shape: vec![2, 3],
+        };
+        assert_eq!(npy_layout::<f32>(&header("<f4"), 152), Ok((2, 3, false)));
+        assert_eq!(npy_layout::<f32>(&header(">f4"), 152), Ok((2, 3, true)));
+
+        // The dtype comes from the file, and may be empty or start with any character.
+        for descr in ["", "<", "é4", "<f8"] {
+            assert!(npy_layout::<f32>(&header(descr), 152).is_err());
+        }
+    }
+}
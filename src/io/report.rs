@@ -0,0 +1,117 @@
+//! A record of how a tree was built, stored alongside the tree for reproducibility audits.
+
+use std::path::Path;
+
+use super::ArtifactKind;
+use super::ByteReader;
+use super::ByteWriter;
+use super::Header;
+
+/// Telemetry collected while building a tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    /// The version of the crate that built the tree.
+    pub crate_version: String,
+
+    /// The name of the metric used to build the tree.
+    pub metric_name: String,
+
+    /// Human-readable descriptions of the partition criteria, e.g. "max_depth=50".
+    pub criteria: Vec<String>,
+
+    /// The seed used for sampling during the build, if the build was seeded.
+    pub seed: Option<u64>,
+
+    /// The wall-clock time, in seconds, taken to build the tree.
+    pub wall_time: f64,
+
+    /// The number of distances requested from the dataset during the build.
+    /// Distances served from a cache are still counted.
+    pub distance_calls: u64,
+
+    /// An estimate of the bytes held by the clusters of the finished tree.
+    pub tree_size_bytes: u64,
+
+    /// An estimate of the most bytes held at once during the build, by the clusters built so far and by the scratch
+    /// of the partitions in progress. This is at least `tree_size_bytes`.
+    pub peak_memory_bytes: u64,
+}
+
+impl BuildReport {
+    pub(crate) fn write(&self, writer: &mut ByteWriter) {
+        writer.write_str(&self.crate_version);
+        writer.write_str(&self.metric_name);
+        writer.write_usize(self.criteria.len());
+        self.criteria.iter().for_each(|criterion| writer.write_str(criterion));
+        match self.seed {
+            Some(seed) => {
+                writer.write_u8(1);
+                writer.write_u64(seed);
+            }
+            None => writer.write_u8(0),
+        }
+        writer.write_f64(self.wall_time);
+        writer.write_u64(self.distance_calls);
+        writer.write_u64(self.tree_size_bytes);
+        writer.write_u64(self.peak_memory_bytes);
+    }
+
+    pub(crate) fn read(reader: &mut ByteReader) -> Result<Self, String> {
+        let crate_version = reader.read_str()?;
+        let metric_name = reader.read_str()?;
+        let num_criteria = reader.read_usize()?;
+        if num_criteria.saturating_mul(8) > reader.remaining() {
+            return Err(format!("Cannot read {} criteria.", num_criteria));
+        }
+        let criteria = (0..num_criteria).map(|_| reader.read_str()).collect::<Result<_, _>>()?;
+        let seed = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_u64()?),
+            flag => return Err(format!("Invalid seed flag {}.", flag)),
+        };
+        Ok(BuildReport {
+            crate_version,
+            metric_name,
+            criteria,
+            seed,
+            wall_time: reader.read_f64()?,
+            distance_calls: reader.read_u64()?,
+            tree_size_bytes: reader.read_u64()?,
+            peak_memory_bytes: reader.read_u64()?,
+        })
+    }
+}
+
+/// Writes the optional report as a flag byte followed by the report.
+pub(crate) fn write_optional(writer: &mut ByteWriter, report: Option<&BuildReport>) {
+    match report {
+        Some(report) => {
+            writer.write_u8(1);
+            report.write(writer);
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+pub(crate) fn read_optional(reader: &mut ByteReader) -> Result<Option<BuildReport>, String> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(BuildReport::read(reader)?)),
+        flag => Err(format!("Invalid build-report flag {}.", flag)),
+    }
+}
+
+/// Reads the build report stored with the tree at the given path, without loading the tree or its dataset.
+pub fn read_report(path: &Path) -> Result<Option<BuildReport>, String> {
+    let bytes = super::read_file(path)?;
+    let mut reader = ByteReader::new(&bytes);
+    let header = Header::read(&mut reader)?;
+    if header.kind != ArtifactKind::Tree {
+        return Err(format!(
+            "Only trees store build reports but found a {:?} artifact.",
+            header.kind
+        ));
+    }
+    header.check_version()?;
+    read_optional(&mut reader)
+}
@@ -0,0 +1,119 @@
+//! Binary format for traces of searches.
+//!
+//! After the header comes the kind of search, as a tag followed by its radius or `k`, then the query, and then the
+//! events in the order of the search. Each event is a tag followed by the name, bound and decision of a cluster, or by
+//! the index of an instance and its distance from the query.
+
+use std::path::Path;
+
+use super::ArtifactKind;
+use super::ByteReader;
+use super::ByteWriter;
+use super::Header;
+use crate::prelude::*;
+use crate::search::trace::Decision;
+use crate::search::trace::SearchTrace;
+use crate::search::trace::TraceEvent;
+use crate::search::trace::TracedSearch;
+
+/// Each event takes at least a tag and an index.
+const MIN_EVENT_BYTES: usize = 9;
+
+/// Serializes the trace of a search.
+pub fn trace_to_bytes<T: Number, U: Number>(trace: &SearchTrace<T, U>) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    Header::new::<T, U>(ArtifactKind::Trace).write(&mut writer);
+    match trace.search {
+        TracedSearch::Rnn { radius } => {
+            writer.write_u8(0);
+            writer.write_number(radius);
+        }
+        TracedSearch::Knn { k } => {
+            writer.write_u8(1);
+            writer.write_usize(k);
+        }
+    }
+    writer.write_numbers(&trace.query);
+    writer.write_usize(trace.events.len());
+    for event in trace.events.iter() {
+        match event {
+            TraceEvent::Cluster { name, bound, decision } => {
+                writer.write_u8(0);
+                writer.write_bitvec(name);
+                writer.write_f64(*bound);
+                writer.write_u8(match decision {
+                    Decision::Pruned => 0,
+                    Decision::Entered => 1,
+                    Decision::Scanned => 2,
+                });
+            }
+            TraceEvent::Instance { index, distance } => {
+                writer.write_u8(1);
+                writer.write_usize(*index);
+                writer.write_number(*distance);
+            }
+        }
+    }
+    writer.into_bytes()
+}
+
+/// Deserializes the trace of a search.
+///
+/// Returns an Err if the bytes are malformed or were written for different types.
+pub fn trace_from_bytes<T: Number, U: Number>(bytes: &[u8]) -> Result<SearchTrace<T, U>, String> {
+    let mut reader = ByteReader::new(bytes);
+    Header::read(&mut reader)?.check::<T, U>(ArtifactKind::Trace)?;
+    let search = match reader.read_u8()? {
+        0 => TracedSearch::Rnn {
+            radius: reader.read_number()?,
+        },
+        1 => TracedSearch::Knn {
+            k: reader.read_usize()?,
+        },
+        tag => return Err(format!("Unknown search tag {}.", tag)),
+    };
+    let query = reader.read_numbers()?;
+
+    let num_events = reader.read_usize()?;
+    if num_events.saturating_mul(MIN_EVENT_BYTES) > reader.remaining() {
+        return Err(format!(
+            "Cannot read {} events from {} bytes.",
+            num_events,
+            reader.remaining()
+        ));
+    }
+    let mut events = Vec::with_capacity(num_events);
+    for _ in 0..num_events {
+        events.push(match reader.read_u8()? {
+            0 => TraceEvent::Cluster {
+                name: reader.read_bitvec()?,
+                bound: reader.read_f64()?,
+                decision: match reader.read_u8()? {
+                    0 => Decision::Pruned,
+                    1 => Decision::Entered,
+                    2 => Decision::Scanned,
+                    tag => return Err(format!("Unknown decision tag {}.", tag)),
+                },
+            },
+            1 => TraceEvent::Instance {
+                index: reader.read_usize()?,
+                distance: reader.read_number()?,
+            },
+            tag => return Err(format!("Unknown event tag {}.", tag)),
+        });
+    }
+    if !reader.is_empty() {
+        return Err(format!("Found {} trailing bytes after the trace.", reader.remaining()));
+    }
+    Ok(SearchTrace { search, query, events })
+}
+
+/// Writes the trace of a search to a file.
+pub fn save_trace<T: Number, U: Number>(trace: &SearchTrace<T, U>, path: &Path) -> Result<(), String> {
+    super::write_file(path, &trace_to_bytes(trace))
+}
+
+/// Reads the trace of a search from a file.
+pub fn load_trace<T: Number, U: Number>(path: &Path) -> Result<SearchTrace<T, U>, String> {
+    trace_from_bytes(&super::read_file(path)?)
+}
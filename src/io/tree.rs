@@ -0,0 +1,339 @@
+//! Binary format for trees of `Clusters`.
+//!
+//! After the header come the optional `BuildReport`, the optional `DatasetCard`, the `CenterPolicy` and `RadiusPolicy`
+//! of the tree and then the clusters in pre-order.
+//! Each cluster record holds its name, indices, argcenter, argradius, radius, lfd and ratios, followed by a byte noting
+//! whether it has children.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::sync::Weak;
+
+use rayon::prelude::*;
+
+use super::card;
+use super::report;
+use super::ArtifactKind;
+use super::BuildReport;
+use super::ByteReader;
+use super::ByteWriter;
+use super::DatasetCard;
+use super::Header;
+use super::TreeHandle;
+use crate::core::Children;
+use crate::memory::Subsystem;
+use crate::memory::Tracked;
+use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::utils::write_cache;
+use crate::CenterPolicy;
+use crate::RadiusPolicy;
+
+/// A tree of `Clusters` along with the report of its build, if any.
+pub type TreeWithReport<T, U> = (Arc<Cluster<T, U>>, Option<BuildReport>);
+
+/// Serializes the tree rooted at the given cluster, along with the optional build report and dataset card.
+pub fn tree_to_bytes<T: Number, U: Number>(
+    root: &Arc<Cluster<T, U>>,
+    report: Option<&BuildReport>,
+    card: Option<&DatasetCard>,
+) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    Header::new::<T, U>(ArtifactKind::Tree).write(&mut writer);
+    report::write_optional(&mut writer, report);
+    card::write_optional(&mut writer, card);
+    write_policies(&mut writer, root.center_policy, root.radius_policy);
+    write_cluster(&mut writer, root);
+    writer.into_bytes()
+}
+
+/// Deserializes a tree, and its build report, and attaches the tree to the given dataset.
+///
+/// Returns an Err if the bytes are malformed, were written for different types,
+/// or refer to instances that are not in the dataset.
+pub fn tree_from_bytes<T: Number, U: Number>(
+    bytes: &[u8],
+    dataset: Arc<dyn Dataset<T, U>>,
+) -> Result<TreeWithReport<T, U>, String> {
+    let handle = TreeHandle::from_bytes(bytes)?;
+    let root = handle.attach(dataset)?;
+    Ok((root, handle.report().cloned()))
+}
+
+/// Writes the tree rooted at the given cluster, and the optional build report and dataset card, to a file.
+pub fn save_tree<T: Number, U: Number>(
+    root: &Arc<Cluster<T, U>>,
+    report: Option<&BuildReport>,
+    card: Option<&DatasetCard>,
+    path: &Path,
+) -> Result<(), String> {
+    super::write_file(path, &tree_to_bytes(root, report, card))
+}
+
+/// Reads a tree, and its build report, from a file and attaches the tree to the given dataset.
+pub fn load_tree<T: Number, U: Number>(
+    path: &Path,
+    dataset: Arc<dyn Dataset<T, U>>,
+) -> Result<TreeWithReport<T, U>, String> {
+    tree_from_bytes(&super::read_file(path)?, dataset)
+}
+
+/// Returns a copy of the tree rooted at the given cluster in which every cluster refers to the given dataset.
+///
+/// The dataset must hold the same instances, under the same indices, as the dataset of the original tree.
+pub fn attach<T: Number, U: Number>(root: &Arc<Cluster<T, U>>, dataset: Arc<dyn Dataset<T, U>>) -> Arc<Cluster<T, U>> {
+    // Every cluster of the tree is still held by the original tree, so each one is copied.
+    attach_owned(Arc::clone(root), dataset)
+}
+
+/// Moves the tree rooted at the given cluster onto the given dataset, as with `attach`, but consumes the tree so
+/// that each cluster hands its indices to its copy, instead of the tree being held twice. Clusters that are still
+/// shared elsewhere are copied instead.
+///
+/// The clusters are moved one depth at a time, without recursion, so that a deep tree cannot overflow the stack.
+pub(crate) fn attach_owned<T: Number, U: Number>(
+    root: Arc<Cluster<T, U>>,
+    dataset: Arc<dyn Dataset<T, U>>,
+) -> Arc<Cluster<T, U>> {
+    let dataset = &dataset;
+    let (root, children) = move_cluster(root, dataset, None);
+    let mut level = vec![(Arc::clone(&root), children)];
+    while !level.is_empty() {
+        level = level
+            .into_par_iter()
+            .flat_map_iter(|(copy, children)| {
+                children.into_iter().flat_map(move |(left, right)| {
+                    let (left, left_children) = move_cluster(left, dataset, Some(Arc::downgrade(&copy)));
+                    let (right, right_children) = move_cluster(right, dataset, Some(Arc::downgrade(&copy)));
+                    *write_cache(&copy.children) = Some((Arc::clone(&left), Arc::clone(&right)));
+                    [(left, left_children), (right, right_children)]
+                })
+            })
+            .collect();
+    }
+    root
+}
+
+/// A cluster moved onto a dataset, without its children, along with the children it had.
+type MovedCluster<T, U> = (Arc<Cluster<T, U>>, Option<Children<T, U>>);
+
+/// Moves a single cluster onto the given dataset, without its children, and returns it along with the children it
+/// had. A cluster that is still shared elsewhere is copied instead.
+fn move_cluster<T: Number, U: Number>(
+    cluster: Arc<Cluster<T, U>>,
+    dataset: &Arc<dyn Dataset<T, U>>,
+    parent: Option<Weak<Cluster<T, U>>>,
+) -> MovedCluster<T, U> {
+    let mut cluster = Arc::try_unwrap(cluster);
+    let (name, indices, memory, children) = match &mut cluster {
+        Ok(cluster) => (
+            std::mem::take(&mut cluster.name),
+            std::mem::take(&mut cluster.indices),
+            std::mem::replace(&mut cluster._memory, Tracked::new(Subsystem::Trees, 0)),
+            cluster
+                .children
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take(),
+        ),
+        Err(cluster) => (
+            cluster.name.clone(),
+            cluster.indices.clone(),
+            Tracked::cluster::<T, U>(cluster.cardinality),
+            read_cache(&cluster.children).clone(),
+        ),
+    };
+    let cluster: &Cluster<T, U> = match &cluster {
+        Ok(cluster) => cluster,
+        Err(cluster) => cluster,
+    };
+    let copy = Arc::new(Cluster {
+        dataset: Arc::clone(dataset),
+        name,
+        cardinality: cluster.cardinality,
+        _memory: memory,
+        indices,
+        argcenter: cluster.argcenter,
+        argradius: cluster.argradius,
+        radius: cluster.radius,
+        lfd: cluster.lfd,
+        children: RwLock::new(None),
+        parent,
+        ratios: cluster.ratios,
+        center_policy: cluster.center_policy,
+        radius_policy: cluster.radius_policy,
+        seed: cluster.seed,
+    });
+    (copy, children)
+}
+
+/// Writes the policies with which the clusters of a tree chose their centers and radii.
+pub(super) fn write_policies(writer: &mut ByteWriter, center_policy: CenterPolicy, radius_policy: RadiusPolicy) {
+    match center_policy {
+        CenterPolicy::Sampled => writer.write_u8(0),
+        CenterPolicy::Exact => writer.write_u8(1),
+        CenterPolicy::SampleSize(size) => {
+            writer.write_u8(2);
+            writer.write_usize(size);
+        }
+        CenterPolicy::Centroid => writer.write_u8(3),
+    }
+    match radius_policy {
+        RadiusPolicy::Exact => writer.write_u8(0),
+        RadiusPolicy::SampleSize(size) => {
+            writer.write_u8(1);
+            writer.write_usize(size);
+        }
+    }
+}
+
+/// Reads the policies written by `write_policies`.
+pub(super) fn read_policies(reader: &mut ByteReader) -> Result<(CenterPolicy, RadiusPolicy), String> {
+    let center_policy = match reader.read_u8()? {
+        0 => CenterPolicy::Sampled,
+        1 => CenterPolicy::Exact,
+        2 => CenterPolicy::SampleSize(reader.read_usize()?),
+        3 => CenterPolicy::Centroid,
+        tag => return Err(format!("Unknown center policy tag {}.", tag)),
+    };
+    let radius_policy = match reader.read_u8()? {
+        0 => RadiusPolicy::Exact,
+        1 => RadiusPolicy::SampleSize(reader.read_usize()?),
+        tag => return Err(format!("Unknown radius policy tag {}.", tag)),
+    };
+    Ok((center_policy, radius_policy))
+}
+
+/// Writes the clusters of the tree rooted at the given cluster in pre-order, with an explicit stack rather than by
+/// recursion, so that a deep tree cannot overflow the stack.
+fn write_cluster<T: Number, U: Number>(writer: &mut ByteWriter, root: &Arc<Cluster<T, U>>) {
+    let mut stack = vec![Arc::clone(root)];
+    while let Some(cluster) = stack.pop() {
+        writer.write_bitvec(&cluster.name);
+        writer.write_usizes(&cluster.indices);
+        writer.write_usize(cluster.argcenter);
+        writer.write_usize(cluster.argradius);
+        writer.write_number(cluster.radius);
+        writer.write_f64(cluster.lfd);
+        cluster.ratios.iter().for_each(|&ratio| writer.write_f64(ratio));
+
+        match read_cache(&cluster.children).clone() {
+            Some((left, right)) => {
+                writer.write_u8(1);
+                stack.extend([right, left]);
+            }
+            None => writer.write_u8(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::utils::readers::read_test_data;
+    use crate::Cakes;
+    use crate::CenterPolicy;
+    use crate::RadiusPolicy;
+
+    use super::tree_from_bytes;
+    use super::tree_to_bytes;
+
+    fn assert_same_tree<T: Number, U: Number>(left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>) {
+        let mut left_tree = left.flatten_tree();
+        left_tree.push(Arc::clone(left));
+        let mut right_tree = right.flatten_tree();
+        right_tree.push(Arc::clone(right));
+        left_tree.sort();
+        right_tree.sort();
+
+        assert_eq!(left_tree.len(), right_tree.len());
+        for (l, r) in left_tree.iter().zip(right_tree.iter()) {
+            assert_eq!(l.name, r.name);
+            assert_eq!(l.indices, r.indices);
+            assert_eq!(l.argcenter, r.argcenter);
+            assert_eq!(l.argradius, r.argradius);
+            assert_eq!(l.radius, r.radius);
+            assert_eq!(l.lfd.to_bits(), r.lfd.to_bits());
+            assert_eq!(l.ratios, r.ratios);
+            assert_eq!(l.parent.is_some(), r.parent.is_some());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (data, _) = read_test_data();
+        let data: Vec<Vec<f32>> = data
+            .into_iter()
+            .map(|row| row.into_iter().map(|v| v as f32).collect())
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f32, f32>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), Some(10), None);
+
+        let bytes = tree_to_bytes(&cakes.root, cakes.report.as_ref(), None);
+        let (root, report) = tree_from_bytes(&bytes, Arc::clone(&dataset)).unwrap();
+        assert_same_tree(&cakes.root, &root);
+        assert_eq!(report, cakes.report);
+
+        // Serializing the loaded tree must reproduce the same bytes.
+        assert_eq!(bytes, tree_to_bytes(&root, report.as_ref(), None));
+
+        let attached = super::attach(&root, Arc::clone(&dataset));
+        assert_same_tree(&root, &attached);
+
+        // Moving a tree gives the same tree, whether or not its clusters are shared elsewhere.
+        let moved = super::attach_owned(attached, Arc::clone(&dataset));
+        assert_same_tree(&root, &moved);
+        let shared = super::attach_owned(Arc::clone(&root), Arc::clone(&dataset));
+        assert_same_tree(&root, &shared);
+
+        // Reading with the wrong type parameters must fail.
+        let metric = metric_from_name("euclidean").unwrap();
+        let other: Arc<dyn Dataset<f32, f64>> = Arc::new(RowMajor::new(Arc::new(vec![vec![0.]]), metric, false));
+        assert!(tree_from_bytes(&bytes, other).is_err());
+
+        // Truncated bytes must fail instead of panicking.
+        assert!(tree_from_bytes(&bytes[..bytes.len() - 3], Arc::clone(&dataset)).is_err());
+    }
+
+    #[test]
+    fn test_bad_indices() {
+        let data = vec![vec![0., 0.], vec![1., 1.], vec![2., 2.], vec![3., 3.]];
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let bytes = tree_to_bytes(&cakes.root, None, None);
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let smaller: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(RowMajor::new(Arc::new(vec![vec![0., 0.], vec![1., 1.]]), metric, false));
+        assert!(tree_from_bytes(&bytes, smaller).is_err());
+    }
+
+    #[test]
+    fn test_policies() {
+        let data: Vec<_> = (0..200).map(|i| vec![i as f64, (i % 7) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let (center_policy, radius_policy) = (CenterPolicy::SampleSize(7), RadiusPolicy::SampleSize(5));
+        let cakes = Cakes::build_with_policies(Arc::clone(&dataset), Some(4), None, center_policy, radius_policy);
+
+        let path = std::env::temp_dir().join(format!("clam-test-policies-{}.tree", std::process::id()));
+        super::save_tree(&cakes.root, cakes.report.as_ref(), None, &path).unwrap();
+        let (root, _) = super::load_tree(&path, Arc::clone(&dataset)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_same_tree(&cakes.root, &root);
+        let mut clusters = root.flatten_tree();
+        clusters.push(Arc::clone(&root));
+        for cluster in clusters {
+            assert_eq!(cluster.center_policy, center_policy);
+            assert_eq!(cluster.radius_policy, radius_policy);
+        }
+    }
+}
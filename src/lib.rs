@@ -5,29 +5,104 @@
 //! - [CLAM](https://arxiv.org/abs/1908.08551)
 //! - [CHAODA](https://arxiv.org/abs/2103.11774)
 //!
+//! # Logging
+//!
+//! The library logs its progress through the `log` facade and never installs a logger itself.
+//! The command-line tool, behind the default `cli` feature, logs to stderr and, optionally, to a file.
+//!
+//! # Memory
+//!
+//! With the `memory-accounting` feature, `memory_report` returns the approximate bytes held by trees, caches, graphs
+//! and compressed trees, e.g. to plan the capacity of a deployment.
+//!
+//! # Chemistry
+//!
+//! With the `chemistry` feature, the `molecules` module reads molecules from SMILES strings and serves their circular
+//! fingerprints as a `Dataset`, e.g. to search for similar molecules with the `tanimoto` metric.
+//!
+//! # SIMD
+//!
+//! With the `simd` feature, the euclidean, euclideansq, manhattan, cosine and angular metrics of `f32` and `f64`
+//! instances use AVX and FMA on x86_64 CPUs that have them, as detected at run time.
+//!
+//! # Arrow
+//!
+//! With the `arrow` feature, `arrow::ArrowDataset` serves the rows of Arrow record batches, or of a Parquet file, as
+//! instances, with some columns as the features and the others as the metadata of each instance.
+//!
+//! # HDF5
+//!
+//! With the `hdf5` feature, `hdf5::Hdf5Dataset` serves a 2-dimensional dataset in an HDF5 file, e.g. the `train` set
+//! of an ANN-benchmarks file, reading it one chunk of rows at a time.
+//!
+//! # Object stores
+//!
+//! With the `object-store` feature, `object_sources::StoreSource` reads objects in S3 or GCS, or in any store of the
+//! `object_store` crate, for `ObjectStoreDataset`.
+//!
+//! # Demo datasets
+//!
+//! With the `demo` feature, `datasets::demo` returns a small vector dataset and a small sequence dataset, bundled
+//! into the library, with the exact nearest neighbors of their queries, e.g. for tutorials and end-to-end tests.
+//!
+//! # Admission
+//!
+//! With the `admission` feature, `admission::Admission` rate-limits, queues and sheds the queries of several clients
+//! to a shared tree, for servers built on the library.
+//!
 
 mod anomaly;
 mod core;
+mod memory;
 mod search;
 mod traits;
 
+#[cfg(feature = "demo")]
+pub mod datasets;
+pub mod io;
+pub mod pipeline;
 pub mod prelude;
 pub mod utils;
 
 pub use crate::anomaly::Chaoda;
 
 pub use crate::core::criteria;
+pub use crate::core::CenterPolicy;
+pub use crate::core::CentersDataset;
 pub use crate::core::Cluster;
 pub use crate::core::Edge;
 pub use crate::core::Graph;
 pub use crate::core::Manifold;
+pub use crate::core::RadiusPolicy;
+pub use crate::core::SamplingError;
+pub use crate::core::Stratification;
+
+pub use crate::memory::memory_report;
+pub use crate::memory::MemoryReport;
+pub use crate::memory::Subsystem;
 
+#[cfg(feature = "admission")]
+pub use crate::search::admission;
 pub use crate::search::codec;
+pub use crate::search::numa;
+pub use crate::search::trace;
+pub use crate::search::AuditReport;
+pub use crate::search::BatchPolicy;
 pub use crate::search::Cakes;
 pub use crate::search::CompressibleDataset;
+pub use crate::search::LeafScan;
+pub use crate::search::MetadataIndex;
 
+#[cfg(feature = "arrow")]
+pub use crate::traits::arrow;
 pub use crate::traits::dataset;
+#[cfg(feature = "hdf5")]
+pub use crate::traits::hdf5;
 pub use crate::traits::metric;
+#[cfg(feature = "chemistry")]
+pub use crate::traits::molecules;
+#[cfg(feature = "object-store")]
+pub use crate::traits::object_sources;
 pub use crate::traits::Dataset;
 pub use crate::traits::Metric;
 pub use crate::traits::Number;
@@ -35,3 +110,5 @@ pub use crate::traits::Number;
 pub use crate::anomaly::get_individual_algorithms;
 pub use crate::anomaly::get_meta_ml_methods;
 pub use crate::traits::metric::metric_from_name;
+pub use crate::traits::metric::metric_names;
+pub use crate::traits::metric::register_metric;
@@ -0,0 +1,168 @@
+//! Approximate accounting of the memory held by each subsystem of `CLAM`, behind the `memory-accounting` feature.
+//!
+//! Clusters, graphs, caches and compressed trees each record the bytes they hold in a global counter for their
+//! subsystem when they are built, as they grow or shrink, and when they are dropped. The counts include the inline
+//! size of each struct and of the contents of its collections, but not the unused capacity of those collections or
+//! the bookkeeping of the allocator, so they are estimates for capacity planning rather than exact measurements.
+//! Datasets are not counted, because their instances are usually owned, or memory-mapped, by the caller.
+//!
+//! Without the feature, nothing is recorded and `memory_report` returns zeros.
+
+#[cfg(feature = "memory-accounting")]
+use std::sync::atomic::AtomicUsize;
+#[cfg(feature = "memory-accounting")]
+use std::sync::atomic::Ordering;
+
+use crate::prelude::*;
+
+/// The subsystems whose memory is accounted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// The clusters of search trees.
+    Trees,
+    /// The distance caches of datasets, the result caches of searches and the decompressed leaves of codecs.
+    Caches,
+    /// The graphs induced from clusters, and their adjacency lists.
+    Graphs,
+    /// The packed clusters of compressed trees.
+    Compressed,
+}
+
+/// The approximate number of bytes held by each subsystem at the time of a call to `memory_report`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub trees: usize,
+    pub caches: usize,
+    pub graphs: usize,
+    pub compressed: usize,
+    /// Whether the crate was built with the `memory-accounting` feature. If not, every count is zero.
+    pub enabled: bool,
+}
+
+impl MemoryReport {
+    /// Returns the number of bytes held by all subsystems.
+    pub fn total(&self) -> usize {
+        self.trees + self.caches + self.graphs + self.compressed
+    }
+}
+
+#[cfg(feature = "memory-accounting")]
+static COUNTERS: [AtomicUsize; 4] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Returns the approximate number of bytes currently held by each subsystem.
+pub fn memory_report() -> MemoryReport {
+    #[cfg(feature = "memory-accounting")]
+    {
+        let count = |subsystem: Subsystem| COUNTERS[subsystem as usize].load(Ordering::Relaxed);
+        MemoryReport {
+            trees: count(Subsystem::Trees),
+            caches: count(Subsystem::Caches),
+            graphs: count(Subsystem::Graphs),
+            compressed: count(Subsystem::Compressed),
+            enabled: true,
+        }
+    }
+    #[cfg(not(feature = "memory-accounting"))]
+    MemoryReport::default()
+}
+
+/// The bytes held by one struct, counted towards its subsystem until it is dropped.
+///
+/// Without the `memory-accounting` feature this is empty and every method does nothing.
+#[derive(Debug)]
+pub(crate) struct Tracked {
+    #[cfg(feature = "memory-accounting")]
+    subsystem: Subsystem,
+    #[cfg(feature = "memory-accounting")]
+    bytes: AtomicUsize,
+}
+
+#[allow(unused_variables)]
+impl Tracked {
+    pub fn new(subsystem: Subsystem, bytes: usize) -> Self {
+        #[cfg(feature = "memory-accounting")]
+        COUNTERS[subsystem as usize].fetch_add(bytes, Ordering::Relaxed);
+        Tracked {
+            #[cfg(feature = "memory-accounting")]
+            subsystem,
+            #[cfg(feature = "memory-accounting")]
+            bytes: AtomicUsize::new(bytes),
+        }
+    }
+
+    /// Tracks the inline size and heap contents of a cluster with the given cardinality.
+    pub fn cluster<T: Number, U: Number>(cardinality: usize) -> Self {
+        let bytes = std::mem::size_of::<Cluster<T, U>>() + cardinality * std::mem::size_of::<Index>();
+        Self::new(Subsystem::Trees, bytes)
+    }
+
+    pub fn grow(&self, bytes: usize) {
+        #[cfg(feature = "memory-accounting")]
+        {
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+            COUNTERS[self.subsystem as usize].fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn shrink(&self, bytes: usize) {
+        #[cfg(feature = "memory-accounting")]
+        {
+            self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            COUNTERS[self.subsystem as usize].fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Replaces the tracked bytes, e.g. once a struct is fully built.
+    pub fn set(&self, bytes: usize) {
+        #[cfg(feature = "memory-accounting")]
+        {
+            let previous = self.bytes.swap(bytes, Ordering::Relaxed);
+            COUNTERS[self.subsystem as usize].fetch_add(bytes, Ordering::Relaxed);
+            COUNTERS[self.subsystem as usize].fetch_sub(previous, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        #[cfg(feature = "memory-accounting")]
+        COUNTERS[self.subsystem as usize].fetch_sub(*self.bytes.get_mut(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::memory_report;
+    use super::MemoryReport;
+
+    #[test]
+    fn test_memory_report() {
+        let data: Vec<_> = (0..200).map(|i| vec![(i * 7 % 31) as f64, (i % 13) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = Arc::new(RowMajor::<f64, f64>::new(Arc::new(data), metric, true));
+        let cakes = Cakes::build(Arc::clone(&dataset).as_arc_dataset(), Some(5), None);
+
+        // Other tests may hold trees and caches too, so the counts are only bounded from below.
+        let report = memory_report();
+        if cfg!(feature = "memory-accounting") {
+            let num_clusters = cakes.root.num_descendants() + 1;
+            assert!(report.enabled);
+            assert!(report.trees >= num_clusters * std::mem::size_of::<Cluster<f64, f64>>() + 200 * 8);
+            assert!(report.caches >= dataset.cache_size().unwrap() * std::mem::size_of::<(Index, Index, f64)>());
+            assert!(report.total() >= report.trees + report.caches);
+        } else {
+            assert_eq!(report, MemoryReport::default());
+        }
+    }
+}
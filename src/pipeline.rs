@@ -0,0 +1,215 @@
+//! Indexing a dataset and searching it in one call, for experiments that need no more control than that.
+//!
+//! `index_and_search` builds the search tree of the instances with the named metric, searches it for every query and
+//! returns the hits. Given a directory in its `PipelineOptions`, it also saves the tree there, records it in a
+//! `Manifest`, so that later calls with the same instances, metric and criteria load the tree instead of building it,
+//! and exports the hits as `hits.csv`.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::dataset::RowMajor;
+use crate::io::content_hash;
+use crate::io::Manifest;
+use crate::prelude::*;
+use crate::BatchPolicy;
+use crate::Cakes;
+
+/// The name of the file, in the directory of a pipeline, that holds the search tree.
+pub const TREE_FILE: &str = "tree.bin";
+
+/// The name of the file, in the directory of a pipeline, to which the hits are exported.
+pub const HITS_FILE: &str = "hits.csv";
+
+/// The search to run for every query of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PipelineSearch<U: Number> {
+    /// `Cakes::knn` with the given `k`.
+    Knn(usize),
+    /// `Cakes::rnn` with the given radius.
+    Rnn(U),
+}
+
+/// How `index_and_search` builds, searches and saves. The defaults build with the defaults of `Cakes::build`, search
+/// for the 10 nearest neighbors and save nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineOptions<U: Number> {
+    /// Clusters deeper than this are not partitioned. Defaults to None, i.e. the default of `Cakes::build`.
+    pub max_depth: Option<usize>,
+
+    /// Clusters with fewer instances are not partitioned. Defaults to None, i.e. the default of `Cakes::build`.
+    pub min_cardinality: Option<usize>,
+
+    /// The search for every query. Defaults to `Knn(10)`.
+    pub search: PipelineSearch<U>,
+
+    /// The directory in which the tree is cached and the hits exported. Defaults to None, i.e. nothing is written.
+    pub directory: Option<PathBuf>,
+
+    /// Whether the dataset caches the distances among its instances. Defaults to false.
+    pub use_cache: bool,
+}
+
+impl<U: Number> Default for PipelineOptions<U> {
+    fn default() -> Self {
+        PipelineOptions {
+            max_depth: None,
+            min_cardinality: None,
+            search: PipelineSearch::Knn(10),
+            directory: None,
+            use_cache: false,
+        }
+    }
+}
+
+/// The hits of every query of a pipeline, and how the tree was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineResults<U: Number> {
+    /// The hits for `queries[i]`, as tuples of index and distance, at position `i`.
+    pub hits: Vec<Vec<(Index, U)>>,
+
+    /// Whether the tree was built, rather than loaded from the directory.
+    pub built: bool,
+
+    /// The path of the exported hits, if there was a directory.
+    pub exported: Option<PathBuf>,
+}
+
+/// Builds, or loads, the search tree of the instances with the named metric and searches it for each query, as the
+/// options say. See the module docs.
+///
+/// Returns an Err if the metric is unknown, the instances do not suit it, or the directory, the tree or the hits
+/// cannot be read or written.
+pub fn index_and_search<T: 'static + Number, U: 'static + Number>(
+    data: Vec<Vec<T>>,
+    metric_name: &str,
+    queries: &[Vec<T>],
+    options: &PipelineOptions<U>,
+) -> Result<PipelineResults<U>, String> {
+    let metric = metric_from_name::<T, U>(metric_name)?;
+    let inputs = inputs_hash(&data, metric_name, options);
+    let dataset = Arc::new(RowMajor::try_from_rows(data, metric, options.use_cache, false)?).as_arc_dataset();
+    let build = || Cakes::build(Arc::clone(&dataset), options.max_depth, options.min_cardinality);
+
+    let (cakes, built) = match &options.directory {
+        Some(directory) => {
+            let mut manifest = Manifest::open(directory)?;
+            let mut built = None;
+            manifest.run_step("tree", TREE_FILE, inputs, |path| {
+                let cakes = build();
+                cakes.save(path)?;
+                built = Some(cakes);
+                Ok(())
+            })?;
+            match built {
+                Some(cakes) => (cakes, true),
+                None => (Cakes::load(&manifest.path_of(TREE_FILE), Arc::clone(&dataset))?, false),
+            }
+        }
+        None => (build(), true),
+    };
+
+    let hits = match options.search {
+        PipelineSearch::Knn(k) => cakes.batch_knn(queries, k, BatchPolicy::default()),
+        PipelineSearch::Rnn(radius) => cakes.batch_rnn(queries, Some(radius), BatchPolicy::default()),
+    };
+    let exported = match &options.directory {
+        Some(directory) => {
+            let path = directory.join(HITS_FILE);
+            export_hits(&hits, &path)?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    Ok(PipelineResults { hits, built, exported })
+}
+
+/// Returns the hash of everything the tree depends on: the instances, the metric, the types and the criteria.
+fn inputs_hash<T: Number, U: Number>(data: &[Vec<T>], metric_name: &str, options: &PipelineOptions<U>) -> u64 {
+    let description = format!(
+        "metric={} types=({}, {}) max_depth={:?} min_cardinality={:?}",
+        metric_name,
+        T::type_name(),
+        U::type_name(),
+        options.max_depth,
+        options.min_cardinality
+    );
+    let mut bytes = description.into_bytes();
+    for instance in data {
+        bytes.extend_from_slice(&(instance.len() as u64).to_be_bytes());
+        instance.iter().for_each(|value| bytes.extend(value.to_bytes()));
+    }
+    content_hash(&bytes)
+}
+
+/// Writes the hits as a delimited file with the columns `query`, `rank`, `index` and `distance`, with the hits of each
+/// query in the order the search returned them.
+fn export_hits<U: Number>(hits: &[Vec<(Index, U)>], path: &Path) -> Result<(), String> {
+    let fail = |error: csv::Error| format!("Error: Failed to write {}. {}", path.display(), error);
+    let mut writer = csv::Writer::from_path(path).map_err(fail)?;
+    writer
+        .write_record(["query", "rank", "index", "distance"])
+        .map_err(fail)?;
+    for (query, hits) in hits.iter().enumerate() {
+        for (rank, (index, distance)) in hits.iter().enumerate() {
+            let record = [
+                query.to_string(),
+                rank.to_string(),
+                index.to_string(),
+                distance.to_string(),
+            ];
+            writer.write_record(record).map_err(fail)?;
+        }
+    }
+    writer.flush().map_err(|error| fail(error.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::index_and_search;
+    use super::PipelineOptions;
+    use super::PipelineSearch;
+
+    #[test]
+    fn test_index_and_search() {
+        let data: Vec<_> = (0..300).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let queries: Vec<_> = data.iter().step_by(30).cloned().collect();
+
+        let results = index_and_search::<f64, f64>(data.clone(), "euclidean", &queries, &Default::default()).unwrap();
+        assert!(results.built && results.exported.is_none());
+        assert!(results.hits.iter().all(|hits| hits.len() == 10 && hits[0].1 == 0.));
+
+        // With a directory, the tree is built once and then loaded, for the same hits.
+        let directory = std::env::temp_dir().join(format!("clam-test-pipeline-{}", std::process::id()));
+        let options = PipelineOptions {
+            search: PipelineSearch::Rnn(5.),
+            directory: Some(directory.clone()),
+            ..Default::default()
+        };
+        let first = index_and_search::<f64, f64>(data.clone(), "euclidean", &queries, &options).unwrap();
+        let second = index_and_search::<f64, f64>(data.clone(), "euclidean", &queries, &options).unwrap();
+        assert!(first.built && !second.built);
+        assert_eq!(first.hits, second.hits);
+
+        let exported = std::fs::read_to_string(second.exported.unwrap()).unwrap();
+        let num_hits: usize = second.hits.iter().map(Vec::len).sum();
+        assert_eq!(exported.lines().count(), num_hits + 1);
+        assert_eq!(exported.lines().next(), Some("query,rank,index,distance"));
+
+        // Other criteria or instances build a new tree.
+        let options = PipelineOptions {
+            max_depth: Some(4),
+            ..options
+        };
+        assert!(
+            index_and_search::<f64, f64>(data, "euclidean", &queries, &options)
+                .unwrap()
+                .built
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(index_and_search::<f64, f64>(vec![vec![0.]], "unknown", &queries, &Default::default()).is_err());
+    }
+}
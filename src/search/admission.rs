@@ -0,0 +1,293 @@
+//! Admission of queries from several clients to a shared search tree, e.g. by a search server, so that one heavy
+//! client cannot starve the others.
+//!
+//! An `Admission` limits the rate of the queries of each client with a bucket of tokens, caps the queries that run at
+//! once over all clients and for each client, and queues the queries beyond the first cap until a slot frees. Queries
+//! that arrive when the queue is full are shed. Rejected queries get an Err saying why, and when a rate-limited client
+//! may retry. Searches are wrapped with `run`, e.g. `admission.run("client", || cakes.knn(&query, 10))`.
+
+use std::collections::HashMap;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Instant;
+
+use crate::utils::lock_cache;
+
+/// The limits of an `Admission`. The defaults admit every query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdmissionPolicy {
+    /// The queries per second each client may run on average. Defaults to None, i.e. no limit.
+    pub rate: Option<f64>,
+
+    /// The queries a client that has been idle may run at once before its rate applies. Defaults to 1.
+    pub burst: usize,
+
+    /// The queries that may run at once over all clients. Defaults to no limit.
+    pub max_concurrent: usize,
+
+    /// The queries one client may run or have queued at once. Defaults to no limit.
+    pub max_per_client: usize,
+
+    /// The queries that may wait for one of the `max_concurrent` slots. Later queries are shed. Defaults to no limit.
+    pub max_queued: usize,
+}
+
+impl Default for AdmissionPolicy {
+    fn default() -> Self {
+        AdmissionPolicy {
+            rate: None,
+            burst: 1,
+            max_concurrent: usize::MAX,
+            max_per_client: usize::MAX,
+            max_queued: usize::MAX,
+        }
+    }
+}
+
+/// The numbers of queries admitted and rejected, by the reason for the rejection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdmissionCounts {
+    pub admitted: u64,
+    /// Rejected because the client exceeded its rate.
+    pub rate_limited: u64,
+    /// Rejected because the client already had `max_per_client` queries.
+    pub client_busy: u64,
+    /// Rejected because the queue was full.
+    pub shed: u64,
+}
+
+/// The bucket of a client and its queries.
+#[derive(Debug)]
+struct Client {
+    tokens: f64,
+    refilled: Instant,
+    /// The running and queued queries of the client.
+    active: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    clients: HashMap<String, Client>,
+    running: usize,
+    queued: usize,
+    counts: AdmissionCounts,
+}
+
+/// Admits queries from clients under an `AdmissionPolicy`. See the module docs.
+#[derive(Debug)]
+pub struct Admission {
+    policy: AdmissionPolicy,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+impl Admission {
+    /// Returns an Err unless the rate, if any, is positive and finite, and the burst and caps are positive.
+    pub fn new(policy: AdmissionPolicy) -> Result<Self, String> {
+        if policy.rate.is_some_and(|rate| !(rate.is_finite() && rate > 0.)) {
+            return Err(format!("The rate must be positive and finite, not {:?}.", policy.rate));
+        }
+        if policy.burst == 0 || policy.max_concurrent == 0 || policy.max_per_client == 0 {
+            return Err("The burst and the caps on concurrent queries must be positive.".to_string());
+        }
+        Ok(Admission {
+            policy,
+            state: Mutex::new(State::default()),
+            freed: Condvar::new(),
+        })
+    }
+
+    pub fn policy(&self) -> &AdmissionPolicy {
+        &self.policy
+    }
+
+    /// Runs the search for the client if the query is admitted, waiting in the queue if every slot is taken.
+    ///
+    /// Returns an Err, without running the search, if the client exceeded its rate or already has `max_per_client`
+    /// queries, or if the queue is full.
+    pub fn run<R>(&self, client: &str, search: impl FnOnce() -> R) -> Result<R, String> {
+        let _permit = self.admit(client)?;
+        Ok(search())
+    }
+
+    /// Admits a query for the client, as in `run`, and returns the permit that frees its slot when dropped.
+    pub fn admit(&self, client: &str) -> Result<Permit<'_>, String> {
+        let now = Instant::now();
+        let mut state = lock_cache(&self.state);
+        let burst = self.policy.burst as f64;
+        if !state.clients.contains_key(client) {
+            // Idle clients whose buckets are full are the same as new ones, so they need not be kept.
+            let rate = self.policy.rate;
+            state
+                .clients
+                .retain(|_, other| other.active > 0 || rate.is_some_and(|rate| refill(other, now, rate, burst) < burst));
+        }
+        let State {
+            clients,
+            running,
+            queued,
+            counts,
+        } = &mut *state;
+        let entry = clients.entry(client.to_string()).or_insert(Client {
+            tokens: burst,
+            refilled: now,
+            active: 0,
+        });
+
+        if let Some(rate) = self.policy.rate {
+            let tokens = refill(entry, now, rate, burst);
+            if tokens < 1. {
+                counts.rate_limited += 1;
+                return Err(format!(
+                    "The client '{}' exceeded its rate of {} queries per second. Retry in {:.3} seconds.",
+                    client,
+                    rate,
+                    (1. - tokens) / rate
+                ));
+            }
+        }
+        if entry.active >= self.policy.max_per_client {
+            counts.client_busy += 1;
+            return Err(format!(
+                "The client '{}' already has {} queries running or queued, which is its limit.",
+                client, entry.active
+            ));
+        }
+        if *running >= self.policy.max_concurrent && *queued >= self.policy.max_queued {
+            counts.shed += 1;
+            return Err(format!(
+                "The query was shed because {} queries are running and {} are queued, which are the limits.",
+                running, queued
+            ));
+        }
+
+        entry.tokens -= 1.;
+        entry.active += 1;
+        counts.admitted += 1;
+        if *running >= self.policy.max_concurrent {
+            *queued += 1;
+            state = self
+                .freed
+                .wait_while(state, |state| state.running >= self.policy.max_concurrent)
+                .unwrap_or_else(PoisonError::into_inner);
+            state.queued -= 1;
+        }
+        state.running += 1;
+        Ok(Permit {
+            admission: self,
+            client: client.to_string(),
+        })
+    }
+
+    /// Returns the numbers of queries admitted and rejected so far.
+    pub fn counts(&self) -> AdmissionCounts {
+        lock_cache(&self.state).counts
+    }
+
+    /// Returns the numbers of queries running and queued.
+    pub fn load(&self) -> (usize, usize) {
+        let state = lock_cache(&self.state);
+        (state.running, state.queued)
+    }
+}
+
+/// Adds the tokens earned since the bucket of the client was last refilled, up to the burst, and returns them.
+fn refill(client: &mut Client, now: Instant, rate: f64, burst: f64) -> f64 {
+    let elapsed = now.saturating_duration_since(client.refilled).as_secs_f64();
+    client.tokens = (client.tokens + elapsed * rate).min(burst);
+    client.refilled = now;
+    client.tokens
+}
+
+/// The slot of an admitted query, which is freed when the permit is dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    admission: &'a Admission,
+    client: String,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut state = lock_cache(&self.admission.state);
+        state.running -= 1;
+        if let Some(client) = state.clients.get_mut(&self.client) {
+            client.active -= 1;
+        }
+        drop(state);
+        self.admission.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Admission;
+    use super::AdmissionCounts;
+    use super::AdmissionPolicy;
+
+    #[test]
+    fn test_admission() {
+        // Each client has its own bucket.
+        let admission = Admission::new(AdmissionPolicy {
+            rate: Some(1e-3),
+            burst: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(admission.run("a", || 1), Ok(1));
+        assert_eq!(admission.run("a", || 2), Ok(2));
+        let error = admission.run("a", || 3).unwrap_err();
+        assert!(error.contains("'a' exceeded its rate") && error.contains("Retry in"));
+        assert_eq!(admission.run("b", || 4), Ok(4));
+
+        // One client cannot take every slot, and queries beyond the queue are shed.
+        let admission = Arc::new(
+            Admission::new(AdmissionPolicy {
+                max_concurrent: 2,
+                max_per_client: 1,
+                max_queued: 1,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let heavy = admission.admit("heavy").unwrap();
+        assert!(admission.admit("heavy").unwrap_err().contains("'heavy' already has 1"));
+        let light = admission.admit("light").unwrap();
+        assert_eq!(admission.load(), (2, 0));
+
+        let waiting = {
+            let admission = Arc::clone(&admission);
+            std::thread::spawn(move || admission.run("waiting", || 5))
+        };
+        while admission.load().1 == 0 {
+            std::thread::yield_now();
+        }
+        assert!(admission.admit("other").unwrap_err().contains("shed"));
+        drop(heavy);
+        assert_eq!(waiting.join().unwrap(), Ok(5));
+        drop(light);
+        assert_eq!(admission.load(), (0, 0));
+        assert_eq!(
+            admission.counts(),
+            AdmissionCounts {
+                admitted: 3,
+                rate_limited: 0,
+                client_busy: 1,
+                shed: 1,
+            }
+        );
+
+        assert!(Admission::new(AdmissionPolicy {
+            rate: Some(0.),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(Admission::new(AdmissionPolicy {
+            max_concurrent: 0,
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
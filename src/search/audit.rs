@@ -0,0 +1,192 @@
+//! Sampled audits of approximate searches, which compare the hits of a random fraction of searches with the exact
+//! hits from a linear scan, in the background, to measure the recall of the approximation while it serves queries.
+
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::compare_distances;
+use crate::utils::lock_cache;
+
+/// The recall measured by the audits of a `Cakes`, as returned by `Cakes::audit_report`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuditReport {
+    /// The number of approximate searches since the audits were enabled.
+    pub searches: u64,
+
+    /// The number of searches whose audits have finished.
+    pub audited: u64,
+
+    /// The number of searches whose audits are still running.
+    pub pending: u64,
+
+    /// The mean recall over the audited searches, or None if none has finished.
+    pub mean_recall: Option<f64>,
+
+    /// The least recall of any audited search, or None if none has finished.
+    pub min_recall: Option<f64>,
+}
+
+/// Chooses which searches to audit and collects the recall of the audited searches.
+pub(crate) struct SearchAudit {
+    fraction: f64,
+    state: Mutex<AuditState>,
+    /// Signalled whenever an audit finishes.
+    finished: Condvar,
+}
+
+struct AuditState {
+    rng: StdRng,
+    searches: u64,
+    audited: u64,
+    pending: u64,
+    recall_sum: f64,
+    min_recall: f64,
+}
+
+impl SearchAudit {
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        SearchAudit {
+            fraction: fraction.clamp(0., 1.),
+            state: Mutex::new(AuditState {
+                rng: StdRng::seed_from_u64(seed),
+                searches: 0,
+                audited: 0,
+                pending: 0,
+                recall_sum: 0.,
+                min_recall: 1.,
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Counts a search, and checks its `k` nearest hits against a linear scan of the dataset on the rayon pool if it
+    /// is chosen for an audit.
+    pub fn knn<T: 'static + Number, U: 'static + Number>(
+        self: &Arc<Self>,
+        dataset: &Arc<dyn Dataset<T, U>>,
+        query: &[T],
+        k: usize,
+        hits: &[(Index, U)],
+    ) {
+        {
+            let mut state = lock_cache(&self.state);
+            state.searches += 1;
+            if !state.rng.gen_bool(self.fraction) {
+                return;
+            }
+            state.pending += 1;
+        }
+
+        let (audit, dataset, query, hits) = (Arc::clone(self), Arc::clone(dataset), query.to_vec(), hits.to_vec());
+        rayon::spawn(move || {
+            let recall = knn_recall(&dataset, &query, k, &hits);
+            let mut state = lock_cache(&audit.state);
+            state.pending -= 1;
+            state.audited += 1;
+            state.recall_sum += recall;
+            state.min_recall = state.min_recall.min(recall);
+            if recall < 1. {
+                log::debug!("An audited search had recall {:.3} with k = {}.", recall, k);
+            }
+            audit.finished.notify_all();
+        });
+    }
+
+    pub fn report(&self) -> AuditReport {
+        let state = lock_cache(&self.state);
+        let finished = state.audited > 0;
+        AuditReport {
+            searches: state.searches,
+            audited: state.audited,
+            pending: state.pending,
+            mean_recall: finished.then(|| state.recall_sum / state.audited as f64),
+            min_recall: finished.then(|| state.min_recall),
+        }
+    }
+
+    /// Blocks until every audit that has started has finished.
+    pub fn wait(&self) {
+        let mut state = lock_cache(&self.state);
+        while state.pending > 0 {
+            state = self
+                .finished
+                .wait(state)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+    }
+}
+
+/// Returns the fraction of the exact `k` nearest neighbors of the query that the hits hold.
+///
+/// A hit counts if it is no farther than the exact k-th nearest neighbor, so that ties at that distance may be broken
+/// either way.
+fn knn_recall<T: Number, U: Number>(
+    dataset: &Arc<dyn Dataset<T, U>>,
+    query: &[T],
+    k: usize,
+    hits: &[(Index, U)],
+) -> f64 {
+    let metric = dataset.metric();
+    let mut exact: Vec<_> = dataset
+        .indices()
+        .into_par_iter()
+        .map(|i| metric.distance(query, &dataset.instance(i)))
+        .collect();
+    exact.sort_by(compare_distances);
+    exact.truncate(k);
+
+    match exact.last() {
+        Some(kth) => {
+            let found = hits.iter().filter(|(_, d)| d <= kth).count();
+            std::cmp::min(found, exact.len()) as f64 / exact.len() as f64
+        }
+        None => 1.,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    #[test]
+    fn test_audit() {
+        let data: Vec<_> = (0..500).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let queries: Vec<_> = (0..40).map(|i| dataset.instance(i * 11)).collect();
+        assert_eq!(Cakes::build(Arc::clone(&dataset), None, None).audit_report(), None);
+
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None).with_audit(1., 42);
+        queries.iter().for_each(|query| drop(cakes.knn_beam(query, 10, 1)));
+        cakes.wait_for_audits();
+        let greedy = cakes.audit_report().unwrap();
+        assert_eq!((greedy.searches, greedy.audited, greedy.pending), (40, 40, 0));
+        let mean = greedy.mean_recall.unwrap();
+        assert!(greedy.min_recall.unwrap() <= mean && mean < 1.);
+
+        // Wide beams are exact, and exact searches are not audited.
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None).with_audit(1., 42);
+        queries.iter().for_each(|query| drop(cakes.knn_beam(query, 10, 1_000)));
+        queries.iter().for_each(|query| drop(cakes.knn(query, 10)));
+        cakes.wait_for_audits();
+        let wide = cakes.audit_report().unwrap();
+        assert_eq!((wide.searches, wide.min_recall), (40, Some(1.)));
+
+        let cakes = Cakes::build(dataset, None, None).with_audit(0.25, 7);
+        queries.iter().for_each(|query| drop(cakes.knn_beam(query, 10, 1)));
+        cakes.wait_for_audits();
+        let sampled = cakes.audit_report().unwrap();
+        assert!(sampled.audited > 0 && sampled.audited < 40);
+    }
+}
@@ -0,0 +1,88 @@
+//! Searches for batches of queries, with a choice of how the queries are spread over the threads of rayon.
+
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::parallelism;
+use crate::Cakes;
+
+/// A Vec of tuples of index of hit and its distance to the query.
+type Hits<U> = Vec<(Index, U)>;
+
+/// How a batch search spreads its queries over the threads of rayon. The hits do not depend on the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchPolicy {
+    /// Each query is one task, searched entirely on the thread that takes it. No query waits on the work of another,
+    /// so the time per query varies the least.
+    PerQuery,
+    /// Every query is a task, and so are the parallel parts of its search, in one pool from which idle threads steal.
+    /// This keeps every thread busy, for the most queries per second.
+    #[default]
+    WorkStealing,
+    /// The queries are split into one contiguous chunk per thread, and each chunk is searched in order on one thread,
+    /// so that each thread keeps to its own queries and their memory, as suits NUMA machines.
+    Chunked,
+}
+
+impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
+    /// Performs `rnn` for each query, with the given policy. The hits for `queries[i]` are at position `i`.
+    pub fn batch_rnn(&self, queries: &[Vec<T>], radius: Option<U>, policy: BatchPolicy) -> Vec<Hits<U>> {
+        batch(queries, policy, |query| self.rnn(query, radius))
+    }
+
+    /// Performs `knn` for each query, with the given policy. The hits for `queries[i]` are at position `i`.
+    pub fn batch_knn(&self, queries: &[Vec<T>], k: usize, policy: BatchPolicy) -> Vec<Hits<U>> {
+        batch(queries, policy, |query| self.knn(query, k))
+    }
+}
+
+/// Runs the search for each query as the policy says and returns the results in the order of the queries.
+fn batch<T: Number, R: Send>(queries: &[Vec<T>], policy: BatchPolicy, search: impl Fn(&[T]) -> R + Sync) -> Vec<R> {
+    match policy {
+        BatchPolicy::PerQuery => queries
+            .par_iter()
+            .with_max_len(1)
+            .map(|query| parallelism::sequentially(|| search(query)))
+            .collect(),
+        BatchPolicy::WorkStealing => queries.par_iter().map(|query| search(query)).collect(),
+        BatchPolicy::Chunked => {
+            let size = queries.len().div_ceil(rayon::current_num_threads()).max(1);
+            queries
+                .par_chunks(size)
+                .flat_map_iter(|chunk| {
+                    parallelism::sequentially(|| chunk.iter().map(|query| search(query)).collect::<Vec<_>>())
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::BatchPolicy;
+
+    #[test]
+    fn test_batch_policies() {
+        let data: Vec<_> = (0..1_000)
+            .map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let queries: Vec<_> = data.iter().step_by(7).cloned().collect();
+
+        let rnn: Vec<_> = queries.iter().map(|query| cakes.rnn(query, Some(5.))).collect();
+        let knn: Vec<_> = queries.iter().map(|query| cakes.knn(query, 10)).collect();
+        for policy in [BatchPolicy::PerQuery, BatchPolicy::WorkStealing, BatchPolicy::Chunked] {
+            assert_eq!(cakes.batch_rnn(&queries, Some(5.), policy), rnn);
+            assert_eq!(cakes.batch_knn(&queries, 10, policy), knn);
+        }
+        assert!(cakes.batch_knn(&[], 10, BatchPolicy::Chunked).is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+//! A bounded cache of search results, keyed by a fingerprint of the query and the search parameters.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::memory::Subsystem;
+use crate::memory::Tracked;
+use crate::prelude::*;
+use crate::utils::lock_cache;
+
+/// The kinds of searches whose results are cached.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CachedSearch {
+    Rnn,
+    Knn,
+    KnnBeam,
+}
+
+struct Entry<U: Number> {
+    /// The full key, compared on lookup so that fingerprint collisions are never served.
+    key: Vec<u8>,
+    last_used: u64,
+    hits: Vec<(Index, U)>,
+}
+
+impl<U: Number> Entry<U> {
+    /// Returns the approximate number of bytes held by the entry, including its fingerprint in both maps.
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + 3 * std::mem::size_of::<u64>()
+            + self.key.len()
+            + self.hits.len() * std::mem::size_of::<(Index, U)>()
+    }
+}
+
+struct State<U: Number> {
+    /// The fingerprint of the tree from which the cached results were computed.
+    tree: u64,
+    tick: u64,
+    entries: HashMap<u64, Entry<U>>,
+    /// Fingerprints of entries ordered by when they were last used.
+    recency: BTreeMap<u64, u64>,
+    hits: u64,
+    misses: u64,
+    /// The memory held by the entries, as counted by `memory_report`.
+    memory: Tracked,
+}
+
+/// A least-recently-used cache of search results.
+///
+/// Every lookup carries a fingerprint of the tree. When the fingerprint changes, e.g. because the root was replaced
+/// or rebuilt, the cache is emptied so that stale results are never returned.
+pub(crate) struct QueryCache<U: Number> {
+    capacity: usize,
+    state: Mutex<State<U>>,
+}
+
+impl<U: Number> QueryCache<U> {
+    pub fn new(capacity: usize) -> Self {
+        QueryCache {
+            capacity,
+            state: Mutex::new(State {
+                tree: 0,
+                tick: 0,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                hits: 0,
+                misses: 0,
+                memory: Tracked::new(Subsystem::Caches, 0),
+            }),
+        }
+    }
+
+    /// Returns the key for a search with the given kind, parameter, and query.
+    pub fn key<T: Number>(search: CachedSearch, parameter: &[u8], query: &[T]) -> Vec<u8> {
+        let mut key = vec![search as u8];
+        key.extend_from_slice(parameter);
+        query.iter().for_each(|v| key.extend(v.to_bytes()));
+        key
+    }
+
+    /// Returns a fingerprint for the tree rooted at the given cluster.
+    pub fn tree_fingerprint<T: Number>(root: &Arc<Cluster<T, U>>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (Arc::as_ptr(root) as usize).hash(&mut hasher);
+        root.cardinality.hash(&mut hasher);
+        root.radius.to_bytes().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn fingerprint(key: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns the cached hits for the key, if any, counting the lookup as a hit or a miss.
+    pub fn get(&self, tree: u64, key: &[u8]) -> Option<Vec<(Index, U)>> {
+        let mut state = lock_cache(&self.state);
+        state.check_tree(tree);
+
+        let fingerprint = Self::fingerprint(key);
+        state.tick += 1;
+        let tick = state.tick;
+        let (last_used, hits) = match state.entries.get_mut(&fingerprint) {
+            Some(entry) if entry.key == key => {
+                let last_used = entry.last_used;
+                entry.last_used = tick;
+                (last_used, entry.hits.clone())
+            }
+            _ => {
+                state.misses += 1;
+                return None;
+            }
+        };
+        state.recency.remove(&last_used);
+        state.recency.insert(tick, fingerprint);
+        state.hits += 1;
+        Some(hits)
+    }
+
+    /// Caches the hits for the key, evicting the least-recently-used entry if the cache is full.
+    pub fn insert(&self, tree: u64, key: Vec<u8>, hits: Vec<(Index, U)>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = lock_cache(&self.state);
+        state.check_tree(tree);
+
+        let fingerprint = Self::fingerprint(&key);
+        if let Some(old) = state.entries.remove(&fingerprint) {
+            state.recency.remove(&old.last_used);
+            state.memory.shrink(old.size());
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, evicted)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = state.entries.remove(&evicted) {
+                state.memory.shrink(evicted.size());
+            }
+        }
+
+        state.tick += 1;
+        let tick = state.tick;
+        state.recency.insert(tick, fingerprint);
+        let entry = Entry {
+            key,
+            last_used: tick,
+            hits,
+        };
+        state.memory.grow(entry.size());
+        state.entries.insert(fingerprint, entry);
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        lock_cache(&self.state).clear();
+    }
+
+    /// Returns the number of lookups that were, and were not, served from the cache.
+    pub fn counts(&self) -> (u64, u64) {
+        let state = lock_cache(&self.state);
+        (state.hits, state.misses)
+    }
+}
+
+impl<U: Number> State<U> {
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.memory.set(0);
+    }
+
+    fn check_tree(&mut self, tree: u64) {
+        if self.tree != tree {
+            self.clear();
+            self.tree = tree;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::CachedSearch;
+    use super::QueryCache;
+
+    #[test]
+    fn test_eviction() {
+        let cache = QueryCache::<f64>::new(2);
+        let keys: Vec<_> = (0..3)
+            .map(|i| QueryCache::<f64>::key(CachedSearch::Knn, &[1], &[i as f64]))
+            .collect();
+
+        cache.insert(7, keys[0].clone(), vec![(0, 0.)]);
+        cache.insert(7, keys[1].clone(), vec![(1, 0.)]);
+        assert_eq!(cache.get(7, &keys[0]), Some(vec![(0, 0.)]));
+
+        // keys[1] is now the least recently used.
+        cache.insert(7, keys[2].clone(), vec![(2, 0.)]);
+        assert_eq!(cache.get(7, &keys[1]), None);
+        assert_eq!(cache.get(7, &keys[0]), Some(vec![(0, 0.)]));
+        assert_eq!(cache.get(7, &keys[2]), Some(vec![(2, 0.)]));
+        assert_eq!(cache.counts(), (3, 1));
+
+        // A different tree invalidates every entry.
+        assert_eq!(cache.get(8, &keys[0]), None);
+        assert_eq!(cache.get(8, &keys[2]), None);
+    }
+
+    #[test]
+    fn test_cakes_cache() {
+        let data = vec![vec![0., 0.], vec![1., 1.], vec![2., 2.], vec![3., 3.]];
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let mut search = Cakes::build(Arc::clone(&dataset), None, None).with_cache(16);
+
+        let rnn = search.rnn(&[0., 1.], Some(1.5));
+        let knn = search.knn(&[0., 1.], 2);
+        assert_eq!(search.cache_counts(), Some((0, 2)));
+
+        assert_eq!(search.rnn(&[0., 1.], Some(1.5)), rnn);
+        assert_eq!(search.knn(&[0., 1.], 2), knn);
+        assert_eq!(search.knn_seeded(&[0., 1.], 2, &[3, 2]), knn);
+        assert_eq!(search.cache_counts(), Some((3, 2)));
+
+        // Different parameters are different queries.
+        search.rnn(&[0., 1.], Some(1.));
+        assert_eq!(search.cache_counts(), Some((3, 3)));
+
+        // Replacing the tree invalidates the cache.
+        search.root = Cakes::build(Arc::clone(&dataset), Some(1), None).root;
+        assert_eq!(search.rnn(&[0., 1.], Some(1.5)).len(), rnn.len());
+        assert_eq!(search.cache_counts(), Some((3, 4)));
+
+        search.invalidate_cache();
+        search.knn(&[0., 1.], 2);
+        assert_eq!(search.cache_counts(), Some((3, 5)));
+
+        assert_eq!(Cakes::build(dataset, None, None).cache_counts(), None);
+    }
+}
@@ -1,11 +1,57 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::RwLock;
 
 use bitvec::prelude::*;
+use ordered_float::OrderedFloat;
 use rayon::prelude::*;
+use serde::de::DeserializeOwned;
 
+use crate::core::BuildGauge;
+use crate::dataset::InstrumentedDataset;
+use crate::dataset::NormEnforcement;
+use crate::dataset::RowMajor;
+use crate::dataset::StreamingDataset;
+use crate::io::BuildReport;
+use crate::io::DatasetCard;
+use crate::memory::Tracked;
+use crate::metric::AsymmetricMetric;
 use crate::prelude::*;
+use crate::utils::compare_distances;
+use crate::utils::parallelism;
+use crate::utils::read_cache;
+use crate::CenterPolicy;
+use crate::RadiusPolicy;
+
+use super::audit::AuditReport;
+use super::audit::SearchAudit;
+use super::cache::CachedSearch;
+use super::cache::QueryCache;
+use super::leaf_scan::CenterDistances;
+use super::leaf_scan::LeafScan;
+use super::metadata::filter_value;
+use super::metadata::LeafFilter;
+use super::metadata::MetadataIndex;
+use super::trace::Decision;
+use super::trace::SearchTrace;
+use super::trace::SearchTracing;
+use super::trace::TracedSearch;
+use super::trace::Tracer;
+
+/// The depth of the deepest cluster used by `knn_adaptive` to predict a radius.
+const KNN_PREDICTION_DEPTH: usize = 4;
+
+/// `knn_adaptive` searches a little beyond its prediction, since too small a radius costs a whole extra search.
+const KNN_RADIUS_MARGIN: f64 = 1.25;
+
+/// The weight of each search in the learned scale of `knn_adaptive`.
+const KNN_SCALE_RATE: f64 = 0.1;
 
 /// A Vec of Clusters that overlap with the query ball.
 type ClusterHits<T, U> = Vec<Arc<Cluster<T, U>>>;
@@ -25,13 +71,46 @@ type Hits<U> = Vec<(Index, U)>;
 ///
 /// TODO Add Compression and Decompression for the dataset and search tree.
 ///
-/// TODO Add Serde support for storing and loading the search tree.
+/// The search tree can be stored with `save` and loaded with `load`. See the `io` module for the format.
 pub struct Cakes<T: Number, U: Number> {
     /// An Arc to any struct that implements the `Dataset` trait.
     pub dataset: Arc<dyn Dataset<T, U>>,
 
     /// The root Cluster of the search tree.
     pub root: Arc<Cluster<T, U>>,
+
+    /// Telemetry from building the tree.
+    /// This is `None` for trees built in batches or loaded from artifacts without a report.
+    pub report: Option<BuildReport>,
+
+    /// An optional cache of search results. See `with_cache`.
+    cache: Option<QueryCache<U>>,
+
+    /// The bits of the log of the factor by which `knn_adaptive` scales its predicted radii.
+    knn_log_scale: AtomicU64,
+
+    /// An optional audit of the recall of approximate searches. See `with_audit`.
+    audit: Option<Arc<SearchAudit>>,
+
+    /// An optional inverted index from metadata to the leaves that hold it. See `with_metadata_index`.
+    metadata_index: Option<MetadataIndex>,
+
+    /// An optional directory to which the trace of every search is written. See `with_tracing`.
+    tracing: Option<SearchTracing>,
+
+    /// How queries are held to the norm that the metric needs. See `with_norm_enforcement`.
+    norm_enforcement: Option<NormEnforcement>,
+
+    /// The description of the dataset, saved with the tree. See `with_card`.
+    card: Option<DatasetCard>,
+
+    /// The sorted distances from the center of each leaf to its instances, for `LeafScan::SortedByCenterDistance`.
+    /// See `with_leaf_scan`.
+    center_distances: Option<CenterDistances<U>>,
+
+    /// The names of the clusters that gained instances since the tree was built or its statistics were refreshed.
+    /// See `refresh_statistics`.
+    stale: HashSet<BitVec>,
 }
 
 impl<T: Number, U: Number> std::fmt::Debug for Cakes<T, U> {
@@ -54,15 +133,113 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
         max_depth: Option<usize>,
         min_cardinality: Option<usize>,
     ) -> Cakes<T, U> {
+        Cakes::build_with_center_policy(dataset, max_depth, min_cardinality, CenterPolicy::default())
+    }
+
+    /// Builds a search tree, as with `build`, whose cluster centers are chosen by the given policy.
+    pub fn build_with_center_policy(
+        dataset: Arc<dyn Dataset<T, U>>,
+        max_depth: Option<usize>,
+        min_cardinality: Option<usize>,
+        center_policy: CenterPolicy,
+    ) -> Cakes<T, U> {
+        Cakes::build_with_policies(
+            dataset,
+            max_depth,
+            min_cardinality,
+            center_policy,
+            RadiusPolicy::default(),
+        )
+    }
+
+    /// Builds a search tree, as with `build`, whose cluster centers and radii are found by the given policies.
+    ///
+    /// With `RadiusPolicy::SampleSize`, radii may be underestimated and searches may silently miss true hits. Replace
+    /// `root` with `root.tighten_radii(None)` before searches that must be exact.
+    pub fn build_with_policies(
+        dataset: Arc<dyn Dataset<T, U>>,
+        max_depth: Option<usize>,
+        min_cardinality: Option<usize>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+    ) -> Cakes<T, U> {
+        Cakes::build_with_sampling(dataset, max_depth, min_cardinality, center_policy, radius_policy, None)
+    }
+
+    /// Builds a search tree, as with `build_with_policies`, whose samples, e.g. of the instances among which centers
+    /// are chosen, are drawn from generators seeded with the given seed, so that the same dataset and seed give the
+    /// same tree. The seed is recorded in the build report.
+    pub fn build_seeded(
+        dataset: Arc<dyn Dataset<T, U>>,
+        max_depth: Option<usize>,
+        min_cardinality: Option<usize>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+        seed: u64,
+    ) -> Cakes<T, U> {
+        Cakes::build_with_sampling(
+            dataset,
+            max_depth,
+            min_cardinality,
+            center_policy,
+            radius_policy,
+            Some(seed),
+        )
+    }
+
+    fn build_with_sampling(
+        dataset: Arc<dyn Dataset<T, U>>,
+        max_depth: Option<usize>,
+        min_cardinality: Option<usize>,
+        center_policy: CenterPolicy,
+        radius_policy: RadiusPolicy,
+        seed: Option<u64>,
+    ) -> Cakes<T, U> {
+        let start = std::time::Instant::now();
+
         // parse the max-depth and min-cardinality and create the partition-criterion.
+        let (max_depth, min_cardinality) = (max_depth.unwrap_or(50), min_cardinality.unwrap_or(1));
         let criteria = vec![
-            criteria::max_depth(max_depth.unwrap_or(50)),
-            criteria::min_cardinality(min_cardinality.unwrap_or(1)),
+            criteria::max_depth(max_depth),
+            criteria::min_cardinality(min_cardinality),
         ];
-        // build the search tree.
-        let root = Cluster::new_root(Arc::clone(&dataset)).partition(&criteria);
-        // return the struct
-        Cakes { dataset, root }
+        // build the search tree over a dataset that counts distance calls, and then move it onto the real dataset.
+        let counting = Arc::new(InstrumentedDataset::new(Arc::clone(&dataset)));
+        let gauge = BuildGauge::default();
+        let root = Cluster::new_root_seeded(
+            Arc::clone(&counting) as Arc<dyn Dataset<T, U>>,
+            center_policy,
+            radius_policy,
+            seed,
+        );
+        gauge.grow(root.memory_bytes());
+        let root = root.partition_gauged(&criteria, &gauge);
+        let root = crate::io::attach_owned(root, Arc::clone(&dataset));
+
+        let report = BuildReport {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            metric_name: dataset.metric_name(),
+            criteria: {
+                let mut criteria = vec![
+                    format!("max_depth={}", max_depth),
+                    format!("min_cardinality={}", min_cardinality),
+                ];
+                if center_policy != CenterPolicy::default() {
+                    criteria.push(format!("center_policy={:?}", center_policy));
+                }
+                if radius_policy != RadiusPolicy::default() {
+                    criteria.push(format!("radius_policy={:?}", radius_policy));
+                }
+                criteria
+            },
+            seed,
+            wall_time: start.elapsed().as_secs_f64(),
+            distance_calls: counting.stats().distances,
+            tree_size_bytes: tree_memory(&root),
+            peak_memory_bytes: gauge.peak() as u64,
+        };
+
+        Cakes::from_root(root, dataset, Some(report))
     }
 
     /// Builds the tree in batches using the memory-fraction provided.
@@ -100,6 +277,7 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
                         dataset: Arc::clone(&dataset),
                         name: cluster.name.clone(),
                         cardinality: indices.len(),
+                        _memory: Tracked::cluster::<T, U>(indices.len()),
                         indices,
                         argcenter,
                         argradius,
@@ -108,6 +286,9 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
                         children: RwLock::new(None),
                         parent: cluster.parent.clone(),
                         ratios: cluster.ratios,
+                        center_policy: cluster.center_policy,
+                        radius_policy: cluster.radius_policy,
+                        seed: cluster.seed,
                     })
                 })
                 .collect()
@@ -122,175 +303,1093 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
         };
 
         for (i, batch_indices) in complement_indices.chunks(batch_size).enumerate() {
-            println!(
+            log::info!(
                 "Inserting batch {} of {} with {} instances",
                 i + 1,
                 num_batches,
                 batch_indices.len()
             );
             let batch_dataset = dataset.row_major_subset(batch_indices);
+            let batch: Vec<_> = (0..batch_indices.len()).map(|i| batch_dataset.instance(i)).collect();
+            let (root, mut stale) = insert_batch(&cakes.root, &flat_tree, &dataset, &batch, batch_indices);
+            stale.extend(cakes.stale);
+            cakes = Cakes {
+                stale,
+                ..Cakes::from_root(root, Arc::clone(&dataset), None)
+            };
 
-            // Build a sparse matrix of cluster insertions.
-            // | Sequence | Cluster 0                   | Cluster 1  |
-            // | seq_00   | None (Not added to cluster) | Some(dist) |
-            // | seq_01   | Some(dist)                  | None       |
-            let insertion_paths: Vec<Vec<_>> = batch_indices
-                .par_iter()
-                .enumerate()
-                .map(|(i, _)| {
-                    let instance = batch_dataset.instance(i);
-                    let distance = cakes.root.dataset.metric().distance(&cakes.root.center(), &instance);
-                    let insertion_path = cakes.root.add_instance(&instance, distance);
+            flat_tree = cakes.root.flatten_tree();
+            flat_tree.push(Arc::clone(&cakes.root));
+        }
 
-                    flat_tree
-                        .par_iter()
-                        .map(|cluster| {
-                            if insertion_path.contains_key(&cluster.name) {
-                                Some(*insertion_path.get(&cluster.name).unwrap())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect()
+        cakes
+    }
+
+    /// Builds a search tree from a stream of instances, without first collecting every instance into a dataset.
+    ///
+    /// The first `sample_size` instances are partitioned as by `build`. The remaining instances are then read in
+    /// batches of `batch_size` and inserted into the tree, as in `build_in_batches`, growing the radii of the clusters
+    /// along the way but never partitioning them anew. Only the instances themselves are kept, in the order in which
+    /// they arrived, and they become the dataset of the returned tree once the stream ends.
+    ///
+    /// Returns an Err if the stream is empty.
+    pub fn build_from_stream(
+        mut stream: impl StreamingDataset<T, U>,
+        sample_size: usize,
+        batch_size: usize,
+        max_depth: Option<usize>,
+        min_cardinality: Option<usize>,
+    ) -> Result<Self, String> {
+        let metric = stream.metric();
+        let sample: Vec<_> = stream.by_ref().take(sample_size.max(1)).collect();
+        if sample.is_empty() {
+            return Err("Cannot build a tree from an empty stream.".to_string());
+        }
+
+        let sample: Arc<dyn Dataset<T, U>> = Arc::new(RowMajor::new(Arc::new(sample), Arc::clone(&metric), false));
+        let mut root = Cakes::build(Arc::clone(&sample), max_depth, min_cardinality).root;
+        let mut flat_tree = root.flatten_tree();
+        flat_tree.push(Arc::clone(&root));
+
+        // Until the stream ends, the clusters refer to the sample, which holds all of their centers.
+        let mut rest = vec![];
+        let mut stale = HashSet::new();
+        loop {
+            let batch: Vec<_> = stream.by_ref().take(batch_size.max(1)).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let start = sample.cardinality() + rest.len();
+            let batch_indices: Vec<_> = (start..(start + batch.len())).collect();
+            let (new_root, inserted) = insert_batch(&root, &flat_tree, &sample, &batch, &batch_indices);
+            root = new_root;
+            stale.extend(inserted);
+            flat_tree = root.flatten_tree();
+            flat_tree.push(Arc::clone(&root));
+            rest.extend(batch);
+        }
+
+        let mut instances: Vec<_> = sample.indices().into_iter().map(|i| sample.instance(i)).collect();
+        instances.extend(rest);
+        let dataset: Arc<dyn Dataset<T, U>> = Arc::new(RowMajor::new(Arc::new(instances), metric, false));
+        let unstacked_tree = flat_tree
+            .into_iter()
+            .map(|cluster| {
+                Arc::new(Cluster {
+                    dataset: Arc::clone(&dataset),
+                    name: cluster.name.clone(),
+                    cardinality: cluster.cardinality,
+                    _memory: Tracked::cluster::<T, U>(cluster.cardinality),
+                    indices: cluster.indices.clone(),
+                    argcenter: cluster.argcenter,
+                    argradius: cluster.argradius,
+                    radius: cluster.radius,
+                    lfd: cluster.lfd,
+                    children: RwLock::new(None),
+                    parent: cluster.parent.clone(),
+                    ratios: cluster.ratios,
+                    center_policy: cluster.center_policy,
+                    radius_policy: cluster.radius_policy,
+                    seed: cluster.seed,
                 })
-                .collect();
+            })
+            .collect();
 
-            // Reduce the matrix to find the maximum
-            let new_radii: Vec<_> = flat_tree
-                .par_iter()
-                .enumerate()
-                .map(|(i, _)| {
-                    let temp: Vec<_> = insertion_paths
+        Ok(Cakes {
+            stale,
+            ..Cakes::from_root(restack_tree(unstacked_tree), dataset, None)
+        })
+    }
+
+    /// Writes the search tree, its build report and its dataset card to the given path. The dataset is not written.
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        crate::io::save_tree(&self.root, self.report.as_ref(), self.card.as_ref(), path)
+    }
+
+    /// Loads a search tree that was written by `save` and attaches it to the given dataset.
+    ///
+    /// Returns an Err if the tree was saved with a card that names a metric other than that of the dataset.
+    pub fn load(path: &std::path::Path, dataset: Arc<dyn Dataset<T, U>>) -> Result<Self, String> {
+        let handle = crate::io::TreeHandle::from_bytes(&crate::io::read_file(path)?)?;
+        Cakes::attach(&handle, dataset)
+    }
+
+    /// Searches the tree of the given `Manifold`, which is shared rather than copied.
+    pub fn from_manifold(manifold: &Manifold<T, U>) -> Self {
+        Cakes::from_root(Arc::clone(&manifold.root), Arc::clone(&manifold.dataset), None)
+    }
+
+    /// Attaches an already parsed search tree to the given dataset. One `TreeHandle` may be attached, in turn,
+    /// to each of several datasets holding the same instances, e.g. on the replicas of a search service.
+    ///
+    /// Returns an Err if the tree was saved with a card that names a metric other than that of the dataset.
+    pub fn attach(handle: &crate::io::TreeHandle<T, U>, dataset: Arc<dyn Dataset<T, U>>) -> Result<Self, String> {
+        let root = handle.attach(Arc::clone(&dataset))?;
+        let cakes = Cakes::from_root(root, dataset, handle.report().cloned());
+        match handle.card() {
+            Some(card) => cakes.with_card(card.clone()),
+            None => Ok(cakes),
+        }
+    }
+
+    /// Wraps a tree whose clusters already refer to the given dataset, with no cache, audit, index or tracing.
+    pub(crate) fn from_root(
+        root: Arc<Cluster<T, U>>,
+        dataset: Arc<dyn Dataset<T, U>>,
+        report: Option<BuildReport>,
+    ) -> Self {
+        Cakes {
+            root,
+            dataset,
+            report,
+            cache: None,
+            knn_log_scale: AtomicU64::new(0_f64.to_bits()),
+            audit: None,
+            metadata_index: None,
+            tracing: None,
+            norm_enforcement: None,
+            card: None,
+            center_distances: None,
+            stale: HashSet::new(),
+        }
+    }
+
+    /// Enables caching of the results of up to `capacity` distinct searches.
+    ///
+    /// Repeated `rnn` and `knn` searches with identical queries and parameters are then served from the cache,
+    /// which evicts the least recently used results when full. The cache is emptied whenever `root` is replaced.
+    /// Call `invalidate_cache` after changing the tree in any other way, e.g. by replacing the children of a cluster.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(QueryCache::new(capacity));
+        self
+    }
+
+    /// Removes all cached search results.
+    pub fn invalidate_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    /// Returns the numbers of searches that were, and were not, served from the cache, if caching is enabled.
+    pub fn cache_counts(&self) -> Option<(u64, u64)> {
+        self.cache.as_ref().map(|cache| cache.counts())
+    }
+
+    /// Enables audits of a random `fraction` of the approximate searches, i.e. `knn_beam`.
+    ///
+    /// The hits of each audited search are compared with the exact hits from a linear scan of the dataset, on the rayon
+    /// pool, so searches do not wait for their audits. The measured recall is returned by `audit_report`. The same
+    /// `seed` chooses the same searches, given the same sequence of searches.
+    pub fn with_audit(mut self, fraction: f64, seed: u64) -> Self {
+        self.audit = Some(Arc::new(SearchAudit::new(fraction, seed)));
+        self
+    }
+
+    /// Returns the recall measured so far by the audits, if they are enabled.
+    pub fn audit_report(&self) -> Option<AuditReport> {
+        self.audit.as_ref().map(|audit| audit.report())
+    }
+
+    /// Blocks until the audits of earlier searches have finished, e.g. before reading a final `audit_report`.
+    pub fn wait_for_audits(&self) {
+        if let Some(audit) = &self.audit {
+            audit.wait();
+        }
+    }
+
+    /// Writes the trace of every `rnn` and `knn` search to a numbered file in the given directory, e.g.
+    /// `trace-000042.bin`, for offline analysis of pruning. See `trace::SearchTrace` and `io::load_trace`.
+    ///
+    /// This is a debugging mode: the traced searches traverse the tree on one thread and bypass the cache. Traces that
+    /// cannot be written are logged and skipped.
+    pub fn with_tracing(mut self, directory: &std::path::Path) -> Self {
+        self.tracing = Some(SearchTracing::new(directory.to_path_buf()));
+        self
+    }
+
+    /// Verifies or normalizes the queries of every search as the enforcement says, if the metric assumes instances of
+    /// unit length, e.g. `angular`. See `Metric::required_norm`. The asymmetric searches check their queries with
+    /// `AsymmetricMetric::check_query` instead.
+    ///
+    /// With `NormEnforcement::Verify`, the searches that return a Result, e.g. `rnn_filtered`, return an Err for
+    /// queries that violate the norm, and the others log a warning and return no hits. Queries may be checked
+    /// beforehand with `prepare_query`. The instances may be enforced with `RowMajor::enforcing_norm`.
+    pub fn with_norm_enforcement(mut self, enforcement: NormEnforcement) -> Self {
+        self.norm_enforcement = Some(enforcement);
+        self
+    }
+
+    /// Attaches a description of the dataset, which `save` writes with the tree and `io::read_card` reads back.
+    /// Returns an Err if the card names a metric other than that of the dataset.
+    pub fn with_card(mut self, card: DatasetCard) -> Result<Self, String> {
+        card.check_metric(&self.dataset.metric_name())?;
+        self.card = Some(card);
+        Ok(self)
+    }
+
+    /// Returns the description of the dataset, if one was attached or loaded with the tree.
+    pub fn card(&self) -> Option<&DatasetCard> {
+        self.card.as_ref()
+    }
+
+    /// Returns the query as the searches search it, i.e. normalized if the enforcement of `with_norm_enforcement`
+    /// says so, or an Err if it violates the norm that the metric needs and cannot be normalized.
+    pub fn prepare_query<'a>(&self, query: &'a [T]) -> Result<Cow<'a, [T]>, String> {
+        match self.norm_enforcement {
+            Some(enforcement) => enforcement.enforce(self.dataset.metric().as_ref(), query),
+            None => Ok(Cow::Borrowed(query)),
+        }
+    }
+
+    /// Performs the search on the query from `prepare_query`. Since most searches cannot return errors, a query that
+    /// it rejects is logged and gets the `rejected` result instead, e.g. no hits.
+    fn prepared<R>(&self, query: &[T], rejected: impl FnOnce() -> R, search: impl FnOnce(&[T]) -> R) -> R {
+        match self.prepare_query(query) {
+            Ok(query) => search(&query),
+            Err(error) => self.rejected(error, rejected),
+        }
+    }
+
+    fn rejected<R>(&self, error: String, rejected: impl FnOnce() -> R) -> R {
+        log::warn!("The query of a search was rejected. {}", error);
+        rejected()
+    }
+
+    /// Returns the number of clusters that gained instances, e.g. in `build_in_batches`, since the tree was built or
+    /// its statistics were last refreshed. Their LFDs, and the ratios throughout the tree, are stale until then.
+    pub fn num_stale_clusters(&self) -> usize {
+        self.stale.len()
+    }
+
+    /// Recomputes the cardinality, radius and LFD of each cluster that gained instances, and the ratios of every
+    /// cluster, so that the pruning bounds of searches and the ratios scored by CHAODA are up to date. Distance calls
+    /// are only made along the paths of the insertions. Centers and partitions are kept as they were.
+    ///
+    /// This replaces `root`, so the cache is emptied.
+    pub fn refresh_statistics(&mut self) {
+        if self.stale.is_empty() {
+            return;
+        }
+        log::info!("Refreshing the statistics of {} clusters.", self.stale.len());
+        self.root = self.root.refresh_statistics(&self.stale);
+        self.stale.clear();
+        if self.metadata_index.is_some() {
+            self.metadata_index = Some(MetadataIndex::build(&self.root));
+        }
+        if self.center_distances.is_some() {
+            self.center_distances = Some(CenterDistances::build(&self.root));
+        }
+    }
+
+    /// Indexes the metadata of the instances by the leaves that hold them, so that `find_by_metadata`, `rnn_filtered`
+    /// and `knn_filtered` visit only those leaves. See `MetadataIndex`.
+    ///
+    /// The index is of the current tree. If `root` is replaced, other than by `refresh_statistics`, those methods
+    /// scan the metadata of every instance until this is called again.
+    pub fn with_metadata_index(mut self) -> Self {
+        self.metadata_index = Some(MetadataIndex::build(&self.root));
+        self
+    }
+
+    /// Chooses how `knn`, `knn_beam` and `knn_traced` scan the instances of leaves. See `LeafScan`.
+    ///
+    /// `LeafScan::SortedByCenterDistance` computes the distance from the center of every leaf to its instances once,
+    /// here. If `root` is replaced, other than by `refresh_statistics`, the searches scan exhaustively until this is
+    /// called again.
+    pub fn with_leaf_scan(mut self, scan: LeafScan) -> Self {
+        self.center_distances = match scan {
+            LeafScan::Exhaustive => None,
+            LeafScan::SortedByCenterDistance => Some(CenterDistances::build(&self.root)),
+        };
+        self
+    }
+
+    /// Returns how `knn` scans the instances of leaves, as chosen with `with_leaf_scan`.
+    pub fn leaf_scan(&self) -> LeafScan {
+        match self.center_distances {
+            Some(_) => LeafScan::SortedByCenterDistance,
+            None => LeafScan::Exhaustive,
+        }
+    }
+
+    /// Returns the index of the metadata, if it was built with `with_metadata_index`.
+    pub fn metadata_index(&self) -> Option<&MetadataIndex> {
+        self.metadata_index.as_ref()
+    }
+
+    /// Returns the leaves, and the instances in them, that match the filter, from the index if it is current.
+    fn leaf_filter(&self, filter: &serde_json::Value) -> LeafFilter {
+        match &self.metadata_index {
+            Some(index) if index.is_current(&self.root) => index.leaf_filter(filter),
+            _ => LeafFilter::scan(&self.root, filter),
+        }
+    }
+
+    /// Returns the indices of the instances whose metadata matches the filter, in sorted order. See `MetadataIndex`
+    /// for how metadata is matched.
+    ///
+    /// Returns an Err if the filter cannot be serialized as metadata.
+    pub fn find_by_metadata<M: serde::Serialize + ?Sized>(&self, filter: &M) -> Result<Vec<Index>, String> {
+        Ok(self.leaf_filter(&filter_value(filter)?).all_indices())
+    }
+
+    /// Returns the cached hits for the search, or performs the search and caches its hits.
+    fn cached(&self, search: CachedSearch, parameter: &[u8], query: &[T], compute: impl FnOnce() -> Hits<U>) -> Hits<U> {
+        match &self.cache {
+            Some(cache) => {
+                let tree = QueryCache::tree_fingerprint(&self.root);
+                let key = QueryCache::<U>::key(search, parameter, query);
+                cache.get(tree, &key).unwrap_or_else(|| {
+                    let hits = compute();
+                    cache.insert(tree, key, hits.clone());
+                    hits
+                })
+            }
+            None => compute(),
+        }
+    }
+
+    /// Returns the diameter of the search tree, a useful property for judging appropriate search radii.
+    pub fn diameter(&self) -> U {
+        U::from(2).unwrap() * self.root.radius
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        self.dataset.metric().distance(x, y)
+    }
+
+    pub fn rnn_indices(&self, query: &[T], radius: Option<U>) -> Vec<Index> {
+        self.rnn(query, radius).into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// Performs accelerated rho-nearest search on the dataset and
+    /// returns all hits inside a sphere of the given `radius` centered at the requested `query`.
+    pub fn rnn(&self, query: &[T], radius: Option<U>) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| self.rnn_prepared(query, radius))
+    }
+
+    /// Performs `rnn` on a query that was already prepared with `prepare_query`.
+    fn rnn_prepared(&self, query: &[T], radius: Option<U>) -> Hits<U> {
+        if let Some(tracing) = &self.tracing {
+            let (hits, trace) = self._rnn_traced(query, radius);
+            tracing.save(&trace);
+            return hits;
+        }
+        let parameter = radius.unwrap_or_else(U::zero).to_bytes();
+        self.cached(CachedSearch::Rnn, &parameter, query, || self._rnn(query, radius))
+    }
+
+    /// Performs `rnn` and returns each hit with the metadata of its instance as an `M`, if it has any.
+    /// See `RowMajor::with_metadata`.
+    ///
+    /// Returns an Err if the metadata of any hit cannot be deserialized as an `M`.
+    pub fn rnn_with_metadata<M: DeserializeOwned>(
+        &self,
+        query: &[T],
+        radius: Option<U>,
+    ) -> Result<Vec<(Index, U, Option<M>)>, String> {
+        let query = self.prepare_query(query)?;
+        self.dataset.hits_with_metadata(&self.rnn_prepared(&query, radius))
+    }
+
+    /// Performs `rnn` among only the instances whose metadata matches the filter. See `find_by_metadata`.
+    ///
+    /// Clusters that hold no matching instance are pruned before any distance to them is computed.
+    /// The results are not cached.
+    pub fn rnn_filtered<M: serde::Serialize + ?Sized>(
+        &self,
+        query: &[T],
+        radius: Option<U>,
+        filter: &M,
+    ) -> Result<Hits<U>, String> {
+        let query = &*self.prepare_query(query)?;
+        let filter = self.leaf_filter(&filter_value(filter)?);
+        let radius = radius.unwrap_or_else(U::zero);
+        let overlaps = |cluster: &Arc<Cluster<T, U>>| {
+            filter.admits(cluster) && self.query_distance(query, cluster.argcenter) <= radius + cluster.radius
+        };
+
+        let mut frontier = vec![Arc::clone(&self.root)];
+        frontier.retain(overlaps);
+        let mut candidates = vec![];
+        while !frontier.is_empty() {
+            let mut children = vec![];
+            for cluster in frontier {
+                match read_cache(&cluster.children).clone() {
+                    Some((left, right)) => children.extend([left, right]),
+                    None => candidates.extend_from_slice(filter.indices(&cluster.name)),
+                }
+            }
+            frontier = children.into_par_iter().filter(|cluster| overlaps(cluster)).collect();
+        }
+
+        Ok(candidates
+            .into_par_iter()
+            .map(|i| (i, self.query_distance(query, i)))
+            .filter(|&(_, d)| d <= radius)
+            .collect())
+    }
+
+    fn _rnn(&self, query: &[T], radius: Option<U>) -> Hits<U> {
+        self._leaf_search(query, radius, self.traced_tree_search(query, radius, None))
+    }
+
+    /// Performs `rnn` and returns its hits along with its trace. The results are not cached.
+    /// See `trace::SearchTrace`.
+    pub fn rnn_traced(&self, query: &[T], radius: Option<U>) -> (Hits<U>, SearchTrace<T, U>) {
+        let rejected = || {
+            let radius = radius.unwrap_or_else(U::zero);
+            (vec![], Tracer::new().finish(TracedSearch::Rnn { radius }, query))
+        };
+        self.prepared(query, rejected, |query| self._rnn_traced(query, radius))
+    }
+
+    fn _rnn_traced(&self, query: &[T], radius: Option<U>) -> (Hits<U>, SearchTrace<T, U>) {
+        let tracer = Tracer::new();
+        let clusters = self.traced_tree_search(query, radius, Some(&tracer));
+        let radius = radius.unwrap_or_else(U::zero);
+        let mut hits = vec![];
+        for &i in clusters.iter().flat_map(|cluster| cluster.indices.iter()) {
+            let distance = self.query_distance(query, i);
+            tracer.instance(i, distance);
+            if distance <= radius {
+                hits.push((i, distance));
+            }
+        }
+        (hits, tracer.finish(TracedSearch::Rnn { radius }, query))
+    }
+
+    /// Performs rho-nearest search for several radii around the same `query` in one traversal of the tree.
+    /// The hits for `radii[i]` are at position `i`. They are those of `rnn` with that radius, sorted by index.
+    ///
+    /// Each cluster is visited with the radii whose query-balls overlap it, and a radius is dropped from the subtree of
+    /// any cluster that lies entirely outside its ball. The distance from the query to each candidate instance is
+    /// computed only once, however many radii it is a candidate for.
+    pub fn rnn_multi(&self, query: &[T], radii: &[U]) -> Vec<Hits<U>> {
+        self.prepared(
+            query,
+            || vec![vec![]; radii.len()],
+            |query| self._rnn_multi(query, radii),
+        )
+    }
+
+    fn _rnn_multi(&self, query: &[T], radii: &[U]) -> Vec<Hits<U>> {
+        let mut hits = vec![vec![]; radii.len()];
+        let overlapping = |distance: U, radius: U, active: &[usize]| -> Vec<usize> {
+            active
+                .iter()
+                .copied()
+                .filter(|&i| distance <= radii[i] + radius)
+                .collect()
+        };
+
+        let root_distance = self.query_distance(query, self.root.argcenter);
+        let active = overlapping(root_distance, self.root.radius, &(0..radii.len()).collect::<Vec<_>>());
+        let mut stack = vec![(Arc::clone(&self.root), root_distance, active)];
+        while let Some((cluster, distance, active)) = stack.pop() {
+            if active.is_empty() {
+                continue;
+            }
+            // Clusters entirely inside every remaining query-ball need no further pruning.
+            let inside = active.iter().all(|&i| distance + cluster.radius <= radii[i]);
+            match read_cache(&cluster.children).clone() {
+                Some((left, right)) if !inside => {
+                    for child in [left, right] {
+                        let child_distance = self.query_distance(query, child.argcenter);
+                        let child_active = overlapping(child_distance, child.radius, &active);
+                        stack.push((child, child_distance, child_active));
+                    }
+                }
+                _ => {
+                    let distances: Vec<_> = cluster
+                        .indices
                         .par_iter()
-                        .map(|inner| inner[i].unwrap_or_else(U::zero))
+                        .map(|&i| (i, self.query_distance(query, i)))
                         .collect();
-                    crate::utils::argmax(&temp)
+                    for &r in active.iter() {
+                        hits[r].extend(distances.iter().filter(|(_, d)| *d <= radii[r]));
+                    }
+                }
+            }
+        }
+
+        hits.iter_mut().for_each(|hits| hits.sort_by_key(|&(i, _)| i));
+        hits
+    }
+
+    /// Returns the distance from the `query` within which the given `fraction` of the dataset lies, i.e. the distance
+    /// to its `ceil(fraction * cardinality)`-th nearest neighbor, or to its nearest neighbor for non-positive fractions.
+    ///
+    /// Each cluster bounds the distances to its instances between `d(query, center) ± radius`, so the bounds and
+    /// cardinalities of the clusters bracket the answer. Only the clusters whose bounds straddle the bracket are split,
+    /// and the distances to individual instances are computed only in the leaves among them.
+    ///
+    /// A query that `prepare_query` rejects is at the greatest distance that `U` can hold.
+    pub fn percentile_distance(&self, query: &[T], fraction: f64) -> U {
+        self.prepared(
+            query,
+            || U::saturating_from_f64(f64::INFINITY),
+            |query| self._percentile_distance(query, fraction),
+        )
+    }
+
+    fn _percentile_distance(&self, query: &[T], fraction: f64) -> U {
+        let n = self.root.cardinality;
+        let target = ((fraction * n as f64).ceil() as usize).clamp(1, n);
+
+        // Clusters, with the distances from the query to their centers, and instances, with their exact distances.
+        let mut clusters = vec![(Arc::clone(&self.root), self.query_distance(query, self.root.argcenter))];
+        let mut points: Vec<U> = vec![];
+        let bounds = |cluster: &Arc<Cluster<T, U>>, distance: U| {
+            let lower = if distance > cluster.radius {
+                distance - cluster.radius
+            } else {
+                U::zero()
+            };
+            (lower, distance + cluster.radius)
+        };
+        // The least distance at which the intervals, each holding some number of instances, enclose the target.
+        let enclosing = |mut intervals: Vec<(U, usize)>| {
+            intervals.sort_by(|(a, _), (b, _)| compare_distances(a, b));
+            let mut count = 0;
+            intervals
+                .into_iter()
+                .find(|&(_, cardinality)| {
+                    count += cardinality;
+                    count >= target
                 })
+                .unwrap()
+                .0
+        };
+
+        loop {
+            let intervals: Vec<_> = clusters
+                .iter()
+                .map(|(cluster, distance)| bounds(cluster, *distance))
                 .collect();
+            let at_least = |bound: fn(&(U, U)) -> U| {
+                enclosing(
+                    intervals
+                        .iter()
+                        .zip(clusters.iter())
+                        .map(|(interval, (cluster, _))| (bound(interval), cluster.cardinality))
+                        .chain(points.iter().map(|&distance| (distance, 1)))
+                        .collect(),
+                )
+            };
+            let (lower, upper) = (at_least(|&(l, _)| l), at_least(|&(_, u)| u));
+            if lower >= upper {
+                return upper;
+            }
 
-            let insertions: Vec<Vec<usize>> = flat_tree
-                .par_iter()
-                .enumerate()
-                .map(|(i, _)| {
-                    let temp: Vec<Option<usize>> = insertion_paths
-                        .par_iter()
-                        .enumerate()
-                        .map(|(j, inner)| {
-                            let distance = inner[i];
-                            if distance.is_some() {
-                                Some(j)
-                            } else {
-                                None
+            // Split the clusters that straddle the bracket, which hold all of the uncertain instances.
+            let (straddling, settled): (Vec<_>, Vec<_>) = clusters
+                .into_iter()
+                .zip(intervals)
+                .partition(|(_, (l, u))| l < u && *l <= upper && *u >= lower);
+            clusters = settled.into_iter().map(|(cluster, _)| cluster).collect();
+            for ((cluster, _), _) in straddling {
+                let children = read_cache(&cluster.children).clone();
+                match children {
+                    Some((left, right)) => {
+                        for child in [left, right] {
+                            let distance = self.query_distance(query, child.argcenter);
+                            clusters.push((child, distance));
+                        }
+                    }
+                    None => points.extend(cluster.indices.iter().map(|&i| self.query_distance(query, i))),
+                }
+            }
+        }
+    }
+
+    pub fn knn_indices(&self, query: &[T], k: usize) -> Vec<Index> {
+        self.knn(query, k).into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// Performs accelerated k-nearest search on the dataset and returns the `k` hits closest to the `query`,
+    /// sorted by increasing distance. Ties are broken by index.
+    ///
+    /// Clusters are visited best-first, in order of the least distance from the query to any point in them,
+    /// `d(query, center) - radius`. The search stops once that distance exceeds the distance to the k-th nearest hit,
+    /// since no unvisited cluster can then hold a nearer instance.
+    pub fn knn(&self, query: &[T], k: usize) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| self.knn_prepared(query, k))
+    }
+
+    /// Performs `knn` on a query that was already prepared with `prepare_query`.
+    fn knn_prepared(&self, query: &[T], k: usize) -> Hits<U> {
+        if let Some(tracing) = &self.tracing {
+            let (hits, trace) = self._knn_traced(query, k);
+            tracing.save(&trace);
+            return hits;
+        }
+        self.cached(CachedSearch::Knn, &(k as u64).to_be_bytes(), query, || {
+            self._knn(query, k)
+        })
+    }
+
+    /// Performs `knn` and returns each hit with the metadata of its instance as an `M`, if it has any.
+    /// See `RowMajor::with_metadata`.
+    ///
+    /// Returns an Err if the metadata of any hit cannot be deserialized as an `M`.
+    pub fn knn_with_metadata<M: DeserializeOwned>(
+        &self,
+        query: &[T],
+        k: usize,
+    ) -> Result<Vec<(Index, U, Option<M>)>, String> {
+        let query = self.prepare_query(query)?;
+        self.dataset.hits_with_metadata(&self.knn_prepared(&query, k))
+    }
+
+    /// Performs approximate k-nearest search, keeping at most `beam_width` clusters queued at a time.
+    ///
+    /// This is the best-first traversal of `knn` with the clusters farthest from the query dropped from the queue.
+    /// A width of 1 is a greedy descent to a single leaf, and wider beams approach the exact results of `knn`.
+    /// The hits are sorted by increasing distance, with ties broken by index, but there may be fewer than `k`.
+    pub fn knn_beam(&self, query: &[T], k: usize, beam_width: usize) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| {
+            let parameter = [(k as u64).to_be_bytes(), (beam_width as u64).to_be_bytes()].concat();
+            let hits = self.cached(CachedSearch::KnnBeam, &parameter, query, || {
+                self.best_first_knn(query, k, Some(beam_width), None)
+            });
+            if let Some(audit) = &self.audit {
+                audit.knn(&self.dataset, query, k, &hits);
+            }
+            hits
+        })
+    }
+
+    fn _knn(&self, query: &[T], k: usize) -> Hits<U> {
+        self.best_first_knn(query, k, None, None)
+    }
+
+    /// Performs `knn` and returns its hits along with its trace. The results are not cached.
+    /// See `trace::SearchTrace`.
+    pub fn knn_traced(&self, query: &[T], k: usize) -> (Hits<U>, SearchTrace<T, U>) {
+        let rejected = || (vec![], Tracer::new().finish(TracedSearch::Knn { k }, query));
+        self.prepared(query, rejected, |query| self._knn_traced(query, k))
+    }
+
+    fn _knn_traced(&self, query: &[T], k: usize) -> (Hits<U>, SearchTrace<T, U>) {
+        let tracer = Tracer::new();
+        let hits = self.best_first_knn(query, k, None, Some(&tracer));
+        (hits, tracer.finish(TracedSearch::Knn { k }, query))
+    }
+
+    fn best_first_knn(&self, query: &[T], k: usize, beam_width: Option<usize>, trace: Option<&Tracer<U>>) -> Hits<U> {
+        let lower_bound = |cluster: &Cluster<T, U>, to_center: U| to_center.as_f64() - cluster.radius.as_f64();
+        let center_distances = self
+            .center_distances
+            .as_ref()
+            .filter(|distances| distances.is_current(&self.root));
+        self.best_first(
+            k,
+            beam_width,
+            &|i| self.query_distance(query, i),
+            &lower_bound,
+            None,
+            center_distances,
+            trace,
+        )
+    }
+
+    /// Performs `knn` among only the instances whose metadata matches the filter. See `find_by_metadata`.
+    ///
+    /// Clusters that hold no matching instance are never queued. There are fewer than `k` hits if fewer instances
+    /// match. The results are not cached.
+    pub fn knn_filtered<M: serde::Serialize + ?Sized>(
+        &self,
+        query: &[T],
+        k: usize,
+        filter: &M,
+    ) -> Result<Hits<U>, String> {
+        let query = &*self.prepare_query(query)?;
+        let filter = self.leaf_filter(&filter_value(filter)?);
+        let lower_bound = |cluster: &Cluster<T, U>, to_center: U| to_center.as_f64() - cluster.radius.as_f64();
+        Ok(self.best_first(
+            k,
+            None,
+            &|i| self.query_distance(query, i),
+            &lower_bound,
+            Some(&filter),
+            None,
+            None,
+        ))
+    }
+
+    /// The best-first traversal of `knn` and `knn_beam`, with the distance from the query to each instance, and the
+    /// least distance to any instance of a cluster given the distance to its center.
+    ///
+    /// Leaves are scanned in order of the distances from their centers to their instances if those are given, which
+    /// needs the distances to be those of the metric of the tree.
+    #[allow(clippy::too_many_arguments)]
+    fn best_first(
+        &self,
+        k: usize,
+        beam_width: Option<usize>,
+        distance_to: &(dyn Fn(Index) -> U + Sync),
+        lower_bound: &dyn Fn(&Cluster<T, U>, U) -> f64,
+        filter: Option<&LeafFilter>,
+        center_distances: Option<&CenterDistances<U>>,
+        trace: Option<&Tracer<U>>,
+    ) -> Hits<U> {
+        let k = std::cmp::min(k, self.root.cardinality);
+        let beam_width = beam_width.map(|width| std::cmp::max(width, 1));
+        let mut hits: Hits<U> = Vec::with_capacity(k + 1);
+        if k == 0 {
+            return hits;
+        }
+
+        // A min-heap of clusters on the least distance from the query to any point in them, along with the distance
+        // from the query to their centers.
+        let entry = |cluster: Arc<Cluster<T, U>>| {
+            let to_center = distance_to(cluster.argcenter);
+            let distance = lower_bound(&cluster, to_center);
+            (
+                Reverse(OrderedFloat(distance.max(0.))),
+                cluster,
+                OrderedFloat(to_center.as_f64()),
+            )
+        };
+        let admits = |cluster: &Arc<Cluster<T, U>>| filter.is_none_or(|filter| filter.admits(cluster));
+        let record = |cluster: &Cluster<T, U>, distance: f64, decision: Decision| {
+            if let Some(trace) = trace {
+                trace.cluster(cluster, distance, decision);
+            }
+        };
+        let mut queue = BinaryHeap::new();
+        if admits(&self.root) {
+            queue.push(entry(Arc::clone(&self.root)));
+        }
+
+        while let Some((Reverse(OrderedFloat(distance)), cluster, OrderedFloat(to_center))) = queue.pop() {
+            if hits.len() == k && distance > hits[k - 1].1.as_f64() {
+                record(&cluster, distance, Decision::Pruned);
+                break;
+            }
+
+            match read_cache(&cluster.children).clone() {
+                Some((left, right)) => {
+                    record(&cluster, distance, Decision::Entered);
+                    for child in [left, right].into_iter().filter(admits) {
+                        queue.push(entry(child));
+                    }
+                    if let Some(width) = beam_width {
+                        if queue.len() > width {
+                            // The sorted queue ends with the nearest clusters.
+                            let mut sorted = queue.into_sorted_vec();
+                            queue = BinaryHeap::from(sorted.split_off(sorted.len() - width));
+                            for (Reverse(OrderedFloat(distance)), dropped, _) in sorted {
+                                record(&dropped, distance, Decision::Pruned);
+                            }
+                        }
+                    }
+                }
+                None => {
+                    record(&cluster, distance, Decision::Scanned);
+                    if let Some(instances) = center_distances.and_then(|d| d.nearest_first(&cluster, to_center)) {
+                        for (bound, i) in instances {
+                            if hits.len() == k && bound > hits[k - 1].1.as_f64() {
+                                break;
                             }
-                        })
+                            let hit = (i, distance_to(i));
+                            if let Some(trace) = trace {
+                                trace.instance(hit.0, hit.1);
+                            }
+                            insert_hit(&mut hits, hit, k);
+                        }
+                        continue;
+                    }
+                    let indices = filter.map_or(&cluster.indices[..], |filter| filter.indices(&cluster.name));
+                    let distances: Vec<_> = indices
+                        .par_iter()
+                        .with_min_len(parallelism::min_len())
+                        .map(|&i| (i, distance_to(i)))
                         .collect();
-                    temp.into_par_iter()
-                        .filter(|&v| v.is_some())
-                        .map(|v| v.unwrap())
-                        .collect()
-                })
-                .collect();
+                    for hit in distances {
+                        if let Some(trace) = trace {
+                            trace.instance(hit.0, hit.1);
+                        }
+                        insert_hit(&mut hits, hit, k);
+                    }
+                }
+            }
+        }
 
-            let unstacked_tree: Vec<_> = flat_tree
-                .par_iter()
-                .zip(new_radii.into_par_iter())
-                .zip(insertions.into_par_iter())
-                .map(|((cluster, (argradius, radius)), indices)| {
-                    let mut indices: Vec<_> = indices.into_iter().map(|i| batch_indices[i]).collect();
-                    indices.extend(cluster.indices.iter());
-
-                    let (argradius, radius) = if radius > cluster.radius {
-                        (batch_indices[argradius], radius)
-                    } else {
-                        (cluster.argradius, cluster.radius)
-                    };
+        hits
+    }
 
-                    Arc::new(Cluster {
-                        dataset: Arc::clone(&dataset),
-                        name: cluster.name.clone(),
-                        cardinality: indices.len(),
-                        indices,
-                        argcenter: cluster.argcenter,
-                        argradius,
-                        radius,
-                        lfd: cluster.lfd,
-                        children: RwLock::new(None),
-                        parent: cluster.parent.clone(),
-                        ratios: cluster.ratios,
-                    })
-                })
-                .collect();
+    /// Performs k-nearest search, as with `knn`, for a query of a different type than the instances, e.g. a short read
+    /// among long references, with a distance from queries to instances.
+    ///
+    /// Clusters are pruned with the `lower_bound` of the metric, so the results are exact if that bound holds.
+    /// The results are not cached, since the cache is keyed on queries of the type of the instances.
+    pub fn knn_asymmetric<Q: ?Sized + Sync>(
+        &self,
+        metric: &dyn AsymmetricMetric<Q, T, U>,
+        query: &Q,
+        k: usize,
+    ) -> Hits<U> {
+        if let Err(error) = self.check_asymmetric_query(metric, query) {
+            return self.rejected(error, Vec::new);
+        }
+        let distance = |i: Index| metric.distance(query, &self.dataset.instance(i));
+        let lower_bound = |cluster: &Cluster<T, U>, to_center: U| metric.lower_bound(query, cluster, to_center).as_f64();
+        self.best_first(k, None, &distance, &lower_bound, None, None, None)
+    }
+
+    /// Returns the Err of `AsymmetricMetric::check_query` for the enforcement of `with_norm_enforcement`, if any.
+    fn check_asymmetric_query<Q: ?Sized>(
+        &self,
+        metric: &dyn AsymmetricMetric<Q, T, U>,
+        query: &Q,
+    ) -> Result<(), String> {
+        match self.norm_enforcement {
+            Some(enforcement) => metric.check_query(query, enforcement),
+            None => Ok(()),
+        }
+    }
+
+    /// Performs rho-nearest search, as with `rnn`, for a query of a different type than the instances, with a
+    /// distance from queries to instances. See `knn_asymmetric`.
+    pub fn rnn_asymmetric<Q: ?Sized + Sync>(
+        &self,
+        metric: &dyn AsymmetricMetric<Q, T, U>,
+        query: &Q,
+        radius: U,
+    ) -> Hits<U> {
+        if let Err(error) = self.check_asymmetric_query(metric, query) {
+            return self.rejected(error, Vec::new);
+        }
+        let distance = |i: Index| metric.distance(query, &self.dataset.instance(i));
+        let overlaps =
+            |cluster: &Arc<Cluster<T, U>>| metric.lower_bound(query, cluster, distance(cluster.argcenter)) <= radius;
+
+        // The tree is searched one depth at a time, keeping the clusters that may hold hits.
+        let mut frontier = vec![Arc::clone(&self.root)];
+        frontier.retain(overlaps);
+        let mut candidates = vec![];
+        while !frontier.is_empty() {
+            let mut children = vec![];
+            for cluster in frontier {
+                match read_cache(&cluster.children).clone() {
+                    Some((left, right)) => children.extend([left, right]),
+                    None => candidates.extend(cluster.indices.iter().copied()),
+                }
+            }
+            frontier = children.into_par_iter().filter(|cluster| overlaps(cluster)).collect();
+        }
 
-            cakes = Cakes {
-                dataset: Arc::clone(&dataset),
-                root: restack_tree(unstacked_tree),
-            };
+        candidates
+            .into_par_iter()
+            .map(|i| (i, distance(i)))
+            .filter(|&(_, d)| d <= radius)
+            .collect()
+    }
 
-            flat_tree = cakes.root.flatten_tree();
-            flat_tree.push(Arc::clone(&cakes.root));
+    /// Performs accelerated k-nearest search using instances that are expected to be near the `query`,
+    /// e.g. hits from another tree over the same dataset, to bound the search.
+    ///
+    /// The distances from the query to any `k` of the `seeds` bound the distance to the k-th nearest neighbor,
+    /// so a single rho-nearest search with that radius suffices. The results are exact for any seeds,
+    /// but better seeds give a smaller radius and so less traversal.
+    /// Falls back to `knn` if there are fewer than `k` distinct seeds in the dataset.
+    ///
+    /// The results are the same as those of `knn`, so they share entries in the cache.
+    pub fn knn_seeded(&self, query: &[T], k: usize, seeds: &[Index]) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| {
+            self.cached(CachedSearch::Knn, &(k as u64).to_be_bytes(), query, || {
+                self._knn_seeded(query, k, seeds)
+            })
+        })
+    }
+
+    fn _knn_seeded(&self, query: &[T], k: usize, seeds: &[Index]) -> Hits<U> {
+        let k = std::cmp::min(k, self.root.cardinality);
+        if k == 0 {
+            return vec![];
         }
 
-        cakes
+        let mut seeds: Vec<_> = seeds
+            .iter()
+            .copied()
+            .filter(|&i| i < self.dataset.cardinality())
+            .collect();
+        seeds.sort_unstable();
+        seeds.dedup();
+        if seeds.len() < k {
+            return self._knn(query, k);
+        }
+
+        let mut distances: Vec<_> = seeds.par_iter().map(|&i| self.query_distance(query, i)).collect();
+        distances.sort_by(compare_distances);
+
+        let mut hits = self._rnn(query, Some(distances[k - 1]));
+        sort_hits(&mut hits);
+        hits.truncate(k);
+        hits
     }
 
-    /// Returns the diameter of the search tree, a useful property for judging appropriate search radii.
-    pub fn diameter(&self) -> U {
-        U::from(2).unwrap() * self.root.radius
+    /// Performs k-nearest search, seeded with the k-nearest hits from a `guide` tree.
+    ///
+    /// The guide must be built over the same instances, under the same indices, as this tree, but may use a different
+    /// metric or build parameters. This is useful when the guide's metric is much cheaper than this tree's metric,
+    /// or when its tree is much shallower, and the two metrics tend to agree on which instances are near.
+    pub fn knn_guided<V: 'static + Number>(&self, guide: &Cakes<T, V>, query: &[T], k: usize) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| {
+            self.knn_seeded(query, k, &guide.knn_indices(query, k))
+        })
     }
 
-    fn distance(&self, x: &[T], y: &[T]) -> U {
-        self.dataset.metric().distance(x, y)
+    /// Performs accelerated k-nearest search, usually with a single rho-nearest search.
+    ///
+    /// The radius of that search is predicted from the cluster nearest to the query among the first few levels of the
+    /// tree: if its `n` instances spread over `lfd` dimensions, about `k` of them lie within `radius * (k / n)^(1 / lfd)`
+    /// of its center. The prediction is then scaled by a factor learned from the k-th nearest distances of earlier
+    /// searches. If the search finds fewer than `k` hits, the radius is doubled until it does, as in `knn`.
+    ///
+    /// The results are the same as those of `knn`, so they share entries in the cache.
+    pub fn knn_adaptive(&self, query: &[T], k: usize) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| {
+            self.cached(CachedSearch::Knn, &(k as u64).to_be_bytes(), query, || {
+                self._knn_adaptive(query, k)
+            })
+        })
     }
 
-    pub fn rnn_indices(&self, query: &[T], radius: Option<U>) -> Vec<Index> {
-        self.rnn(query, radius).into_iter().map(|(i, _)| i).collect()
+    /// Returns the factor by which `knn_adaptive` currently scales its predicted radii.
+    pub fn knn_radius_scale(&self) -> f64 {
+        f64::from_bits(self.knn_log_scale.load(Ordering::Relaxed)).exp()
     }
 
-    /// Performs accelerated rho-nearest search on the dataset and
-    /// returns all hits inside a sphere of the given `radius` centered at the requested `query`.
-    pub fn rnn(&self, query: &[T], radius: Option<U>) -> Hits<U> {
-        self.leaf_search(query, radius, self.tree_search(query, radius))
+    fn _knn_adaptive(&self, query: &[T], k: usize) -> Hits<U> {
+        let k = std::cmp::min(k, self.root.cardinality);
+        if k == 0 {
+            return vec![];
+        }
+
+        let predicted = self.predict_knn_radius(query, k);
+        let scaled = predicted * self.knn_radius_scale() * KNN_RADIUS_MARGIN;
+        let mut radius = U::from(scaled).unwrap_or_else(U::zero);
+
+        // Every instance lies within this radius of the query.
+        let max_radius = self.root.radius + self.distance(&self.root.center(), query);
+        let mut hits = self._rnn(query, Some(radius));
+        while hits.len() < k && radius < max_radius {
+            radius = if radius == U::zero() { U::one() } else { radius + radius };
+            hits = self._rnn(query, Some(radius));
+        }
+        // The radius may stop growing before it reaches k hits, e.g. when it overflows `U` or when radii were
+        // estimated from samples, so the exact search answers instead.
+        if hits.len() < k {
+            return self._knn(query, k);
+        }
+        sort_hits(&mut hits);
+        hits.truncate(k);
+
+        // Move the learned scale toward the ratio of the true k-th nearest distance to the prediction.
+        let kth = hits[k - 1].1.as_f64();
+        if predicted > 0. && kth > 0. {
+            let log_scale = f64::from_bits(self.knn_log_scale.load(Ordering::Relaxed));
+            let log_scale = log_scale + KNN_SCALE_RATE * ((kth / predicted).ln() - log_scale);
+            self.knn_log_scale.store(log_scale.to_bits(), Ordering::Relaxed);
+        }
+
+        hits
+    }
+
+    /// Predicts the distance from the query to its k-th nearest neighbor, before any learned scaling.
+    fn predict_knn_radius(&self, query: &[T], k: usize) -> f64 {
+        let mut cluster = Arc::clone(&self.root);
+        let mut distance = self.distance(&cluster.center(), query);
+        while cluster.depth() < KNN_PREDICTION_DEPTH {
+            let (left, right) = match read_cache(&cluster.children).clone() {
+                Some(children) => children,
+                None => break,
+            };
+            let (left_distance, right_distance) = (
+                self.distance(&left.center(), query),
+                self.distance(&right.center(), query),
+            );
+            let (child, child_distance) = if left_distance <= right_distance {
+                (left, left_distance)
+            } else {
+                (right, right_distance)
+            };
+            if child.cardinality < k {
+                break;
+            }
+            cluster = child;
+            distance = child_distance;
+        }
+
+        let lfd = if cluster.lfd > 0. { cluster.lfd } else { 1. };
+        let spread = cluster.radius.as_f64() * (k as f64 / cluster.cardinality as f64).powf(1. / lfd);
+        let gap = (distance.as_f64() - cluster.radius.as_f64()).max(0.);
+        gap + spread
     }
 
     /// Performs coarse-grained tree-search to find all clusters that could potentially contain hits.
     pub fn tree_search(&self, query: &[T], radius: Option<U>) -> ClusterHits<T, U> {
+        self.prepared(query, Vec::new, |query| self.traced_tree_search(query, radius, None))
+    }
+
+    fn traced_tree_search(&self, query: &[T], radius: Option<U>, trace: Option<&Tracer<U>>) -> ClusterHits<T, U> {
         // parse the search radius
         let radius = radius.unwrap_or_else(U::zero);
         // if query ball has overlapping volume with the root, delegate to the recursive, private method.
-        if self.distance(&self.root.center(), query) <= (radius + self.root.radius) {
-            self._tree_search(&self.root, query, radius)
+        if self.overlaps(&self.root, self.distance(&self.root.center(), query), radius, trace) {
+            self._tree_search(&self.root, query, radius, trace)
         } else {
             // otherwise, return an empty Vec signifying no possible hits.
             vec![]
         }
     }
 
+    /// Returns whether the query-ball overlaps the cluster, given the distance from the query to its center, and
+    /// records the decision in the trace.
+    fn overlaps(&self, cluster: &Cluster<T, U>, to_center: U, radius: U, trace: Option<&Tracer<U>>) -> bool {
+        let overlaps = to_center <= (radius + cluster.radius);
+        if let Some(trace) = trace {
+            let decision = match (overlaps, read_cache(&cluster.children).is_some()) {
+                (false, _) => Decision::Pruned,
+                (true, true) => Decision::Entered,
+                (true, false) => Decision::Scanned,
+            };
+            trace.cluster(cluster, to_center.as_f64() - cluster.radius.as_f64(), decision);
+        }
+        overlaps
+    }
+
     //noinspection DuplicatedCode
-    fn _tree_search(&self, cluster: &Arc<Cluster<T, U>>, query: &[T], radius: U) -> ClusterHits<T, U> {
+    fn _tree_search(
+        &self,
+        cluster: &Arc<Cluster<T, U>>,
+        query: &[T],
+        radius: U,
+        trace: Option<&Tracer<U>>,
+    ) -> ClusterHits<T, U> {
         // Invariant: Entering this function means that the current cluster has overlapping volume with the query-ball.
         // Invariant: Triangle-inequality guarantees exactness of results from each recursive call.
-        match cluster.children.read().unwrap().clone() {
+        match read_cache(&cluster.children).clone() {
             // There are children. Make recursive calls if necessary.
             Some((left, right)) => {
-                // get the two vectors of hits from up to two recursive calls.
-                let (mut left, mut right) = rayon::join(
-                    || {
-                        // If the child has overlap with the query-ball, recurse into the child
-                        if self.query_distance(query, left.argcenter) <= (radius + left.radius) {
-                            self._tree_search(&left, query, radius)
-                        } else {
-                            // otherwise return an empty vec.
-                            vec![]
-                        }
-                    },
-                    || {
-                        if self.query_distance(query, right.argcenter) <= (radius + right.radius) {
-                            self._tree_search(&right, query, radius)
-                        } else {
-                            vec![]
-                        }
-                    },
-                );
+                let search = |child: &Arc<Cluster<T, U>>| {
+                    // If the child has overlap with the query-ball, recurse into the child
+                    if self.overlaps(child, self.query_distance(query, child.argcenter), radius, trace) {
+                        self._tree_search(child, query, radius, trace)
+                    } else {
+                        // otherwise return an empty vec.
+                        vec![]
+                    }
+                };
+                // get the two vectors of hits from up to two recursive calls, one after the other if they are traced.
+                let (mut left, mut right) = match trace {
+                    Some(_) => (search(&left), search(&right)),
+                    None => parallelism::join(cluster.cardinality, || search(&left), || search(&right)),
+                };
                 // combine both Vectors into one.
                 left.append(&mut right);
                 left
@@ -305,13 +1404,17 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
     /// Exhaustively searches the clusters identified by tree-search and
     /// returns a HashMap of all hits and their distance from the query.
     pub fn leaf_search(&self, query: &[T], radius: Option<U>, clusters: ClusterHits<T, U>) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| self._leaf_search(query, radius, clusters))
+    }
+
+    fn _leaf_search(&self, query: &[T], radius: Option<U>, clusters: ClusterHits<T, U>) -> Hits<U> {
         let indices = clusters
             .iter()
             .map(|c| c.indices.clone())
             .into_iter()
             .flatten()
             .collect();
-        self.linear_search(query, radius, Some(indices))
+        self._linear_search(query, radius, Some(indices))
     }
 
     pub fn linear_search_indices(&self, query: &[T], radius: Option<U>, indices: Option<Vec<Index>>) -> Vec<Index> {
@@ -323,10 +1426,15 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
 
     /// Naive search. Useful for leaf-search and for measuring acceleration from entropy-scaling search.
     pub fn linear_search(&self, query: &[T], radius: Option<U>, indices: Option<Vec<Index>>) -> Hits<U> {
+        self.prepared(query, Vec::new, |query| self._linear_search(query, radius, indices))
+    }
+
+    fn _linear_search(&self, query: &[T], radius: Option<U>, indices: Option<Vec<Index>>) -> Hits<U> {
         let radius = radius.unwrap_or_else(U::zero);
         let indices = indices.unwrap_or_else(|| self.dataset.indices());
         indices
             .par_iter()
+            .with_min_len(parallelism::min_len())
             .map(|&i| (i, self.query_distance(query, i)))
             .filter(|(_, d)| *d <= radius)
             .collect()
@@ -338,6 +1446,30 @@ impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
     }
 }
 
+/// Inserts the hit into the hits, sorted by increasing distance with ties broken by index, if it is among the `k`
+/// nearest.
+pub(crate) fn insert_hit<U: Number>(hits: &mut Hits<U>, hit: (Index, U), k: usize) {
+    let is_before = |other: &(Index, U)| other.1 < hit.1 || (other.1 == hit.1 && other.0 < hit.0);
+    if hits.len() == k && is_before(&hits[k - 1]) {
+        return;
+    }
+    let position = hits.partition_point(is_before);
+    hits.insert(position, hit);
+    hits.truncate(k);
+}
+
+/// Sorts hits by increasing distance, breaking ties by index.
+fn sort_hits<U: Number>(hits: &mut Hits<U>) {
+    hits.sort_by(|(i, a), (j, b)| compare_distances(a, b).then(i.cmp(j)));
+}
+
+/// Returns an estimate of the bytes held by the clusters in the tree.
+fn tree_memory<T: Number, U: Number>(root: &Arc<Cluster<T, U>>) -> u64 {
+    let mut tree = root.flatten_tree();
+    tree.push(Arc::clone(root));
+    tree.par_iter().map(|cluster| cluster.memory_bytes()).sum::<usize>() as u64
+}
+
 fn child_names<T: Number, U: Number>(cluster: &Arc<Cluster<T, U>>) -> (BitVec, BitVec) {
     let mut left_name = cluster.name.clone();
     left_name.push(false);
@@ -348,11 +1480,124 @@ fn child_names<T: Number, U: Number>(cluster: &Arc<Cluster<T, U>>) -> (BitVec, B
     (left_name, right_name)
 }
 
+/// Inserts the instances of the batch, which have the given indices in `dataset`, into the tree and returns the root of
+/// the new tree, whose clusters refer to `dataset`, along with the names of the clusters that gained instances.
+///
+/// `flat_tree` holds every cluster of the tree under `root`, which may refer to another dataset with the same centers.
+fn insert_batch<T: Number, U: Number>(
+    root: &Arc<Cluster<T, U>>,
+    flat_tree: &[Arc<Cluster<T, U>>],
+    dataset: &Arc<dyn Dataset<T, U>>,
+    batch: &[Vec<T>],
+    batch_indices: &[Index],
+) -> (Arc<Cluster<T, U>>, HashSet<BitVec>) {
+    // Build a sparse matrix of cluster insertions.
+    // | Sequence | Cluster 0                   | Cluster 1  |
+    // | seq_00   | None (Not added to cluster) | Some(dist) |
+    // | seq_01   | Some(dist)                  | None       |
+    let insertion_paths: Vec<Vec<_>> = batch_indices
+        .par_iter()
+        .enumerate()
+        .map(|(i, _)| {
+            let instance = &batch[i];
+            let distance = root.dataset.metric().distance(&root.center(), instance);
+            let insertion_path = root.add_instance(instance, distance);
+
+            flat_tree
+                .par_iter()
+                .map(|cluster| insertion_path.get(&cluster.name).copied())
+                .collect()
+        })
+        .collect();
+
+    // Reduce the matrix to find the maximum
+    let new_radii: Vec<_> = flat_tree
+        .par_iter()
+        .enumerate()
+        .map(|(i, _)| {
+            let temp: Vec<_> = insertion_paths
+                .par_iter()
+                .map(|inner| inner[i].unwrap_or_else(U::zero))
+                .collect();
+            crate::utils::argmax(&temp)
+        })
+        .collect();
+
+    let insertions: Vec<Vec<usize>> = flat_tree
+        .par_iter()
+        .enumerate()
+        .map(|(i, _)| {
+            let temp: Vec<Option<usize>> = insertion_paths
+                .par_iter()
+                .enumerate()
+                .map(|(j, inner)| {
+                    let distance = inner[i];
+                    if distance.is_some() {
+                        Some(j)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            temp.into_par_iter().flatten().collect()
+        })
+        .collect();
+
+    let stale = flat_tree
+        .iter()
+        .zip(insertions.iter())
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(cluster, _)| cluster.name.clone())
+        .collect();
+
+    let unstacked_tree: Vec<_> = flat_tree
+        .par_iter()
+        .zip(new_radii.into_par_iter())
+        .zip(insertions.into_par_iter())
+        .map(|((cluster, (argradius, radius)), indices)| {
+            let mut indices: Vec<_> = indices.into_iter().map(|i| batch_indices[i]).collect();
+            indices.extend(cluster.indices.iter());
+
+            let (argradius, radius) = if radius > cluster.radius {
+                (batch_indices[argradius], radius)
+            } else {
+                (cluster.argradius, cluster.radius)
+            };
+
+            Arc::new(Cluster {
+                dataset: Arc::clone(dataset),
+                name: cluster.name.clone(),
+                cardinality: indices.len(),
+                _memory: Tracked::cluster::<T, U>(indices.len()),
+                indices,
+                argcenter: cluster.argcenter,
+                argradius,
+                radius,
+                lfd: cluster.lfd,
+                children: RwLock::new(None),
+                parent: cluster.parent.clone(),
+                ratios: cluster.ratios,
+                center_policy: cluster.center_policy,
+                radius_policy: cluster.radius_policy,
+                seed: cluster.seed,
+            })
+        })
+        .collect();
+
+    (restack_tree(unstacked_tree), stale)
+}
+
 /// Given an unstacked tree as a HashMap of Clusters, rebuild all
 /// parent-child relationships and return the root cluster.
 /// This consumed the given HashMap.
+///
+/// The tree must hold the root and both children of every cluster that was partitioned.
 pub fn restack_tree<T: Number, U: Number>(tree: Vec<Arc<Cluster<T, U>>>) -> Arc<Cluster<T, U>> {
-    let depth = tree.par_iter().map(|c| c.depth()).max().unwrap();
+    let depth = tree
+        .par_iter()
+        .map(|c| c.depth())
+        .max()
+        .expect("the tree holds its root");
     let mut tree: HashMap<_, _> = tree.into_par_iter().map(|c| (c.name.clone(), c)).collect();
 
     for d in (0..depth).rev() {
@@ -367,8 +1612,9 @@ pub fn restack_tree<T: Number, U: Number>(tree: Vec<Arc<Cluster<T, U>>>) -> Arc<
                 let (left_name, right_name) = child_names(cluster);
 
                 let (children, indices) = if leaves.contains_key(&left_name) {
-                    let left = Arc::clone(leaves.get(&left_name).unwrap());
-                    let right = Arc::clone(leaves.get(&right_name).unwrap());
+                    // Partitions always make two children, so the right child is present along with the left.
+                    let left = Arc::clone(leaves.get(&left_name).expect("checked above"));
+                    let right = Arc::clone(leaves.get(&right_name).expect("partitions make both children"));
 
                     let mut indices = left.indices.clone();
                     indices.append(&mut right.indices.clone());
@@ -382,6 +1628,7 @@ pub fn restack_tree<T: Number, U: Number>(tree: Vec<Arc<Cluster<T, U>>>) -> Arc<
                     dataset: Arc::clone(&cluster.dataset),
                     name: cluster.name.clone(),
                     cardinality: indices.len(),
+                    _memory: Tracked::cluster::<T, U>(indices.len()),
                     indices,
                     children: RwLock::new(children),
                     argcenter: cluster.argcenter,
@@ -390,6 +1637,9 @@ pub fn restack_tree<T: Number, U: Number>(tree: Vec<Arc<Cluster<T, U>>>) -> Arc<
                     lfd: cluster.lfd,
                     parent: cluster.parent.clone(),
                     ratios: cluster.ratios,
+                    center_policy: cluster.center_policy,
+                    radius_policy: cluster.radius_policy,
+                    seed: cluster.seed,
                 });
 
                 (cluster.name.clone(), cluster)
@@ -404,16 +1654,21 @@ pub fn restack_tree<T: Number, U: Number>(tree: Vec<Arc<Cluster<T, U>>>) -> Arc<
     }
 
     assert_eq!(1, tree.len());
-    Arc::clone(tree.get(&bitvec![1]).unwrap())
+    Arc::clone(tree.get(&bitvec![1]).expect("the only cluster left is the root"))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
 
+    use crate::dataset::InstanceStream;
+    use crate::dataset::NormEnforcement;
     use crate::dataset::RowMajor;
     use crate::prelude::*;
     use crate::utils::readers::read_test_data;
+    use crate::CenterPolicy;
+    use crate::RadiusPolicy;
 
     use super::Cakes;
 
@@ -441,6 +1696,505 @@ mod tests {
         assert!(!results.contains(&3));
     }
 
+    #[test]
+    fn test_knn() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            let mut naive = search.linear_search(&query, Some(search.diameter()), None);
+            naive.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap().then(i.cmp(j)));
+
+            for k in [1, 10, 100] {
+                let hits = search.knn(&query, k);
+                assert_eq!(hits.len(), k);
+                let distances: Vec<_> = hits.iter().map(|(_, d)| *d).collect();
+                let expected: Vec<_> = naive[..k].iter().map(|(_, d)| *d).collect();
+                assert_eq!(distances, expected);
+            }
+        }
+
+        assert_eq!(
+            search.knn(&dataset.instance(0), dataset.cardinality() + 5).len(),
+            dataset.cardinality()
+        );
+        assert!(search.knn(&dataset.instance(0), 0).is_empty());
+    }
+
+    #[test]
+    fn test_norm_enforcement() {
+        let data: Vec<Vec<f64>> = (0..300)
+            .map(|i| (0..4).map(|j| ((i * 7 + j * 13) % 17) as f64 - 8.).collect())
+            .collect();
+        let metric = metric_from_name::<f64, f64>("angular").unwrap();
+        let raw = RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false);
+        let error = raw.enforcing_norm(NormEnforcement::Verify).unwrap_err();
+        assert!(
+            error.starts_with("Instance 0: The instance has a length of"),
+            "{}",
+            error
+        );
+
+        // Normalized instances pass verification, and normalized queries find the same hits as unit ones.
+        let raw = RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false);
+        let normalized = raw.enforcing_norm(NormEnforcement::Normalize).unwrap();
+        let unit = normalized.transform_query(&data[5]);
+        let normalized = normalized.enforcing_norm(NormEnforcement::Verify).unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(normalized);
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let expected = cakes.knn(&unit, 10);
+        assert_eq!(expected[0].0, 5);
+        assert_ne!(cakes.knn(&data[5], 10), expected);
+
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None).with_norm_enforcement(NormEnforcement::Normalize);
+        assert_eq!(cakes.knn(&data[5], 10), expected);
+        assert_eq!(cakes.rnn(&data[5], Some(0.5)), cakes.rnn(&unit, Some(0.5)));
+        assert_eq!(cakes.knn_seeded(&data[5], 10, &[1, 2, 3]), expected);
+        assert_eq!(
+            cakes.linear_search(&data[5], Some(0.5), None),
+            cakes.linear_search(&unit, Some(0.5), None)
+        );
+
+        let cakes = Cakes::build(dataset, None, None).with_norm_enforcement(NormEnforcement::Verify);
+        assert!(cakes.prepare_query(&unit).is_ok());
+        assert!(cakes.prepare_query(&data[5]).is_err());
+        assert!(cakes.knn(&data[5], 10).is_empty());
+        assert!(cakes.knn_beam(&data[5], 10, 4).is_empty());
+        assert!(cakes.rnn_multi(&data[5], &[0.5, 1.]).iter().all(Vec::is_empty));
+        assert!(cakes.rnn_filtered(&data[5], Some(0.5), &serde_json::json!({})).is_err());
+        assert!(cakes.knn_with_metadata::<serde_json::Value>(&data[5], 10).is_err());
+        assert_eq!(cakes.percentile_distance(&data[5], 0.5), f64::MAX);
+        assert_eq!(cakes.knn_adaptive(&unit, 10), expected);
+
+        // Metrics that need no norm are left alone.
+        let euclidean = RowMajor::new(
+            Arc::new(data),
+            metric_from_name::<f64, f64>("euclidean").unwrap(),
+            false,
+        );
+        assert!(euclidean.enforcing_norm(NormEnforcement::Verify).is_ok());
+    }
+
+    #[test]
+    fn test_knn_beam() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            for k in [1, 10, 100] {
+                let exact = search.knn(&query, k);
+                assert_eq!(search.knn_beam(&query, k, dataset.cardinality()), exact);
+
+                // Narrow beams miss some neighbors, but never find any nearer than the exact ones.
+                for width in [1, 4] {
+                    let hits = search.knn_beam(&query, k, width);
+                    assert!(!hits.is_empty() && hits.len() <= k);
+                    assert!(hits.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+                    for ((_, approximate), (_, exact)) in hits.iter().zip(exact.iter()) {
+                        assert!(approximate >= exact);
+                    }
+                }
+            }
+        }
+        assert!(search.knn_beam(&dataset.instance(0), 0, 4).is_empty());
+    }
+
+    #[test]
+    fn test_asymmetric_search() {
+        use rand::Rng;
+        use rand::SeedableRng;
+
+        use crate::metric::AsymmetricMetric;
+        use crate::metric::WindowHamming;
+
+        // Reads of 12 bases, each with a substitution, searched among references of 40 bases.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let references: Vec<Vec<u8>> = (0..300)
+            .map(|_| (0..40).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+        let reads: Vec<Vec<u8>> = (0..10)
+            .map(|i| {
+                let start = rng.gen_range(0..28);
+                let mut read = references[i * 29][start..start + 12].to_vec();
+                read[rng.gen_range(0..12)] = b'T';
+                read
+            })
+            .collect();
+        let dataset = RowMajor::new(
+            Arc::new(references.clone()),
+            metric_from_name("hamming").unwrap(),
+            false,
+        );
+        let cakes = Cakes::<u8, u64>::build(Arc::new(dataset).as_arc_dataset(), None, None);
+
+        let metric: &dyn AsymmetricMetric<[u8], u8, u64> = &WindowHamming;
+        for (i, read) in reads.iter().enumerate() {
+            let mut exact: Vec<_> = references
+                .iter()
+                .enumerate()
+                .map(|(j, reference)| (j, metric.distance(read.as_slice(), reference)))
+                .collect();
+
+            let mut hits = cakes.rnn_asymmetric(metric, read.as_slice(), 3);
+            hits.sort_unstable();
+            let within: Vec<_> = exact.iter().copied().filter(|&(_, d)| d <= 3).collect();
+            assert_eq!(hits, within);
+            assert!(hits.iter().any(|&(j, d)| j == i * 29 && d <= 1));
+
+            exact.sort_by_key(|&(j, d)| (d, j));
+            assert_eq!(cakes.knn_asymmetric(metric, read.as_slice(), 5), exact[..5].to_vec());
+        }
+    }
+
+    #[test]
+    fn test_containment_search() {
+        use rand::Rng;
+        use rand::SeedableRng;
+
+        use crate::metric::AsymmetricMetric;
+        use crate::metric::SemiGlobalEdit;
+
+        // Reads of 16 bases, each with two edits, searched within references of 50 to 80 bases.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let references: Vec<Vec<u8>> = (0..200)
+            .map(|_| {
+                let length = rng.gen_range(50..80);
+                (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect()
+            })
+            .collect();
+        let reads: Vec<Vec<u8>> = (0..8)
+            .map(|i| {
+                let reference = &references[i * 23];
+                let start = rng.gen_range(0..reference.len() - 16);
+                let mut read = reference[start..start + 16].to_vec();
+                read.remove(rng.gen_range(0..16));
+                read.insert(rng.gen_range(0..15), b'G');
+                read[rng.gen_range(0..16)] = b'T';
+                read
+            })
+            .collect();
+        let dataset = RowMajor::new(
+            Arc::new(references.clone()),
+            metric_from_name("levenshtein").unwrap(),
+            false,
+        );
+        let cakes = Cakes::<u8, u64>::build(Arc::new(dataset).as_arc_dataset(), None, Some(4));
+
+        let sketched = SemiGlobalEdit::with_sketches(&cakes.root, 4);
+        assert_eq!(sketched.num_sketches(), cakes.root.num_descendants() + 1);
+        for metric in [&SemiGlobalEdit::default(), &sketched] {
+            let metric: &dyn AsymmetricMetric<[u8], u8, u64> = metric;
+            for (i, read) in reads.iter().enumerate() {
+                let mut exact: Vec<_> = references
+                    .iter()
+                    .enumerate()
+                    .map(|(j, reference)| (j, metric.distance(read.as_slice(), reference)))
+                    .collect();
+
+                let mut hits = cakes.rnn_asymmetric(metric, read.as_slice(), 3);
+                hits.sort_unstable();
+                let within: Vec<_> = exact.iter().copied().filter(|&(_, d)| d <= 3).collect();
+                assert_eq!(hits, within);
+                assert!(hits.iter().any(|&(j, _)| j == i * 23));
+
+                exact.sort_by_key(|&(j, d)| (d, j));
+                assert_eq!(cakes.knn_asymmetric(metric, read.as_slice(), 3), exact[..3].to_vec());
+            }
+        }
+
+        // The sketches bound some clusters more tightly than their radii do.
+        let read = reads[0].as_slice();
+        let tighter = cakes
+            .root
+            .flatten_tree()
+            .iter()
+            .filter(|cluster| {
+                let to_center = AsymmetricMetric::<[u8], u8, u64>::distance(&sketched, read, &cluster.center());
+                sketched.lower_bound(read, cluster, to_center)
+                    > SemiGlobalEdit::default().lower_bound(read, cluster, to_center)
+            })
+            .count();
+        assert!(tighter > 0);
+    }
+
+    #[test]
+    fn test_filtered_search() {
+        let data: Vec<_> = (0..300).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let metadata: Vec<_> = (0..300)
+            .map(|i| serde_json::json!({"label": i % 4, "even": i % 2 == 0}))
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = RowMajor::<f64, f64>::new(Arc::new(data.clone()), metric, false)
+            .with_metadata(&metadata)
+            .unwrap();
+        let dataset = Arc::new(dataset).as_arc_dataset();
+        let scanned = Cakes::build(Arc::clone(&dataset), None, None);
+        let indexed = Cakes::build(Arc::clone(&dataset), None, None).with_metadata_index();
+        assert!(scanned.metadata_index().is_none());
+        assert_eq!(indexed.metadata_index().unwrap().num_terms(), 6);
+
+        let threes: Vec<_> = (0..300).filter(|i| i % 4 == 3).collect();
+        let filter = serde_json::json!({"label": 3});
+        assert_eq!(indexed.find_by_metadata(&filter).unwrap(), threes);
+        assert_eq!(scanned.find_by_metadata(&filter).unwrap(), threes);
+        let both = serde_json::json!({"label": 3, "even": true});
+        assert!(indexed.find_by_metadata(&both).unwrap().is_empty());
+        assert!(indexed.find_by_metadata("three").unwrap().is_empty());
+
+        let leaves = indexed.metadata_index().unwrap().leaves(&filter);
+        assert!(!leaves.is_empty() && leaves.len() <= threes.len());
+
+        for q in [0, 57, 123] {
+            let query = &data[q];
+            let mut exact: Vec<_> = threes
+                .iter()
+                .map(|&i| (i, scanned.dataset.metric().distance(query, &data[i])))
+                .collect();
+            exact.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap().then(i.cmp(j)));
+
+            for cakes in [&scanned, &indexed] {
+                let hits = cakes.knn_filtered(query, 5, &filter).unwrap();
+                assert_eq!(hits, exact[..5].to_vec());
+
+                let mut hits = cakes.rnn_filtered(query, Some(15.), &filter).unwrap();
+                hits.sort_by_key(|&(i, _)| i);
+                let mut within: Vec<_> = exact.iter().copied().filter(|&(_, d)| d <= 15.).collect();
+                within.sort_by_key(|&(i, _)| i);
+                assert_eq!(hits, within);
+            }
+        }
+        assert_eq!(
+            indexed.knn_filtered(&data[0], 500, &filter).unwrap().len(),
+            threes.len()
+        );
+    }
+
+    #[test]
+    fn test_rnn_multi() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+
+        let radii = [0., 0.05, 0.25, 0.5, 0.1, search.diameter()];
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            let multi = search.rnn_multi(&query, &radii);
+            assert_eq!(multi.len(), radii.len());
+            for (&radius, hits) in radii.iter().zip(multi.iter()) {
+                let mut expected = search.rnn(&query, Some(radius));
+                expected.sort_by_key(|&(i, _)| i);
+                assert_eq!(hits, &expected);
+            }
+            assert_eq!(multi.last().unwrap().len(), dataset.cardinality());
+        }
+        assert!(search.rnn_multi(&dataset.instance(0), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_percentile_distance() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+        let n = dataset.cardinality();
+
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            let mut distances = search.linear_search(&query, Some(search.diameter()), None);
+            distances.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+            for fraction in [0.001, 0.01, 0.1, 0.5, 0.9, 1.] {
+                let k = (fraction * n as f64).ceil() as usize;
+                assert_eq!(search.percentile_distance(&query, fraction), distances[k - 1].1);
+            }
+            assert_eq!(search.percentile_distance(&query, 0.), 0.);
+            assert_eq!(search.percentile_distance(&query, 2.), distances[n - 1].1);
+        }
+    }
+
+    #[test]
+    fn test_build_from_stream() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let stream = InstanceStream::new(data.clone(), Arc::clone(&metric));
+        let search = Cakes::build_from_stream(stream, 200, 500, Some(50), None).unwrap();
+        assert_eq!(search.dataset.cardinality(), data.len());
+        assert_eq!(search.root.cardinality, data.len());
+        assert_eq!(search.dataset.instance(data.len() - 1), data[data.len() - 1]);
+        assert!(search.root.flatten_tree().iter().all(|cluster| cluster.depth() <= 50));
+
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            for radius in [0.05, 0.25] {
+                let mut expected = search.linear_search_indices(&query, Some(radius), None);
+                let mut actual = search.rnn_indices(&query, Some(radius));
+                expected.sort_unstable();
+                actual.sort_unstable();
+                assert_eq!(actual, expected);
+            }
+        }
+
+        let empty = InstanceStream::new(vec![], metric_from_name("euclidean").unwrap());
+        assert!(Cakes::<f64, f64>::build_from_stream(empty, 10, 10, None, None).is_err());
+    }
+
+    #[test]
+    fn test_refresh_statistics() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let stream = InstanceStream::new(data.clone(), metric);
+        let mut search = Cakes::<f64, f64>::build_from_stream(stream, 200, 500, Some(10), None).unwrap();
+        let stale = search.num_stale_clusters();
+        assert!(stale > 0 && stale <= search.root.num_descendants() + 1);
+        let before = search.knn(&data[7], 10);
+        let mut lfds: HashMap<_, _> = search
+            .root
+            .flatten_tree()
+            .iter()
+            .map(|c| (c.name.clone(), c.lfd))
+            .collect();
+        lfds.insert(search.root.name.clone(), search.root.lfd);
+
+        search.refresh_statistics();
+        assert_eq!(search.num_stale_clusters(), 0);
+        let mut tree = search.root.flatten_tree();
+        tree.push(Arc::clone(&search.root));
+        for cluster in tree.iter() {
+            assert_eq!(cluster.cardinality, cluster.indices.len());
+            let distances = search.dataset.distances_from(cluster.argcenter, &cluster.indices);
+            assert!(distances.iter().all(|&d| d <= cluster.radius));
+            if let Some(parent) = &cluster.parent {
+                assert!(cluster.name.starts_with(&parent.upgrade().unwrap().name));
+            }
+        }
+
+        // Insertions change the LFDs, but not the hits.
+        let changed = tree.iter().filter(|cluster| lfds[&cluster.name] != cluster.lfd).count();
+        assert!(changed > 0 && changed <= stale);
+        assert_eq!(search.knn(&data[7], 10), before);
+    }
+
+    #[test]
+    fn test_knn_guided() {
+        let (data, _) = read_test_data();
+        let data = Arc::new(data);
+        let euclidean = metric_from_name("euclidean").unwrap();
+        let manhattan = metric_from_name("manhattan").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::clone(&data), euclidean, false));
+        let guide: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(data, manhattan, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+        let guide = Cakes::build(guide, Some(10), None);
+
+        for &q in dataset.indices()[0..10].iter() {
+            let query = dataset.instance(q);
+            for k in [1, 10, 100] {
+                let expected = search.knn(&query, k);
+                assert_eq!(search.knn_guided(&guide, &query, k), expected);
+
+                // Bad seeds give exact results too.
+                let seeds: Vec<_> = dataset.indices().into_iter().rev().take(k).collect();
+                assert_eq!(search.knn_seeded(&query, k, &seeds), expected);
+                assert_eq!(search.knn_seeded(&query, k, &[]), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_knn_adaptive() {
+        let (data, _) = read_test_data();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<_, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(50), None);
+        assert_eq!(search.knn_radius_scale(), 1.);
+
+        for &q in dataset.indices()[0..30].iter() {
+            let query = dataset.instance(q);
+            for k in [1, 10, 100] {
+                assert_eq!(search.knn_adaptive(&query, k), search.knn(&query, k));
+            }
+        }
+        assert_ne!(search.knn_radius_scale(), 1.);
+
+        assert_eq!(
+            search
+                .knn_adaptive(&dataset.instance(0), dataset.cardinality() + 5)
+                .len(),
+            dataset.cardinality()
+        );
+        assert!(search.knn_adaptive(&dataset.instance(0), 0).is_empty());
+
+        // Duplicated instances have a zero radius but still need to grow.
+        let data = vec![vec![0., 0.]; 5].into_iter().chain(vec![vec![1., 1.]; 5]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(dataset, None, None);
+        assert_eq!(search.knn_adaptive(&[0., 0.], 7), search.knn(&[0., 0.], 7));
+
+        // Sampled radii may keep the growing radius from ever covering k hits.
+        let data = (0..64).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build_with_policies(dataset, None, None, CenterPolicy::Exact, RadiusPolicy::SampleSize(1));
+        assert_eq!(search.knn_adaptive(&[0.], 64).len(), 64);
+    }
+
+    #[test]
+    fn test_build_report() {
+        let data = vec![vec![0., 0.], vec![1., 1.], vec![2., 2.], vec![3., 3.]];
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), Some(5), None);
+
+        let report = search.report.clone().unwrap();
+        assert_eq!(report.metric_name, "euclidean");
+        assert_eq!(report.criteria, vec!["max_depth=5", "min_cardinality=1"]);
+        assert!(report.distance_calls > 0);
+        assert!(report.tree_size_bytes > 0);
+        assert!(report.peak_memory_bytes >= report.tree_size_bytes);
+        assert!(Arc::ptr_eq(&search.root.dataset, &dataset));
+
+        let path = std::env::temp_dir().join(format!("clam-test-build-report-{}.tree", std::process::id()));
+        search.save(&path).unwrap();
+        assert_eq!(crate::io::read_report(&path).unwrap(), Some(report.clone()));
+        let loaded = Cakes::load(&path, Arc::clone(&dataset)).unwrap();
+        assert_eq!(loaded.report, Some(report));
+        assert_eq!(loaded.rnn_indices(&[0., 1.], Some(1.5)).len(), 2);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(search.report.unwrap().seed, None);
+
+        // Seeded builds sample the same instances, so they build the same tree, and record the seed.
+        let data: Vec<_> = (0..500).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let build = |seed| {
+            Cakes::build_seeded(
+                Arc::clone(&dataset),
+                None,
+                None,
+                CenterPolicy::Sampled,
+                RadiusPolicy::SampleSize(20),
+                seed,
+            )
+        };
+        let (first, second) = (build(7), build(7));
+        let tree_bytes = |search: &Cakes<f64, f64>| crate::io::tree_to_bytes(&search.root, None, None);
+        assert_eq!(tree_bytes(&first), tree_bytes(&second));
+        assert_eq!(first.report.as_ref().unwrap().seed, Some(7));
+        assert_eq!(first.root.seed, Some(7));
+        assert!((0..5).any(|seed| tree_bytes(&build(seed)) != tree_bytes(&first)));
+
+        first.save(&path).unwrap();
+        assert_eq!(Cakes::load(&path, Arc::clone(&dataset)).unwrap().root.seed, Some(7));
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_search_large() {
         let (data, _) = read_test_data();
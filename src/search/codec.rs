@@ -1,12 +1,25 @@
 //! Implements Compression and Decompression for `Datasets` and `Clusters`.
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
 
 use bitvec::prelude::*;
 use rayon::prelude::*;
 
+use crate::dataset::MmapDataset;
+use crate::dataset::Provenance;
 use crate::dataset::RowMajor;
+use crate::io::DatasetCard;
+use crate::memory::Subsystem;
+use crate::memory::Tracked;
+use crate::utils::compare_distances;
+use crate::utils::lock_cache;
+use crate::utils::parallelism;
+use crate::utils::read_cache;
+use crate::utils::write_cache;
 use crate::{prelude::*, Cakes};
 
 /// A `Dataset` that also allows for compression and decompression.
@@ -33,39 +46,168 @@ impl<T: 'static + Number, U: 'static + Number> RowMajor<T, U> {
     }
 }
 
+impl<T: Number, U: Number> CompressibleDataset<T, U> for MmapDataset<T, U> {}
+
+impl<T: 'static + Number, U: 'static + Number> MmapDataset<T, U> {
+    pub fn as_arc_compressible_dataset(self: Arc<Self>) -> Arc<dyn CompressibleDataset<T, U>> {
+        self
+    }
+}
+
+/// Shared, read-only bytes, e.g. a buffer read from a file or a memory-mapped file.
+pub type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// The encoded instances of a cluster, stored contiguously as length-prefixed byte strings.
+///
+/// A block is a range in some `SharedBytes`, so the blocks of every cluster loaded from one artifact share its
+/// bytes instead of copying them.
+#[derive(Clone)]
+pub struct EncodingBlock {
+    bytes: SharedBytes,
+    start: usize,
+    end: usize,
+    len: usize,
+}
+
+impl EncodingBlock {
+    /// Copies the given encodings into a new block.
+    pub fn from_encodings(encodings: &[Vec<u8>]) -> Self {
+        let mut bytes = Vec::with_capacity(encodings.iter().map(|encoding| 8 + encoding.len()).sum());
+        for encoding in encodings.iter() {
+            bytes.extend_from_slice(&(encoding.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(encoding);
+        }
+        let end = bytes.len();
+        EncodingBlock {
+            bytes: Arc::new(bytes),
+            start: 0,
+            end,
+            len: encodings.len(),
+        }
+    }
+
+    /// Returns the block of `len` encodings held in `bytes[start..end]`, without copying.
+    ///
+    /// Only the range is checked here. Malformed encodings are reported when they are read.
+    pub fn from_shared(bytes: SharedBytes, start: usize, end: usize, len: usize) -> Result<Self, String> {
+        if start > end || end > (*bytes).as_ref().len() {
+            return Err(format!(
+                "Block {}..{} is out of bounds for {} bytes.",
+                start,
+                end,
+                (*bytes).as_ref().len()
+            ));
+        }
+        if len.saturating_mul(8) > end - start {
+            return Err(format!("Cannot read {} encodings from {} bytes.", len, end - start));
+        }
+        Ok(EncodingBlock { bytes, start, end, len })
+    }
+
+    /// Returns the encoding at the given position in the block, reading past the earlier ones without copying them.
+    pub fn get(&self, position: usize) -> Option<Result<&[u8], String>> {
+        self.iter().nth(position)
+    }
+
+    /// Returns the number of encodings in the block.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the bytes of the block, i.e. every encoding, each preceded by its length as a big-endian `u64`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &(*self.bytes).as_ref()[self.start..self.end]
+    }
+
+    /// Iterates over the encodings in the block. The iterator stops after the first malformed encoding.
+    pub fn iter(&self) -> impl Iterator<Item = Result<&[u8], String>> + '_ {
+        let bytes = self.as_bytes();
+        let (mut position, mut remaining) = (0, self.len);
+        std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let encoding = bytes
+                .get(position..(position + 8))
+                .map(|length| u64::from_be_bytes(length.try_into().unwrap()) as usize)
+                .and_then(|length| bytes.get((position + 8)..(position + 8).checked_add(length)?));
+            match encoding {
+                Some(encoding) => {
+                    position += 8 + encoding.len();
+                    Some(Ok(encoding))
+                }
+                None => {
+                    remaining = 0;
+                    Some(Err(format!("Malformed encoding at offset {} of the block.", position)))
+                }
+            }
+        })
+    }
+}
+
+impl PartialEq for EncodingBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl std::fmt::Debug for EncodingBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncodingBlock")
+            .field("len", &self.len)
+            .field("num_bytes", &(self.end - self.start))
+            .finish()
+    }
+}
+
 pub struct PackableCluster<U: Number> {
     pub name: BitVec,
     pub cardinality: usize,
+    /// The `Indices` of the instances in the order in which `decode_instances` returns them.
+    pub indices: Vec<Index>,
     pub center: Vec<u8>,
     pub radius: U,
-    pub encodings: Vec<Vec<u8>>,
+    /// The encodings of every instance other than the center, in the order of `indices`.
+    pub encodings: EncodingBlock,
 }
 
 impl<U: Number> PackableCluster<U> {
+    /// Packs the given cluster. The center is encoded in terms of the `reference` and, if `direct`,
+    /// every other instance in the cluster is encoded in terms of the center.
     pub fn from_cluster<T: Number>(
         cluster: Arc<Cluster<T, U>>,
         dataset: Arc<dyn CompressibleDataset<T, U>>,
         reference: Index,
         direct: bool,
     ) -> Result<Self, String> {
-        let encodings: Result<Vec<_>, String> = if direct {
-            let encodings: Vec<_> = cluster
+        let mut indices: Vec<_> = if direct {
+            cluster
                 .indices
                 .par_iter()
                 .filter(|&&i| i != cluster.argcenter)
-                .map(|&i| dataset.encode(reference, i))
-                .collect();
-            encodings.into_iter().collect()
+                .cloned()
+                .collect()
         } else {
-            Ok(vec![])
+            vec![]
         };
+        let encodings: Result<Vec<_>, String> = indices
+            .par_iter()
+            .map(|&i| dataset.encode(cluster.argcenter, i))
+            .collect();
+        indices.push(cluster.argcenter);
 
         Ok(PackableCluster {
             name: cluster.name.clone(),
             cardinality: cluster.cardinality,
+            indices,
             center: dataset.encode(reference, cluster.argcenter)?,
             radius: cluster.radius,
-            encodings: encodings?,
+            encodings: EncodingBlock::from_encodings(&encodings?),
         })
     }
 
@@ -74,7 +216,7 @@ impl<U: Number> PackableCluster<U> {
     }
 
     pub fn is_singleton(&self) -> bool {
-        self.radius == U::from(0).unwrap()
+        self.radius == U::zero()
     }
 
     pub fn decode_center<T: Number>(&self, metric: &Arc<dyn Metric<T, U>>, reference: &[T]) -> Result<Vec<T>, String> {
@@ -90,7 +232,7 @@ impl<U: Number> PackableCluster<U> {
         let mut instances: Vec<_> = self
             .encodings
             .iter()
-            .map(|encoding| metric.decode(&center, encoding))
+            .map(|encoding| encoding.and_then(|encoding| metric.decode(&center, encoding)))
             .collect();
         instances.push(Ok(center));
         instances.into_iter().collect()
@@ -103,38 +245,403 @@ type ClusterHits<U> = Vec<Arc<PackableCluster<U>>>;
 /// A HashMap of indices of all hits and their distances to the query.
 type Hits<T, U> = Vec<(Vec<T>, U)>;
 
+/// The two children of a cluster.
+type Children<U> = (Arc<PackableCluster<U>>, Arc<PackableCluster<U>>);
+
+/// The decompressed instances of a leaf.
+type Instances<T> = Arc<Vec<Vec<T>>>;
+
+/// The estimated costs, in bytes, of the two ways to compress a `Cluster`, and the decision made when squishing its tree.
+///
+/// The unitary cost is that of storing the cluster as a leaf: its center and the encodings of its other instances in
+/// terms of the center. The recursive cost is that of storing its center and then each child as squishing leaves it.
+/// Squishing a tree collapses the clusters chosen by the `SquishCriteria` into leaves, and trims their descendants.
+/// By default, these are the clusters whose unitary cost is no more than their recursive cost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SquishCost {
+    /// The name of the `Cluster`.
+    pub name: BitVec,
+
+    /// The number of instances in the `Cluster`.
+    pub cardinality: usize,
+
+    pub unitary_cost: usize,
+
+    /// The same as the unitary cost for leaves.
+    pub recursive_cost: usize,
+
+    /// Whether squishing turns this `Cluster`, which has children, into a leaf.
+    pub collapsed: bool,
+
+    /// Whether squishing removes this `Cluster`, because one of its ancestors was collapsed.
+    pub trimmed: bool,
+}
+
+impl SquishCost {
+    /// Returns the cost of storing the `Cluster` after squishing.
+    pub fn cost(&self) -> usize {
+        if self.collapsed {
+            self.unitary_cost
+        } else {
+            self.recursive_cost
+        }
+    }
+
+    /// Returns the unitary cost as a fraction of the recursive cost. Values up to 1 favor collapsing.
+    pub fn cost_ratio(&self) -> f64 {
+        if self.recursive_cost == 0 {
+            1.
+        } else {
+            self.unitary_cost as f64 / self.recursive_cost as f64
+        }
+    }
+}
+
+/// Decides which clusters squishing collapses into leaves. A cluster with children is collapsed if either criterion
+/// holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SquishCriteria {
+    /// Collapse clusters whose unitary cost is at most this fraction of their recursive cost. Values above 1 trade a
+    /// larger artifact for shallower trees, and so fewer centers to decode per search.
+    pub max_cost_ratio: f64,
+
+    /// Collapse clusters with at most this many instances, whatever their costs.
+    pub min_cardinality: usize,
+}
+
+impl Default for SquishCriteria {
+    fn default() -> Self {
+        SquishCriteria {
+            max_cost_ratio: 1.,
+            min_cardinality: 1,
+        }
+    }
+}
+
+impl SquishCriteria {
+    fn collapses(&self, cost: &SquishCost) -> bool {
+        cost.cardinality <= self.min_cardinality || cost.cost_ratio() <= self.max_cost_ratio
+    }
+}
+
+/// Decides which leaves of a `Codec` are kept decompressed in memory.
+///
+/// Leaves are ranked by how often searches have visited them. The `hot_leaves` most visited are kept decompressed
+/// and the rest are decoded from their compressed bytes on every visit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TieringPolicy {
+    /// The maximum number of leaves to keep decompressed. Zero disables tiering.
+    pub hot_leaves: usize,
+
+    /// Every rebalance multiplies the visit counts by this factor, in [0, 1], so that the hot leaves follow recent
+    /// traffic rather than all traffic since the `Codec` was created.
+    pub decay: f64,
+}
+
+impl Default for TieringPolicy {
+    fn default() -> Self {
+        TieringPolicy {
+            hot_leaves: 0,
+            decay: 0.5,
+        }
+    }
+}
+
+/// The hot tier of decompressed leaves and the telemetry used to choose it.
+struct LeafTiers<T: Number> {
+    policy: RwLock<TieringPolicy>,
+    visits: Mutex<HashMap<BitVec, f64>>,
+    hot: RwLock<HashMap<BitVec, Instances<T>>>,
+    /// The memory held by the decoded hot leaves, as counted by `memory_report`.
+    memory: Tracked,
+}
+
 pub struct Codec<T: Number, U: Number> {
     pub dataset: Arc<dyn CompressibleDataset<T, U>>,
     pub root: Arc<PackableCluster<U>>,
     pub center: Vec<T>,
     pub tree_map: HashMap<BitVec, Arc<PackableCluster<U>>>,
+    tiers: LeafTiers<T>,
+    /// The leaf that holds each instance and the position of the instance in the `indices` of that leaf.
+    locations: HashMap<Index, (Arc<PackableCluster<U>>, usize)>,
+    /// The metadata of each instance, e.g. its name, at the position of its index.
+    metadata: Option<Vec<String>>,
+    /// The description of the dataset, saved with the `Codec`. See `with_card`.
+    card: Option<DatasetCard>,
+    /// The memory held by the packed clusters, as counted by `memory_report`. It is released when this is dropped.
+    _memory: Tracked,
+}
+
+/// Returns the approximate number of bytes held by the packed clusters, including their encodings.
+fn packed_size<U: Number>(tree_map: &HashMap<BitVec, Arc<PackableCluster<U>>>) -> usize {
+    tree_map
+        .values()
+        .map(|cluster| {
+            std::mem::size_of::<PackableCluster<U>>()
+                + cluster.indices.len() * std::mem::size_of::<Index>()
+                + cluster.center.len()
+                + (cluster.encodings.end - cluster.encodings.start)
+        })
+        .sum()
+}
+
+/// An instance found in a search of a `Codec`, along with its index and metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedHit<'a, T: Number, U: Number> {
+    pub index: Index,
+    /// The metadata of the instance, if the `Codec` has metadata.
+    pub metadata: Option<&'a str>,
+    pub instance: Vec<T>,
+    pub distance: U,
 }
 
 impl<T: Number, U: Number> Codec<T, U> {
-    pub fn from_cakes(_dataset: &Arc<dyn CompressibleDataset<T, U>>, _cakes: &Cakes<T, U>) -> Self {
-        todo!()
+    /// Creates a `Codec` from the packed clusters of a tree and the reference center in terms of which their centers
+    /// were encoded. Returns an Err if there is no root cluster.
+    pub fn new(
+        dataset: Arc<dyn CompressibleDataset<T, U>>,
+        center: Vec<T>,
+        tree_map: HashMap<BitVec, Arc<PackableCluster<U>>>,
+    ) -> Result<Self, String> {
+        let root = match tree_map.get(&bitvec![1]) {
+            Some(root) => Arc::clone(root),
+            None => return Err("The compressed tree has no root cluster.".to_string()),
+        };
+        let mut locations = HashMap::new();
+        for cluster in tree_map.values() {
+            let mut left = cluster.name.clone();
+            left.push(false);
+            if tree_map.contains_key(&left) {
+                continue;
+            }
+            for (position, &index) in cluster.indices.iter().enumerate() {
+                locations.insert(index, (Arc::clone(cluster), position));
+            }
+        }
+        let location_size = locations.len() * std::mem::size_of::<(Index, (Arc<PackableCluster<U>>, usize))>();
+        let memory = Tracked::new(Subsystem::Compressed, packed_size(&tree_map) + location_size);
+        Ok(Codec {
+            dataset,
+            root,
+            center,
+            tree_map,
+            tiers: LeafTiers {
+                policy: RwLock::new(TieringPolicy::default()),
+                visits: Mutex::new(HashMap::new()),
+                hot: RwLock::new(HashMap::new()),
+                memory: Tracked::new(Subsystem::Caches, 0),
+            },
+            locations,
+            metadata: None,
+            card: None,
+            _memory: memory,
+        })
+    }
+
+    /// Attaches the metadata of the instances, one entry per instance of the dataset in the order of their indices.
+    /// The metadata is saved with the `Codec` and returned with the hits of `rnn_with_metadata`.
+    ///
+    /// Returns an Err if the number of entries differs from the cardinality of the dataset.
+    pub fn with_metadata(mut self, metadata: Vec<String>) -> Result<Self, String> {
+        if metadata.len() != self.dataset.cardinality() {
+            return Err(format!(
+                "Expected metadata for {} instances but got {}.",
+                self.dataset.cardinality(),
+                metadata.len()
+            ));
+        }
+        self.metadata = Some(metadata);
+        Ok(self)
+    }
+
+    /// Returns the metadata of the instances, if any was attached.
+    pub fn metadata(&self) -> Option<&[String]> {
+        self.metadata.as_deref()
+    }
+
+    /// Attaches a description of the dataset, which is saved with the `Codec` and may be read back with
+    /// `io::read_card`. Returns an Err if the card names a metric other than that of the dataset.
+    pub fn with_card(mut self, card: DatasetCard) -> Result<Self, String> {
+        card.check_metric(&self.dataset.metric_name())?;
+        self.card = Some(card);
+        Ok(self)
+    }
+
+    /// Returns the description of the dataset, if one was attached.
+    pub fn card(&self) -> Option<&DatasetCard> {
+        self.card.as_ref()
+    }
+
+    /// Returns the instance with the given original index, e.g. that of a hit from `rnn_with_metadata` or from a
+    /// search of the uncompressed tree.
+    ///
+    /// Only the center of the leaf that holds the instance, and then the one encoding of the instance, are decoded.
+    /// Hot leaves are served from memory. Returns an Err if no leaf holds the index or if an encoding is malformed.
+    pub fn get(&self, index: Index) -> Result<Vec<T>, String> {
+        let (leaf, position) = self
+            .locations
+            .get(&index)
+            .ok_or_else(|| format!("Index {} is not in any leaf.", index))?;
+        if let Some(instances) = read_cache(&self.tiers.hot).get(&leaf.name) {
+            return Ok(instances[*position].clone());
+        }
+
+        let metric = self.dataset.metric();
+        let center = leaf.decode_center(&metric, &self.center)?;
+        // The center is the last of the `indices` and has no encoding of its own.
+        if *position == leaf.indices.len() - 1 {
+            return Ok(center);
+        }
+        match leaf.encodings.get(*position) {
+            Some(encoding) => metric.decode(&center, encoding?),
+            None => Err(format!("Leaf {:?} has no encoding for index {}.", leaf.name, index)),
+        }
+    }
+
+    /// Returns the instances with the given original indices, in the same order, as by `get`.
+    pub fn get_many(&self, indices: &[Index]) -> Result<Vec<Vec<T>>, String> {
+        indices.par_iter().map(|&index| self.get(index)).collect()
+    }
+
+    /// Returns the original rows of the instances of the compressed dataset.
+    ///
+    /// Compression keeps the indices of the dataset, so the `index` of a `CompressedHit`, and the indices returned by
+    /// `par_decode_leaves`, are traced back to their original rows by this provenance.
+    pub fn provenance(&self) -> Provenance {
+        self.dataset.provenance()
+    }
+
+    /// Compresses the search tree. The center of the root is the reference and only the leaves store encodings
+    /// of their instances.
+    pub fn from_cakes(dataset: &Arc<dyn CompressibleDataset<T, U>>, cakes: &Cakes<T, U>) -> Result<Self, String> {
+        let mut tree = cakes.root.flatten_tree();
+        tree.push(Arc::clone(&cakes.root));
+        let reference = cakes.root.argcenter;
+        let tree_map = tree
+            .into_par_iter()
+            .map(|cluster| {
+                let is_leaf = read_cache(&cluster.children).is_none();
+                let packed = PackableCluster::from_cluster(cluster, Arc::clone(dataset), reference, is_leaf)?;
+                Ok((packed.name.clone(), Arc::new(packed)))
+            })
+            .collect::<Result<_, String>>()?;
+        Codec::new(Arc::clone(dataset), cakes.root.center(), tree_map)
+    }
+
+    /// Compresses the search tree after squishing it, i.e. after collapsing every subtree that is cheaper to store as
+    /// a single leaf. See `SquishCost`. The center of the root is the reference, as in `from_cakes`.
+    pub fn from_cakes_squished(
+        dataset: &Arc<dyn CompressibleDataset<T, U>>,
+        cakes: &Cakes<T, U>,
+    ) -> Result<Self, String> {
+        Self::from_cakes_squished_with_criteria(dataset, cakes, SquishCriteria::default())
+    }
+
+    /// Compresses the search tree after collapsing the subtrees chosen by the given criteria.
+    pub fn from_cakes_squished_with_criteria(
+        dataset: &Arc<dyn CompressibleDataset<T, U>>,
+        cakes: &Cakes<T, U>,
+        criteria: SquishCriteria,
+    ) -> Result<Self, String> {
+        let costs: HashMap<_, _> = Self::squish_costs_with_criteria(dataset, cakes, criteria)?
+            .into_iter()
+            .map(|cost| (cost.name.clone(), cost))
+            .collect();
+        let mut tree = cakes.root.flatten_tree();
+        tree.push(Arc::clone(&cakes.root));
+        let reference = cakes.root.argcenter;
+        let tree_map = tree
+            .into_par_iter()
+            .filter(|cluster| !costs[&cluster.name].trimmed)
+            .map(|cluster| {
+                let is_leaf = read_cache(&cluster.children).is_none() || costs[&cluster.name].collapsed;
+                let packed = PackableCluster::from_cluster(cluster, Arc::clone(dataset), reference, is_leaf)?;
+                Ok((packed.name.clone(), Arc::new(packed)))
+            })
+            .collect::<Result<_, String>>()?;
+        Codec::new(Arc::clone(dataset), cakes.root.center(), tree_map)
+    }
+
+    /// Returns the costs of compressing every cluster in the search tree, and the decisions that squishing the tree
+    /// would make, in pre-order. The costs are those of `from_cakes_squished`, in which the center of the root is the
+    /// reference.
+    ///
+    /// This encodes every instance once for each of its ancestors, so it is about as expensive as squishing the tree.
+    pub fn squish_costs(
+        dataset: &Arc<dyn CompressibleDataset<T, U>>,
+        cakes: &Cakes<T, U>,
+    ) -> Result<Vec<SquishCost>, String> {
+        Self::squish_costs_with_criteria(dataset, cakes, SquishCriteria::default())
+    }
+
+    /// Returns the costs of compressing every cluster in the search tree, and the decisions that squishing the tree
+    /// with the given criteria would make, in pre-order.
+    pub fn squish_costs_with_criteria(
+        dataset: &Arc<dyn CompressibleDataset<T, U>>,
+        cakes: &Cakes<T, U>,
+        criteria: SquishCriteria,
+    ) -> Result<Vec<SquishCost>, String> {
+        squish_costs(dataset, &cakes.root, cakes.root.argcenter, &criteria)
     }
 
     pub fn diameter(&self) -> U {
-        U::from(2).unwrap() * self.root.radius
+        self.root.radius + self.root.radius
     }
 
     fn distance(&self, x: &[T], y: &[T]) -> U {
         self.dataset.metric().distance(x, y)
     }
 
-    pub fn rnn_instances(&self, query: &[T], radius: Option<U>) -> Vec<Vec<T>> {
-        self.rnn(query, radius)
+    /// Returns the children of the given cluster, if it has any.
+    pub fn children(&self, cluster: &PackableCluster<U>) -> Option<Children<U>> {
+        let mut name = cluster.name.clone();
+        name.push(false);
+        let left = self.tree_map.get(&name)?;
+        let last = name.len() - 1;
+        name.set(last, true);
+        let right = self.tree_map.get(&name)?;
+        Some((Arc::clone(left), Arc::clone(right)))
+    }
+
+    pub fn rnn_instances(&self, query: &[T], radius: Option<U>) -> Result<Vec<Vec<T>>, String> {
+        Ok(self
+            .rnn(query, radius)?
             .into_iter()
             .map(|(instance, _)| instance)
-            .collect()
+            .collect())
+    }
+
+    /// Returns the instances within `radius` of the query, and their distances.
+    ///
+    /// Returns an Err if a center or a leaf on the path of the search cannot be decoded, e.g. from a corrupt file.
+    pub fn rnn(&self, query: &[T], radius: Option<U>) -> Result<Hits<T, U>, String> {
+        let clusters = self.tree_search(query, radius)?;
+        self.leaf_search(query, radius, clusters)
     }
 
-    pub fn rnn(&self, query: &[T], radius: Option<U>) -> Hits<T, U> {
-        self.leaf_search(query, radius, self.tree_search(query, radius))
+    /// Like `rnn`, but each hit also carries the index and metadata of its instance. Hits are sorted by index.
+    pub fn rnn_with_metadata(&self, query: &[T], radius: Option<U>) -> Result<Vec<CompressedHit<'_, T, U>>, String> {
+        let threshold = radius.unwrap_or_else(U::zero);
+        let mut hits = vec![];
+        for cluster in self.tree_search(query, radius)? {
+            let instances = self.leaf_instances(&cluster)?;
+            for (&index, instance) in cluster.indices.iter().zip(instances.iter()) {
+                let distance = self.distance(query, instance);
+                if distance <= threshold {
+                    hits.push(CompressedHit {
+                        index,
+                        metadata: self.metadata.as_ref().map(|metadata| metadata[index].as_str()),
+                        instance: instance.clone(),
+                        distance,
+                    });
+                }
+            }
+        }
+        hits.sort_by_key(|hit| hit.index);
+        Ok(hits)
     }
 
-    pub fn tree_search(&self, query: &[T], radius: Option<U>) -> ClusterHits<U> {
+    pub fn tree_search(&self, query: &[T], radius: Option<U>) -> Result<ClusterHits<U>, String> {
         // parse the search radius
         let radius = radius.unwrap_or_else(U::zero);
         // if query ball has overlapping volume with the root, delegate to the recursive, private method.
@@ -142,16 +649,39 @@ impl<T: Number, U: Number> Codec<T, U> {
             self._tree_search(&self.root, query, radius)
         } else {
             // otherwise, return an empty Vec signifying no possible hits.
-            vec![]
+            Ok(vec![])
         }
     }
 
-    fn _tree_search(&self, _cluster: &Arc<PackableCluster<U>>, _query: &[T], _radius: U) -> ClusterHits<U> {
-        todo!()
+    fn _tree_search(&self, cluster: &Arc<PackableCluster<U>>, query: &[T], radius: U) -> Result<ClusterHits<U>, String> {
+        // Invariant: Entering this function means that the current cluster has overlapping volume with the query-ball.
+        match self.children(cluster) {
+            Some((left, right)) => {
+                let metric = self.dataset.metric();
+                let search = |child: &Arc<PackableCluster<U>>| {
+                    let center = child.decode_center(&metric, &self.center)?;
+                    if self.distance(&center, query) <= (radius + child.radius) {
+                        self._tree_search(child, query, radius)
+                    } else {
+                        Ok(vec![])
+                    }
+                };
+                let (left, right) = parallelism::join(cluster.cardinality, || search(&left), || search(&right));
+                let mut left = left?;
+                left.append(&mut right?);
+                Ok(left)
+            }
+            None => Ok(vec![Arc::clone(cluster)]),
+        }
     }
 
-    pub fn leaf_search(&self, _query: &[T], _radius: Option<U>, _clusters: ClusterHits<U>) -> Hits<T, U> {
-        todo!()
+    /// Exhaustively searches the leaves identified by tree-search, decompressing those that are not hot.
+    pub fn leaf_search(&self, query: &[T], radius: Option<U>, clusters: ClusterHits<U>) -> Result<Hits<T, U>, String> {
+        let mut instances = vec![];
+        for cluster in clusters.iter() {
+            instances.extend(self.leaf_instances(cluster)?.iter().cloned());
+        }
+        Ok(self.linear_search(query, radius, instances))
     }
 
     pub fn linear_search_instances(&self, query: &[T], radius: Option<U>, instances: Vec<Vec<T>>) -> Vec<Vec<T>> {
@@ -172,15 +702,271 @@ impl<T: Number, U: Number> Codec<T, U> {
             .filter(|(_, d)| *d <= radius)
             .collect()
     }
+
+    /// Returns the decompressed instances of the given leaf, in the order of its `indices`, and counts the visit.
+    ///
+    /// Hot leaves are served from memory and all others are decoded.
+    pub fn leaf_instances(&self, cluster: &PackableCluster<U>) -> Result<Instances<T>, String> {
+        if read_cache(&self.tiers.policy).hot_leaves > 0 {
+            *lock_cache(&self.tiers.visits).entry(cluster.name.clone()).or_insert(0.) += 1.;
+        }
+        self.decode_leaf(cluster)
+    }
+
+    /// Returns the decompressed instances of the given leaf without counting a visit.
+    fn decode_leaf(&self, cluster: &PackableCluster<U>) -> Result<Instances<T>, String> {
+        if let Some(instances) = read_cache(&self.tiers.hot).get(&cluster.name) {
+            return Ok(Arc::clone(instances));
+        }
+        Ok(Arc::new(
+            cluster.decode_instances(&self.dataset.metric(), &self.center)?,
+        ))
+    }
+
+    /// Returns the current tiering policy.
+    pub fn tiering_policy(&self) -> TieringPolicy {
+        *read_cache(&self.tiers.policy)
+    }
+
+    /// Replaces the tiering policy. The hot leaves change at the next `rebalance`.
+    pub fn set_tiering_policy(&self, policy: TieringPolicy) {
+        *write_cache(&self.tiers.policy) = policy;
+    }
+
+    /// Returns the names of the leaves that are currently kept decompressed, in sorted order.
+    pub fn hot_leaves(&self) -> Vec<BitVec> {
+        let mut names: Vec<_> = read_cache(&self.tiers.hot).keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Promotes the most visited leaves to the hot tier, demotes the rest, and then decays the visit counts.
+    ///
+    /// Returns the numbers of leaves that were promoted and demoted.
+    pub fn rebalance(&self) -> Result<(usize, usize), String> {
+        let policy = self.tiering_policy();
+
+        let wanted: HashSet<BitVec> = {
+            let mut visits = lock_cache(&self.tiers.visits);
+            let mut ranked: Vec<_> = visits.iter().map(|(name, &count)| (name.clone(), count)).collect();
+            ranked.sort_by(|(a, x), (b, y)| compare_distances(y, x).then_with(|| a.cmp(b)));
+            visits.values_mut().for_each(|count| *count *= policy.decay);
+            visits.retain(|_, count| *count >= 1e-3);
+            ranked
+                .into_iter()
+                .take(policy.hot_leaves)
+                .map(|(name, _)| name)
+                .collect()
+        };
+
+        // Decode the new hot leaves before taking the write lock so that searches are not blocked.
+        let current: HashSet<_> = read_cache(&self.tiers.hot).keys().cloned().collect();
+        let metric = self.dataset.metric();
+        let promoted = wanted
+            .difference(&current)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .filter_map(|name| self.tree_map.get(name))
+            .map(|cluster| {
+                let instances = cluster.decode_instances(&metric, &self.center)?;
+                Ok((cluster.name.clone(), Arc::new(instances)))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut hot = write_cache(&self.tiers.hot);
+        let before = hot.len();
+        hot.retain(|name, _| wanted.contains(name));
+        let demoted = before - hot.len();
+        let num_promoted = promoted.len();
+        hot.extend(promoted);
+        let decoded_size = hot
+            .values()
+            .flat_map(|instances| instances.iter())
+            .map(|instance| std::mem::size_of::<Vec<T>>() + instance.len() * std::mem::size_of::<T>())
+            .sum();
+        self.tiers.memory.set(decoded_size);
+        Ok((num_promoted, demoted))
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> Codec<T, U> {
+    /// Decodes the named leaves in parallel.
+    ///
+    /// Returns the original indices of the decoded instances and an uncompressed dataset in which instance `i` is the
+    /// instance with original index `indices[i]`. Returns an Err if a name is not that of a leaf.
+    pub fn par_decode_leaves(&self, leaf_names: &[BitVec]) -> Result<(Vec<Index>, RowMajor<T, U>), String> {
+        let leaves = leaf_names
+            .iter()
+            .map(|name| match self.tree_map.get(name) {
+                Some(cluster) if self.children(cluster).is_none() => Ok(Arc::clone(cluster)),
+                _ => Err(format!("There is no leaf named {:?}.", name)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let decoded = leaves
+            .par_iter()
+            .map(|leaf| Ok((leaf.indices.clone(), self.decode_leaf(leaf)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut indices = Vec::new();
+        let mut instances = Vec::new();
+        for (leaf_indices, leaf_instances) in decoded {
+            indices.extend(leaf_indices);
+            instances.extend(leaf_instances.iter().cloned());
+        }
+        Ok((
+            indices,
+            RowMajor::new(Arc::new(instances), self.dataset.metric(), false),
+        ))
+    }
+
+    /// Decodes every leaf in parallel and returns an uncompressed dataset in which every instance has its
+    /// original index.
+    ///
+    /// This trades memory for speed, e.g. when switching from storing a corpus to serving many queries over it.
+    pub fn par_decompress(&self) -> Result<RowMajor<T, U>, String> {
+        let mut leaves: Vec<_> = self
+            .tree_map
+            .values()
+            .filter(|cluster| self.children(cluster).is_none())
+            .map(|cluster| cluster.name.clone())
+            .collect();
+        leaves.sort();
+        let (indices, decoded) = self.par_decode_leaves(&leaves)?;
+
+        let cardinality = self.root.cardinality;
+        let mut instances = vec![None; cardinality];
+        for (position, index) in indices.into_iter().enumerate() {
+            match instances.get_mut(index) {
+                Some(slot @ None) => *slot = Some(decoded.instance(position)),
+                Some(Some(_)) => return Err(format!("Index {} appears in more than one leaf.", index)),
+                None => {
+                    return Err(format!(
+                        "Index {} is outside the {} compressed instances.",
+                        index, cardinality
+                    ))
+                }
+            }
+        }
+        let instances = instances
+            .into_iter()
+            .enumerate()
+            .map(|(index, instance)| instance.ok_or_else(|| format!("Index {} is not in any leaf.", index)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(RowMajor::new(Arc::new(instances), self.dataset.metric(), false))
+    }
+
+    /// Starts a background thread that calls `rebalance` every `interval`.
+    ///
+    /// The thread holds only a weak reference to the `Codec` and stops once the `Codec` is dropped.
+    pub fn start_tiering(self: &Arc<Self>, interval: std::time::Duration) -> std::thread::JoinHandle<()> {
+        let codec = Arc::downgrade(self);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            match codec.upgrade() {
+                Some(codec) => {
+                    // A failed rebalance leaves the hot leaves as they were, so it is retried on the next tick.
+                    if let Err(error) = codec.rebalance() {
+                        log::warn!("Failed to rebalance the hot leaves: {}", error);
+                    }
+                }
+                None => break,
+            }
+        })
+    }
+}
+
+/// Returns the costs of the cluster and its descendants in pre-order, with centers encoded in terms of the reference.
+fn squish_costs<T: Number, U: Number>(
+    dataset: &Arc<dyn CompressibleDataset<T, U>>,
+    cluster: &Arc<Cluster<T, U>>,
+    reference: Index,
+    criteria: &SquishCriteria,
+) -> Result<Vec<SquishCost>, String> {
+    let center_cost = dataset.encode(reference, cluster.argcenter)?.len();
+    let encodings: Result<Vec<_>, String> = cluster
+        .indices
+        .par_iter()
+        .filter(|&&i| i != cluster.argcenter)
+        .map(|&i| dataset.encode(cluster.argcenter, i).map(|encoding| encoding.len()))
+        .collect();
+    let unitary_cost = center_cost + encodings?.into_iter().sum::<usize>();
+
+    let mut cost = SquishCost {
+        name: cluster.name.clone(),
+        cardinality: cluster.cardinality,
+        unitary_cost,
+        recursive_cost: unitary_cost,
+        collapsed: false,
+        trimmed: false,
+    };
+    let mut descendants = match read_cache(&cluster.children).clone() {
+        Some((left, right)) => {
+            let (left, right) = parallelism::join(
+                cluster.cardinality,
+                || squish_costs(dataset, &left, reference, criteria),
+                || squish_costs(dataset, &right, reference, criteria),
+            );
+            let (mut left, mut right) = (left?, right?);
+            cost.recursive_cost = center_cost + left[0].cost() + right[0].cost();
+            cost.collapsed = criteria.collapses(&cost);
+            left.append(&mut right);
+            left
+        }
+        None => vec![],
+    };
+    if cost.collapsed {
+        descendants.iter_mut().for_each(|descendant| descendant.trimmed = true);
+    }
+
+    let mut costs = vec![cost];
+    costs.append(&mut descendants);
+    Ok(costs)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
     use std::sync::Arc;
 
     use crate::dataset::RowMajor;
     use crate::metric_from_name;
+    use crate::Cakes;
     use crate::CompressibleDataset;
+    use crate::Dataset;
+
+    use super::Codec;
+    use super::PackableCluster;
+    use super::SquishCriteria;
+    use super::TieringPolicy;
+
+    /// Returns sequences over a small alphabet, in families of similar sequences, along with a search tree for them.
+    fn sequences() -> (Arc<dyn CompressibleDataset<u8, u64>>, Cakes<u8, u64>) {
+        let data: Vec<Vec<u8>> = (0..200)
+            .map(|i: usize| {
+                let family = i % 5;
+                (0..32)
+                    .map(|j| {
+                        if (i * 7 + j * 13) % 11 == 3 {
+                            (j % 4) as u8
+                        } else {
+                            family as u8
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&row_major).as_arc_dataset(), None, None);
+        (row_major.as_arc_compressible_dataset(), cakes)
+    }
+
+    fn sorted<T: Ord + Clone>(mut values: Vec<T>) -> Vec<T> {
+        values.sort();
+        values
+    }
 
     #[test]
     fn test_codec() {
@@ -193,4 +979,288 @@ mod tests {
 
         assert_eq!(dataset.instance(1), decoded);
     }
+
+    #[test]
+    fn test_codec_search() {
+        let (dataset, cakes) = sequences();
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+
+        for cluster in codec.tree_map.values().filter(|c| codec.children(c).is_none()) {
+            let instances = codec.leaf_instances(cluster).unwrap();
+            let expected: Vec<_> = cluster.indices.iter().map(|&i| dataset.instance(i)).collect();
+            assert_eq!(instances.as_ref(), &expected);
+        }
+
+        for q in [0, 17, 101] {
+            let query = dataset.instance(q);
+            for radius in [0, 2, 5] {
+                let expected: Vec<_> = cakes
+                    .rnn_indices(&query, Some(radius))
+                    .into_iter()
+                    .map(|i| dataset.instance(i))
+                    .collect();
+                assert_eq!(
+                    sorted(codec.rnn_instances(&query, Some(radius)).unwrap()),
+                    sorted(expected)
+                );
+            }
+        }
+
+        // A corrupt center is an error rather than a panic.
+        let mut codec = codec;
+        for cluster in codec.tree_map.values_mut().filter(|c| c.depth() > 0) {
+            *cluster = Arc::new(PackableCluster {
+                name: cluster.name.clone(),
+                cardinality: cluster.cardinality,
+                indices: cluster.indices.clone(),
+                center: vec![0; 3],
+                radius: cluster.radius,
+                encodings: cluster.encodings.clone(),
+            });
+        }
+        assert!(codec.rnn(&dataset.instance(0), Some(u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn test_rnn_with_metadata() {
+        let (dataset, cakes) = sequences();
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+        assert!(codec.metadata().is_none());
+        let query = dataset.instance(17);
+        assert!(codec
+            .rnn_with_metadata(&query, Some(2))
+            .unwrap()
+            .iter()
+            .all(|hit| hit.metadata.is_none()));
+
+        assert!(Codec::from_cakes(&dataset, &cakes)
+            .unwrap()
+            .with_metadata(vec!["too few".to_string()])
+            .is_err());
+        let names: Vec<_> = (0..200).map(|i| format!("sequence-{}", i)).collect();
+        let codec = codec.with_metadata(names.clone()).unwrap();
+        assert_eq!(codec.metadata(), Some(names.as_slice()));
+
+        for q in [0, 17, 101] {
+            let query = dataset.instance(q);
+            for radius in [0, 2, 5] {
+                let hits = codec.rnn_with_metadata(&query, Some(radius)).unwrap();
+                let indices: Vec<_> = hits.iter().map(|hit| hit.index).collect();
+                assert_eq!(indices, sorted(cakes.rnn_indices(&query, Some(radius))));
+                for hit in hits {
+                    assert_eq!(hit.metadata, Some(names[hit.index].as_str()));
+                    assert_eq!(hit.instance, dataset.instance(hit.index));
+                    assert_eq!(hit.distance, dataset.metric().distance(&query, &hit.instance));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiering() {
+        let (dataset, cakes) = sequences();
+        let codec = Arc::new(Codec::from_cakes(&dataset, &cakes).unwrap());
+        let query = dataset.instance(3);
+        let expected = sorted(codec.rnn_instances(&query, Some(1)).unwrap());
+
+        // Visits are only counted once tiering is enabled.
+        assert_eq!(codec.rebalance().unwrap(), (0, 0));
+        codec.set_tiering_policy(TieringPolicy {
+            hot_leaves: 2,
+            decay: 0.5,
+        });
+        codec.rnn(&query, Some(1)).unwrap();
+        let visited = codec.tree_search(&query, Some(1)).unwrap().len();
+        let (promoted, demoted) = codec.rebalance().unwrap();
+        assert_eq!((promoted, demoted), (std::cmp::min(2, visited), 0));
+        assert_eq!(codec.hot_leaves().len(), promoted);
+
+        // Hot leaves give the same results.
+        assert_eq!(sorted(codec.rnn_instances(&query, Some(1)).unwrap()), expected);
+
+        // Heavier traffic elsewhere promotes other leaves.
+        let other = dataset.instance(4);
+        let other_leaves: Vec<_> = codec
+            .tree_search(&other, Some(0))
+            .unwrap()
+            .iter()
+            .map(|c| c.name.clone())
+            .collect();
+        for _ in 0..3 {
+            codec.rnn(&other, Some(0)).unwrap();
+            codec.rnn(&other, Some(0)).unwrap();
+            codec.rebalance().unwrap();
+        }
+        let hot = codec.hot_leaves();
+        assert!(other_leaves.iter().take(2).all(|name| hot.contains(name)));
+
+        // The background thread rebalances, and stops once the codec is dropped.
+        codec.set_tiering_policy(TieringPolicy {
+            hot_leaves: 0,
+            decay: 0.5,
+        });
+        let handle = codec.start_tiering(std::time::Duration::from_millis(5));
+        while !codec.hot_leaves().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        drop(codec);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_decompress() {
+        let (dataset, cakes) = sequences();
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+
+        let decompressed = codec.par_decompress().unwrap();
+        assert_eq!(decompressed.cardinality(), dataset.cardinality());
+        for i in dataset.indices() {
+            assert_eq!(decompressed.instance(i), dataset.instance(i));
+        }
+
+        let mut leaves: Vec<_> = codec
+            .tree_map
+            .values()
+            .filter(|c| codec.children(c).is_none())
+            .map(|c| c.name.clone())
+            .collect();
+        leaves.sort();
+        let (indices, decoded) = codec.par_decode_leaves(&leaves[..2]).unwrap();
+        assert_eq!(indices.len(), decoded.cardinality());
+        for (position, &i) in indices.iter().enumerate() {
+            assert_eq!(decoded.instance(position), dataset.instance(i));
+        }
+
+        assert!(codec.par_decode_leaves(std::slice::from_ref(&codec.root.name)).is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let (dataset, cakes) = sequences();
+        let codec = Codec::from_cakes(&dataset, &cakes).unwrap();
+        for i in dataset.indices() {
+            assert_eq!(codec.get(i).unwrap(), dataset.instance(i));
+        }
+        assert!(codec.get(dataset.cardinality()).is_err());
+
+        let hits = cakes.rnn(&dataset.instance(3), Some(4));
+        let indices: Vec<_> = hits.iter().map(|&(i, _)| i).collect();
+        let instances = codec.get_many(&indices).unwrap();
+        assert!(indices
+            .iter()
+            .zip(instances.iter())
+            .all(|(&i, instance)| instance == &dataset.instance(i)));
+
+        // Hot leaves give the same instances.
+        codec.set_tiering_policy(TieringPolicy {
+            hot_leaves: 4,
+            decay: 1.,
+        });
+        indices
+            .iter()
+            .for_each(|&i| drop(codec.rnn(&dataset.instance(i), None)));
+        codec.rebalance().unwrap();
+        assert_eq!(codec.get_many(&indices).unwrap(), instances);
+    }
+
+    #[test]
+    fn test_squish() {
+        let (dataset, cakes) = sequences();
+        let costs = Codec::squish_costs(&dataset, &cakes).unwrap();
+        assert_eq!(costs.len(), cakes.root.num_descendants() + 1);
+        assert_eq!(costs[0].name, cakes.root.name);
+        let mut leaves = cakes.root.flatten_tree();
+        leaves.retain(|cluster| cluster.children.read().unwrap().is_none());
+        let leaves: HashSet<_> = leaves.into_iter().map(|cluster| cluster.name.clone()).collect();
+        for cost in costs.iter() {
+            if leaves.contains(&cost.name) {
+                assert!(!cost.collapsed && cost.recursive_cost == cost.unitary_cost);
+            } else {
+                assert_eq!(cost.collapsed, cost.cost_ratio() <= 1.);
+            }
+            let is_trimmed = costs.iter().any(|other| {
+                other.collapsed && other.name.len() < cost.name.len() && cost.name.starts_with(&other.name)
+            });
+            assert_eq!(cost.trimmed, is_trimmed);
+        }
+
+        // The sequences in each family differ in a few positions, so some subtrees are cheaper as leaves.
+        assert!(costs.iter().any(|cost| cost.collapsed));
+        let codec = Codec::from_cakes_squished(&dataset, &cakes).unwrap();
+        assert_eq!(codec.tree_map.len(), costs.iter().filter(|cost| !cost.trimmed).count());
+        for cost in costs.iter().filter(|cost| cost.collapsed && !cost.trimmed) {
+            assert!(codec.children(&codec.tree_map[&cost.name]).is_none());
+        }
+
+        let unsquished = Codec::from_cakes(&dataset, &cakes).unwrap();
+        let stored = |codec: &Codec<u8, u64>| {
+            codec
+                .tree_map
+                .values()
+                .map(|cluster| cluster.center.len() + cluster.encodings.as_bytes().len())
+                .sum::<usize>()
+        };
+        assert!(stored(&codec) <= stored(&unsquished));
+
+        for q in [0, 17, 101] {
+            let query = dataset.instance(q);
+            for radius in [0, 2, 5] {
+                assert_eq!(
+                    sorted(codec.rnn_instances(&query, Some(radius)).unwrap()),
+                    sorted(unsquished.rnn_instances(&query, Some(radius)).unwrap())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_squish_criteria() {
+        let (dataset, cakes) = sequences();
+        let default = Codec::squish_costs(&dataset, &cakes).unwrap();
+        let collapsed =
+            |costs: &[super::SquishCost]| costs.iter().filter(|cost| cost.collapsed && !cost.trimmed).count();
+
+        // More aggressive criteria collapse more of the tree.
+        let aggressive = SquishCriteria {
+            max_cost_ratio: 1.5,
+            min_cardinality: 8,
+        };
+        let costs = Codec::squish_costs_with_criteria(&dataset, &cakes, aggressive).unwrap();
+        assert!(costs.iter().filter(|cost| !cost.trimmed).count() < default.iter().filter(|cost| !cost.trimmed).count());
+        for cost in costs.iter().filter(|cost| !cost.trimmed && cost.cardinality <= 8) {
+            assert!(cost.collapsed || cost.recursive_cost == cost.unitary_cost);
+        }
+
+        // Collapsing the root leaves a single leaf.
+        let everything = SquishCriteria {
+            max_cost_ratio: f64::INFINITY,
+            min_cardinality: 1,
+        };
+        let costs = Codec::squish_costs_with_criteria(&dataset, &cakes, everything).unwrap();
+        assert!(costs[0].collapsed && costs[1..].iter().all(|cost| cost.trimmed));
+        assert_eq!(collapsed(&costs), 1);
+
+        // Nothing is collapsed if no criterion holds.
+        let nothing = SquishCriteria {
+            max_cost_ratio: 0.,
+            min_cardinality: 0,
+        };
+        assert_eq!(
+            collapsed(&Codec::squish_costs_with_criteria(&dataset, &cakes, nothing).unwrap()),
+            0
+        );
+
+        for criteria in [aggressive, everything, nothing] {
+            let codec = Codec::from_cakes_squished_with_criteria(&dataset, &cakes, criteria).unwrap();
+            for q in [0, 17, 101] {
+                let query = dataset.instance(q);
+                let expected: Vec<_> = cakes
+                    .rnn_indices(&query, Some(2))
+                    .into_iter()
+                    .map(|i| dataset.instance(i))
+                    .collect();
+                assert_eq!(sorted(codec.rnn_instances(&query, Some(2)).unwrap()), sorted(expected));
+            }
+        }
+    }
 }
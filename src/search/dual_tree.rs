@@ -0,0 +1,94 @@
+//! A traversal of the pairs of clusters from two trees, shared by the joins of search trees.
+//!
+//! A pair of clusters is pruned when no pair of their instances can matter to the visitor, e.g. when the distance
+//! between their centers exceeds the sum of their radii and the search radius. Otherwise the larger cluster is split
+//! until both are leaves, whose instances the visitor then compares.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::read_cache;
+
+/// Decides which pairs of clusters a dual-tree traversal descends into, and handles the pairs of leaves it reaches.
+pub(crate) trait DualTreeVisitor<T: Number, U: Number> {
+    /// Returns whether the traversal may skip the pair of clusters, whose centers are `distance` apart.
+    ///
+    /// The visitor may account for all pairs of instances in the clusters at once before skipping them,
+    /// e.g. when counting pairs within a radius of the far side of both clusters.
+    fn prune(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>, distance: U) -> bool;
+
+    /// Handles a pair of leaves that was not pruned.
+    ///
+    /// In a traversal of a tree with itself, each unordered pair of leaves is visited once, and the pair of a leaf
+    /// with itself is visited too, so the visitor must skip the repeated pairs of instances within that leaf.
+    fn visit(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>);
+}
+
+/// Traverses the pairs of clusters from the two trees with the visitor.
+///
+/// If both roots are the same cluster, the tree is traversed with itself, and each unordered pair of clusters is
+/// offered to the visitor only once.
+pub(crate) fn dual_tree<T: Number, U: Number>(
+    left: &Arc<Cluster<T, U>>,
+    right: &Arc<Cluster<T, U>>,
+    visitor: &mut impl DualTreeVisitor<T, U>,
+) {
+    let mut stack = vec![(Arc::clone(left), Arc::clone(right))];
+    while let Some((left, right)) = stack.pop() {
+        if visitor.prune(&left, &right, center_distance(&left, &right)) {
+            continue;
+        }
+
+        let left_children = read_cache(&left.children).clone();
+        let right_children = read_cache(&right.children).clone();
+        match (left_children, right_children) {
+            (None, None) => visitor.visit(&left, &right),
+            (Some((l, r)), Some(_)) if Arc::ptr_eq(&left, &right) => {
+                stack.push((Arc::clone(&l), Arc::clone(&r)));
+                stack.push((Arc::clone(&r), r));
+                stack.push((Arc::clone(&l), l));
+            }
+            (Some((l, r)), None) => {
+                stack.push((r, Arc::clone(&right)));
+                stack.push((l, right));
+            }
+            (None, Some((l, r))) => {
+                stack.push((Arc::clone(&left), r));
+                stack.push((left, l));
+            }
+            (Some((l, r)), Some(_)) if left.radius >= right.radius => {
+                stack.push((r, Arc::clone(&right)));
+                stack.push((l, right));
+            }
+            (Some(_), Some((l, r))) => {
+                stack.push((Arc::clone(&left), r));
+                stack.push((left, l));
+            }
+        }
+    }
+}
+
+/// Returns the distance between the centers of the clusters, which may be from trees over different datasets.
+fn center_distance<T: Number, U: Number>(left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>) -> U {
+    if Arc::ptr_eq(&left.dataset, &right.dataset) {
+        left.dataset.distance(left.argcenter, right.argcenter)
+    } else {
+        left.dataset.metric().distance(&left.center(), &right.center())
+    }
+}
+
+/// Returns the distances from the instances in the left cluster to those in the right cluster.
+pub(crate) fn leaf_distances<T: Number, U: Number>(left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>) -> Array2<U> {
+    if Arc::ptr_eq(&left.dataset, &right.dataset) {
+        left.dataset.distances_among(&left.indices, &right.indices)
+    } else {
+        let metric = left.dataset.metric();
+        let left_instances: Vec<_> = left.indices.iter().map(|&i| left.dataset.instance(i)).collect();
+        let right_instances: Vec<_> = right.indices.iter().map(|&j| right.dataset.instance(j)).collect();
+        Array2::from_shape_fn((left.indices.len(), right.indices.len()), |(i, j)| {
+            metric.distance(&left_instances[i], &right_instances[j])
+        })
+    }
+}
@@ -0,0 +1,271 @@
+//! Joins of search trees: the k-nearest-neighbors self-join, rho-nearest joins and pair counting.
+
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::Cakes;
+
+use super::cakes::insert_hit;
+use super::dual_tree::dual_tree;
+use super::dual_tree::leaf_distances;
+use super::dual_tree::DualTreeVisitor;
+
+impl<T: 'static + Number, U: 'static + Number> Cakes<T, U> {
+    /// Returns the `k` nearest neighbors of every instance in the tree, other than the instance itself.
+    /// The neighbors of the instance at index `i` are at position `i`, sorted by increasing distance with ties broken
+    /// by index, as in `knn`.
+    ///
+    /// Instead of searching for each instance on its own, the instances of each leaf are searched for together.
+    /// A cluster is pruned for all of them at once when the distance between its center and that of the leaf,
+    /// less both radii, exceeds the distance to the k-th nearest neighbor found so far for every instance in the leaf.
+    pub fn self_knn(&self, k: usize) -> Vec<Vec<(Index, U)>> {
+        let mut neighbors = vec![vec![]; self.dataset.cardinality()];
+        let k = std::cmp::min(k, self.root.cardinality.saturating_sub(1));
+        if k == 0 {
+            return neighbors;
+        }
+
+        let mut leaves = self.root.flatten_tree();
+        leaves.push(Arc::clone(&self.root));
+        leaves.retain(|cluster| read_cache(&cluster.children).is_none());
+
+        let joined: Vec<_> = leaves.par_iter().flat_map(|leaf| self.leaf_knn(leaf, k)).collect();
+        for (i, hits) in joined {
+            neighbors[i] = hits;
+        }
+        neighbors
+    }
+
+    /// Returns every pair of an instance in this tree and an instance in the `other` tree that are within `radius` of
+    /// each other, as `(index in this tree, index in the other tree, distance)`, sorted by the indices.
+    ///
+    /// The other tree must be over instances of the same type and with the same metric, but may be over another
+    /// dataset. Pairs of clusters whose centers are farther apart than the sum of their radii and `radius` are pruned.
+    pub fn rnn_join(&self, other: &Cakes<T, U>, radius: U) -> Vec<(Index, Index, U)> {
+        let mut join = RnnJoin { radius, pairs: vec![] };
+        dual_tree(&self.root, &other.root, &mut join);
+        join.pairs.sort_by(|(a, b, _), (c, d, _)| a.cmp(c).then(b.cmp(d)));
+        join.pairs
+    }
+
+    /// Returns every pair of distinct instances in the tree that are within `radius` of each other,
+    /// as `(i, j, distance)` with `i < j`, sorted by the indices.
+    pub fn self_rnn_join(&self, radius: U) -> Vec<(Index, Index, U)> {
+        let mut join = RnnJoin { radius, pairs: vec![] };
+        dual_tree(&self.root, &self.root, &mut join);
+        let mut pairs: Vec<_> = join
+            .pairs
+            .into_iter()
+            .filter(|&(i, j, _)| i != j)
+            .map(|(i, j, d)| if i < j { (i, j, d) } else { (j, i, d) })
+            .collect();
+        pairs.sort_by(|(a, b, _), (c, d, _)| a.cmp(c).then(b.cmp(d)));
+        pairs.dedup_by(|(a, b, _), (c, d, _)| a == c && b == d);
+        pairs
+    }
+
+    /// Returns the number of unordered pairs of distinct instances in the tree that are within `radius` of each
+    /// other, i.e. the length of `self_rnn_join`.
+    ///
+    /// Pairs of clusters that lie entirely within `radius` of each other are counted without computing the distances
+    /// between their instances.
+    pub fn count_pairs(&self, radius: U) -> usize {
+        let mut counter = PairCounter { radius, count: 0 };
+        dual_tree(&self.root, &self.root, &mut counter);
+        counter.count
+    }
+
+    /// Returns the k nearest neighbors of each instance in the leaf.
+    fn leaf_knn(&self, leaf: &Arc<Cluster<T, U>>, k: usize) -> Vec<(Index, Vec<(Index, U)>)> {
+        let mut best = vec![Vec::with_capacity(k + 1); leaf.cardinality];
+
+        // Clusters to visit, along with the distance from their centers to the center of the leaf.
+        let mut stack = vec![(
+            Arc::clone(&self.root),
+            self.dataset.distance(leaf.argcenter, self.root.argcenter),
+        )];
+        while let Some((cluster, distance)) = stack.pop() {
+            let gap = distance.as_f64() - leaf.radius.as_f64() - cluster.radius.as_f64();
+            if gap > kth_bound(&best, k) {
+                continue;
+            }
+
+            match read_cache(&cluster.children).clone() {
+                Some((left, right)) => {
+                    let left_distance = self.dataset.distance(leaf.argcenter, left.argcenter);
+                    let right_distance = self.dataset.distance(leaf.argcenter, right.argcenter);
+                    // Visit the nearer child first, so that the bound tightens sooner.
+                    if left_distance <= right_distance {
+                        stack.push((right, right_distance));
+                        stack.push((left, left_distance));
+                    } else {
+                        stack.push((left, left_distance));
+                        stack.push((right, right_distance));
+                    }
+                }
+                None => {
+                    let distances = self.dataset.distances_among(&leaf.indices, &cluster.indices);
+                    for ((&q, row), hits) in leaf.indices.iter().zip(distances.outer_iter()).zip(best.iter_mut()) {
+                        for (&r, &d) in cluster.indices.iter().zip(row.iter()) {
+                            if r != q {
+                                insert_hit(hits, (r, d), k);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        leaf.indices.iter().copied().zip(best).collect()
+    }
+}
+
+/// Collects the pairs of instances within a radius of each other.
+struct RnnJoin<U: Number> {
+    radius: U,
+    pairs: Vec<(Index, Index, U)>,
+}
+
+impl<T: Number, U: Number> DualTreeVisitor<T, U> for RnnJoin<U> {
+    fn prune(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>, distance: U) -> bool {
+        distance > self.radius + left.radius + right.radius
+    }
+
+    fn visit(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>) {
+        let distances = leaf_distances(left, right);
+        for (&i, row) in left.indices.iter().zip(distances.outer_iter()) {
+            for (&j, &d) in right.indices.iter().zip(row.iter()) {
+                if d <= self.radius {
+                    self.pairs.push((i, j, d));
+                }
+            }
+        }
+    }
+}
+
+/// Counts the unordered pairs of distinct instances within a radius of each other, in a traversal of a tree with
+/// itself.
+struct PairCounter<U: Number> {
+    radius: U,
+    count: usize,
+}
+
+impl<T: Number, U: Number> DualTreeVisitor<T, U> for PairCounter<U> {
+    fn prune(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>, distance: U) -> bool {
+        if distance > self.radius + left.radius + right.radius {
+            true
+        } else if distance + left.radius + right.radius <= self.radius {
+            self.count += if Arc::ptr_eq(left, right) {
+                left.cardinality * (left.cardinality - 1) / 2
+            } else {
+                left.cardinality * right.cardinality
+            };
+            true
+        } else {
+            false
+        }
+    }
+
+    fn visit(&mut self, left: &Arc<Cluster<T, U>>, right: &Arc<Cluster<T, U>>) {
+        let same = Arc::ptr_eq(left, right);
+        let distances = leaf_distances(left, right);
+        for (a, row) in distances.outer_iter().enumerate() {
+            let start = if same { a + 1 } else { 0 };
+            self.count += row.iter().skip(start).filter(|&&d| d <= self.radius).count();
+        }
+    }
+}
+
+/// Returns the largest distance from any instance in the leaf to its k-th nearest neighbor so far.
+fn kth_bound<U: Number>(best: &[Vec<(Index, U)>], k: usize) -> f64 {
+    best.iter()
+        .map(|hits| {
+            if hits.len() < k {
+                f64::INFINITY
+            } else {
+                hits[k - 1].1.as_f64()
+            }
+        })
+        .fold(0., f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    #[test]
+    fn test_self_knn() {
+        // Every instance is duplicated, so there are ties at distance zero.
+        let data: Vec<_> = (0..300)
+            .map(|i| vec![(i % 150 * 37 % 101) as f64, (i % 150 % 7) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let search = Cakes::build(Arc::clone(&dataset), None, None);
+
+        for k in [1, 5, 20] {
+            let neighbors = search.self_knn(k);
+            assert_eq!(neighbors.len(), 300);
+            for (i, hits) in neighbors.iter().enumerate() {
+                let mut expected = search.knn(&dataset.instance(i), k + 1);
+                expected.retain(|&(j, _)| j != i);
+                expected.truncate(k);
+                assert_eq!(hits, &expected, "instance {} with k = {}", i, k);
+            }
+        }
+
+        assert!(search.self_knn(0).iter().all(|hits| hits.is_empty()));
+        assert!(search.self_knn(1000).iter().all(|hits| hits.len() == 299));
+    }
+
+    #[test]
+    fn test_rnn_joins() {
+        let data: Vec<_> = (0..300)
+            .map(|i| vec![(i % 150 * 37 % 101) as f64, (i % 150 % 7) as f64])
+            .collect();
+        let others: Vec<_> = (0..120)
+            .map(|i| vec![(i * 13 % 97) as f64 + 0.5, (i % 5) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false));
+        let other: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(RowMajor::new(Arc::new(others.clone()), Arc::clone(&metric), false));
+        let search = Cakes::build(Arc::clone(&dataset), None, None);
+        let other_search = Cakes::build(other, Some(5), None);
+
+        for radius in [0., 1.5, 6., 40.] {
+            let mut expected = vec![];
+            for (i, x) in data.iter().enumerate() {
+                for (j, y) in others.iter().enumerate() {
+                    let distance = metric.distance(x, y);
+                    if distance <= radius {
+                        expected.push((i, j, distance));
+                    }
+                }
+            }
+            assert_eq!(search.rnn_join(&other_search, radius), expected, "radius {}", radius);
+
+            let mut expected = vec![];
+            for i in 0..data.len() {
+                for j in i + 1..data.len() {
+                    let distance = dataset.distance(i, j);
+                    if distance <= radius {
+                        expected.push((i, j, distance));
+                    }
+                }
+            }
+            assert_eq!(search.self_rnn_join(radius), expected, "radius {}", radius);
+            assert_eq!(search.count_pairs(radius), expected.len(), "radius {}", radius);
+        }
+
+        assert_eq!(search.count_pairs(search.diameter()), 300 * 299 / 2);
+    }
+}
@@ -0,0 +1,133 @@
+//! Orders in which k-nearest searches scan the instances of the leaves they reach.
+//!
+//! By the triangle inequality, `d(q, x) >= |d(q, c) - d(c, x)|` for a query `q` and an instance `x` of a leaf with
+//! center `c`. With the distances from the center of each leaf to its instances stored, a search that knows `d(q, c)`
+//! can scan the instances in order of that bound, so that the nearest instances of the leaf tend to come first and
+//! tighten the distance to the k-th nearest hit early, and stop as soon as the bound exceeds it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::compare_distances;
+
+use super::cache::QueryCache;
+use super::metadata::leaf_clusters;
+
+/// How `knn` scans the instances of the leaves it reaches. See `Cakes::with_leaf_scan`. The hits do not depend on the
+/// scan, as long as the metric obeys the triangle inequality.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeafScan {
+    /// Every instance of the leaf is compared to the query, in parallel.
+    #[default]
+    Exhaustive,
+    /// The instances are compared to the query one at a time, in order of the least distance to the query that their
+    /// distances to the center of the leaf allow, until that bound exceeds the distance to the k-th nearest hit. This
+    /// computes fewer distances, but on one thread, so it suits expensive metrics and large leaves.
+    SortedByCenterDistance,
+}
+
+/// The distances from the center of each leaf of a tree to the instances of the leaf, sorted by distance.
+#[derive(Debug, Clone)]
+pub(crate) struct CenterDistances<U: Number> {
+    leaves: HashMap<BitVec, Vec<(U, Index)>>,
+    /// The fingerprint of the tree the distances were computed for, so that those of a replaced tree are not used.
+    tree: u64,
+}
+
+impl<U: Number> CenterDistances<U> {
+    /// Computes the distances from the center of every leaf of the tree under `root` to its instances.
+    pub fn build<T: Number>(root: &Arc<Cluster<T, U>>) -> Self {
+        let leaves = leaf_clusters(root)
+            .into_par_iter()
+            .map(|leaf| {
+                let distances = leaf.dataset.distances_from(leaf.argcenter, &leaf.indices);
+                let mut sorted: Vec<_> = distances.into_iter().zip(leaf.indices.iter().copied()).collect();
+                sorted.sort_by(|(a, _), (b, _)| compare_distances(a, b));
+                (leaf.name.clone(), sorted)
+            })
+            .collect();
+        CenterDistances {
+            leaves,
+            tree: QueryCache::<U>::tree_fingerprint(root),
+        }
+    }
+
+    /// Returns whether the distances were computed for the tree under `root`.
+    pub fn is_current<T: Number>(&self, root: &Arc<Cluster<T, U>>) -> bool {
+        self.tree == QueryCache::<U>::tree_fingerprint(root)
+    }
+
+    /// Returns the instances of the leaf, with their lower bounds on the distance to a query at `to_center` from the
+    /// center of the leaf, in increasing order of the bounds. Returns None if the distances of the leaf are unknown.
+    pub fn nearest_first<T: Number>(
+        &self,
+        leaf: &Cluster<T, U>,
+        to_center: f64,
+    ) -> Option<impl Iterator<Item = (f64, Index)> + '_> {
+        let sorted = self
+            .leaves
+            .get(&leaf.name)
+            .filter(|sorted| sorted.len() == leaf.indices.len())?;
+        // The instances nearer the center than the query are below `split`, and the others from it on, so the bounds
+        // rise walking away from `split` in either direction. The two walks are merged.
+        let split = sorted.partition_point(|(distance, _)| distance.as_f64() < to_center);
+        let (mut below, mut above) = (
+            sorted[..split].iter().rev().peekable(),
+            sorted[split..].iter().peekable(),
+        );
+        Some(std::iter::from_fn(move || {
+            let take_below = match (below.peek(), above.peek()) {
+                (Some((b, _)), Some((a, _))) => to_center - b.as_f64() <= a.as_f64() - to_center,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let &(distance, index) = if take_below { below.next() } else { above.next() }?;
+            Some(((to_center - distance.as_f64()).abs(), index))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::LeafScan;
+
+    #[test]
+    fn test_sorted_leaf_scan() {
+        let data: Vec<_> = (0..2_000)
+            .map(|i| vec![(i * 37 % 101) as f64, (i * 11 % 97) as f64, (i % 23) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let exhaustive = Cakes::build(Arc::clone(&dataset), None, Some(50));
+        let sorted = Cakes::from_root(Arc::clone(&exhaustive.root), Arc::clone(&dataset), None)
+            .with_leaf_scan(LeafScan::SortedByCenterDistance);
+        assert_eq!(exhaustive.leaf_scan(), LeafScan::Exhaustive);
+        assert_eq!(sorted.leaf_scan(), LeafScan::SortedByCenterDistance);
+
+        // The hits are the same, for fewer distances.
+        let (mut exhaustive_distances, mut sorted_distances) = (0, 0);
+        for q in (0..dataset.cardinality()).step_by(97) {
+            let query: Vec<_> = dataset.instance(q).iter().map(|v| v + 0.5).collect();
+            for k in [1, 10] {
+                let (expected, exhaustive_trace) = exhaustive.knn_traced(&query, k);
+                let (hits, sorted_trace) = sorted.knn_traced(&query, k);
+                assert_eq!(hits, expected);
+                assert_eq!(sorted.knn(&query, k), expected);
+                exhaustive_distances += exhaustive_trace.num_distances();
+                sorted_distances += sorted_trace.num_distances();
+            }
+            assert_eq!(sorted.knn_beam(&query, 10, 4), exhaustive.knn_beam(&query, 10, 4));
+        }
+        assert!(sorted_distances < exhaustive_distances);
+    }
+}
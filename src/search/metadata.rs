@@ -0,0 +1,213 @@
+//! An inverted index from the metadata values of instances to the leaves of a search tree that hold them, so that
+//! lookups and filtered searches by metadata visit only those leaves instead of scanning the metadata of every
+//! instance.
+//!
+//! A metadata value is indexed by its terms. Each field of an object is a term, e.g. `{"species": "owl"}` of
+//! `{"species": "owl", "age": 3}`, and any other value, including an empty object, is a single term. An instance matches a filter if its metadata
+//! has every term of the filter, so a filter of one field matches every instance whose metadata has that field with
+//! that value.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::prelude::*;
+use crate::utils::read_cache;
+
+use super::cache::QueryCache;
+
+/// The instances with a metadata term, grouped by the names of the leaves that hold them.
+type Postings = BTreeMap<BitVec, Vec<Index>>;
+
+/// Returns the terms of a metadata value.
+fn terms(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => fields
+            .iter()
+            .map(|(key, value)| {
+                let mut field = serde_json::Map::new();
+                field.insert(key.clone(), value.clone());
+                Value::Object(field).to_string()
+            })
+            .collect(),
+        value => vec![value.to_string()],
+    }
+}
+
+/// Converts a filter to a metadata value.
+pub(crate) fn filter_value<M: Serialize + ?Sized>(filter: &M) -> Result<Value, String> {
+    serde_json::to_value(filter).map_err(|error| format!("Error: Failed to serialize the metadata filter. {}", error))
+}
+
+/// Returns whether the metadata has every term of the filter.
+pub(crate) fn matches(metadata: &Value, filter: &Value) -> bool {
+    let terms: HashSet<_> = terms(metadata).into_iter().collect();
+    self::terms(filter).iter().all(|term| terms.contains(term))
+}
+
+/// The leaves of a search tree, and the instances in them, that match a metadata filter.
+pub(crate) struct LeafFilter {
+    leaves: HashMap<BitVec, Vec<Index>>,
+    /// The names of the matching leaves and of all of their ancestors.
+    ancestors: HashSet<BitVec>,
+}
+
+impl LeafFilter {
+    fn new(leaves: HashMap<BitVec, Vec<Index>>) -> Self {
+        let mut ancestors = HashSet::new();
+        for name in leaves.keys() {
+            for depth in 1..=name.len() {
+                ancestors.insert(name[..depth].to_bitvec());
+            }
+        }
+        LeafFilter { leaves, ancestors }
+    }
+
+    /// Finds the matching instances by scanning the metadata of every instance in the tree.
+    pub fn scan<T: Number, U: Number>(root: &Arc<Cluster<T, U>>, filter: &Value) -> Self {
+        let mut leaves = HashMap::new();
+        for leaf in leaf_clusters(root) {
+            let indices: Vec<_> = leaf
+                .indices
+                .iter()
+                .copied()
+                .filter(|&i| root.dataset.instance_metadata(i).is_some_and(|m| matches(&m, filter)))
+                .collect();
+            if !indices.is_empty() {
+                leaves.insert(leaf.name.clone(), indices);
+            }
+        }
+        Self::new(leaves)
+    }
+
+    /// Returns whether the cluster is, or is an ancestor of, a leaf with matching instances.
+    pub fn admits<T: Number, U: Number>(&self, cluster: &Cluster<T, U>) -> bool {
+        self.ancestors.contains(&cluster.name)
+    }
+
+    /// Returns the matching instances in the given leaf.
+    pub fn indices(&self, leaf: &BitVec) -> &[Index] {
+        self.leaves.get(leaf).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns every matching instance, sorted by index.
+    pub fn all_indices(&self) -> Vec<Index> {
+        let mut indices: Vec<_> = self.leaves.values().flatten().copied().collect();
+        indices.sort_unstable();
+        indices
+    }
+}
+
+/// Returns the leaves of the tree under `root`.
+pub(super) fn leaf_clusters<T: Number, U: Number>(root: &Arc<Cluster<T, U>>) -> Vec<Arc<Cluster<T, U>>> {
+    let mut leaves = root.flatten_tree();
+    leaves.push(Arc::clone(root));
+    leaves.retain(|cluster| read_cache(&cluster.children).is_none());
+    leaves
+}
+
+/// An inverted index from the metadata terms of the instances in a search tree to the leaves that hold them.
+/// See `Cakes::with_metadata_index`.
+#[derive(Debug, Clone)]
+pub struct MetadataIndex {
+    postings: HashMap<String, Postings>,
+    /// The fingerprint of the tree the index was built from, so that an index of a replaced tree is not used.
+    tree: u64,
+}
+
+impl MetadataIndex {
+    /// Indexes the metadata of every instance in the leaves of the tree under `root`.
+    /// Instances without metadata are not indexed.
+    pub fn build<T: Number, U: Number>(root: &Arc<Cluster<T, U>>) -> Self {
+        let mut postings: HashMap<String, Postings> = HashMap::new();
+        for leaf in leaf_clusters(root) {
+            for &i in leaf.indices.iter() {
+                for term in root.dataset.instance_metadata(i).iter().flat_map(terms) {
+                    postings
+                        .entry(term)
+                        .or_default()
+                        .entry(leaf.name.clone())
+                        .or_default()
+                        .push(i);
+                }
+            }
+        }
+        MetadataIndex {
+            postings,
+            tree: QueryCache::<U>::tree_fingerprint(root),
+        }
+    }
+
+    /// Returns the number of distinct terms in the index.
+    pub fn num_terms(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Returns whether the index was built from the tree under `root`.
+    pub fn is_current<T: Number, U: Number>(&self, root: &Arc<Cluster<T, U>>) -> bool {
+        self.tree == QueryCache::<U>::tree_fingerprint(root)
+    }
+
+    /// Returns the names of the leaves that hold instances matching the filter, in sorted order.
+    pub fn leaves(&self, filter: &Value) -> Vec<BitVec> {
+        let mut leaves: Vec<_> = self.leaf_filter(filter).leaves.into_keys().collect();
+        leaves.sort();
+        leaves
+    }
+
+    /// Returns the indices of the instances matching the filter, in sorted order.
+    pub fn indices(&self, filter: &Value) -> Vec<Index> {
+        self.leaf_filter(filter).all_indices()
+    }
+
+    /// Intersects the postings of the terms of the filter, starting from the term with the fewest instances.
+    pub(crate) fn leaf_filter(&self, filter: &Value) -> LeafFilter {
+        let mut postings = Vec::new();
+        for term in terms(filter) {
+            match self.postings.get(&term) {
+                Some(posting) => postings.push(posting),
+                None => return LeafFilter::new(HashMap::new()),
+            }
+        }
+        postings.sort_by_key(|posting| posting.values().map(Vec::len).sum::<usize>());
+
+        let mut leaves = HashMap::new();
+        if let Some((first, rest)) = postings.split_first() {
+            for (name, indices) in first.iter() {
+                let mut indices = indices.clone();
+                for posting in rest {
+                    let others: HashSet<_> = posting.get(name).into_iter().flatten().collect();
+                    indices.retain(|i| others.contains(i));
+                }
+                if !indices.is_empty() {
+                    leaves.insert(name.clone(), indices);
+                }
+            }
+        }
+        LeafFilter::new(leaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::matches;
+
+    #[test]
+    fn test_matches() {
+        let owl = json!({"species": "owl", "age": 3});
+        assert!(matches(&owl, &json!({"species": "owl"})));
+        assert!(matches(&owl, &json!({"age": 3, "species": "owl"})));
+        assert!(!matches(&owl, &json!({"species": "owl", "age": 4})));
+        assert!(!matches(&owl, &json!("owl")));
+        assert!(matches(&json!("owl"), &json!("owl")));
+        assert!(!matches(&json!(["owl", 3]), &json!("owl")));
+        assert!(!matches(&owl, &json!({})));
+    }
+}
@@ -1,5 +1,20 @@
+pub use audit::AuditReport;
+pub use batch::BatchPolicy;
 pub use cakes::Cakes;
 pub use codec::CompressibleDataset;
+pub use leaf_scan::LeafScan;
+pub use metadata::MetadataIndex;
 
+#[cfg(feature = "admission")]
+pub mod admission;
+mod audit;
+mod batch;
+mod cache;
 mod cakes;
 pub mod codec;
+mod dual_tree;
+mod join;
+mod leaf_scan;
+mod metadata;
+pub mod numa;
+pub mod trace;
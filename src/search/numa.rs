@@ -0,0 +1,423 @@
+//! NUMA-aware placement of search trees and datasets on machines with several sockets.
+//!
+//! On such machines memory is attached to the NUMA node of each socket, and reading the memory of another node is
+//! slower than reading local memory, so searches whose threads roam over all of it are capped by the links between
+//! sockets. A `NumaCakes` keeps a replica of the search tree, which is small, on every node, and splits the instances
+//! of the dataset among the nodes by leaf, so that each instance is still held only once. Every node has a pool of
+//! threads pinned to its CPUs. A query is searched with the replica of the node that holds the leaf nearest to it, and
+//! the leaves of a rho-nearest search are scanned by the threads of the nodes that hold them.
+//!
+//! Memory is placed by the first-touch policy of Linux, since the replicas and the instances of each node are
+//! allocated by the pinned threads of that node. The nodes are read from `/sys/devices/system/node` on Linux.
+//! Elsewhere, or if those files cannot be read, the machine is treated as one node and threads are not pinned.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::Cakes;
+
+/// A Vec of tuples of index of hit and its distance to the query.
+type Hits<U> = Vec<(Index, U)>;
+
+/// A NUMA node and the CPUs attached to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// Returns the NUMA nodes of the machine that have CPUs, or a single node with every CPU if they cannot be read.
+pub fn numa_nodes() -> Vec<NumaNode> {
+    match read_nodes() {
+        Ok(nodes) if !nodes.is_empty() => nodes,
+        _ => {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            vec![NumaNode {
+                id: 0,
+                cpus: (0..cpus).collect(),
+            }]
+        }
+    }
+}
+
+fn read_nodes() -> Result<Vec<NumaNode>, String> {
+    let directory = std::path::Path::new("/sys/devices/system/node");
+    let entries = std::fs::read_dir(directory).map_err(|error| format!("Could not read {:?}: {}", directory, error))?;
+    let mut nodes = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(id) = name.strip_prefix("node").and_then(|id| id.parse().ok()) {
+            let path = entry.path().join("cpulist");
+            let list =
+                std::fs::read_to_string(&path).map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+            let cpus = parse_cpu_list(&list)?;
+            if !cpus.is_empty() {
+                nodes.push(NumaNode { id, cpus });
+            }
+        }
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+/// Parses a list of CPUs in the format of Linux, e.g. `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let parse = |cpu: &str| {
+        cpu.parse::<usize>()
+            .map_err(|_| format!("Bad CPU '{}' in the list '{}'.", cpu, list.trim()))
+    };
+    let mut cpus = vec![];
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(parse(first)?..=parse(last)?),
+            None => cpus.push(parse(range)?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Pins the current thread to the given CPUs. Failures are logged rather than returned, since an unpinned thread
+/// still searches correctly.
+#[cfg(target_os = "linux")]
+fn pin_to(cpus: &[usize]) {
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        log::warn!(
+            "Failed to pin a thread to the CPUs {:?}. {}",
+            cpus,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to(_cpus: &[usize]) {}
+
+/// The instances of a dataset, split among NUMA nodes.
+struct NumaDataset<T: Number, U: Number> {
+    metric: Arc<dyn Metric<T, U>>,
+    /// The instances held by each node.
+    blocks: Vec<Vec<Vec<T>>>,
+    /// The node holding each instance and its position among the instances of that node.
+    owners: Vec<(usize, usize)>,
+    dimensionality: usize,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for NumaDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("NumaDataset")
+            .field("data-cardinality", &self.owners.len())
+            .field("data-dimensionality", &self.dimensionality)
+            .field("nodes", &self.blocks.len())
+            .finish()
+    }
+}
+
+impl<T: Number, U: Number> NumaDataset<T, U> {
+    fn get(&self, index: Index) -> &[T] {
+        let (node, position) = self.owners[index];
+        &self.blocks[node][position]
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for NumaDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.owners.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.owners.len()).collect()
+    }
+
+    fn instance(&self, i: Index) -> Vec<T> {
+        self.get(i).to_vec()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(self.get(left), self.get(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+/// The replica of the search tree on one NUMA node, and the threads pinned to it.
+struct Replica<T: Number, U: Number> {
+    node: NumaNode,
+    pool: rayon::ThreadPool,
+    cakes: Cakes<T, U>,
+}
+
+/// A search tree replicated on every NUMA node of the machine over a dataset split among them. See the module docs.
+///
+/// The hits of every search are those of the same search on the original tree.
+pub struct NumaCakes<T: Number, U: Number> {
+    replicas: Vec<Replica<T, U>>,
+    dataset: Arc<NumaDataset<T, U>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for NumaCakes<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("NumaCakes")
+            .field("dataset", &self.dataset)
+            .field("nodes", &self.replicas.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> NumaCakes<T, U> {
+    /// Replicates the search tree on every NUMA node of the machine. See `numa_nodes`.
+    ///
+    /// Returns an Err if the threads of a node cannot be started.
+    pub fn new(cakes: &Cakes<T, U>) -> Result<Self, String> {
+        Self::with_nodes(cakes, numa_nodes())
+    }
+
+    /// Replicates the search tree on each of the given nodes, e.g. on a subset of the nodes of the machine.
+    ///
+    /// The leaves of the tree, in depth-first order, are split into runs of about equal cardinality, one per node, and
+    /// each node holds the instances of its run, copied from the dataset of the tree.
+    ///
+    /// Returns an Err if there are no nodes, if a node has no CPUs, or if the threads of a node cannot be started.
+    pub fn with_nodes(cakes: &Cakes<T, U>, nodes: Vec<NumaNode>) -> Result<Self, String> {
+        if nodes.is_empty() || nodes.iter().any(|node| node.cpus.is_empty()) {
+            return Err("There must be at least one NUMA node, and every node must have a CPU.".to_string());
+        }
+        let pools = nodes
+            .iter()
+            .map(|node| {
+                let (id, cpus) = (node.id, node.cpus.clone());
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(node.cpus.len())
+                    .thread_name(move |i| format!("clam-numa-{}-{}", id, i))
+                    .start_handler(move |_| pin_to(&cpus))
+                    .build()
+                    .map_err(|error| format!("Error: Failed to start the threads of NUMA node {}. {}", id, error))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Each node takes the next leaves until it holds its share of the instances. Instances in no leaf go to the
+        // first node.
+        let mut leaves = cakes.root.flatten_tree();
+        leaves.push(Arc::clone(&cakes.root));
+        leaves.retain(|cluster| read_cache(&cluster.children).is_none());
+        leaves.sort_by(|a, b| a.name.cmp(&b.name));
+        let share = cakes.root.cardinality.div_ceil(nodes.len()).max(1);
+        let mut members = vec![vec![]; nodes.len()];
+        let mut assigned = 0;
+        for leaf in leaves {
+            members[std::cmp::min(assigned / share, nodes.len() - 1)].extend_from_slice(&leaf.indices);
+            assigned += leaf.cardinality;
+        }
+        let mut owners = vec![None; cakes.dataset.cardinality()];
+        for (node, indices) in members.iter().enumerate() {
+            for (position, &i) in indices.iter().enumerate() {
+                owners[i] = Some((node, position));
+            }
+        }
+        for (i, owner) in owners.iter_mut().enumerate().filter(|(_, owner)| owner.is_none()) {
+            *owner = Some((0, members[0].len()));
+            members[0].push(i);
+        }
+
+        // The instances of each node are copied by its own threads, so that they are placed in its memory.
+        let blocks = pools
+            .iter()
+            .zip(members.iter())
+            .map(|(pool, indices)| pool.install(|| indices.par_iter().map(|&i| cakes.dataset.instance(i)).collect()))
+            .collect();
+        let dataset = Arc::new(NumaDataset {
+            metric: cakes.dataset.metric(),
+            blocks,
+            owners: owners.into_iter().flatten().collect(),
+            dimensionality: cakes.dataset.dimensionality(),
+        });
+
+        let replicas = nodes
+            .into_iter()
+            .zip(pools)
+            .map(|(node, pool)| {
+                let shared: Arc<dyn Dataset<T, U>> = dataset.clone();
+                let root = pool.install(|| crate::io::attach(&cakes.root, Arc::clone(&shared)));
+                let cakes = Cakes::from_root(root, shared, cakes.report.clone());
+                Replica { node, pool, cakes }
+            })
+            .collect();
+        Ok(NumaCakes { replicas, dataset })
+    }
+
+    /// Returns the nodes holding the replicas.
+    pub fn nodes(&self) -> Vec<&NumaNode> {
+        self.replicas.iter().map(|replica| &replica.node).collect()
+    }
+
+    /// Returns the number of instances held by each node, in the order of `nodes`.
+    pub fn node_cardinalities(&self) -> Vec<usize> {
+        self.dataset.blocks.iter().map(Vec::len).collect()
+    }
+
+    /// Returns the position, in `nodes`, of the node that holds the leaf reached by descending from the root to the
+    /// child whose center is nearer the query. The query is searched on that node.
+    pub fn home_node(&self, query: &[T]) -> usize {
+        let metric = self.dataset.metric();
+        let mut cluster = Arc::clone(&self.replicas[0].cakes.root);
+        loop {
+            let children = read_cache(&cluster.children).clone();
+            match children {
+                Some((left, right)) => {
+                    let left_distance = metric.distance(query, self.dataset.get(left.argcenter));
+                    let right_distance = metric.distance(query, self.dataset.get(right.argcenter));
+                    cluster = if left_distance <= right_distance { left } else { right };
+                }
+                None => return self.dataset.owners[cluster.argcenter].0,
+            }
+        }
+    }
+
+    /// Performs `Cakes::rnn`. The tree is searched on the home node of the query, and the instances of the leaves it
+    /// finds are scanned by the threads of the nodes holding them.
+    pub fn rnn(&self, query: &[T], radius: Option<U>) -> Hits<U> {
+        let home = &self.replicas[self.home_node(query)];
+        let clusters = home.pool.install(|| home.cakes.tree_search(query, radius));
+
+        let mut candidates = vec![vec![]; self.replicas.len()];
+        for &i in clusters.iter().flat_map(|cluster| cluster.indices.iter()) {
+            candidates[self.dataset.owners[i].0].push(i);
+        }
+        // The leaves of each node are consecutive in depth-first order, so the hits stay in the order of `Cakes::rnn`.
+        candidates
+            .into_par_iter()
+            .zip(self.replicas.par_iter())
+            .filter(|(indices, _)| !indices.is_empty())
+            .flat_map_iter(|(indices, replica)| {
+                replica
+                    .pool
+                    .install(|| replica.cakes.linear_search(query, radius, Some(indices)))
+            })
+            .collect()
+    }
+
+    /// Performs `Cakes::knn` on the home node of the query.
+    pub fn knn(&self, query: &[T], k: usize) -> Hits<U> {
+        let home = &self.replicas[self.home_node(query)];
+        home.pool.install(|| home.cakes.knn(query, k))
+    }
+
+    /// Performs `rnn` for each query. The hits for `queries[i]` are at position `i`.
+    pub fn batch_rnn(&self, queries: &[Vec<T>], radius: Option<U>) -> Vec<Hits<U>> {
+        self.batch(queries, |query| self.rnn(query, radius))
+    }
+
+    /// Performs `knn` for each query. The hits for `queries[i]` are at position `i`.
+    pub fn batch_knn(&self, queries: &[Vec<T>], k: usize) -> Vec<Hits<U>> {
+        self.batch(queries, |query| self.knn(query, k))
+    }
+
+    /// Searches the queries of each home node on the threads of that node, and all nodes at once.
+    fn batch(&self, queries: &[Vec<T>], search: impl Fn(&[T]) -> Hits<U> + Sync) -> Vec<Hits<U>> {
+        let mut groups = vec![vec![]; self.replicas.len()];
+        for (position, query) in queries.iter().enumerate() {
+            groups[self.home_node(query)].push(position);
+        }
+
+        let mut hits = vec![vec![]; queries.len()];
+        let searched: Vec<Vec<_>> = groups
+            .into_par_iter()
+            .zip(self.replicas.par_iter())
+            .map(|(positions, replica)| {
+                replica.pool.install(|| {
+                    positions
+                        .into_par_iter()
+                        .map(|position| (position, search(&queries[position])))
+                        .collect()
+                })
+            })
+            .collect();
+        for (position, query_hits) in searched.into_iter().flatten() {
+            hits[position] = query_hits;
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::numa_nodes;
+    use super::parse_cpu_list;
+    use super::NumaCakes;
+    use super::NumaNode;
+
+    #[test]
+    fn test_numa_cakes() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("\n").unwrap(), Vec::<usize>::new());
+        assert!(parse_cpu_list("0-a").is_err());
+        assert!(numa_nodes().iter().all(|node| !node.cpus.is_empty()));
+
+        let data: Vec<_> = (0..1_000)
+            .map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data.clone()), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+
+        // Several nodes may share the CPUs of this machine, so that the data is split among them.
+        let cpus = numa_nodes()[0].cpus.clone();
+        let nodes: Vec<_> = (0..3).map(|id| NumaNode { id, cpus: cpus.clone() }).collect();
+        let numa = NumaCakes::with_nodes(&cakes, nodes).unwrap();
+        let cardinalities = numa.node_cardinalities();
+        assert_eq!(cardinalities.iter().sum::<usize>(), data.len());
+        assert!(cardinalities.iter().all(|&cardinality| cardinality > 0));
+
+        let queries: Vec<_> = data.iter().step_by(11).cloned().collect();
+        let rnn: Vec<_> = queries.iter().map(|query| cakes.rnn(query, Some(8.))).collect();
+        let knn: Vec<_> = queries.iter().map(|query| cakes.knn(query, 10)).collect();
+        assert_eq!(numa.batch_rnn(&queries, Some(8.)), rnn);
+        assert_eq!(numa.batch_knn(&queries, 10), knn);
+        assert_eq!(numa.rnn(&queries[3], Some(8.)), rnn[3]);
+
+        assert!(NumaCakes::with_nodes(&cakes, vec![]).is_err());
+        assert!(NumaCakes::with_nodes(&cakes, vec![NumaNode { id: 0, cpus: vec![] }]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Traces of searches, for offline analysis of how the search tree prunes, and for reproducing queries that search
+//! slowly or wrongly.
+//!
+//! A trace records the query, the parameters of the search and, in the order the search made them, its decisions about
+//! clusters and the distances it computed to instances. `Cakes::rnn_traced` and `Cakes::knn_traced` return the trace
+//! of one search, and `Cakes::with_tracing` writes the trace of every `rnn` and `knn` to a file. Traces are written and
+//! read with `io::trace_to_bytes`, `io::save_trace` and their counterparts.
+//!
+//! The traced searches run their traversal on one thread, so that the events are in a fixed order, and bypass the
+//! cache of search results. Their hits are the same as those of the untraced searches.
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use bitvec::prelude::*;
+
+use crate::prelude::*;
+use crate::utils::lock_cache;
+use crate::Cakes;
+
+/// A Vec of tuples of index of hit and its distance to the query.
+type Hits<U> = Vec<(Index, U)>;
+
+/// What a search did with a cluster once it had bounded the distance from the query to the instances in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The cluster could not hold a hit, so its subtree was skipped.
+    Pruned,
+    /// The search descended into the children of the cluster.
+    Entered,
+    /// The cluster is a leaf whose instances were searched.
+    Scanned,
+}
+
+/// One step of a search.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent<U: Number> {
+    /// The search bounded the distance from the query to the instances of the named cluster from below, by the
+    /// distance to its center less its radius or by zero, and made a decision about it.
+    Cluster {
+        name: BitVec,
+        bound: f64,
+        decision: Decision,
+    },
+    /// The search computed the distance from the query to an instance.
+    Instance { index: Index, distance: U },
+}
+
+/// The kind and parameters of a traced search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TracedSearch<U: Number> {
+    /// `Cakes::rnn` with the given radius.
+    Rnn { radius: U },
+    /// `Cakes::knn` with the given `k`.
+    Knn { k: usize },
+}
+
+/// The trace of one search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTrace<T: Number, U: Number> {
+    pub search: TracedSearch<U>,
+    pub query: Vec<T>,
+    pub events: Vec<TraceEvent<U>>,
+}
+
+impl<T: 'static + Number, U: 'static + Number> SearchTrace<T, U> {
+    /// Returns the number of clusters the search made the given decision about.
+    pub fn count(&self, decision: Decision) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event, TraceEvent::Cluster { decision: d, .. } if *d == decision))
+            .count()
+    }
+
+    /// Returns the number of distances the search computed to instances.
+    pub fn num_distances(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event, TraceEvent::Instance { .. }))
+            .count()
+    }
+
+    /// Repeats the traced search on the given tree, e.g. to reproduce a reported query, and returns its hits and its new
+    /// trace.
+    pub fn replay(&self, cakes: &Cakes<T, U>) -> (Hits<U>, SearchTrace<T, U>) {
+        match self.search {
+            TracedSearch::Rnn { radius } => cakes.rnn_traced(&self.query, Some(radius)),
+            TracedSearch::Knn { k } => cakes.knn_traced(&self.query, k),
+        }
+    }
+}
+
+/// Collects the events of a search.
+pub(crate) struct Tracer<U: Number> {
+    events: Mutex<Vec<TraceEvent<U>>>,
+}
+
+impl<U: Number> Tracer<U> {
+    pub fn new() -> Self {
+        Tracer {
+            events: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn cluster<T: Number>(&self, cluster: &Cluster<T, U>, bound: f64, decision: Decision) {
+        lock_cache(&self.events).push(TraceEvent::Cluster {
+            name: cluster.name.clone(),
+            bound: bound.max(0.),
+            decision,
+        });
+    }
+
+    pub fn instance(&self, index: Index, distance: U) {
+        lock_cache(&self.events).push(TraceEvent::Instance { index, distance });
+    }
+
+    pub fn finish<T: Number>(self, search: TracedSearch<U>, query: &[T]) -> SearchTrace<T, U> {
+        SearchTrace {
+            search,
+            query: query.to_vec(),
+            events: self
+                .events
+                .into_inner()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        }
+    }
+}
+
+/// Writes the trace of every search to a numbered file in a directory. See `Cakes::with_tracing`.
+pub(crate) struct SearchTracing {
+    directory: PathBuf,
+    count: AtomicU64,
+}
+
+impl SearchTracing {
+    pub fn new(directory: PathBuf) -> Self {
+        SearchTracing {
+            directory,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes the trace to the next file, e.g. `trace-000042.bin`. Failures are logged rather than returned, so that
+    /// tracing never fails a search.
+    pub fn save<T: Number, U: Number>(&self, trace: &SearchTrace<T, U>) {
+        let number = self.count.fetch_add(1, Ordering::Relaxed);
+        let path = self.directory.join(format!("trace-{:06}.bin", number));
+        if let Err(error) = crate::io::save_trace(trace, &path) {
+            log::warn!("Failed to save the trace of a search: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::io;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::Decision;
+    use super::TraceEvent;
+    use super::TracedSearch;
+
+    #[test]
+    fn test_traces() {
+        let data: Vec<_> = (0..500).map(|i| vec![(i * 37 % 101) as f64, (i % 23) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let query = dataset.instance(42);
+
+        // Traced searches find the same hits, and count every distance to an instance and every pruned cluster.
+        let (hits, trace) = cakes.rnn_traced(&query, Some(10.));
+        assert_eq!(hits, cakes.rnn(&query, Some(10.)));
+        assert_eq!(trace.search, TracedSearch::Rnn { radius: 10. });
+        assert!(trace.count(Decision::Pruned) > 0 && trace.count(Decision::Scanned) > 0);
+        assert!(trace.num_distances() >= hits.len() && trace.num_distances() < dataset.cardinality());
+        assert!(trace.events.iter().all(|event| match event {
+            TraceEvent::Cluster { bound, decision, .. } => (*bound <= 10.) == (*decision != Decision::Pruned),
+            TraceEvent::Instance { .. } => true,
+        }));
+
+        let (hits, trace) = cakes.knn_traced(&query, 10);
+        assert_eq!(hits, cakes.knn(&query, 10));
+        assert_eq!(trace.replay(&cakes), (hits, trace.clone()));
+
+        // Traces survive a round trip through bytes, and with tracing every search writes one.
+        let bytes = io::trace_to_bytes(&trace);
+        assert_eq!(io::trace_from_bytes::<f64, f64>(&bytes).unwrap(), trace);
+        assert!(io::trace_from_bytes::<f32, f64>(&bytes).is_err());
+        assert!(io::trace_from_bytes::<f64, f64>(&bytes[..bytes.len() - 1]).is_err());
+
+        let directory = std::env::temp_dir().join(format!("clam-test-traces-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let traced = Cakes::build(Arc::clone(&dataset), None, None).with_tracing(&directory);
+        traced.rnn(&query, Some(10.));
+        traced.knn(&query, 10);
+        let second = io::load_trace::<f64, f64>(&directory.join("trace-000001.bin")).unwrap();
+        assert_eq!(second.search, TracedSearch::Knn { k: 10 });
+        assert_eq!(second.query, query);
+        assert!(directory.join("trace-000000.bin").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+//! A `Dataset` over Arrow record batches and Parquet files, behind the `arrow` feature.
+//!
+//! Some columns of the batches hold the features of the instances and the others are kept as the metadata of each
+//! instance. The instances are read from the Arrow buffers as they are needed, so the batches are never copied into
+//! rows or into an `Array2`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::Array;
+use arrow_array::ArrowNativeTypeOp;
+use arrow_array::ArrowPrimitiveType;
+use arrow_array::FixedSizeListArray;
+use arrow_array::RecordBatch;
+use arrow_schema::DataType;
+use arrow_schema::SchemaRef;
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::prelude::*;
+
+/// A `Number` that Arrow stores in primitive arrays, i.e. every `Number`.
+pub trait ArrowNumber: Number + ArrowNativeTypeOp {
+    type ArrowType: ArrowPrimitiveType<Native = Self>;
+}
+
+macro_rules! impl_arrow_number {
+    ($($ty:ty: $arrow:ty),*) => {
+        $(
+            impl ArrowNumber for $ty {
+                type ArrowType = $arrow;
+            }
+        )*
+    }
+}
+
+impl_arrow_number!(
+    f32: Float32Type,
+    f64: Float64Type,
+    u8: UInt8Type,
+    i8: Int8Type,
+    u16: UInt16Type,
+    i16: Int16Type,
+    u32: UInt32Type,
+    i32: Int32Type,
+    u64: UInt64Type,
+    i64: Int64Type
+);
+
+/// The values of one feature column in one batch.
+#[derive(Debug, Clone)]
+enum FeatureColumn<T: ArrowNumber> {
+    /// A column of `T`, with one feature per row.
+    Scalar(arrow_array::PrimitiveArray<T::ArrowType>),
+    /// A column of fixed-size lists of `T`, with `width` features per row.
+    List(FixedSizeListArray, arrow_array::PrimitiveArray<T::ArrowType>, usize),
+}
+
+impl<T: ArrowNumber> FeatureColumn<T> {
+    /// Reads the column with the given name from the batch. Returns an Err if it does not hold `T`, or lists of `T`,
+    /// or if it has any nulls.
+    fn read(batch: &RecordBatch, name: &str) -> Result<Self, String> {
+        let column = batch
+            .column_by_name(name)
+            .ok_or_else(|| format!("There is no column named '{}'.", name))?;
+        let wrong_type = || {
+            format!(
+                "Column '{}' holds {} but {} was requested.",
+                name,
+                column.data_type(),
+                T::type_name()
+            )
+        };
+        if column.null_count() > 0 {
+            return Err(format!("Column '{}' has {} nulls.", name, column.null_count()));
+        }
+        match column.data_type() {
+            DataType::FixedSizeList(_, width) => {
+                let list = column.as_fixed_size_list().clone();
+                let values = list
+                    .values()
+                    .as_primitive_opt::<T::ArrowType>()
+                    .ok_or_else(wrong_type)?
+                    .clone();
+                if values.null_count() > 0 {
+                    return Err(format!(
+                        "The lists in column '{}' have {} nulls.",
+                        name,
+                        values.null_count()
+                    ));
+                }
+                Ok(FeatureColumn::List(list, values, *width as usize))
+            }
+            _ => Ok(FeatureColumn::Scalar(
+                column
+                    .as_primitive_opt::<T::ArrowType>()
+                    .ok_or_else(wrong_type)?
+                    .clone(),
+            )),
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            FeatureColumn::Scalar(_) => 1,
+            FeatureColumn::List(_, _, width) => *width,
+        }
+    }
+
+    /// Appends the features of the given row of the batch to the instance.
+    fn extend(&self, row: usize, instance: &mut Vec<T>) {
+        match self {
+            FeatureColumn::Scalar(values) => instance.push(values.value(row)),
+            FeatureColumn::List(list, values, width) => {
+                let start = list.value_offset(row) as usize;
+                instance.extend_from_slice(&values.values()[start..(start + width)]);
+            }
+        }
+    }
+}
+
+/// ArrowDataset serves the rows of Arrow record batches, e.g. as read from a Parquet file, as instances.
+///
+/// The features of each instance are read, in order, from the feature columns, each of which holds either one `T` or
+/// a fixed-size list of `T` per row. Every other column is kept as metadata and returned, by `instance_metadata`, as
+/// a JSON object from the names of those columns to their values in the row of the instance.
+pub struct ArrowDataset<T: ArrowNumber, U: Number> {
+    batches: Vec<RecordBatch>,
+    /// The feature columns of each batch.
+    features: Vec<Vec<FeatureColumn<T>>>,
+    /// The positions of the metadata columns in the schema.
+    metadata_columns: Vec<usize>,
+    /// The index of the first instance of each batch, followed by the cardinality of the dataset.
+    starts: Vec<Index>,
+    dimensionality: usize,
+    metric: Arc<dyn Metric<T, U>>,
+}
+
+impl<T: ArrowNumber, U: Number> std::fmt::Debug for ArrowDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("ArrowDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality)
+            .field("num-batches", &self.batches.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + ArrowNumber, U: 'static + Number> ArrowDataset<T, U> {
+    /// Serves the rows of the batches, which must share a schema, with the named columns as the features.
+    ///
+    /// Returns an Err if there are no batches, if the schemas differ, if a feature column is missing, has nulls, or
+    /// does not hold `T` or fixed-size lists of `T`, or if there are no features.
+    pub fn from_batches(
+        batches: Vec<RecordBatch>,
+        feature_columns: &[&str],
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let schema = batches
+            .first()
+            .map(|batch| batch.schema())
+            .ok_or_else(|| "An Arrow dataset needs at least one record batch.".to_string())?;
+        if let Some(position) = batches.iter().position(|batch| batch.schema() != schema) {
+            return Err(format!(
+                "Batch {} has a different schema from the first batch.",
+                position
+            ));
+        }
+
+        let features = batches
+            .iter()
+            .map(|batch| {
+                feature_columns
+                    .iter()
+                    .map(|&name| FeatureColumn::read(batch, name))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let dimensionality = features[0].iter().map(|column| column.width()).sum();
+        if dimensionality == 0 {
+            return Err("An Arrow dataset needs at least one feature.".to_string());
+        }
+
+        let metadata_columns = metadata_columns(&schema, feature_columns);
+        let mut starts = vec![0];
+        for batch in batches.iter() {
+            starts.push(starts.last().unwrap() + batch.num_rows());
+        }
+
+        Ok(ArrowDataset {
+            batches,
+            features,
+            metadata_columns,
+            starts,
+            dimensionality,
+            metric,
+        })
+    }
+
+    /// Reads the Parquet file into record batches and serves their rows, with the named columns as the features.
+    /// See `from_batches`.
+    pub fn from_parquet(path: &Path, feature_columns: &[&str], metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let fail = |error: String| format!("Error: Failed to read {}. {}", path.display(), error);
+        let file = std::fs::File::open(path).map_err(|error| fail(error.to_string()))?;
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+            .and_then(|builder| builder.build())
+            .map_err(|error| fail(error.to_string()))?;
+        let batches = reader
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| fail(error.to_string()))?;
+        Self::from_batches(batches, feature_columns, metric).map_err(fail)
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: ArrowNumber, U: Number> ArrowDataset<T, U> {
+    /// Returns the schema shared by the batches.
+    pub fn schema(&self) -> SchemaRef {
+        self.batches[0].schema()
+    }
+
+    /// Returns the names of the columns that are kept as metadata, in the order of the schema.
+    pub fn metadata_columns(&self) -> Vec<String> {
+        let schema = self.schema();
+        self.metadata_columns
+            .iter()
+            .map(|&position| schema.field(position).name().clone())
+            .collect()
+    }
+
+    /// Returns the batch holding the instance at the given index, and the row of the instance in the batch.
+    fn batch_of(&self, index: Index) -> (usize, usize) {
+        let batch = self.starts.partition_point(|&start| start <= index) - 1;
+        (batch, index - self.starts[batch])
+    }
+}
+
+/// Returns the positions of the columns of the schema that are not feature columns.
+fn metadata_columns(schema: &SchemaRef, feature_columns: &[&str]) -> Vec<usize> {
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| !feature_columns.contains(&field.name().as_str()))
+        .map(|(position, _)| position)
+        .collect()
+}
+
+/// Returns the value at the given row of the array as JSON. Booleans, numbers and strings keep their types, and
+/// values of other types are formatted as text.
+fn json_value(array: &dyn Array, row: usize) -> Value {
+    if array.is_null(row) {
+        return Value::Null;
+    }
+    match array.data_type() {
+        DataType::Boolean => Value::from(array.as_boolean().value(row)),
+        DataType::Int8 => Value::from(array.as_primitive::<Int8Type>().value(row)),
+        DataType::Int16 => Value::from(array.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => Value::from(array.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => Value::from(array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::from(array.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => Value::from(array.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => Value::from(array.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => Value::from(array.as_primitive::<UInt64Type>().value(row)),
+        DataType::Float32 => Value::from(array.as_primitive::<Float32Type>().value(row)),
+        DataType::Float64 => Value::from(array.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::from(array.as_string::<i32>().value(row)),
+        DataType::LargeUtf8 => Value::from(array.as_string::<i64>().value(row)),
+        _ => arrow_cast::display::ArrayFormatter::try_new(array, &Default::default())
+            .map_or(Value::Null, |formatter| Value::from(formatter.value(row).to_string())),
+    }
+}
+
+impl<T: ArrowNumber, U: Number> Dataset<T, U> for ArrowDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        *self.starts.last().unwrap()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Reads the features of the instance at the provided index from its batch.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let (batch, row) = self.batch_of(i);
+        let mut instance = Vec::with_capacity(self.dimensionality);
+        self.features[batch]
+            .iter()
+            .for_each(|column| column.extend(row, &mut instance));
+        instance
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        Array1::from_vec(
+            right
+                .par_iter()
+                .map(|&r| {
+                    if r == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &self.instance(r))
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+
+    /// Returns the values of the metadata columns in the row of the instance, as a JSON object keyed by their names.
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        if self.metadata_columns.is_empty() {
+            return None;
+        }
+        let (batch, row) = self.batch_of(index);
+        let batch = &self.batches[batch];
+        let schema = batch.schema();
+        let object: Map<_, _> = self
+            .metadata_columns
+            .iter()
+            .map(|&position| {
+                let name = schema.field(position).name().clone();
+                (name, json_value(batch.column(position).as_ref(), row))
+            })
+            .collect();
+        Some(Value::Object(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::types::Float32Type;
+    use arrow_array::ArrayRef;
+    use arrow_array::FixedSizeListArray;
+    use arrow_array::Float32Array;
+    use arrow_array::Int64Array;
+    use arrow_array::RecordBatch;
+    use arrow_array::StringArray;
+    use serde_json::json;
+
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::ArrowDataset;
+
+    fn batch(rows: std::ops::Range<usize>) -> RecordBatch {
+        let embeddings = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+            rows.clone().map(|i| Some(vec![Some(i as f32), Some((i % 5) as f32)])),
+            2,
+        );
+        let scale = Float32Array::from_iter_values(rows.clone().map(|i| (i % 3) as f32));
+        let ids = Int64Array::from_iter_values(rows.clone().map(|i| i as i64));
+        let names = StringArray::from_iter(rows.map(|i| (i % 4 != 0).then(|| format!("row-{}", i))));
+        RecordBatch::try_from_iter([
+            ("id", Arc::new(ids) as ArrayRef),
+            ("embedding", Arc::new(embeddings) as ArrayRef),
+            ("scale", Arc::new(scale) as ArrayRef),
+            ("name", Arc::new(names) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_arrow_dataset() {
+        let metric = metric_from_name::<f32, f32>("euclidean").unwrap();
+        let batches = vec![batch(0..40), batch(40..100).slice(10, 50)];
+        let dataset = ArrowDataset::from_batches(batches.clone(), &["embedding", "scale"], Arc::clone(&metric)).unwrap();
+        assert_eq!(dataset.cardinality(), 90);
+        assert_eq!(dataset.dimensionality(), 3);
+        assert_eq!(dataset.metadata_columns(), vec!["id", "name"]);
+        assert_eq!(dataset.instance(3), vec![3., 3., 0.]);
+        // The second batch starts at its tenth row.
+        assert_eq!(dataset.instance(40), vec![50., 0., 2.]);
+        assert_eq!(dataset.instance_metadata(41), Some(json!({"id": 51, "name": "row-51"})));
+        assert_eq!(dataset.instance_metadata(0), Some(json!({"id": 0, "name": null})));
+
+        let dataset = Arc::new(dataset);
+        let cakes = Cakes::build(Arc::clone(&dataset).as_arc_dataset(), None, None);
+        let query = dataset.instance(17);
+        let mut hits = cakes.rnn_indices(&query, Some(3.));
+        let mut expected = cakes.linear_search_indices(&query, Some(3.), None);
+        hits.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(hits, expected);
+
+        // Parquet files are read into the same batches.
+        let path = std::env::temp_dir().join(format!("clam-test-arrow-{}.parquet", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = parquet::arrow::ArrowWriter::try_new(file, batches[0].schema(), None).unwrap();
+        batches.iter().for_each(|batch| writer.write(batch).unwrap());
+        writer.close().unwrap();
+        let parquet = ArrowDataset::from_parquet(&path, &["embedding", "scale"], Arc::clone(&metric)).unwrap();
+        assert_eq!(parquet.cardinality(), 90);
+        for i in [0, 39, 40, 89] {
+            assert_eq!(parquet.instance(i), dataset.instance(i));
+            assert_eq!(parquet.instance_metadata(i), dataset.instance_metadata(i));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // Feature columns must exist, hold the requested type and have no nulls.
+        assert!(ArrowDataset::from_batches(batches.clone(), &["missing"], Arc::clone(&metric)).is_err());
+        assert!(ArrowDataset::from_batches(batches.clone(), &["id"], Arc::clone(&metric)).is_err());
+        assert!(ArrowDataset::from_batches(batches.clone(), &[], Arc::clone(&metric)).is_err());
+        assert!(ArrowDataset::from_batches(vec![], &["scale"], Arc::clone(&metric)).is_err());
+        let nulls =
+            RecordBatch::try_from_iter([("scale", Arc::new(Float32Array::from(vec![Some(1.), None])) as ArrayRef)])
+                .unwrap();
+        assert!(ArrowDataset::from_batches(vec![nulls], &["scale"], metric).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! `BitPackedDataset`, a dataset of binary instances, e.g. fingerprints, packed into words for hamming and tanimoto
+//! search.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::metric::tanimoto_distance;
+use crate::prelude::*;
+
+/// BitPackedDataset stores binary instances, e.g. fingerprints, as bits packed into `u64` words, so that each feature
+/// takes one bit. The hamming and tanimoto distances between two instances are computed with popcounts of each pair of
+/// words. So is the packed-tanimoto distance, which the unpacked instances also have.
+///
+/// Instances are returned with every feature as zero or one. Other metrics are computed on the unpacked instances.
+pub struct BitPackedDataset<T: Number, U: Number> {
+    words: Vec<u64>,
+    num_bits: usize,
+    words_per_instance: usize,
+    metric: Arc<dyn Metric<T, U>>,
+    /// The metric computed directly on the packed words, if it is hamming or tanimoto.
+    popcount: Option<PopcountKernel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PopcountKernel {
+    Hamming,
+    Tanimoto,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for BitPackedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("BitPackedDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.num_bits)
+            .field("popcount", &self.popcount)
+            .finish()
+    }
+}
+
+impl<T: Number, U: Number> BitPackedDataset<T, U> {
+    /// Packs the instances, which must all have the same dimensionality. Every non-zero feature is a set bit.
+    ///
+    /// Returns an Err if the instances have different dimensionalities.
+    pub fn from_instances(data: &[Vec<T>], metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let num_bits = data.first().map_or(0, |row| row.len());
+        if let Some(i) = data.iter().position(|row| row.len() != num_bits) {
+            return Err(format!(
+                "Instance {} has {} features but the first instance has {}.",
+                i,
+                data[i].len(),
+                num_bits
+            ));
+        }
+
+        Self::from_words(data.iter().flat_map(|row| pack_bits(row)).collect(), num_bits, metric)
+    }
+
+    /// Creates a dataset from packed words, `ceil(num_bits / 64)` per instance with bit `j` of the instance in bit
+    /// `j % 64` of word `j / 64`. The unused high bits of the last word of each instance must be zero.
+    ///
+    /// Returns an Err if the number of words is not a multiple of the words per instance, or an unused bit is set.
+    pub fn from_words(words: Vec<u64>, num_bits: usize, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let words_per_instance = num_bits.div_ceil(64);
+        if words_per_instance == 0 && !words.is_empty() {
+            return Err("Cannot hold words for instances with no bits.".to_string());
+        }
+        if words_per_instance > 0 && !words.len().is_multiple_of(words_per_instance) {
+            return Err(format!(
+                "Expected a multiple of {} words but got {}.",
+                words_per_instance,
+                words.len()
+            ));
+        }
+        if !num_bits.is_multiple_of(64) {
+            let unused = !0 << (num_bits % 64);
+            let last_words = words.iter().skip(words_per_instance - 1).step_by(words_per_instance);
+            if let Some(i) = last_words.into_iter().position(|&word| word & unused != 0) {
+                return Err(format!("Instance {} has bits set beyond its {} bits.", i, num_bits));
+            }
+        }
+
+        Ok(BitPackedDataset {
+            words,
+            num_bits,
+            words_per_instance,
+            popcount: match metric.name().as_str() {
+                "hamming" => Some(PopcountKernel::Hamming),
+                "tanimoto" | "packed-tanimoto" => Some(PopcountKernel::Tanimoto),
+                _ => None,
+            },
+            metric,
+        })
+    }
+
+    /// Returns the packed words of the instance at the given index.
+    pub fn words(&self, index: Index) -> &[u64] {
+        &self.words[index * self.words_per_instance..(index + 1) * self.words_per_instance]
+    }
+
+    /// Returns the number of bits that differ between the instances.
+    pub fn hamming(&self, left: Index, right: Index) -> usize {
+        self.words(left)
+            .iter()
+            .zip(self.words(right).iter())
+            .map(|(a, b)| (a ^ b).count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns the tanimoto distance between the instances.
+    pub fn tanimoto(&self, left: Index, right: Index) -> f64 {
+        let (both, either) =
+            self.words(left)
+                .iter()
+                .zip(self.words(right).iter())
+                .fold((0, 0), |(both, either), (a, b)| {
+                    (
+                        both + (a & b).count_ones() as usize,
+                        either + (a | b).count_ones() as usize,
+                    )
+                });
+        tanimoto_distance(both, either)
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>>
+    where
+        T: 'static,
+        U: 'static,
+    {
+        self
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for BitPackedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.words.len().checked_div(self.words_per_instance).unwrap_or(0)
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.num_bits
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Returns the unpacked instance at the provided index, with every feature as zero or one.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let words = self.words(i);
+        (0..self.num_bits)
+            .map(|bit| {
+                if words[bit / 64] >> (bit % 64) & 1 == 1 {
+                    T::one()
+                } else {
+                    T::zero()
+                }
+            })
+            .collect()
+    }
+
+    fn instance_size(&self) -> usize {
+        std::cmp::max(self.words_per_instance * 8, 1)
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else if let Some(kernel) = self.popcount {
+            U::saturating_from_f64(match kernel {
+                PopcountKernel::Hamming => self.hamming(left, right) as f64,
+                PopcountKernel::Tanimoto => self.tanimoto(left, right),
+            })
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+/// Packs the features of an instance into `u64` words as `BitPackedDataset` does, with every non-zero feature as a set
+/// bit, e.g. to search a `RowMajor` of packed fingerprints with the "packed-tanimoto" metric.
+pub fn pack_bits<T: Number>(instance: &[T]) -> Vec<u64> {
+    let mut words = vec![0; instance.len().div_ceil(64)];
+    for (bit, _) in instance.iter().enumerate().filter(|(_, &value)| value != T::zero()) {
+        words[bit / 64] |= 1 << (bit % 64);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+
+    use crate::dataset::Dataset;
+    use crate::dataset::RowMajor;
+    use crate::metric_from_name;
+
+    use super::pack_bits;
+    use super::BitPackedDataset;
+
+    #[test]
+    fn test_bit_packed_dataset() {
+        let data: Vec<Vec<u8>> = (0..40)
+            .map(|i: usize| (0..130).map(|j| ((i * 31 + j * 17) % 7 < 3) as u8).collect())
+            .collect();
+        let hamming = metric_from_name::<u8, u64>("hamming").unwrap();
+        let dense = RowMajor::new(Arc::new(data.clone()), Arc::clone(&hamming), false);
+        let packed = BitPackedDataset::from_instances(&data, Arc::clone(&hamming)).unwrap();
+        assert_eq!(packed.cardinality(), 40);
+        assert_eq!(packed.dimensionality(), 130);
+        assert_eq!(packed.instance_size(), 24);
+        assert_eq!(packed.words(3).len(), 3);
+
+        for (i, j) in [(0, 1), (5, 39), (7, 7), (21, 2)] {
+            assert_eq!(packed.instance(i), data[i]);
+            assert_eq!(packed.distance(i, j), dense.distance(i, j));
+        }
+
+        let floats: Vec<Vec<f64>> = data.iter().map(|row| row.iter().map(|&b| b as f64).collect()).collect();
+        let euclidean = metric_from_name::<f64, f64>("euclidean").unwrap();
+        let unpacked = BitPackedDataset::from_instances(&floats, Arc::clone(&euclidean)).unwrap();
+        let distance = euclidean.distance(&floats[5], &floats[39]);
+        assert!(approx_eq!(f64, unpacked.distance(5, 39), distance));
+
+        let tanimoto = metric_from_name::<u8, f64>("tanimoto").unwrap();
+        let fingerprints = BitPackedDataset::from_instances(&data, Arc::clone(&tanimoto)).unwrap();
+        let distance = tanimoto.distance(&data[5], &data[39]);
+        assert!(approx_eq!(f64, fingerprints.distance(5, 39), distance));
+        assert_eq!(fingerprints.words(5), pack_bits(&data[5]));
+
+        let words = packed.words.clone();
+        assert!(BitPackedDataset::from_words(words[..7].to_vec(), 130, Arc::clone(&hamming)).is_err());
+        let mut dirty = words;
+        dirty[2] |= 1 << 63;
+        assert!(BitPackedDataset::from_words(dirty, 130, Arc::clone(&hamming)).is_err());
+        assert!(BitPackedDataset::from_instances(&[vec![1], vec![0, 1]], hamming).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! `FastaDataset`, a dataset of the variable-length sequences in FASTA/FASTQ files.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::io::MappedFile;
+use crate::prelude::*;
+
+/// The sequences in a FASTA or FASTQ file, e.g. of genomes or proteins, mapped into memory.
+///
+/// The file is indexed when it is opened, but each sequence is only read from the mapping when it is needed,
+/// so that files larger than RAM may be searched. Sequences may be wrapped over several lines and have different
+/// lengths, so the metric should handle both, as edit distances do. The dimensionality is the length of the longest
+/// sequence.
+///
+/// Each sequence is identified by the first word of its header line, and `index_of` maps these ids to indices.
+/// The file must not be modified while it is mapped.
+pub struct FastaDataset<U: Number> {
+    file: Arc<MappedFile>,
+    metric: Arc<dyn Metric<u8, U>>,
+    records: Vec<SequenceRecord>,
+    ids: HashMap<String, Index>,
+    max_length: usize,
+}
+
+/// The id of a sequence and where to find its residues, and qualities for FASTQ files, in the file.
+#[derive(Debug, Clone)]
+struct SequenceRecord {
+    id: String,
+    /// The lines holding the sequence, including the line breaks between them.
+    sequence: Range<usize>,
+    quality: Option<Range<usize>>,
+}
+
+impl<U: Number> std::fmt::Debug for FastaDataset<U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("FastaDataset")
+            .field("data-cardinality", &self.records.len())
+            .field("data-dimensionality", &self.max_length)
+            .finish()
+    }
+}
+
+impl<U: 'static + Number> FastaDataset<U> {
+    /// Maps and indexes the FASTA or FASTQ file at the given path. The format is read from the first header.
+    ///
+    /// Returns an Err if the file is malformed, holds no sequences, or repeats an id.
+    pub fn open(path: &Path, metric: Arc<dyn Metric<u8, U>>) -> Result<Self, String> {
+        Self::from_file(Arc::new(MappedFile::open(path)?), metric)
+            .map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))
+    }
+
+    /// Indexes an already mapped FASTA or FASTQ file.
+    pub fn from_file(file: Arc<MappedFile>, metric: Arc<dyn Metric<u8, U>>) -> Result<Self, String> {
+        let bytes = (*file).as_ref();
+        let records = match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'>') => read_fasta_records(bytes)?,
+            Some(b'@') => read_fastq_records(bytes)?,
+            Some(_) => return Err("Expected a FASTA header starting with '>' or a FASTQ header with '@'.".to_string()),
+            None => return Err("The file holds no sequences.".to_string()),
+        };
+
+        let mut ids = HashMap::with_capacity(records.len());
+        for (i, record) in records.iter().enumerate() {
+            if ids.insert(record.id.clone(), i).is_some() {
+                return Err(format!("The id '{}' is used by more than one sequence.", record.id));
+            }
+        }
+        let max_length = records
+            .iter()
+            .map(|record| residues(&bytes[record.sequence.clone()]).count())
+            .max()
+            .unwrap_or(0);
+
+        Ok(FastaDataset {
+            file,
+            metric,
+            records,
+            ids,
+            max_length,
+        })
+    }
+
+    /// Returns the id of the sequence at the given index.
+    pub fn id(&self, index: Index) -> &str {
+        &self.records[index].id
+    }
+
+    /// Returns the index of the sequence with the given id, if there is one.
+    pub fn index_of(&self, id: &str) -> Option<Index> {
+        self.ids.get(id).copied()
+    }
+
+    /// Returns the quality scores of the sequence at the given index, or None for FASTA files.
+    pub fn quality(&self, index: Index) -> Option<Vec<u8>> {
+        let quality = self.records[index].quality.clone()?;
+        Some(residues(&(*self.file).as_ref()[quality]).collect())
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<u8, U>> {
+        self
+    }
+}
+
+impl<U: Number> Dataset<u8, U> for FastaDataset<U> {
+    fn metric(&self) -> Arc<dyn Metric<u8, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.records.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.max_length
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.records.len()).collect()
+    }
+
+    /// Reads the sequence at the provided index from the mapping, without its line breaks.
+    fn instance(&self, i: Index) -> Vec<u8> {
+        residues(&(*self.file).as_ref()[self.records[i].sequence.clone()]).collect()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        Array1::from_vec(
+            right
+                .par_iter()
+                .map(|&r| {
+                    if r == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &self.instance(r))
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+/// Returns the bytes of the lines, skipping line breaks.
+fn residues(lines: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    lines.iter().copied().filter(|&b| b != b'\n' && b != b'\r')
+}
+
+/// Returns the non-empty lines of the bytes, without line breaks, along with their positions in the bytes.
+fn lines(bytes: &[u8]) -> impl Iterator<Item = (Range<usize>, &[u8])> {
+    let mut start = 0;
+    bytes
+        .split(|&b| b == b'\n')
+        .map(move |line| {
+            let range = start..start + line.len();
+            start = range.end + 1;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            (range.start..range.start + line.len(), line)
+        })
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Returns the id of a sequence from its header line, i.e. the first word after the marker.
+fn header_id(header: &[u8]) -> Result<String, String> {
+    let id = header[1..]
+        .split(|b| b.is_ascii_whitespace())
+        .next()
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| format!("The header '{}' has no id.", String::from_utf8_lossy(header)))?;
+    Ok(String::from_utf8_lossy(id).into_owned())
+}
+
+/// Indexes the records of a FASTA file. Lines starting with ';' are comments.
+fn read_fasta_records(bytes: &[u8]) -> Result<Vec<SequenceRecord>, String> {
+    let mut records: Vec<SequenceRecord> = Vec::new();
+    for (range, line) in lines(bytes).filter(|(_, line)| line[0] != b';') {
+        if line[0] == b'>' {
+            records.push(SequenceRecord {
+                id: header_id(line)?,
+                sequence: range.end..range.end,
+                quality: None,
+            });
+        } else if let Some(record) = records.last_mut() {
+            if record.sequence.is_empty() {
+                record.sequence.start = range.start;
+            }
+            record.sequence.end = range.end;
+        }
+    }
+    Ok(records)
+}
+
+/// Indexes the records of a FASTQ file. The sequence and the qualities may each be wrapped over several lines.
+fn read_fastq_records(bytes: &[u8]) -> Result<Vec<SequenceRecord>, String> {
+    let mut records = Vec::new();
+    let mut lines = lines(bytes).peekable();
+    while let Some((_, header)) = lines.next() {
+        if header[0] != b'@' {
+            return Err(format!(
+                "Expected a FASTQ header but found '{}'.",
+                String::from_utf8_lossy(header)
+            ));
+        }
+        let id = header_id(header)?;
+
+        let mut sequence = 0..0;
+        let mut length = 0;
+        while let Some((range, line)) = lines.next_if(|(_, line)| line[0] != b'+') {
+            sequence = if length == 0 {
+                range.clone()
+            } else {
+                sequence.start..range.end
+            };
+            length += line.len();
+        }
+        if lines.next().is_none() {
+            return Err(format!("The sequence '{}' has no '+' line and qualities.", id));
+        }
+
+        let mut quality = 0..0;
+        let mut quality_length = 0;
+        while quality_length < length {
+            let (range, line) = lines
+                .next()
+                .ok_or_else(|| format!("The qualities of the sequence '{}' are truncated.", id))?;
+            quality = if quality_length == 0 {
+                range.clone()
+            } else {
+                quality.start..range.end
+            };
+            quality_length += line.len();
+        }
+        if quality_length != length {
+            return Err(format!(
+                "The sequence '{}' has {} residues but {} qualities.",
+                id, length, quality_length
+            ));
+        }
+
+        records.push(SequenceRecord {
+            id,
+            sequence,
+            quality: Some(quality),
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::FastaDataset;
+
+    #[test]
+    fn test_fasta_dataset() {
+        let path = std::env::temp_dir().join(format!("clam-test-fasta-dataset-{}.fasta", std::process::id()));
+        let metric = metric_from_name("hamming").unwrap();
+
+        // Wrapped sequences, Windows line breaks and comments.
+        let fasta = ">seq-a first sequence\r\nACGT\r\nAC\r\n; a comment\n>seq-b\nACGA\nAG\n\n>seq-c\nTTTTTT\n";
+        std::fs::write(&path, fasta).unwrap();
+        let dataset = FastaDataset::<u32>::open(&path, Arc::clone(&metric)).unwrap();
+        assert_eq!(dataset.cardinality(), 3);
+        assert_eq!(dataset.dimensionality(), 6);
+        assert_eq!(dataset.instance(0), b"ACGTAC");
+        assert_eq!(dataset.instance(1), b"ACGAAG");
+        assert_eq!(dataset.id(0), "seq-a");
+        assert_eq!(dataset.index_of("seq-c"), Some(2));
+        assert_eq!(dataset.index_of("first"), None);
+        assert_eq!(dataset.quality(0), None);
+        assert_eq!(dataset.distance(0, 1), 2);
+        assert_eq!(dataset.distance(0, 2), 5);
+
+        // Qualities may start with the markers of headers and separators.
+        let fastq = "@read-1\nACGT\nAC\n+read-1\n@+II\nII\n@read-2 lane 2\nTTGTAC\n+\n+@@@@I\n";
+        std::fs::write(&path, fastq).unwrap();
+        let dataset = FastaDataset::<u32>::open(&path, Arc::clone(&metric)).unwrap();
+        assert_eq!(dataset.cardinality(), 2);
+        assert_eq!(dataset.instance(0), b"ACGTAC");
+        assert_eq!(dataset.instance(1), b"TTGTAC");
+        assert_eq!(dataset.quality(0), Some(b"@+IIII".to_vec()));
+        assert_eq!(dataset.quality(1), Some(b"+@@@@I".to_vec()));
+        assert_eq!(dataset.index_of("read-2"), Some(1));
+        assert_eq!(dataset.distance(0, 1), 2);
+        drop(dataset);
+
+        // A tree over many sequences agrees with a linear scan.
+        let sequences: Vec<String> = (0..200)
+            .map(|i: usize| {
+                (0..12)
+                    .map(|j| ['A', 'C', 'G', 'T'][(i * (j + 1) / 7 + j) % 4])
+                    .collect()
+            })
+            .collect();
+        let fasta: String = sequences
+            .iter()
+            .enumerate()
+            .map(|(i, sequence)| format!(">{}\n{}\n{}\n", i, &sequence[..5], &sequence[5..]))
+            .collect();
+        std::fs::write(&path, fasta).unwrap();
+        let dataset = Arc::new(FastaDataset::<u32>::open(&path, Arc::clone(&metric)).unwrap());
+        assert_eq!(dataset.instance(17), sequences[17].as_bytes());
+        let cakes = Cakes::build(dataset.as_arc_dataset(), None, None);
+        for query in sequences.iter().step_by(13) {
+            let mut hits = cakes.rnn_indices(query.as_bytes(), Some(3));
+            let mut expected = cakes.linear_search_indices(query.as_bytes(), Some(3), None);
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+        }
+        drop(cakes);
+
+        for malformed in [
+            "",
+            "ACGT\n",
+            ">a\nAC\n>a\nGT\n",
+            "@a\nACGT\n+\nII\n",
+            "@a\nACGT\n",
+            "> \nAC\n",
+        ] {
+            std::fs::write(&path, malformed).unwrap();
+            assert!(
+                FastaDataset::<u32>::open(&path, Arc::clone(&metric)).is_err(),
+                "{:?}",
+                malformed
+            );
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
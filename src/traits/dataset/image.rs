@@ -0,0 +1,439 @@
+//! `ImageDataset`, a dataset of images, or of the patches tiled from them, as instances of flattened pixels.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::io::read_file;
+use crate::prelude::*;
+
+/// The height, width and number of channels of an image or of a patch of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageShape {
+    pub height: usize,
+    pub width: usize,
+    /// e.g. 1 for grayscale or 3 for RGB.
+    pub channels: usize,
+}
+
+impl ImageShape {
+    pub fn new(height: usize, width: usize, channels: usize) -> Self {
+        ImageShape {
+            height,
+            width,
+            channels,
+        }
+    }
+
+    /// Returns the number of values in an image of this shape.
+    pub fn len(&self) -> usize {
+        self.height * self.width * self.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// How to tile images into square patches for an `ImageDataset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchOptions {
+    /// The height and width of each patch.
+    pub size: usize,
+    /// The number of pixels between the corners of neighboring patches. Patches overlap if this is less than `size`.
+    pub stride: usize,
+}
+
+impl PatchOptions {
+    /// Tiles images into patches of the given size that do not overlap.
+    pub fn new(size: usize) -> Self {
+        PatchOptions { size, stride: size }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+}
+
+/// Where a patch of an `ImageDataset` was cut from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchOrigin {
+    /// The position of the image among the images given to the dataset.
+    pub image: usize,
+    /// The row of the top-left pixel of the patch in the image.
+    pub row: usize,
+    /// The column of the top-left pixel of the patch in the image.
+    pub column: usize,
+}
+
+/// ImageDataset holds images, e.g. the spaxel maps of the SDSS-MaNGA survey, or the patches tiled from them, as
+/// instances of flattened pixels.
+///
+/// Each instance is the pixels of one image or patch in row-major order, with the channels of each pixel next to each
+/// other, so it may be searched with `euclidean` or with the `ssim` metric. Every instance has the same shape,
+/// `patch_shape`. Images may have different shapes if they are tiled into patches, and the pixels beyond the last
+/// whole patch of a row or column are left out. Overlapping patches are copied, so a stride of half the size needs
+/// about four times the memory of the images.
+pub struct ImageDataset<T: Number, U: Number> {
+    pixels: Vec<T>,
+    shape: ImageShape,
+    origins: Vec<PatchOrigin>,
+    /// The name of each image, e.g. its file name, at the position of the image.
+    names: Vec<String>,
+    metric: Arc<dyn Metric<T, U>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for ImageDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("ImageDataset")
+            .field("data-cardinality", &self.origins.len())
+            .field("patch-shape", &self.shape)
+            .field("num-images", &self.names.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> ImageDataset<T, U> {
+    /// Creates a dataset from the flattened pixels of images of the same shape, end to end, optionally tiling each
+    /// image into patches. The images are named by their positions.
+    ///
+    /// Returns an Err if the pixels do not hold a whole number of images, or if the patches do not fit the images.
+    pub fn from_pixels(
+        pixels: &[T],
+        shape: ImageShape,
+        patches: Option<PatchOptions>,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        if shape.is_empty() || !pixels.chunks_exact(shape.len()).remainder().is_empty() {
+            return Err(format!(
+                "{} pixels do not hold a whole number of images of shape {:?}.",
+                pixels.len(),
+                shape
+            ));
+        }
+        let images = pixels
+            .chunks(shape.len())
+            .enumerate()
+            .map(|(i, image)| (i.to_string(), shape, image));
+        Self::tile(images, patches, metric)
+    }
+
+    /// Reads the binary or plain PGM and PPM images in the directory, in the order of their file names, optionally
+    /// tiling each image into patches. Other files are ignored.
+    ///
+    /// Returns an Err if an image is malformed, if the images have different shapes and are not tiled, or if the
+    /// directory holds no images.
+    pub fn from_directory(
+        directory: &Path,
+        patches: Option<PatchOptions>,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let fail = |error: std::io::Error| format!("Error: Failed to read {}. {}", directory.display(), error);
+        let mut paths: Vec<_> = std::fs::read_dir(directory)
+            .map_err(fail)?
+            .map(|entry| entry.map(|entry| entry.path()).map_err(fail))
+            .collect::<Result<_, _>>()?;
+        paths.retain(|path| {
+            let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+            matches!(extension.as_deref(), Some("pgm" | "ppm" | "pnm"))
+        });
+        paths.sort();
+        if paths.is_empty() {
+            return Err(format!("There are no PGM or PPM images in {}.", directory.display()));
+        }
+
+        let images = paths
+            .iter()
+            .map(|path| {
+                let (shape, values) = read_netpbm(path)?;
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                let values: Vec<T> = values.into_iter().map(|v| T::saturating_from_f64(v as f64)).collect();
+                Ok((name, shape, values))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let images = images
+            .iter()
+            .map(|(name, shape, values)| (name.clone(), *shape, values.as_slice()));
+        Self::tile(images, patches, metric)
+    }
+
+    /// Copies each image, or each of its patches, into the buffer of pixels.
+    fn tile<'a>(
+        images: impl Iterator<Item = (String, ImageShape, &'a [T])>,
+        patches: Option<PatchOptions>,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let (mut pixels, mut origins, mut names) = (vec![], vec![], vec![]);
+        let mut patch_shape = None;
+        for (image, (name, shape, values)) in images.enumerate() {
+            let this_shape = match patches {
+                Some(options) => ImageShape::new(options.size, options.size, shape.channels),
+                None => shape,
+            };
+            if *patch_shape.get_or_insert(this_shape) != this_shape {
+                return Err(format!(
+                    "The image {} has shape {:?} but the earlier images have {:?}.",
+                    name,
+                    this_shape,
+                    patch_shape.unwrap()
+                ));
+            }
+
+            match patches {
+                None => {
+                    pixels.extend_from_slice(values);
+                    origins.push(PatchOrigin {
+                        image,
+                        row: 0,
+                        column: 0,
+                    });
+                }
+                Some(options) => {
+                    if options.size == 0 || options.stride == 0 {
+                        return Err("The size and stride of patches must be positive.".to_string());
+                    }
+                    if options.size > shape.height || options.size > shape.width {
+                        return Err(format!(
+                            "Patches of size {} do not fit in the image {} of shape {:?}.",
+                            options.size, name, shape
+                        ));
+                    }
+                    let row_length = shape.width * shape.channels;
+                    let patch_length = options.size * shape.channels;
+                    for row in (0..=shape.height - options.size).step_by(options.stride) {
+                        for column in (0..=shape.width - options.size).step_by(options.stride) {
+                            for r in row..row + options.size {
+                                let start = r * row_length + column * shape.channels;
+                                pixels.extend_from_slice(&values[start..start + patch_length]);
+                            }
+                            origins.push(PatchOrigin { image, row, column });
+                        }
+                    }
+                }
+            }
+            names.push(name);
+        }
+
+        Ok(ImageDataset {
+            pixels,
+            shape: patch_shape.ok_or_else(|| "There are no images.".to_string())?,
+            origins,
+            names,
+            metric,
+        })
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> ImageDataset<T, U> {
+    /// Returns the pixels of the image or patch at the given index, without copying them.
+    pub fn patch(&self, index: Index) -> &[T] {
+        let length = self.shape.len();
+        &self.pixels[index * length..(index + 1) * length]
+    }
+
+    /// Returns the shape of every instance.
+    pub fn patch_shape(&self) -> ImageShape {
+        self.shape
+    }
+
+    /// Returns the image and the position in it of the patch at the given index.
+    pub fn origin(&self, index: Index) -> PatchOrigin {
+        self.origins[index]
+    }
+
+    /// Returns the name of the image at the given position, e.g. its file name.
+    pub fn image_name(&self, image: usize) -> &str {
+        &self.names[image]
+    }
+
+    /// Returns the number of images the instances were cut from.
+    pub fn num_images(&self) -> usize {
+        self.names.len()
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for ImageDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.origins.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.shape.len()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.patch(index).to_vec()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(self.patch(left), self.patch(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+/// Reads a binary (P5, P6) or plain (P2, P3) PGM or PPM image, returning its shape and its values in row-major order.
+fn read_netpbm(path: &Path) -> Result<(ImageShape, Vec<u16>), String> {
+    let bytes = read_file(path)?;
+    let fail = |error: &str| format!("Error: Failed to read {}. {}", path.display(), error);
+
+    // The header is the magic number, width, height and maximum value, separated by whitespace and comments.
+    let mut position = 0;
+    let mut header = vec![];
+    while header.len() < 4 {
+        while position < bytes.len() && (bytes[position].is_ascii_whitespace() || bytes[position] == b'#') {
+            if bytes[position] == b'#' {
+                while position < bytes.len() && bytes[position] != b'\n' {
+                    position += 1;
+                }
+            } else {
+                position += 1;
+            }
+        }
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(fail("The header is truncated."));
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..position]).into_owned());
+    }
+    let number = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| fail("The header has a malformed number."))
+    };
+    let (width, height, max_value) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+    let (channels, binary) = match header[0].as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(fail("Expected a PGM or PPM image.")),
+    };
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(fail("The maximum value must be between 1 and 65535."));
+    }
+
+    let shape = ImageShape::new(height, width, channels);
+    let values: Vec<u16> = if binary {
+        // A single whitespace separates the header from the raster.
+        let raster = bytes.get(position + 1..).unwrap_or_default();
+        if max_value < 256 {
+            raster.iter().take(shape.len()).map(|&v| v as u16).collect()
+        } else {
+            raster
+                .chunks_exact(2)
+                .take(shape.len())
+                .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                .collect()
+        }
+    } else {
+        String::from_utf8_lossy(&bytes[position..])
+            .split_ascii_whitespace()
+            .take(shape.len())
+            .map(|v| v.parse().map_err(|_| fail("The raster has a malformed value.")))
+            .collect::<Result<_, _>>()?
+    };
+    if values.len() < shape.len() {
+        return Err(fail("The raster is truncated."));
+    }
+    Ok((shape, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::ImageDataset;
+    use super::ImageShape;
+    use super::PatchOptions;
+    use super::PatchOrigin;
+
+    #[test]
+    fn test_image_dataset() {
+        // Two 4x6 grayscale images, with the value of each pixel encoding its image, row and column.
+        let pixels: Vec<u8> = (0..2)
+            .flat_map(|i| (0..4).flat_map(move |r| (0..6).map(move |c| 100 * i + 10 * r + c)))
+            .collect();
+        let metric = metric_from_name::<u8, f64>("euclidean").unwrap();
+        let shape = ImageShape::new(4, 6, 1);
+        let images = ImageDataset::from_pixels(&pixels, shape, None, Arc::clone(&metric)).unwrap();
+        assert_eq!((images.cardinality(), images.dimensionality()), (2, 24));
+        assert_eq!(images.patch(1), &pixels[24..]);
+        assert!(ImageDataset::from_pixels(&pixels[1..], shape, None, Arc::clone(&metric)).is_err());
+
+        let options = PatchOptions::new(3).with_stride(2);
+        let patches = ImageDataset::from_pixels(&pixels, shape, Some(options), Arc::clone(&metric)).unwrap();
+        // Each image has one row of patches, at columns 0 and 2.
+        assert_eq!((patches.cardinality(), patches.num_images()), (4, 2));
+        assert_eq!(
+            patches.origin(3),
+            PatchOrigin {
+                image: 1,
+                row: 0,
+                column: 2
+            }
+        );
+        assert_eq!(patches.patch(3), &[102, 103, 104, 112, 113, 114, 122, 123, 124]);
+        assert!(ImageDataset::from_pixels(&pixels, shape, Some(PatchOptions::new(5)), Arc::clone(&metric)).is_err());
+
+        let directory = std::env::temp_dir().join(format!("clam-test-images-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut binary = b"P5\n# a comment\n6 4\n255\n".to_vec();
+        binary.extend_from_slice(&pixels[..24]);
+        std::fs::write(directory.join("a.pgm"), binary).unwrap();
+        let plain: Vec<_> = pixels[24..].iter().map(u8::to_string).collect();
+        std::fs::write(directory.join("b.PGM"), format!("P2 6 4 255 {}", plain.join(" "))).unwrap();
+        std::fs::write(directory.join("notes.txt"), "not an image").unwrap();
+        let read = ImageDataset::from_directory(&directory, Some(options), Arc::clone(&metric)).unwrap();
+        assert_eq!(read.image_name(1), "b.PGM");
+        assert!(read.indices().into_iter().all(|i| read.patch(i) == patches.patch(i)));
+
+        std::fs::write(directory.join("c.ppm"), "P6 6 4 255 truncated").unwrap();
+        assert!(ImageDataset::from_directory(&directory, Some(options), Arc::clone(&metric)).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let dataset = Arc::new(patches).as_arc_dataset();
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        assert_eq!(cakes.rnn_indices(&dataset.instance(2), Some(0.)), vec![2]);
+    }
+}
@@ -0,0 +1,233 @@
+//! `InstrumentedDataset`, which counts the instances fetched, the distances computed and the hits of the cache of
+//! another dataset.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ndarray::prelude::*;
+use serde_json::Value;
+
+use super::CacheLookups;
+use super::Provenance;
+use crate::prelude::*;
+use crate::utils::lock_cache;
+
+/// The accesses to a dataset counted by an `InstrumentedDataset`, as returned by `InstrumentedDataset::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccessStats {
+    /// The number of instances fetched with `instance`.
+    pub instance_fetches: u64,
+
+    /// The number of calls to `distance`, `distances_from`, `distances_among` and `pairwise_distances`.
+    pub distance_calls: u64,
+
+    /// The number of distances requested by those calls, e.g. `left.len() * right.len()` for `distances_among`.
+    pub distances: u64,
+
+    /// The lookups in the distance cache of the dataset, or None if it has no cache.
+    pub cache: Option<CacheLookups>,
+}
+
+impl AccessStats {
+    /// Returns the fraction of the lookups in the distance cache that were hits, or None if there were none.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let cache = self.cache?;
+        let lookups = cache.hits + cache.misses;
+        (lookups > 0).then(|| cache.hits as f64 / lookups as f64)
+    }
+}
+
+/// Serves the instances of another dataset unchanged and counts the accesses to them, e.g. to profile how many
+/// distances the building of a tree or a search takes.
+///
+/// The counts are of the calls made to this dataset, so a tree must be built over the `InstrumentedDataset` rather
+/// than over the dataset it wraps.
+pub struct InstrumentedDataset<T: Number, U: Number> {
+    dataset: Arc<dyn Dataset<T, U>>,
+    instance_fetches: AtomicU64,
+    distance_calls: AtomicU64,
+    distances: AtomicU64,
+    /// The lookups in the cache of the dataset at the last `reset`, which are subtracted from those reported.
+    baseline: Mutex<Option<CacheLookups>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for InstrumentedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("InstrumentedDataset")
+            .field("dataset", &self.dataset)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<T: Number, U: Number> InstrumentedDataset<T, U> {
+    pub fn new(dataset: Arc<dyn Dataset<T, U>>) -> Self {
+        let baseline = dataset.cache_lookups();
+        InstrumentedDataset {
+            dataset,
+            instance_fetches: AtomicU64::new(0),
+            distance_calls: AtomicU64::new(0),
+            distances: AtomicU64::new(0),
+            baseline: Mutex::new(baseline),
+        }
+    }
+
+    /// Returns the underlying dataset.
+    pub fn dataset(&self) -> &Arc<dyn Dataset<T, U>> {
+        &self.dataset
+    }
+
+    /// Returns the accesses since this was created or last `reset`.
+    pub fn stats(&self) -> AccessStats {
+        let baseline = lock_cache(&self.baseline).unwrap_or_default();
+        AccessStats {
+            instance_fetches: self.instance_fetches.load(Ordering::Relaxed),
+            distance_calls: self.distance_calls.load(Ordering::Relaxed),
+            distances: self.distances.load(Ordering::Relaxed),
+            cache: self.dataset.cache_lookups().map(|lookups| CacheLookups {
+                hits: lookups.hits.saturating_sub(baseline.hits),
+                misses: lookups.misses.saturating_sub(baseline.misses),
+            }),
+        }
+    }
+
+    /// Sets every count back to zero, e.g. between building a tree and searching it.
+    pub fn reset(&self) {
+        let mut baseline = lock_cache(&self.baseline);
+        self.instance_fetches.store(0, Ordering::Relaxed);
+        self.distance_calls.store(0, Ordering::Relaxed);
+        self.distances.store(0, Ordering::Relaxed);
+        *baseline = self.dataset.cache_lookups();
+    }
+
+    fn count_distances(&self, distances: usize) {
+        self.distance_calls.fetch_add(1, Ordering::Relaxed);
+        self.distances.fetch_add(distances as u64, Ordering::Relaxed);
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> InstrumentedDataset<T, U> {
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for InstrumentedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        self.dataset.metric()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.dataset.cardinality()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dataset.dimensionality()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        self.dataset.indices()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.instance_fetches.fetch_add(1, Ordering::Relaxed);
+        self.dataset.instance(index)
+    }
+
+    fn instance_size(&self) -> usize {
+        self.dataset.instance_size()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        self.count_distances(1);
+        self.dataset.distance(left, right)
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        self.count_distances(right.len());
+        self.dataset.distances_from(left, right)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        self.count_distances(left.len() * right.len());
+        self.dataset.distances_among(left, right)
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.count_distances(indices.len() * indices.len());
+        self.dataset.pairwise_distances(indices)
+    }
+
+    fn provenance(&self) -> Provenance {
+        self.dataset.provenance()
+    }
+
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        self.dataset.instance_metadata(index)
+    }
+
+    fn cache_lookups(&self) -> Option<CacheLookups> {
+        self.dataset.cache_lookups()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::dataset::RowMajor;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::InstrumentedDataset;
+
+    #[test]
+    fn test_instrumented_dataset() {
+        let data: Vec<_> = (0..100).map(|i| vec![(i * 37 % 100) as f64, (i % 9) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let row_major = RowMajor::<f64, f64>::new(Arc::new(data), metric, true);
+        row_major.distance(0, 1);
+        let instrumented = Arc::new(InstrumentedDataset::new(Arc::new(row_major)));
+        let stats = instrumented.stats();
+        assert_eq!(
+            (stats.instance_fetches, stats.distance_calls, stats.distances),
+            (0, 0, 0)
+        );
+        assert_eq!(stats.cache_hit_rate(), None);
+
+        instrumented.distance(1, 0);
+        instrumented.distance(2, 3);
+        instrumented.distances_among(&[0, 1], &[2, 3, 4]);
+        instrumented.instance(5);
+        let stats = instrumented.stats();
+        assert_eq!(
+            (stats.instance_fetches, stats.distance_calls, stats.distances),
+            (1, 3, 8)
+        );
+        // Only (0, 1) was cached, before the dataset was wrapped.
+        let cache = stats.cache.unwrap();
+        assert_eq!((cache.hits, cache.misses), (1, 7));
+        assert_eq!(stats.cache_hit_rate(), Some(0.125));
+
+        instrumented.reset();
+        let cakes = Cakes::build(Arc::clone(&instrumented).as_arc_dataset(), Some(5), None);
+        let build = instrumented.stats();
+        assert!(build.distances > 0 && build.cache.unwrap().misses > 0);
+
+        // Searches fetch the instances of the clusters they visit and compare them with the query by the metric, so
+        // they make no lookups in the cache.
+        instrumented.reset();
+        let hits = cakes.knn(&instrumented.dataset().instance(42), 3);
+        assert_eq!(hits[0], (42, 0.));
+        let search = instrumented.stats();
+        assert!(search.instance_fetches > 0);
+        assert_eq!(search.cache_hit_rate(), None);
+
+        let uncached =
+            RowMajor::<f64, f64>::new(Arc::new(vec![vec![0.]]), metric_from_name("euclidean").unwrap(), false);
+        assert_eq!(InstrumentedDataset::new(Arc::new(uncached)).stats().cache, None);
+    }
+}
@@ -0,0 +1,218 @@
+//! `MaskedDataset`, a dataset of instances with missing values.
+
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::metric::MaskedMetric;
+use crate::prelude::*;
+
+/// MaskedDataset serves instances with missing values, e.g. the rows of tabular data with empty or NaN fields, with a
+/// mask of the dimensions that each instance observes. Distances are computed by a `MaskedMetric` over the dimensions
+/// that both instances observe.
+///
+/// The missing values of the instances it returns are NaN if `T` is a float and zero otherwise. The metric of the
+/// dataset is its `MaskedMetric`, which treats NaN values as missing, so queries with missing values may be searched
+/// if their missing values are NaN.
+pub struct MaskedDataset<T: Number, U: Number> {
+    data: Vec<Vec<T>>,
+    observed: Vec<BitVec>,
+    metric: Arc<MaskedMetric<T, U>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for MaskedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("MaskedDataset")
+            .field("data-cardinality", &self.data.len())
+            .field("num-missing", &self.num_missing())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> MaskedDataset<T, U> {
+    /// Creates a dataset from the instances and the mask of each, which is true for the dimensions that the instance
+    /// observes. The values at the other dimensions are ignored.
+    ///
+    /// Returns an Err if there is not one mask per instance or if a mask does not have one entry per dimension of its
+    /// instance.
+    pub fn new(data: Vec<Vec<T>>, observed: Vec<Vec<bool>>, metric: MaskedMetric<T, U>) -> Result<Self, String> {
+        if observed.len() != data.len() {
+            return Err(format!(
+                "Expected masks for {} instances but got {}.",
+                data.len(),
+                observed.len()
+            ));
+        }
+        if let Some(i) = (0..data.len()).find(|&i| data[i].len() != observed[i].len()) {
+            return Err(format!(
+                "Instance {} has {} features but its mask has {} entries.",
+                i,
+                data[i].len(),
+                observed[i].len()
+            ));
+        }
+
+        let missing = T::saturating_from_f64(f64::NAN);
+        let data = data
+            .into_iter()
+            .zip(observed.iter())
+            .map(|(row, mask)| {
+                row.into_iter()
+                    .zip(mask.iter())
+                    .map(|(value, &observed)| if observed { value } else { missing })
+                    .collect()
+            })
+            .collect();
+        let observed = observed.iter().map(|mask| mask.iter().collect()).collect();
+        Ok(MaskedDataset {
+            data,
+            observed,
+            metric: Arc::new(metric),
+        })
+    }
+
+    /// Creates a dataset from instances whose missing values are NaN, e.g. as read from a `.npy` file.
+    pub fn from_nan(data: Vec<Vec<T>>, metric: MaskedMetric<T, U>) -> Self {
+        let observed = data
+            .iter()
+            .map(|row| row.iter().map(|value| !value.as_f64().is_nan()).collect())
+            .collect();
+        Self::new(data, observed, metric).unwrap()
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> MaskedDataset<T, U> {
+    /// Returns the mask of the instance at the given index, which is true for the dimensions that it observes.
+    pub fn observed(&self, index: Index) -> &BitSlice {
+        &self.observed[index]
+    }
+
+    /// Returns the number of missing values among all instances.
+    pub fn num_missing(&self) -> usize {
+        self.observed.iter().map(|mask| mask.count_zeros()).sum()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> Dataset<T, U> for MaskedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric) as Arc<dyn Metric<T, U>>
+    }
+
+    fn cardinality(&self) -> usize {
+        self.data.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.data.iter().map(|row| row.len()).max().unwrap_or(0)
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.data[index].clone()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.masked_distance(
+                &self.data[left],
+                &self.observed[left],
+                &self.data[right],
+                &self.observed[right],
+            )
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::MaskedDataset;
+
+    #[test]
+    fn test_masked_dataset() {
+        use crate::metric::MaskedMetric;
+
+        // Every third feature of the odd instances is missing.
+        let data: Vec<Vec<f64>> = (0..60)
+            .map(|i| {
+                (0..6)
+                    .map(|j| {
+                        if i % 2 == 1 && j % 3 == 0 {
+                            f64::NAN
+                        } else {
+                            (i * j % 7) as f64
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let manhattan = || MaskedMetric::new(metric_from_name("manhattan").unwrap(), false);
+        let dataset = MaskedDataset::<f64, f64>::from_nan(data.clone(), manhattan());
+        assert_eq!(dataset.num_missing(), 60);
+        assert_eq!(dataset.observed(1).count_ones(), 4);
+        assert!(dataset.instance(1)[0].is_nan());
+        assert_eq!(dataset.metric_name(), "masked-manhattan");
+
+        // Distances between instances agree with the metric on instances whose missing values are NaN.
+        for (i, j) in [(0, 2), (0, 1), (1, 3), (5, 8)] {
+            assert_eq!(dataset.distance(i, j), dataset.metric().distance(&data[i], &data[j]));
+        }
+        // Instances 1 and 3 are [_, 1, 2, _, 4, 5] and [_, 3, 6, _, 5, 1].
+        assert_eq!(dataset.distance(1, 3), 11.);
+
+        // Searches with queries that have missing values find their instances, among any others that are at distance zero
+        // over their observed dimensions.
+        let cakes = Cakes::build(Arc::new(dataset).as_arc_dataset(), None, None);
+        for i in [1, 4, 33] {
+            assert!(cakes.rnn_indices(&data[i], None).contains(&i));
+        }
+
+        // Integer instances keep zeros at their missing values.
+        let integers = MaskedDataset::<u8, u64>::new(
+            vec![vec![3, 7], vec![5, 1]],
+            vec![vec![true, false], vec![true, true]],
+            MaskedMetric::new(metric_from_name("manhattan").unwrap(), true),
+        )
+        .unwrap();
+        assert_eq!(integers.instance(0), vec![3, 0]);
+        assert_eq!(integers.distance(0, 1), 4);
+        assert!(MaskedDataset::<u8, u64>::new(
+            vec![vec![3, 7]],
+            vec![vec![true]],
+            MaskedMetric::new(metric_from_name("manhattan").unwrap(), true),
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,1847 @@
+//! `Dataset` trait and some structs implementing it.
+//!
+//! Contains the declaration and definition of the `Dataset` trait and the
+//! `RowMajor` struct implementing Dataset to serves most of the use cases for `CLAM`.
+//! A `RowMajor` may be read from `.npy`, `.npz` and delimited files, e.g. with `RowMajor::from_csv`.
+//! The `SparseRowMajor` struct serves datasets whose instances are mostly zeros, and the `QuantizedDataset` struct
+//! serves instances whose features are scalar-quantized to bytes.
+//! The `BitPackedDataset` struct serves binary instances, e.g. fingerprints, packed into words for hamming and
+//! tanimoto search.
+//! The `MmapDataset` and `ShardedDataset` structs serve datasets that are too large to fit in RAM, and the
+//! `ObjectStoreDataset` struct serves datasets from object stores, e.g. S3 or GCS, through an `ObjectSource`, of
+//! which the `object_sources` module has clients for S3 and GCS, behind the `object-store` feature.
+//! The `FastaDataset` struct serves the variable-length sequences in FASTA/FASTQ files, and the `TimeSeriesDataset`
+//! struct serves numeric series of different lengths.
+//! The `SetDataset` struct serves sets, e.g. of shingles or tokens, for jaccard search.
+//! The `MaskedDataset` struct serves instances with missing values, whose distances are computed over the dimensions
+//! that both instances observe.
+//! The `ImageDataset` struct serves images, or the patches tiled from them, as instances of flattened pixels.
+//! A `ConcatDataset` serves several datasets as one, and a `DatasetView` serves a subset of another dataset,
+//! e.g. the splits and samples from `split` and `sample`, or a `FilteredDataset` of the instances matching a predicate.
+//! A `PermutedDataset` serves the instances of another dataset in a different order, and an `InstrumentedDataset`
+//! serves them unchanged while counting the instances fetched, the distances computed and the hits of the cache.
+//! Molecules, read from SMILES strings, are served as fingerprints by the `molecules` module, behind the `chemistry`
+//! feature, Arrow record batches and Parquet files are served by the `arrow` module, behind the `arrow` feature, and
+//! datasets in HDF5 files are served by the `hdf5` module, behind the `hdf5` feature.
+//! A `RowMajor` may be normalized or standardized with a `Transform`, which must then be applied to its queries too.
+//! Instances for metrics that assume unit lengths, e.g. `angular`, may be checked or normalized with a
+//! `NormEnforcement`.
+//! A `RowMajor` may carry a metadata value for each instance, e.g. a name or a label, which `hits_with_metadata`
+//! returns alongside the indices of search results.
+//! The `Provenance` of any of these datasets traces each of its instances back to its row in the original dataset.
+//! The `StreamingDataset` trait serves instances that arrive one at a time, e.g. from generators or network streams.
+
+mod bitpacked;
+mod fasta;
+mod image;
+mod instrumented;
+mod masked;
+mod object_store;
+mod quantized;
+mod set;
+mod sharded;
+mod sparse;
+mod time_series;
+mod views;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use ndarray::prelude::*;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::RngCore;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sysinfo::System;
+use sysinfo::SystemExt;
+
+use crate::io::decode_row;
+use crate::io::npy_layout;
+use crate::io::read_file;
+use crate::io::read_npy_header;
+use crate::io::NpyHeader;
+use crate::memory::Subsystem;
+use crate::memory::Tracked;
+use crate::metric::Dimensionality;
+use crate::metric::DistanceAs;
+use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::utils::write_cache;
+
+pub use bitpacked::pack_bits;
+pub use bitpacked::BitPackedDataset;
+pub use fasta::FastaDataset;
+pub use image::ImageDataset;
+pub use image::ImageShape;
+pub use image::PatchOptions;
+pub use image::PatchOrigin;
+pub use instrumented::AccessStats;
+pub use instrumented::InstrumentedDataset;
+pub use masked::MaskedDataset;
+pub use object_store::FileSource;
+pub use object_store::ObjectSource;
+pub use object_store::ObjectStoreDataset;
+pub use quantized::QuantizedDataset;
+pub use set::hash_tokens;
+pub use set::SetDataset;
+pub use sharded::ShardedDataset;
+pub use sparse::SparseRowMajor;
+pub use time_series::TimeSeriesDataset;
+pub use views::ConcatDataset;
+pub use views::DatasetView;
+pub use views::FilteredDataset;
+pub use views::PermutedDataset;
+
+pub(crate) use sharded::LoadedShards;
+pub(crate) use sharded::Shard;
+
+type Cache<U> = Arc<DistanceCache<U>>;
+
+/// The default maximum number of distances held in the cache of a `RowMajor`, about 64 MiB of `f64` distances.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1 << 20;
+
+/// A bounded cache of the distances between pairs of instances, evicting with the clock (second-chance) policy.
+///
+/// Each cached distance has a reference bit that a lookup sets under the shared read lock, so hits never wait on
+/// each other. Once the cache holds `capacity` distances, inserting another sweeps a hand over the distances,
+/// clearing the bits it passes, and evicts the first distance that was not used since the hand last passed it.
+struct DistanceCache<U: Number> {
+    capacity: usize,
+    state: RwLock<DistanceCacheState<U>>,
+    /// The memory held by the cached distances, as counted by `memory_report`.
+    memory: Tracked,
+    /// The numbers of lookups that found, and did not find, their distance.
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct DistanceCacheState<U: Number> {
+    /// The position in `slots` of each cached distance.
+    positions: HashMap<(Index, Index), usize>,
+    slots: Vec<CacheSlot<U>>,
+    /// The position in `slots` at which the next eviction starts looking.
+    hand: usize,
+}
+
+struct CacheSlot<U: Number> {
+    key: (Index, Index),
+    distance: U,
+    /// Whether the distance was used since the hand last passed it.
+    referenced: AtomicBool,
+}
+
+impl<U: Number> DistanceCache<U> {
+    fn new(capacity: usize) -> Self {
+        DistanceCache {
+            capacity,
+            state: RwLock::new(DistanceCacheState {
+                positions: HashMap::new(),
+                slots: Vec::new(),
+                hand: 0,
+            }),
+            memory: Tracked::new(Subsystem::Caches, 0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of bytes one cached distance takes, including its slot and its entry in the positions.
+    fn entry_size() -> usize {
+        std::mem::size_of::<CacheSlot<U>>() + std::mem::size_of::<((Index, Index), usize)>()
+    }
+
+    fn get(&self, key: (Index, Index)) -> Option<U> {
+        let state = read_cache(&self.state);
+        let Some(&position) = state.positions.get(&key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let slot = &state.slots[position];
+        slot.referenced.store(true, Ordering::Relaxed);
+        Some(slot.distance)
+    }
+
+    fn insert(&self, key: (Index, Index), distance: U) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = write_cache(&self.state);
+        let state = &mut *state;
+        if let Some(&position) = state.positions.get(&key) {
+            let slot = &mut state.slots[position];
+            slot.distance = distance;
+            *slot.referenced.get_mut() = true;
+            return;
+        }
+        let slot = CacheSlot {
+            key,
+            distance,
+            referenced: AtomicBool::new(false),
+        };
+        if state.slots.len() < self.capacity {
+            state.positions.insert(key, state.slots.len());
+            state.slots.push(slot);
+            self.memory.grow(Self::entry_size());
+            return;
+        }
+        // Every pass of the hand clears the bits it finds set, so this stops within two sweeps.
+        while std::mem::take(state.slots[state.hand].referenced.get_mut()) {
+            state.hand = (state.hand + 1) % state.slots.len();
+        }
+        let evicted = std::mem::replace(&mut state.slots[state.hand], slot);
+        state.positions.remove(&evicted.key);
+        state.positions.insert(key, state.hand);
+        state.hand = (state.hand + 1) % state.slots.len();
+    }
+
+    fn len(&self) -> usize {
+        read_cache(&self.state).slots.len()
+    }
+
+    fn lookups(&self) -> CacheLookups {
+        CacheLookups {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn clear(&self) {
+        let mut state = write_cache(&self.state);
+        state.positions.clear();
+        state.slots.clear();
+        state.hand = 0;
+        self.memory.set(0);
+    }
+}
+
+/// The numbers of lookups in the distance cache of a dataset since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheLookups {
+    /// The lookups that found their distance in the cache.
+    pub hits: u64,
+    /// The lookups whose distance had to be computed.
+    pub misses: u64,
+}
+
+/// All datasets supplied to `CLAM` must implement this trait.
+pub trait Dataset<T: Number, U: Number>: std::fmt::Debug + Send + Sync {
+    /// Returns the function used to compute the distance between instances.
+    fn metric(&self) -> Arc<dyn Metric<T, U>>;
+
+    fn metric_name(&self) -> String {
+        self.metric().name()
+    }
+
+    /// Returns the number of instances in the dataset.
+    fn cardinality(&self) -> usize;
+
+    /// Returns the dimensionality of the dataset
+    fn dimensionality(&self) -> usize;
+
+    /// Returns the Indices for the dataset.
+    fn indices(&self) -> Vec<Index>;
+
+    /// Returns the instance at the given index.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the instance to return from the dataset.
+    ///
+    fn instance(&self, index: Index) -> Vec<T>;
+
+    /// Randomly chooses `n` unique instances from the given indices and returns their indices.
+    ///
+    /// The chosen instances are at non-zero distances from each other, so that no two of them are identical under the
+    /// metric, e.g. so that the poles of a partition are distinct. There are fewer than `n` if the given indices hold
+    /// fewer unique instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices` - Indices from among which to collect sample.
+    /// * `n` - The number of unique instances
+    fn choose_unique(&self, indices: Vec<Index>, n: usize) -> Vec<Index> {
+        self.choose_unique_with_rng(indices, n, &mut rand::thread_rng())
+    }
+
+    /// Chooses unique instances as with `choose_unique`, shuffling the indices with the given generator, e.g. a seeded
+    /// one so that the choice is reproducible.
+    fn choose_unique_with_rng(&self, indices: Vec<Index>, n: usize, rng: &mut dyn RngCore) -> Vec<Index> {
+        let mut indices = indices;
+        indices.shuffle(rng);
+        let mut chosen = Vec::with_capacity(n);
+        for i in indices {
+            if chosen.len() == n {
+                break;
+            }
+            if self
+                .distances_from(i, &chosen)
+                .iter()
+                .all(|&distance| distance > U::zero())
+            {
+                chosen.push(i);
+            }
+        }
+        chosen
+    }
+
+    /// Groups the identical instances and returns the index of the representative of each instance, at the position
+    /// of its index, which is the first instance of its group. The instances in a group are identical value for
+    /// value, so they are at distance zero under any metric.
+    fn deduplicate(&self) -> Vec<Index> {
+        let mut representatives: HashMap<Vec<u8>, Index> = HashMap::new();
+        (0..self.cardinality())
+            .map(|i| {
+                let bytes = self.instance(i).iter().flat_map(|value| value.to_bytes()).collect();
+                *representatives.entry(bytes).or_insert(i)
+            })
+            .collect()
+    }
+
+    /// Randomly sub-samples n unique indices (without replacement) from the dataset.
+    /// Returns a tuple of the sub-sampled indices and the remaining indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The number of indices to choose.
+    fn subsample_indices(&self, n: usize) -> (Vec<Index>, Vec<Index>) {
+        let mut indices = self.indices();
+        indices.shuffle(&mut rand::thread_rng());
+        let (sample, complement) = indices.split_at(n);
+        (sample.to_vec(), complement.to_vec())
+    }
+
+    /// Returns the size of the memory footprint of an instance in Bytes.
+    fn instance_size(&self) -> usize {
+        self.dimensionality() * (U::num_bytes() as usize)
+    }
+
+    /// Returns the batch-size to use depending on available RAM.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_fraction` - The fraction (between 0 and 1) of RAM to use for each batch.
+    ///                       Defaults to 0.5
+    fn batch_size(&self, memory_fraction: Option<f32>) -> usize {
+        let batch_fraction = match memory_fraction {
+            Some(f) => {
+                if 0.0 < f && f < 0.9 {
+                    f
+                } else {
+                    0.5
+                }
+            }
+            None => 0.5,
+        };
+
+        let system = System::new_all();
+        let available_memory = system.available_memory() as usize;
+        let batch_size = (available_memory as f32) * batch_fraction * 1024.;
+        (batch_size as usize) / self.instance_size()
+    }
+
+    /// Collects the instances corresponding to the given indices and returns them as a RowMajor dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices` - The indices from which to build the subset
+    fn row_major_subset(&self, indices: &[Index]) -> Arc<RowMajor<T, U>> {
+        let instances = indices.par_iter().map(|&i| self.instance(i)).collect();
+        let metadata = indices
+            .iter()
+            .map(|&i| self.instance_metadata(i))
+            .collect::<Option<_>>();
+        let subset = RowMajor {
+            data: Arc::new(instances),
+            use_cache: true,
+            metric: self.metric(),
+            cache: Arc::new(DistanceCache::new(DEFAULT_CACHE_CAPACITY)),
+            metadata: metadata.map(Arc::new),
+            transforms: vec![],
+        };
+        Arc::new(subset)
+    }
+
+    /// Returns the distance between the two instances at the indices provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Index of an instance to compute distance from
+    /// * `right`- Index of an instance to compute distance from
+    fn distance(&self, left: Index, right: Index) -> U;
+
+    /// Returns the distances from the instance at left to all instances with indices in right.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Index of the instance to compute distances from
+    /// * `right` - Indices of the instances to compute distances to
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U>;
+
+    /// Returns distances from the instances with indices in left to the instances
+    /// with indices in right.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Indices of instances
+    /// * `right` - Indices of instances
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U>;
+
+    /// Returns the pairwise distances between the instances at the given indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices` - Indices of instances among which to compute pairwise distances.
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U>;
+
+    /// Returns the row of the original dataset that each instance came from.
+    ///
+    /// Datasets that rearrange the instances of another dataset, e.g. views and permutations, compose their own
+    /// rearrangement with the provenance of that dataset, so the rows are those of the innermost dataset.
+    /// All other datasets are their own originals.
+    fn provenance(&self) -> Provenance {
+        Provenance::identity(self.cardinality())
+    }
+
+    /// Returns the metadata of the instance at the given index, e.g. its name or label, or None if it has none.
+    ///
+    /// Datasets that rearrange or combine the instances of other datasets return the metadata of the instance in
+    /// the dataset it came from.
+    fn instance_metadata(&self, _index: Index) -> Option<Value> {
+        None
+    }
+
+    /// Returns the numbers of hits and misses of the distance cache of the dataset, or None if it has no cache.
+    ///
+    /// Datasets that wrap another dataset return the lookups of the cache of that dataset.
+    fn cache_lookups(&self) -> Option<CacheLookups> {
+        None
+    }
+}
+
+/// A source of instances that arrive one at a time, e.g. from a generator or a network stream.
+///
+/// `Cakes::build_from_stream` partitions the first instances of the stream and inserts the rest into that tree,
+/// so the instances need not be collected into a `Dataset` before the tree is built.
+pub trait StreamingDataset<T: Number, U: Number>: Iterator<Item = Vec<T>> {
+    /// Returns the metric with which the instances are compared.
+    fn metric(&self) -> Arc<dyn Metric<T, U>>;
+}
+
+/// A `StreamingDataset` over any iterator of instances.
+pub struct InstanceStream<T: Number, U: Number, I: Iterator<Item = Vec<T>>> {
+    instances: I,
+    metric: Arc<dyn Metric<T, U>>,
+}
+
+impl<T: Number, U: Number, I: Iterator<Item = Vec<T>>> InstanceStream<T, U, I> {
+    pub fn new(instances: impl IntoIterator<IntoIter = I>, metric: Arc<dyn Metric<T, U>>) -> Self {
+        InstanceStream {
+            instances: instances.into_iter(),
+            metric,
+        }
+    }
+}
+
+impl<T: Number, U: Number, I: Iterator<Item = Vec<T>>> Iterator for InstanceStream<T, U, I> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        self.instances.next()
+    }
+}
+
+impl<T: Number, U: Number, I: Iterator<Item = Vec<T>>> StreamingDataset<T, U> for InstanceStream<T, U, I> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+}
+
+/// RowMajor represents a dataset stored as a 2-dimensional array
+/// where rows are instances and columns are features/attributes.
+///
+/// A wrapper around an `ndarray::Array2` of data, along with a `Metric`,
+/// to provide an interface for computing distances between instances
+/// contained within the dataset.
+///
+/// The resulting structure can make use of caching techniques to prevent
+/// repeated (potentially expensive) calls to its internal distance function.
+pub struct RowMajor<T: Number, U: Number> {
+    /// 2D array of data
+    pub data: Arc<Vec<Vec<T>>>,
+
+    // The stored function, used to compute distances.
+    pub metric: Arc<dyn Metric<T, U>>,
+
+    /// Whether this dataset should use an internal cache (recommended)
+    pub use_cache: bool,
+
+    // The internal cache, which holds at most `DEFAULT_CACHE_CAPACITY` distances unless configured otherwise.
+    cache: Cache<U>,
+
+    /// The metadata of each instance, at the position of its index, if any was attached with `with_metadata`.
+    metadata: Option<Arc<Vec<Value>>>,
+
+    /// The transforms that were applied to the instances, in order, by `normalized`, `standardized` or `transformed`.
+    transforms: Vec<Transform>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for RowMajor<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("RowMajor Dataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality())
+            .field("cache-usage", &self.use_cache)
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> RowMajor<T, U> {
+    /// Create a new Dataset, using the provided data and metric, optionally use a cache.
+    ///
+    /// # Arguments
+    ///
+    /// * data - a 2-dimensional array.
+    /// * metric - distance-metric to use.
+    /// * use_cache - whether to use an internal cache for storing distances.
+    pub fn new(data: Arc<Vec<Vec<T>>>, metric: Arc<dyn Metric<T, U>>, use_cache: bool) -> RowMajor<T, U> {
+        RowMajor {
+            data,
+            metric,
+            use_cache,
+            cache: Arc::new(DistanceCache::new(DEFAULT_CACHE_CAPACITY)),
+            metadata: None,
+            transforms: vec![],
+        }
+    }
+
+    /// Create a new Dataset as with `new`, after checking the rows against the metric.
+    ///
+    /// Returns an Err naming the first malformed row if there are no rows, if a row does not have the length the
+    /// metric needs, see `Metric::dimensionality`, or, with `require_finite`, if a row holds NaN or an infinity.
+    pub fn try_from_rows(
+        rows: Vec<Vec<T>>,
+        metric: Arc<dyn Metric<T, U>>,
+        use_cache: bool,
+        require_finite: bool,
+    ) -> Result<Self, String> {
+        if rows.is_empty() {
+            return Err("A dataset must have at least one row.".to_string());
+        }
+        let expected = match metric.dimensionality() {
+            Dimensionality::Any => None,
+            Dimensionality::Equal => Some(rows[0].len()),
+            Dimensionality::Exactly(length) => Some(length),
+        };
+        for (i, row) in rows.iter().enumerate() {
+            if let Some(expected) = expected.filter(|&expected| row.len() != expected) {
+                return Err(format!(
+                    "Row {} has {} features but the {} metric needs {}.",
+                    i,
+                    row.len(),
+                    metric.name(),
+                    expected
+                ));
+            }
+            if let Some(j) = row
+                .iter()
+                .position(|value| require_finite && !value.as_f64().is_finite())
+            {
+                return Err(format!("Row {} holds {} in column {}.", i, row[j], j));
+            }
+        }
+        Ok(Self::new(Arc::new(rows), metric, use_cache))
+    }
+
+    /// Attaches a metadata value to each instance, at the position of its index, e.g. the metadata columns returned
+    /// by `from_csv`. The values are held as JSON, so any type that serde can serialize may be attached, and read back
+    /// as that type with `metadata_as` or `hits_with_metadata`.
+    ///
+    /// Returns an Err if there is not one value per instance or if a value cannot be serialized.
+    pub fn with_metadata<M: Serialize>(mut self, metadata: &[M]) -> Result<Self, String> {
+        if metadata.len() != self.data.len() {
+            return Err(format!(
+                "Expected metadata for {} instances but got {}.",
+                self.data.len(),
+                metadata.len()
+            ));
+        }
+        let values = metadata
+            .iter()
+            .map(|value| serde_json::to_value(value).map_err(|error| format!("Failed to serialize metadata. {}", error)))
+            .collect::<Result<_, _>>()?;
+        self.metadata = Some(Arc::new(values));
+        Ok(self)
+    }
+
+    /// Returns the metadata of every instance, at the position of its index, if any was attached.
+    pub fn metadata(&self) -> Option<&[Value]> {
+        self.metadata.as_ref().map(|metadata| metadata.as_slice())
+    }
+
+    /// Replaces the internal cache with an empty one that holds at most `capacity` distances, evicting the least
+    /// recently used distance when it is full.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = Arc::new(DistanceCache::new(capacity));
+        self
+    }
+
+    /// Replaces the internal cache with an empty one that holds as many distances as fit in about `bytes` bytes.
+    pub fn with_cache_budget(self, bytes: usize) -> Self {
+        self.with_cache_capacity(bytes / DistanceCache::<U>::entry_size())
+    }
+
+    /// Returns the maximum number of distances held in the internal cache.
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.capacity
+    }
+
+    /// Clears the internal cache.
+    pub fn clear_cache(&self) {
+        self.cache.clear()
+    }
+
+    /// Returns the number of elements in the internal cache.
+    pub fn cache_size(&self) -> Option<usize> {
+        Some(self.cache.len())
+    }
+
+    /// Returns the numbers of hits and misses of the internal cache. Replacing the cache resets them.
+    pub fn cache_lookups(&self) -> CacheLookups {
+        self.cache.lookups()
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+
+    /// Reads a Dataset from a `.npy` file holding a 2-dimensional, row-major array of `T`, e.g. as written by numpy.
+    ///
+    /// Returns an Err if the file holds any other array. See `NpyRowMajor` to read arrays whose type is not known.
+    pub fn from_npy(path: &Path, metric: Arc<dyn Metric<T, U>>, use_cache: bool) -> Result<Self, String> {
+        let bytes = read_file(path)?;
+        let data =
+            rows_from_npy(&bytes).map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))?;
+        Ok(Self::new(Arc::new(data), metric, use_cache))
+    }
+
+    /// Reads a Dataset from the array with the given name in a `.npz` file, e.g. as written by `numpy.savez`.
+    /// Arrays saved without names are named `arr_0`, `arr_1` and so on.
+    pub fn from_npz(path: &Path, name: &str, metric: Arc<dyn Metric<T, U>>, use_cache: bool) -> Result<Self, String> {
+        let bytes = read_npz_member(path, name)?;
+        let data = rows_from_npy(&bytes)
+            .map_err(|error| format!("Error: Failed to read '{}' in {}. {}", name, path.display(), error))?;
+        Ok(Self::new(Arc::new(data), metric, use_cache))
+    }
+
+    /// Reads a Dataset from the feature columns of a delimited file, and returns it along with the values of the
+    /// metadata columns of each row, in the order in which they were selected.
+    ///
+    /// Each feature is parsed as a `T`. Integral values may be written like floats, e.g. `3.0` or `1e3`, and `true`
+    /// and `false` are read as 1 and 0. Returns an Err for empty fields, fields that cannot be represented as a `T`,
+    /// rows of the wrong length and columns that are not in the file.
+    pub fn from_csv(
+        path: &Path,
+        options: &CsvOptions,
+        metric: Arc<dyn Metric<T, U>>,
+        use_cache: bool,
+    ) -> Result<(Self, Vec<Vec<String>>), String> {
+        let fail = |error: String| format!("Error: Failed to read {}. {}", path.display(), error);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .has_headers(options.has_header)
+            .from_path(path)
+            .map_err(|error| fail(error.to_string()))?;
+        let header = if options.has_header {
+            Some(reader.headers().map_err(|error| fail(error.to_string()))?.clone())
+        } else {
+            None
+        };
+
+        let (mut data, mut metadata) = (vec![], vec![]);
+        let mut columns = None;
+        for record in reader.records() {
+            let record = record.map_err(|error| fail(error.to_string()))?;
+            let (features, metadata_columns) = match &columns {
+                Some(columns) => columns,
+                None => columns.insert(options.resolve(header.as_ref(), record.len()).map_err(fail)?),
+            };
+            let line = record.position().map_or(0, |position| position.line());
+            let row = features
+                .iter()
+                .map(|&column| {
+                    parse_csv_field(&record[column])
+                        .map_err(|error| fail(format!("Line {}, column {}: {}", line, column, error)))
+                })
+                .collect::<Result<_, _>>()?;
+            data.push(row);
+            metadata.push(
+                metadata_columns
+                    .iter()
+                    .map(|&column| record[column].to_string())
+                    .collect(),
+            );
+        }
+
+        Ok((Self::new(Arc::new(data), metric, use_cache), metadata))
+    }
+
+    /// Returns a dataset of the instances scaled to unit length in the given norm, e.g. for cosine search.
+    /// Instances of length zero are left as they are.
+    ///
+    /// Returns an Err if `T` is not a float. See `transformed`.
+    pub fn normalized(&self, norm: Norm) -> Result<Self, String> {
+        self.transformed(Transform::Normalize(norm))
+    }
+
+    /// Returns a dataset of the instances with each feature shifted to a mean of 0 and scaled to a standard deviation
+    /// of 1, so that features with larger ranges do not dominate the distances. Features that are constant are only
+    /// shifted.
+    ///
+    /// Returns an Err if `T` is not a float or if the instances have different dimensionalities. See `transformed`.
+    pub fn standardized(&self) -> Result<Self, String> {
+        self.transformed(Transform::standardizing(&self.data)?)
+    }
+
+    /// Returns a dataset of the instances with the transform applied, e.g. one fitted to another dataset, with the
+    /// same metric, metadata and cache capacity and with an empty cache.
+    ///
+    /// Queries must be transformed in the same way before they are searched, e.g. with `transform_query`.
+    ///
+    /// Returns an Err if `T` is not a float, since the transformed values of integers would be rounded, or if the
+    /// instances do not all have the dimensionality of a `Standardize` transform.
+    pub fn transformed(&self, transform: Transform) -> Result<Self, String> {
+        if !matches!(T::type_name(), "f32" | "f64") {
+            return Err(format!("Cannot transform instances of {}.", T::type_name()));
+        }
+        if let Transform::Standardize { means, .. } = &transform {
+            if let Some(i) = self.data.iter().position(|row| row.len() != means.len()) {
+                return Err(format!(
+                    "Instance {} has {} features but the transform has {}.",
+                    i,
+                    self.data[i].len(),
+                    means.len()
+                ));
+            }
+        }
+
+        let data = self.data.par_iter().map(|row| transform.apply(row)).collect();
+        let mut transforms = self.transforms.clone();
+        transforms.push(transform);
+        Ok(RowMajor {
+            data: Arc::new(data),
+            metric: Arc::clone(&self.metric),
+            use_cache: self.use_cache,
+            cache: Arc::new(DistanceCache::new(self.cache.capacity)),
+            metadata: self.metadata.clone(),
+            transforms,
+        })
+    }
+
+    /// Verifies that the instances have the unit length that the metric needs, see `Metric::required_norm`, or
+    /// normalizes them, as the enforcement says. Instances of length zero cannot be normalized, so they are left as
+    /// they are. Datasets whose metric needs no norm are returned as they are.
+    ///
+    /// Normalizing records the transform, so queries must be normalized too, e.g. with `transform_query` or by
+    /// `Cakes::with_norm_enforcement`.
+    ///
+    /// Returns an Err naming the first instance that violates the norm, or, when normalizing, if `T` is not a float.
+    pub fn enforcing_norm(self, enforcement: NormEnforcement) -> Result<Self, String> {
+        let norm = match self.metric.required_norm() {
+            Some(norm) => norm,
+            None => return Ok(self),
+        };
+        match enforcement {
+            NormEnforcement::Verify => {
+                for (i, row) in self.data.iter().enumerate() {
+                    enforcement
+                        .enforce(self.metric.as_ref(), row)
+                        .map_err(|error| format!("Instance {}: {}", i, error))?;
+                }
+                Ok(self)
+            }
+            NormEnforcement::Normalize => self.normalized(norm),
+        }
+    }
+
+    /// Returns a dataset of the same instances, without copying them, whose distances are those of the metric converted
+    /// to `V` by a `DistanceAs`, e.g. to search one set of strings with both `u32` and `f64` edit distances. The
+    /// metadata, transforms and cache capacity are kept, and the cache starts empty.
+    ///
+    /// See `DistanceAs` for how distances are rounded and what precision each type holds.
+    pub fn with_distance_type<V: 'static + Number>(&self) -> RowMajor<T, V> {
+        RowMajor {
+            data: Arc::clone(&self.data),
+            metric: Arc::new(DistanceAs::new(Arc::clone(&self.metric))),
+            use_cache: self.use_cache,
+            cache: Arc::new(DistanceCache::new(self.cache.capacity)),
+            metadata: self.metadata.clone(),
+            transforms: self.transforms.clone(),
+        }
+    }
+
+    /// Returns the transforms that were applied to the instances, in order.
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+
+    /// Applies the transforms of the instances to a query, in order, so that it may be searched against them.
+    pub fn transform_query(&self, query: &[T]) -> Vec<T> {
+        self.transforms
+            .iter()
+            .fold(query.to_vec(), |query, transform| transform.apply(&query))
+    }
+}
+
+/// The norms to which `RowMajor::normalized` may scale instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Norm {
+    /// The sum of the absolute values of the features.
+    L1,
+    /// The euclidean length.
+    L2,
+    /// The largest absolute value of any feature.
+    Max,
+}
+
+impl Norm {
+    /// Returns the length of the instance in this norm.
+    pub fn length<T: Number>(&self, instance: &[T]) -> f64 {
+        let values = instance.iter().map(|value| value.as_f64());
+        match self {
+            Norm::L1 => values.map(f64::abs).sum(),
+            Norm::L2 => values.map(|value| value * value).sum::<f64>().sqrt(),
+            Norm::Max => values.map(f64::abs).fold(0., f64::max),
+        }
+    }
+}
+
+/// The relative difference from one within which the length of an instance counts as unit, for `NormEnforcement`.
+pub const UNIT_NORM_TOLERANCE: f64 = 1e-4;
+
+/// How instances and queries are held to the norm that their metric needs, see `Metric::required_norm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormEnforcement {
+    /// Instances whose lengths are not within `UNIT_NORM_TOLERANCE` of one are errors.
+    Verify,
+    /// Instances whose lengths are not within `UNIT_NORM_TOLERANCE` of one are scaled to unit length.
+    Normalize,
+}
+
+impl NormEnforcement {
+    /// Returns the instance as the metric needs it, borrowed if it already has unit length or the metric needs no
+    /// norm, or an Err if it violates the norm under `Verify`, or cannot be normalized, i.e. if it has length zero
+    /// or `T` is not a float.
+    pub fn enforce<'a, T: Number, U: Number>(
+        &self,
+        metric: &dyn Metric<T, U>,
+        instance: &'a [T],
+    ) -> Result<Cow<'a, [T]>, String> {
+        let norm = match metric.required_norm() {
+            Some(norm) => norm,
+            None => return Ok(Cow::Borrowed(instance)),
+        };
+        let length = norm.length(instance);
+        if (length - 1.).abs() <= UNIT_NORM_TOLERANCE {
+            return Ok(Cow::Borrowed(instance));
+        }
+        let normalizable = length > 0. && matches!(T::type_name(), "f32" | "f64");
+        match self {
+            NormEnforcement::Normalize if normalizable => Ok(Cow::Owned(Transform::Normalize(norm).apply(instance))),
+            _ => Err(format!(
+                "The instance has a length of {} in the {:?} norm but the {} metric needs instances of unit length.",
+                length,
+                norm,
+                metric.name()
+            )),
+        }
+    }
+}
+
+/// A rescaling of instances before their distances are computed. See `RowMajor::transformed`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    /// Scales each instance to unit length in the norm.
+    Normalize(Norm),
+
+    /// Subtracts the mean of each feature and divides the difference by the standard deviation of the feature,
+    /// or by 1 if the deviation is zero.
+    Standardize { means: Vec<f64>, deviations: Vec<f64> },
+}
+
+impl Transform {
+    /// Returns the `Standardize` transform with the means and standard deviations of the features of the instances.
+    ///
+    /// Returns an Err if there are no instances or if they have different dimensionalities.
+    pub fn standardizing<T: Number>(data: &[Vec<T>]) -> Result<Self, String> {
+        let dimensionality = match data.first() {
+            Some(row) => row.len(),
+            None => return Err("Cannot standardize zero instances.".to_string()),
+        };
+        if let Some(i) = data.iter().position(|row| row.len() != dimensionality) {
+            return Err(format!(
+                "Instance {} has {} features but the first instance has {}.",
+                i,
+                data[i].len(),
+                dimensionality
+            ));
+        }
+
+        let n = data.len() as f64;
+        let mut means = vec![0.; dimensionality];
+        for row in data.iter() {
+            means
+                .iter_mut()
+                .zip(row.iter())
+                .for_each(|(mean, value)| *mean += value.as_f64() / n);
+        }
+        let mut deviations = vec![0.; dimensionality];
+        for row in data.iter() {
+            for (j, value) in row.iter().enumerate() {
+                deviations[j] += (value.as_f64() - means[j]).powi(2) / n;
+            }
+        }
+        let deviations = deviations.into_iter().map(f64::sqrt).collect();
+        Ok(Transform::Standardize { means, deviations })
+    }
+
+    /// Returns the transformed instance.
+    ///
+    /// # Panics
+    ///
+    /// * If the transform is `Standardize` and the instance has more features than it has means.
+    pub fn apply<T: Number>(&self, instance: &[T]) -> Vec<T> {
+        let values = instance.iter().map(|value| value.as_f64());
+        match self {
+            Transform::Normalize(norm) => {
+                let length = norm.length(instance);
+                if length > 0. {
+                    instance
+                        .iter()
+                        .map(|value| T::saturating_from_f64(value.as_f64() / length))
+                        .collect()
+                } else {
+                    instance.to_vec()
+                }
+            }
+            Transform::Standardize { means, deviations } => values
+                .enumerate()
+                .map(|(j, value)| {
+                    let deviation = if deviations[j] > 0. { deviations[j] } else { 1. };
+                    T::saturating_from_f64((value - means[j]) / deviation)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A column of a delimited file, given by its position or by its name in the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvColumn {
+    Position(usize),
+    Name(String),
+}
+
+/// How `RowMajor::from_csv` reads a delimited file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    /// The byte that separates fields. Defaults to a comma.
+    pub delimiter: u8,
+
+    /// Whether the first row names the columns. Defaults to true.
+    pub has_header: bool,
+
+    /// The columns holding the features of the instances, in order.
+    /// If None, every column that is not a metadata column is a feature.
+    pub features: Option<Vec<CsvColumn>>,
+
+    /// The columns holding metadata, e.g. names or labels, which are returned as text.
+    pub metadata: Vec<CsvColumn>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            has_header: true,
+            features: None,
+            metadata: vec![],
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Returns the positions of the feature and metadata columns in rows with `num_columns` fields.
+    fn resolve(
+        &self,
+        header: Option<&csv::StringRecord>,
+        num_columns: usize,
+    ) -> Result<(Vec<usize>, Vec<usize>), String> {
+        let position = |column: &CsvColumn| match column {
+            CsvColumn::Position(i) if *i < num_columns => Ok(*i),
+            CsvColumn::Position(i) => Err(format!("Column {} is out of bounds for {} columns.", i, num_columns)),
+            CsvColumn::Name(name) => header
+                .and_then(|header| header.iter().position(|field| field == name))
+                .ok_or_else(|| format!("There is no column named '{}'.", name)),
+        };
+        let metadata = self.metadata.iter().map(position).collect::<Result<Vec<_>, _>>()?;
+        let features = match &self.features {
+            Some(features) => features.iter().map(position).collect::<Result<_, _>>()?,
+            None => (0..num_columns).filter(|i| !metadata.contains(i)).collect(),
+        };
+        Ok((features, metadata))
+    }
+}
+
+/// Parses a field of a delimited file as a `T`.
+fn parse_csv_field<T: Number>(field: &str) -> Result<T, String> {
+    let field = field.trim();
+    if let Ok(value) = T::from_str_radix(field, 10) {
+        return Ok(value);
+    }
+    let value = match field.to_lowercase().as_str() {
+        "" => return Err("The field is empty.".to_string()),
+        "true" => 1.,
+        "false" => 0.,
+        _ => field
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is not a number.", field))?,
+    };
+    T::checked_from_f64(value).ok_or_else(|| format!("'{}' cannot be represented as {}.", field, T::type_name()))
+}
+
+/// A `RowMajor` dataset read from a `.npy` or `.npz` file, with the type of its instances inferred from the array.
+pub enum NpyRowMajor<U: Number> {
+    F32(RowMajor<f32, U>),
+    F64(RowMajor<f64, U>),
+    U8(RowMajor<u8, U>),
+}
+
+impl<U: 'static + Number> NpyRowMajor<U> {
+    /// Reads a Dataset from a `.npy` file holding a 2-dimensional, row-major array of f32, f64 or u8.
+    ///
+    /// # Arguments
+    ///
+    /// * path - the `.npy` file.
+    /// * metric - the name of the distance-metric to use. See `metric_from_name`.
+    /// * use_cache - whether to use an internal cache for storing distances.
+    pub fn from_npy(path: &Path, metric: &str, use_cache: bool) -> Result<Self, String> {
+        let bytes = read_file(path)?;
+        Self::from_npy_bytes(&bytes, metric, use_cache)
+            .map_err(|error| format!("Error: Failed to read {}. {}", path.display(), error))
+    }
+
+    /// Reads a Dataset from the array with the given name in a `.npz` file. See `from_npy`.
+    pub fn from_npz(path: &Path, name: &str, metric: &str, use_cache: bool) -> Result<Self, String> {
+        let bytes = read_npz_member(path, name)?;
+        Self::from_npy_bytes(&bytes, metric, use_cache)
+            .map_err(|error| format!("Error: Failed to read '{}' in {}. {}", name, path.display(), error))
+    }
+
+    fn from_npy_bytes(bytes: &[u8], metric: &str, use_cache: bool) -> Result<Self, String> {
+        let header = read_npy_header(bytes)?;
+        match header.descr.get(1..) {
+            Some("f4") => Ok(Self::F32(RowMajor::new(
+                Arc::new(rows_from_npy(bytes)?),
+                metric_from_name(metric)?,
+                use_cache,
+            ))),
+            Some("f8") => Ok(Self::F64(RowMajor::new(
+                Arc::new(rows_from_npy(bytes)?),
+                metric_from_name(metric)?,
+                use_cache,
+            ))),
+            Some("u1") => Ok(Self::U8(RowMajor::new(
+                Arc::new(rows_from_npy(bytes)?),
+                metric_from_name(metric)?,
+                use_cache,
+            ))),
+            _ => Err(format!(
+                "The array holds '{}' but only f32, f64 and u8 are supported.",
+                header.descr
+            )),
+        }
+    }
+
+    /// Returns the name of the type of the instances, e.g. "f32".
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::F32(_) => f32::type_name(),
+            Self::F64(_) => f64::type_name(),
+            Self::U8(_) => u8::type_name(),
+        }
+    }
+}
+
+/// Decodes the rows of the 2-dimensional array of `T` in the bytes of a `.npy` file.
+fn rows_from_npy<T: Number>(bytes: &[u8]) -> Result<Vec<Vec<T>>, String> {
+    let header = read_npy_header(bytes)?;
+    let (cardinality, dimensionality, big_endian) = npy_layout::<T>(&header, bytes.len())?;
+    let length = dimensionality * T::num_bytes() as usize;
+    if length == 0 {
+        return Ok(vec![vec![]; cardinality]);
+    }
+    Ok(bytes[header.offset..]
+        .chunks(length)
+        .map(|row| decode_row(row, big_endian))
+        .collect())
+}
+
+/// Returns the bytes of the `.npy` file of the array with the given name in a `.npz` file.
+fn read_npz_member(path: &Path, name: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let fail = |error: String| format!("Error: Failed to read '{}' in {}. {}", name, path.display(), error);
+    let file = std::fs::File::open(path).map_err(|error| fail(error.to_string()))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|error| fail(error.to_string()))?;
+    // numpy names the members after the arrays, with a `.npy` extension, but other writers may leave it out.
+    let member_name = [format!("{}.npy", name), name.to_string()]
+        .into_iter()
+        .find(|candidate| archive.file_names().any(|file_name| file_name == candidate))
+        .ok_or_else(|| fail("There is no such array in the archive.".to_string()))?;
+    let mut member = archive.by_name(&member_name).map_err(|error| fail(error.to_string()))?;
+    let mut bytes = Vec::with_capacity(member.size() as usize);
+    member
+        .read_to_end(&mut bytes)
+        .map_err(|error| fail(error.to_string()))?;
+    Ok(bytes)
+}
+
+/// Reads the header of the `.npy` file at the given path, without reading its array, and returns it along with the
+/// length of the file.
+fn read_npy_file_header(path: &Path) -> Result<(NpyHeader, usize), String> {
+    use std::io::Read;
+    use std::io::Seek;
+
+    let mut file = std::fs::File::open(path).map_err(|error| error.to_string())?;
+    let file_length = file.metadata().map_err(|error| error.to_string())?.len() as usize;
+    let header = read_npy_prefix(file_length, |range| {
+        let mut bytes = vec![0; range.len()];
+        file.seek(std::io::SeekFrom::Start(range.start as u64))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(|error| error.to_string())?;
+        Ok(bytes)
+    })?;
+    Ok((header, file_length))
+}
+
+/// Reads the `.npy` header at the start of a file or object of `length` bytes, given a reader of ranges of its bytes,
+/// without reading any of the array.
+fn read_npy_prefix(
+    length: usize,
+    mut read: impl FnMut(Range<usize>) -> Result<Vec<u8>, String>,
+) -> Result<NpyHeader, String> {
+    // The magic bytes, the version and the length of the header come first.
+    let prefix = read(0..std::cmp::min(12, length))?;
+    let header_end = match prefix.get(6..12) {
+        Some([1, _, a, b, ..]) => 10 + u16::from_le_bytes([*a, *b]) as usize,
+        Some([2 | 3, _, a, b, c, d]) => 12 + u32::from_le_bytes([*a, *b, *c, *d]) as usize,
+        _ => return Err("Not a .npy file.".to_string()),
+    };
+    if header_end > length {
+        return Err("The .npy header is truncated.".to_string());
+    }
+
+    let mut header = prefix;
+    if header_end > 12 {
+        header.extend(read(12..header_end)?);
+    }
+    read_npy_header(&header)
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for RowMajor<T, U> {
+    /// Return the Metric used for the dataset.
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    /// Returns the number of rows in the dataset.
+    fn cardinality(&self) -> usize {
+        self.data.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.data.par_iter().map(|row| row.len()).max().unwrap()
+    }
+
+    /// Return all of the indices in the dataset.
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Return the row at the provided index.
+    fn instance(&self, i: Index) -> Vec<T> {
+        self.data[i].clone()
+    }
+
+    /// Compute the distance between `left` and `right`.
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else if !self.use_cache {
+            self.metric.distance(&self.data[left], &self.data[right])
+        } else {
+            let key = if left < right { (left, right) } else { (right, left) };
+            self.cache.get(key).unwrap_or_else(|| {
+                let distance = self.metric.distance(&self.data[left], &self.data[right]);
+                self.cache.insert(key, distance);
+                distance
+            })
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        // TODO Optimize this to only make distance calls for lower triangular matrix
+        self.distances_among(indices, indices)
+    }
+
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        self.metadata.as_ref().map(|metadata| metadata[index].clone())
+    }
+
+    fn cache_lookups(&self) -> Option<CacheLookups> {
+        self.use_cache.then(|| self.cache.lookups())
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> dyn Dataset<T, U> {
+    /// Randomly splits the dataset into two views, the first with `fraction` of the instances, rounded to the nearest
+    /// whole number, and the second with the rest. The same `seed` always gives the same split.
+    pub fn split(self: &Arc<Self>, fraction: f64, seed: u64) -> (DatasetView<T, U>, DatasetView<T, U>) {
+        let mut indices = self.indices();
+        indices.shuffle(&mut StdRng::seed_from_u64(seed));
+        let n = ((fraction.clamp(0., 1.) * indices.len() as f64).round() as usize).min(indices.len());
+        let rest = indices.split_off(n);
+        (
+            DatasetView::new(Arc::clone(self), indices),
+            DatasetView::new(Arc::clone(self), rest),
+        )
+    }
+
+    /// Returns a view of `n` randomly chosen instances, without replacement, or of every instance if there are fewer
+    /// than `n`. The same `seed` always gives the same sample.
+    pub fn sample(self: &Arc<Self>, n: usize, seed: u64) -> DatasetView<T, U> {
+        let mut indices = self.indices();
+        indices.shuffle(&mut StdRng::seed_from_u64(seed));
+        indices.truncate(n);
+        DatasetView::new(Arc::clone(self), indices)
+    }
+
+    /// Returns the metadata of the instance at the given index as an `M`, or None if it has none.
+    ///
+    /// Returns an Err if the metadata cannot be deserialized as an `M`.
+    pub fn metadata_as<M: DeserializeOwned>(&self, index: Index) -> Result<Option<M>, String> {
+        self.instance_metadata(index)
+            .map(|value| {
+                serde_json::from_value(value)
+                    .map_err(|error| format!("Failed to read the metadata of instance {}. {}", index, error))
+            })
+            .transpose()
+    }
+
+    /// Returns the hits of a search over this dataset, in order, each with the metadata of its instance as an `M`.
+    ///
+    /// Returns an Err if the metadata of any hit cannot be deserialized as an `M`.
+    pub fn hits_with_metadata<M: DeserializeOwned, D: Copy>(
+        &self,
+        hits: &[(Index, D)],
+    ) -> Result<Vec<(Index, D, Option<M>)>, String> {
+        hits.iter().map(|&(i, d)| Ok((i, d, self.metadata_as(i)?))).collect()
+    }
+}
+
+/// Maps each index of a derived dataset, e.g. a sample, a permutation or a deduplication of another dataset, to the
+/// row of the original dataset that the instance came from.
+///
+/// Each rearrangement of the instances is a `Provenance` of its own, and `then` composes it with the provenance of
+/// the dataset it rearranged, so that an index at any stage can be traced back to its original row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    origins: Vec<Index>,
+    original_cardinality: usize,
+}
+
+impl Provenance {
+    /// The provenance of a dataset with the given cardinality that is its own original.
+    pub fn identity(cardinality: usize) -> Self {
+        Provenance {
+            origins: (0..cardinality).collect(),
+            original_cardinality: cardinality,
+        }
+    }
+
+    /// The provenance of a dataset whose instance at index `i` is the row `origins[i]` of an original dataset with
+    /// `original_cardinality` instances. Rows may be repeated or missing.
+    ///
+    /// Returns an Err if a row is out of bounds.
+    pub fn new(origins: Vec<Index>, original_cardinality: usize) -> Result<Self, String> {
+        match origins.iter().find(|&&row| row >= original_cardinality) {
+            Some(row) => Err(format!(
+                "Row {} is out of bounds for an original dataset of {} instances.",
+                row, original_cardinality
+            )),
+            None => Ok(Provenance {
+                origins,
+                original_cardinality,
+            }),
+        }
+    }
+
+    /// Returns the number of instances in the derived dataset.
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+
+    /// Returns the number of instances in the original dataset.
+    pub fn original_cardinality(&self) -> usize {
+        self.original_cardinality
+    }
+
+    /// Returns whether every instance is at the index of its original row.
+    pub fn is_identity(&self) -> bool {
+        self.origins.len() == self.original_cardinality && self.origins.iter().enumerate().all(|(i, &row)| i == row)
+    }
+
+    /// Returns the original row of each instance, at the position of its index.
+    pub fn origins(&self) -> &[Index] {
+        &self.origins
+    }
+
+    /// Returns the original row of the instance at the given index.
+    pub fn origin(&self, index: Index) -> Index {
+        self.origins[index]
+    }
+
+    /// Composes this provenance with that of a later rearrangement, whose original dataset is the derived dataset of
+    /// this one, e.g. a sample of a permutation.
+    ///
+    /// Returns an Err if the later rearrangement is not over a dataset with the cardinality of this derived dataset.
+    pub fn then(&self, later: &Provenance) -> Result<Self, String> {
+        if later.original_cardinality != self.len() {
+            return Err(format!(
+                "Cannot compose a provenance of {} instances with one over {} instances.",
+                self.len(),
+                later.original_cardinality
+            ));
+        }
+        Ok(Provenance {
+            origins: later.origins.iter().map(|&i| self.origins[i]).collect(),
+            original_cardinality: self.original_cardinality,
+        })
+    }
+
+    /// Returns the index in the derived dataset of each original row, at the position of the row, or None if the row
+    /// was left out. If a row was repeated, its first index is returned.
+    pub fn inverse(&self) -> Vec<Option<Index>> {
+        let mut inverse = vec![None; self.original_cardinality];
+        for (i, &row) in self.origins.iter().enumerate().rev() {
+            inverse[row] = Some(i);
+        }
+        inverse
+    }
+
+    /// Replaces the indices of the hits of a search over the derived dataset with their original rows.
+    pub fn original_hits<D: Copy>(&self, hits: &[(Index, D)]) -> Vec<(Index, D)> {
+        hits.iter().map(|&(i, d)| (self.origins[i], d)).collect()
+    }
+}
+
+/// MmapDataset reads instances from a memory-mapped `.npy` file or headerless file of rows, so that trees can be built
+/// and searched over datasets that do not fit in RAM. See `io::MappedDataset`.
+pub type MmapDataset<T, U> = crate::io::MappedDataset<T, U>;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+    use ndarray::prelude::*;
+    use ndarray_npy::write_npy;
+
+    use crate::metric_from_name;
+    use crate::utils::read_cache;
+    use crate::Cakes;
+
+    use super::ConcatDataset;
+    use super::CsvColumn;
+    use super::CsvOptions;
+    use super::Dataset;
+    use super::DatasetView;
+    use super::Norm;
+    use super::NpyRowMajor;
+    use super::PermutedDataset;
+    use super::Provenance;
+    use super::RowMajor;
+
+    #[test]
+    fn test_dataset() {
+        let data = vec![vec![1., 2., 3.], vec![3., 3., 1.]];
+        let row_0 = vec![1., 2., 3.];
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = RowMajor::new(Arc::new(data), metric, false);
+        assert_eq!(dataset.cardinality(), 2);
+        assert_eq!(dataset.instance(0), row_0);
+
+        approx_eq!(f64, dataset.distance(0, 0), 0.);
+        approx_eq!(f64, dataset.distance(0, 1), 3.);
+        approx_eq!(f64, dataset.distance(1, 0), 3.);
+        approx_eq!(f64, dataset.distance(1, 1), 0.);
+    }
+
+    #[test]
+    fn test_try_from_rows() {
+        let euclidean = metric_from_name::<f64, f64>("euclidean").unwrap();
+        let rows = vec![vec![1., 2., 3.], vec![3., 3., 1.], vec![0., 1.]];
+        let error = RowMajor::try_from_rows(rows.clone(), Arc::clone(&euclidean), false, false).unwrap_err();
+        assert_eq!(error, "Row 2 has 2 features but the euclidean metric needs 3.");
+        let dataset = RowMajor::try_from_rows(rows[..2].to_vec(), Arc::clone(&euclidean), false, true).unwrap();
+        assert_eq!(dataset.cardinality(), 2);
+        assert!(RowMajor::try_from_rows(vec![], Arc::clone(&euclidean), false, false).is_err());
+
+        // Edit distances take rows of any lengths, and NaN is rejected only on request.
+        let levenshtein = metric_from_name::<f64, f64>("levenshtein").unwrap();
+        assert!(RowMajor::try_from_rows(rows, Arc::clone(&levenshtein), false, true).is_ok());
+        let rows = vec![vec![1., 2.], vec![f64::NAN, 1.]];
+        assert!(RowMajor::try_from_rows(rows.clone(), Arc::clone(&euclidean), false, false).is_ok());
+        let error = RowMajor::try_from_rows(rows, euclidean, false, true).unwrap_err();
+        assert_eq!(error, "Row 1 holds NaN in column 0.");
+
+        let identity = ndarray::Array2::eye(2);
+        let mahalanobis: Arc<dyn crate::Metric<f64, f64>> =
+            Arc::new(crate::metric::Mahalanobis::new(&identity).unwrap());
+        let error = RowMajor::try_from_rows(vec![vec![1., 2., 3.]], mahalanobis, false, false).unwrap_err();
+        assert_eq!(error, "Row 0 has 3 features but the mahalanobis metric needs 2.");
+    }
+
+    #[test]
+    fn test_with_distance_type() {
+        let sequences: Vec<Vec<u8>> = (0..200_usize)
+            .map(|i| (0..8 + i % 5).map(|j| b"ACGT"[(i * 7 + j * j) % 4]).collect())
+            .collect();
+        let levenshtein = metric_from_name::<u8, f64>("levenshtein").unwrap();
+        let precise = RowMajor::new(Arc::new(sequences), levenshtein, true);
+        let compact = precise.with_distance_type::<u32>();
+        assert!(Arc::ptr_eq(&precise.data, &compact.data));
+        assert_eq!(compact.metric_name(), "levenshtein");
+        for (i, j) in [(0, 1), (3, 150), (42, 42)] {
+            assert_eq!(compact.distance(i, j) as f64, precise.distance(i, j));
+        }
+
+        // Both trees find the same neighbors, at the same distances.
+        let precise: Arc<dyn Dataset<u8, f64>> = Arc::new(precise);
+        let compact: Arc<dyn Dataset<u8, u32>> = Arc::new(compact);
+        let (precise, compact) = (Cakes::build(precise, None, None), Cakes::build(compact, None, None));
+        let query = precise.dataset.instance(7);
+        let mut hits: Vec<_> = compact
+            .rnn(&query, Some(3))
+            .into_iter()
+            .map(|(i, d)| (i, d as f64))
+            .collect();
+        let mut expected = precise.rnn(&query, Some(3.));
+        hits.sort_by_key(|&(i, _)| i);
+        expected.sort_by_key(|&(i, _)| i);
+        assert_eq!(hits, expected);
+
+        // Real distances are rounded up for integer types, and to the nearest for float types.
+        let euclidean = metric_from_name::<f64, f64>("euclidean").unwrap();
+        let euclidean = RowMajor::new(Arc::new(vec![vec![0., 0.], vec![1., 1.]]), euclidean, false);
+        assert_eq!(euclidean.with_distance_type::<u32>().distance(0, 1), 2);
+        assert_eq!(euclidean.with_distance_type::<f32>().distance(0, 1), 2_f32.sqrt());
+    }
+
+    #[test]
+    fn test_bounded_cache() {
+        let data: Vec<_> = (0..10).map(|i| vec![i as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset =
+            RowMajor::<f64, f64>::new(Arc::new(data.clone()), Arc::clone(&metric), true).with_cache_capacity(3);
+        assert_eq!(dataset.cache_capacity(), 3);
+
+        dataset.distance(0, 1);
+        dataset.distance(0, 2);
+        dataset.distance(0, 3);
+        assert_eq!(dataset.cache_size(), Some(3));
+
+        // Using (0, 1) again gives it a second chance, so the hand passes over it and evicts (0, 2).
+        dataset.distance(1, 0);
+        assert!(approx_eq!(f64, dataset.distance(0, 4), 4.));
+        assert_eq!(dataset.cache_size(), Some(3));
+        let cached: Vec<_> = read_cache(&dataset.cache.state).positions.keys().copied().collect();
+        assert!(!cached.contains(&(0, 2)));
+        assert!(cached.contains(&(0, 1)));
+
+        dataset.clear_cache();
+        assert_eq!(dataset.cache_size(), Some(0));
+
+        let budget =
+            RowMajor::<f64, f64>::new(Arc::new(data.clone()), Arc::clone(&metric), true).with_cache_budget(1 << 10);
+        assert!(budget.cache_capacity() > 0 && budget.cache_capacity() < 1 << 10);
+        assert_eq!(
+            RowMajor::<f64, f64>::new(Arc::new(data.clone()), Arc::clone(&metric), true).cache_capacity(),
+            super::DEFAULT_CACHE_CAPACITY
+        );
+
+        let uncached = RowMajor::<f64, f64>::new(Arc::new(data), metric, false);
+        assert!(approx_eq!(f64, uncached.distance(2, 5), 3.));
+        assert_eq!(uncached.cache_size(), Some(0));
+    }
+
+    #[test]
+    fn test_choose_unique() {
+        let data = vec![vec![1., 2., 3.], vec![3., 3., 1.]];
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: RowMajor<f64, f64> = RowMajor::new(Arc::new(data), metric, false);
+        assert_eq!(dataset.choose_unique(vec![0], 1), [0]);
+        assert_eq!(dataset.choose_unique(vec![0], 5), [0]);
+        assert_eq!(dataset.choose_unique(vec![0, 1], 1).len(), 1);
+
+        // Identical instances, and repeated indices, are chosen at most once.
+        let data = vec![
+            vec![1., 2.],
+            vec![1., 2.],
+            vec![0., 0.],
+            vec![1., 2.],
+            vec![0., 0.],
+            vec![5., 5.],
+        ];
+        let dataset = RowMajor::<f64, f64>::new(Arc::new(data), metric_from_name("euclidean").unwrap(), false);
+        let representatives = dataset.deduplicate();
+        assert_eq!(representatives, vec![0, 0, 2, 0, 2, 5]);
+        for _ in 0..10 {
+            let mut chosen: Vec<_> = dataset
+                .choose_unique(vec![0, 1, 2, 3, 4, 5, 5], 6)
+                .into_iter()
+                .map(|i| representatives[i])
+                .collect();
+            chosen.sort_unstable();
+            assert_eq!(chosen, vec![0, 2, 5]);
+        }
+    }
+
+    #[test]
+    fn test_npy_row_major() {
+        let array = Array2::from_shape_fn((30, 4), |(i, j)| (i * 4 + j) as f32 * 0.5);
+        let path = std::env::temp_dir().join(format!("clam-test-npy-row-major-{}.npy", std::process::id()));
+        write_npy(&path, &array).unwrap();
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = RowMajor::<f32, f32>::from_npy(&path, Arc::clone(&metric), false).unwrap();
+        assert_eq!(dataset.cardinality(), 30);
+        assert_eq!(dataset.instance(3), array.row(3).to_vec());
+        assert!(RowMajor::<f64, f32>::from_npy(&path, metric_from_name("euclidean").unwrap(), false).is_err());
+
+        match NpyRowMajor::<f32>::from_npy(&path, "manhattan", true).unwrap() {
+            NpyRowMajor::F32(dataset) => {
+                assert_eq!(dataset.instance(29), array.row(29).to_vec());
+                assert_eq!(dataset.metric_name(), "manhattan");
+            }
+            other => panic!("Inferred {} instead of f32.", other.type_name()),
+        }
+        let bytes = Array2::from_shape_fn((5, 3), |(i, j)| (i * j) as u8);
+        write_npy(&path, &bytes).unwrap();
+        assert_eq!(
+            NpyRowMajor::<u32>::from_npy(&path, "hamming", false)
+                .unwrap()
+                .type_name(),
+            "u8"
+        );
+        write_npy(&path, &Array2::<i32>::zeros((2, 2))).unwrap();
+        assert!(NpyRowMajor::<f32>::from_npy(&path, "euclidean", false).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // Arrays in an archive are read by name.
+        let npz_path = path.with_extension("npz");
+        let mut npz = ndarray_npy::NpzWriter::new_compressed(std::fs::File::create(&npz_path).unwrap());
+        npz.add_array("train", &array).unwrap();
+        npz.add_array("arr_0", &array.mapv(f64::from)).unwrap();
+        npz.finish().unwrap();
+        let train = RowMajor::<f32, f32>::from_npz(&npz_path, "train", Arc::clone(&metric), false).unwrap();
+        assert_eq!(train.instance(7), array.row(7).to_vec());
+        assert_eq!(
+            NpyRowMajor::<f64>::from_npz(&npz_path, "arr_0", "euclidean", false)
+                .unwrap()
+                .type_name(),
+            "f64"
+        );
+        assert!(RowMajor::<f32, f32>::from_npz(&npz_path, "test", metric, false).is_err());
+        std::fs::remove_file(&npz_path).unwrap();
+    }
+
+    #[test]
+    fn test_split_and_sample() {
+        let data: Vec<_> = (0..100).map(|i| vec![i as f64, (i % 7) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, true));
+
+        let (train, test) = dataset.split(0.8, 42);
+        assert_eq!((train.cardinality(), test.cardinality()), (80, 20));
+        let mut all: Vec<_> = train
+            .parent_indices()
+            .iter()
+            .chain(test.parent_indices())
+            .copied()
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all, dataset.indices());
+        assert_eq!(dataset.split(0.8, 42).0.parent_indices(), train.parent_indices());
+        assert_ne!(dataset.split(0.8, 7).0.parent_indices(), train.parent_indices());
+
+        // Views index their own instances, but compute distances with the underlying dataset.
+        for i in [0, 13, 79] {
+            assert_eq!(train.instance(i), dataset.instance(train.parent_index(i)));
+            assert_eq!(
+                train.distance(i, 5),
+                dataset.distance(train.parent_index(i), train.parent_index(5))
+            );
+        }
+        let distances = train.distances_among(&[0, 1], &[2, 3, 4]);
+        assert_eq!(distances[[1, 2]], train.distance(1, 4));
+        assert_eq!(train.pairwise_distances(&[3, 4])[[0, 1]], train.distance(3, 4));
+
+        let sample = dataset.sample(10, 3);
+        assert_eq!(sample.cardinality(), 10);
+        assert_eq!(dataset.sample(1000, 3).cardinality(), 100);
+
+        // Trees can be built over views.
+        let train: Arc<dyn Dataset<f64, f64>> = Arc::new(train);
+        let cakes = Cakes::build(Arc::clone(&train), None, None);
+        assert_eq!(cakes.root.cardinality, 80);
+        let view = DatasetView::new(Arc::clone(&dataset), vec![5, 5, 9]);
+        assert_eq!(view.distance(0, 1), 0.);
+        assert_eq!(view.distances_from(2, &[0, 1]).to_vec(), vec![5., 5.]);
+    }
+
+    #[test]
+    fn test_provenance() {
+        // Every instance appears twice, at rows i and i + 50.
+        let data: Vec<_> = (0..100)
+            .map(|i| vec![(i % 50) as f64, (i % 50 * 7 % 11) as f64])
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        assert!(dataset.provenance().is_identity());
+
+        let (unique, representatives) = DatasetView::deduplicated(Arc::clone(&dataset));
+        assert_eq!(unique.cardinality(), 50);
+        assert_eq!(representatives[7], representatives[57]);
+        assert_eq!(unique.provenance().origins(), (0..50).collect::<Vec<_>>());
+
+        // A sample of a permutation of the deduplicated instances traces back to the original rows.
+        let unique: Arc<dyn Dataset<f64, f64>> = Arc::new(unique);
+        let reversed: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(PermutedDataset::new(Arc::clone(&unique), (0..50).rev().collect()).unwrap());
+        let sample = reversed.sample(10, 42);
+        let provenance = sample.provenance();
+        assert_eq!(provenance.len(), 10);
+        assert_eq!(provenance.original_cardinality(), 100);
+        for i in 0..10 {
+            assert_eq!(sample.instance(i), dataset.instance(provenance.origin(i)));
+        }
+        assert_eq!(provenance.original_hits(&[(3, 1.5)]), vec![(provenance.origin(3), 1.5)]);
+
+        // Composition and inversion.
+        let steps = Provenance::new(vec![2, 0, 2], 3).unwrap();
+        let composed = Provenance::new(vec![9, 5, 7], 10).unwrap().then(&steps).unwrap();
+        assert_eq!(composed.origins(), [7, 9, 7]);
+        let inverse = composed.inverse();
+        assert_eq!((inverse[7], inverse[9], inverse[5]), (Some(0), Some(1), None));
+        assert!(steps.then(&composed).is_err());
+        assert!(Provenance::new(vec![3], 3).is_err());
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let path = std::env::temp_dir().join(format!("clam-test-from-csv-{}.csv", std::process::id()));
+        std::fs::write(&path, "name,x,y,label\na,1,2.0,true\nb, 3 ,1e1,false\nc,0,-0,true\n").unwrap();
+        let metric = metric_from_name("euclidean").unwrap();
+
+        // Every column other than the metadata is a feature.
+        let options = CsvOptions {
+            metadata: vec![CsvColumn::Name("name".to_string())],
+            ..Default::default()
+        };
+        let (dataset, metadata) = RowMajor::<f64, f64>::from_csv(&path, &options, Arc::clone(&metric), false).unwrap();
+        assert_eq!(dataset.cardinality(), 3);
+        assert_eq!(dataset.instance(1), vec![3., 10., 0.]);
+        assert_eq!(metadata, vec![vec!["a"], vec!["b"], vec!["c"]]);
+
+        // Features are coerced to integers when they are integral.
+        let options = CsvOptions {
+            features: Some(vec![CsvColumn::Position(2), CsvColumn::Name("x".to_string())]),
+            metadata: vec![CsvColumn::Position(3), CsvColumn::Position(0)],
+            ..Default::default()
+        };
+        let (dataset, metadata) =
+            RowMajor::<u8, f64>::from_csv(&path, &options, metric_from_name("euclidean").unwrap(), false).unwrap();
+        assert_eq!(dataset.instance(0), vec![2, 1]);
+        assert_eq!(dataset.instance(1), vec![10, 3]);
+        assert_eq!(metadata[2], vec!["true", "c"]);
+
+        let bad_column = CsvOptions {
+            features: Some(vec![CsvColumn::Name("z".to_string())]),
+            ..Default::default()
+        };
+        assert!(RowMajor::<f64, f64>::from_csv(&path, &bad_column, Arc::clone(&metric), false).is_err());
+        let names_as_features = CsvOptions::default();
+        assert!(RowMajor::<f64, f64>::from_csv(&path, &names_as_features, Arc::clone(&metric), false).is_err());
+
+        // Without a header, columns are selected by position.
+        std::fs::write(&path, "1.5;-2;x\n0.25;4;y\n").unwrap();
+        let options = CsvOptions {
+            delimiter: b';',
+            has_header: false,
+            metadata: vec![CsvColumn::Position(2)],
+            ..Default::default()
+        };
+        let (dataset, metadata) =
+            RowMajor::<f32, f32>::from_csv(&path, &options, metric_from_name("euclidean").unwrap(), false).unwrap();
+        assert_eq!(dataset.instance(1), vec![0.25, 4.]);
+        assert_eq!(metadata, vec![vec!["x"], vec!["y"]]);
+        let metric = metric_from_name("euclidean").unwrap();
+        assert!(RowMajor::<u8, f32>::from_csv(&path, &options, metric, false).is_err());
+        assert!(RowMajor::<f32, f32>::from_csv(
+            &path,
+            &CsvOptions {
+                metadata: vec![CsvColumn::Name("name".to_string())],
+                ..options
+            },
+            metric_from_name("euclidean").unwrap(),
+            false
+        )
+        .is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_metadata() {
+        let data: Vec<_> = (0..100).map(|i| vec![i as f64, (i % 7) as f64]).collect();
+        let metadata: Vec<_> = (0..100).map(|i| (format!("point-{}", i), i % 3)).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let plain = RowMajor::<f64, f64>::new(Arc::new(data.clone()), Arc::clone(&metric), false);
+        assert_eq!((plain.metadata(), plain.instance_metadata(0)), (None, None));
+        assert!(plain.with_metadata(&metadata[1..]).is_err());
+
+        let dataset = RowMajor::<f64, f64>::new(Arc::new(data.clone()), metric, false)
+            .with_metadata(&metadata)
+            .unwrap();
+        assert_eq!(dataset.metadata().unwrap().len(), 100);
+        let dataset = Arc::new(dataset).as_arc_dataset();
+        assert_eq!(dataset.metadata_as(42).unwrap(), Some(("point-42".to_string(), 0)));
+        assert!(dataset.metadata_as::<u32>(42).is_err());
+
+        // Search results carry the metadata of their instances.
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let hits = cakes.knn_with_metadata::<(String, usize)>(&data[10], 3).unwrap();
+        assert_eq!(hits[0], (10, 0., Some(("point-10".to_string(), 1))));
+        let hits = cakes
+            .rnn_with_metadata::<(String, usize)>(&data[10], Some(0.5))
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+
+        // Derived datasets return the metadata of the instances they came from.
+        let sample = Arc::new(dataset.sample(10, 7));
+        for i in 0..10 {
+            assert_eq!(
+                sample.instance_metadata(i),
+                dataset.instance_metadata(sample.parent_index(i))
+            );
+        }
+        let subset = sample.row_major_subset(&[3, 5]);
+        assert_eq!(subset.instance_metadata(1), sample.instance_metadata(5));
+        let concat = ConcatDataset::new(vec![Arc::clone(&dataset), sample.as_arc_dataset()]).unwrap();
+        assert_eq!(concat.instance_metadata(103), subset.instance_metadata(0));
+    }
+
+    #[test]
+    fn test_transforms() {
+        // The second feature is a thousand times larger than the first, and the third is always zero.
+        let mut data: Vec<_> = (0..50)
+            .map(|i| vec![(1 + i % 10) as f64, (i * 37 % 50) as f64 * 1e3, 0.])
+            .collect();
+        data.push(vec![0.; 3]);
+        let dataset = RowMajor::<f64, f64>::new(Arc::new(data.clone()), metric_from_name("euclidean").unwrap(), true);
+
+        let standardized = dataset.standardized().unwrap();
+        for j in 0..3 {
+            let column: Vec<_> = standardized.data.iter().map(|row| row[j]).collect();
+            let mean = column.iter().sum::<f64>() / 51.;
+            let variance = column.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / 51.;
+            assert!(approx_eq!(f64, mean, 0., epsilon = 1e-9));
+            assert!(approx_eq!(f64, variance, if j == 2 { 0. } else { 1. }, epsilon = 1e-9));
+        }
+
+        for norm in [Norm::L1, Norm::L2, Norm::Max] {
+            let length = |row: &[f64]| match norm {
+                Norm::L1 => row.iter().map(|v| v.abs()).sum::<f64>(),
+                Norm::L2 => row.iter().map(|v| v * v).sum::<f64>().sqrt(),
+                Norm::Max => row.iter().fold(0., |max, v| v.abs().max(max)),
+            };
+            let normalized = dataset.normalized(norm).unwrap();
+            assert!(normalized.data[..50]
+                .iter()
+                .all(|row| approx_eq!(f64, length(row), 1., epsilon = 1e-9)));
+            assert_eq!(normalized.instance(50), vec![0.; 3]);
+        }
+
+        // Queries are transformed like the instances, so searching for an instance finds it.
+        let both = Arc::new(standardized.normalized(Norm::L2).unwrap());
+        assert_eq!(both.transforms().len(), 2);
+        let cakes = Cakes::build(Arc::clone(&both).as_arc_dataset(), None, None);
+        for i in [0, 17, 42] {
+            let query = both.transform_query(&data[i]);
+            assert_eq!(query, both.instance(i));
+            assert_eq!(cakes.knn(&query, 1)[0].0, i);
+        }
+
+        // A transform fitted to one dataset applies to another.
+        let other = RowMajor::<f64, f64>::new(
+            Arc::new(data[..5].to_vec()),
+            metric_from_name("euclidean").unwrap(),
+            false,
+        )
+        .transformed(standardized.transforms()[0].clone())
+        .unwrap();
+        assert_eq!(other.instance(3), standardized.instance(3));
+        let short = RowMajor::<f64, f64>::new(
+            Arc::new(vec![vec![1., 2.]]),
+            metric_from_name("euclidean").unwrap(),
+            false,
+        );
+        assert!(short.transformed(standardized.transforms()[0].clone()).is_err());
+
+        let integers = RowMajor::<u8, f64>::new(
+            Arc::new(vec![vec![1, 2]]),
+            metric_from_name("euclidean").unwrap(),
+            false,
+        );
+        assert!(integers.normalized(Norm::L2).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+//! `ObjectStoreDataset`, a dataset served from an object store, e.g. S3 or GCS, through an `ObjectSource`.
+
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use super::read_npy_prefix;
+use super::LoadedShards;
+use super::Shard;
+use crate::io::decode_row;
+use crate::io::npy_layout;
+use crate::prelude::*;
+use crate::utils::lock_cache;
+
+/// A source of ranges of bytes from one object, e.g. a blob in an object store such as S3 or GCS, or a local file.
+///
+/// An `ObjectStoreDataset` calls these methods from the threads of the rayon pool. Clients built on an async runtime
+/// should block on their requests inside them, e.g. with the `block_on` of their runtime, as the `StoreSource` of the
+/// `object_sources` module does.
+pub trait ObjectSource: Send + Sync {
+    /// Returns the length of the object in bytes.
+    fn len(&self) -> Result<u64, String>;
+
+    /// Returns whether the object holds no bytes.
+    fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns the bytes in the given range of the object.
+    fn read_range(&self, range: Range<u64>) -> Result<Vec<u8>, String>;
+}
+
+/// An `ObjectSource` that reads ranges of a local file, e.g. on a network file system.
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: &Path) -> Self {
+        FileSource {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl ObjectSource for FileSource {
+    fn len(&self) -> Result<u64, String> {
+        std::fs::metadata(&self.path)
+            .map(|metadata| metadata.len())
+            .map_err(|error| format!("Error: Failed to read {}. {}", self.path.display(), error))
+    }
+
+    fn read_range(&self, range: Range<u64>) -> Result<Vec<u8>, String> {
+        use std::io::Read;
+        use std::io::Seek;
+
+        let fail = |error: std::io::Error| format!("Error: Failed to read {}. {}", self.path.display(), error);
+        let mut file = std::fs::File::open(&self.path).map_err(fail)?;
+        file.seek(std::io::SeekFrom::Start(range.start)).map_err(fail)?;
+        let mut bytes = vec![0; range.end.saturating_sub(range.start) as usize];
+        file.read_exact(&mut bytes).map_err(fail)?;
+        Ok(bytes)
+    }
+}
+
+/// The number of times `ObjectStoreDataset` tries to fetch a block before giving up.
+const FETCH_ATTEMPTS: usize = 4;
+
+/// How long `ObjectStoreDataset` waits before it tries to fetch a block the second time. The wait doubles after each
+/// further failure.
+const FETCH_BACKOFF: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Reads the range from the source, trying again after a failure, up to `FETCH_ATTEMPTS` times in all, with an
+/// exponential backoff so that a throttled or briefly unavailable store is not flooded with requests.
+fn fetch(source: &dyn ObjectSource, range: Range<u64>) -> Result<Vec<u8>, String> {
+    let mut attempt = 1;
+    let mut backoff = FETCH_BACKOFF;
+    loop {
+        match source.read_range(range.clone()) {
+            Ok(bytes) => return Ok(bytes),
+            Err(error) if attempt == FETCH_ATTEMPTS => return Err(format!("{} attempts failed. {}", attempt, error)),
+            Err(error) => log::warn!("Attempt {} to read {:?} failed: {}", attempt, range, error),
+        }
+        std::thread::sleep(backoff);
+        attempt += 1;
+        backoff *= 2;
+    }
+}
+
+/// ObjectStoreDataset serves the rows of one `.npy` or headerless object, e.g. in S3 or GCS, fetching blocks of
+/// consecutive instances only when they are needed.
+///
+/// At most `max_cached_blocks` blocks are kept in memory, and the least recently used block is dropped to make room
+/// for another. As with `ShardedDataset`, distances are computed one block at a time, so tree-building and search
+/// fetch each block they need once per batch of distances, rather than once per instance. A `block_size` of a few
+/// thousand instances keeps the number of requests small without fetching much more than is needed.
+///
+/// The object must not change while it is served.
+///
+/// # Errors
+///
+/// The `Dataset` trait cannot return errors, so when a block cannot be fetched after several attempts, or the source
+/// returns fewer bytes than the block holds, its methods log the error, serve zeros for the instances of that block
+/// and keep the first such error for `failure`. The block is fetched again the next time it is needed.
+/// `validate` fetches every block once, so that a source that cannot serve the object fails before a tree is built or
+/// searched rather than in the middle of it, and `try_instance` returns these failures as an Err instead.
+pub struct ObjectStoreDataset<T: Number, U: Number> {
+    source: Arc<dyn ObjectSource>,
+    /// The position of the first byte of the array in the object.
+    offset: u64,
+    cardinality: usize,
+    dimensionality: usize,
+    big_endian: bool,
+    block_size: usize,
+    metric: Arc<dyn Metric<T, U>>,
+    max_cached_blocks: usize,
+    cached: Mutex<LoadedShards<T>>,
+    block_fetches: AtomicU64,
+    /// The first error from fetching a block for the methods of the `Dataset` trait.
+    failure: Mutex<Option<String>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for ObjectStoreDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("ObjectStoreDataset")
+            .field("data-cardinality", &self.cardinality)
+            .field("data-dimensionality", &self.dimensionality)
+            .field("block-size", &self.block_size)
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> ObjectStoreDataset<T, U> {
+    /// Serves the `.npy` object from the source, which must hold a 2-dimensional, row-major array of `T`.
+    /// Only its header is fetched.
+    pub fn open_npy(
+        source: Arc<dyn ObjectSource>,
+        block_size: usize,
+        max_cached_blocks: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let length = source.len()? as usize;
+        let header = read_npy_prefix(length, |range| {
+            fetch(source.as_ref(), range.start as u64..range.end as u64)
+        })?;
+        let (cardinality, dimensionality, big_endian) = npy_layout::<T>(&header, length)?;
+        Ok(Self::from_layout(
+            source,
+            (header.offset as u64, cardinality, dimensionality, big_endian),
+            block_size,
+            max_cached_blocks,
+            metric,
+        ))
+    }
+
+    /// Serves a headerless object of rows of `dimensionality` little-endian numbers, e.g. as written by numpy's
+    /// `tofile`.
+    pub fn open_raw(
+        source: Arc<dyn ObjectSource>,
+        dimensionality: usize,
+        block_size: usize,
+        max_cached_blocks: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let length = source.len()?;
+        let row_length = (dimensionality * T::num_bytes() as usize) as u64;
+        if row_length == 0 || !length.is_multiple_of(row_length) {
+            return Err(format!(
+                "{} bytes do not hold a whole number of rows of {} bytes.",
+                length, row_length
+            ));
+        }
+        let cardinality = (length / row_length) as usize;
+        Ok(Self::from_layout(
+            source,
+            (0, cardinality, dimensionality, false),
+            block_size,
+            max_cached_blocks,
+            metric,
+        ))
+    }
+
+    fn from_layout(
+        source: Arc<dyn ObjectSource>,
+        (offset, cardinality, dimensionality, big_endian): (u64, usize, usize, bool),
+        block_size: usize,
+        max_cached_blocks: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Self {
+        ObjectStoreDataset {
+            source,
+            offset,
+            cardinality,
+            dimensionality,
+            big_endian,
+            block_size: std::cmp::max(block_size, 1),
+            metric,
+            max_cached_blocks: std::cmp::max(max_cached_blocks, 1),
+            cached: Mutex::new(LoadedShards::new()),
+            block_fetches: AtomicU64::new(0),
+            failure: Mutex::new(None),
+        }
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> ObjectStoreDataset<T, U> {
+    pub fn num_blocks(&self) -> usize {
+        self.cardinality.div_ceil(self.block_size)
+    }
+
+    /// Returns the block holding the instance at the given index, and the position of the instance in the block.
+    pub fn block_of(&self, index: Index) -> (usize, usize) {
+        (index / self.block_size, index % self.block_size)
+    }
+
+    /// Returns the blocks that are currently in memory, in sorted order.
+    pub fn cached_blocks(&self) -> Vec<usize> {
+        let mut blocks: Vec<_> = lock_cache(&self.cached).shards.keys().copied().collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Returns the number of times a block has been fetched from the source.
+    pub fn block_fetches(&self) -> u64 {
+        self.block_fetches.load(Ordering::Relaxed)
+    }
+
+    /// Returns the first error from fetching a block for the methods of the `Dataset` trait, if any, in which case
+    /// the trees built and the hits found since then may be wrong.
+    pub fn failure(&self) -> Option<String> {
+        lock_cache(&self.failure).clone()
+    }
+
+    /// Returns the instance at the given index, or an Err if its block cannot be fetched.
+    pub fn try_instance(&self, i: Index) -> Result<Vec<T>, String> {
+        if i >= self.cardinality {
+            return Err(format!(
+                "Index {} is out of range for a dataset of {} instances.",
+                i, self.cardinality
+            ));
+        }
+        let (block, position) = self.block_of(i);
+        Ok(self.try_block(block)?[position].clone())
+    }
+
+    /// Fetches every block of the object once, returning an Err for the first block that cannot be fetched whole.
+    ///
+    /// At most `max_cached_blocks` of the blocks stay in memory afterwards.
+    pub fn validate(&self) -> Result<(), String> {
+        (0..self.num_blocks()).try_for_each(|block| self.try_block(block).map(|_| ()))
+    }
+
+    /// Returns the instances of the block, fetching it from the source if it is not in memory, or zeros if it cannot
+    /// be fetched, as described for `ObjectStoreDataset`.
+    fn block(&self, block: usize) -> Shard<T> {
+        self.try_block(block).unwrap_or_else(|error| {
+            log::error!("{}", error);
+            lock_cache(&self.failure).get_or_insert(error);
+            let cardinality = std::cmp::min(
+                self.block_size,
+                self.cardinality.saturating_sub(block * self.block_size),
+            );
+            Arc::new(vec![vec![T::zero(); self.dimensionality]; cardinality])
+        })
+    }
+
+    /// Returns the instances of the block, fetching it from the source if it is not in memory, or an Err if there is
+    /// no such block, if it cannot be fetched after several attempts or if the source returns fewer bytes than the
+    /// block holds.
+    fn try_block(&self, block: usize) -> Result<Shard<T>, String> {
+        if block >= self.num_blocks() {
+            return Err(format!(
+                "Block {} is out of range for a dataset of {} blocks.",
+                block,
+                self.num_blocks()
+            ));
+        }
+        if let Some(instances) = lock_cache(&self.cached).get(block) {
+            return Ok(instances);
+        }
+
+        // The block is fetched without holding the lock, so that other blocks may be served meanwhile.
+        let start = block * self.block_size;
+        let cardinality = std::cmp::min(self.block_size, self.cardinality - start);
+        let length = self.dimensionality * T::num_bytes() as usize;
+        let range = (self.offset + (start * length) as u64)..(self.offset + ((start + cardinality) * length) as u64);
+        let fail = |error: String| format!("Error: Failed to fetch block {} of {:?}. {}", block, self, error);
+        let bytes = fetch(self.source.as_ref(), range).map_err(fail)?;
+        if bytes.len() != cardinality * length {
+            return Err(fail(format!(
+                "Expected {} bytes but got {}.",
+                cardinality * length,
+                bytes.len()
+            )));
+        }
+        let instances: Vec<_> = bytes
+            .chunks(length)
+            .map(|row| decode_row(row, self.big_endian))
+            .collect();
+        let instances = Arc::new(instances);
+        self.block_fetches.fetch_add(1, Ordering::Relaxed);
+
+        lock_cache(&self.cached).insert(block, Arc::clone(&instances), self.max_cached_blocks);
+        Ok(instances)
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for ObjectStoreDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality).collect()
+    }
+
+    /// Returns the instance at the provided index, fetching its block if it is not in memory.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let (block, position) = self.block_of(i);
+        self.block(block)[position].clone()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    /// Computes the distances one block at a time, so that each block is needed only once.
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        let mut order: Vec<_> = (0..right.len()).collect();
+        order.sort_by_key(|&p| right[p]);
+
+        let mut distances = vec![U::zero(); right.len()];
+        for run in order.chunk_by(|&a, &b| self.block_of(right[a]).0 == self.block_of(right[b]).0) {
+            let instances = self.block(self.block_of(right[run[0]]).0);
+            let run_distances: Vec<_> = run
+                .par_iter()
+                .map(|&p| {
+                    if right[p] == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &instances[self.block_of(right[p]).1])
+                    }
+                })
+                .collect();
+            for (&p, distance) in run.iter().zip(run_distances) {
+                distances[p] = distance;
+            }
+        }
+        Array1::from_vec(distances)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+    use ndarray::prelude::*;
+    use ndarray_npy::write_npy;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::FileSource;
+    use super::ObjectSource;
+    use super::ObjectStoreDataset;
+
+    #[test]
+    fn test_object_store_dataset() {
+        struct FlakySource {
+            file: FileSource,
+            failed: std::sync::Mutex<std::collections::HashSet<std::ops::Range<u64>>>,
+        }
+
+        impl ObjectSource for FlakySource {
+            fn len(&self) -> Result<u64, String> {
+                self.file.len()
+            }
+
+            // The first read of each range fails, as requests to a remote store sometimes do.
+            fn read_range(&self, range: std::ops::Range<u64>) -> Result<Vec<u8>, String> {
+                if self.failed.lock().unwrap().insert(range.clone()) {
+                    Err("connection reset".to_string())
+                } else {
+                    self.file.read_range(range)
+                }
+            }
+        }
+
+        let data: Vec<Vec<f32>> = (0..230)
+            .map(|i| (0..3).map(|j| ((i * (j + 3)) % 17) as f32 - 0.5 * j as f32).collect())
+            .collect();
+        let path = std::env::temp_dir().join(format!("clam-test-object-store-{}.npy", std::process::id()));
+        let array = Array2::from_shape_vec((230, 3), data.iter().flatten().cloned().collect()).unwrap();
+        write_npy(&path, &array).unwrap();
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let source = Arc::new(FlakySource {
+            file: FileSource::new(&path),
+            failed: Default::default(),
+        });
+        let store = ObjectStoreDataset::<f32, f32>::open_npy(source, 50, 2, Arc::clone(&metric)).unwrap();
+        let store = Arc::new(store);
+        assert_eq!(
+            (store.cardinality(), store.dimensionality(), store.num_blocks()),
+            (230, 3, 5)
+        );
+        assert_eq!(store.block_of(229), (4, 29));
+        assert!(store.cached_blocks().is_empty());
+        for i in (0..230).step_by(7) {
+            assert_eq!(store.instance(i), data[i]);
+        }
+        assert_eq!(store.cached_blocks().len(), 2);
+
+        // Each batch of distances fetches each block at most once.
+        let fetches = store.block_fetches();
+        let distances = store.distances_from(5, &(0..230).rev().collect::<Vec<_>>());
+        assert!(store.block_fetches() - fetches <= 5);
+        for (i, &distance) in (0..230).rev().zip(distances.iter()) {
+            assert!(approx_eq!(f32, distance, metric.distance(&data[5], &data[i])));
+        }
+
+        let cakes = Cakes::build(Arc::clone(&store).as_arc_dataset(), Some(6), None);
+        for query in data.iter().step_by(31) {
+            let mut hits = cakes.rnn_indices(query, Some(3.));
+            let mut expected = cakes.linear_search_indices(query, Some(3.), None);
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+        }
+
+        // Headerless objects are rows of little-endian numbers.
+        let raw: Vec<u8> = data.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+        std::fs::write(&path, raw).unwrap();
+        let source = Arc::new(FileSource::new(&path));
+        let raw = ObjectStoreDataset::<f32, f32>::open_raw(source.clone(), 3, 64, 1, Arc::clone(&metric)).unwrap();
+        assert_eq!((raw.cardinality(), raw.instance(229)), (230, data[229].clone()));
+        assert!(ObjectStoreDataset::<f32, f32>::open_raw(source.clone(), 7, 64, 1, Arc::clone(&metric)).is_err());
+        assert!(ObjectStoreDataset::<f32, f32>::open_npy(source, 64, 1, Arc::clone(&metric)).is_err());
+        assert!(raw.validate().is_ok());
+
+        // A source that always fails for one block is caught by validate, and try_instance reports it as an Err.
+        struct BrokenSource(FileSource);
+
+        impl ObjectSource for BrokenSource {
+            fn len(&self) -> Result<u64, String> {
+                self.0.len()
+            }
+
+            fn read_range(&self, range: std::ops::Range<u64>) -> Result<Vec<u8>, String> {
+                if range.start == 0 {
+                    Err("access denied".to_string())
+                } else {
+                    self.0.read_range(range)
+                }
+            }
+        }
+
+        let source = Arc::new(BrokenSource(FileSource::new(&path)));
+        let broken = ObjectStoreDataset::<f32, f32>::open_raw(source, 3, 64, 1, metric).unwrap();
+        assert!(broken.validate().unwrap_err().contains("access denied"));
+        assert!(broken.try_instance(3).is_err());
+        assert_eq!(broken.try_instance(229).unwrap(), data[229]);
+        assert!(broken.try_instance(230).is_err());
+        assert!(broken.try_block(broken.num_blocks()).is_err());
+
+        // The methods of the `Dataset` trait serve zeros for such a block instead of panicking, and keep the error.
+        assert!(broken.failure().is_none());
+        assert_eq!(broken.instance(3), vec![0.; 3]);
+        assert!(broken.failure().unwrap().contains("access denied"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! `QuantizedDataset`, a dataset of instances whose features are scalar-quantized to bytes.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+
+/// QuantizedDataset stores each feature of each instance as a `u8`, scalar-quantized with a scale and offset for
+/// each dimension, so that a dataset of `f32` takes a quarter of the memory.
+///
+/// The value of feature `j` of an instance is approximated by `offsets[j] + scales[j] * code`, which is within half of
+/// `scales[j]` of the original value. Distances for the euclidean, euclideansq and manhattan metrics are computed from
+/// the codes directly. Other metrics are computed on the dequantized instances.
+pub struct QuantizedDataset<T: Number, U: Number> {
+    codes: Vec<u8>,
+    dimensionality: usize,
+    scales: Vec<f64>,
+    offsets: Vec<f64>,
+    metric: Arc<dyn Metric<T, U>>,
+    kernel: QuantizedKernel,
+}
+
+/// How a `QuantizedDataset` computes distances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuantizedKernel {
+    Euclidean,
+    EuclideanSq,
+    Manhattan,
+    Dequantized,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for QuantizedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("QuantizedDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality)
+            .field("kernel", &self.kernel)
+            .finish()
+    }
+}
+
+impl<T: Number, U: Number> QuantizedDataset<T, U> {
+    /// Quantizes the instances, which must all have the same dimensionality. The scale and offset of each dimension
+    /// are chosen so that the codes 0 and 255 are the smallest and largest values of the dimension.
+    ///
+    /// Returns an Err if the instances have different dimensionalities or a value is not finite.
+    pub fn quantize(data: &[Vec<T>], metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let dimensionality = data.first().map_or(0, |row| row.len());
+        if let Some(i) = data.iter().position(|row| row.len() != dimensionality) {
+            return Err(format!(
+                "Instance {} has {} features but the first instance has {}.",
+                i,
+                data[i].len(),
+                dimensionality
+            ));
+        }
+
+        let mut offsets = vec![f64::INFINITY; dimensionality];
+        let mut maxima = vec![f64::NEG_INFINITY; dimensionality];
+        for row in data.iter() {
+            for (j, value) in row.iter().enumerate() {
+                let value = value.as_f64();
+                if !value.is_finite() {
+                    return Err(format!("Cannot quantize the value {} in dimension {}.", value, j));
+                }
+                offsets[j] = offsets[j].min(value);
+                maxima[j] = maxima[j].max(value);
+            }
+        }
+        let scales: Vec<_> = offsets
+            .iter()
+            .zip(maxima.iter())
+            .map(|(min, max)| (max - min) / 255.)
+            .collect();
+
+        let codes = data
+            .iter()
+            .flat_map(|row| {
+                row.iter()
+                    .zip(offsets.iter().zip(scales.iter()))
+                    .map(|(value, (&offset, &scale))| {
+                        if scale > 0. {
+                            ((value.as_f64() - offset) / scale).round().clamp(0., 255.) as u8
+                        } else {
+                            0
+                        }
+                    })
+            })
+            .collect();
+
+        Ok(Self::from_codes(codes, dimensionality, scales, offsets, metric))
+    }
+
+    /// Creates a dataset from codes in row-major order and the scale and offset of each dimension, e.g. to load a
+    /// dataset that was quantized earlier.
+    pub fn from_codes(
+        codes: Vec<u8>,
+        dimensionality: usize,
+        scales: Vec<f64>,
+        offsets: Vec<f64>,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Self {
+        assert_eq!(scales.len(), dimensionality, "Expected a scale for each dimension.");
+        assert_eq!(offsets.len(), dimensionality, "Expected an offset for each dimension.");
+        assert!(
+            dimensionality == 0 || codes.len().is_multiple_of(dimensionality),
+            "The codes must hold the same number of features for every instance."
+        );
+        let kernel = match metric.name().as_str() {
+            "euclidean" => QuantizedKernel::Euclidean,
+            "euclideansq" => QuantizedKernel::EuclideanSq,
+            "manhattan" => QuantizedKernel::Manhattan,
+            _ => QuantizedKernel::Dequantized,
+        };
+        QuantizedDataset {
+            codes,
+            dimensionality,
+            scales,
+            offsets,
+            metric,
+            kernel,
+        }
+    }
+
+    /// Returns the codes of the instance at the given index.
+    pub fn codes(&self, index: Index) -> &[u8] {
+        &self.codes[index * self.dimensionality..(index + 1) * self.dimensionality]
+    }
+
+    /// Returns the scale of each dimension, i.e. the difference between the values of consecutive codes.
+    pub fn scales(&self) -> &[f64] {
+        &self.scales
+    }
+
+    /// Returns the offset of each dimension, i.e. the value of the code 0.
+    pub fn offsets(&self) -> &[f64] {
+        &self.offsets
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>>
+    where
+        T: 'static,
+        U: 'static,
+    {
+        self
+    }
+
+    /// Returns the weighted sum of the differences of the codes of the instances, raised to the given power.
+    fn code_distance(&self, left: Index, right: Index, power: i32) -> f64 {
+        self.codes(left)
+            .iter()
+            .zip(self.codes(right).iter())
+            .zip(self.scales.iter())
+            .map(|((&a, &b), &scale)| (scale * (a as f64 - b as f64)).abs().powi(power))
+            .sum()
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for QuantizedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.codes.len().checked_div(self.dimensionality).unwrap_or(0)
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Returns the dequantized instance at the provided index.
+    fn instance(&self, i: Index) -> Vec<T> {
+        self.codes(i)
+            .iter()
+            .zip(self.offsets.iter().zip(self.scales.iter()))
+            .map(|(&code, (&offset, &scale))| T::saturating_from_f64(offset + scale * code as f64))
+            .collect()
+    }
+
+    fn instance_size(&self) -> usize {
+        std::cmp::max(self.dimensionality, 1)
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            return U::zero();
+        }
+        match self.kernel {
+            QuantizedKernel::Euclidean => U::saturating_from_f64(self.code_distance(left, right, 2).sqrt()),
+            QuantizedKernel::EuclideanSq => U::saturating_from_f64(self.code_distance(left, right, 2)),
+            QuantizedKernel::Manhattan => U::saturating_from_f64(self.code_distance(left, right, 1)),
+            QuantizedKernel::Dequantized => self.metric.distance(&self.instance(left), &self.instance(right)),
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::dataset::RowMajor;
+    use crate::metric_from_name;
+
+    use super::QuantizedDataset;
+
+    #[test]
+    fn test_quantized_dataset() {
+        let data: Vec<_> = (0..50)
+            .map(|i| vec![(i % 7) as f32 * 0.5 - 1., (i * 13 % 29) as f32, 3.])
+            .collect();
+        let dense = RowMajor::<f32, f32>::new(Arc::new(data.clone()), metric_from_name("euclidean").unwrap(), false);
+
+        for name in ["euclidean", "manhattan", "cosine"] {
+            let quantized = QuantizedDataset::<f32, f32>::quantize(&data, metric_from_name(name).unwrap()).unwrap();
+            assert_eq!(quantized.cardinality(), 50);
+            assert_eq!(quantized.dimensionality(), 3);
+            assert_eq!(quantized.instance_size(), 3);
+
+            // Each feature is within half a step of the original, and the constant dimension is exact.
+            for i in [0, 9, 49] {
+                let instance = quantized.instance(i);
+                for (j, (&a, &b)) in instance.iter().zip(data[i].iter()).enumerate() {
+                    assert!((a - b).abs() as f64 <= quantized.scales()[j] / 2. + 1e-6);
+                }
+                assert_eq!(instance[2], 3.);
+            }
+
+            let metric = quantized.metric();
+            for (i, j) in [(0, 1), (3, 40), (10, 11)] {
+                let expected = metric.distance(&quantized.instance(i), &quantized.instance(j));
+                assert!(
+                    (quantized.distance(i, j) - expected).abs() < 1e-3,
+                    "{} ({}, {})",
+                    name,
+                    i,
+                    j
+                );
+            }
+        }
+
+        let quantized = QuantizedDataset::<f32, f32>::quantize(&data, metric_from_name("euclidean").unwrap()).unwrap();
+        let error: f32 = quantized.scales().iter().map(|&s| (s / 2.).powi(2)).sum::<f64>().sqrt() as f32 * 2.;
+        assert!((quantized.distance(3, 40) - dense.distance(3, 40)).abs() <= error);
+
+        let ragged = vec![vec![1., 2.], vec![3.]];
+        assert!(QuantizedDataset::<f32, f32>::quantize(&ragged, metric_from_name("euclidean").unwrap()).is_err());
+        let infinite = vec![vec![f32::INFINITY]];
+        assert!(QuantizedDataset::<f32, f32>::quantize(&infinite, metric_from_name("euclidean").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! `SetDataset`, a dataset of sets, e.g. of shingles or tokens, for jaccard search.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::io::content_hash;
+use crate::prelude::*;
+
+/// SetDataset serves instances that are sets, e.g. the shingles of documents or the tokens of records, for search with
+/// the jaccard metric.
+///
+/// Each set is stored sorted and without repeated elements, which is how `instance` returns it, so that the jaccard
+/// metric compares two sets in one merge of their elements. Sets of tokens, e.g. strings, are hashed to `u64` elements
+/// with `SetDataset::from_tokens`, and their queries must be hashed the same way, with `hash_tokens`. The
+/// dimensionality is the size of the largest set.
+pub struct SetDataset<T: Number, U: Number> {
+    /// The elements of set `i` are at positions `offsets[i]..offsets[i + 1]` in `elements`.
+    offsets: Vec<usize>,
+    elements: Vec<T>,
+    metric: Arc<dyn Metric<T, U>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for SetDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("SetDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality())
+            .field("stored-elements", &self.elements.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> SetDataset<T, U> {
+    /// Create a new Dataset from the given sets, whose elements may be in any order and may repeat.
+    ///
+    /// Returns an Err if the elements of a set cannot be ordered, e.g. because one is NaN.
+    pub fn new(sets: Vec<Vec<T>>, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let mut offsets = vec![0];
+        let mut elements = Vec::with_capacity(sets.iter().map(Vec::len).sum());
+        for (i, mut set) in sets.into_iter().enumerate() {
+            if set.iter().any(|a| a.partial_cmp(a).is_none()) {
+                return Err(format!("The elements of set {} cannot be ordered.", i));
+            }
+            set.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            set.dedup();
+            elements.extend(set);
+            offsets.push(elements.len());
+        }
+        Ok(SetDataset {
+            offsets,
+            elements,
+            metric,
+        })
+    }
+
+    /// Returns the sorted elements of the set at the given index.
+    pub fn set(&self, index: Index) -> &[T] {
+        &self.elements[self.offsets[index]..self.offsets[index + 1]]
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<U: 'static + Number> SetDataset<u64, U> {
+    /// Create a new Dataset from sets of tokens, each hashed to an element with `hash_tokens`.
+    pub fn from_tokens<S: AsRef<[u8]>>(sets: &[Vec<S>], metric: Arc<dyn Metric<u64, U>>) -> Result<Self, String> {
+        Self::new(sets.iter().map(|tokens| hash_tokens(tokens)).collect(), metric)
+    }
+}
+
+/// Returns the sorted set of the hashes of the tokens, e.g. the query for a `SetDataset::from_tokens`. The tokens are
+/// hashed with `io::content_hash`, which is the same across platforms and releases.
+pub fn hash_tokens<S: AsRef<[u8]>>(tokens: &[S]) -> Vec<u64> {
+    let mut hashes: Vec<_> = tokens.iter().map(|token| content_hash(token.as_ref())).collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for SetDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.offsets
+            .windows(2)
+            .map(|bounds| bounds[1] - bounds[0])
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    fn instance(&self, i: Index) -> Vec<T> {
+        self.elements[self.offsets[i]..self.offsets[i + 1]].to_vec()
+    }
+
+    fn instance_size(&self) -> usize {
+        // The average size of the elements of a set.
+        let bytes = self.elements.len() * T::num_bytes() as usize;
+        std::cmp::max(bytes / std::cmp::max(self.cardinality(), 1), 1)
+    }
+
+    /// Computes the distance between the stored sets, without copying them.
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            let set = |i: Index| &self.elements[self.offsets[i]..self.offsets[i + 1]];
+            self.metric.distance(set(left), set(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::hash_tokens;
+    use super::SetDataset;
+
+    #[test]
+    fn test_set_dataset() {
+        let metric = metric_from_name::<u32, f64>("jaccard").unwrap();
+        let sets: Vec<Vec<u32>> = (0..200)
+            .map(|i| (0..10).map(|j| (i * 7 + j * 13) % 50).rev().collect())
+            .collect();
+        let dataset = SetDataset::new(sets.clone(), Arc::clone(&metric)).unwrap();
+        assert_eq!(dataset.set(0), &[0, 2, 4, 13, 15, 17, 26, 28, 39, 41]);
+
+        // The sorted sets have the same jaccard distances as the unsorted ones.
+        for (i, j) in [(0, 1), (3, 17), (5, 5), (42, 199)] {
+            assert_eq!(dataset.distance(i, j), metric.distance(&sets[i], &sets[j]));
+        }
+        let repeated = SetDataset::new(vec![vec![3, 1, 3, 2], vec![2, 1, 3]], Arc::clone(&metric)).unwrap();
+        assert_eq!(repeated.instance(0), vec![1, 2, 3]);
+        assert_eq!(repeated.distance(0, 1), 0.);
+        assert_eq!(repeated.dimensionality(), 3);
+        assert!(SetDataset::new(
+            vec![vec![1., f64::NAN]],
+            metric_from_name::<f64, f64>("jaccard").unwrap()
+        )
+        .is_err());
+
+        // Shingles of documents are hashed, and so are those of their queries.
+        let shingles = |text: &str| -> Vec<String> {
+            let chars: Vec<char> = text.chars().collect();
+            chars.windows(3).map(|window| window.iter().collect()).collect()
+        };
+        let documents: Vec<_> = (0..100)
+            .map(|i| shingles(&format!("the quick brown fox {} jumps over the lazy dog {}", i, i * i)))
+            .collect();
+        let dataset = Arc::new(SetDataset::from_tokens(&documents, metric_from_name("jaccard").unwrap()).unwrap());
+        let cakes = Cakes::<u64, f64>::build(dataset.as_arc_dataset(), None, None);
+        let query = hash_tokens(&shingles("the quick brown fox 42 jumps over the lazy dog 1764"));
+        assert_eq!(cakes.knn(&query, 1), vec![(42, 0.)]);
+    }
+}
@@ -0,0 +1,370 @@
+//! `ShardedDataset`, a dataset split over several `.npy` files that are read into memory only while they are needed.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use super::read_npy_file_header;
+use crate::io::decode_row;
+use crate::io::npy_layout;
+use crate::prelude::*;
+use crate::utils::lock_cache;
+
+/// ShardedDataset splits the instances over several `.npy` files, the shards, which are read into memory only while
+/// they are needed.
+///
+/// The instances of the first shard come first, then those of the second, and so on. At most `max_loaded_shards` are
+/// kept in memory, and the least recently used shard is dropped to make room for another. Distances are computed one
+/// shard at a time, so tree-building and search load each shard they need once per batch of distances, rather than
+/// once per instance.
+///
+/// The shards are checked when the dataset is opened, and their files must not change afterwards.
+///
+/// # Panics
+///
+/// Since the `Dataset` trait cannot return errors, failing to read a shard again after it was dropped, e.g. because
+/// its file was removed or changed in length, panics. `try_instance` returns these failures as an Err instead.
+pub struct ShardedDataset<T: Number, U: Number> {
+    paths: Vec<PathBuf>,
+    /// The layout of each shard: the position of its first instance in the file, and whether it is big-endian.
+    layouts: Vec<(usize, bool)>,
+    /// The index of the first instance of each shard, followed by the cardinality of the dataset.
+    starts: Vec<Index>,
+    dimensionality: usize,
+    metric: Arc<dyn Metric<T, U>>,
+    max_loaded_shards: usize,
+    loaded: Mutex<LoadedShards<T>>,
+    shard_loads: AtomicU64,
+}
+
+/// The instances of a shard in memory.
+pub(crate) type Shard<T> = Arc<Vec<Vec<T>>>;
+
+/// The shards in memory, with the time at which each was last used.
+pub(crate) struct LoadedShards<T> {
+    pub(crate) shards: HashMap<usize, (Shard<T>, u64)>,
+    clock: u64,
+}
+
+impl<T> LoadedShards<T> {
+    pub(crate) fn new() -> Self {
+        LoadedShards {
+            shards: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the shard if it is in memory, marking it as the most recently used.
+    pub(crate) fn get(&mut self, shard: usize) -> Option<Shard<T>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (instances, last_used) = self.shards.get_mut(&shard)?;
+        *last_used = clock;
+        Some(Arc::clone(instances))
+    }
+
+    /// Keeps the shard in memory, first dropping the least recently used shards so that at most `capacity` are kept.
+    pub(crate) fn insert(&mut self, shard: usize, instances: Shard<T>, capacity: usize) {
+        while self.shards.len() >= capacity {
+            let oldest = self.shards.iter().min_by_key(|(_, (_, last_used))| *last_used);
+            let Some(&oldest) = oldest.map(|(shard, _)| shard) else {
+                break;
+            };
+            self.shards.remove(&oldest);
+        }
+        self.shards.insert(shard, (instances, self.clock));
+    }
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for ShardedDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("ShardedDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality)
+            .field("num-shards", &self.paths.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> ShardedDataset<T, U> {
+    /// Opens the shards at the given paths, which must each hold a 2-dimensional, row-major array of `T` with the same
+    /// number of columns. Only their headers are read.
+    pub fn open(paths: &[PathBuf], max_loaded_shards: usize, metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        if paths.is_empty() {
+            return Err("A sharded dataset needs at least one shard.".to_string());
+        }
+
+        let mut layouts = Vec::with_capacity(paths.len());
+        let mut starts = vec![0];
+        let mut dimensionality = None;
+        for path in paths.iter() {
+            let fail = |error: String| format!("Error: Failed to read {}. {}", path.display(), error);
+            let (header, file_length) = read_npy_file_header(path).map_err(fail)?;
+            let (cardinality, columns, big_endian) = npy_layout::<T>(&header, file_length).map_err(fail)?;
+            if *dimensionality.get_or_insert(columns) != columns {
+                return Err(fail(format!(
+                    "The shard has {} columns but the first shard has {}.",
+                    columns,
+                    dimensionality.unwrap()
+                )));
+            }
+            layouts.push((header.offset, big_endian));
+            starts.push(starts.last().unwrap() + cardinality);
+        }
+
+        Ok(ShardedDataset {
+            paths: paths.to_vec(),
+            layouts,
+            starts,
+            dimensionality: dimensionality.unwrap(),
+            metric,
+            max_loaded_shards: std::cmp::max(max_loaded_shards, 1),
+            loaded: Mutex::new(LoadedShards::new()),
+            shard_loads: AtomicU64::new(0),
+        })
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> ShardedDataset<T, U> {
+    pub fn num_shards(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Returns the shard holding the instance at the given index, and the position of the instance in the shard.
+    pub fn shard_of(&self, index: Index) -> (usize, usize) {
+        let shard = self.starts.partition_point(|&start| start <= index) - 1;
+        (shard, index - self.starts[shard])
+    }
+
+    /// Returns the shards that are currently in memory, in sorted order.
+    pub fn loaded_shards(&self) -> Vec<usize> {
+        let mut shards: Vec<_> = lock_cache(&self.loaded).shards.keys().copied().collect();
+        shards.sort_unstable();
+        shards
+    }
+
+    /// Returns the number of times a shard has been read from disk.
+    pub fn shard_loads(&self) -> u64 {
+        self.shard_loads.load(Ordering::Relaxed)
+    }
+
+    /// Returns the instance at the provided index, reading its shard if it is not in memory.
+    ///
+    /// Returns an Err if the index is out of range or if the shard cannot be read.
+    pub fn try_instance(&self, i: Index) -> Result<Vec<T>, String> {
+        if i >= self.cardinality() {
+            return Err(format!(
+                "Index {} is out of range for a dataset of {} instances.",
+                i,
+                self.cardinality()
+            ));
+        }
+        let (shard, position) = self.shard_of(i);
+        Ok(self.try_shard(shard)?[position].clone())
+    }
+
+    /// Returns the instances of the shard, reading it from disk if it is not in memory.
+    fn shard(&self, shard: usize) -> Shard<T> {
+        self.try_shard(shard).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Returns the instances of the shard, reading it from disk if it is not in memory, or an Err if the file cannot
+    /// be read or no longer has the length it had when the dataset was opened.
+    fn try_shard(&self, shard: usize) -> Result<Shard<T>, String> {
+        if let Some(instances) = lock_cache(&self.loaded).get(shard) {
+            return Ok(instances);
+        }
+
+        // The shard is read without holding the lock, so that other shards may be served meanwhile.
+        let path = &self.paths[shard];
+        let fail = |error: String| format!("Error: Failed to read {}. {}", path.display(), error);
+        let bytes = std::fs::read(path).map_err(|error| fail(error.to_string()))?;
+        let (offset, big_endian) = self.layouts[shard];
+        let length = self.dimensionality * T::num_bytes() as usize;
+        let cardinality = self.starts[shard + 1] - self.starts[shard];
+        if bytes.len() != offset + cardinality * length {
+            return Err(fail(format!(
+                "The shard has {} bytes but had {} when the dataset was opened.",
+                bytes.len(),
+                offset + cardinality * length
+            )));
+        }
+        let instances: Vec<_> = bytes[offset..]
+            .chunks(length)
+            .map(|row| decode_row(row, big_endian))
+            .collect();
+        let instances = Arc::new(instances);
+        self.shard_loads.fetch_add(1, Ordering::Relaxed);
+
+        lock_cache(&self.loaded).insert(shard, Arc::clone(&instances), self.max_loaded_shards);
+        Ok(instances)
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for ShardedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        *self.starts.last().unwrap()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Returns the instance at the provided index, reading its shard if it is not in memory.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let (shard, position) = self.shard_of(i);
+        self.shard(shard)[position].clone()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    /// Computes the distances one shard at a time, so that each shard is needed only once.
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        let mut order: Vec<_> = (0..right.len()).collect();
+        order.sort_by_key(|&p| right[p]);
+
+        let mut distances = vec![U::zero(); right.len()];
+        for run in order.chunk_by(|&a, &b| self.shard_of(right[a]).0 == self.shard_of(right[b]).0) {
+            let instances = self.shard(self.shard_of(right[run[0]]).0);
+            let run_distances: Vec<_> = run
+                .par_iter()
+                .map(|&p| {
+                    if right[p] == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &instances[self.shard_of(right[p]).1])
+                    }
+                })
+                .collect();
+            for (&p, distance) in run.iter().zip(run_distances) {
+                distances[p] = distance;
+            }
+        }
+        Array1::from_vec(distances)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+    use ndarray::prelude::*;
+    use ndarray_npy::write_npy;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::ShardedDataset;
+
+    #[test]
+    fn test_sharded_dataset() {
+        let data: Vec<Vec<f64>> = (0..250)
+            .map(|i| (0..4).map(|j| ((i * (j + 5)) % 23) as f64 + 0.25 * j as f64).collect())
+            .collect();
+        let directory = std::env::temp_dir().join(format!("clam-test-sharded-dataset-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let paths: Vec<_> = data
+            .chunks(60)
+            .enumerate()
+            .map(|(i, shard)| {
+                let path = directory.join(format!("shard-{}.npy", i));
+                let array = Array2::from_shape_vec((shard.len(), 4), shard.iter().flatten().cloned().collect()).unwrap();
+                write_npy(&path, &array).unwrap();
+                path
+            })
+            .collect();
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let sharded = Arc::new(ShardedDataset::<f64, f64>::open(&paths, 2, Arc::clone(&metric)).unwrap());
+        assert_eq!(sharded.num_shards(), 5);
+        assert_eq!(sharded.cardinality(), 250);
+        assert_eq!(sharded.dimensionality(), 4);
+        assert_eq!(sharded.shard_of(0), (0, 0));
+        assert_eq!(sharded.shard_of(130), (2, 10));
+        assert_eq!(sharded.shard_of(249), (4, 9));
+        assert!(sharded.loaded_shards().is_empty());
+        for i in (0..250).step_by(9) {
+            assert_eq!(sharded.instance(i), data[i]);
+        }
+        assert_eq!(sharded.loaded_shards().len(), 2);
+
+        // Each batch of distances reads each shard at most once.
+        let loads = sharded.shard_loads();
+        let distances = sharded.distances_from(3, &(0..250).rev().collect::<Vec<_>>());
+        assert!(sharded.shard_loads() - loads <= 5);
+        for (i, &distance) in (0..250).rev().zip(distances.iter()) {
+            assert!(approx_eq!(f64, distance, metric.distance(&data[3], &data[i])));
+        }
+
+        let cakes = Cakes::build(Arc::clone(&sharded).as_arc_dataset(), Some(6), None);
+        assert!(sharded.loaded_shards().len() <= 2);
+        for query in data.iter().step_by(23) {
+            let mut hits = cakes.rnn_indices(query, Some(4.));
+            let mut expected = cakes.linear_search_indices(query, Some(4.), None);
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+        }
+
+        // The shards must agree on their columns and types.
+        let other = directory.join("other.npy");
+        write_npy(&other, &Array2::<f64>::zeros((3, 5))).unwrap();
+        let mismatched = [paths[0].clone(), other];
+        assert!(ShardedDataset::<f64, f64>::open(&mismatched, 2, Arc::clone(&metric)).is_err());
+        assert!(ShardedDataset::<f32, f32>::open(&paths, 2, metric_from_name("euclidean").unwrap()).is_err());
+        assert!(ShardedDataset::<f64, f64>::open(&[], 2, Arc::clone(&metric)).is_err());
+
+        // Shards that change after the dataset was opened, and indices out of range, give an Err.
+        let sharded = ShardedDataset::<f64, f64>::open(&paths, 2, metric).unwrap();
+        assert_eq!(sharded.try_instance(130).unwrap(), data[130]);
+        assert!(sharded.try_instance(250).is_err());
+        write_npy(&paths[3], &Array2::<f64>::zeros((59, 4))).unwrap();
+        assert!(sharded
+            .try_instance(190)
+            .unwrap_err()
+            .contains("when the dataset was opened"));
+        std::fs::remove_file(&paths[4]).unwrap();
+        assert!(sharded.try_instance(249).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+//! `SparseRowMajor`, a dataset of instances that are mostly zeros.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+
+/// SparseRowMajor represents a dataset stored in the compressed sparse row (CSR) format,
+/// where only the non-zero features of each instance are stored.
+///
+/// The distances for the euclidean, euclideansq, manhattan, cosine, hamming and jaccard metrics are computed from the
+/// stored features alone, and are the same as those of the metric on the dense instances. Any other metric is given
+/// the dense instances.
+pub struct SparseRowMajor<T: Number, U: Number> {
+    /// The stored features of instance `i` are at positions `offsets[i]..offsets[i + 1]` in `columns` and `values`.
+    offsets: Vec<usize>,
+    /// The column of each stored feature, increasing within each instance.
+    columns: Vec<usize>,
+    values: Vec<T>,
+    dimensionality: usize,
+    metric: Arc<dyn Metric<T, U>>,
+    kernel: SparseKernel,
+}
+
+/// The metrics whose distances are computed from the stored features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SparseKernel {
+    Euclidean,
+    EuclideanSq,
+    Manhattan,
+    Cosine,
+    Hamming,
+    Jaccard,
+    Dense,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for SparseRowMajor<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("SparseRowMajor Dataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("data-dimensionality", &self.dimensionality)
+            .field("stored-features", &self.values.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> SparseRowMajor<T, U> {
+    /// Create a new Dataset from the arrays of the CSR format, using the provided metric.
+    ///
+    /// # Arguments
+    ///
+    /// * offsets - `cardinality + 1` increasing positions, starting at 0, of the first stored feature of each instance.
+    /// * columns - the column of each stored feature, increasing within each instance.
+    /// * values - the value of each stored feature.
+    /// * dimensionality - the number of columns.
+    /// * metric - distance-metric to use.
+    ///
+    /// Returns an Err if the arrays do not describe a valid CSR matrix.
+    pub fn new(
+        offsets: Vec<usize>,
+        columns: Vec<usize>,
+        values: Vec<T>,
+        dimensionality: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        if offsets.first() != Some(&0) || offsets.last() != Some(&columns.len()) || columns.len() != values.len() {
+            return Err(format!(
+                "The offsets must run from 0 to {} for {} columns and {} values.",
+                columns.len(),
+                columns.len(),
+                values.len()
+            ));
+        }
+        for (i, bounds) in offsets.windows(2).enumerate() {
+            if bounds[0] > bounds[1] {
+                return Err(format!("The offsets of instance {} decrease.", i));
+            }
+            let row = &columns[bounds[0]..bounds[1]];
+            if row.windows(2).any(|pair| pair[0] >= pair[1]) || row.last().is_some_and(|&c| c >= dimensionality) {
+                return Err(format!(
+                    "The columns of instance {} must increase and be less than {}.",
+                    i, dimensionality
+                ));
+            }
+        }
+
+        let kernel = match metric.name().as_str() {
+            "euclidean" => SparseKernel::Euclidean,
+            "euclideansq" => SparseKernel::EuclideanSq,
+            "manhattan" => SparseKernel::Manhattan,
+            "cosine" => SparseKernel::Cosine,
+            "hamming" => SparseKernel::Hamming,
+            "jaccard" => SparseKernel::Jaccard,
+            _ => SparseKernel::Dense,
+        };
+
+        Ok(SparseRowMajor {
+            offsets,
+            columns,
+            values,
+            dimensionality,
+            metric,
+            kernel,
+        })
+    }
+
+    /// Create a new Dataset from dense instances, storing only their non-zero features.
+    pub fn from_dense(data: &[Vec<T>], metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let mut offsets = vec![0];
+        let mut columns = vec![];
+        let mut values = vec![];
+        for row in data.iter() {
+            for (column, &value) in row.iter().enumerate().filter(|(_, &value)| value != T::zero()) {
+                columns.push(column);
+                values.push(value);
+            }
+            offsets.push(columns.len());
+        }
+        let dimensionality = data.iter().map(|row| row.len()).max().unwrap_or(0);
+        Self::new(offsets, columns, values, dimensionality, metric)
+    }
+
+    /// Returns the number of stored features.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> SparseRowMajor<T, U> {
+    /// Returns the columns and values of the stored features of the instance at the given index.
+    pub fn row(&self, index: Index) -> (&[usize], &[T]) {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        (&self.columns[range.clone()], &self.values[range])
+    }
+
+    /// Computes the distance between two instances from their stored features.
+    fn sparse_distance(&self, left: Index, right: Index) -> U {
+        let x = self.row(left);
+        let y = self.row(right);
+        match self.kernel {
+            SparseKernel::Euclidean => {
+                let d: f64 = merge_sparse(x, y).map(|(a, b)| (a.as_f64() - b.as_f64()).powi(2)).sum();
+                U::saturating_from_f64(d.sqrt())
+            }
+            SparseKernel::EuclideanSq => {
+                U::saturating_from_f64(merge_sparse(x, y).map(|(a, b)| (a.as_f64() - b.as_f64()).powi(2)).sum())
+            }
+            SparseKernel::Manhattan => {
+                U::saturating_from_f64(merge_sparse(x, y).map(|(a, b)| (a.as_f64() - b.as_f64()).abs()).sum())
+            }
+            SparseKernel::Hamming => U::saturating_from_f64(merge_sparse(x, y).filter(|(a, b)| a != b).count() as f64),
+            SparseKernel::Cosine => {
+                let xx: f64 = x.1.iter().map(|a| a.as_f64().powi(2)).sum();
+                let yy: f64 = y.1.iter().map(|b| b.as_f64().powi(2)).sum();
+                let xy: f64 = merge_sparse(x, y).map(|(a, b)| a.as_f64() * b.as_f64()).sum();
+                if xx == 0. || yy == 0. || xy <= 0. {
+                    return U::one();
+                }
+                U::saturating_from_f64(1. - (xy * xy / (xx * yy)).sqrt())
+            }
+            SparseKernel::Jaccard => self.sparse_jaccard(x, y),
+            SparseKernel::Dense => self.metric.distance(&self.instance(left), &self.instance(right)),
+        }
+    }
+
+    /// The jaccard metric treats the features of an instance as a set, so the unstored features add a zero to the set.
+    fn sparse_jaccard(&self, (_, x): (&[usize], &[T]), (_, y): (&[usize], &[T])) -> U {
+        if self.dimensionality == 0 {
+            return U::one();
+        }
+
+        let zero = T::zero().to_bytes();
+        let mut x_set: HashSet<Vec<u8>> = x.iter().map(|a| a.to_bytes()).collect();
+        if x.len() < self.dimensionality {
+            x_set.insert(zero.clone());
+        }
+        let mut intersect = y.iter().filter(|b| x_set.contains(&b.to_bytes())).count();
+        if x_set.contains(&zero) {
+            intersect += self.dimensionality - y.len();
+        }
+
+        if intersect == x_set.len() && intersect == self.dimensionality {
+            return U::zero();
+        }
+
+        U::saturating_from_f64(1. - intersect as f64 / (x_set.len() + self.dimensionality - intersect) as f64)
+    }
+}
+
+/// Returns the pairs of values, with zeros for unstored features, of each column stored in either instance.
+fn merge_sparse<'a, T: Number>(
+    (x_columns, x_values): (&'a [usize], &'a [T]),
+    (y_columns, y_values): (&'a [usize], &'a [T]),
+) -> impl Iterator<Item = (T, T)> + 'a {
+    let (mut i, mut j) = (0, 0);
+    std::iter::from_fn(move || match (x_columns.get(i), y_columns.get(j)) {
+        (Some(a), Some(b)) if a == b => {
+            i += 1;
+            j += 1;
+            Some((x_values[i - 1], y_values[j - 1]))
+        }
+        (Some(a), Some(b)) if a < b => {
+            i += 1;
+            Some((x_values[i - 1], T::zero()))
+        }
+        (Some(_), None) => {
+            i += 1;
+            Some((x_values[i - 1], T::zero()))
+        }
+        (_, Some(_)) => {
+            j += 1;
+            Some((T::zero(), y_values[j - 1]))
+        }
+        (None, None) => None,
+    })
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for SparseRowMajor<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    /// Returns the dense instance at the provided index.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let mut instance = vec![T::zero(); self.dimensionality];
+        let (columns, values) = self.row(i);
+        for (&column, &value) in columns.iter().zip(values.iter()) {
+            instance[column] = value;
+        }
+        instance
+    }
+
+    fn instance_size(&self) -> usize {
+        // The average size of the stored features of an instance.
+        let bytes = self.values.len() * (std::mem::size_of::<usize>() + T::num_bytes() as usize);
+        std::cmp::max(bytes / std::cmp::max(self.cardinality(), 1), 1)
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.sparse_distance(left, right)
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::SparseRowMajor;
+
+    #[test]
+    fn test_sparse_row_major() {
+        // Mostly zeros, with whole-number values so that the sparse and dense sums are exact.
+        let data: Vec<Vec<f64>> = (0..150)
+            .map(|i: usize| {
+                (0..40)
+                    .map(|j: usize| {
+                        if (i * 7 + j * 3).is_multiple_of(11) {
+                            ((i + j) % 4) as f64
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        for name in ["euclidean", "euclideansq", "manhattan", "cosine", "hamming", "jaccard"] {
+            let metric = metric_from_name(name).unwrap();
+            let sparse = SparseRowMajor::<f64, f64>::from_dense(&data, Arc::clone(&metric)).unwrap();
+            assert_eq!(sparse.cardinality(), 150);
+            assert_eq!(sparse.dimensionality(), 40);
+            assert!(sparse.nnz() * 5 < 150 * 40);
+            for i in (0..150).step_by(7) {
+                assert_eq!(sparse.instance(i), data[i]);
+                // Every dataset gives zero for an instance with itself.
+                for j in (0..150).step_by(5).filter(|&j| j != i) {
+                    let expected = metric.distance(&data[i], &data[j]);
+                    assert!(
+                        approx_eq!(f64, sparse.distance(i, j), expected, ulps = 4),
+                        "{} between {} and {}",
+                        name,
+                        i,
+                        j
+                    );
+                }
+            }
+        }
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let sparse = Arc::new(SparseRowMajor::<f64, f64>::from_dense(&data, Arc::clone(&metric)).unwrap());
+        assert_eq!(sparse.row(0), (&[11, 22, 33][..], &[3., 2., 1.][..]));
+        let cakes = Cakes::build(sparse.as_arc_dataset(), None, None);
+        for query in data.iter().step_by(17) {
+            let mut hits = cakes.rnn_indices(query, Some(3.));
+            let mut expected = cakes.linear_search_indices(query, Some(3.), None);
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+        }
+
+        assert!(SparseRowMajor::<f64, f64>::new(vec![0, 2], vec![1, 1], vec![1., 2.], 3, Arc::clone(&metric)).is_err());
+        assert!(SparseRowMajor::<f64, f64>::new(vec![0, 1], vec![3], vec![1.], 3, Arc::clone(&metric)).is_err());
+        assert!(
+            SparseRowMajor::<f64, f64>::new(vec![0, 2, 1], vec![0, 1], vec![1., 2.], 3, Arc::clone(&metric)).is_err()
+        );
+        assert!(SparseRowMajor::<f64, f64>::new(vec![0, 1], vec![0, 1], vec![1., 2.], 3, metric).is_err());
+    }
+}
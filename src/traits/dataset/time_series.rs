@@ -0,0 +1,178 @@
+//! `TimeSeriesDataset`, a dataset of numeric series of different lengths.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::prelude::*;
+
+/// TimeSeriesDataset holds numeric series of different lengths, e.g. sensor recordings or spectra, end to end in one
+/// buffer.
+///
+/// Each series is served as a slice of the buffer by `series`, and the metric is given those slices, so it should
+/// handle series of different lengths, as `dtw` does. The dimensionality is the length of the longest series.
+pub struct TimeSeriesDataset<T: Number, U: Number> {
+    values: Vec<T>,
+    /// The start of each series in `values`, followed by the end of the last series.
+    offsets: Vec<usize>,
+    metric: Arc<dyn Metric<T, U>>,
+    max_length: usize,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for TimeSeriesDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("TimeSeriesDataset")
+            .field("data-cardinality", &(self.offsets.len() - 1))
+            .field("data-dimensionality", &self.max_length)
+            .field("num-values", &self.values.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> TimeSeriesDataset<T, U> {
+    /// Copies the series into one buffer.
+    pub fn new(series: &[Vec<T>], metric: Arc<dyn Metric<T, U>>) -> Self {
+        let lengths: Vec<_> = series.iter().map(Vec::len).collect();
+        Self::from_parts(series.concat(), &lengths, metric)
+    }
+
+    /// Creates a dataset from the values of every series, end to end, and the length of each series.
+    ///
+    /// Returns an Err if the lengths do not add up to the number of values.
+    pub fn from_values(values: Vec<T>, lengths: &[usize], metric: Arc<dyn Metric<T, U>>) -> Result<Self, String> {
+        let total: usize = lengths.iter().sum();
+        if total != values.len() {
+            return Err(format!(
+                "The lengths of the series add up to {} but there are {} values.",
+                total,
+                values.len()
+            ));
+        }
+        Ok(Self::from_parts(values, lengths, metric))
+    }
+
+    fn from_parts(values: Vec<T>, lengths: &[usize], metric: Arc<dyn Metric<T, U>>) -> Self {
+        let mut offsets = vec![0];
+        offsets.extend(lengths.iter().scan(0, |end, &length| {
+            *end += length;
+            Some(*end)
+        }));
+        TimeSeriesDataset {
+            values,
+            offsets,
+            metric,
+            max_length: lengths.iter().copied().max().unwrap_or(0),
+        }
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> TimeSeriesDataset<T, U> {
+    /// Returns the series at the given index, without copying it.
+    pub fn series(&self, index: Index) -> &[T] {
+        &self.values[self.offsets[index]..self.offsets[index + 1]]
+    }
+
+    /// Returns the length of the series at the given index.
+    pub fn length(&self, index: Index) -> usize {
+        self.offsets[index + 1] - self.offsets[index]
+    }
+
+    /// Returns the length of every series, at the position of its index.
+    pub fn lengths(&self) -> Vec<usize> {
+        self.offsets.windows(2).map(|window| window[1] - window[0]).collect()
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for TimeSeriesDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.max_length
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.series(index).to_vec()
+    }
+
+    /// The mean size of a series, since the series have different lengths.
+    fn instance_size(&self) -> usize {
+        let cardinality = std::cmp::max(self.cardinality(), 1);
+        std::cmp::max(self.values.len() / cardinality, 1) * T::num_bytes() as usize
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(self.series(left), self.series(right))
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        Array1::from_vec(right.par_iter().map(|&r| self.distance(left, r)).collect())
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::TimeSeriesDataset;
+
+    #[test]
+    fn test_time_series_dataset() {
+        let series: Vec<Vec<f64>> = (0..60)
+            .map(|i| (0..10 + i % 7).map(|t| ((t * (i % 5 + 1)) % 11) as f64).collect())
+            .collect();
+        let metric = metric_from_name("dtw").unwrap();
+        let dataset = TimeSeriesDataset::new(&series, Arc::clone(&metric));
+        assert_eq!(dataset.cardinality(), 60);
+        assert_eq!(dataset.dimensionality(), 16);
+        assert_eq!(dataset.length(3), 13);
+        assert_eq!(dataset.series(3), series[3].as_slice());
+        assert_eq!(dataset.lengths(), series.iter().map(Vec::len).collect::<Vec<_>>());
+        assert_eq!(dataset.distance(1, 8), metric.distance(&series[1], &series[8]));
+
+        let values = series.concat();
+        let lengths = dataset.lengths();
+        assert!(TimeSeriesDataset::from_values(values.clone(), &lengths[1..], Arc::clone(&metric)).is_err());
+        let dataset = TimeSeriesDataset::from_values(values, &lengths, metric).unwrap();
+        let dataset = Arc::new(dataset).as_arc_dataset();
+        let cakes = Cakes::build(Arc::clone(&dataset), Some(4), None);
+        for i in [0, 29, 59] {
+            let hits = cakes.rnn_indices(&series[i], Some(0.));
+            assert!(hits.contains(&i));
+        }
+    }
+}
@@ -0,0 +1,624 @@
+//! Datasets that serve the instances of other datasets: a `DatasetView` of a subset of them, a `PermutedDataset` of
+//! them in a different order and a `ConcatDataset` of several datasets as one.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use serde_json::Value;
+
+use super::CacheLookups;
+use super::Provenance;
+use crate::prelude::*;
+use crate::utils::read_cache;
+
+/// A `Dataset` over a subset of the instances of another dataset, which are not copied.
+///
+/// The instance at index `i` of the view is the instance at index `indices[i]` of the underlying dataset, so distances
+/// are computed, and cached, by the underlying dataset.
+#[derive(Debug, Clone)]
+pub struct DatasetView<T: Number, U: Number> {
+    dataset: Arc<dyn Dataset<T, U>>,
+    indices: Vec<Index>,
+}
+
+/// A `DatasetView` of the instances that match a mask or a predicate on their metadata. See `DatasetView::from_mask`.
+pub type FilteredDataset<T, U> = DatasetView<T, U>;
+
+impl<T: 'static + Number, U: 'static + Number> DatasetView<T, U> {
+    /// Creates a view of the instances at the given indices of the dataset, in the given order.
+    pub fn new(dataset: Arc<dyn Dataset<T, U>>, indices: Vec<Index>) -> Self {
+        DatasetView { dataset, indices }
+    }
+
+    /// Creates a view of the instances whose entries in the mask are true, e.g. those of one time window.
+    ///
+    /// Returns an Err if the mask does not have one entry per instance of the dataset.
+    pub fn from_mask(dataset: Arc<dyn Dataset<T, U>>, mask: &[bool]) -> Result<Self, String> {
+        if mask.len() != dataset.cardinality() {
+            return Err(format!(
+                "Expected a mask of {} entries but got {}.",
+                dataset.cardinality(),
+                mask.len()
+            ));
+        }
+        let indices = (0..mask.len()).filter(|&i| mask[i]).collect();
+        Ok(Self::new(dataset, indices))
+    }
+
+    /// Creates a view of the instances whose metadata satisfy the predicate, e.g. those of one species.
+    ///
+    /// Returns an Err if the metadata do not have one entry per instance of the dataset.
+    pub fn from_metadata<M>(
+        dataset: Arc<dyn Dataset<T, U>>,
+        metadata: &[M],
+        predicate: impl Fn(&M) -> bool,
+    ) -> Result<Self, String> {
+        let mask: Vec<_> = metadata.iter().map(predicate).collect();
+        Self::from_mask(dataset, &mask)
+    }
+
+    /// Creates a view of the first instance of each group of identical instances, in their order in the dataset.
+    ///
+    /// Also returns the index in the view of the instance identical to each instance of the dataset, at the position
+    /// of its index, so that the instances that were dropped can be traced to the one that was kept.
+    pub fn deduplicated(dataset: Arc<dyn Dataset<T, U>>) -> (Self, Vec<Index>) {
+        let representatives = dataset.deduplicate();
+        let mut positions = vec![0; representatives.len()];
+        let mut indices = vec![];
+        for (i, &representative) in representatives.iter().enumerate() {
+            if representative == i {
+                positions[i] = indices.len();
+                indices.push(i);
+            }
+        }
+        let representatives = representatives.iter().map(|&r| positions[r]).collect();
+        (Self::new(dataset, indices), representatives)
+    }
+
+    /// Returns the underlying dataset.
+    pub fn dataset(&self) -> &Arc<dyn Dataset<T, U>> {
+        &self.dataset
+    }
+
+    /// Returns the indices, in the underlying dataset, of the instances in the view.
+    pub fn parent_indices(&self) -> &[Index] {
+        &self.indices
+    }
+
+    /// Returns the index, in the underlying dataset, of the instance at the given index of the view.
+    pub fn parent_index(&self, index: Index) -> Index {
+        self.indices[index]
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+
+    fn parents(&self, indices: &[Index]) -> Vec<Index> {
+        indices.iter().map(|&i| self.indices[i]).collect()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> Dataset<T, U> for DatasetView<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        self.dataset.metric()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dataset.dimensionality()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.indices.len()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.dataset.instance(self.indices[index])
+    }
+
+    fn instance_size(&self) -> usize {
+        self.dataset.instance_size()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        self.dataset.distance(self.indices[left], self.indices[right])
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        self.dataset.distances_from(self.indices[left], &self.parents(right))
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        self.dataset.distances_among(&self.parents(left), &self.parents(right))
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.dataset.pairwise_distances(&self.parents(indices))
+    }
+
+    fn provenance(&self) -> Provenance {
+        let view = Provenance {
+            origins: self.indices.clone(),
+            original_cardinality: self.dataset.cardinality(),
+        };
+        // The indices of a view are always those of its underlying dataset, so the composition is never an Err.
+        self.dataset.provenance().then(&view).unwrap_or(view)
+    }
+
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        self.dataset.instance_metadata(self.indices[index])
+    }
+
+    fn cache_lookups(&self) -> Option<CacheLookups> {
+        self.dataset.cache_lookups()
+    }
+}
+
+/// A `Dataset` holding the instances of another dataset in a different order, e.g. the depth-first order of the
+/// leaves of a tree, so that the instances of each cluster have contiguous indices.
+///
+/// The instance at index `i` is the instance at index `permutation[i]`, its original index, in the underlying
+/// dataset. Use `original_index` and `original_hits` to report search results in terms of the original indices.
+#[derive(Debug, Clone)]
+pub struct PermutedDataset<T: Number, U: Number> {
+    view: DatasetView<T, U>,
+    /// The index of each original instance in this dataset.
+    inverse: Vec<Index>,
+}
+
+impl<T: 'static + Number, U: 'static + Number> PermutedDataset<T, U> {
+    /// Reorders the dataset so that the instance at index `i` is the instance at index `permutation[i]` of `dataset`.
+    ///
+    /// Returns an Err if the permutation does not hold every index of the dataset exactly once.
+    pub fn new(dataset: Arc<dyn Dataset<T, U>>, permutation: Vec<Index>) -> Result<Self, String> {
+        let mut inverse = vec![usize::MAX; dataset.cardinality()];
+        if permutation.len() != inverse.len() {
+            return Err(format!(
+                "Expected a permutation of {} indices but got {}.",
+                inverse.len(),
+                permutation.len()
+            ));
+        }
+        for (i, &original) in permutation.iter().enumerate() {
+            match inverse.get_mut(original) {
+                Some(position) if *position == usize::MAX => *position = i,
+                Some(_) => return Err(format!("Index {} appears more than once in the permutation.", original)),
+                None => return Err(format!("Index {} is out of bounds for the permutation.", original)),
+            }
+        }
+        Ok(PermutedDataset {
+            view: DatasetView::new(dataset, permutation),
+            inverse,
+        })
+    }
+
+    /// Reorders the dataset of the tree into the depth-first order of its leaves.
+    /// Instances that are in no leaf of the tree come last, in their original order.
+    pub fn depth_first(root: &Arc<Cluster<T, U>>) -> Self {
+        let mut leaves = root.flatten_tree();
+        leaves.push(Arc::clone(root));
+        leaves.retain(|cluster| read_cache(&cluster.children).is_none());
+        // The depth-first order of clusters is the lexicographic order of their names.
+        leaves.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut permutation: Vec<_> = leaves.iter().flat_map(|leaf| leaf.indices.iter().copied()).collect();
+        let mut in_tree = vec![false; root.dataset.cardinality()];
+        permutation.iter().for_each(|&i| in_tree[i] = true);
+        permutation.extend((0..in_tree.len()).filter(|&i| !in_tree[i]));
+        Self::new(Arc::clone(&root.dataset), permutation).unwrap()
+    }
+
+    /// Returns the underlying dataset.
+    pub fn dataset(&self) -> &Arc<dyn Dataset<T, U>> {
+        self.view.dataset()
+    }
+
+    /// Returns the original index of each instance, at the position of its index in this dataset.
+    pub fn permutation(&self) -> &[Index] {
+        self.view.parent_indices()
+    }
+
+    /// Returns the original index of the instance at the given index.
+    pub fn original_index(&self, index: Index) -> Index {
+        self.view.parent_index(index)
+    }
+
+    /// Returns the index in this dataset of the instance at the given original index.
+    pub fn permuted_index(&self, original: Index) -> Index {
+        self.inverse[original]
+    }
+
+    /// Replaces the indices of the hits of a search over this dataset with the original indices.
+    pub fn original_hits<D: Copy>(&self, hits: &[(Index, D)]) -> Vec<(Index, D)> {
+        hits.iter().map(|&(i, d)| (self.original_index(i), d)).collect()
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> Dataset<T, U> for PermutedDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        self.view.metric()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.view.cardinality()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.view.dimensionality()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        self.view.indices()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        self.view.instance(index)
+    }
+
+    fn instance_size(&self) -> usize {
+        self.view.instance_size()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        self.view.distance(left, right)
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        self.view.distances_from(left, right)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        self.view.distances_among(left, right)
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.view.pairwise_distances(indices)
+    }
+
+    fn provenance(&self) -> Provenance {
+        self.view.provenance()
+    }
+
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        self.view.instance_metadata(index)
+    }
+
+    fn cache_lookups(&self) -> Option<CacheLookups> {
+        self.view.cache_lookups()
+    }
+}
+
+/// ConcatDataset combines several datasets, whose instances are compared with the same metric, into one dataset
+/// without copying their instances.
+///
+/// The instances of the i-th part come after those of the parts before it, so appending a part, e.g. the batch of
+/// another month, leaves the indices of the existing instances unchanged.
+pub struct ConcatDataset<T: Number, U: Number> {
+    parts: Vec<Arc<dyn Dataset<T, U>>>,
+    /// The index of the first instance of each part, followed by the cardinality of the dataset.
+    starts: Vec<Index>,
+    metric: Arc<dyn Metric<T, U>>,
+    metadata: Option<Vec<String>>,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for ConcatDataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("ConcatDataset")
+            .field("data-cardinality", &self.cardinality())
+            .field("num-parts", &self.parts.len())
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> ConcatDataset<T, U> {
+    /// Combines the parts, in order. Returns an Err if there are none or if their metrics differ.
+    pub fn new(parts: Vec<Arc<dyn Dataset<T, U>>>) -> Result<Self, String> {
+        let metric = match parts.first() {
+            Some(part) => part.metric(),
+            None => return Err("Cannot concatenate zero datasets.".to_string()),
+        };
+        if let Some(part) = parts.iter().find(|part| part.metric_name() != metric.name()) {
+            return Err(format!(
+                "Cannot concatenate datasets with the {} and {} metrics.",
+                metric.name(),
+                part.metric_name()
+            ));
+        }
+
+        let mut starts = vec![0];
+        for part in parts.iter() {
+            starts.push(starts.last().unwrap() + part.cardinality());
+        }
+        Ok(ConcatDataset {
+            parts,
+            starts,
+            metric,
+            metadata: None,
+        })
+    }
+
+    /// Attaches the metadata of each part, in order, which are merged so that the metadata of an instance is at the
+    /// position of its index. Returns an Err if the metadata of any part does not have one entry per instance.
+    pub fn with_metadata(mut self, metadata: Vec<Vec<String>>) -> Result<Self, String> {
+        if metadata.len() != self.parts.len() {
+            return Err(format!(
+                "Expected metadata for {} parts but got {}.",
+                self.parts.len(),
+                metadata.len()
+            ));
+        }
+        for (i, (part, entries)) in self.parts.iter().zip(metadata.iter()).enumerate() {
+            if part.cardinality() != entries.len() {
+                return Err(format!(
+                    "Part {} has {} instances but {} entries of metadata.",
+                    i,
+                    part.cardinality(),
+                    entries.len()
+                ));
+            }
+        }
+        self.metadata = Some(metadata.into_iter().flatten().collect());
+        Ok(self)
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> ConcatDataset<T, U> {
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Returns the parts of the dataset, in order.
+    pub fn parts(&self) -> &[Arc<dyn Dataset<T, U>>] {
+        &self.parts
+    }
+
+    /// Returns the index of the first instance of the given part.
+    pub fn offset(&self, part: usize) -> Index {
+        self.starts[part]
+    }
+
+    /// Returns the part holding the instance at the given index, and the index of the instance in that part.
+    pub fn part_of(&self, index: Index) -> (usize, Index) {
+        let part = self.starts.partition_point(|&start| start <= index) - 1;
+        (part, index - self.starts[part])
+    }
+
+    /// Returns the merged metadata of the parts, if any was attached.
+    pub fn metadata(&self) -> Option<&[String]> {
+        self.metadata.as_deref()
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for ConcatDataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        *self.starts.last().unwrap()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.parts.iter().map(|part| part.dimensionality()).max().unwrap()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<T> {
+        let (part, index) = self.part_of(index);
+        self.parts[part].instance(index)
+    }
+
+    fn instance_size(&self) -> usize {
+        self.parts.iter().map(|part| part.instance_size()).max().unwrap()
+    }
+
+    /// Distances between instances of the same part are computed, and cached, by that part.
+    fn distance(&self, left: Index, right: Index) -> U {
+        let ((left_part, left), (right_part, right)) = (self.part_of(left), self.part_of(right));
+        if left_part == right_part {
+            self.parts[left_part].distance(left, right)
+        } else {
+            self.metric.distance(
+                &self.parts[left_part].instance(left),
+                &self.parts[right_part].instance(right),
+            )
+        }
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        let (left_part, left) = self.part_of(left);
+        Array1::from_vec(
+            right
+                .par_iter()
+                .map(|&r| match self.part_of(r) {
+                    (part, r) if part == left_part => self.parts[part].distance(left, r),
+                    (part, r) => self.metric.distance(&instance, &self.parts[part].instance(r)),
+                })
+                .collect(),
+        )
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+
+    /// Returns the metadata attached with `with_metadata`, as a string, if any, and otherwise that of the part.
+    fn instance_metadata(&self, index: Index) -> Option<Value> {
+        match &self.metadata {
+            Some(metadata) => Some(Value::String(metadata[index].clone())),
+            None => {
+                let (part, index) = self.part_of(index);
+                self.parts[part].instance_metadata(index)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::Dataset;
+    use crate::dataset::RowMajor;
+    use crate::metric_from_name;
+    use crate::Cakes;
+
+    use super::ConcatDataset;
+    use super::FilteredDataset;
+    use super::PermutedDataset;
+
+    #[test]
+    fn test_concat_dataset() {
+        let months: Vec<Vec<Vec<f64>>> = (0..3)
+            .map(|m| {
+                (0..(10 + m * 5))
+                    .map(|i| vec![(m * 100 + i) as f64, (i % 3) as f64])
+                    .collect()
+            })
+            .collect();
+        let parts: Vec<Arc<dyn Dataset<f64, f64>>> = months
+            .iter()
+            .map(|month| {
+                let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(
+                    Arc::new(month.clone()),
+                    metric_from_name("euclidean").unwrap(),
+                    true,
+                ));
+                dataset
+            })
+            .collect();
+        let all: Vec<_> = months.iter().flatten().cloned().collect();
+        let flat = RowMajor::<f64, f64>::new(Arc::new(all.clone()), metric_from_name("euclidean").unwrap(), false);
+
+        let metadata: Vec<Vec<String>> = months
+            .iter()
+            .enumerate()
+            .map(|(m, month)| (0..month.len()).map(|i| format!("{}-{}", m, i)).collect())
+            .collect();
+        let concat = ConcatDataset::new(parts.clone())
+            .unwrap()
+            .with_metadata(metadata.clone())
+            .unwrap();
+        assert_eq!(concat.cardinality(), 45);
+        assert_eq!((concat.offset(1), concat.offset(2)), (10, 25));
+        assert_eq!(concat.part_of(24), (1, 14));
+        assert_eq!(concat.metadata().unwrap()[25], "2-0");
+        for i in [0, 9, 10, 30, 44] {
+            assert_eq!(concat.instance(i), all[i]);
+        }
+        let indices = concat.indices();
+        assert_eq!(
+            concat.distances_among(&[0, 12, 40], &indices),
+            flat.distances_among(&[0, 12, 40], &indices)
+        );
+
+        // Appending a part keeps the indices of the existing instances.
+        let mut more = parts.clone();
+        more.push(Arc::clone(&parts[0]));
+        let appended = ConcatDataset::new(more).unwrap();
+        assert_eq!(appended.cardinality(), 55);
+        assert_eq!(appended.instance(30), concat.instance(30));
+        let cakes = Cakes::build(Arc::new(appended).as_arc_dataset(), None, None);
+        assert_eq!(cakes.rnn_indices(&all[3], Some(0.)).len(), 2);
+
+        assert!(ConcatDataset::new(parts.clone())
+            .unwrap()
+            .with_metadata(metadata[1..].to_vec())
+            .is_err());
+        let hamming: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(
+            Arc::new(months[0].clone()),
+            metric_from_name("hamming").unwrap(),
+            false,
+        ));
+        assert!(ConcatDataset::new(vec![Arc::clone(&parts[0]), hamming]).is_err());
+        assert!(ConcatDataset::<f64, f64>::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_filtered_dataset() {
+        let data: Vec<_> = (0..60).map(|i| vec![i as f64, (i % 4) as f64]).collect();
+        let species: Vec<_> = (0..60).map(|i| ["cat", "dog", "owl"][i % 3]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+
+        let owls = FilteredDataset::from_metadata(Arc::clone(&dataset), &species, |&s| s == "owl").unwrap();
+        assert_eq!(owls.cardinality(), 20);
+        assert!(owls.parent_indices().iter().all(|&i| species[i] == "owl"));
+        let owls: Arc<dyn Dataset<f64, f64>> = Arc::new(owls);
+        let cakes = Cakes::build(Arc::clone(&owls), None, None);
+        let hits = cakes.rnn_indices(&dataset.instance(2), Some(3.5));
+        let mut hits: Vec<_> = hits.into_iter().map(|i| owls.instance(i)[0] as usize).collect();
+        hits.sort_unstable();
+        assert_eq!(hits, vec![2, 5]);
+
+        let early: Vec<_> = (0..60).map(|i| i < 15).collect();
+        let early = FilteredDataset::from_mask(Arc::clone(&dataset), &early).unwrap();
+        assert_eq!(early.parent_indices(), (0..15).collect::<Vec<_>>());
+        assert!(FilteredDataset::from_mask(Arc::clone(&dataset), &[true; 10]).is_err());
+        assert!(FilteredDataset::from_metadata(dataset, &species[1..], |_| true).is_err());
+    }
+
+    #[test]
+    fn test_permuted_dataset() {
+        let data: Vec<_> = (0..100).map(|i| vec![(i * 37 % 100) as f64, (i % 9) as f64]).collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(Arc::clone(&dataset), Some(5), None);
+
+        let permuted = PermutedDataset::depth_first(&cakes.root);
+        let mut sorted = permuted.permutation().to_vec();
+        sorted.sort_unstable();
+        assert_eq!(sorted, dataset.indices());
+        for i in [0, 17, 99] {
+            assert_eq!(permuted.instance(i), dataset.instance(permuted.original_index(i)));
+            assert_eq!(permuted.permuted_index(permuted.original_index(i)), i);
+        }
+
+        // The instances of each leaf are contiguous.
+        let mut leaves = cakes.root.flatten_tree();
+        leaves.retain(|cluster| cluster.children.read().unwrap().is_none());
+        for leaf in leaves {
+            let mut positions: Vec<_> = leaf.indices.iter().map(|&i| permuted.permuted_index(i)).collect();
+            positions.sort_unstable();
+            assert_eq!(positions.last().unwrap() - positions[0] + 1, positions.len());
+        }
+
+        // Searches over the permuted dataset report the same hits in terms of the original indices.
+        let permuted_cakes = Cakes::build(Arc::new(permuted.clone()).as_arc_dataset(), Some(5), None);
+        let query = dataset.instance(42);
+        let mut expected = cakes.rnn(&query, Some(20.));
+        let mut actual = permuted.original_hits(&permuted_cakes.rnn(&query, Some(20.)));
+        expected.sort_by_key(|&(i, _)| i);
+        actual.sort_by_key(|&(i, _)| i);
+        assert_eq!(actual, expected);
+
+        assert!(PermutedDataset::new(Arc::clone(&dataset), vec![0; 100]).is_err());
+        assert!(PermutedDataset::new(Arc::clone(&dataset), (1..=100).collect()).is_err());
+        assert!(PermutedDataset::new(dataset, (0..99).collect()).is_err());
+    }
+}
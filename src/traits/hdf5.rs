@@ -0,0 +1,369 @@
+//! A `Dataset` over a 2-dimensional dataset in an HDF5 file, e.g. the `train` set of an ANN-benchmarks file, behind
+//! the `hdf5` feature.
+//!
+//! The files are read with the pure-Rust `hdf5-pure` crate, so the HDF5 C library is not needed.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+use crate::dataset::LoadedShards;
+use crate::dataset::Shard;
+use crate::prelude::*;
+use crate::utils::lock_cache;
+
+/// The number of bytes of instances in a block of a contiguous, i.e. unchunked, HDF5 dataset.
+const CONTIGUOUS_BLOCK_BYTES: usize = 1 << 20;
+
+/// Hdf5Dataset serves the rows of a 2-dimensional HDF5 dataset of `T`, reading blocks of consecutive rows only when
+/// they are needed.
+///
+/// The blocks of a chunked dataset are its chunks along the rows, so each block is decompressed from whole chunks
+/// and no chunk is read for more than one block. Contiguous datasets are read in blocks of about a MiB.
+/// At most `max_loaded_blocks` blocks are kept in memory, and the least recently used block is dropped to make room
+/// for another. As with `ShardedDataset`, distances are computed one block at a time, so tree-building and search
+/// read each block they need once per batch of distances, rather than once per instance.
+///
+/// The file must not change while it is served.
+///
+/// # Panics
+///
+/// Since the `Dataset` trait cannot return errors, failing to read a block panics. `try_instance` returns these
+/// failures as an Err instead.
+pub struct Hdf5Dataset<T: Number, U: Number> {
+    path: PathBuf,
+    name: String,
+    dataset: hdf5_pure::Dataset,
+    cardinality: usize,
+    dimensionality: usize,
+    block_size: usize,
+    metric: Arc<dyn Metric<T, U>>,
+    max_loaded_blocks: usize,
+    loaded: Mutex<LoadedShards<T>>,
+    block_reads: AtomicU64,
+}
+
+impl<T: Number, U: Number> std::fmt::Debug for Hdf5Dataset<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("Hdf5Dataset")
+            .field("path", &self.path)
+            .field("name", &self.name)
+            .field("data-cardinality", &self.cardinality)
+            .field("data-dimensionality", &self.dimensionality)
+            .field("block-size", &self.block_size)
+            .finish()
+    }
+}
+
+impl<T: 'static + Number, U: 'static + Number> Hdf5Dataset<T, U> {
+    /// Serves the HDF5 dataset with the given name, or path within the file, e.g. "train" or "vectors/base", which
+    /// must be a 2-dimensional array of `T`. Only the metadata of the dataset is read.
+    pub fn open(
+        path: &Path,
+        name: &str,
+        max_loaded_blocks: usize,
+        metric: Arc<dyn Metric<T, U>>,
+    ) -> Result<Self, String> {
+        let fail = |error: String| format!("Error: Failed to read '{}' in {}. {}", name, path.display(), error);
+        let file = hdf5_pure::File::open(path).map_err(|error| fail(error.to_string()))?;
+        let dataset = file.dataset(name).map_err(|error| fail(error.to_string()))?;
+
+        let dtype = dataset.dtype().map_err(|error| fail(error.to_string()))?;
+        if dtype.to_string() != T::type_name() {
+            return Err(fail(format!(
+                "The dataset holds {} but {} was requested.",
+                dtype,
+                T::type_name()
+            )));
+        }
+        let shape = dataset.shape().map_err(|error| fail(error.to_string()))?;
+        let (cardinality, dimensionality) = match shape[..] {
+            [cardinality, dimensionality] => (cardinality as usize, dimensionality as usize),
+            _ => {
+                return Err(fail(format!(
+                    "The dataset must be 2-dimensional but has shape {:?}.",
+                    shape
+                )))
+            }
+        };
+        if dimensionality == 0 {
+            return Err(fail("The instances must have at least one feature.".to_string()));
+        }
+
+        let block_size = match dataset.chunk_shape().map_err(|error| fail(error.to_string()))? {
+            Some(chunk) => chunk[0] as usize,
+            None => CONTIGUOUS_BLOCK_BYTES / (dimensionality * T::num_bytes() as usize),
+        };
+
+        Ok(Hdf5Dataset {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+            dataset,
+            cardinality,
+            dimensionality,
+            block_size: std::cmp::max(block_size, 1),
+            metric,
+            max_loaded_blocks: std::cmp::max(max_loaded_blocks, 1),
+            loaded: Mutex::new(LoadedShards::new()),
+            block_reads: AtomicU64::new(0),
+        })
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<T, U>> {
+        self
+    }
+}
+
+impl<T: Number, U: Number> Hdf5Dataset<T, U> {
+    /// Returns the number of rows in each block, i.e. the number of rows in each chunk of a chunked dataset.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.cardinality.div_ceil(self.block_size)
+    }
+
+    /// Returns the block holding the instance at the given index, and the position of the instance in the block.
+    pub fn block_of(&self, index: Index) -> (usize, usize) {
+        (index / self.block_size, index % self.block_size)
+    }
+
+    /// Returns the blocks that are currently in memory, in sorted order.
+    pub fn loaded_blocks(&self) -> Vec<usize> {
+        let mut blocks: Vec<_> = lock_cache(&self.loaded).shards.keys().copied().collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Returns the number of times a block has been read from the file.
+    pub fn block_reads(&self) -> u64 {
+        self.block_reads.load(Ordering::Relaxed)
+    }
+
+    /// Returns the instance at the provided index, reading its block if it is not in memory.
+    ///
+    /// Returns an Err if the index is out of range or if the block cannot be read.
+    pub fn try_instance(&self, i: Index) -> Result<Vec<T>, String> {
+        if i >= self.cardinality {
+            return Err(format!(
+                "Index {} is out of range for a dataset of {} instances.",
+                i, self.cardinality
+            ));
+        }
+        let (block, position) = self.block_of(i);
+        Ok(self.try_block(block)?[position].clone())
+    }
+
+    /// Returns the instances of the block, reading it from the file if it is not in memory.
+    fn block(&self, block: usize) -> Shard<T> {
+        self.try_block(block).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Returns the instances of the block, reading it from the file if it is not in memory, or an Err if it cannot
+    /// be read.
+    fn try_block(&self, block: usize) -> Result<Shard<T>, String> {
+        if let Some(instances) = lock_cache(&self.loaded).get(block) {
+            return Ok(instances);
+        }
+
+        // The block is read without holding the lock, so that other blocks may be served meanwhile.
+        let start = block * self.block_size;
+        let cardinality = std::cmp::min(self.block_size, self.cardinality - start);
+        let fail = |error: String| {
+            format!(
+                "Error: Failed to read block {} of '{}' in {}. {}",
+                block,
+                self.name,
+                self.path.display(),
+                error
+            )
+        };
+        let values = read_rows::<T>(&self.dataset, start as u64, cardinality as u64).map_err(fail)?;
+        if values.len() != cardinality * self.dimensionality {
+            return Err(fail(format!(
+                "Expected {} values but read {}.",
+                cardinality * self.dimensionality,
+                values.len()
+            )));
+        }
+        let instances: Vec<_> = values.chunks(self.dimensionality).map(|row| row.to_vec()).collect();
+        let instances = Arc::new(instances);
+        self.block_reads.fetch_add(1, Ordering::Relaxed);
+
+        lock_cache(&self.loaded).insert(block, Arc::clone(&instances), self.max_loaded_blocks);
+        Ok(instances)
+    }
+}
+
+/// Reads the values of `count` rows of the dataset, starting at the given row, whose type must be `T`.
+fn read_rows<T: Number>(dataset: &hdf5_pure::Dataset, start: u64, count: u64) -> Result<Vec<T>, String> {
+    fn cast<T: Number, V: Number>(values: Result<Vec<V>, hdf5_pure::Error>) -> Result<Vec<T>, String> {
+        values
+            .map(|values| values.into_iter().map(|v| T::from(v).unwrap()).collect())
+            .map_err(|error| error.to_string())
+    }
+    match T::type_name() {
+        "f32" => cast(dataset.read_f32_rows(start, count)),
+        "f64" => cast(dataset.read_f64_rows(start, count)),
+        "u8" => cast(dataset.read_u8_rows(start, count)),
+        "i8" => cast(dataset.read_i8_rows(start, count)),
+        "u16" => cast(dataset.read_u16_rows(start, count)),
+        "i16" => cast(dataset.read_i16_rows(start, count)),
+        "u32" => cast(dataset.read_u32_rows(start, count)),
+        "i32" => cast(dataset.read_i32_rows(start, count)),
+        "u64" => cast(dataset.read_u64_rows(start, count)),
+        "i64" => cast(dataset.read_i64_rows(start, count)),
+        name => Err(format!("Cannot read {} from HDF5.", name)),
+    }
+}
+
+impl<T: Number, U: Number> Dataset<T, U> for Hdf5Dataset<T, U> {
+    fn metric(&self) -> Arc<dyn Metric<T, U>> {
+        Arc::clone(&self.metric)
+    }
+
+    fn cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.dimensionality
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.cardinality).collect()
+    }
+
+    /// Returns the instance at the provided index, reading its block if it is not in memory.
+    fn instance(&self, i: Index) -> Vec<T> {
+        let (block, position) = self.block_of(i);
+        self.block(block)[position].clone()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        if left == right {
+            U::zero()
+        } else {
+            self.metric.distance(&self.instance(left), &self.instance(right))
+        }
+    }
+
+    /// Computes the distances one block at a time, so that each block is needed only once.
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        let instance = self.instance(left);
+        let mut order: Vec<_> = (0..right.len()).collect();
+        order.sort_by_key(|&p| right[p]);
+
+        let mut distances = vec![U::zero(); right.len()];
+        for run in order.chunk_by(|&a, &b| self.block_of(right[a]).0 == self.block_of(right[b]).0) {
+            let instances = self.block(self.block_of(right[run[0]]).0);
+            let run_distances: Vec<_> = run
+                .par_iter()
+                .map(|&p| {
+                    if right[p] == left {
+                        U::zero()
+                    } else {
+                        self.metric.distance(&instance, &instances[self.block_of(right[p]).1])
+                    }
+                })
+                .collect();
+            for (&p, distance) in run.iter().zip(run_distances) {
+                distances[p] = distance;
+            }
+        }
+        Array1::from_vec(distances)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        let distances: Array1<U> = left
+            .iter()
+            .flat_map(|&l| self.distances_from(l, right).to_vec())
+            .collect();
+        distances.into_shape((left.len(), right.len())).unwrap()
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.distances_among(indices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::Hdf5Dataset;
+
+    #[test]
+    fn test_hdf5_dataset() {
+        let data: Vec<Vec<f32>> = (0..300)
+            .map(|i| (0..3).map(|j| ((i * (j + 3)) % 17) as f32 + 0.5 * j as f32).collect())
+            .collect();
+        let flat: Vec<f32> = data.iter().flatten().copied().collect();
+        let path = std::env::temp_dir().join(format!("clam-test-hdf5-{}.h5", std::process::id()));
+        let mut builder = hdf5_pure::FileBuilder::new();
+        builder
+            .create_dataset("train")
+            .with_f32_data(&flat)
+            .with_shape(&[300, 3])
+            .with_chunks(&[64, 3])
+            .with_deflate(4);
+        builder.create_dataset("flat").with_f32_data(&flat).with_shape(&[900]);
+        builder
+            .create_dataset("contiguous")
+            .with_f32_data(&flat)
+            .with_shape(&[300, 3]);
+        builder.write(&path).unwrap();
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = Arc::new(Hdf5Dataset::<f32, f32>::open(&path, "train", 2, Arc::clone(&metric)).unwrap());
+        assert_eq!((dataset.cardinality(), dataset.dimensionality()), (300, 3));
+        // The blocks are the chunks of the dataset.
+        assert_eq!((dataset.block_size(), dataset.num_blocks()), (64, 5));
+        assert!(dataset.loaded_blocks().is_empty());
+        for i in (0..300).step_by(7) {
+            assert_eq!(dataset.instance(i), data[i]);
+        }
+        assert_eq!(dataset.loaded_blocks().len(), 2);
+        assert_eq!(dataset.try_instance(299).unwrap(), data[299]);
+        assert!(dataset.try_instance(300).is_err());
+
+        // Each batch of distances reads each block at most once.
+        let reads = dataset.block_reads();
+        let distances = dataset.distances_from(3, &(0..300).rev().collect::<Vec<_>>());
+        assert!(dataset.block_reads() - reads <= 5);
+        for (i, &distance) in (0..300).rev().zip(distances.iter()) {
+            assert_eq!(distance, metric.distance(&data[3], &data[i]));
+        }
+
+        let cakes = Cakes::build(Arc::clone(&dataset).as_arc_dataset(), Some(6), None);
+        assert!(dataset.loaded_blocks().len() <= 2);
+        for query in data.iter().step_by(31) {
+            let mut hits = cakes.rnn_indices(query, Some(3.));
+            let mut expected = cakes.linear_search_indices(query, Some(3.), None);
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+        }
+
+        let contiguous = Hdf5Dataset::<f32, f32>::open(&path, "contiguous", 2, Arc::clone(&metric)).unwrap();
+        assert_eq!(contiguous.num_blocks(), 1);
+        assert_eq!(contiguous.instance(150), data[150]);
+
+        // The dataset must exist, be 2-dimensional and hold the requested type.
+        assert!(Hdf5Dataset::<f32, f32>::open(&path, "missing", 2, Arc::clone(&metric)).is_err());
+        assert!(Hdf5Dataset::<f32, f32>::open(&path, "flat", 2, Arc::clone(&metric)).is_err());
+        let euclidean = metric_from_name("euclidean").unwrap();
+        assert!(Hdf5Dataset::<f64, f64>::open(&path, "train", 2, euclidean).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
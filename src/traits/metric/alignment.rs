@@ -0,0 +1,681 @@
+//! Metrics that align sequences: `Levenshtein`, `WeightedLevenshtein`, `NeedlemanWunsch` and `SmithWaterman`.
+
+use std::collections::HashMap;
+
+use ndarray::prelude::*;
+
+use super::Metric;
+use crate::Number;
+
+/// Implements Levenshtein distance, the least number of substitutions, insertions and deletions that turn one
+/// sequence into the other. The sequences may have different lengths.
+pub struct Levenshtein;
+
+impl<T: Number, U: Number> Metric<T, U> for Levenshtein {
+    fn name(&self) -> String {
+        "levenshtein".to_string()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        U::saturating_from_f64(edits(x, y, false) as f64)
+    }
+}
+
+/// Returns the least number of substitutions, insertions and deletions that turn `x` into `y`, or into any substring
+/// of `y` if `within` is true.
+pub(super) fn edits<T: PartialEq>(x: &[T], y: &[T], within: bool) -> usize {
+    // Only the previous row of the table of edits is kept. A match within `y` may start anywhere, at no cost.
+    let mut previous: Vec<_> = if within {
+        vec![0; y.len() + 1]
+    } else {
+        (0..=y.len()).collect()
+    };
+    let mut current = vec![0; y.len() + 1];
+    for (i, a) in x.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b) in y.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    if within {
+        // A match within `y` may also end anywhere.
+        previous.into_iter().min().unwrap()
+    } else {
+        previous[y.len()]
+    }
+}
+
+/// Implements Levenshtein distance with a cost for substituting each character of an alphabet with each other, and a
+/// cost for inserting and for deleting each character, e.g. to make transitions cheaper than transversions among
+/// nucleotides, or confusable glyphs cheaper to substitute when correcting OCR. The distance is the least total cost of
+/// the edits that turn one sequence into the other.
+///
+/// Characters outside the alphabet cost 1 to substitute, insert or delete, as in `Levenshtein`.
+///
+/// The distance is a metric, so that searches with it are exact, if the costs are: if substituting `a` with `b` costs
+/// the same as `b` with `a`, inserting a character costs the same as deleting it, and no edit costs more than a detour
+/// through other characters, those outside the alphabet, or the gaps of insertions and deletions. `new` checks this, as
+/// given by `obeys_triangle_inequality`, and whether the costs are symmetric, as given by `is_symmetric`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedLevenshtein {
+    /// The position of each character of the alphabet, by its `raw_bits`.
+    positions: HashMap<u64, usize>,
+    /// The cost of substituting the character at position `a` with that at `b` is at `[[a, b]]`.
+    substitution: Array2<f64>,
+    insertion: Vec<f64>,
+    deletion: Vec<f64>,
+    is_symmetric: bool,
+    is_metric: bool,
+}
+
+impl WeightedLevenshtein {
+    /// Returns the distance for the costs of edits among the characters of the alphabet, given in its order.
+    ///
+    /// Returns an Err if the alphabet repeats a character, if the matrix is not square or the costs of insertions and
+    /// deletions are not one per character, or if a cost is negative, not finite, or, for substituting a character
+    /// with itself, not zero.
+    pub fn new<T: Number>(
+        alphabet: &[T],
+        substitution: Array2<f64>,
+        insertion: Vec<f64>,
+        deletion: Vec<f64>,
+    ) -> Result<Self, String> {
+        let k = alphabet.len();
+        let mut positions = HashMap::new();
+        for (i, character) in alphabet.iter().enumerate() {
+            if positions.insert(character.raw_bits(), i).is_some() {
+                return Err(format!("The alphabet has {} more than once.", character));
+            }
+        }
+        if substitution.shape() != [k, k] || insertion.len() != k || deletion.len() != k {
+            return Err(format!(
+                "An alphabet of {} characters needs a {} by {} matrix of substitutions and {} costs of insertions and \
+                 deletions, not a matrix of shape {:?}, {} and {}.",
+                k,
+                k,
+                k,
+                k,
+                substitution.shape(),
+                insertion.len(),
+                deletion.len()
+            ));
+        }
+        let mut costs = substitution.iter().chain(insertion.iter()).chain(deletion.iter());
+        if costs.any(|cost| !(cost.is_finite() && *cost >= 0.)) {
+            return Err("The costs of edits must be non-negative and finite.".to_string());
+        }
+        if let Some(i) = (0..k).find(|&i| substitution[[i, i]] != 0.) {
+            return Err(format!("Substituting {} with itself must cost 0.", alphabet[i]));
+        }
+
+        // The costs are those of a metric among the characters, the gap, which is at position `k`, and the characters
+        // outside the alphabet, at position `k + 1`, which cost 1 to edit into anything else.
+        let mut costs = Array2::ones((k + 2, k + 2));
+        costs.slice_mut(s![..k, ..k]).assign(&substitution);
+        for i in 0..k {
+            costs[[i, k]] = deletion[i];
+            costs[[k, i]] = insertion[i];
+        }
+        costs[[k, k]] = 0.;
+        costs[[k + 1, k + 1]] = 0.;
+        let nodes = 0..k + 2;
+        let is_symmetric = nodes
+            .clone()
+            .all(|a| nodes.clone().all(|b| costs[[a, b]] == costs[[b, a]]));
+        let is_metric = is_symmetric
+            && nodes.clone().all(|a| {
+                nodes
+                    .clone()
+                    .all(|b| nodes.clone().all(|c| costs[[a, c]] <= costs[[a, b]] + costs[[b, c]]))
+            });
+
+        Ok(WeightedLevenshtein {
+            positions,
+            substitution,
+            insertion,
+            deletion,
+            is_symmetric,
+            is_metric,
+        })
+    }
+
+    /// Returns the least total cost of the edits that turn `x` into `y`.
+    pub fn cost<T: Number>(&self, x: &[T], y: &[T]) -> f64 {
+        let position = |character: &T| self.positions.get(&character.raw_bits()).copied();
+        let (xs, ys): (Vec<_>, Vec<_>) = (x.iter().map(position).collect(), y.iter().map(position).collect());
+        let insertion = |b: Option<usize>| b.map_or(1., |b| self.insertion[b]);
+        let deletion = |a: Option<usize>| a.map_or(1., |a| self.deletion[a]);
+        let substitution = |a: Option<usize>, b: Option<usize>| match (a, b) {
+            (Some(a), Some(b)) => self.substitution[[a, b]],
+            _ => 1.,
+        };
+
+        // Only the previous row of the table of costs is kept.
+        let mut previous = vec![0.; y.len() + 1];
+        for (j, &b) in ys.iter().enumerate() {
+            previous[j + 1] = previous[j] + insertion(b);
+        }
+        let mut current = vec![0.; y.len() + 1];
+        for (i, &a) in xs.iter().enumerate() {
+            current[0] = previous[0] + deletion(a);
+            for (j, &b) in ys.iter().enumerate() {
+                let step = if x[i] == y[j] { 0. } else { substitution(a, b) };
+                current[j + 1] = (previous[j] + step)
+                    .min(previous[j + 1] + deletion(a))
+                    .min(current[j] + insertion(b));
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        previous[y.len()]
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for WeightedLevenshtein {
+    fn name(&self) -> String {
+        "weighted-levenshtein".to_string()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        U::saturating_from_f64(self.cost(x, y))
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.is_metric
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.is_symmetric
+    }
+}
+
+/// Implements the cost of an optimal global alignment of two sequences, found by the Needleman-Wunsch algorithm,
+/// for the given scores of a match, a mismatch and a gap.
+///
+/// An alignment of `x` and `y` with `M` matches, `S` mismatches and `G` gaps has the score `M match + S mismatch +
+/// G gap`, and the distance is `match (|x| + |y|) / 2` less the best score, i.e. `S (match - mismatch) + G (match / 2
+/// - gap)`. Every sequence is then at distance zero from itself, and the distance is a metric if a mismatch scores
+/// less than a match and a gap less than half a match, which `new` requires. With integer distance types the distance
+/// is rounded down, so costs that are not integers need a floating-point `U`.
+///
+/// `encode` and `decode` emit and apply the edits of an optimal alignment, so that a `CompressibleDataset` of
+/// sequences stores each one as its alignment to the center of its cluster.
+pub struct NeedlemanWunsch {
+    /// The cost of aligning two different elements.
+    mismatch: f64,
+    /// The cost of aligning an element with a gap.
+    gap: f64,
+}
+
+/// One step of an alignment of a reference to a target, as given by `NeedlemanWunsch::alignment`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit<T> {
+    /// Copies the given number of elements of the reference.
+    Keep(usize),
+    /// Replaces the next element of the reference with the given one.
+    Substitute(T),
+    /// Inserts the given element.
+    Insert(T),
+    /// Skips the given number of elements of the reference.
+    Delete(usize),
+}
+
+impl Default for NeedlemanWunsch {
+    /// Scores a match as 0 and a mismatch or a gap as -1, so that the distance is the Levenshtein distance.
+    fn default() -> Self {
+        NeedlemanWunsch { mismatch: 1., gap: 1. }
+    }
+}
+
+impl NeedlemanWunsch {
+    /// Returns the distance for the given scores, or an Err unless a mismatch scores less than a match and a gap less
+    /// than half a match. E.g. `new(1., -1., -2.)` is a common choice for DNA.
+    pub fn new(matching: f64, mismatch: f64, gap: f64) -> Result<Self, String> {
+        let (mismatch, gap) = (matching - mismatch, matching / 2. - gap);
+        if mismatch.is_finite() && gap.is_finite() && mismatch > 0. && gap > 0. {
+            Ok(NeedlemanWunsch { mismatch, gap })
+        } else {
+            Err("A mismatch must score less than a match, and a gap less than half a match.".to_string())
+        }
+    }
+
+    /// Returns the edits of an optimal alignment that turn the reference into the target. Among optimal alignments, a
+    /// match or substitution is preferred over a deletion and a deletion over an insertion.
+    pub fn alignment<T: Number>(&self, reference: &[T], target: &[T]) -> Vec<Edit<T>> {
+        let (n, m) = (reference.len(), target.len());
+        let mut costs = vec![0.; (n + 1) * (m + 1)];
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        for i in 0..=n {
+            for j in 0..=m {
+                costs[at(i, j)] = match (i, j) {
+                    (0, _) => j as f64 * self.gap,
+                    (_, 0) => i as f64 * self.gap,
+                    _ => {
+                        let step = self.step(reference[i - 1], target[j - 1]);
+                        (costs[at(i - 1, j - 1)] + step)
+                            .min(costs[at(i - 1, j)] + self.gap)
+                            .min(costs[at(i, j - 1)] + self.gap)
+                    }
+                };
+            }
+        }
+
+        // Trace back from the end of both sequences, and then reverse the edits.
+        let (mut i, mut j) = (n, m);
+        let mut edits = vec![];
+        let push = |edits: &mut Vec<Edit<T>>, edit: Edit<T>| match (edits.last_mut(), edit) {
+            (Some(Edit::Keep(k)), Edit::Keep(1)) | (Some(Edit::Delete(k)), Edit::Delete(1)) => *k += 1,
+            _ => edits.push(edit),
+        };
+        while i > 0 || j > 0 {
+            let cost = costs[at(i, j)];
+            if i > 0 && j > 0 && cost == costs[at(i - 1, j - 1)] + self.step(reference[i - 1], target[j - 1]) {
+                let edit = if reference[i - 1] == target[j - 1] {
+                    Edit::Keep(1)
+                } else {
+                    Edit::Substitute(target[j - 1])
+                };
+                push(&mut edits, edit);
+                (i, j) = (i - 1, j - 1);
+            } else if i > 0 && cost == costs[at(i - 1, j)] + self.gap {
+                push(&mut edits, Edit::Delete(1));
+                i -= 1;
+            } else {
+                push(&mut edits, Edit::Insert(target[j - 1]));
+                j -= 1;
+            }
+        }
+        edits.reverse();
+        edits
+    }
+
+    /// Returns the cost of aligning two elements.
+    fn step<T: Number>(&self, a: T, b: T) -> f64 {
+        if a == b {
+            0.
+        } else {
+            self.mismatch
+        }
+    }
+
+    /// Returns the cost of an optimal alignment, keeping only one row of the table at a time.
+    fn cost<T: Number>(&self, x: &[T], y: &[T]) -> f64 {
+        let mut previous: Vec<_> = (0..=y.len()).map(|j| j as f64 * self.gap).collect();
+        let mut current = vec![0.; y.len() + 1];
+        for (i, &a) in x.iter().enumerate() {
+            current[0] = (i + 1) as f64 * self.gap;
+            for (j, &b) in y.iter().enumerate() {
+                current[j + 1] = (previous[j] + self.step(a, b))
+                    .min(previous[j + 1] + self.gap)
+                    .min(current[j] + self.gap);
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        previous[y.len()]
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for NeedlemanWunsch {
+    fn name(&self) -> String {
+        "needleman-wunsch".to_string()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        U::saturating_from_f64(self.cost(x, y))
+    }
+
+    /// Writes each edit of `alignment` as a tag byte followed by its count, as a `u64`, or by its element.
+    fn encode(&self, reference: &[T], target: &[T]) -> Result<Vec<u8>, String> {
+        let mut encoding = vec![];
+        for edit in self.alignment(reference, target) {
+            match edit {
+                Edit::Keep(count) => {
+                    encoding.push(0);
+                    encoding.extend_from_slice(&(count as u64).to_be_bytes());
+                }
+                Edit::Substitute(value) => {
+                    encoding.push(1);
+                    encoding.append(&mut value.to_bytes());
+                }
+                Edit::Insert(value) => {
+                    encoding.push(2);
+                    encoding.append(&mut value.to_bytes());
+                }
+                Edit::Delete(count) => {
+                    encoding.push(3);
+                    encoding.extend_from_slice(&(count as u64).to_be_bytes());
+                }
+            }
+        }
+        Ok(encoding)
+    }
+
+    fn decode(&self, reference: &[T], encoding: &[u8]) -> Result<Vec<T>, String> {
+        let width = T::num_bytes() as usize;
+        let truncated = || "A needleman-wunsch encoding is truncated.".to_string();
+        let take = |position: &mut usize, length: usize| {
+            let bytes = encoding.get(*position..*position + length).ok_or_else(truncated)?;
+            *position += length;
+            Ok::<_, String>(bytes)
+        };
+        let count = |bytes: &[u8]| {
+            let mut count = [0; 8];
+            count.copy_from_slice(bytes);
+            usize::try_from(u64::from_be_bytes(count)).map_err(|_| "A needleman-wunsch count is too large.".to_string())
+        };
+
+        let (mut position, mut next) = (0, 0_usize);
+        let mut decoded = Vec::with_capacity(reference.len());
+        while position < encoding.len() {
+            let tag = take(&mut position, 1)?[0];
+            match tag {
+                0 | 3 => {
+                    let count = count(take(&mut position, 8)?)?;
+                    let Some(end) = next.checked_add(count).filter(|&end| end <= reference.len()) else {
+                        return Err(format!(
+                            "A needleman-wunsch encoding refers to element {} of a reference with only {} elements.",
+                            next.saturating_add(count) - 1,
+                            reference.len()
+                        ));
+                    };
+                    if tag == 0 {
+                        decoded.extend_from_slice(&reference[next..end]);
+                    }
+                    next = end;
+                }
+                1 | 2 => {
+                    decoded.push(T::from_bytes(take(&mut position, width)?));
+                    if tag == 1 {
+                        if next == reference.len() {
+                            return Err(
+                                "A needleman-wunsch encoding substitutes past the end of the reference.".to_string()
+                            );
+                        }
+                        next += 1;
+                    }
+                }
+                _ => return Err(format!("Unknown needleman-wunsch edit {}.", tag)),
+            }
+        }
+        if next != reference.len() {
+            return Err(format!(
+                "A needleman-wunsch encoding leaves {} elements of the reference unaligned.",
+                reference.len() - next
+            ));
+        }
+        Ok(decoded)
+    }
+}
+
+/// Implements a distance from the score of an optimal local alignment of two sequences, found by the Smith-Waterman
+/// algorithm, for the given scores of a match and a mismatch and affine gap penalties.
+///
+/// A gap of `n` elements scores `gap_open + (n - 1) gap_extend`, so one long gap is preferred over several short
+/// ones. With `S` the best local score, the distance is `(S(x, x) + S(y, y)) / 2 - S(x, y)`, which is zero exactly for
+/// identical sequences and grows as the best local match of the two covers less of them. With integer distance types
+/// the distance is rounded down.
+///
+/// Warning: The distance does not obey the triangle inequality in general, so searches with it may miss some hits.
+pub struct SmithWaterman {
+    matching: f64,
+    mismatch: f64,
+    gap_open: f64,
+    gap_extend: f64,
+}
+
+impl SmithWaterman {
+    /// Returns the distance for the given scores, or an Err unless a match scores more than zero, a mismatch less,
+    /// and opening a gap at most as much as extending one, which scores less than zero. E.g.
+    /// `new(2., -1., -4., -1.)` suits DNA.
+    pub fn new(matching: f64, mismatch: f64, gap_open: f64, gap_extend: f64) -> Result<Self, String> {
+        let finite = [matching, mismatch, gap_open, gap_extend]
+            .iter()
+            .all(|score| score.is_finite());
+        if finite && matching > 0. && mismatch < 0. && gap_open <= gap_extend && gap_extend < 0. {
+            Ok(SmithWaterman {
+                matching,
+                mismatch,
+                gap_open,
+                gap_extend,
+            })
+        } else {
+            Err(
+                "A match must score more than zero, a mismatch less, and opening a gap at most as much as extending \
+                 one, which must score less than zero."
+                    .to_string(),
+            )
+        }
+    }
+
+    /// Returns the score of an optimal local alignment of the sequences, by Gotoh's algorithm for affine gaps,
+    /// keeping only one row of each table at a time.
+    pub fn score<T: Number>(&self, x: &[T], y: &[T]) -> f64 {
+        // The best scores of alignments ending at the current cell, and of those ending with a gap in `x` or in `y`.
+        let mut previous = vec![0.; y.len() + 1];
+        let mut current = vec![0.; y.len() + 1];
+        let mut gap_in_x = vec![f64::NEG_INFINITY; y.len() + 1];
+        let mut best = 0_f64;
+        for &a in x.iter() {
+            let mut gap_in_y = f64::NEG_INFINITY;
+            for (j, &b) in y.iter().enumerate() {
+                gap_in_x[j + 1] = (previous[j + 1] + self.gap_open).max(gap_in_x[j + 1] + self.gap_extend);
+                gap_in_y = (current[j] + self.gap_open).max(gap_in_y + self.gap_extend);
+                let step = if a == b { self.matching } else { self.mismatch };
+                current[j + 1] = (previous[j] + step).max(gap_in_x[j + 1]).max(gap_in_y).max(0.);
+                best = best.max(current[j + 1]);
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        best
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for SmithWaterman {
+    fn name(&self) -> String {
+        "smith-waterman".to_string()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let distance = (self.score(x, x) + self.score(y, y)) / 2. - self.score(x, y);
+        U::saturating_from_f64(distance.max(0.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ndarray::{arr2, Array2};
+
+    use crate::dataset::RowMajor;
+    use crate::metric::metric_from_name;
+    use crate::metric::Edit;
+    use crate::metric::NeedlemanWunsch;
+    use crate::metric::SmithWaterman;
+    use crate::metric::WeightedLevenshtein;
+    use crate::Dataset;
+    use crate::Metric;
+
+    #[test]
+    fn test_edit_distances() {
+        let levenshtein = metric_from_name::<u8, u64>("levenshtein").unwrap();
+        assert_eq!(levenshtein.distance(b"kitten", b"sitting"), 3);
+        assert_eq!(levenshtein.distance(b"", b"abc"), 3);
+        assert_eq!(levenshtein.distance(b"flaw", b"lawn"), 2);
+
+        let metric = crate::metric::SemiGlobalEdit::default();
+        let distance = |query: &[u8], instance: &[u8]| {
+            crate::metric::AsymmetricMetric::<[u8], u8, u64>::distance(&metric, query, instance)
+        };
+        assert_eq!(distance(b"GATT", b"CCGATTACA"), 0);
+        assert_eq!(distance(b"GACT", b"CCGATTACA"), 1);
+        assert_eq!(distance(b"GATTTA", b"CCGATTACA"), 1);
+        assert_eq!(distance(b"ACGT", b"AC"), 2);
+    }
+
+    #[test]
+    fn test_weighted_levenshtein() {
+        // Transitions, between A and G or C and T, cost half as much as transversions.
+        let substitution = arr2(&[
+            [0., 1., 0.5, 1.],
+            [1., 0., 1., 0.5],
+            [0.5, 1., 0., 1.],
+            [1., 0.5, 1., 0.],
+        ]);
+        let metric = WeightedLevenshtein::new(b"ACGT", substitution.clone(), vec![1.; 4], vec![1.; 4]).unwrap();
+        assert!(Metric::<u8, f64>::obeys_triangle_inequality(&metric));
+        assert!(Metric::<u8, f64>::is_symmetric(&metric));
+        assert_eq!(metric.cost(b"ACGT", b"GCGT"), 0.5);
+        assert_eq!(metric.cost(b"ACGT", b"CCGT"), 1.);
+        assert_eq!(metric.cost(b"ACGT", b"ACG"), 1.);
+        assert_eq!(metric.cost(b"ACNGT", b"GCNGT"), 0.5);
+        assert_eq!(metric.cost(b"ACGT", b"ACNT"), 1.);
+
+        // With uniform costs, this is the Levenshtein distance.
+        let uniform = Array2::from_shape_fn((4, 4), |(i, j)| if i == j { 0. } else { 1. });
+        let uniform = WeightedLevenshtein::new(b"ACGT", uniform, vec![1.; 4], vec![1.; 4]).unwrap();
+        let levenshtein = metric_from_name::<u8, f64>("levenshtein").unwrap();
+        let sequences: Vec<Vec<u8>> = (0..20_usize)
+            .map(|i| (0..3 + i % 6).map(|j| b"ACGT"[(i * 5 + j * j * 3) % 4]).collect())
+            .collect();
+        for x in &sequences {
+            for y in &sequences {
+                assert_eq!(Metric::<u8, f64>::distance(&uniform, x, y), levenshtein.distance(x, y));
+            }
+        }
+
+        // Costs that are not those of a metric are allowed, but reported.
+        let mut expensive = substitution.clone();
+        expensive[[0, 1]] = 5.;
+        let metric = WeightedLevenshtein::new(b"ACGT", expensive, vec![1.; 4], vec![1.; 4]).unwrap();
+        assert!(!Metric::<u8, f64>::obeys_triangle_inequality(&metric));
+        assert!(!Metric::<u8, f64>::is_symmetric(&metric));
+        assert_eq!(metric.cost(b"A", b"C"), 2.);
+        let metric = WeightedLevenshtein::new(b"ACGT", substitution.clone(), vec![1.; 4], vec![2.; 4]).unwrap();
+        assert!(!Metric::<u8, f64>::obeys_triangle_inequality(&metric));
+        assert!(!Metric::<u8, f64>::is_symmetric(&metric));
+        assert_eq!((metric.cost(b"AC", b"A"), metric.cost(b"A", b"AC")), (2., 1.));
+
+        // Substituting through a character outside the alphabet costs 2, so no substitution may cost more.
+        let costly = Array2::from_shape_fn((4, 4), |(i, j)| if i == j { 0. } else { 3. });
+        let metric = WeightedLevenshtein::new(b"ACGT", costly, vec![2.; 4], vec![2.; 4]).unwrap();
+        assert!(Metric::<u8, f64>::is_symmetric(&metric));
+        assert!(!Metric::<u8, f64>::obeys_triangle_inequality(&metric));
+        assert!(metric.cost(b"A", b"C") > metric.cost(b"A", b"N") + metric.cost(b"N", b"C"));
+
+        assert!(WeightedLevenshtein::new(b"ACGA", substitution.clone(), vec![1.; 4], vec![1.; 4]).is_err());
+        assert!(WeightedLevenshtein::new(b"ACGT", substitution.clone(), vec![1.; 3], vec![1.; 4]).is_err());
+        assert!(WeightedLevenshtein::new(b"ACGT", substitution, vec![-1.; 4], vec![1.; 4]).is_err());
+        assert!(WeightedLevenshtein::new(b"ACGT", Array2::ones((4, 4)), vec![1.; 4], vec![1.; 4]).is_err());
+    }
+
+    #[test]
+    fn test_needleman_wunsch() {
+        let sequences: Vec<&[u8]> = vec![b"GATTACA", b"GCATGCU", b"GATACA", b"", b"ACGTACGTAC", b"GATTACA"];
+        let levenshtein = metric_from_name::<u8, u64>("levenshtein").unwrap();
+        let default = NeedlemanWunsch::default();
+        let scored = NeedlemanWunsch::new(1., -1., -2.).unwrap();
+        for x in sequences.iter() {
+            for y in sequences.iter() {
+                let distance: u64 = default.distance(x, y);
+                assert_eq!(distance, levenshtein.distance(x, y));
+
+                // The edits of an alignment turn the reference into the target, and cost the distance.
+                let edits = scored.alignment(x, y);
+                let cost: f64 = edits
+                    .iter()
+                    .map(|edit| match edit {
+                        Edit::Keep(_) => 0.,
+                        Edit::Substitute(_) => 2.,
+                        Edit::Insert(_) => 2.5,
+                        Edit::Delete(count) => 2.5 * *count as f64,
+                    })
+                    .sum();
+                assert_eq!(cost, Metric::<u8, f64>::distance(&scored, x, y));
+                let encoding = Metric::<u8, f64>::encode(&scored, x, y).unwrap();
+                assert_eq!(Metric::<u8, f64>::decode(&scored, x, &encoding).unwrap(), y.to_vec());
+            }
+        }
+        assert_eq!(
+            scored.alignment(b"GATTACA", b"GATACA"),
+            vec![Edit::Keep(2), Edit::Delete(1), Edit::Keep(4)]
+        );
+
+        let encoding = Metric::<u8, f64>::encode(&scored, b"GATTACA", b"GCATGCU").unwrap();
+        let decode = |reference: &[u8], encoding: &[u8]| Metric::<u8, f64>::decode(&scored, reference, encoding);
+        assert!(decode(b"GATTACA", &encoding[..encoding.len() - 1]).is_err());
+        assert!(decode(b"GATTAC", &encoding).is_err());
+        assert!(decode(b"GATTACAA", &encoding).is_err());
+        assert!(decode(b"GATTACA", &[7]).is_err());
+        // Counts come from the encoding, and must not overflow the position in the reference.
+        for tag in [0, 3] {
+            let oversized = [&[0, 0, 0, 0, 0, 0, 0, 0, 1][..], &[tag], &u64::MAX.to_be_bytes()].concat();
+            assert!(decode(b"GATTACA", &oversized).is_err());
+        }
+
+        assert!(NeedlemanWunsch::new(1., 1., -2.).is_err());
+        assert!(NeedlemanWunsch::new(1., -1., 0.5).is_err());
+
+        // Sequences of different lengths compress as their alignments to the centers of their clusters.
+        let data: Vec<Vec<u8>> = (0..64)
+            .map(|i| {
+                let mut sequence = b"ACGTTGCAACGTAGCT".to_vec();
+                sequence[i % 16] = b"ACGT"[i % 4];
+                sequence.truncate(16 - i % 3);
+                sequence
+            })
+            .collect();
+        let row_major = Arc::new(RowMajor::<u8, f64>::new(
+            Arc::new(data.clone()),
+            Arc::new(scored),
+            false,
+        ));
+        let cakes = crate::Cakes::build(Arc::clone(&row_major).as_arc_dataset(), None, None);
+        let codec = crate::codec::Codec::from_cakes(&row_major.as_arc_compressible_dataset(), &cakes).unwrap();
+        assert!((0..data.len()).all(|i| codec.get(i).unwrap() == data[i]));
+    }
+
+    #[test]
+    fn test_smith_waterman() {
+        let affine = SmithWaterman::new(1., -1., -3., -1.).unwrap();
+        let linear = SmithWaterman::new(1., -1., -2., -2.).unwrap();
+        assert_eq!(affine.score(b"GATTACA", b"TTAC"), 4.);
+        assert_eq!(affine.score(b"GATTACA", b""), 0.);
+
+        // One gap of three costs less than three gaps of one.
+        let (x, y) = (b"AAAAAAAACCCCCCCC", b"AAAAAAAAGGGCCCCCCCC");
+        assert_eq!(affine.score(x, y), 11.);
+        assert_eq!(linear.score(x, y), 10.);
+        assert_eq!(Metric::<u8, f64>::distance(&affine, x, y), 6.5);
+        assert_eq!(Metric::<u8, f64>::distance(&affine, y, x), 6.5);
+        assert_eq!(Metric::<u8, u64>::distance(&affine, x, y), 6);
+        assert_eq!(Metric::<u8, f64>::distance(&affine, x, x), 0.);
+
+        assert!(SmithWaterman::new(0., -1., -3., -1.).is_err());
+        assert!(SmithWaterman::new(1., 0., -3., -1.).is_err());
+        assert!(SmithWaterman::new(1., -1., -1., -3.).is_err());
+        assert!(SmithWaterman::new(1., -1., -3., f64::NAN).is_err());
+
+        // Each sequence is found by a search of radius zero around it.
+        let data: Vec<Vec<u8>> = (0..64)
+            .map(|i| {
+                let mut sequence = b"ACGTTGCAACGTAGCT".to_vec();
+                sequence.rotate_left(i % 16);
+                sequence.truncate(16 - i / 16);
+                sequence
+            })
+            .collect();
+        let dataset: Arc<dyn Dataset<u8, f64>> =
+            Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::new(affine), false));
+        let cakes = crate::Cakes::build(Arc::clone(&dataset), None, None);
+        assert!(data
+            .iter()
+            .enumerate()
+            .all(|(i, sequence)| cakes.rnn(sequence, Some(0.)).iter().any(|&(hit, _)| hit == i)));
+    }
+}
@@ -0,0 +1,682 @@
+//! Metrics built from other metrics, e.g. `Scaled`, `Capped` and `WeightedSum`, or from closures with `from_fn`.
+
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+
+use super::Dimensionality;
+use super::Metric;
+use crate::dataset::Norm;
+use crate::Number;
+
+/// Adapts a `Metric` to instances with missing values, by computing it over only the dimensions that both instances
+/// observe. As a `Metric`, it treats NaN values as missing, and `MaskedDataset` gives it the mask of each instance.
+///
+/// With rescaling, the distance over `d` of `n` dimensions is scaled up by `(n / d)` for the manhattan, euclideansq
+/// and hamming metrics and by its square root for the euclidean metric, estimating the distance over all `n`. Other
+/// metrics, e.g. cosine, are not rescaled. Instances that observe no dimension in common are at distance zero.
+///
+/// Warning: Masked distances do not obey the triangle inequality, so searches with them may miss some hits.
+pub struct MaskedMetric<T: Number, U: Number> {
+    metric: Arc<dyn Metric<T, U>>,
+    rescale: bool,
+}
+
+impl<T: Number, U: Number> MaskedMetric<T, U> {
+    pub fn new(metric: Arc<dyn Metric<T, U>>, rescale: bool) -> Self {
+        MaskedMetric { metric, rescale }
+    }
+
+    /// Returns the metric over the observed dimensions.
+    pub fn metric(&self) -> &Arc<dyn Metric<T, U>> {
+        &self.metric
+    }
+
+    /// Returns the distance between the instances over the dimensions that both observe, as given by their masks.
+    pub fn masked_distance(&self, x: &[T], x_observed: &BitSlice, y: &[T], y_observed: &BitSlice) -> U {
+        let n = std::cmp::min(x.len(), y.len());
+        let common: Vec<_> = (0..n).filter(|&j| x_observed[j] && y_observed[j]).collect();
+        self.distance_over(x, y, &common, n)
+    }
+
+    fn distance_over(&self, x: &[T], y: &[T], common: &[usize], n: usize) -> U {
+        if common.is_empty() {
+            return U::zero();
+        }
+        let gather = |values: &[T]| common.iter().map(|&j| values[j]).collect::<Vec<_>>();
+        let distance = self.metric.distance(&gather(x), &gather(y));
+        let exponent = match self.metric.name().as_str() {
+            "manhattan" | "euclideansq" | "hamming" if self.rescale => 1.,
+            "euclidean" if self.rescale => 0.5,
+            _ => return distance,
+        };
+        let scale = (n as f64 / common.len() as f64).powf(exponent);
+        U::saturating_from_f64(distance.as_f64() * scale)
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for MaskedMetric<T, U> {
+    fn name(&self) -> String {
+        format!("masked-{}", self.metric.name())
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        match self.metric.dimensionality() {
+            Dimensionality::Any => Dimensionality::Equal,
+            dimensionality => dimensionality,
+        }
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let n = std::cmp::min(x.len(), y.len());
+        let common: Vec<_> = (0..n)
+            .filter(|&j| !x[j].as_f64().is_nan() && !y[j].as_f64().is_nan())
+            .collect();
+        self.distance_over(x, y, &common, n)
+    }
+}
+
+/// Adapts a `Metric` with distances of type `U` to distances of type `V`, e.g. so that one set of instances can be
+/// searched with `u32` edit distances to save memory and with `f64` distances for precision. See
+/// `RowMajor::with_distance_type`.
+///
+/// Distances converted to an integer type are rounded up, which keeps the triangle inequality, so that searches stay
+/// exact, and leaves integral distances, e.g. edit distances, as they are. Distances converted to a float type are
+/// rounded to the nearest, as computing in that type would round them. Distances beyond the range of `V` saturate to
+/// its maximum, beyond which no two instances can be told apart.
+///
+/// The type of the distances is also that of the radii of clusters and of the caches of distances, which take 4 bytes
+/// per distance as `f32` or `u32` and 8 as `f64`. An `f32` has 24 bits of precision, so it holds integral distances
+/// exactly up to 2^24 and real distances to about 7 digits, and an `f64` has 53 bits, which hold about 16 digits.
+pub struct DistanceAs<T: Number, U: Number, V: Number> {
+    metric: Arc<dyn Metric<T, U>>,
+    _distances: std::marker::PhantomData<V>,
+}
+
+impl<T: Number, U: Number, V: Number> DistanceAs<T, U, V> {
+    pub fn new(metric: Arc<dyn Metric<T, U>>) -> Self {
+        DistanceAs {
+            metric,
+            _distances: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns the metric whose distances are converted.
+    pub fn metric(&self) -> &Arc<dyn Metric<T, U>> {
+        &self.metric
+    }
+
+    /// Returns the distance converted to `V`, as described in the docs of the struct.
+    pub fn convert(distance: U) -> V {
+        let distance = distance.as_f64();
+        if matches!(V::type_name(), "f32" | "f64") {
+            V::saturating_from_f64(distance)
+        } else {
+            V::saturating_from_f64(distance.ceil())
+        }
+    }
+}
+
+/// The name and the properties are those of the adapted metric, so that the metric is treated as it would be with its
+/// own type of distances.
+impl<T: Number, U: Number, V: Number> Metric<T, V> for DistanceAs<T, U, V> {
+    fn name(&self) -> String {
+        self.metric.name()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> V {
+        Self::convert(self.metric.distance(x, y))
+    }
+
+    fn encode(&self, reference: &[T], target: &[T]) -> Result<Vec<u8>, String> {
+        self.metric.encode(reference, target)
+    }
+
+    fn decode(&self, reference: &[T], encoding: &[u8]) -> Result<Vec<T>, String> {
+        self.metric.decode(reference, encoding)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.metric.dimensionality()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.metric.obeys_triangle_inequality()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.metric.is_symmetric()
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        self.metric.required_norm()
+    }
+}
+
+/// A `Metric` that computes its distances with a closure, for one-off distances that do not deserve a type of their
+/// own. See `from_fn`.
+///
+/// Until they are declared with the builder methods, the distances are assumed to be neither symmetric nor to obey the
+/// triangle inequality, and instances may have any lengths. The closure cannot encode or decode instances.
+pub struct FnMetric<T: Number, U: Number, F: Fn(&[T], &[T]) -> U + Send + Sync> {
+    name: String,
+    distance: F,
+    symmetric: bool,
+    triangle_inequality: bool,
+    dimensionality: Dimensionality,
+    _types: std::marker::PhantomData<fn(&[T]) -> U>,
+}
+
+/// Returns a `Metric` with the given name whose distances are computed by the closure, e.g.
+/// `from_fn("max-gap", |x: &[f64], y: &[f64]| ...).symmetric(true)`.
+pub fn from_fn<T: Number, U: Number, F: Fn(&[T], &[T]) -> U + Send + Sync>(
+    name: &str,
+    distance: F,
+) -> FnMetric<T, U, F> {
+    FnMetric {
+        name: name.to_string(),
+        distance,
+        symmetric: false,
+        triangle_inequality: false,
+        dimensionality: Dimensionality::Any,
+        _types: std::marker::PhantomData,
+    }
+}
+
+impl<T: Number, U: Number, F: Fn(&[T], &[T]) -> U + Send + Sync> FnMetric<T, U, F> {
+    /// Declares whether the distances are symmetric.
+    pub fn symmetric(mut self, symmetric: bool) -> Self {
+        self.symmetric = symmetric;
+        self
+    }
+
+    /// Declares whether the distances obey the triangle inequality, so that searches with them are exact.
+    pub fn obeying_triangle_inequality(mut self, obeys: bool) -> Self {
+        self.triangle_inequality = obeys;
+        self
+    }
+
+    /// Declares the lengths of the instances that the closure can compare.
+    pub fn with_dimensionality(mut self, dimensionality: Dimensionality) -> Self {
+        self.dimensionality = dimensionality;
+        self
+    }
+}
+
+impl<T: Number, U: Number, F: Fn(&[T], &[T]) -> U + Send + Sync> Metric<T, U> for FnMetric<T, U, F> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        (self.distance)(x, y)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.dimensionality
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.triangle_inequality
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.symmetric
+    }
+}
+
+/// Multiplies the distances of a `Metric` by a positive factor, e.g. to bring distances of different units to a common
+/// scale before they are summed. Scaling keeps every property of the metric.
+pub struct Scaled<T: Number, U: Number> {
+    metric: Arc<dyn Metric<T, U>>,
+    factor: f64,
+}
+
+impl<T: Number, U: Number> Scaled<T, U> {
+    /// Returns an Err unless the factor is positive and finite.
+    pub fn new(metric: Arc<dyn Metric<T, U>>, factor: f64) -> Result<Self, String> {
+        if !(factor.is_finite() && factor > 0.) {
+            return Err(format!(
+                "The factor of a scaled metric must be positive and finite, not {}.",
+                factor
+            ));
+        }
+        Ok(Scaled { metric, factor })
+    }
+
+    pub fn metric(&self) -> &Arc<dyn Metric<T, U>> {
+        &self.metric
+    }
+
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+}
+
+/// Integral distances are rounded up, as in `DistanceAs`, which keeps the triangle inequality.
+impl<T: Number, U: Number> Metric<T, U> for Scaled<T, U> {
+    fn name(&self) -> String {
+        format!("{}*{}", self.factor, self.metric.name())
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        DistanceAs::<T, f64, U>::convert(self.metric.distance(x, y).as_f64() * self.factor)
+    }
+
+    fn encode(&self, reference: &[T], target: &[T]) -> Result<Vec<u8>, String> {
+        self.metric.encode(reference, target)
+    }
+
+    fn decode(&self, reference: &[T], encoding: &[u8]) -> Result<Vec<T>, String> {
+        self.metric.decode(reference, encoding)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.metric.dimensionality()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.metric.obeys_triangle_inequality()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.metric.is_symmetric()
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        self.metric.required_norm()
+    }
+}
+
+/// Caps the distances of a `Metric` at a positive value, e.g. so that one term of a `Sum` cannot outweigh the others
+/// however far its instances are. The least of a metric and a constant is still a metric, so capping keeps every
+/// property of the metric, but searches cannot tell apart the instances beyond the cap.
+pub struct Capped<T: Number, U: Number> {
+    metric: Arc<dyn Metric<T, U>>,
+    cap: U,
+}
+
+impl<T: Number, U: Number> Capped<T, U> {
+    /// Returns an Err unless the cap is positive and finite.
+    pub fn new(metric: Arc<dyn Metric<T, U>>, cap: U) -> Result<Self, String> {
+        if !(cap.as_f64().is_finite() && cap > U::zero()) {
+            return Err(format!(
+                "The cap of a capped metric must be positive and finite, not {}.",
+                cap
+            ));
+        }
+        Ok(Capped { metric, cap })
+    }
+
+    pub fn metric(&self) -> &Arc<dyn Metric<T, U>> {
+        &self.metric
+    }
+
+    pub fn cap(&self) -> U {
+        self.cap
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Capped<T, U> {
+    fn name(&self) -> String {
+        format!("min({}, {})", self.metric.name(), self.cap)
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let distance = self.metric.distance(x, y);
+        if distance > self.cap {
+            self.cap
+        } else {
+            distance
+        }
+    }
+
+    fn encode(&self, reference: &[T], target: &[T]) -> Result<Vec<u8>, String> {
+        self.metric.encode(reference, target)
+    }
+
+    fn decode(&self, reference: &[T], encoding: &[u8]) -> Result<Vec<T>, String> {
+        self.metric.decode(reference, encoding)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.metric.dimensionality()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.metric.obeys_triangle_inequality()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.metric.is_symmetric()
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        self.metric.required_norm()
+    }
+}
+
+/// Sums the distances of several `Metric`s between the same instances, each weighted by a non-negative factor, e.g. a
+/// structural distance and a distance over metadata, so that one tree can be searched with both.
+///
+/// The sum obeys the triangle inequality, or is symmetric, only if every metric is. Instances must have the lengths
+/// that every metric allows, and the norm that any metric requires. The sum cannot encode or decode instances.
+pub struct WeightedSum<T: Number, U: Number> {
+    metrics: Vec<Arc<dyn Metric<T, U>>>,
+    weights: Vec<f64>,
+    dimensionality: Dimensionality,
+    norm: Option<Norm>,
+}
+
+impl<T: Number, U: Number> WeightedSum<T, U> {
+    /// Returns an Err if there are no metrics, if there is not one weight per metric, if a weight is negative or not
+    /// finite, or if the metrics require different lengths or norms of the instances.
+    pub fn new(metrics: Vec<Arc<dyn Metric<T, U>>>, weights: Vec<f64>) -> Result<Self, String> {
+        if metrics.is_empty() {
+            return Err("A sum of metrics needs at least one metric.".to_string());
+        }
+        if metrics.len() != weights.len() {
+            return Err(format!(
+                "A sum of {} metrics needs as many weights, not {}.",
+                metrics.len(),
+                weights.len()
+            ));
+        }
+        if let Some(j) = weights.iter().position(|w| !(w.is_finite() && *w >= 0.)) {
+            return Err(format!(
+                "Weight {} is {} but must be non-negative and finite.",
+                j, weights[j]
+            ));
+        }
+
+        let mut dimensionality = Dimensionality::Any;
+        let mut norm = None;
+        for metric in &metrics {
+            dimensionality = match (dimensionality, metric.dimensionality()) {
+                (Dimensionality::Exactly(a), Dimensionality::Exactly(b)) if a != b => {
+                    return Err(format!(
+                        "The metric {} compares instances of length {} but another compares those of length {}.",
+                        metric.name(),
+                        b,
+                        a
+                    ))
+                }
+                (Dimensionality::Exactly(n), _) | (_, Dimensionality::Exactly(n)) => Dimensionality::Exactly(n),
+                (Dimensionality::Equal, _) | (_, Dimensionality::Equal) => Dimensionality::Equal,
+                _ => Dimensionality::Any,
+            };
+            norm = match (norm, metric.required_norm()) {
+                (Some(a), Some(b)) if a != b => {
+                    return Err(format!(
+                        "The metric {} requires instances of unit {:?} norm but another requires the {:?} norm.",
+                        metric.name(),
+                        b,
+                        a
+                    ))
+                }
+                (a, b) => a.or(b),
+            };
+        }
+        Ok(WeightedSum {
+            metrics,
+            weights,
+            dimensionality,
+            norm,
+        })
+    }
+
+    pub fn metrics(&self) -> &[Arc<dyn Metric<T, U>>] {
+        &self.metrics
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+}
+
+/// Integral distances are rounded up, as in `DistanceAs`, which keeps the triangle inequality.
+impl<T: Number, U: Number> Metric<T, U> for WeightedSum<T, U> {
+    fn name(&self) -> String {
+        let terms: Vec<_> = self
+            .metrics
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(metric, weight)| format!("{}*{}", weight, metric.name()))
+            .collect();
+        terms.join("+")
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let sum = self
+            .metrics
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(metric, weight)| metric.distance(x, y).as_f64() * weight)
+            .sum();
+        DistanceAs::<T, f64, U>::convert(sum)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.dimensionality
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.metrics.iter().all(|metric| metric.obeys_triangle_inequality())
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.metrics.iter().all(|metric| metric.is_symmetric())
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        self.norm
+    }
+}
+
+/// Sums the distances of several `Metric`s between the same instances. This is a `WeightedSum` whose weights are all
+/// 1, and has the same properties.
+pub struct Sum<T: Number, U: Number> {
+    sum: WeightedSum<T, U>,
+}
+
+impl<T: Number, U: Number> Sum<T, U> {
+    /// Returns an Err if there are no metrics, or if the metrics require different lengths or norms of the instances.
+    pub fn new(metrics: Vec<Arc<dyn Metric<T, U>>>) -> Result<Self, String> {
+        let weights = vec![1.; metrics.len()];
+        Ok(Sum {
+            sum: WeightedSum::new(metrics, weights)?,
+        })
+    }
+
+    pub fn metrics(&self) -> &[Arc<dyn Metric<T, U>>] {
+        self.sum.metrics()
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Sum<T, U> {
+    fn name(&self) -> String {
+        let names: Vec<_> = self.sum.metrics.iter().map(|metric| metric.name()).collect();
+        names.join("+")
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        self.sum.distance(x, y)
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        self.sum.dimensionality()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        self.sum.obeys_triangle_inequality()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.sum.is_symmetric()
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        self.sum.required_norm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+
+    use crate::dataset::RowMajor;
+    use crate::metric::from_fn;
+    use crate::metric::metric_from_name;
+    use crate::metric::Capped;
+    use crate::metric::Dimensionality;
+    use crate::metric::Scaled;
+    use crate::metric::Sum;
+    use crate::metric::WeightedSum;
+    use crate::Dataset;
+    use crate::Metric;
+
+    #[test]
+    fn test_masked_metric() {
+        let observed = |mask: &[bool]| mask.iter().collect::<bitvec::vec::BitVec>();
+        let (x, y) = ([1., 5., 2., 0.], [4., 9., 6., 0.]);
+        let (x_observed, y_observed) = (
+            observed(&[true, false, true, true]),
+            observed(&[true, true, true, false]),
+        );
+        for (name, rescale, expected) in [
+            ("manhattan", false, 7.),
+            ("manhattan", true, 14.),
+            ("euclidean", true, 5. * 2_f64.sqrt()),
+            ("cosine", true, 1. - 16. / 260_f64.sqrt()),
+        ] {
+            let metric = super::MaskedMetric::<f64, f64>::new(metric_from_name(name).unwrap(), rescale);
+            let distance = metric.masked_distance(&x, &x_observed, &y, &y_observed);
+            assert!(approx_eq!(f64, distance, expected, epsilon = 1e-9), "{}", name);
+        }
+
+        // NaN values are missing when the metric is used on its own.
+        let metric = super::MaskedMetric::<f64, f64>::new(metric_from_name("manhattan").unwrap(), false);
+        let nan = f64::NAN;
+        let distance = crate::Metric::distance(&metric, &[1., nan, 2., 0.], &[4., 9., 6., nan]);
+        assert!(approx_eq!(f64, distance, 7.));
+        assert_eq!(crate::Metric::<f64, f64>::distance(&metric, &[nan], &[1.]), 0.);
+    }
+
+    #[test]
+    fn test_from_fn() {
+        let metric = from_fn("max-gap", |x: &[f64], y: &[f64]| {
+            x.iter().zip(y.iter()).map(|(a, b)| (a - b).abs()).fold(0., f64::max)
+        });
+        assert_eq!(metric.name(), "max-gap");
+        assert_eq!(metric.distance(&[0., 1., 5.], &[2., 1., 4.]), 2.);
+        assert!(!metric.obeys_triangle_inequality() && !metric.is_symmetric());
+        assert_eq!(metric.dimensionality(), Dimensionality::Any);
+        assert!(metric.encode(&[0.], &[1.]).is_err());
+
+        let metric = metric
+            .symmetric(true)
+            .obeying_triangle_inequality(true)
+            .with_dimensionality(Dimensionality::Equal);
+        assert!(metric.obeys_triangle_inequality() && metric.is_symmetric());
+        assert_eq!(metric.dimensionality(), Dimensionality::Equal);
+
+        // The closure searches like any other metric.
+        let data: Vec<_> = (0..100).map(|i| vec![(i % 10) as f64, (i / 10) as f64]).collect();
+        let metric: Arc<dyn Metric<f64, f64>> = Arc::new(metric);
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = crate::Cakes::build(dataset, None, None);
+        assert_eq!(cakes.rnn(&[4., 4.], Some(1.)).len(), 9);
+    }
+
+    #[test]
+    fn test_metric_combinators() {
+        let euclidean = metric_from_name::<f64, f64>("euclidean").unwrap();
+        let manhattan = metric_from_name::<f64, f64>("manhattan").unwrap();
+        let (x, y) = ([0., 0.], [3., 4.]);
+
+        let scaled = Scaled::new(Arc::clone(&euclidean), 2.).unwrap();
+        assert_eq!(scaled.name(), "2*euclidean");
+        assert_eq!(scaled.distance(&x, &y), 10.);
+        assert!(scaled.obeys_triangle_inequality() && scaled.is_symmetric());
+        assert!(Scaled::new(Arc::clone(&euclidean), 0.).is_err());
+        assert!(Scaled::new(Arc::clone(&euclidean), f64::INFINITY).is_err());
+
+        let capped = Capped::new(Arc::clone(&euclidean), 4.).unwrap();
+        assert_eq!(capped.name(), "min(euclidean, 4)");
+        assert_eq!(capped.distance(&x, &y), 4.);
+        assert_eq!(capped.distance(&x, &[0., 1.]), 1.);
+        assert!(capped.obeys_triangle_inequality());
+        assert!(Capped::new(Arc::clone(&euclidean), -1.).is_err());
+
+        let sum = Sum::new(vec![Arc::clone(&euclidean), Arc::clone(&manhattan)]).unwrap();
+        assert_eq!(sum.name(), "euclidean+manhattan");
+        assert_eq!(sum.distance(&x, &y), 12.);
+        assert!(sum.obeys_triangle_inequality() && sum.is_symmetric());
+        assert!(Sum::<f64, f64>::new(vec![]).is_err());
+
+        let weighted = WeightedSum::new(vec![Arc::clone(&euclidean), Arc::clone(&manhattan)], vec![0.5, 2.]).unwrap();
+        assert_eq!(weighted.name(), "0.5*euclidean+2*manhattan");
+        assert_eq!(weighted.distance(&x, &y), 16.5);
+        assert!(WeightedSum::new(vec![Arc::clone(&euclidean)], vec![-1.]).is_err());
+        assert!(WeightedSum::new(vec![Arc::clone(&euclidean)], vec![1., 1.]).is_err());
+
+        // Integral distances are rounded up.
+        let hamming = metric_from_name::<u8, u64>("hamming").unwrap();
+        let scaled = Scaled::new(hamming, 0.5).unwrap();
+        assert_eq!(scaled.distance(&[0, 0, 0], &[1, 1, 1]), 2);
+
+        // The properties of the terms are combined conservatively.
+        let quirky: Arc<dyn Metric<f64, f64>> =
+            Arc::new(from_fn("quirky", |x: &[f64], y: &[f64]| (x[0] - y[0]).powi(2)).symmetric(true));
+        let sum = Sum::new(vec![Arc::clone(&euclidean), Arc::clone(&quirky)]).unwrap();
+        assert!(!sum.obeys_triangle_inequality() && sum.is_symmetric());
+        assert_eq!(sum.dimensionality(), Dimensionality::Equal);
+        let angular = metric_from_name::<f64, f64>("angular").unwrap();
+        let sum = Sum::new(vec![Arc::clone(&euclidean), angular]).unwrap();
+        assert_eq!(sum.required_norm(), Some(crate::dataset::Norm::L2));
+
+        let fixed = |n: usize| -> Arc<dyn Metric<f64, f64>> {
+            Arc::new(from_fn("fixed", |_: &[f64], _: &[f64]| 0.).with_dimensionality(Dimensionality::Exactly(n)))
+        };
+        let sum = Sum::new(vec![Arc::clone(&euclidean), fixed(2), fixed(2)]).unwrap();
+        assert_eq!(sum.dimensionality(), Dimensionality::Exactly(2));
+        assert!(Sum::new(vec![fixed(2), fixed(3)]).is_err());
+
+        // A structural distance and a capped distance over metadata, in the last column, search one tree.
+        let data: Vec<_> = (0..200)
+            .map(|i| vec![(i % 10) as f64, (i / 10 % 10) as f64, (i % 3) as f64])
+            .collect();
+        let structure: Arc<dyn Metric<f64, f64>> = Arc::new(
+            from_fn("structure", |x: &[f64], y: &[f64]| {
+                ((x[0] - y[0]).powi(2) + (x[1] - y[1]).powi(2)).sqrt()
+            })
+            .symmetric(true)
+            .obeying_triangle_inequality(true),
+        );
+        let metadata: Arc<dyn Metric<f64, f64>> = Arc::new(
+            from_fn("metadata", |x: &[f64], y: &[f64]| (x[2] - y[2]).abs())
+                .symmetric(true)
+                .obeying_triangle_inequality(true),
+        );
+        let metadata = Arc::new(Capped::new(metadata, 1.).unwrap());
+        let metric: Arc<dyn Metric<f64, f64>> =
+            Arc::new(WeightedSum::new(vec![structure, metadata], vec![1., 10.]).unwrap());
+        let dataset: Arc<dyn Dataset<f64, f64>> =
+            Arc::new(RowMajor::new(Arc::new(data.clone()), Arc::clone(&metric), false));
+        let cakes = crate::Cakes::build(dataset, None, None);
+        let query = [4., 4., 1.];
+        let mut hits: Vec<_> = cakes.rnn(&query, Some(1.5)).into_iter().map(|(i, _)| i).collect();
+        hits.sort_unstable();
+        let expected: Vec<_> = (0..data.len())
+            .filter(|&i| metric.distance(&query, &data[i]) <= 1.5)
+            .collect();
+        assert_eq!(hits, expected);
+        assert!(hits.iter().all(|&i| data[i][2] == 1.));
+    }
+}
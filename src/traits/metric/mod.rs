@@ -0,0 +1,438 @@
+//! A `Metric` allows for calculating distances between instances in a `Dataset`.
+
+mod alignment;
+mod combinators;
+mod histogram;
+mod set;
+mod signal;
+mod substring;
+mod vector;
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+
+use crate::dataset::Norm;
+use crate::dataset::NormEnforcement;
+use crate::Cluster;
+use crate::Number;
+
+pub use alignment::Edit;
+pub use alignment::Levenshtein;
+pub use alignment::NeedlemanWunsch;
+pub use alignment::SmithWaterman;
+pub use alignment::WeightedLevenshtein;
+pub use combinators::from_fn;
+pub use combinators::Capped;
+pub use combinators::DistanceAs;
+pub use combinators::FnMetric;
+pub use combinators::MaskedMetric;
+pub use combinators::Scaled;
+pub use combinators::Sum;
+pub use combinators::WeightedSum;
+pub use histogram::EarthMovers;
+pub use set::Jaccard;
+pub use set::PackedTanimoto;
+pub use set::Tanimoto;
+pub use signal::Dtw;
+pub use signal::Ssim;
+pub use substring::SemiGlobalEdit;
+pub use substring::WindowHamming;
+pub use vector::Angular;
+pub use vector::Cosine;
+pub use vector::Euclidean;
+pub use vector::EuclideanSq;
+pub use vector::Hamming;
+pub use vector::Haversine;
+pub use vector::Mahalanobis;
+pub use vector::Manhattan;
+pub use vector::Minkowski;
+pub use vector::EARTH_RADIUS_KM;
+
+pub(crate) use set::tanimoto_distance;
+
+/// A `Metric` is a function that takes two instances (generic over a `Number` T)
+/// from a `Dataset` and deterministically produces a non-negative `Number` U.
+///
+/// Optionally, a `Metric` also allows us to encode one instance in terms of another
+/// and decode decode an instance from a reference and an encoding.
+///
+/// The built-in metrics accumulate in f64 and convert the distance to U with `Number::saturating_from_f64`, so they
+/// never panic on a distance that does not fit in U. A distance beyond the range of U is clamped to its maximum, and
+/// an integer U rounds distances toward zero and turns NaN into 0, e.g. a euclidean distance of 2.9 becomes 2 as a
+/// `u32`. Use a float U for metrics whose distances are not integers.
+pub trait Metric<T, U>: Send + Sync {
+    /// Returns the name of the `Metric` as a String.
+    fn name(&self) -> String;
+
+    /// Returns the distance between two instances.
+    fn distance(&self, x: &[T], y: &[T]) -> U;
+
+    /// Encodes the target instance in terms of the reference and produces a vec of bytes.
+    ///
+    /// This method is optional and so the default just returns an Err.
+    #[allow(unused_variables)]
+    fn encode(&self, reference: &[T], target: &[T]) -> Result<Vec<u8>, String> {
+        Err(format!("encode is not implemented for {:?}", self.name()))
+    }
+
+    /// Decodes a target instances from a reference instance and a bytes encoding.
+    ///
+    /// This method is optional and so the default just returns an Err.
+    #[allow(unused_variables)]
+    fn decode(&self, reference: &[T], encoding: &[u8]) -> Result<Vec<T>, String> {
+        Err(format!("decode is not implemented for {:?}", self.name()))
+    }
+
+    /// Returns the lengths of the instances that the `Metric` can compare, so that datasets can be checked against it,
+    /// e.g. by `RowMajor::try_from_rows`.
+    ///
+    /// The default allows instances of any lengths, as edit distances do.
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Any
+    }
+
+    /// Returns whether `d(x, z) <= d(x, y) + d(y, z)` for all instances, which searches need for their pruning to be
+    /// exact. Searches with distances that do not obey it may miss some hits, but never return others.
+    ///
+    /// The default is true, so distances that do not obey it should override this.
+    fn obeys_triangle_inequality(&self) -> bool {
+        true
+    }
+
+    /// Returns whether `d(x, y) == d(y, x)` for all instances. Searches compare queries to clusters in one direction
+    /// only, so searches with asymmetric distances may also miss some hits.
+    ///
+    /// The default is true, so asymmetric distances should override this.
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+
+    /// Returns the norm in which the `Metric` assumes that instances have unit length, if it does, e.g. to compute
+    /// angles from dot products alone. Distances among other instances are silently wrong, so datasets and queries
+    /// may be checked or normalized with a `NormEnforcement`.
+    ///
+    /// The default makes no assumption.
+    fn required_norm(&self) -> Option<Norm> {
+        None
+    }
+}
+
+/// The lengths of the instances that a `Metric` can compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimensionality {
+    /// Instances may have any lengths.
+    Any,
+    /// Instances must all have the same length.
+    Equal,
+    /// Instances must all have exactly this length.
+    Exactly(usize),
+}
+
+/// An `AsymmetricMetric` is a function from a query of type `Q`, e.g. a short read, to an instance of type `T`, e.g.
+/// a long reference, for searches whose queries cannot be mapped into the type of the instances.
+/// See `Cakes::rnn_asymmetric` and `Cakes::knn_asymmetric`.
+///
+/// The searches prune a cluster when the `lower_bound` on the distance from the query to its instances is greater than
+/// the search radius, so their results are exact only if that bound holds.
+pub trait AsymmetricMetric<Q: ?Sized, T: Number, U: Number>: Send + Sync {
+    /// Returns the name of the `AsymmetricMetric` as a String.
+    fn name(&self) -> String;
+
+    /// Returns the distance from the query to the instance.
+    fn distance(&self, query: &Q, instance: &[T]) -> U;
+
+    /// Returns a lower bound on the distance from the query to any instance of the cluster, given the distance from
+    /// the query to the center of the cluster.
+    ///
+    /// The default, `to_center - radius`, holds if `d(q, x) >= d(q, c) - m(c, x)` for every query `q` and instances
+    /// `c` and `x`, where `d` is this distance and `m` the metric of the instances. Distances that only obey a looser
+    /// form of the triangle inequality should override this, and distances that can bound the instances of a cluster
+    /// in other ways, e.g. from summaries of the clusters, may give a tighter bound.
+    #[allow(unused_variables)]
+    fn lower_bound(&self, query: &Q, cluster: &Cluster<T, U>, to_center: U) -> U {
+        if to_center > cluster.radius {
+            to_center - cluster.radius
+        } else {
+            U::zero()
+        }
+    }
+
+    /// Returns an Err if the query violates a norm that the distance needs, for the asymmetric searches of a `Cakes`
+    /// with a `NormEnforcement`. Queries of another type than the instances cannot be normalized for the distance, so
+    /// the default accepts every query.
+    #[allow(unused_variables)]
+    fn check_query(&self, query: &Q, enforcement: NormEnforcement) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Returns a `Metric` from a given name, or an Err if the name
+/// is not found among the implemented `Metrics`.
+///
+/// # Arguments
+///
+/// * `metric`: A `&str` name of a distance function.
+/// This can be one of:
+///   - "euclidean": L2-norm.
+///   - "euclideansq": Squared L2-norm.
+///   - "manhattan": L1-norm.
+///   - "minkowski-p", e.g. "minkowski-3", "minkowski-1.5" or "minkowski-inf": Lp-norm for any p of at least 1.
+///     Weights for the features are given with `Minkowski::new`.
+///   - "cosine": Cosine distance.
+///   - "angular": The angle between instances of unit euclidean length, e.g. those of `RowMajor::normalized`.
+///   - "haversine": Great-circle distance in kilometres on the Earth, between points given as latitude and longitude
+///     in degrees. "haversine-r" is the distance on a sphere of radius r, and "haversine-r-rad" is for points in
+///     radians, as made with `Haversine::new`.
+///   - "hamming": Hamming distance.
+///   - "jaccard": Jaccard distance between the sets of elements of instances, e.g. those of a `SetDataset`.
+///   - "tanimoto": Tanimoto distance between binary fingerprints, e.g. of molecules.
+///   - "packed-tanimoto": Tanimoto distance between binary fingerprints packed into words, e.g. `u64`s.
+///   - "earth-movers": Wasserstein-1 distance between histograms over the same bins, e.g. spectra.
+///   - "dtw": Dynamic-Time-Warping distance among series of different lengths.
+///   - "ssim": 1 - Structural-Similarity among images of the same shape.
+///   - "levenshtein": Edit-distance among strings (e.g. genomic/amino-acid sequences).
+///   - Any name registered with `register_metric` for these types, e.g. a metric of the application.
+///
+/// `metric_names` lists these names with what each metric computes.
+///
+/// `Mahalanobis` needs a covariance matrix, so it is constructed with `Mahalanobis::new` or
+/// `Mahalanobis::from_dataset` instead, and `NeedlemanWunsch`, `SmithWaterman` and `WeightedLevenshtein`, which need
+/// the scores or costs of an alignment, with their `new`.
+///
+/// We plan on adding the following:
+///   - "wasserstein": Earth-Mover-Distance among high-dimensional probability distributions (will be usable with images)
+///     rather than the one-dimensional histograms of "earth-movers"
+pub fn metric_from_name<T: 'static + Number, U: 'static + Number>(
+    metric: &str,
+) -> Result<Arc<dyn Metric<T, U>>, String> {
+    match metric {
+        "euclidean" => Ok(Arc::new(Euclidean)),
+        "euclideansq" => Ok(Arc::new(EuclideanSq)),
+        "manhattan" => Ok(Arc::new(Manhattan)),
+        "cosine" => Ok(Arc::new(Cosine)),
+        "angular" => Ok(Arc::new(Angular)),
+        "haversine" => Ok(Arc::new(Haversine::earth())),
+        "hamming" => Ok(Arc::new(Hamming)),
+        "jaccard" => Ok(Arc::new(Jaccard)),
+        "tanimoto" => Ok(Arc::new(Tanimoto)),
+        "packed-tanimoto" => Ok(Arc::new(PackedTanimoto)),
+        "earth-movers" => Ok(Arc::new(EarthMovers)),
+        "dtw" => Ok(Arc::new(Dtw)),
+        "ssim" => Ok(Arc::new(Ssim)),
+        "levenshtein" => Ok(Arc::new(Levenshtein)),
+        _ => {
+            if let Some(Ok(p)) = metric.strip_prefix("minkowski-").map(str::parse) {
+                return Ok(Arc::new(Minkowski::new(p, None)?));
+            }
+            if let Some(radius) = metric.strip_prefix("haversine-") {
+                let (radius, degrees) = match radius.strip_suffix("-rad") {
+                    Some(radius) => (radius, false),
+                    None => (radius, true),
+                };
+                if let Ok(radius) = radius.parse() {
+                    return Ok(Arc::new(Haversine::new(radius, degrees)?));
+                }
+            }
+            registered_metric(metric)
+        }
+    }
+}
+
+/// The names of the metrics that `metric_from_name` knows without registration, with what they compute.
+const BUILT_IN_METRICS: [(&str, &str); 15] = [
+    ("euclidean", "L2-norm."),
+    ("euclideansq", "Squared L2-norm."),
+    ("manhattan", "L1-norm."),
+    (
+        "minkowski-p",
+        "Lp-norm for any p of at least 1, e.g. minkowski-3 or minkowski-inf.",
+    ),
+    ("cosine", "Cosine distance."),
+    ("angular", "The angle between instances of unit euclidean length."),
+    (
+        "haversine",
+        "Great-circle distance in kilometres between latitudes and longitudes in degrees, or on a sphere of radius r \
+         with haversine-r, in radians with haversine-r-rad.",
+    ),
+    ("hamming", "Hamming distance."),
+    ("jaccard", "Jaccard distance between the sets of elements of instances."),
+    ("tanimoto", "Tanimoto distance between binary fingerprints."),
+    (
+        "packed-tanimoto",
+        "Tanimoto distance between binary fingerprints packed into words.",
+    ),
+    (
+        "earth-movers",
+        "Wasserstein-1 distance between histograms over the same bins.",
+    ),
+    (
+        "dtw",
+        "Dynamic-Time-Warping distance among series of different lengths.",
+    ),
+    ("ssim", "1 - Structural-Similarity among images of the same shape."),
+    ("levenshtein", "Edit-distance among strings."),
+];
+
+/// Makes the `Metric` registered under a name, e.g. with the parameters that the closure captured.
+type MetricFactory<T, U> = Arc<dyn Fn() -> Arc<dyn Metric<T, U>> + Send + Sync>;
+
+/// A metric registered with `register_metric`, for one pair of types.
+struct RegisteredMetric {
+    description: String,
+    /// The `MetricFactory` for the types of the key.
+    factory: Box<dyn Any + Send + Sync>,
+}
+
+/// The registered metrics, by their names and the names of their types of instances and distances.
+type MetricRegistry = HashMap<(String, &'static str, &'static str), RegisteredMetric>;
+
+static METRIC_REGISTRY: OnceLock<RwLock<MetricRegistry>> = OnceLock::new();
+
+fn metric_registry() -> &'static RwLock<MetricRegistry> {
+    METRIC_REGISTRY.get_or_init(Default::default)
+}
+
+/// Registers a metric under a name, with a description for `metric_names`, so that `metric_from_name` makes it for
+/// instances of `T` and distances of `U`, e.g. when a configuration file names it. The factory is called for each
+/// `metric_from_name`. The same name may be registered once for each pair of types. Registration is thread-safe and
+/// lasts for the rest of the process.
+///
+/// Returns an Err if the name is empty or is that of a built-in metric, if it is already registered for these types,
+/// or if the factory makes a metric with a different `name`, so that every metric from `metric_from_name` has the name
+/// it was made from.
+pub fn register_metric<T: 'static + Number, U: 'static + Number>(
+    name: &str,
+    description: &str,
+    factory: impl Fn() -> Arc<dyn Metric<T, U>> + Send + Sync + 'static,
+) -> Result<(), String> {
+    let built_in = BUILT_IN_METRICS.iter().any(|&(built_in, _)| built_in == name) || name.starts_with("minkowski-");
+    if name.is_empty() || built_in {
+        return Err(format!(
+            "'{}' cannot be registered as a metric, since it is empty or built in.",
+            name
+        ));
+    }
+    let made = factory().name();
+    if made != name {
+        return Err(format!("The factory for '{}' makes a metric named '{}'.", name, made));
+    }
+
+    let mut registry = metric_registry().write().unwrap_or_else(PoisonError::into_inner);
+    let key = (name.to_string(), T::type_name(), U::type_name());
+    if registry.contains_key(&key) {
+        return Err(format!(
+            "'{}' is already registered as a metric for {} instances and {} distances.",
+            name, key.1, key.2
+        ));
+    }
+    let factory: MetricFactory<T, U> = Arc::new(factory);
+    registry.insert(
+        key,
+        RegisteredMetric {
+            description: description.to_string(),
+            factory: Box::new(factory),
+        },
+    );
+    Ok(())
+}
+
+/// Returns the names of the metrics that `metric_from_name` can make, the built-in ones first and then those
+/// registered for any types, in order of name, each with what it computes.
+pub fn metric_names() -> Vec<(String, String)> {
+    let mut names: Vec<_> = BUILT_IN_METRICS
+        .iter()
+        .map(|&(name, description)| (name.to_string(), description.to_string()))
+        .collect();
+    let registry = metric_registry().read().unwrap_or_else(PoisonError::into_inner);
+    let registered: std::collections::BTreeMap<_, _> = registry
+        .iter()
+        .map(|((name, _, _), metric)| (name.clone(), metric.description.clone()))
+        .collect();
+    names.extend(registered);
+    names
+}
+
+/// Returns the metric registered under the name for these types, or an Err if there is none.
+fn registered_metric<T: 'static + Number, U: 'static + Number>(name: &str) -> Result<Arc<dyn Metric<T, U>>, String> {
+    let registry = metric_registry().read().unwrap_or_else(PoisonError::into_inner);
+    let key = (name.to_string(), T::type_name(), U::type_name());
+    if let Some(metric) = registry.get(&key) {
+        let factory = metric.factory.downcast_ref::<MetricFactory<T, U>>();
+        return Ok(factory.expect("The factory is registered for the types of its key.")());
+    }
+    match registry.keys().find(|(registered, _, _)| registered == name) {
+        Some((_, t, u)) => Err(format!(
+            "{} is registered as a metric for {} instances and {} distances, not for {} and {}.",
+            name, t, u, key.1, key.2
+        )),
+        None => Err(format!("{} is not defined as a metric.", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+
+    use crate::metric::metric_from_name;
+    use crate::metric::metric_names;
+    use crate::metric::register_metric;
+    use crate::metric::Minkowski;
+    use crate::Metric;
+
+    #[test]
+    fn test_on_unsigned() {
+        // Differences of unsigned instances neither underflow nor overflow the type of the instances.
+        let (x, y) = (vec![0_u8, 255, 7], vec![255_u8, 0, 7]);
+        let metric = metric_from_name::<u8, u32>("euclideansq").unwrap();
+        assert_eq!(metric.distance(&x, &y), 2 * 255 * 255);
+        let metric = metric_from_name::<u8, u16>("manhattan").unwrap();
+        assert_eq!(metric.distance(&x, &y), 510);
+        let metric = metric_from_name::<u8, u8>("euclideansq").unwrap();
+        assert_eq!(metric.distance(&x, &y), u8::MAX);
+        let metric = metric_from_name::<u8, f32>("jaccard").unwrap();
+        assert!(approx_eq!(f32, metric.distance(&x, &[7, 9]), 0.75));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_panic() {
+        let _ = metric_from_name::<f32, f32>("aloha").unwrap();
+    }
+
+    #[test]
+    fn test_metric_registry() {
+        struct Chebyshev;
+        impl Metric<f32, f32> for Chebyshev {
+            fn name(&self) -> String {
+                "test-chebyshev".to_string()
+            }
+
+            fn distance(&self, x: &[f32], y: &[f32]) -> f32 {
+                x.iter().zip(y.iter()).map(|(a, b)| (a - b).abs()).fold(0., f32::max)
+            }
+        }
+
+        assert!(metric_from_name::<f32, f32>("test-chebyshev").is_err());
+        register_metric("test-chebyshev", "The largest difference.", || Arc::new(Chebyshev)).unwrap();
+        let metric = metric_from_name::<f32, f32>("test-chebyshev").unwrap();
+        assert_eq!(metric.distance(&[1., 5.], &[2., 2.]), 3.);
+        let error = metric_from_name::<f64, f64>("test-chebyshev").err().unwrap();
+        assert!(error.contains("for f32 instances and f32 distances"), "{}", error);
+        assert!(metric_names().contains(&("test-chebyshev".to_string(), "The largest difference.".to_string())));
+        assert_eq!(metric_names()[0].0, "euclidean");
+
+        // Names are registered once for each pair of types, and must be the names of the metrics.
+        assert!(register_metric("test-chebyshev", "", || Arc::new(Chebyshev)).is_err());
+        assert!(register_metric("euclidean", "", || Arc::new(Chebyshev)).is_err());
+        assert!(register_metric("minkowski-7", "", || Arc::new(Chebyshev)).is_err());
+        assert!(register_metric("test-other", "", || Arc::new(Chebyshev)).is_err());
+        let minkowski = || Arc::new(Minkowski::new(f64::INFINITY, None).unwrap()) as Arc<dyn Metric<f64, f64>>;
+        assert!(register_metric("test-chebyshev", "", minkowski).is_err());
+    }
+}
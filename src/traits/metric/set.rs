@@ -0,0 +1,153 @@
+//! Metrics between sets and binary fingerprints: `Jaccard`, `Tanimoto` and `PackedTanimoto`.
+
+use std::collections::HashSet;
+
+use super::Dimensionality;
+use super::Metric;
+use crate::Number;
+
+/// Implements Cosine distance, 1 - jaccard-similarity.
+///
+/// Instances that are sorted sets, with strictly increasing elements as a `SetDataset` stores them, are compared in one
+/// merge of their elements. Any other instances are compared by hashing their elements.
+///
+/// Warning: DO NOT use this with floating-point numbers.
+pub struct Jaccard;
+
+impl<T: Number, U: Number> Metric<T, U> for Jaccard {
+    fn name(&self) -> String {
+        "jaccard".to_string()
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        if x.is_empty() || y.is_empty() {
+            return U::one();
+        }
+
+        let (intersect, union) = if is_sorted_set(x) && is_sorted_set(y) {
+            sorted_overlap(x, y)
+        } else {
+            // The elements are compared by their bytes, so that no element is ever cast out of its range.
+            let x: HashSet<Vec<u8>> = x.iter().map(|a| a.to_bytes()).collect();
+            let intersect = y.iter().filter(|b| x.contains(&b.to_bytes())).count();
+            (intersect, x.len() + y.len() - intersect)
+        };
+
+        if intersect == union {
+            return U::zero();
+        }
+
+        U::saturating_from_f64(1. - intersect as f64 / union as f64)
+    }
+}
+
+/// Returns whether the elements strictly increase.
+fn is_sorted_set<T: Number>(x: &[T]) -> bool {
+    x.windows(2).all(|pair| pair[0] < pair[1])
+}
+
+/// Returns the sizes of the intersection and the union of two sorted sets.
+fn sorted_overlap<T: Number>(x: &[T], y: &[T]) -> (usize, usize) {
+    let (mut i, mut j, mut intersect) = (0, 0, 0);
+    while i < x.len() && j < y.len() {
+        if x[i] < y[j] {
+            i += 1;
+        } else if y[j] < x[i] {
+            j += 1;
+        } else {
+            intersect += 1;
+            i += 1;
+            j += 1;
+        }
+    }
+    (intersect, x.len() + y.len() - intersect)
+}
+
+/// Implements Tanimoto distance, 1 - the number of features that are non-zero in both instances over the number that
+/// are non-zero in either. This is the jaccard distance between the sets of bits of binary fingerprints.
+pub struct Tanimoto;
+
+impl<T: Number, U: Number> Metric<T, U> for Tanimoto {
+    fn name(&self) -> String {
+        "tanimoto".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let (mut both, mut either) = (0, 0);
+        for (&a, &b) in x.iter().zip(y.iter()) {
+            let (a, b) = (a != T::zero(), b != T::zero());
+            both += (a && b) as usize;
+            either += (a || b) as usize;
+        }
+        U::saturating_from_f64(tanimoto_distance(both, either))
+    }
+}
+
+/// Implements Tanimoto distance between binary fingerprints packed into words, e.g. with `dataset::pack_bits`, from the
+/// popcounts of each pair of words. This is the tanimoto distance between the unpacked fingerprints, and also between
+/// fingerprints that are not packed but have every feature as zero or one.
+pub struct PackedTanimoto;
+
+impl<T: Number, U: Number> Metric<T, U> for PackedTanimoto {
+    fn name(&self) -> String {
+        "packed-tanimoto".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let (mut both, mut either) = (0, 0);
+        for (a, b) in x.iter().zip(y.iter()) {
+            let (a, b) = (a.raw_bits(), b.raw_bits());
+            both += (a & b).count_ones() as usize;
+            either += (a | b).count_ones() as usize;
+        }
+        U::saturating_from_f64(tanimoto_distance(both, either))
+    }
+}
+
+/// Returns the tanimoto distance from the number of bits set in both fingerprints and in either of them.
+/// Two empty fingerprints are identical.
+pub(crate) fn tanimoto_distance(both: usize, either: usize) -> f64 {
+    if either == 0 {
+        0.
+    } else {
+        1. - both as f64 / either as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use crate::dataset::pack_bits;
+    use crate::metric::metric_from_name;
+
+    #[test]
+    fn test_tanimoto() {
+        let metric = metric_from_name::<u8, f64>("tanimoto").unwrap();
+        assert!(approx_eq!(f64, metric.distance(&[1, 1, 0, 1], &[1, 0, 0, 1]), 1. / 3.));
+        assert!(approx_eq!(f64, metric.distance(&[0, 0], &[0, 0]), 0.));
+        assert!(approx_eq!(f64, metric.distance(&[1, 0], &[0, 1]), 1.));
+
+        // Packed fingerprints have the distance of their bits.
+        let fingerprints: Vec<Vec<u8>> = (0..20)
+            .map(|i: usize| (0..150).map(|j| ((i * 13 + j * 7) % 5 < 2) as u8).collect())
+            .collect();
+        let packed = metric_from_name::<u64, f64>("packed-tanimoto").unwrap();
+        for (x, y) in [(0, 1), (4, 17), (9, 9)] {
+            let distance = metric.distance(&fingerprints[x], &fingerprints[y]);
+            let (x, y) = (pack_bits(&fingerprints[x]), pack_bits(&fingerprints[y]));
+            assert_eq!(x.len(), 3);
+            assert!(approx_eq!(f64, packed.distance(&x, &y), distance));
+        }
+        let bits = metric_from_name::<u8, f64>("packed-tanimoto").unwrap();
+        assert!(approx_eq!(f64, bits.distance(&[1, 1, 0, 1], &[1, 0, 0, 1]), 1. / 3.));
+    }
+}
@@ -0,0 +1,120 @@
+//! Metrics between signals: `Dtw` for series of different lengths and `Ssim` for images.
+
+use super::Dimensionality;
+use super::Metric;
+use crate::Number;
+
+/// Implements Dynamic-Time-Warping distance, the least total absolute difference over the alignments of two series
+/// that may stretch either series in time. The series may have different lengths.
+///
+/// Warning: DTW does not obey the triangle inequality, so searches with it may miss some hits.
+pub struct Dtw;
+
+impl<T: Number, U: Number> Metric<T, U> for Dtw {
+    fn name(&self) -> String {
+        "dtw".to_string()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        if x.is_empty() || y.is_empty() {
+            let rest = x.iter().chain(y.iter()).map(|a| a.as_f64().abs()).sum();
+            return U::saturating_from_f64(rest);
+        }
+
+        // Only the previous row of the table of costs is kept.
+        let mut previous = vec![f64::INFINITY; y.len() + 1];
+        let mut current = vec![f64::INFINITY; y.len() + 1];
+        previous[0] = 0.;
+        for a in x {
+            current[0] = f64::INFINITY;
+            for (j, b) in y.iter().enumerate() {
+                let cost = (a.as_f64() - b.as_f64()).abs();
+                current[j + 1] = cost + previous[j].min(previous[j + 1]).min(current[j]);
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        U::saturating_from_f64(previous[y.len()])
+    }
+}
+
+/// Implements 1 - SSIM, the structural similarity of two images, or patches, computed over the whole of each image
+/// rather than over sliding windows. Identical images have distance 0 and the distance is at most 2.
+///
+/// The stabilizing constants of SSIM are scaled by the largest absolute value in either image, which stands in for
+/// the dynamic range of the pixels.
+///
+/// Warning: SSIM does not obey the triangle inequality, so searches with it may miss some hits.
+pub struct Ssim;
+
+impl<T: Number, U: Number> Metric<T, U> for Ssim {
+    fn name(&self) -> String {
+        "ssim".to_string()
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let n = std::cmp::min(x.len(), y.len());
+        let range = x.iter().chain(y.iter()).map(|a| a.as_f64().abs()).fold(0., f64::max);
+        if n == 0 || range == 0. {
+            return U::zero();
+        }
+
+        let (x, y) = (&x[..n], &y[..n]);
+        let mean = |values: &[T]| values.iter().map(|a| a.as_f64()).sum::<f64>() / n as f64;
+        let (mx, my) = (mean(x), mean(y));
+        let (mut vx, mut vy, mut cov) = (0., 0., 0.);
+        for (a, b) in x.iter().zip(y.iter()) {
+            let (dx, dy) = (a.as_f64() - mx, b.as_f64() - my);
+            vx += dx * dx;
+            vy += dy * dy;
+            cov += dx * dy;
+        }
+        let (vx, vy, cov) = (vx / n as f64, vy / n as f64, cov / n as f64);
+
+        let (c1, c2) = ((0.01 * range).powi(2), (0.03 * range).powi(2));
+        let ssim = ((2. * mx * my + c1) * (2. * cov + c2)) / ((mx * mx + my * my + c1) * (vx + vy + c2));
+        U::saturating_from_f64((1. - ssim).clamp(0., 2.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use crate::metric::metric_from_name;
+
+    #[test]
+    fn test_dtw() {
+        let metric = metric_from_name::<f64, f64>("dtw").unwrap();
+        let series = [0., 1., 2., 3.];
+        assert!(approx_eq!(f64, metric.distance(&series, &series), 0.));
+        // Repeating values stretches the series in time at no cost.
+        assert!(approx_eq!(f64, metric.distance(&series, &[0., 0., 1., 2., 2., 3.]), 0.));
+        assert!(approx_eq!(f64, metric.distance(&series, &[0., 1., 3.]), 1.));
+        assert!(approx_eq!(f64, metric.distance(&series, &[]), 6.));
+    }
+
+    #[test]
+    fn test_ssim() {
+        let metric = metric_from_name::<u8, f64>("ssim").unwrap();
+        let image = [10, 200, 30, 120, 90, 250];
+        assert!(approx_eq!(f64, metric.distance(&image, &image), 0.));
+        let inverted: Vec<_> = image.iter().map(|&v| 255 - v).collect();
+        let brighter: Vec<_> = image.iter().map(|&v| v / 2 + 100).collect();
+        // Changing the brightness keeps the structure and inverting the image reverses it.
+        assert!(metric.distance(&image, &brighter) < 1.);
+        assert!(metric.distance(&image, &inverted) > 1.);
+        assert!(approx_eq!(f64, metric.distance(&[0, 0], &[0, 0]), 0.));
+    }
+}
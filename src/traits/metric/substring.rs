@@ -0,0 +1,150 @@
+//! Asymmetric metrics that find queries within longer instances: `WindowHamming` and `SemiGlobalEdit`.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+
+use super::alignment::edits;
+use super::AsymmetricMetric;
+use crate::utils::read_cache;
+use crate::Cluster;
+use crate::Number;
+
+/// Implements the least Hamming distance from a query to any window of an instance with the length of the query, e.g.
+/// from a short read to the references it may have come from. Positions of the query beyond the end of the instance
+/// count as mismatches.
+///
+/// With the `hamming` metric among instances of the same length, the default `lower_bound` holds, since a window of
+/// an instance differs from the same window of another in at most as many positions as the whole instances do.
+pub struct WindowHamming;
+
+impl<T: Number, U: Number> AsymmetricMetric<[T], T, U> for WindowHamming {
+    fn name(&self) -> String {
+        "window-hamming".to_string()
+    }
+
+    fn distance(&self, query: &[T], instance: &[T]) -> U {
+        let mismatches = |window: &[T]| {
+            window.iter().zip(query.iter()).filter(|(a, b)| a != b).count() + query.len().saturating_sub(window.len())
+        };
+        let d = if query.is_empty() || query.len() >= instance.len() {
+            mismatches(instance)
+        } else {
+            instance.windows(query.len()).map(mismatches).min().unwrap_or(0)
+        };
+        U::saturating_from_f64(d as f64)
+    }
+}
+
+/// Implements the semi-global edit distance from a query to an instance, the least number of substitutions,
+/// insertions and deletions that turn the query into any substring of the instance, e.g. for the best alignment of a
+/// short read within each of a set of references.
+///
+/// The bound on the distance to the instances of a cluster is the greatest of:
+///
+/// * The default `lower_bound`, which holds if the instances are compared with the `levenshtein` or `hamming`
+///   metrics, since the substring of an instance that the query matches is within as many edits of a substring of
+///   the center as the whole instance is of the whole center.
+/// * With sketches, the number of characters by which the query is longer than the longest instance of the cluster.
+/// * With sketches, the q-gram bound: each edit of the query changes at most `k` of its k-mers, so if only `s` of
+///   its `n` k-mers are among the k-mers of the instances of the cluster, there are at least `(n - s) / k` edits.
+///
+/// Sketches are built with `with_sketches` and hold the k-mers of every cluster in the tree, so they may take a lot
+/// of memory for large trees. Without sketches, only the first bound is used.
+#[derive(Default)]
+pub struct SemiGlobalEdit {
+    k: usize,
+    /// The sketch of each cluster, by name.
+    sketches: HashMap<BitVec, ClusterSketch>,
+}
+
+/// The k-mers of the instances of a cluster, packed into words, and the length of its longest instance.
+#[derive(Clone)]
+struct ClusterSketch {
+    kmers: HashSet<u64>,
+    max_length: usize,
+}
+
+impl SemiGlobalEdit {
+    /// Sketches every cluster in the tree with its k-mers for the given `k`, which is clamped to between 1 and 8.
+    pub fn with_sketches<U: Number>(root: &Arc<Cluster<u8, U>>, k: usize) -> Self {
+        let k = k.clamp(1, 8);
+        let mut sketches = HashMap::new();
+        sketch(root, k, &mut sketches);
+        SemiGlobalEdit { k, sketches }
+    }
+
+    /// Returns the number of clusters that have sketches.
+    pub fn num_sketches(&self) -> usize {
+        self.sketches.len()
+    }
+}
+
+/// Sketches the cluster and its descendants, each from the sketches of its children or, for leaves, its instances.
+fn sketch<U: Number>(cluster: &Arc<Cluster<u8, U>>, k: usize, sketches: &mut HashMap<BitVec, ClusterSketch>) {
+    let sketch = match read_cache(&cluster.children).clone() {
+        Some((left, right)) => {
+            sketch(&left, k, sketches);
+            sketch(&right, k, sketches);
+            let (left, right) = (&sketches[&left.name], &sketches[&right.name]);
+            ClusterSketch {
+                kmers: left.kmers.union(&right.kmers).copied().collect(),
+                max_length: std::cmp::max(left.max_length, right.max_length),
+            }
+        }
+        None => {
+            let instances: Vec<_> = cluster.indices.iter().map(|&i| cluster.dataset.instance(i)).collect();
+            ClusterSketch {
+                kmers: instances.iter().flat_map(|instance| kmers(instance, k)).collect(),
+                max_length: instances.iter().map(Vec::len).max().unwrap_or(0),
+            }
+        }
+    };
+    sketches.insert(cluster.name.clone(), sketch);
+}
+
+/// Returns the k-mers of the sequence, each packed into a word, for `k` of at most 8.
+fn kmers(sequence: &[u8], k: usize) -> impl Iterator<Item = u64> + '_ {
+    sequence
+        .windows(k)
+        .map(|kmer| kmer.iter().fold(0, |word, &byte| (word << 8) | byte as u64))
+}
+
+impl<U: Number> AsymmetricMetric<[u8], u8, U> for SemiGlobalEdit {
+    fn name(&self) -> String {
+        "semiglobal".to_string()
+    }
+
+    fn distance(&self, query: &[u8], instance: &[u8]) -> U {
+        U::saturating_from_f64(edits(query, instance, true) as f64)
+    }
+
+    fn lower_bound(&self, query: &[u8], cluster: &Cluster<u8, U>, to_center: U) -> U {
+        let mut bound = (to_center.as_f64() - cluster.radius.as_f64()).max(0.);
+        if let Some(sketch) = self.sketches.get(&cluster.name) {
+            bound = bound.max(query.len().saturating_sub(sketch.max_length) as f64);
+            if query.len() >= self.k {
+                let n = query.len() - self.k + 1;
+                let shared = kmers(query, self.k).filter(|kmer| sketch.kmers.contains(kmer)).count();
+                bound = bound.max(((n - shared) as f64 / self.k as f64).ceil());
+            }
+        }
+        U::saturating_from_f64(bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_window_hamming() {
+        let metric = super::WindowHamming;
+        let distance =
+            |query: &[u8], instance: &[u8]| super::AsymmetricMetric::<[u8], u8, u64>::distance(&metric, query, instance);
+        assert_eq!(distance(b"GAT", b"CCGATTA"), 0);
+        assert_eq!(distance(b"GCT", b"CCGATTA"), 1);
+        assert_eq!(distance(b"TTAC", b"CCT"), 4);
+        assert_eq!(distance(b"", b"ACGT"), 0);
+    }
+}
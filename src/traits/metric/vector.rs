@@ -0,0 +1,757 @@
+//! Metrics between vectors of equal length: the minkowski family, e.g. `Euclidean` and `Manhattan`, `Cosine`,
+//! `Angular`, `Mahalanobis`, `Haversine` and `Hamming`.
+
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+
+use super::Dimensionality;
+use super::Metric;
+use crate::dataset::Norm;
+use crate::utils::summation;
+use crate::Dataset;
+use crate::Number;
+
+/// Implements Euclidean distance, the L2-norm.
+pub struct Euclidean;
+
+/// Returns the squared L2-norm of the difference, accumulated in f64 so that unsigned and narrow types never
+/// overflow or underflow.
+fn squared_euclidean<T: Number>(x: &[T], y: &[T]) -> f64 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if let Some(d) = crate::utils::simd::squared_euclidean(x, y) {
+        return d;
+    }
+    x.iter()
+        .zip(y.iter())
+        .map(|(a, b)| (a.as_f64() - b.as_f64()).powi(2))
+        .sum()
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Euclidean {
+    fn name(&self) -> String {
+        "euclidean".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        U::saturating_from_f64(squared_euclidean(x, y).sqrt())
+    }
+}
+
+/// Implements Squared-Euclidean distance, the squared L2-norm.
+pub struct EuclideanSq;
+
+impl<T: Number, U: Number> Metric<T, U> for EuclideanSq {
+    fn name(&self) -> String {
+        "euclideansq".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        U::saturating_from_f64(squared_euclidean(x, y))
+    }
+}
+
+/// Implements Manhattan/Cityblock distance, the L1-norm.
+pub struct Manhattan;
+
+impl<T: Number, U: Number> Metric<T, U> for Manhattan {
+    fn name(&self) -> String {
+        "manhattan".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if let Some(d) = crate::utils::simd::manhattan(x, y) {
+            return U::saturating_from_f64(d);
+        }
+        let d: f64 = x
+            .iter()
+            .zip(y.iter())
+            .map(|(a, b)| (a.as_f64() - b.as_f64()).abs())
+            .sum();
+        U::saturating_from_f64(d)
+    }
+}
+
+/// Implements the Lp-norm of the difference, `(sum_i w_i |x_i - y_i|^p)^(1/p)`, with optional non-negative weights
+/// `w_i` for the features, which are otherwise 1. The limit as `p` grows, `p = f64::INFINITY`, is the largest
+/// difference over the features of positive weight, i.e. the Chebyshev distance.
+///
+/// With `p` of 1 or 2, this is the manhattan or euclidean distance. `new` requires `p >= 1`, since smaller `p` do not
+/// obey the triangle inequality.
+///
+/// Features beyond the weights, e.g. of a query longer than the instances, have weight 0 and are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Minkowski {
+    p: f64,
+    weights: Option<Vec<f64>>,
+}
+
+impl Minkowski {
+    /// Returns the distance for the given `p` and weights, or an Err if `p` is NaN or less than 1, or if a weight is
+    /// negative or not finite.
+    pub fn new(p: f64, weights: Option<Vec<f64>>) -> Result<Self, String> {
+        if p.is_nan() || p < 1. {
+            return Err(format!("The p of a Minkowski distance must be at least 1, not {}.", p));
+        }
+        if let Some(weights) = &weights {
+            if let Some(j) = weights.iter().position(|w| !(w.is_finite() && *w >= 0.)) {
+                return Err(format!(
+                    "Weight {} is {} but must be non-negative and finite.",
+                    j, weights[j]
+                ));
+            }
+        }
+        Ok(Minkowski { p, weights })
+    }
+
+    pub fn p(&self) -> f64 {
+        self.p
+    }
+
+    pub fn weights(&self) -> Option<&[f64]> {
+        self.weights.as_deref()
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Minkowski {
+    fn name(&self) -> String {
+        match self.weights {
+            Some(_) => format!("weighted-minkowski-{}", self.p),
+            None => format!("minkowski-{}", self.p),
+        }
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        match &self.weights {
+            Some(weights) => Dimensionality::Exactly(weights.len()),
+            None => Dimensionality::Equal,
+        }
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let weight = |j: usize| {
+            self.weights
+                .as_ref()
+                .map_or(1., |weights| weights.get(j).copied().unwrap_or(0.))
+        };
+        let differences = x
+            .iter()
+            .zip(y.iter())
+            .enumerate()
+            .map(|(j, (a, b))| (weight(j), (a.as_f64() - b.as_f64()).abs()));
+        let d = if self.p == f64::INFINITY {
+            differences
+                .filter(|&(w, _)| w > 0.)
+                .map(|(_, difference)| difference)
+                .fold(0., f64::max)
+        } else if self.p == 1. {
+            differences.map(|(w, difference)| w * difference).sum()
+        } else if self.p == 2. {
+            differences
+                .map(|(w, difference)| w * difference * difference)
+                .sum::<f64>()
+                .sqrt()
+        } else {
+            differences
+                .map(|(w, difference)| w * difference.powf(self.p))
+                .sum::<f64>()
+                .powf(1. / self.p)
+        };
+        U::saturating_from_f64(d)
+    }
+}
+
+/// Implements Cosine distance, 1 - cosine-similarity.
+///
+/// The distance is in [0, 1]. Instances at a right or obtuse angle to each other are at distance 1, and so is a zero
+/// instance from every instance.
+pub struct Cosine;
+
+fn dot<T: Number>(x: &[T], y: &[T]) -> f64 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if let Some(d) = crate::utils::simd::dot(x, y) {
+        return d;
+    }
+    x.iter().zip(y.iter()).map(|(a, b)| a.as_f64() * b.as_f64()).sum()
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Cosine {
+    fn name(&self) -> String {
+        "cosine".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn obeys_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if let Some([xy, xx, yy]) = crate::utils::simd::dots(x, y) {
+            return cosine_distance(xy, xx, yy);
+        }
+
+        let xx = dot(x, x);
+        if xx == 0. {
+            return U::one();
+        }
+
+        let yy = dot(y, y);
+        if yy == 0. {
+            return U::one();
+        }
+        cosine_distance(dot(x, y), xx, yy)
+    }
+}
+
+/// Returns the cosine distance from the dot products `x.y`, `x.x` and `y.y`.
+fn cosine_distance<U: Number>(xy: f64, xx: f64, yy: f64) -> U {
+    if xx == 0. || yy == 0. || xy <= 0. {
+        U::one()
+    } else {
+        U::saturating_from_f64(1. - (xy * xy / (xx * yy)).sqrt())
+    }
+}
+
+/// Implements Mahalanobis distance, `sqrt((x - y)' S^-1 (x - y))` for the covariance matrix `S` of the features, which
+/// measures differences in units of the spread of the data along each direction, so that correlated features are not
+/// counted twice.
+///
+/// The inverse of the covariance, the precision, is computed once by `new` or `from_dataset`. Since the distance needs
+/// a covariance matrix, it is not available from `metric_from_name`. Instances must have as many features as the
+/// covariance matrix has rows.
+///
+/// The meta-ml models of `Chaoda` select clusters by the name of their metric, so scoring anomalies with this distance
+/// needs models for "mahalanobis", e.g. those of `get_meta_ml_methods` for "euclidean" with their `metric` renamed.
+pub struct Mahalanobis {
+    precision: Array2<f64>,
+}
+
+impl Mahalanobis {
+    /// Returns the distance for the given covariance matrix, or an Err if the matrix is not square, symmetric and
+    /// positive-definite.
+    pub fn new(covariance: &Array2<f64>) -> Result<Self, String> {
+        let n = covariance.nrows();
+        if covariance.ncols() != n {
+            return Err(format!(
+                "The covariance matrix must be square but has shape {:?}.",
+                covariance.shape()
+            ));
+        }
+        let scale = covariance.iter().fold(0_f64, |scale, &c| scale.max(c.abs()));
+        if (0..n).any(|i| (0..i).any(|j| (covariance[[i, j]] - covariance[[j, i]]).abs() > 1e-9 * scale)) {
+            return Err("The covariance matrix must be symmetric.".to_string());
+        }
+
+        // The Cholesky factor, `S = L L'`, exists exactly when `S` is positive-definite.
+        let mut lower = Array2::<f64>::zeros((n, n));
+        for i in 0..n {
+            for j in 0..=i {
+                let sum: f64 = (0..j).map(|k| lower[[i, k]] * lower[[j, k]]).sum();
+                if i == j {
+                    let pivot = covariance[[i, i]] - sum;
+                    if pivot <= 1e-12 * scale {
+                        return Err(
+                            "The covariance matrix must be positive-definite. Features that are constant, or linear \
+                             combinations of others, make it singular; see the ridge of `Mahalanobis::from_dataset`."
+                                .to_string(),
+                        );
+                    }
+                    lower[[i, i]] = pivot.sqrt();
+                } else {
+                    lower[[i, j]] = (covariance[[i, j]] - sum) / lower[[j, j]];
+                }
+            }
+        }
+
+        // Invert the factor by forward substitution, and then `S^-1 = L^-1' L^-1`.
+        let mut inverse = Array2::<f64>::zeros((n, n));
+        for j in 0..n {
+            inverse[[j, j]] = 1. / lower[[j, j]];
+            for i in j + 1..n {
+                let sum: f64 = (j..i).map(|k| lower[[i, k]] * inverse[[k, j]]).sum();
+                inverse[[i, j]] = -sum / lower[[i, i]];
+            }
+        }
+        let precision = inverse.t().dot(&inverse);
+
+        Ok(Mahalanobis { precision })
+    }
+
+    /// Estimates the covariance of the features from every instance of the dataset, adds `ridge` to its diagonal, and
+    /// returns the distance for it, or an Err if the estimate is not positive-definite. A small ridge, e.g. 1e-6 times
+    /// the variance of the features, regularizes covariances that are singular because some features are constant or
+    /// depend on others.
+    pub fn from_dataset<T: Number, U: Number>(dataset: &Arc<dyn Dataset<T, U>>, ridge: f64) -> Result<Self, String> {
+        let instances: Vec<Vec<f64>> = dataset
+            .indices()
+            .into_iter()
+            .map(|i| dataset.instance(i).iter().map(|v| v.as_f64()).collect())
+            .collect();
+        if instances.len() < 2 {
+            return Err("Estimating a covariance needs at least two instances.".to_string());
+        }
+        let n = instances[0].len();
+        if instances.iter().any(|instance| instance.len() != n) {
+            return Err("Estimating a covariance needs instances with the same number of features.".to_string());
+        }
+
+        let column = |j: usize| -> Vec<f64> { instances.iter().map(|instance| instance[j]).collect() };
+        let means: Vec<f64> = (0..n)
+            .map(|j| summation::sum(&column(j)) / instances.len() as f64)
+            .collect();
+        let mut covariance = Array2::<f64>::zeros((n, n));
+        for i in 0..n {
+            for j in 0..=i {
+                let products: Vec<_> = instances
+                    .iter()
+                    .map(|instance| (instance[i] - means[i]) * (instance[j] - means[j]))
+                    .collect();
+                let c = summation::sum(&products) / (instances.len() - 1) as f64;
+                covariance[[i, j]] = c;
+                covariance[[j, i]] = c;
+            }
+            covariance[[i, i]] += ridge;
+        }
+        Self::new(&covariance)
+    }
+
+    /// Returns the inverse of the covariance matrix.
+    pub fn precision(&self) -> &Array2<f64> {
+        &self.precision
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Mahalanobis {
+    fn name(&self) -> String {
+        "mahalanobis".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Exactly(self.precision.nrows())
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let difference: Array1<f64> = x.iter().zip(y.iter()).map(|(a, b)| a.as_f64() - b.as_f64()).collect();
+        // Rounding may take the quadratic form of nearly identical instances slightly below zero.
+        let squared = difference.dot(&self.precision.dot(&difference)).max(0.);
+        U::saturating_from_f64(squared.sqrt())
+    }
+}
+
+/// Implements the angle, in radians, between instances of unit euclidean length, i.e. the great-circle distance on
+/// the unit sphere.
+///
+/// The angle is computed from the dot product alone, without the norms that `Cosine` computes for every distance, so
+/// instances must be scaled to unit length first, e.g. with `RowMajor::normalized`; see `NormEnforcement`. Unlike the
+/// cosine distance, the angle obeys the triangle inequality, and it orders the instances within a right angle of each
+/// other the same way.
+pub struct Angular;
+
+impl<T: Number, U: Number> Metric<T, U> for Angular {
+    fn name(&self) -> String {
+        "angular".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn required_norm(&self) -> Option<Norm> {
+        Some(Norm::L2)
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        // Rounding may take the dot product of nearly identical instances slightly beyond one.
+        U::saturating_from_f64(dot(x, y).clamp(-1., 1.).acos())
+    }
+}
+
+/// Implements the great-circle distance between points on a sphere, by the haversine formula. Each instance is a
+/// latitude and a longitude, in that order, in degrees or radians, and the distance is in the units of the radius.
+///
+/// The distance is a metric, with pruning as exact as for euclidean distances. The haversine formula stays accurate
+/// for points that are close, where the spherical law of cosines loses precision.
+///
+/// A coordinate missing from an instance shorter than 2, e.g. a malformed query, is read as 0.
+///
+/// The name holds the radius and units, e.g. "haversine-1-rad" for the unit sphere in radians, so that trees record
+/// the distance they were built with and `metric_from_name` makes it again. The distance of `earth` is "haversine".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Haversine {
+    radius: f64,
+    degrees: bool,
+}
+
+/// The mean radius of the Earth in kilometres.
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+impl Haversine {
+    /// Returns the distance on a sphere of the given radius, for coordinates in degrees or in radians, or an Err if
+    /// the radius is not positive and finite.
+    pub fn new(radius: f64, degrees: bool) -> Result<Self, String> {
+        if !(radius.is_finite() && radius > 0.) {
+            return Err(format!("The radius must be positive and finite, not {}.", radius));
+        }
+        Ok(Haversine { radius, degrees })
+    }
+
+    /// Returns the distance in kilometres on the Earth, for coordinates in degrees, as named "haversine".
+    pub fn earth() -> Self {
+        Haversine {
+            radius: EARTH_RADIUS_KM,
+            degrees: true,
+        }
+    }
+
+    /// Returns the central angle, in radians, between two points given as latitudes and longitudes in radians.
+    fn central_angle(lat_x: f64, lon_x: f64, lat_y: f64, lon_y: f64) -> f64 {
+        let half =
+            ((lat_y - lat_x) / 2.).sin().powi(2) + lat_x.cos() * lat_y.cos() * ((lon_y - lon_x) / 2.).sin().powi(2);
+        // Rounding may take `half` of antipodal points slightly above one.
+        2. * half.sqrt().min(1.).asin()
+    }
+}
+
+impl<T: Number, U: Number> Metric<T, U> for Haversine {
+    fn name(&self) -> String {
+        match (*self == Self::earth(), self.degrees) {
+            (true, _) => "haversine".to_string(),
+            (false, true) => format!("haversine-{}", self.radius),
+            (false, false) => format!("haversine-{}-rad", self.radius),
+        }
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Exactly(2)
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let radians = |value: &T| {
+            if self.degrees {
+                value.as_f64().to_radians()
+            } else {
+                value.as_f64()
+            }
+        };
+        let coordinate = |instance: &[T], i: usize| instance.get(i).map_or(0., radians);
+        let angle = Self::central_angle(coordinate(x, 0), coordinate(x, 1), coordinate(y, 0), coordinate(y, 1));
+        U::saturating_from_f64(self.radius * angle)
+    }
+}
+
+/// Implements Hamming distance.
+/// This is not normalized by the number of features.
+pub struct Hamming;
+
+impl<T: Number, U: Number> Metric<T, U> for Hamming {
+    fn name(&self) -> String {
+        "hamming".to_string()
+    }
+
+    fn dimensionality(&self) -> Dimensionality {
+        Dimensionality::Equal
+    }
+
+    fn distance(&self, x: &[T], y: &[T]) -> U {
+        let d = x.iter().zip(y.iter()).filter(|(&a, &b)| a != b).count();
+        U::saturating_from_f64(d as f64)
+    }
+
+    fn encode(&self, x: &[T], y: &[T]) -> Result<Vec<u8>, String> {
+        let encoding = x
+            .iter()
+            .zip(y.iter())
+            .enumerate()
+            .filter(|(_, (&l, &r))| l != r)
+            .flat_map(|(i, (_, &r))| {
+                let mut i = (i as u64).to_be_bytes().to_vec();
+                i.append(&mut r.to_bytes());
+                i
+            })
+            .collect();
+        Ok(encoding)
+    }
+
+    fn decode(&self, x: &[T], y: &[u8]) -> Result<Vec<T>, String> {
+        let step = (8 + T::num_bytes()) as usize;
+        if !y.len().is_multiple_of(step) {
+            return Err(format!(
+                "A hamming encoding must be a multiple of {} bytes but has {}.",
+                step,
+                y.len()
+            ));
+        }
+        let mut decoded = x.to_owned();
+        for chunk in y.chunks(step) {
+            let (index, value) = chunk.split_at(std::mem::size_of::<u64>());
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(index);
+            let index = u64::from_be_bytes(bytes);
+            match usize::try_from(index).ok().and_then(|i| decoded.get_mut(i)) {
+                Some(element) => *element = T::from_bytes(value),
+                None => {
+                    return Err(format!(
+                        "A hamming encoding refers to index {} of a reference with only {} elements.",
+                        index,
+                        x.len()
+                    ))
+                }
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use float_cmp::approx_eq;
+    use ndarray::{arr2, Array2};
+
+    use crate::dataset::RowMajor;
+    use crate::metric::metric_from_name;
+    use crate::metric::Haversine;
+    use crate::metric::Mahalanobis;
+    use crate::metric::Minkowski;
+    use crate::Dataset;
+    use crate::Metric;
+
+    #[test]
+    fn test_on_real() {
+        let data: Array2<f64> = arr2(&[[1., 2., 3.], [3., 3., 1.]]);
+        let row0 = data.row(0).to_vec();
+        let row1 = data.row(1).to_vec();
+
+        let metric = metric_from_name("euclideansq").unwrap();
+        approx_eq!(f64, metric.distance(&row0, &row0), 0.);
+        approx_eq!(f64, metric.distance(&row0, &row1), 9.);
+
+        let metric = metric_from_name("euclidean").unwrap();
+        approx_eq!(f64, metric.distance(&row0, &row0), 0.);
+        approx_eq!(f64, metric.distance(&row0, &row1), 3.);
+
+        let metric = metric_from_name("manhattan").unwrap();
+        approx_eq!(f64, metric.distance(&row0, &row0), 0.);
+        approx_eq!(f64, metric.distance(&row0, &row1), 5.);
+    }
+
+    #[test]
+    fn test_cosine() {
+        let metric = metric_from_name::<f32, f32>("cosine").unwrap();
+        assert_eq!(metric.name(), "cosine");
+        assert!(approx_eq!(
+            f32,
+            metric.distance(&[1., 2., 3.], &[2., 4., 6.]),
+            0.,
+            epsilon = 1e-6
+        ));
+        assert!(approx_eq!(f32, metric.distance(&[1., 0.], &[0., 1.]), 1.));
+        assert!(approx_eq!(f32, metric.distance(&[1., 1.], &[-1., -1.]), 1.));
+        assert!(approx_eq!(f32, metric.distance(&[1., 0.], &[-1., 1.]), 1.));
+        assert!(approx_eq!(
+            f32,
+            metric.distance(&[1., 0.], &[1., 1.]),
+            1. - 0.5_f32.sqrt(),
+            epsilon = 1e-6
+        ));
+        assert!(approx_eq!(f32, metric.distance(&[0., 0.], &[3., 4.]), 1.));
+
+        // The angle between unit instances orders them as the cosine distance does.
+        let angular = metric_from_name::<f32, f32>("angular").unwrap();
+        assert!(approx_eq!(
+            f32,
+            angular.distance(&[1., 0.], &[0., 1.]),
+            std::f32::consts::FRAC_PI_2
+        ));
+        assert!(approx_eq!(
+            f32,
+            angular.distance(&[0.6, 0.8], &[-0.6, -0.8]),
+            std::f32::consts::PI
+        ));
+        assert_eq!(angular.distance(&[0.6, 0.8], &[0.6, 0.8]), 0.);
+
+        // Cosine distance does not obey the triangle inequality, so searches may miss hits but never return others.
+        let data: Vec<Vec<f32>> = (0..200)
+            .map(|i| (0..8).map(|j| ((i * 7 + j * 13) % 17) as f32 - 8.).collect())
+            .collect();
+        let dataset = std::sync::Arc::new(crate::dataset::RowMajor::new(std::sync::Arc::new(data), metric, false));
+        let cakes = crate::Cakes::build(dataset.as_arc_dataset(), None, None);
+        let query = cakes.dataset.instance(3);
+        let expected = cakes.linear_search(&query, Some(0.25), None);
+        let hits = cakes.rnn(&query, Some(0.25));
+        assert!(hits.iter().any(|&(i, _)| i == 3));
+        assert!(hits.iter().all(|hit| expected.contains(hit)));
+    }
+
+    #[test]
+    fn test_hamming_codec() {
+        let metric = metric_from_name::<u16, u64>("hamming").unwrap();
+        let (reference, target) = (vec![1, 2, 3, 4], vec![1, 7, 3, 9]);
+        let encoding = metric.encode(&reference, &target).unwrap();
+        assert_eq!(encoding.len(), 2 * 10);
+        assert_eq!(metric.decode(&reference, &encoding).unwrap(), target);
+
+        // Malformed encodings are errors, not panics.
+        assert!(metric.decode(&reference, &encoding[..15]).is_err());
+        assert!(metric.decode(&reference[..2], &encoding).is_err());
+        let mut huge = u64::MAX.to_be_bytes().to_vec();
+        huge.extend_from_slice(&[0, 0]);
+        assert!(metric.decode(&reference, &huge).is_err());
+    }
+
+    #[test]
+    fn test_mahalanobis() {
+        let covariance = arr2(&[[4., 0.], [0., 1.]]);
+        let metric = Mahalanobis::new(&covariance).unwrap();
+        assert!(approx_eq!(f64, metric.precision()[[0, 0]], 0.25));
+        let distance = |x: &[f64], y: &[f64]| Metric::<f64, f64>::distance(&metric, x, y);
+        assert!(approx_eq!(f64, distance(&[0., 0.], &[2., 0.]), 1.));
+        assert!(approx_eq!(f64, distance(&[0., 0.], &[0., 1.]), 1.));
+        assert_eq!(distance(&[3., 5.], &[3., 5.]), 0.);
+
+        // Along the correlation of the features, instances are closer than across it.
+        let correlated = Mahalanobis::new(&arr2(&[[1., 0.9], [0.9, 1.]])).unwrap();
+        let distance = |x: &[f64], y: &[f64]| Metric::<f64, f64>::distance(&correlated, x, y);
+        assert!(distance(&[0., 0.], &[1., 1.]) < distance(&[0., 0.], &[1., -1.]));
+
+        assert!(Mahalanobis::new(&arr2(&[[1., 2., 3.]])).is_err());
+        assert!(Mahalanobis::new(&arr2(&[[1., 0.5], [0., 1.]])).is_err());
+        assert!(Mahalanobis::new(&arr2(&[[1., 1.], [1., 1.]])).is_err());
+
+        // The variance of each feature of these instances is 2 / 3.
+        let data = vec![vec![1., 0., 5.], vec![-1., 0., 5.], vec![0., 1., 5.], vec![0., -1., 5.]];
+        let metric = metric_from_name::<f64, f64>("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        assert!(
+            Mahalanobis::from_dataset(&dataset, 0.).is_err(),
+            "The third feature is constant."
+        );
+        let estimated = Mahalanobis::from_dataset(&dataset, 1e-9).unwrap();
+        let d: f64 = estimated.distance(&[0., 0., 5.], &[1., 0., 5.]);
+        assert!(approx_eq!(f64, d, 1.5_f64.sqrt(), epsilon = 1e-6));
+    }
+
+    #[test]
+    fn test_minkowski() {
+        let (x, y) = ([1., 2., 3.], [3., 3., 1.]);
+        let distance = |name: &str| metric_from_name::<f64, f64>(name).unwrap().distance(&x, &y);
+        assert_eq!(distance("minkowski-1"), distance("manhattan"));
+        assert!(approx_eq!(f64, distance("minkowski-2"), distance("euclidean")));
+        assert!(approx_eq!(f64, distance("minkowski-3"), 17_f64.cbrt()));
+        assert!(approx_eq!(
+            f64,
+            distance("minkowski-1.5"),
+            (2. * 8_f64.sqrt() + 1.).powf(1. / 1.5)
+        ));
+        assert_eq!(distance("minkowski-inf"), 2.);
+        assert_eq!(
+            metric_from_name::<f64, f64>("minkowski-3").unwrap().name(),
+            "minkowski-3"
+        );
+        assert!(metric_from_name::<f64, f64>("minkowski-0.5").is_err());
+        assert!(metric_from_name::<f64, f64>("minkowski-x").is_err());
+
+        // Features of weight zero are ignored, even by the Chebyshev distance.
+        let weighted = Minkowski::new(3., Some(vec![1., 0., 8.])).unwrap();
+        let distance = |x: &[f64], y: &[f64]| Metric::<f64, f64>::distance(&weighted, x, y);
+        assert!(approx_eq!(f64, distance(&x, &y), 72_f64.cbrt()));
+        let chebyshev = Minkowski::new(f64::INFINITY, Some(vec![1., 0., 1.])).unwrap();
+        assert_eq!(
+            Metric::<f64, f64>::distance(&chebyshev, &[0., 0., 0.], &[1., 5., 2.]),
+            2.
+        );
+        assert_eq!(
+            Metric::<f64, f64>::dimensionality(&weighted),
+            crate::metric::Dimensionality::Exactly(3)
+        );
+        assert!(Minkowski::new(2., Some(vec![1., -1.])).is_err());
+        assert!(Minkowski::new(f64::NAN, None).is_err());
+
+        // Features beyond the weights are ignored, so an over-long query is searched by its weighted features.
+        assert!(approx_eq!(f64, distance(&[1., 2., 3., 9.], &y), 72_f64.cbrt()));
+        let data: Vec<_> = (0..100).map(|i| vec![(i % 10) as f64, 0., (i / 10) as f64]).collect();
+        let metric: Arc<dyn Metric<f64, f64>> = Arc::new(weighted);
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = crate::Cakes::build(dataset, None, None);
+        assert_eq!(cakes.rnn_indices(&[4., 7., 0., 100.], Some(0.)), vec![4]);
+        assert_eq!(cakes.knn_indices(&[4., 7., 0., 100.], 1), vec![4]);
+    }
+
+    #[test]
+    fn test_haversine() {
+        let metric = metric_from_name::<f64, f64>("haversine").unwrap();
+        let (london, paris) = ([51.5074, -0.1278], [48.8566, 2.3522]);
+        assert!(approx_eq!(f64, metric.distance(&london, &paris), 343.6, epsilon = 0.5));
+        assert_eq!(metric.distance(&london, &london), 0.);
+        assert!(approx_eq!(
+            f64,
+            metric.distance(&[0., 0.], &[0., 180.]),
+            std::f64::consts::PI * 6371.0088
+        ));
+
+        let unit = Haversine::new(1., false).unwrap();
+        let distance = |x: &[f64], y: &[f64]| Metric::<f64, f64>::distance(&unit, x, y);
+        assert!(approx_eq!(
+            f64,
+            distance(&[0., 0.], &[0., std::f64::consts::FRAC_PI_2]),
+            std::f64::consts::FRAC_PI_2
+        ));
+        assert!(Haversine::new(0., true).is_err());
+
+        // The name of every sphere and unit makes the same distance again, e.g. when a tree is loaded.
+        assert_eq!(Metric::<f64, f64>::name(&unit), "haversine-1-rad");
+        let reloaded = metric_from_name::<f64, f64>("haversine-1-rad").unwrap();
+        assert_eq!(reloaded.distance(&[0., 0.], &[0., 1.]), 1.);
+        let miles = Haversine::new(3958.8, true).unwrap();
+        assert_eq!(Metric::<f64, f64>::name(&miles), "haversine-3958.8");
+        let reloaded = metric_from_name::<f64, f64>("haversine-3958.8").unwrap();
+        assert_eq!(
+            reloaded.distance(&london, &paris),
+            Metric::<f64, f64>::distance(&miles, &london, &paris)
+        );
+        assert!(metric_from_name::<f64, f64>("haversine-0").is_err());
+        assert!(metric_from_name::<f64, f64>("haversine-x").is_err());
+
+        // Missing coordinates are read as 0 rather than panicking.
+        assert_eq!(metric.distance(&[0.], &[0., 0.]), 0.);
+        assert_eq!(metric.distance(&[], &london), metric.distance(&[0., 0.], &london));
+
+        // Great-circle distances obey the triangle inequality, so searches with them prune exactly.
+        assert!(metric.obeys_triangle_inequality());
+        assert!(!metric_from_name::<f64, f64>("cosine")
+            .unwrap()
+            .obeys_triangle_inequality());
+        let points: Vec<_> = (0..20)
+            .map(|i| [(i * 37 % 170) as f64 - 85., (i * 71 % 360) as f64 - 180.])
+            .collect();
+        for x in &points {
+            for y in &points {
+                for z in &points {
+                    assert!(metric.distance(x, z) <= metric.distance(x, y) + metric.distance(y, z) + 1e-9);
+                }
+            }
+        }
+    }
+}
@@ -2,6 +2,14 @@ pub use dataset::Dataset;
 pub use metric::Metric;
 pub use number::Number;
 
+#[cfg(feature = "arrow")]
+pub mod arrow;
 pub mod dataset;
+#[cfg(feature = "hdf5")]
+pub mod hdf5;
 pub mod metric;
+#[cfg(feature = "chemistry")]
+pub mod molecules;
 mod number;
+#[cfg(feature = "object-store")]
+pub mod object_sources;
@@ -0,0 +1,660 @@
+//! Molecules read from SMILES strings, and a `Dataset` of their fingerprints for tanimoto search, behind the
+//! `chemistry` feature.
+//!
+//! The fingerprints are circular (Morgan) fingerprints in the style of ECFP: each atom starts with an identifier
+//! hashed from its element, degree, hydrogens, charge and aromaticity, and in each of `radius` iterations the
+//! identifier of each atom is hashed with the bonds to, and identifiers of, its neighbors. Every identifier from every
+//! iteration sets one bit of the folded fingerprint. A radius of 2 corresponds to ECFP4.
+//!
+//! The SMILES parser reads the organic subset, bracket atoms, bonds, branches, ring closures and disconnected parts,
+//! but it does not perceive aromaticity, so the Kekulé and aromatic forms of a molecule have different fingerprints,
+//! and it ignores stereochemistry. The fingerprints do not depend on the order in which the atoms are written, but
+//! they are not bit-for-bit those of other toolkits.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::prelude::*;
+
+use crate::dataset::BitPackedDataset;
+use crate::io::content_hash;
+use crate::prelude::*;
+
+/// An atom of a `Molecule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Atom {
+    /// The element symbol, capitalized, e.g. "C" or "Cl", or "*" for a wildcard.
+    pub element: String,
+    /// Whether the atom was written in lower case, as part of an aromatic ring.
+    pub aromatic: bool,
+    pub charge: i32,
+    pub isotope: Option<u32>,
+    /// The number of attached hydrogens, given in brackets or implied by the valence of the organic subset.
+    pub hydrogens: u32,
+}
+
+/// The order of a bond between two atoms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BondOrder {
+    Single,
+    Double,
+    Triple,
+    Quadruple,
+    Aromatic,
+}
+
+/// The atoms and bonds of a molecule, as read from a SMILES string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Molecule {
+    pub atoms: Vec<Atom>,
+    /// The bonds, as the indices of the two atoms and the order of the bond.
+    pub bonds: Vec<(usize, usize, BondOrder)>,
+}
+
+/// The parameters of the circular fingerprints of a `MoleculeDataset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FingerprintOptions {
+    /// The number of iterations over the neighbors of each atom. Defaults to 2, as in ECFP4.
+    pub radius: usize,
+
+    /// The number of bits to fold the identifiers into. Defaults to 2048.
+    pub num_bits: usize,
+}
+
+impl Default for FingerprintOptions {
+    fn default() -> Self {
+        FingerprintOptions {
+            radius: 2,
+            num_bits: 2048,
+        }
+    }
+}
+
+impl Molecule {
+    /// Reads a molecule from a SMILES string, e.g. "CC(=O)Oc1ccccc1C(=O)O".
+    ///
+    /// Returns an Err if the string is not valid SMILES, e.g. with an unknown element, an unclosed ring or branch,
+    /// or a bond to nothing.
+    pub fn from_smiles(smiles: &str) -> Result<Self, String> {
+        parse_smiles(smiles).map_err(|error| format!("Invalid SMILES '{}': {}", smiles, error))
+    }
+
+    /// Returns the neighbors of each atom, with the orders of the bonds to them, at the position of the atom.
+    pub fn neighbors(&self) -> Vec<Vec<(usize, BondOrder)>> {
+        let mut neighbors = vec![vec![]; self.atoms.len()];
+        for &(a, b, order) in self.bonds.iter() {
+            neighbors[a].push((b, order));
+            neighbors[b].push((a, order));
+        }
+        neighbors
+    }
+
+    /// Returns the circular fingerprint of the molecule as packed words, in the layout of `BitPackedDataset`.
+    pub fn fingerprint(&self, options: FingerprintOptions) -> Vec<u64> {
+        let neighbors = self.neighbors();
+        let mut identifiers: Vec<u64> = self
+            .atoms
+            .iter()
+            .zip(neighbors.iter())
+            .map(|(atom, neighbors)| {
+                let invariants = format!(
+                    "{}|{}|{}|{}|{}|{:?}",
+                    atom.element,
+                    neighbors.len(),
+                    atom.hydrogens,
+                    atom.charge,
+                    atom.aromatic,
+                    atom.isotope
+                );
+                content_hash(invariants.as_bytes())
+            })
+            .collect();
+
+        let mut words = vec![0; options.num_bits.div_ceil(64)];
+        let mut set = |identifier: u64| {
+            if options.num_bits > 0 {
+                let bit = (identifier % options.num_bits as u64) as usize;
+                words[bit / 64] |= 1 << (bit % 64);
+            }
+        };
+        identifiers.iter().copied().for_each(&mut set);
+
+        for iteration in 0..options.radius {
+            identifiers = neighbors
+                .iter()
+                .zip(identifiers.iter())
+                .map(|(neighbors, &identifier)| {
+                    // The neighbors are sorted so that the identifier does not depend on the order of the atoms.
+                    let mut environment: Vec<_> = neighbors
+                        .iter()
+                        .map(|&(neighbor, order)| (order as u8, identifiers[neighbor]))
+                        .collect();
+                    environment.sort_unstable();
+                    let mut bytes = vec![iteration as u8];
+                    bytes.extend_from_slice(&identifier.to_be_bytes());
+                    for (order, neighbor) in environment {
+                        bytes.push(order);
+                        bytes.extend_from_slice(&neighbor.to_be_bytes());
+                    }
+                    content_hash(&bytes)
+                })
+                .collect();
+            identifiers.iter().copied().for_each(&mut set);
+        }
+        words
+    }
+}
+
+/// The elements of the organic subset, which may be written without brackets, and their usual valences.
+const ORGANIC_SUBSET: [(&str, &[u32]); 11] = [
+    ("B", &[3]),
+    ("C", &[4]),
+    ("N", &[3, 5]),
+    ("O", &[2]),
+    ("P", &[3, 5]),
+    ("S", &[2, 4, 6]),
+    ("F", &[1]),
+    ("Cl", &[1]),
+    ("Br", &[1]),
+    ("I", &[1]),
+    ("*", &[]),
+];
+
+/// The elements that may be written in lower case, as aromatic atoms.
+const AROMATIC: [&str; 8] = ["b", "c", "n", "o", "p", "s", "se", "as"];
+
+const ELEMENTS: [&str; 118] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl", "Ar", "K", "Ca", "Sc",
+    "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr",
+    "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In", "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr",
+    "Nd", "Pm", "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir", "Pt",
+    "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk",
+    "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh", "Fl", "Mc", "Lv",
+    "Ts", "Og",
+];
+
+/// The state of a SMILES parser.
+struct Parser {
+    atoms: Vec<Atom>,
+    bonds: Vec<(usize, usize, BondOrder)>,
+    /// Whether each atom was written in brackets, so that its hydrogens are not implied.
+    bracketed: Vec<bool>,
+    previous: Option<usize>,
+    branches: Vec<Option<usize>>,
+    bond: Option<BondOrder>,
+    /// The atom and bond order at which each open ring closure was opened.
+    rings: HashMap<u32, (usize, Option<BondOrder>)>,
+}
+
+fn parse_smiles(smiles: &str) -> Result<Molecule, String> {
+    let mut parser = Parser {
+        atoms: vec![],
+        bonds: vec![],
+        bracketed: vec![],
+        previous: None,
+        branches: vec![],
+        bond: None,
+        rings: HashMap::new(),
+    };
+
+    let chars: Vec<char> = smiles.trim().chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '(' => parser
+                .branches
+                .push(Some(parser.previous.ok_or("A branch opens before any atom.")?)),
+            ')' => {
+                parser.previous = parser.branches.pop().ok_or("A branch closes without opening.")?;
+                parser.bond.take().map_or(Ok(()), |_| Err("A bond ends a branch."))?;
+            }
+            '-' | '/' | '\\' => parser.set_bond(BondOrder::Single)?,
+            '=' => parser.set_bond(BondOrder::Double)?,
+            '#' => parser.set_bond(BondOrder::Triple)?,
+            '$' => parser.set_bond(BondOrder::Quadruple)?,
+            ':' => parser.set_bond(BondOrder::Aromatic)?,
+            '.' => {
+                parser.bond.take().map_or(Ok(()), |_| Err("A bond precedes a '.'."))?;
+                parser.previous = None;
+            }
+            '0'..='9' => parser.ring(c.to_digit(10).unwrap_or_default())?,
+            '%' => {
+                let digits: String = chars.iter().skip(i).take(2).collect();
+                let number = digits
+                    .parse()
+                    .ok()
+                    .filter(|_| digits.len() == 2)
+                    .ok_or("A '%' must be followed by two digits.")?;
+                i += 2;
+                parser.ring(number)?;
+            }
+            '[' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == ']')
+                    .ok_or("A bracket atom does not close.")?;
+                let atom = parse_bracket_atom(&chars[i..i + end].iter().collect::<String>())?;
+                i += end + 1;
+                parser.add_atom(atom, true)?;
+            }
+            _ => {
+                let two: String = chars[i - 1..std::cmp::min(i + 1, chars.len())].iter().collect();
+                let symbol = if two == "Cl" || two == "Br" {
+                    i += 1;
+                    two
+                } else {
+                    c.to_string()
+                };
+                let (element, aromatic) = if AROMATIC.contains(&symbol.as_str()) {
+                    (capitalize(&symbol), true)
+                } else if ORGANIC_SUBSET.iter().any(|&(element, _)| element == symbol) {
+                    (symbol, false)
+                } else {
+                    return Err(format!("'{}' is not an atom of the organic subset.", symbol));
+                };
+                let atom = Atom {
+                    element,
+                    aromatic,
+                    charge: 0,
+                    isotope: None,
+                    hydrogens: 0,
+                };
+                parser.add_atom(atom, false)?;
+            }
+        }
+    }
+
+    if parser.bond.is_some() {
+        return Err("A bond ends the string.".to_string());
+    }
+    if !parser.branches.is_empty() {
+        return Err("A branch does not close.".to_string());
+    }
+    if let Some(number) = parser.rings.keys().min() {
+        return Err(format!("Ring {} does not close.", number));
+    }
+    if parser.atoms.is_empty() {
+        return Err("There are no atoms.".to_string());
+    }
+    parser.imply_hydrogens();
+
+    Ok(Molecule {
+        atoms: parser.atoms,
+        bonds: parser.bonds,
+    })
+}
+
+impl Parser {
+    fn set_bond(&mut self, order: BondOrder) -> Result<(), String> {
+        match self.bond.replace(order) {
+            Some(_) => Err("Two bonds follow each other.".to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn add_atom(&mut self, atom: Atom, bracketed: bool) -> Result<(), String> {
+        let index = self.atoms.len();
+        self.atoms.push(atom);
+        self.bracketed.push(bracketed);
+        match (self.previous, self.bond.take()) {
+            (Some(previous), bond) => self.add_bond(previous, index, bond),
+            (None, Some(_)) => return Err("A bond does not follow an atom.".to_string()),
+            (None, None) => (),
+        }
+        self.previous = Some(index);
+        Ok(())
+    }
+
+    /// Adds a bond of the given order, or of the order implied by the atoms.
+    fn add_bond(&mut self, a: usize, b: usize, order: Option<BondOrder>) {
+        let order = order.unwrap_or(if self.atoms[a].aromatic && self.atoms[b].aromatic {
+            BondOrder::Aromatic
+        } else {
+            BondOrder::Single
+        });
+        self.bonds.push((a, b, order));
+    }
+
+    /// Opens or closes the numbered ring at the previous atom.
+    fn ring(&mut self, number: u32) -> Result<(), String> {
+        let atom = self.previous.ok_or("A ring closure precedes every atom.")?;
+        let bond = self.bond.take();
+        match self.rings.remove(&number) {
+            Some((start, _)) if start == atom => Err(format!("Ring {} closes on the atom that opened it.", number)),
+            Some((start, opening)) => {
+                if opening.is_some() && bond.is_some() && opening != bond {
+                    return Err(format!("Ring {} has different bonds at its ends.", number));
+                }
+                self.add_bond(start, atom, bond.or(opening));
+                Ok(())
+            }
+            None => {
+                self.rings.insert(number, (atom, bond));
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the hydrogens of the atoms of the organic subset to fill their lowest usual valence that their bonds do
+    /// not exceed. Aromatic atoms count one more bond, for their share of the aromatic system.
+    fn imply_hydrogens(&mut self) {
+        let mut valences = vec![0; self.atoms.len()];
+        for &(a, b, order) in self.bonds.iter() {
+            let order = match order {
+                BondOrder::Single | BondOrder::Aromatic => 1,
+                BondOrder::Double => 2,
+                BondOrder::Triple => 3,
+                BondOrder::Quadruple => 4,
+            };
+            valences[a] += order;
+            valences[b] += order;
+        }
+        for ((atom, &bracketed), valence) in self.atoms.iter_mut().zip(self.bracketed.iter()).zip(valences) {
+            if bracketed {
+                continue;
+            }
+            let valence = valence + atom.aromatic as u32;
+            let usual = ORGANIC_SUBSET
+                .iter()
+                .find(|&&(element, _)| element == atom.element)
+                .map_or(&[][..], |&(_, usual)| usual);
+            atom.hydrogens = usual.iter().find(|&&v| v >= valence).map_or(0, |&v| v - valence);
+        }
+    }
+}
+
+/// Reads the contents of a bracket atom, e.g. "13CH4", "nH", "Fe+2" or "C@@H".
+fn parse_bracket_atom(contents: &str) -> Result<Atom, String> {
+    let chars: Vec<char> = contents.chars().collect();
+    let mut i = 0;
+    let number = |i: &mut usize| {
+        let start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>().parse::<u32>().ok()
+    };
+
+    let isotope = number(&mut i);
+    let letters = chars[i..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+    // The longest known symbol wins, e.g. "Cl" over "C" but "C" in "CH4".
+    let (symbol, aromatic) = (1..=std::cmp::min(letters, 2))
+        .rev()
+        .map(|length| chars[i..i + length].iter().collect::<String>())
+        .find_map(|symbol| {
+            if AROMATIC.contains(&symbol.as_str()) {
+                Some((capitalize(&symbol), true))
+            } else if symbol == "*" || ELEMENTS.contains(&symbol.as_str()) {
+                Some((symbol, false))
+            } else {
+                None
+            }
+        })
+        .or_else(|| (chars.get(i) == Some(&'*')).then(|| ("*".to_string(), false)))
+        .ok_or_else(|| format!("'[{}]' has no known element.", contents))?;
+    i += if symbol == "*" { 1 } else { symbol.len() };
+
+    while chars.get(i) == Some(&'@') {
+        i += 1;
+    }
+    let hydrogens = if chars.get(i) == Some(&'H') {
+        i += 1;
+        number(&mut i).unwrap_or(1)
+    } else {
+        0
+    };
+    let mut charge = 0;
+    while let Some(&sign @ ('+' | '-')) = chars.get(i) {
+        i += 1;
+        let sign = if sign == '+' { 1 } else { -1 };
+        charge += sign * number(&mut i).map_or(1, |n| n as i32);
+    }
+    if chars.get(i) == Some(&':') {
+        i += 1;
+        number(&mut i).ok_or_else(|| format!("'[{}]' has an empty atom class.", contents))?;
+    }
+    if i != chars.len() {
+        return Err(format!("'[{}]' has unexpected characters.", contents));
+    }
+
+    Ok(Atom {
+        element: symbol,
+        aromatic,
+        charge,
+        isotope,
+        hydrogens,
+    })
+}
+
+fn capitalize(symbol: &str) -> String {
+    let mut chars = symbol.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+/// MoleculeDataset holds the circular fingerprints of molecules, packed into bits, for search with the tanimoto or
+/// hamming metric. Instances are the fingerprints, with every bit as zero or one.
+pub struct MoleculeDataset<U: Number> {
+    fingerprints: BitPackedDataset<u8, U>,
+    smiles: Vec<String>,
+    names: Vec<Option<String>>,
+}
+
+impl<U: Number> std::fmt::Debug for MoleculeDataset<U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("MoleculeDataset")
+            .field("data-cardinality", &self.smiles.len())
+            .field("data-dimensionality", &self.fingerprints.dimensionality())
+            .finish()
+    }
+}
+
+impl<U: 'static + Number> MoleculeDataset<U> {
+    /// Parses and fingerprints the SMILES strings.
+    ///
+    /// Returns an Err, naming the position of the string, if a string is not valid SMILES.
+    pub fn from_smiles<S: AsRef<str>>(
+        smiles: &[S],
+        options: FingerprintOptions,
+        metric: Arc<dyn Metric<u8, U>>,
+    ) -> Result<Self, String> {
+        let named: Vec<_> = smiles.iter().map(|s| (s.as_ref().to_string(), None)).collect();
+        Self::from_named(named, options, metric)
+    }
+
+    /// Reads a SMILES file, with one molecule per line as a SMILES string optionally followed by whitespace and a
+    /// name, as in `.smi` files. Empty lines and lines starting with '#' are skipped.
+    pub fn from_file(path: &Path, options: FingerprintOptions, metric: Arc<dyn Metric<u8, U>>) -> Result<Self, String> {
+        let fail = |error: String| format!("Error: Failed to read {}. {}", path.display(), error);
+        let text = std::fs::read_to_string(path).map_err(|error| fail(error.to_string()))?;
+        let named: Vec<_> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once(char::is_whitespace) {
+                Some((smiles, name)) => (smiles.to_string(), Some(name.trim().to_string())),
+                None => (line.to_string(), None),
+            })
+            .collect();
+        Self::from_named(named, options, metric).map_err(fail)
+    }
+
+    fn from_named(
+        named: Vec<(String, Option<String>)>,
+        options: FingerprintOptions,
+        metric: Arc<dyn Metric<u8, U>>,
+    ) -> Result<Self, String> {
+        let mut words = Vec::with_capacity(named.len() * options.num_bits.div_ceil(64));
+        for (i, (smiles, _)) in named.iter().enumerate() {
+            let molecule = Molecule::from_smiles(smiles).map_err(|error| format!("Molecule {}: {}", i, error))?;
+            words.extend(molecule.fingerprint(options));
+        }
+        let (smiles, names) = named.into_iter().unzip();
+        Ok(MoleculeDataset {
+            fingerprints: BitPackedDataset::from_words(words, options.num_bits, metric)?,
+            smiles,
+            names,
+        })
+    }
+
+    /// Returns the SMILES string of the molecule at the given index.
+    pub fn smiles(&self, index: Index) -> &str {
+        &self.smiles[index]
+    }
+
+    /// Returns the name of the molecule at the given index, if its file named it.
+    pub fn name(&self, index: Index) -> Option<&str> {
+        self.names[index].as_deref()
+    }
+
+    /// Returns the packed fingerprints.
+    pub fn fingerprints(&self) -> &BitPackedDataset<u8, U> {
+        &self.fingerprints
+    }
+
+    pub fn as_arc_dataset(self: Arc<Self>) -> Arc<dyn Dataset<u8, U>> {
+        self
+    }
+}
+
+impl<U: Number> Dataset<u8, U> for MoleculeDataset<U> {
+    fn metric(&self) -> Arc<dyn Metric<u8, U>> {
+        self.fingerprints.metric()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.smiles.len()
+    }
+
+    fn dimensionality(&self) -> usize {
+        self.fingerprints.dimensionality()
+    }
+
+    fn indices(&self) -> Vec<Index> {
+        (0..self.smiles.len()).collect()
+    }
+
+    fn instance(&self, index: Index) -> Vec<u8> {
+        self.fingerprints.instance(index)
+    }
+
+    fn instance_size(&self) -> usize {
+        self.fingerprints.instance_size()
+    }
+
+    fn distance(&self, left: Index, right: Index) -> U {
+        self.fingerprints.distance(left, right)
+    }
+
+    fn distances_from(&self, left: Index, right: &[Index]) -> Array1<U> {
+        self.fingerprints.distances_from(left, right)
+    }
+
+    fn distances_among(&self, left: &[Index], right: &[Index]) -> Array2<U> {
+        self.fingerprints.distances_among(left, right)
+    }
+
+    fn pairwise_distances(&self, indices: &[Index]) -> Array2<U> {
+        self.fingerprints.pairwise_distances(indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::BondOrder;
+    use super::FingerprintOptions;
+    use super::Molecule;
+    use super::MoleculeDataset;
+
+    #[test]
+    fn test_smiles() {
+        let aspirin = Molecule::from_smiles("CC(=O)Oc1ccccc1C(=O)O").unwrap();
+        assert_eq!(aspirin.atoms.len(), 13);
+        assert_eq!(aspirin.bonds.len(), 13);
+        assert_eq!(aspirin.bonds.iter().filter(|b| b.2 == BondOrder::Aromatic).count(), 6);
+        assert_eq!(aspirin.atoms.iter().map(|a| a.hydrogens).sum::<u32>(), 8);
+
+        let ammonium = Molecule::from_smiles("[15NH4+].[Cl-]").unwrap();
+        assert_eq!((ammonium.atoms[0].hydrogens, ammonium.atoms[0].charge), (4, 1));
+        assert_eq!(ammonium.atoms[0].isotope, Some(15));
+        assert_eq!((ammonium.atoms[1].element.as_str(), ammonium.bonds.len()), ("Cl", 0));
+        assert_eq!(Molecule::from_smiles("C%12CC%12").unwrap().bonds.len(), 3);
+        assert_eq!(Molecule::from_smiles("[nH]1cccc1").unwrap().atoms[0].element, "N");
+
+        for invalid in ["C1CC", "C(C", "C)C", "C=", "=C", "[Xx]", "Q", "C==C", "", "[C"] {
+            assert!(Molecule::from_smiles(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let options = FingerprintOptions::default();
+        let fingerprint = |smiles| Molecule::from_smiles(smiles).unwrap().fingerprint(options);
+        assert_eq!(fingerprint("CCO").len(), 32);
+        // The fingerprint does not depend on the order of the atoms.
+        assert_eq!(fingerprint("CCO"), fingerprint("OCC"));
+        assert_eq!(fingerprint("c1ccccc1O"), fingerprint("Oc1ccccc1"));
+        assert_ne!(fingerprint("CCO"), fingerprint("CCN"));
+
+        let bits = |words: Vec<u64>| words.iter().map(|w| w.count_ones()).sum::<u32>();
+        assert!(bits(fingerprint("CCCCCCO")) > bits(fingerprint("CCO")));
+        let shallow = Molecule::from_smiles("CCCCCCO")
+            .unwrap()
+            .fingerprint(FingerprintOptions {
+                radius: 0,
+                num_bits: 64,
+            });
+        assert!(bits(shallow) <= 3);
+    }
+
+    #[test]
+    fn test_molecule_dataset() {
+        let path = std::env::temp_dir().join(format!("clam-molecules-{}.smi", std::process::id()));
+        let lines = [
+            "# A few small molecules.",
+            "CCO ethanol",
+            "CCCO propanol",
+            "CCCCO butanol",
+            "c1ccccc1 benzene",
+            "Cc1ccccc1 toluene",
+            "CC(=O)Oc1ccccc1C(=O)O aspirin",
+            "OC(=O)c1ccccc1O",
+            "",
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let metric = metric_from_name::<u8, f64>("tanimoto").unwrap();
+        let dataset = MoleculeDataset::from_file(&path, FingerprintOptions::default(), Arc::clone(&metric)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(dataset.cardinality(), 7);
+        assert_eq!(dataset.dimensionality(), 2048);
+        assert_eq!(dataset.name(3), Some("benzene"));
+        assert_eq!((dataset.smiles(6), dataset.name(6)), ("OC(=O)c1ccccc1O", None));
+        assert_eq!(dataset.distance(0, 0), 0.);
+        assert!(dataset.distance(0, 1) < dataset.distance(0, 3));
+        let (x, y) = (dataset.instance(4), dataset.instance(5));
+        assert!(float_cmp::approx_eq!(
+            f64,
+            dataset.distance(4, 5),
+            metric.distance(&x, &y)
+        ));
+
+        let dataset = Arc::new(dataset).as_arc_dataset();
+        let cakes = Cakes::build(Arc::clone(&dataset), None, None);
+        let hits = cakes.rnn_indices(&dataset.instance(1), Some(0.75));
+        assert!(hits.contains(&0) && hits.contains(&1) && hits.contains(&2));
+        assert!(!hits.contains(&5));
+
+        assert!(MoleculeDataset::from_smiles(&["CCO", "C1CC"], FingerprintOptions::default(), metric).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 //! A `Number` is a general numeric type.
 //!
 //! We calculate distances over collections of `Numbers`.
+//! Statistics should convert `Numbers` with `as_f64`, `checked_from_f64` and `saturating_from_f64`, whose behavior is
+//! the same in debug and release builds.
 
 use std::convert::TryInto;
 use std::fmt::Debug;
@@ -42,12 +44,42 @@ pub trait Number:
     /// This must be the inverse of to_bytes.
     fn from_bytes(bytes: &[u8]) -> Self;
 
-    /// Convers thhe number to an f64 for some helpful functions.
+    /// Converts the number to the nearest f64. This never panics, but integers beyond 2^53 in magnitude may be
+    /// rounded.
     fn as_f64(&self) -> f64;
+
+    /// Converts the number to the nearest f32. This never panics, but integers beyond 2^24 in magnitude may be
+    /// rounded, and f64s beyond the range of f32 become infinite.
+    fn as_f32(&self) -> f32;
+
+    /// Converts the f64 to this type if it can be represented, up to the rounding of floats to the type's precision.
+    ///
+    /// Returns None for non-integral values, NaN and infinities if this is an integer type, and for finite values
+    /// beyond the range of this type.
+    fn checked_from_f64(value: f64) -> Option<Self>;
+
+    /// Converts the f64 to this type, clamping values beyond its range to its minimum or maximum.
+    ///
+    /// Integers are rounded toward zero and NaN becomes 0. Floats keep NaN.
+    fn saturating_from_f64(value: f64) -> Self;
+
+    /// Returns the name of the primitive type, e.g. "f32".
+    ///
+    /// This is stored in serialized artifacts so that they are never read back as a different type.
+    fn type_name() -> &'static str;
+
+    /// Returns the bits of the number, zero-extended to 64, e.g. to count the set bits of a word of packed bits.
+    fn raw_bits(&self) -> u64;
+
+    /// Returns the values as a slice of `f32`, without copying, if this is `f32`, e.g. for vector kernels.
+    fn as_f32s(values: &[Self]) -> Option<&[f32]>;
+
+    /// Returns the values as a slice of `f64`, without copying, if this is `f64`, e.g. for vector kernels.
+    fn as_f64s(values: &[Self]) -> Option<&[f64]>;
 }
 
 macro_rules! impl_number {
-    ($($ty:ty),*) => {
+    ($($ty:ty: $is_float:expr),*) => {
         $(
             impl Number for $ty {
                 fn num_bytes() -> u8 {
@@ -64,11 +96,108 @@ macro_rules! impl_number {
                 }
 
                 fn as_f64(&self) -> f64 {
-                    f64::conv(*self)
+                    *self as f64
+                }
+
+                fn as_f32(&self) -> f32 {
+                    *self as f32
+                }
+
+                fn checked_from_f64(value: f64) -> Option<$ty> {
+                    let (min, max) = (<$ty>::MIN as f64, <$ty>::MAX as f64);
+                    if $is_float {
+                        (!value.is_finite() || (min..=max).contains(&value)).then(|| value as $ty)
+                    } else {
+                        // The maximum of a wide integer type rounds up to a power of 2, which is itself out of range.
+                        let in_range = value >= min && value < max + 1.;
+                        (value.fract() == 0. && in_range).then(|| value as $ty)
+                    }
+                }
+
+                fn saturating_from_f64(value: f64) -> $ty {
+                    if $is_float && !value.is_nan() {
+                        value.clamp(<$ty>::MIN as f64, <$ty>::MAX as f64) as $ty
+                    } else {
+                        // Casts from floats to integers saturate, round toward zero and map NaN to 0.
+                        value as $ty
+                    }
+                }
+
+                fn type_name() -> &'static str {
+                    stringify!($ty)
+                }
+
+                fn raw_bits(&self) -> u64 {
+                    let mut bytes = [0; 8];
+                    bytes[..std::mem::size_of::<$ty>()].copy_from_slice(&<$ty>::to_le_bytes(*self));
+                    u64::from_le_bytes(bytes)
+                }
+
+                fn as_f32s(values: &[$ty]) -> Option<&[f32]> {
+                    // The slice is cast only to its own type.
+                    (std::any::TypeId::of::<$ty>() == std::any::TypeId::of::<f32>())
+                        .then(|| unsafe { std::slice::from_raw_parts(values.as_ptr().cast(), values.len()) })
+                }
+
+                fn as_f64s(values: &[$ty]) -> Option<&[f64]> {
+                    (std::any::TypeId::of::<$ty>() == std::any::TypeId::of::<f64>())
+                        .then(|| unsafe { std::slice::from_raw_parts(values.as_ptr().cast(), values.len()) })
                 }
             }
         )*
     }
 }
 
-impl_number!(f32, f64, u8, i8, u16, i16, u32, i32, u64, i64);
+impl_number!(
+    f32: true,
+    f64: true,
+    u8: false,
+    i8: false,
+    u16: false,
+    i16: false,
+    u32: false,
+    i32: false,
+    u64: false,
+    i64: false
+);
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(u64::MAX.as_f64(), 2_f64.powi(64));
+        assert_eq!((-3_i8).as_f32(), -3.);
+        assert_eq!(1e300.as_f32(), f32::INFINITY);
+
+        assert_eq!(u8::checked_from_f64(255.), Some(255));
+        assert_eq!(u8::checked_from_f64(256.), None);
+        assert_eq!(u8::checked_from_f64(-1.), None);
+        assert_eq!(u8::checked_from_f64(1.5), None);
+        assert_eq!(i64::checked_from_f64(-(2_f64.powi(63))), Some(i64::MIN));
+        assert_eq!(i64::checked_from_f64(2_f64.powi(63)), None);
+        assert_eq!(u64::checked_from_f64(2_f64.powi(64)), None);
+        assert_eq!(u32::checked_from_f64(f64::NAN), None);
+        assert_eq!(f32::checked_from_f64(0.1), Some(0.1));
+        assert_eq!(f32::checked_from_f64(1e300), None);
+        assert_eq!(f32::checked_from_f64(f64::NEG_INFINITY), Some(f32::NEG_INFINITY));
+        assert!(f64::checked_from_f64(f64::NAN).unwrap().is_nan());
+
+        assert_eq!((-1_i8).raw_bits(), 0xff);
+        assert_eq!(u64::MAX.raw_bits(), u64::MAX);
+        assert_eq!(1_f32.raw_bits(), 1_f32.to_bits() as u64);
+
+        assert_eq!(f32::as_f32s(&[1., 2.]), Some(&[1_f32, 2.][..]));
+        assert_eq!(f64::as_f64s(&[3.]), Some(&[3_f64][..]));
+        assert!(f64::as_f32s(&[3.]).is_none() && u32::as_f32s(&[3]).is_none());
+
+        assert_eq!(u8::saturating_from_f64(300.), 255);
+        assert_eq!(u8::saturating_from_f64(-7.9), 0);
+        assert_eq!(i16::saturating_from_f64(-7.9), -7);
+        assert_eq!(i32::saturating_from_f64(f64::NAN), 0);
+        assert_eq!(f32::saturating_from_f64(1e300), f32::MAX);
+        assert_eq!(f64::saturating_from_f64(f64::INFINITY), f64::MAX);
+        assert!(f32::saturating_from_f64(f64::NAN).is_nan());
+    }
+}
@@ -0,0 +1,121 @@
+//! Clients of S3 and GCS implementing `ObjectSource`, for `ObjectStoreDataset`, behind the `object-store` feature.
+//!
+//! The clients are those of the `object_store` crate, whose requests are asynchronous. Each `StoreSource` blocks on
+//! them with a tokio runtime of its own, so it may be called from the threads of the rayon pool but not from within
+//! another async runtime.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use tokio::runtime::Runtime;
+
+use crate::dataset::ObjectSource;
+
+/// An `ObjectSource` that reads ranges of one object in a store of the `object_store` crate, e.g. S3 or GCS.
+#[derive(Debug)]
+pub struct StoreSource {
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+    runtime: Runtime,
+}
+
+impl StoreSource {
+    /// Serves the object at the given path in the store, e.g. an `object_store::memory::InMemory` in tests or a store
+    /// configured in ways that `s3` and `gcs` do not cover.
+    pub fn new(store: Arc<dyn ObjectStore>, path: &str) -> Result<Self, String> {
+        let path = Path::parse(path).map_err(|error| format!("Error: Invalid object path {}. {}", path, error))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|error| format!("Error: Failed to start a runtime for {}. {}", path, error))?;
+        Ok(StoreSource { store, path, runtime })
+    }
+
+    /// Serves the object with the given key in an S3 bucket.
+    ///
+    /// The credentials, region and endpoint are read from the environment, e.g. from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_REGION`.
+    pub fn s3(bucket: &str, key: &str) -> Result<Self, String> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(|error| format!("Error: Failed to connect to S3 bucket {}. {}", bucket, error))?;
+        Self::new(Arc::new(store), key)
+    }
+
+    /// Serves the object with the given name in a GCS bucket.
+    ///
+    /// The credentials are read from the environment, e.g. from `GOOGLE_SERVICE_ACCOUNT` or
+    /// `GOOGLE_APPLICATION_CREDENTIALS`.
+    pub fn gcs(bucket: &str, name: &str) -> Result<Self, String> {
+        let store = GoogleCloudStorageBuilder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(|error| format!("Error: Failed to connect to GCS bucket {}. {}", bucket, error))?;
+        Self::new(Arc::new(store), name)
+    }
+}
+
+impl ObjectSource for StoreSource {
+    fn len(&self) -> Result<u64, String> {
+        self.runtime
+            .block_on(self.store.head(&self.path))
+            .map(|meta| meta.size)
+            .map_err(|error| format!("Error: Failed to read {} in {}. {}", self.path, self.store, error))
+    }
+
+    fn read_range(&self, range: Range<u64>) -> Result<Vec<u8>, String> {
+        self.runtime
+            .block_on(self.store.get_range(&self.path, range))
+            .map(|bytes| bytes.to_vec())
+            .map_err(|error| format!("Error: Failed to read {} in {}. {}", self.path, self.store, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ndarray::prelude::*;
+    use ndarray_npy::WriteNpyExt;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use object_store::ObjectStore;
+
+    use crate::dataset::ObjectSource;
+    use crate::dataset::ObjectStoreDataset;
+    use crate::prelude::*;
+
+    use super::StoreSource;
+
+    #[test]
+    fn test_store_source() {
+        let data: Vec<Vec<f32>> = (0..90).map(|i| vec![i as f32, (i % 7) as f32]).collect();
+        let array = Array2::from_shape_vec((90, 2), data.iter().flatten().cloned().collect()).unwrap();
+        let mut bytes = Vec::new();
+        array.write_npy(&mut bytes).unwrap();
+
+        let store = Arc::new(InMemory::new());
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime
+            .block_on(store.put(&Path::from("data/train.npy"), bytes.clone().into()))
+            .unwrap();
+
+        let source = StoreSource::new(store.clone(), "data/train.npy").unwrap();
+        assert_eq!(source.len().unwrap(), bytes.len() as u64);
+        assert_eq!(source.read_range(3..9).unwrap(), bytes[3..9].to_vec());
+
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset = ObjectStoreDataset::<f32, f32>::open_npy(Arc::new(source), 16, 2, metric).unwrap();
+        assert!(dataset.validate().is_ok());
+        assert_eq!((dataset.cardinality(), dataset.instance(77)), (90, data[77].clone()));
+
+        let missing = StoreSource::new(store, "data/test.npy").unwrap();
+        assert!(missing.len().is_err());
+        assert!(missing.read_range(0..4).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Estimating the density of a dataset around each of its instances.
+
+use std::sync::Arc;
+
+use crate::prelude::*;
+use crate::utils::read_cache;
+use crate::Cakes;
+
+/// How `density` estimates the density around an instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DensityMode {
+    /// `k / (n * r^d)`, where `r` is the distance to the k-th nearest neighbor of the instance, other than itself,
+    /// and `d` is the local fractal dimension of the leaf holding the instance.
+    Knn(usize),
+
+    /// `c / (n * r^d)`, where `c`, `r` and `d` are the cardinality, radius and local fractal dimension of the leaf
+    /// holding the instance.
+    Cluster,
+}
+
+/// Returns the estimated density around each instance of the dataset of the `Manifold`, at the position of its index.
+///
+/// Densities are relative to the cardinality `n` of the dataset, so they may be compared across datasets. They are
+/// infinite for instances with duplicates among their nearest neighbors, or in leaves of zero radius, and zero for
+/// instances with no neighbors. Leaves whose local fractal dimension is not positive are taken to be 1-dimensional.
+pub fn density<T: 'static + Number, U: 'static + Number>(manifold: &Manifold<T, U>, mode: DensityMode) -> Vec<f64> {
+    let n = manifold.dataset.cardinality();
+    let mut leaves = manifold.root.flatten_tree();
+    leaves.push(Arc::clone(&manifold.root));
+    leaves.retain(|cluster| read_cache(&cluster.children).is_none());
+
+    let mut dimensions = vec![1.; n];
+    for leaf in leaves.iter() {
+        let dimension = if leaf.lfd > 0. { leaf.lfd } else { 1. };
+        leaf.indices.iter().for_each(|&i| dimensions[i] = dimension);
+    }
+    let estimate = |count: usize, radius: f64, dimension: f64| {
+        if count == 0 {
+            0.
+        } else {
+            count as f64 / (n as f64 * radius.powf(dimension))
+        }
+    };
+
+    match mode {
+        DensityMode::Knn(k) => Cakes::from_manifold(manifold)
+            .self_knn(k)
+            .into_iter()
+            .zip(dimensions)
+            .map(|(hits, dimension)| match hits.last() {
+                Some(&(_, distance)) => estimate(hits.len(), distance.as_f64(), dimension),
+                None => 0.,
+            })
+            .collect(),
+        DensityMode::Cluster => {
+            let mut densities = vec![0.; n];
+            for leaf in leaves {
+                let density = estimate(leaf.cardinality, leaf.radius.as_f64(), dimensions[leaf.argcenter]);
+                leaf.indices.iter().for_each(|&i| densities[i] = density);
+            }
+            densities
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+
+    use super::density;
+    use super::DensityMode;
+
+    #[test]
+    fn test_density() {
+        // A tight cluster of 100 instances and a sparse cluster of 20.
+        let data: Vec<_> = (0..120)
+            .map(|i| {
+                if i < 100 {
+                    vec![(i % 10) as f64 * 0.01, (i / 10) as f64 * 0.01]
+                } else {
+                    vec![100. + (i % 5) as f64 * 3., (i % 4) as f64 * 3.]
+                }
+            })
+            .collect();
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let manifold = Manifold::new(dataset, &[criteria::max_depth(3), criteria::min_cardinality(1)]);
+
+        for mode in [DensityMode::Knn(5), DensityMode::Cluster] {
+            let densities = density(&manifold, mode);
+            assert_eq!(densities.len(), 120);
+            assert!(densities.iter().all(|&d| d > 0.), "{:?}", mode);
+            let dense = densities[..100].iter().cloned().fold(f64::INFINITY, f64::min);
+            let sparse = densities[100..].iter().cloned().fold(0., f64::max);
+            assert!(dense > sparse, "{:?}: {} <= {}", mode, dense, sparse);
+        }
+
+        // The 1st nearest neighbor of every instance in the tight cluster is 0.01 away in one direction.
+        let densities = density(&manifold, DensityMode::Knn(1));
+        let lfd = |i: usize| {
+            let cluster = manifold
+                .root
+                .flatten_tree()
+                .into_iter()
+                .find(|c| c.children.read().unwrap().is_none() && c.indices.contains(&i));
+            cluster.map_or(1., |c| if c.lfd > 0. { c.lfd } else { 1. })
+        };
+        for i in [0, 55, 99] {
+            let expected = 1. / (120. * 0.01_f64.powf(lfd(i)));
+            assert!(float_cmp::approx_eq!(
+                f64,
+                densities[i],
+                expected,
+                epsilon = 1e-6 * expected
+            ));
+        }
+    }
+}
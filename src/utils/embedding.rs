@@ -0,0 +1,159 @@
+//! Measures of how well an embedding, e.g. from dimension reduction, preserves the neighborhoods of a dataset.
+//!
+//! The k-nearest neighbors in both spaces are found with `Cakes`, so the ground truth is exact whenever the metrics
+//! obey the triangle inequality.
+
+use std::collections::HashSet;
+
+use rayon::prelude::*;
+
+use crate::prelude::*;
+use crate::Cakes;
+
+/// Neighborhood-preservation scores for an embedding. Each score lies in [0, 1] and higher is better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmbeddingQuality {
+    /// The number of neighbors considered for each instance.
+    pub k: usize,
+
+    /// Penalizes instances that are among the k-nearest neighbors in the embedding but not in the original space,
+    /// in proportion to how far down the original ranking they are.
+    pub trustworthiness: f64,
+
+    /// Penalizes instances that are among the k-nearest neighbors in the original space but not in the embedding,
+    /// in proportion to how far down the embedded ranking they are.
+    pub continuity: f64,
+
+    /// The mean fraction of the k-nearest neighbors in the original space that are also among the k-nearest
+    /// neighbors in the embedding.
+    pub knn_recall: f64,
+}
+
+/// Computes the trustworthiness, continuity and knn-recall of an embedding.
+///
+/// # Arguments
+///
+/// * `original` - A search tree over the original dataset.
+/// * `embedded` - A search tree over the embedded dataset. Instance `i` must be the image of original instance `i`.
+/// * `k` - The number of neighbors to consider. Must be positive and smaller than `(2n - 1) / 3` for `n` instances.
+pub fn embedding_quality<T, U, E, V>(
+    original: &Cakes<T, U>,
+    embedded: &Cakes<E, V>,
+    k: usize,
+) -> Result<EmbeddingQuality, String>
+where
+    T: 'static + Number,
+    U: 'static + Number,
+    E: 'static + Number,
+    V: 'static + Number,
+{
+    let n = original.dataset.cardinality();
+    if embedded.dataset.cardinality() != n {
+        return Err(format!(
+            "The original dataset has {} instances but the embedding has {}.",
+            n,
+            embedded.dataset.cardinality()
+        ));
+    }
+    if k == 0 || 3 * k + 1 >= 2 * n {
+        return Err(format!(
+            "k must be in [1, (2n - 1) / 3) but was {} for {} instances.",
+            k, n
+        ));
+    }
+
+    let (trust_penalty, continuity_penalty, shared) = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            let original_neighbors = neighbors_of(original, i, k);
+            let embedded_neighbors = neighbors_of(embedded, i, k);
+            let original_set: HashSet<_> = original_neighbors.iter().map(|&(j, _)| j).collect();
+            let embedded_set: HashSet<_> = embedded_neighbors.iter().map(|&(j, _)| j).collect();
+
+            let trust_penalty: usize = embedded_set
+                .difference(&original_set)
+                .map(|&j| rank_of(original, i, j) - k)
+                .sum();
+            let continuity_penalty: usize = original_set
+                .difference(&embedded_set)
+                .map(|&j| rank_of(embedded, i, j) - k)
+                .sum();
+            let shared = original_set.intersection(&embedded_set).count();
+
+            (trust_penalty, continuity_penalty, shared)
+        })
+        .reduce(|| (0, 0, 0), |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2));
+
+    let normalizer = 2. / ((n * k) as f64 * (2 * n - 3 * k - 1) as f64);
+    Ok(EmbeddingQuality {
+        k,
+        trustworthiness: 1. - normalizer * trust_penalty as f64,
+        continuity: 1. - normalizer * continuity_penalty as f64,
+        knn_recall: shared as f64 / (n * k) as f64,
+    })
+}
+
+/// Returns the `k`-nearest neighbors of instance `i`, excluding `i` itself.
+fn neighbors_of<T: 'static + Number, U: 'static + Number>(cakes: &Cakes<T, U>, i: Index, k: usize) -> Vec<(Index, U)> {
+    let mut neighbors: Vec<_> = cakes
+        .knn(&cakes.dataset.instance(i), k + 1)
+        .into_iter()
+        .filter(|&(j, _)| j != i)
+        .collect();
+    neighbors.truncate(k);
+    neighbors
+}
+
+/// Returns the rank of instance `j` among the neighbors of instance `i`, i.e. one more than the number of
+/// instances, other than `i`, that are closer to `i` than `j` is. Ties are broken by index, as in `Cakes::knn`.
+fn rank_of<T: 'static + Number, U: 'static + Number>(cakes: &Cakes<T, U>, i: Index, j: Index) -> usize {
+    let distance = cakes.dataset.distance(i, j);
+    let closer = cakes
+        .rnn(&cakes.dataset.instance(i), Some(distance))
+        .into_iter()
+        .filter(|&(l, d)| l != i && (d < distance || (d == distance && l < j)))
+        .count();
+    closer + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::embedding_quality;
+
+    fn build(data: Vec<Vec<f64>>) -> Cakes<f64, f64> {
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        Cakes::build(dataset, None, None)
+    }
+
+    #[test]
+    fn test_embedding_quality() {
+        // Points on a slightly curved line in 3 dimensions embedded, by projection, onto a line.
+        // An even k avoids ties between neighbors on either side of a point.
+        let original: Vec<_> = (0..40).map(|i| vec![i as f64, (i as f64 / 10.).sin(), 0.]).collect();
+        let projected: Vec<_> = original.iter().map(|x| vec![x[0]]).collect();
+        let original = build(original);
+
+        let quality = embedding_quality(&original, &build(projected), 4).unwrap();
+        float_cmp::assert_approx_eq!(f64, quality.trustworthiness, 1.);
+        float_cmp::assert_approx_eq!(f64, quality.continuity, 1.);
+        float_cmp::assert_approx_eq!(f64, quality.knn_recall, 1.);
+
+        // Folding the line in half places far-apart points together.
+        let folded: Vec<_> = (0..40).map(|i| vec![(i as f64 - 19.5).abs()]).collect();
+        let quality = embedding_quality(&original, &build(folded), 4).unwrap();
+        assert!(quality.trustworthiness < 1.);
+        assert!(quality.knn_recall < 1.);
+        assert!(quality.trustworthiness >= 0. && quality.continuity >= 0.);
+
+        assert!(embedding_quality(&original, &build(vec![vec![0.]; 10]), 5).is_err());
+        assert!(embedding_quality(&original, &original, 0).is_err());
+        assert!(embedding_quality(&original, &original, 30).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Running an experiment over every combination of its parameters, e.g. criteria, seeds, metrics and algorithms,
+//! and collecting the measurements of every run into one table with a row per run.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+/// The values to try for each named parameter of an experiment.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterGrid {
+    axes: Vec<(String, Vec<String>)>,
+}
+
+/// One combination of the values of the parameters in a `ParameterGrid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridPoint {
+    /// The position of the combination in the grid.
+    pub index: usize,
+
+    /// The name and value of each parameter, in the order of the axes of the grid.
+    pub parameters: Vec<(String, String)>,
+}
+
+/// The parameters and measurements of every run of an experiment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentResults {
+    /// The names of the parameters, followed by the names of the measurements in the order they were first reported.
+    pub columns: Vec<String>,
+
+    /// The number of columns that hold parameters.
+    pub num_parameters: usize,
+
+    /// One row per run, in the order of the grid. A measurement that a run did not report is `None`.
+    pub rows: Vec<ExperimentRow>,
+}
+
+/// The outcome of one run of an experiment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentRow {
+    pub point: GridPoint,
+
+    /// The value of each measurement, in the order of the measurement columns.
+    pub measurements: Vec<Option<f64>>,
+
+    /// The time taken by the run, in seconds.
+    pub seconds: f64,
+
+    /// The Err returned by the run, if it failed.
+    pub error: Option<String>,
+}
+
+impl ParameterGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter with the values to try. Later parameters vary fastest in the order of the grid.
+    pub fn axis<V: ToString>(mut self, name: &str, values: impl IntoIterator<Item = V>) -> Self {
+        let values = values.into_iter().map(|value| value.to_string()).collect();
+        self.axes.push((name.to_string(), values));
+        self
+    }
+
+    /// Returns the names of the parameters.
+    pub fn names(&self) -> Vec<&str> {
+        self.axes.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Returns the number of combinations of values in the grid.
+    pub fn len(&self) -> usize {
+        if self.axes.is_empty() {
+            0
+        } else {
+            self.axes.iter().map(|(_, values)| values.len()).product()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns every combination of values, in order.
+    pub fn points(&self) -> Vec<GridPoint> {
+        (0..self.len())
+            .map(|index| {
+                let mut remainder = index;
+                let mut parameters: Vec<_> = self
+                    .axes
+                    .iter()
+                    .rev()
+                    .map(|(name, values)| {
+                        let value = values[remainder % values.len()].clone();
+                        remainder /= values.len();
+                        (name.clone(), value)
+                    })
+                    .collect();
+                parameters.reverse();
+                GridPoint { index, parameters }
+            })
+            .collect()
+    }
+
+    /// Runs the experiment once for every combination of values and collects the measurements it reports, as pairs
+    /// of a name and a value. Runs that return an Err are kept, with their error, so that one failure does not lose
+    /// the results of the other runs.
+    ///
+    /// If `parallel` is true, the runs are spread over the threads of the rayon pool, so their times are only
+    /// comparable if each run is single-threaded.
+    pub fn run<F>(&self, parallel: bool, experiment: F) -> ExperimentResults
+    where
+        F: Fn(&GridPoint) -> Result<Vec<(String, f64)>, String> + Sync,
+    {
+        let run = |point: GridPoint| {
+            log::info!("Running {:?}.", point.parameters);
+            let start = std::time::Instant::now();
+            let outcome = experiment(&point);
+            (point, outcome, start.elapsed().as_secs_f64())
+        };
+        let outcomes: Vec<_> = if parallel {
+            self.points().into_par_iter().map(run).collect()
+        } else {
+            self.points().into_iter().map(run).collect()
+        };
+
+        let mut columns: Vec<String> = self.names().into_iter().map(String::from).collect();
+        let num_parameters = columns.len();
+        for (_, outcome, _) in outcomes.iter() {
+            for (name, _) in outcome.iter().flatten() {
+                if !columns[num_parameters..].contains(name) {
+                    columns.push(name.clone());
+                }
+            }
+        }
+
+        let rows = outcomes
+            .into_iter()
+            .map(|(point, outcome, seconds)| {
+                let mut measurements = vec![None; columns.len() - num_parameters];
+                let error = match outcome {
+                    Ok(values) => {
+                        for (name, value) in values {
+                            let column = columns[num_parameters..].iter().position(|c| c == &name).unwrap();
+                            measurements[column] = Some(value);
+                        }
+                        None
+                    }
+                    Err(error) => {
+                        log::warn!("Run {:?} failed: {}", point.parameters, error);
+                        Some(error)
+                    }
+                };
+                ExperimentRow {
+                    point,
+                    measurements,
+                    seconds,
+                    error,
+                }
+            })
+            .collect();
+
+        ExperimentResults {
+            columns,
+            num_parameters,
+            rows,
+        }
+    }
+}
+
+impl GridPoint {
+    /// Returns the value of the named parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the value of the named parameter, e.g. `point.parse::<usize>("max_depth")`.
+    pub fn parse<V: FromStr>(&self, name: &str) -> Result<V, String> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| format!("There is no parameter named '{}'.", name))?;
+        value
+            .parse()
+            .map_err(|_| format!("Could not parse the value '{}' of parameter '{}'.", value, name))
+    }
+}
+
+impl ExperimentResults {
+    /// Returns the runs that failed.
+    pub fn failures(&self) -> Vec<&ExperimentRow> {
+        self.rows.iter().filter(|row| row.error.is_some()).collect()
+    }
+
+    /// Returns the values of the named measurement, at the positions of the runs, if there is such a measurement.
+    pub fn column(&self, name: &str) -> Option<Vec<Option<f64>>> {
+        let column = self.columns[self.num_parameters..].iter().position(|c| c == name)?;
+        Some(self.rows.iter().map(|row| row.measurements[column]).collect())
+    }
+
+    /// Writes the results as a delimited file with a header: the parameters, the measurements, then `seconds` and
+    /// `error` columns. Missing measurements and errors are empty fields.
+    pub fn to_csv(&self, path: &Path) -> Result<(), String> {
+        let fail = |error: csv::Error| format!("Error: Failed to write {}. {}", path.display(), error);
+        let mut writer = csv::Writer::from_path(path).map_err(fail)?;
+        let header = self.columns.iter().map(String::as_str).chain(["seconds", "error"]);
+        writer.write_record(header).map_err(fail)?;
+        for row in self.rows.iter() {
+            let record = row
+                .point
+                .parameters
+                .iter()
+                .map(|(_, value)| value.clone())
+                .chain(
+                    row.measurements
+                        .iter()
+                        .map(|m| m.map_or_else(String::new, |m| m.to_string())),
+                )
+                .chain([row.seconds.to_string(), row.error.clone().unwrap_or_default()]);
+            writer.write_record(record).map_err(fail)?;
+        }
+        writer.flush().map_err(|error| fail(error.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::ParameterGrid;
+
+    #[test]
+    fn test_grid() {
+        let grid = ParameterGrid::new()
+            .axis("metric", ["euclidean", "manhattan", "unknown"])
+            .axis("max_depth", [2, 6]);
+        assert_eq!(grid.len(), 6);
+        let points = grid.points();
+        assert_eq!(points[1].get("metric"), Some("euclidean"));
+        assert_eq!(points[1].parse::<usize>("max_depth"), Ok(6));
+        assert!(points[1].parse::<usize>("metric").is_err());
+        assert!(ParameterGrid::new().is_empty());
+
+        let data: Vec<_> = (0..100).map(|i| vec![(i % 10) as f64, (i / 10) as f64]).collect();
+        let data = Arc::new(data);
+        let experiment = |point: &super::GridPoint| {
+            let metric = metric_from_name::<f64, f64>(point.get("metric").unwrap())?;
+            let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::clone(&data), metric, false));
+            let cakes = Cakes::build(dataset, Some(point.parse("max_depth")?), None);
+            let mut measurements = vec![("num_clusters".to_string(), (cakes.root.num_descendants() + 1) as f64)];
+            if point.get("metric") == Some("euclidean") {
+                measurements.push(("diameter".to_string(), cakes.diameter()));
+            }
+            Ok(measurements)
+        };
+
+        let serial = grid.run(false, experiment);
+        let parallel = grid.run(true, experiment);
+        assert_eq!(serial.columns, ["metric", "max_depth", "num_clusters", "diameter"]);
+        assert_eq!(serial.rows.len(), 6);
+        assert_eq!(serial.failures().len(), 2);
+        assert_eq!(serial.column("num_clusters"), parallel.column("num_clusters"));
+        let diameters = serial.column("diameter").unwrap();
+        assert!(diameters[0].is_some() && diameters[2].is_none() && diameters[4].is_none());
+
+        let path = std::env::temp_dir().join(format!("clam-grid-{}.csv", std::process::id()));
+        serial.to_csv(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "metric,max_depth,num_clusters,diameter,seconds,error");
+        assert!(lines[3].starts_with("manhattan,2,") && lines[3].contains(",,"));
+    }
+}
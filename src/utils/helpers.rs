@@ -1,6 +1,43 @@
+use std::cmp::Ordering;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+
 use ndarray::prelude::*;
 use rayon::prelude::*;
 
+use super::summation;
+
+/// Compares two distances, ordering NaN after every other value, so that sorting by distance never panics.
+pub fn compare_distances<U: PartialOrd>(a: &U, b: &U) -> Ordering {
+    #[allow(clippy::eq_op)]
+    let is_nan = |x: &U| x != x;
+    a.partial_cmp(b).unwrap_or_else(|| is_nan(a).cmp(&is_nan(b)))
+}
+
+/// Locks the mutex of a cache, recovering it if another thread panicked while holding the lock.
+///
+/// Every cache in the crate keeps its entries consistent between statements, so after such a panic it may miss the
+/// entry that was being written but never serves a wrong one.
+pub(crate) fn lock_cache<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Takes the read lock of a cache, recovering it as in `lock_cache`.
+///
+/// Searches also read the children of clusters through this, since they are only ever replaced whole.
+pub(crate) fn read_cache<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Takes the write lock of a cache, recovering it as in `lock_cache`.
+pub(crate) fn write_cache<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
 pub fn argmin<T: PartialOrd + Copy>(values: &[T]) -> (usize, T) {
     values
         .iter()
@@ -29,15 +66,9 @@ pub fn argmax<T: PartialOrd + Copy>(values: &[T]) -> (usize, T) {
 
 pub fn normalize_1d(values: &[f64]) -> Vec<f64> {
     let num_values = values.len() as f64;
-    let mean = values.iter().sum::<f64>() / num_values;
-    let std_dev = 1e-8
-        + values
-            .iter()
-            .map(|&value| value - mean)
-            .map(|delta| delta.powi(2))
-            .sum::<f64>()
-            .sqrt()
-            / num_values;
+    let mean = summation::sum(values) / num_values;
+    let squares: Vec<_> = values.iter().map(|&value| (value - mean).powi(2)).collect();
+    let std_dev = 1e-8 + summation::sum(&squares).sqrt() / num_values;
     values
         .iter()
         .map(|value| (value - mean) / (std_dev * 2_f64.sqrt()))
@@ -0,0 +1,97 @@
+//! Measuring hubness, i.e. how unevenly the instances of a dataset appear among the nearest neighbors of the others.
+//!
+//! In high-dimensional data, a few hubs are among the nearest neighbors of many instances, while many anti-hubs are
+//! among the nearest neighbors of none. Both affect the quality of search and of anomaly detection.
+
+use crate::prelude::*;
+use crate::Cakes;
+
+/// The k-occurrences of the instances of a dataset and statistics of their distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HubnessReport {
+    /// The number of nearest neighbors of each instance that were considered.
+    pub k: usize,
+
+    /// The number of instances among whose `k` nearest neighbors each instance appears, at the position of its index.
+    pub occurrences: Vec<usize>,
+
+    /// The skewness of the k-occurrences. This is 0 for a symmetric distribution and large for a dataset with hubs.
+    pub skewness: f64,
+
+    /// Indices of the instances that appear more than `2k` times, in decreasing order of their k-occurrences.
+    pub hubs: Vec<Index>,
+
+    /// Indices of the instances that appear in no list of nearest neighbors, in increasing order.
+    pub anti_hubs: Vec<Index>,
+}
+
+/// Counts how often each instance is among the `k` nearest neighbors of the others, using `Cakes::self_knn`.
+pub fn hubness<T: 'static + Number, U: 'static + Number>(cakes: &Cakes<T, U>, k: usize) -> HubnessReport {
+    let mut occurrences = vec![0; cakes.dataset.cardinality()];
+    for hits in cakes.self_knn(k) {
+        hits.into_iter().for_each(|(i, _)| occurrences[i] += 1);
+    }
+
+    let n = occurrences.len() as f64;
+    let mean = occurrences.iter().sum::<usize>() as f64 / n;
+    let moment = |power: i32| occurrences.iter().map(|&o| (o as f64 - mean).powi(power)).sum::<f64>() / n;
+    let variance = moment(2);
+    let skewness = if variance > 0. {
+        moment(3) / variance.powf(1.5)
+    } else {
+        0.
+    };
+
+    let mut hubs: Vec<_> = (0..occurrences.len()).filter(|&i| occurrences[i] > 2 * k).collect();
+    hubs.sort_by_key(|&i| std::cmp::Reverse(occurrences[i]));
+    let anti_hubs = (0..occurrences.len()).filter(|&i| occurrences[i] == 0).collect();
+
+    HubnessReport {
+        k,
+        occurrences,
+        skewness,
+        hubs,
+        anti_hubs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::hubness;
+
+    #[test]
+    fn test_hubness() {
+        // A central instance surrounded by a ring of instances that are farther from each other than from it.
+        let mut data = vec![vec![0., 0.]];
+        data.extend((0..5).map(|i| {
+            let angle = i as f64 * std::f64::consts::PI * 0.4;
+            vec![angle.cos(), angle.sin()]
+        }));
+        data.extend((0..20).map(|i| vec![50. + (i % 5) as f64, (i / 5) as f64]));
+        let metric = metric_from_name("euclidean").unwrap();
+        let dataset: Arc<dyn Dataset<f64, f64>> = Arc::new(RowMajor::new(Arc::new(data), metric, false));
+        let cakes = Cakes::build(dataset, None, None);
+
+        let report = hubness(&cakes, 1);
+        assert_eq!(report.occurrences.len(), 26);
+        assert_eq!(report.occurrences.iter().sum::<usize>(), 26);
+        assert_eq!(report.occurrences[0], 5);
+        assert_eq!(report.hubs[0], 0);
+        assert!(report.anti_hubs.iter().all(|&i| report.occurrences[i] == 0));
+        // Only the instance of the ring that is nearest to the center appears among any nearest neighbors.
+        assert_eq!((1..6).filter(|i| report.anti_hubs.contains(i)).count(), 4);
+        assert!(report.skewness > 1.);
+
+        // Every instance is among the nearest neighbors of every other, so there are neither hubs nor anti-hubs.
+        let report = hubness(&cakes, 25);
+        assert!(report.occurrences.iter().all(|&o| o == 25));
+        assert!(report.hubs.is_empty() && report.anti_hubs.is_empty());
+        assert_eq!(report.skewness, 0.);
+    }
+}
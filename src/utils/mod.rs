@@ -1,5 +1,24 @@
+mod density;
+mod experiment;
 mod helpers;
+mod hubness;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+pub(crate) mod simd;
+mod verify;
 
+pub mod embedding;
+pub mod parallelism;
 pub mod readers;
+pub mod summation;
 
+pub use density::density;
+pub use density::DensityMode;
+pub use experiment::ExperimentResults;
+pub use experiment::ExperimentRow;
+pub use experiment::GridPoint;
+pub use experiment::ParameterGrid;
 pub use helpers::*;
+pub use hubness::hubness;
+pub use hubness::HubnessReport;
+pub use verify::verify_compression;
+pub use verify::CompressionReport;
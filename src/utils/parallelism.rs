@@ -0,0 +1,115 @@
+//! Adaptive parallelism for partitioning trees and traversing them.
+//!
+//! Splitting work among the threads of rayon costs a task for every split, which dominates the work itself deep in a
+//! tree, where clusters hold only a few instances. Partitions and traversals therefore run sequentially for clusters
+//! whose cardinality is below a threshold, chosen for the whole process with `set_parallel_threshold`, and parallel
+//! iterators over the instances of a cluster hand at least that many instances to each task. A threshold of 0
+//! parallelizes everything and `usize::MAX` nothing. The results do not depend on the threshold.
+//!
+//! Work installed in a pool built with `thread_pool` uses the threshold of that pool instead, leaving the rest of the
+//! process unaffected.
+//!
+//! Work run through `sequentially` ignores the threshold and stays on the thread that runs it, e.g. each search of a
+//! batch with `BatchPolicy::PerQuery`.
+
+use std::cell::Cell;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// The cardinality of clusters below which partitions and traversals run sequentially, unless another is chosen.
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 256;
+
+static PARALLEL_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_PARALLEL_THRESHOLD);
+
+thread_local! {
+    static SEQUENTIAL: Cell<bool> = const { Cell::new(false) };
+
+    /// The threshold of the pool that owns the current thread, if it was built with `thread_pool`.
+    static POOL_THRESHOLD: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Chooses the cardinality of clusters below which partitions and traversals run sequentially from now on.
+pub fn set_parallel_threshold(threshold: usize) {
+    PARALLEL_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// Returns the threshold of the pool running the current thread if it was built with `thread_pool`, and otherwise
+/// the one chosen with `set_parallel_threshold`, which is `DEFAULT_PARALLEL_THRESHOLD` unless another was chosen.
+pub fn parallel_threshold() -> usize {
+    POOL_THRESHOLD
+        .get()
+        .unwrap_or_else(|| PARALLEL_THRESHOLD.load(Ordering::Relaxed))
+}
+
+/// Builds a rayon pool of `num_threads` threads, or of rayon's default number if it is 0, whose partitions and
+/// traversals use the given threshold in place of the process-wide one.
+///
+/// Returns an Err if the threads cannot be started.
+pub fn thread_pool(num_threads: usize, threshold: usize) -> Result<rayon::ThreadPool, String> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .start_handler(move |_| POOL_THRESHOLD.set(Some(threshold)))
+        .build()
+        .map_err(|error| format!("Error: Failed to start the threads of the pool. {}", error))
+}
+
+/// Returns the least number of items each task of a parallel iterator should take, for `with_min_len`.
+pub(crate) fn min_len() -> usize {
+    if SEQUENTIAL.get() {
+        usize::MAX
+    } else {
+        parallel_threshold().max(1)
+    }
+}
+
+/// Runs the closure with every partition, traversal and parallel iterator that respects the threshold kept on the
+/// current thread.
+pub(crate) fn sequentially<R>(f: impl FnOnce() -> R) -> R {
+    let previous = SEQUENTIAL.replace(true);
+    let result = f();
+    SEQUENTIAL.set(previous);
+    result
+}
+
+/// Runs both closures, with `rayon::join` if the cluster they work on has a `cardinality` of at least the threshold,
+/// and one after the other otherwise.
+pub(crate) fn join<A, B, RA, RB>(cardinality: usize, a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    if !SEQUENTIAL.get() && cardinality >= parallel_threshold() {
+        rayon::join(a, b)
+    } else {
+        (a(), b())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_pool() {
+        let on_one_thread = |threshold: usize, cardinality: usize| {
+            let pool = thread_pool(2, threshold).unwrap();
+            pool.install(|| {
+                assert_eq!(parallel_threshold(), threshold);
+                let thread = || std::thread::current().id();
+                let (a, b) = join(cardinality, thread, thread);
+                a == b
+            })
+        };
+        // Clusters below the threshold of the pool are partitioned on one thread.
+        assert!(on_one_thread(usize::MAX, 1_000));
+        assert!(on_one_thread(1_001, 1_000));
+        assert_eq!(thread_pool(1, 5).unwrap().install(min_len), 5);
+        assert_eq!(thread_pool(1, 0).unwrap().install(min_len), 1);
+        assert_eq!(thread_pool(1, 5).unwrap().install(|| sequentially(min_len)), usize::MAX);
+
+        // The threshold of a pool stays on its threads.
+        assert_eq!(parallel_threshold(), DEFAULT_PARALLEL_THRESHOLD);
+    }
+}
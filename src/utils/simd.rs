@@ -0,0 +1,221 @@
+//! Kernels for the dense-vector metrics with the vector instructions of the CPU, behind the `simd` feature.
+//!
+//! The euclidean, euclideansq, manhattan, cosine and angular metrics hand their instances to these kernels when both
+//! are slices of `f32` or `f64` of the same length and the CPU has AVX and FMA, as detected at run time. The kernels
+//! convert to and accumulate in `f64`, as the scalar loops do, so their distances differ only by the rounding of sums
+//! taken in a different order. Other types, lengths and CPUs get None, and the scalar loops. The module is only built
+//! for x86_64.
+
+use crate::Number;
+
+/// Returns the squared L2-norm of the difference.
+pub(crate) fn squared_euclidean<T: Number>(x: &[T], y: &[T]) -> Option<f64> {
+    dispatch(x, y, kernels::squared_euclidean_f32, kernels::squared_euclidean_f64)
+}
+
+/// Returns the L1-norm of the difference.
+pub(crate) fn manhattan<T: Number>(x: &[T], y: &[T]) -> Option<f64> {
+    dispatch(x, y, kernels::manhattan_f32, kernels::manhattan_f64)
+}
+
+/// Returns the dot product.
+pub(crate) fn dot<T: Number>(x: &[T], y: &[T]) -> Option<f64> {
+    dispatch(x, y, kernels::dot_f32, kernels::dot_f64)
+}
+
+/// Returns the dot products `x.y`, `x.x` and `y.y`, in one pass, for the cosine distance.
+pub(crate) fn dots<T: Number>(x: &[T], y: &[T]) -> Option<[f64; 3]> {
+    dispatch(x, y, kernels::dots_f32, kernels::dots_f64)
+}
+
+fn dispatch<T: Number, R>(
+    x: &[T],
+    y: &[T],
+    f32s: unsafe fn(&[f32], &[f32]) -> R,
+    f64s: unsafe fn(&[f64], &[f64]) -> R,
+) -> Option<R> {
+    if x.len() != y.len() || !(is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma")) {
+        return None;
+    }
+    // The kernels need AVX and FMA, which the CPU has.
+    if let (Some(x), Some(y)) = (T::as_f32s(x), T::as_f32s(y)) {
+        Some(unsafe { f32s(x, y) })
+    } else if let (Some(x), Some(y)) = (T::as_f64s(x), T::as_f64s(y)) {
+        Some(unsafe { f64s(x, y) })
+    } else {
+        None
+    }
+}
+
+mod kernels {
+    use std::arch::x86_64::*;
+
+    use crate::Number;
+
+    /// Loads four values from the pointer into the lanes of a vector of `f64`.
+    macro_rules! load {
+        (f32, $ptr:expr) => {
+            _mm256_cvtps_pd(_mm_loadu_ps($ptr))
+        };
+        (f64, $ptr:expr) => {
+            _mm256_loadu_pd($ptr)
+        };
+    }
+
+    /// Returns the sum of the lanes.
+    #[target_feature(enable = "avx")]
+    unsafe fn sum(lanes: __m256d) -> f64 {
+        let mut values = [0.; 4];
+        _mm256_storeu_pd(values.as_mut_ptr(), lanes);
+        values.iter().sum()
+    }
+
+    /// Defines the kernels for slices of one type, which must have the same length. Each loop takes eight values of
+    /// each slice at a time, into two accumulators, and the last few values are added one by one.
+    macro_rules! kernels {
+        ($ty:tt, $squared_euclidean:ident, $manhattan:ident, $dot:ident, $dots:ident) => {
+            #[target_feature(enable = "avx,fma")]
+            pub(super) unsafe fn $squared_euclidean(x: &[$ty], y: &[$ty]) -> f64 {
+                let (n, mut i) = (x.len(), 0);
+                let (mut a, mut b) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+                while i + 8 <= n {
+                    let d = _mm256_sub_pd(load!($ty, x.as_ptr().add(i)), load!($ty, y.as_ptr().add(i)));
+                    a = _mm256_fmadd_pd(d, d, a);
+                    let d = _mm256_sub_pd(
+                        load!($ty, x.as_ptr().add(i + 4)),
+                        load!($ty, y.as_ptr().add(i + 4)),
+                    );
+                    b = _mm256_fmadd_pd(d, d, b);
+                    i += 8;
+                }
+                let tail: f64 = (i..n).map(|j| (x[j].as_f64() - y[j].as_f64()).powi(2)).sum();
+                sum(_mm256_add_pd(a, b)) + tail
+            }
+
+            #[target_feature(enable = "avx,fma")]
+            pub(super) unsafe fn $manhattan(x: &[$ty], y: &[$ty]) -> f64 {
+                let (n, mut i) = (x.len(), 0);
+                let (mut a, mut b) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+                // Clearing the sign bit takes the absolute value.
+                let sign = _mm256_set1_pd(-0.);
+                while i + 8 <= n {
+                    let d = _mm256_sub_pd(load!($ty, x.as_ptr().add(i)), load!($ty, y.as_ptr().add(i)));
+                    a = _mm256_add_pd(a, _mm256_andnot_pd(sign, d));
+                    let d = _mm256_sub_pd(
+                        load!($ty, x.as_ptr().add(i + 4)),
+                        load!($ty, y.as_ptr().add(i + 4)),
+                    );
+                    b = _mm256_add_pd(b, _mm256_andnot_pd(sign, d));
+                    i += 8;
+                }
+                let tail: f64 = (i..n).map(|j| (x[j].as_f64() - y[j].as_f64()).abs()).sum();
+                sum(_mm256_add_pd(a, b)) + tail
+            }
+
+            #[target_feature(enable = "avx,fma")]
+            pub(super) unsafe fn $dot(x: &[$ty], y: &[$ty]) -> f64 {
+                let (n, mut i) = (x.len(), 0);
+                let (mut a, mut b) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+                while i + 8 <= n {
+                    a = _mm256_fmadd_pd(load!($ty, x.as_ptr().add(i)), load!($ty, y.as_ptr().add(i)), a);
+                    b = _mm256_fmadd_pd(
+                        load!($ty, x.as_ptr().add(i + 4)),
+                        load!($ty, y.as_ptr().add(i + 4)),
+                        b,
+                    );
+                    i += 8;
+                }
+                let tail: f64 = (i..n).map(|j| x[j].as_f64() * y[j].as_f64()).sum();
+                sum(_mm256_add_pd(a, b)) + tail
+            }
+
+            #[target_feature(enable = "avx,fma")]
+            pub(super) unsafe fn $dots(x: &[$ty], y: &[$ty]) -> [f64; 3] {
+                let (n, mut i) = (x.len(), 0);
+                let (mut xy, mut xx, mut yy) = (_mm256_setzero_pd(), _mm256_setzero_pd(), _mm256_setzero_pd());
+                while i + 4 <= n {
+                    let (a, b) = (load!($ty, x.as_ptr().add(i)), load!($ty, y.as_ptr().add(i)));
+                    xy = _mm256_fmadd_pd(a, b, xy);
+                    xx = _mm256_fmadd_pd(a, a, xx);
+                    yy = _mm256_fmadd_pd(b, b, yy);
+                    i += 4;
+                }
+                let mut dots = [sum(xy), sum(xx), sum(yy)];
+                for j in i..n {
+                    let (a, b) = (x[j].as_f64(), y[j].as_f64());
+                    dots[0] += a * b;
+                    dots[1] += a * a;
+                    dots[2] += b * b;
+                }
+                dots
+            }
+        };
+    }
+
+    kernels!(f32, squared_euclidean_f32, manhattan_f32, dot_f32, dots_f32);
+    kernels!(f64, squared_euclidean_f64, manhattan_f64, dot_f64, dots_f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use crate::Number;
+
+    #[test]
+    fn test_simd_kernels() {
+        let scalar = |x: &[f64], y: &[f64]| {
+            let pairs = || x.iter().zip(y.iter());
+            [
+                pairs().map(|(a, b)| (a - b).powi(2)).sum::<f64>(),
+                pairs().map(|(a, b)| (a - b).abs()).sum::<f64>(),
+                pairs().map(|(a, b)| a * b).sum::<f64>(),
+                x.iter().map(|a| a * a).sum::<f64>(),
+                y.iter().map(|b| b * b).sum::<f64>(),
+            ]
+        };
+        // Every length up to a few chunks, and so every length of the tail.
+        for n in 0..40 {
+            let x: Vec<f32> = (0..n).map(|i| ((i * 37 % 11) as f32 - 5.) / 3.).collect();
+            let y: Vec<f32> = (0..n).map(|i| ((i * 13 % 7) as f32 - 3.) * 1.5).collect();
+            let expected = scalar(
+                &x.iter().map(|v| v.as_f64()).collect::<Vec<_>>(),
+                &y.iter().map(|v| v.as_f64()).collect::<Vec<_>>(),
+            );
+            check(&x, &y, expected);
+            let (x, y): (Vec<f64>, Vec<f64>) = (
+                x.iter().map(|v| v.as_f64()).collect(),
+                y.iter().map(|v| v.as_f64()).collect(),
+            );
+            check(&x, &y, expected);
+        }
+
+        // Integers, and slices of different lengths, are left to the scalar loops.
+        assert!(super::squared_euclidean(&[1_u8, 2], &[3, 4]).is_none());
+        assert!(super::dot(&[1_f32, 2.], &[3.]).is_none());
+    }
+
+    fn check<T: Number>(x: &[T], y: &[T], expected: [f64; 5]) {
+        let Some(squared) = super::squared_euclidean(x, y) else {
+            // The CPU has no AVX or FMA.
+            return;
+        };
+        let [xy, xx, yy] = super::dots(x, y).unwrap();
+        let actual = [
+            squared,
+            super::manhattan(x, y).unwrap(),
+            super::dot(x, y).unwrap(),
+            xx,
+            yy,
+        ];
+        for (actual, expected) in actual.into_iter().zip(expected) {
+            assert!(
+                approx_eq!(f64, actual, expected, epsilon = 1e-9),
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+        assert!(approx_eq!(f64, xy, expected[2], epsilon = 1e-9));
+    }
+}
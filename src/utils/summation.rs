@@ -0,0 +1,112 @@
+//! Reproducible sums of floats, for the statistics of clusters and the scores of anomalies.
+//!
+//! Floating-point addition is not associative, so a sum reduced in parallel depends on how the work was split among
+//! threads. The statistics in `CLAM` therefore collect the values to be summed in a fixed order and then add them with
+//! one of the algorithms of `Summation`, which is chosen for the whole process with `set_summation`. The results are
+//! bit-identical across runs and thread counts for any of them.
+
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+/// The number of values below which `Summation::Pairwise` adds them in order.
+const PAIRWISE_BLOCK: usize = 32;
+
+/// How to add a sequence of floats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Summation {
+    /// Adds the values in order. The rounding error may grow linearly with the number of values.
+    #[default]
+    Sequential,
+    /// Adds the sums of the two halves of the values, recursively. The rounding error grows with the logarithm of the
+    /// number of values, at about the cost of `Sequential`.
+    Pairwise,
+    /// Carries the rounding error of each addition forward, as in Neumaier's variant of Kahan summation. The rounding
+    /// error is independent of the number of values, at about four times the cost of `Sequential`.
+    Kahan,
+}
+
+static SUMMATION: AtomicU8 = AtomicU8::new(0);
+
+impl Summation {
+    /// Returns the sum of the values, added in their order.
+    pub fn sum(self, values: &[f64]) -> f64 {
+        match self {
+            Summation::Sequential => values.iter().sum(),
+            Summation::Pairwise => pairwise(values),
+            Summation::Kahan => {
+                let (mut sum, mut compensation) = (0., 0.);
+                for &value in values {
+                    let total = sum + value;
+                    compensation += if f64::abs(sum) >= f64::abs(value) {
+                        (sum - total) + value
+                    } else {
+                        (value - total) + sum
+                    };
+                    sum = total;
+                }
+                sum + compensation
+            }
+        }
+    }
+}
+
+fn pairwise(values: &[f64]) -> f64 {
+    if values.len() <= PAIRWISE_BLOCK {
+        values.iter().sum()
+    } else {
+        let (left, right) = values.split_at(values.len() / 2);
+        pairwise(left) + pairwise(right)
+    }
+}
+
+/// Chooses how every sum in the statistics of clusters and the scores of anomalies is added from now on.
+pub fn set_summation(summation: Summation) {
+    SUMMATION.store(summation as u8, Ordering::Relaxed);
+}
+
+/// Returns the summation chosen with `set_summation`, which is `Summation::Sequential` unless another was chosen.
+pub fn summation() -> Summation {
+    match SUMMATION.load(Ordering::Relaxed) {
+        1 => Summation::Pairwise,
+        2 => Summation::Kahan,
+        _ => Summation::Sequential,
+    }
+}
+
+/// Returns the sum of the values, added in their order with the chosen summation.
+pub fn sum(values: &[f64]) -> f64 {
+    summation().sum(values)
+}
+
+/// Returns the sum of values whose order carries no meaning, e.g. scores collected from a set of clusters. They are
+/// added in sorted order, so that the sum does not depend on the order in which they arrive.
+pub fn sum_unordered(values: &[f64]) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    sum(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sum_unordered;
+    use super::Summation;
+
+    #[test]
+    fn test_summation() {
+        // Sequential addition loses the small values next to the large ones.
+        let values = [1e16, 1., 1., -1e16];
+        assert_eq!(Summation::Sequential.sum(&values), 0.);
+        assert_eq!(Summation::Kahan.sum(&values), 2.);
+
+        let values: Vec<_> = (0..10_000).map(|i| 0.1 + (i % 7) as f64 * 1e-3).collect();
+        let exact = 1_000. + (0..10_000).map(|i| i % 7).sum::<usize>() as f64 * 1e-3;
+        let error = |summation: Summation| (summation.sum(&values) - exact).abs();
+        assert!(error(Summation::Kahan) <= error(Summation::Pairwise));
+        assert!(error(Summation::Pairwise) < error(Summation::Sequential));
+        assert_eq!(Summation::Pairwise.sum(&[]), 0.);
+
+        let mut reversed = values.clone();
+        reversed.reverse();
+        assert_eq!(sum_unordered(&values).to_bits(), sum_unordered(&reversed).to_bits());
+    }
+}
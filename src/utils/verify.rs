@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::codec::Codec;
+use crate::prelude::*;
+
+/// The outcome of comparing a compressed dataset to the original.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionReport {
+    /// The number of instances that were decoded.
+    pub checked: usize,
+
+    /// Indices of the decoded instances that differ from the originals, in increasing order.
+    /// This includes every instance of a leaf that failed to decode and any index that is not in the original.
+    pub mismatched: Vec<Index>,
+
+    /// Indices of original instances that are not in any leaf, in increasing order.
+    pub missing: Vec<Index>,
+
+    /// The largest absolute difference between any element of a decoded instance and that of its original.
+    pub max_error: f64,
+}
+
+impl CompressionReport {
+    /// Returns whether every original instance was decoded correctly.
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Decodes every instance in the compressed tree, in parallel, and compares it to the original instance.
+///
+/// With no `tolerance`, decoded instances must equal the originals exactly, as they should for lossless codecs.
+/// Otherwise, every element may differ from the original by at most `tolerance`.
+pub fn verify_compression<T: Number, U: Number>(
+    original: &Arc<dyn Dataset<T, U>>,
+    compressed: &Codec<T, U>,
+    tolerance: Option<f64>,
+) -> CompressionReport {
+    let metric = compressed.dataset.metric();
+    let leaves: Vec<_> = compressed
+        .tree_map
+        .values()
+        .filter(|cluster| compressed.children(cluster).is_none())
+        .collect();
+
+    // For each decoded instance: its index and the largest element-wise error, or None if it did not match.
+    let decoded: Vec<(Index, Option<f64>)> = leaves
+        .par_iter()
+        .flat_map(|leaf| match leaf.decode_instances(&metric, &compressed.center) {
+            Ok(instances) => leaf
+                .indices
+                .par_iter()
+                .zip(instances.into_par_iter())
+                .map(|(&i, instance)| (i, compare(original, i, &instance, tolerance)))
+                .collect::<Vec<_>>(),
+            Err(_) => leaf.indices.iter().map(|&i| (i, None)).collect(),
+        })
+        .collect();
+
+    let mut seen = vec![false; original.cardinality()];
+    let mut mismatched = Vec::new();
+    let mut max_error = 0_f64;
+    for &(i, error) in decoded.iter() {
+        match (seen.get_mut(i), error) {
+            (Some(seen), Some(error)) => {
+                *seen = true;
+                max_error = max_error.max(error);
+            }
+            (Some(seen), None) => {
+                *seen = true;
+                mismatched.push(i);
+            }
+            (None, _) => mismatched.push(i),
+        }
+    }
+    mismatched.sort_unstable();
+    mismatched.dedup();
+
+    CompressionReport {
+        checked: decoded.len(),
+        mismatched,
+        missing: seen.iter().enumerate().filter(|(_, &s)| !s).map(|(i, _)| i).collect(),
+        max_error,
+    }
+}
+
+/// Returns the largest element-wise error of the decoded instance, or None if it does not match within the tolerance.
+fn compare<T: Number, U: Number>(
+    original: &Arc<dyn Dataset<T, U>>,
+    index: Index,
+    decoded: &[T],
+    tolerance: Option<f64>,
+) -> Option<f64> {
+    if index >= original.cardinality() {
+        return None;
+    }
+    let instance = original.instance(index);
+    if instance.len() != decoded.len() {
+        return None;
+    }
+    match tolerance {
+        None => {
+            if instance.as_slice() == decoded {
+                Some(0.)
+            } else {
+                None
+            }
+        }
+        Some(tolerance) => {
+            let error = instance
+                .iter()
+                .zip(decoded.iter())
+                .map(|(a, b)| (a.as_f64() - b.as_f64()).abs())
+                .fold(0_f64, f64::max);
+            if error <= tolerance {
+                Some(error)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::codec::Codec;
+    use crate::codec::PackableCluster;
+    use crate::dataset::RowMajor;
+    use crate::prelude::*;
+    use crate::Cakes;
+
+    use super::verify_compression;
+
+    #[test]
+    fn test_verify_compression() {
+        let data: Vec<Vec<u8>> = (0..50)
+            .map(|i: usize| (0..8).map(|j| ((i * (j + 1) + i / 7) % 4) as u8).collect())
+            .collect();
+        let metric = metric_from_name("hamming").unwrap();
+        let row_major = Arc::new(RowMajor::<u8, u64>::new(Arc::new(data), metric, false));
+        let original = Arc::clone(&row_major).as_arc_dataset();
+        let compressible = row_major.as_arc_compressible_dataset();
+        let cakes = Cakes::build(Arc::clone(&original), None, None);
+
+        let codec = Codec::from_cakes(&compressible, &cakes).unwrap();
+        let report = verify_compression(&original, &codec, None);
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.checked, 50);
+        assert_eq!(report.max_error, 0.);
+
+        // Corrupt one leaf by pointing it at the instance of another leaf.
+        let mut leaves: Vec<_> = codec
+            .tree_map
+            .values()
+            .filter(|c| codec.children(c).is_none())
+            .map(|c| c.name.clone())
+            .collect();
+        leaves.sort();
+        let mut tree_map: HashMap<_, _> = codec
+            .tree_map
+            .iter()
+            .map(|(name, cluster)| (name.clone(), Arc::clone(cluster)))
+            .collect();
+        let corrupted = tree_map.get(&leaves[0]).unwrap();
+        let wrong_index = tree_map.get(&leaves[1]).unwrap().indices[0];
+        let mut missing = corrupted.indices.clone();
+        let corrupted = PackableCluster {
+            name: corrupted.name.clone(),
+            cardinality: corrupted.cardinality,
+            indices: vec![wrong_index; corrupted.indices.len()],
+            center: corrupted.center.clone(),
+            radius: corrupted.radius,
+            encodings: corrupted.encodings.clone(),
+        };
+        tree_map.insert(leaves[0].clone(), Arc::new(corrupted));
+        missing.sort_unstable();
+        let codec = Codec::new(compressible, codec.center.clone(), tree_map).unwrap();
+
+        let report = verify_compression(&original, &codec, Some(0.5));
+        assert!(!report.is_ok());
+        assert_eq!(report.mismatched, vec![wrong_index]);
+        assert_eq!(report.missing, missing);
+
+        // A loose enough tolerance accepts the corrupted leaf.
+        let report = verify_compression(&original, &codec, Some(3.));
+        assert!(report.mismatched.is_empty());
+        assert!(report.max_error > 0.);
+    }
+}